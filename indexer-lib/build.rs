@@ -0,0 +1,36 @@
+//! Exposes the resolved kaspa-consensus-core version to the crate, so header re-validation
+//! can detect dependency upgrades.
+
+use std::path::PathBuf;
+
+fn main() {
+    let lock = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("..")
+        .join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let version = std::fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| consensus_core_version(&lock))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=KASPA_CONSENSUS_CORE_VERSION={version}");
+}
+
+/// `<version> <source>` of the kaspa-consensus-core package, the source pins the git revision
+fn consensus_core_version(lock: &str) -> Option<String> {
+    let package = lock
+        .split("[[package]]")
+        .find(|package| package.contains("name = \"kaspa-consensus-core\""))?;
+    let field = |name: &str| {
+        package.lines().find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(" = \"")?
+                .strip_suffix('"')
+                .map(str::to_string)
+        })
+    };
+    let version = field("version")?;
+    Some(match field("source") {
+        Some(source) => format!("{version} {source}"),
+        None => version,
+    })
+}
@@ -0,0 +1,166 @@
+//! Replays blocks through the block processor, committing every block on its own and in
+//! batches of increasing size.
+//!
+//! `cargo run --release --example batched_commit_bench`
+
+use fjall::TxKeyspace;
+use indexer_lib::BlockOrMany;
+use indexer_lib::block_processor::{BlockProcessor, FlushPolicy};
+use indexer_lib::database::block_stats::BlockStatsPartition;
+use indexer_lib::database::block_transactions::BlockTransactionsPartition;
+use indexer_lib::database::headers::{
+    BlockCompactHeaderPartition, BlockRelationsPartition, ChainMembershipPartition,
+    DaaIndexPartition,
+};
+use indexer_lib::database::messages::{
+    ContextualMessageBySenderPartition, HandshakeByReceiverPartition, PaymentByReceiverPartition,
+    TxIdToHandshakePartition, TxIdToPaymentPartition,
+};
+use indexer_lib::database::metadata::MetadataPartition;
+use indexer_lib::database::miners::{BlockMinerPartition, MinerBlocksPartition};
+use indexer_lib::database::processing::{
+    OrphanPoolPartition, OutpointPartition, PendingSpendPartition, ProcessedBlockPartition,
+    SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition,
+};
+use indexer_lib::database::token_operations::TokenOperationPartition;
+use indexer_lib::fifo_set::FifoSet;
+use indexer_lib::metrics::create_shared_metrics;
+use indexer_lib::shutdown::Shutdown;
+use kaspa_consensus_core::header::Header;
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_NATIVE;
+use kaspa_consensus_core::tx::{
+    ScriptPublicKey, Transaction, TransactionInput, TransactionOutpoint, TransactionOutput,
+};
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransaction};
+use std::time::{Duration, Instant};
+
+const BLOCKS: u64 = 10_000;
+const TXS_PER_BLOCK: u64 = 20;
+/// Blocks per intake message, as delivered by the historical syncer
+const CHUNK: usize = 100;
+
+fn main() -> anyhow::Result<()> {
+    let blocks = (0..BLOCKS).map(block).collect::<Vec<_>>();
+    println!("Replaying {BLOCKS} blocks with {TXS_PER_BLOCK} transactions each");
+    let per_block = replay(&blocks, FlushPolicy::PER_BLOCK)?;
+    println!("per block: {per_block:?} ({:.0} blocks/s)", rate(per_block));
+    for max_blocks in [10, 100, 1000] {
+        let elapsed = replay(&blocks, FlushPolicy::batched(max_blocks))?;
+        println!(
+            "batches of {max_blocks}: {elapsed:?} ({:.0} blocks/s, speedup {:.2}x)",
+            rate(elapsed),
+            per_block.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+fn rate(elapsed: Duration) -> f64 {
+    BLOCKS as f64 / elapsed.as_secs_f64()
+}
+
+fn replay(blocks: &[RpcBlock], flush_policy: FlushPolicy) -> anyhow::Result<Duration> {
+    let keyspace = fjall::Config::new(std::env::temp_dir().join(format!(
+        "kasia-indexer-batched-commit-bench-{}-{}",
+        std::process::id(),
+        flush_policy.max_blocks
+    )))
+    .temporary(true)
+    .open_transactional()?;
+    let (intake_tx, intake_rx) = flume::unbounded();
+    let shutdown = Shutdown::new();
+    let mut processor = processor(&keyspace, intake_rx, shutdown.clone(), flush_policy)?;
+    for chunk in blocks.chunks(CHUNK) {
+        intake_tx.send(BlockOrMany::Many(
+            chunk.to_vec(),
+            "bench".into(),
+            Default::default(),
+        ))?;
+    }
+    shutdown.cancel();
+
+    // the processor waits for its intake through the runtime
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let _runtime = runtime.enter();
+    let start = Instant::now();
+    processor.process()?;
+    Ok(start.elapsed())
+}
+
+fn processor(
+    keyspace: &TxKeyspace,
+    intake: flume::Receiver<BlockOrMany>,
+    shutdown: Shutdown,
+    flush_policy: FlushPolicy,
+) -> anyhow::Result<BlockProcessor> {
+    Ok(BlockProcessor::builder()
+        .processed_blocks(FifoSet::new(256))
+        .processed_txs(FifoSet::new(
+            300/*txs per block*/ * 255, /*max mergeset size*/
+        ))
+        .intake(intake)
+        .shutdown(shutdown)
+        .tx_keyspace(keyspace.clone())
+        .metadata_partition(MetadataPartition::new(keyspace)?)
+        .handshake_by_receiver_partition(HandshakeByReceiverPartition::new(keyspace)?)
+        .tx_id_to_handshake_partition(TxIdToHandshakePartition::new(keyspace)?)
+        .contextual_message_partition(ContextualMessageBySenderPartition::new(keyspace)?)
+        .payment_by_receiver_partition(PaymentByReceiverPartition::new(keyspace)?)
+        .tx_id_to_payment_partition(TxIdToPaymentPartition::new(keyspace)?)
+        .tx_id_to_acceptance_partition(TxIDToAcceptancePartition::new(keyspace)?)
+        .skip_tx_partition(SkipTxPartition::new(keyspace)?)
+        .skip_tx_by_block_partition(SkipTxByBlockPartition::new(keyspace)?)
+        .block_compact_header_partition(BlockCompactHeaderPartition::new(keyspace)?)
+        .block_daa_index(DaaIndexPartition::new(keyspace)?)
+        .chain_membership_partition(ChainMembershipPartition::new(keyspace)?)
+        .block_relations_partition(BlockRelationsPartition::new(keyspace)?)
+        .orphan_pool_partition(OrphanPoolPartition::new(keyspace)?)
+        .block_miner_partition(BlockMinerPartition::new(keyspace)?)
+        .miner_blocks_partition(MinerBlocksPartition::new(keyspace)?)
+        .outpoint_partition(OutpointPartition::new(keyspace)?)
+        .pending_spend_partition(PendingSpendPartition::new(keyspace)?)
+        .block_stats_partition(BlockStatsPartition::new(keyspace)?)
+        .block_transactions_partition(BlockTransactionsPartition::new(keyspace)?)
+        .processed_block_partition(ProcessedBlockPartition::new(keyspace)?)
+        .token_operation_partition(TokenOperationPartition::new(keyspace)?)
+        .metrics(create_shared_metrics())
+        .virtual_daa(Default::default())
+        .flush_policy(flush_policy)
+        .build())
+}
+
+/// Block of payments without parents, each block is processed as soon as it arrives
+fn block(i: u64) -> RpcBlock {
+    let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(i), vec![]);
+    header.daa_score = i;
+    let mut script = vec![0x20];
+    script.extend_from_slice(&[7; 32]);
+    script.push(0xac);
+    let transactions = (0..TXS_PER_BLOCK)
+        .map(|j| {
+            let tx = Transaction::new(
+                0,
+                vec![TransactionInput::new(
+                    TransactionOutpoint::new(RpcHash::from_u64_word(i), j as u32),
+                    vec![0; 66],
+                    0,
+                    1,
+                )],
+                vec![TransactionOutput::new(
+                    j,
+                    ScriptPublicKey::from_vec(0, script.clone()),
+                )],
+                0,
+                SUBNETWORK_ID_NATIVE,
+                0,
+                format!("ciph_msg:1:payment:{i:x}{j:x}").into_bytes(),
+            );
+            RpcTransaction::from(&tx)
+        })
+        .collect();
+    RpcBlock {
+        header: (&header).into(),
+        transactions,
+        verbose_data: None,
+    }
+}
@@ -0,0 +1,77 @@
+//! Measures block decoding throughput of the block processor per worker count.
+//!
+//! `cargo run --release --example block_preparation_bench`
+
+use indexer_lib::block_processor::prepare_blocks;
+use kaspa_consensus_core::header::Header;
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_NATIVE;
+use kaspa_consensus_core::tx::{
+    ScriptPublicKey, Transaction, TransactionInput, TransactionOutpoint, TransactionOutput,
+};
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransaction};
+use std::time::{Duration, Instant};
+
+const BLOCKS: u64 = 600;
+const TXS_PER_BLOCK: u64 = 300;
+const ROUNDS: u32 = 5;
+
+fn main() {
+    let blocks = (0..BLOCKS).map(block).collect::<Vec<_>>();
+    println!("{BLOCKS} blocks with {TXS_PER_BLOCK} transactions each, best of {ROUNDS} rounds");
+    let mut baseline = None;
+    for workers in [1, 2, 4, 8] {
+        let best = (0..ROUNDS)
+            .map(|_| {
+                let start = Instant::now();
+                let prepared = prepare_blocks(&blocks, workers, false);
+                let elapsed = start.elapsed();
+                assert!(prepared.iter().all(Result::is_ok));
+                std::hint::black_box(prepared);
+                elapsed
+            })
+            .min()
+            .unwrap_or(Duration::ZERO);
+        let baseline = *baseline.get_or_insert(best);
+        println!(
+            "{workers} workers: {best:?} ({:.0} blocks/s, speedup {:.2}x)",
+            BLOCKS as f64 / best.as_secs_f64(),
+            baseline.as_secs_f64() / best.as_secs_f64()
+        );
+    }
+}
+
+/// Block of payments without verbose data, so every transaction id is hashed
+fn block(i: u64) -> RpcBlock {
+    let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(i), vec![]);
+    header.daa_score = i;
+    let mut script = vec![0x20];
+    script.extend_from_slice(&[7; 32]);
+    script.push(0xac);
+    let transactions = (0..TXS_PER_BLOCK)
+        .map(|j| {
+            let tx = Transaction::new(
+                0,
+                vec![TransactionInput::new(
+                    TransactionOutpoint::new(RpcHash::from_u64_word(i), j as u32),
+                    vec![0; 66],
+                    0,
+                    1,
+                )],
+                vec![TransactionOutput::new(
+                    j,
+                    ScriptPublicKey::from_vec(0, script.clone()),
+                )],
+                0,
+                SUBNETWORK_ID_NATIVE,
+                0,
+                format!("ciph_msg:1:payment:{}", "ab".repeat(200)).into_bytes(),
+            );
+            RpcTransaction::from(&tx)
+        })
+        .collect();
+    RpcBlock {
+        header: (&header).into(),
+        transactions,
+        verbose_data: None,
+    }
+}
@@ -0,0 +1,49 @@
+//! Deduplicates block hashes from several threads through one mutex-wrapped FifoSet and
+//! through a sharded ConcurrentFifoSet.
+//!
+//! `cargo run --release --example concurrent_fifo_set_bench`
+
+use indexer_lib::fifo_set::{ConcurrentFifoSet, FastHasher, FifoSet};
+use kaspa_rpc_core::RpcHash;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+const CAPACITY: usize = 100_000;
+const INSERTS_PER_THREAD: u64 = 1_000_000;
+const SHARDS: usize = 64;
+
+fn main() {
+    for threads in [1, 2, 4, 8] {
+        let mutex = Mutex::new(FifoSet::with_hasher(CAPACITY, FastHasher::default()));
+        let mutex_elapsed = run(threads, |hash| mutex.lock().insert(hash));
+        let sharded = ConcurrentFifoSet::with_hasher(CAPACITY, SHARDS, FastHasher::default());
+        let sharded_elapsed = run(threads, |hash| sharded.insert(hash));
+        println!(
+            "{threads} threads: mutex {mutex_elapsed:?} ({:.0} inserts/s), sharded {sharded_elapsed:?} ({:.0} inserts/s, speedup {:.2}x)",
+            rate(threads, mutex_elapsed),
+            rate(threads, sharded_elapsed),
+            mutex_elapsed.as_secs_f64() / sharded_elapsed.as_secs_f64()
+        );
+    }
+}
+
+fn rate(threads: u64, elapsed: Duration) -> f64 {
+    (threads * INSERTS_PER_THREAD) as f64 / elapsed.as_secs_f64()
+}
+
+/// Every thread inserts its own hashes, each twice as a notification from a mirror node would
+fn run(threads: u64, insert: impl Fn(RpcHash) -> bool + Sync) -> Duration {
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for thread in 0..threads {
+            let insert = &insert;
+            scope.spawn(move || {
+                for i in 0..INSERTS_PER_THREAD {
+                    let word = thread * INSERTS_PER_THREAD + i / 2;
+                    std::hint::black_box(insert(RpcHash::from_u64_word(word)));
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
@@ -1,21 +1,28 @@
 use fjall::{Config, TxKeyspace};
+use indexer_lib::database::block_stats::BlockStatsPartition;
+use indexer_lib::database::block_transactions::BlockTransactionsPartition;
 use indexer_lib::database::headers::{
-    BlockCompactHeaderPartition, BlockGapsPartition, DaaIndexPartition,
+    BlockCompactHeaderPartition, BlockGapsPartition, BlockRelationsPartition,
+    ChainMembershipPartition, DaaIndexPartition,
 };
 use indexer_lib::database::messages::{
     ContextualMessageBySenderPartition, HandshakeByReceiverPartition, PaymentByReceiverPartition,
     TxIdToHandshakePartition, TxIdToPaymentPartition,
 };
 use indexer_lib::database::metadata::MetadataPartition;
+use indexer_lib::database::miners::{BlockMinerPartition, MinerBlocksPartition};
 use indexer_lib::database::processing::{
+    OrphanPoolPartition, OutpointPartition, PendingSpendPartition, ProcessedBlockPartition,
     SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition,
 };
+use indexer_lib::database::token_operations::TokenOperationPartition;
 use indexer_lib::metrics::create_shared_metrics;
 use indexer_lib::{
     BlockOrMany,
     block_processor::BlockProcessor,
     fifo_set::FifoSet,
     historical_syncer::{Cursor, HistoricalDataSyncer},
+    shutdown::Shutdown,
 };
 use kaspa_rpc_core::{GetBlockDagInfoResponse, GetServerInfoResponse, api::rpc::RpcApi};
 use kaspa_wrpc_client::{
@@ -76,7 +83,7 @@ async fn run_syncer() -> anyhow::Result<()> {
 
     // Create communication channels
     let (block_tx, block_rx) = flume::bounded::<BlockOrMany>(256);
-    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let shutdown = Shutdown::new();
 
     // Clone client for syncer task
     let syncer_client = client.clone();
@@ -84,11 +91,10 @@ async fn run_syncer() -> anyhow::Result<()> {
     let tx_keyspace = TxKeyspace::open(Config::default().temporary(true))?;
     let block_gaps = BlockGapsPartition::new(&tx_keyspace)?;
 
-    let (worker_shutdown_tx, worker_shutdown_rx) = flume::unbounded();
     let mut worker = BlockProcessor::builder()
         .processed_blocks(FifoSet::new(256))
         .intake(block_rx)
-        .shutdown(worker_shutdown_rx)
+        .shutdown(shutdown.clone())
         .tx_keyspace(tx_keyspace.clone())
         .metadata_partition(MetadataPartition::new(&tx_keyspace)?)
         .handshake_by_receiver_partition(HandshakeByReceiverPartition::new(&tx_keyspace)?)
@@ -105,30 +111,43 @@ async fn run_syncer() -> anyhow::Result<()> {
         ))
         .skip_tx_by_block_partition(SkipTxByBlockPartition::new(&tx_keyspace)?)
         .block_daa_index(DaaIndexPartition::new(&tx_keyspace)?)
+        .chain_membership_partition(ChainMembershipPartition::new(&tx_keyspace)?)
+        .block_relations_partition(BlockRelationsPartition::new(&tx_keyspace)?)
+        .orphan_pool_partition(OrphanPoolPartition::new(&tx_keyspace)?)
+        .block_miner_partition(BlockMinerPartition::new(&tx_keyspace)?)
+        .miner_blocks_partition(MinerBlocksPartition::new(&tx_keyspace)?)
+        .outpoint_partition(OutpointPartition::new(&tx_keyspace)?)
+        .pending_spend_partition(PendingSpendPartition::new(&tx_keyspace)?)
+        .block_stats_partition(BlockStatsPartition::new(&tx_keyspace)?)
+        .block_transactions_partition(BlockTransactionsPartition::new(&tx_keyspace)?)
+        .processed_block_partition(ProcessedBlockPartition::new(&tx_keyspace)?)
+        .token_operation_partition(TokenOperationPartition::new(&tx_keyspace)?)
+        .virtual_daa(Default::default())
         .build();
 
     info!("Starting syncer and block processor tasks");
 
     // Task 1: Historical data syncer
+    let syncer_shutdown = shutdown.clone();
     let syncer_handle = tokio::spawn(async move {
         let mut syncer = HistoricalDataSyncer::new(
             syncer_client,
             start_cursor,
             target_cursor,
             block_tx,
-            shutdown_rx,
+            syncer_shutdown,
             block_gaps,
         );
 
         if let Err(e) = syncer.sync().await {
-            error!("Syncer task failed: {}", e);
+            error!("Syncer task failed: {:#}", anyhow::Error::from(e));
         } else {
             info!("Syncer task completed successfully");
         }
     });
 
     // Task 2: Block processor (reads from flume channel)
-    let processor_handle = std::thread::spawn(move || {
+    let processor_handle = shutdown.spawn_blocking(move || {
         if let Err(e) = worker.process() {
             error!("Block worker failed: {}", e);
         } else {
@@ -140,8 +159,7 @@ async fn run_syncer() -> anyhow::Result<()> {
     tokio::select! {
         _ = signal::ctrl_c() => {
             warn!("Shutdown signal received");
-            let _ = shutdown_tx.send(());
-            let _ = worker_shutdown_tx.send(());
+            shutdown.cancel();
         }
         result = syncer_handle => {
             match result {
@@ -150,7 +168,7 @@ async fn run_syncer() -> anyhow::Result<()> {
             }
         }
     }
-    _ = processor_handle.join().inspect_err(|_err| {
+    _ = processor_handle.await.inspect_err(|_err| {
         error!("Block worker thread panicked");
     });
     // Cleanup
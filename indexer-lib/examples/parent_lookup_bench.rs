@@ -0,0 +1,115 @@
+//! Looks up the parents of blocks one by one and batched within a read snapshot, the lookup
+//! the block processor runs for every block, in both header storage modes. The header cache
+//! is disabled so every lookup reads the store.
+//!
+//! `cargo run --release --example parent_lookup_bench`
+
+use fjall::TxKeyspace;
+use indexer_lib::database::headers::{BlockCompactHeaderPartition, HeaderStorageMode};
+use kaspa_consensus_core::header::Header;
+use kaspa_rpc_core::{RpcHash, RpcHeader};
+use std::time::{Duration, Instant};
+
+const STORED: u64 = 200_000;
+const BLOCKS: u64 = 100_000;
+/// Parents of every block, as many as a block of a wide DAG references
+const PARENTS: u64 = 8;
+/// Every tenth parent is not stored
+const MISSING_EVERY: u64 = 10;
+
+fn main() -> anyhow::Result<()> {
+    for mode in [HeaderStorageMode::Compact, HeaderStorageMode::Full] {
+        let keyspace = fjall::Config::new(std::env::temp_dir().join(format!(
+            "kasia-indexer-parent-lookup-bench-{}-{mode:?}",
+            std::process::id()
+        )))
+        .temporary(true)
+        .open_transactional()?;
+        let partition =
+            BlockCompactHeaderPartition::new_with_mode(&keyspace, mode)?.with_cache_capacity(0);
+        println!("Storing {STORED} headers in {mode:?} mode");
+        for chunk in (0..STORED).collect::<Vec<_>>().chunks(10_000) {
+            let mut wtx = keyspace.write_tx()?;
+            for &n in chunk {
+                partition.insert_header_wtx(&mut wtx, &header(n))?;
+            }
+            wtx.commit()??;
+        }
+
+        println!("Looking up {PARENTS} parents of {BLOCKS} blocks");
+        let (found, elapsed) = one_by_one(&partition, mode)?;
+        println!("one by one: {found} found, {elapsed:?}");
+        let (batched_found, batched_elapsed) = batched(&keyspace, &partition, mode)?;
+        println!(
+            "batched: {batched_found} found, {batched_elapsed:?} (speedup {:.2}x)",
+            elapsed.as_secs_f64() / batched_elapsed.as_secs_f64()
+        );
+        assert_eq!(found, batched_found);
+    }
+    Ok(())
+}
+
+fn one_by_one(
+    partition: &BlockCompactHeaderPartition,
+    mode: HeaderStorageMode,
+) -> anyhow::Result<(u64, Duration)> {
+    let mut found = 0;
+    let start = Instant::now();
+    for block in 0..BLOCKS {
+        for parent in parents(block) {
+            let stored = match mode {
+                HeaderStorageMode::Compact => partition.get_daa_score(parent)?.is_some(),
+                HeaderStorageMode::Full => partition.get_stored_header(parent)?.is_some(),
+            };
+            found += stored as u64;
+        }
+    }
+    Ok((found, start.elapsed()))
+}
+
+fn batched(
+    keyspace: &TxKeyspace,
+    partition: &BlockCompactHeaderPartition,
+    mode: HeaderStorageMode,
+) -> anyhow::Result<(u64, Duration)> {
+    let mut found = 0;
+    let start = Instant::now();
+    for block in 0..BLOCKS {
+        let parents = parents(block);
+        let rtx = keyspace.read_tx();
+        found += match mode {
+            HeaderStorageMode::Compact => partition
+                .get_many_rtx(&rtx, &parents)?
+                .iter()
+                .flatten()
+                .count(),
+            HeaderStorageMode::Full => partition
+                .get_many_stored_rtx(&rtx, &parents)?
+                .iter()
+                .flatten()
+                .count(),
+        } as u64;
+    }
+    Ok((found, start.elapsed()))
+}
+
+fn parents(block: u64) -> Vec<RpcHash> {
+    (0..PARENTS)
+        .map(|p| {
+            let n = (block * PARENTS + p) % STORED;
+            match (block * PARENTS + p) % MISSING_EVERY {
+                0 => RpcHash::from_u64_word(STORED + n),
+                _ => RpcHash::from_u64_word(n),
+            }
+        })
+        .collect()
+}
+
+fn header(n: u64) -> RpcHeader {
+    let parents = (1..=PARENTS)
+        .map(|p| RpcHash::from_u64_word(n.saturating_sub(p)))
+        .collect();
+    let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(n), parents);
+    header.daa_score = n;
+    RpcHeader::from(&header)
+}
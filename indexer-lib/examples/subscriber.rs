@@ -1,7 +1,8 @@
 use fjall::{Config, TxKeyspace};
 use indexer_lib::database::headers::BlockGapsPartition;
+use indexer_lib::database::provenance::ProvenancePartition;
 use indexer_lib::selected_chain_syncer::Intake;
-use indexer_lib::{BlockOrMany, subscriber::Subscriber};
+use indexer_lib::{BlockOrMany, shutdown::Shutdown, subscriber::Subscriber};
 use kaspa_wrpc_client::{
     KaspaRpcClient, Resolver, WrpcEncoding,
     client::{ConnectOptions, ConnectStrategy},
@@ -40,7 +41,7 @@ async fn run_subscriber() -> anyhow::Result<()> {
     // Create communication channels
     let (block_tx, block_rx) = flume::bounded::<BlockOrMany>(256);
     let (intake_tx, mut intake_rx) = tokio::sync::mpsc::channel::<Intake>(256);
-    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let shutdown = Shutdown::new();
 
     // Create worker task that prints intake events and block information
     let worker_handle = tokio::spawn(async move {
@@ -88,10 +89,10 @@ async fn run_subscriber() -> anyhow::Result<()> {
 
         while let Ok(block) = block_rx.recv() {
             match block {
-                BlockOrMany::Block(block) => {
+                BlockOrMany::Block(block, ..) => {
                     info!("📋 BLOCK RECEIVED: {:?}", block.header.hash);
                 }
-                BlockOrMany::Many(blocks) => {
+                BlockOrMany::Many(blocks, ..) => {
                     info!("📋 BLOCKS RECEIVED: {} blocks", blocks.len());
                 }
             }
@@ -106,18 +107,23 @@ async fn run_subscriber() -> anyhow::Result<()> {
     // Setup database for block gaps
     let tx_keyspace = TxKeyspace::open(Config::default().temporary(true))?;
     let block_gaps_partition = BlockGapsPartition::new(&tx_keyspace)?;
+    let provenance_partition = ProvenancePartition::new(&tx_keyspace)?;
 
     // Create subscriber for real-time notifications
     let subscriber_client = client.clone();
+    let subscriber_shutdown = shutdown.clone();
     let subscriber_handle = tokio::spawn(async move {
         let mut subscriber = Subscriber::new(
             subscriber_client,
             block_tx,
-            shutdown_rx,
+            subscriber_shutdown,
             block_gaps_partition,
+            provenance_partition,
             intake_tx,
             None,
             Default::default(),
+            Default::default(),
+            Default::default(),
         );
         if let Err(e) = subscriber.task().await {
             error!("Subscriber task failed: {}", e);
@@ -132,7 +138,7 @@ async fn run_subscriber() -> anyhow::Result<()> {
     tokio::select! {
         _ = signal::ctrl_c() => {
             warn!("Shutdown signal received");
-            let _ = shutdown_tx.send(());
+            shutdown.cancel();
         }
         result = subscriber_handle => {
             match result {
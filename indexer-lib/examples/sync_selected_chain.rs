@@ -1,10 +1,12 @@
 use fjall::{Config, TxKeyspace};
 use indexer_lib::database::headers::{BlockCompactHeaderPartition, BlockGapsPartition};
+use indexer_lib::database::provenance::ProvenancePartition;
 use indexer_lib::virtual_chain_processor::VirtualChainChangedNotificationAndBlueWork;
 use indexer_lib::{
     BlockOrMany,
     database::metadata::MetadataPartition,
     selected_chain_syncer::{Intake, SelectedChainSyncer},
+    shutdown::Shutdown,
     subscriber::Subscriber,
 };
 use kaspa_wrpc_client::{
@@ -50,13 +52,14 @@ async fn run_selected_chain_syncer() -> anyhow::Result<()> {
     let metadata_partition = MetadataPartition::new(&tx_keyspace)?;
     let block_compact_header_partition = BlockCompactHeaderPartition::new(&tx_keyspace)?;
     let block_gaps_partition = BlockGapsPartition::new(&tx_keyspace)?;
+    let provenance_partition = ProvenancePartition::new(&tx_keyspace)?;
 
     // Create communication channels
     let (block_tx, block_rx) = flume::bounded::<BlockOrMany>(256);
     let (intake_tx, intake_rx) = tokio::sync::mpsc::channel::<Intake>(256);
     let (historical_sync_done_tx, historical_sync_done_rx) = tokio::sync::mpsc::channel(256);
     let (worker_tx, worker_rx) = flume::bounded(256);
-    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let shutdown = Shutdown::new();
 
     // Create selected chain syncer
     let mut selected_chain_syncer = SelectedChainSyncer::new(
@@ -67,21 +70,24 @@ async fn run_selected_chain_syncer() -> anyhow::Result<()> {
         historical_sync_done_rx,
         historical_sync_done_tx,
         worker_tx,
-        shutdown_rx,
+        shutdown.clone(),
     );
 
-    let (subscriber_shutdown_tx, subscriber_shutdown_rx) = tokio::sync::oneshot::channel();
+    let subscriber_shutdown = shutdown.clone();
     // Create subscriber for real-time notifications
     let subscriber_client = client.clone();
     let subscriber_handle = tokio::spawn(async move {
         let mut subscriber = Subscriber::new(
             subscriber_client,
             block_tx,
-            subscriber_shutdown_rx,
+            subscriber_shutdown,
             block_gaps_partition,
+            provenance_partition,
             intake_tx,
             None,
             Default::default(),
+            Default::default(),
+            Default::default(),
         );
         if let Err(e) = subscriber.task().await {
             error!("Subscriber task failed: {}", e);
@@ -135,10 +141,10 @@ async fn run_selected_chain_syncer() -> anyhow::Result<()> {
 
         while let Ok(block) = block_rx.recv() {
             match block {
-                BlockOrMany::Block(block) => {
+                BlockOrMany::Block(block, ..) => {
                     info!("📋 BLOCK RECEIVED: {:?}", block.header.hash);
                 }
-                BlockOrMany::Many(blocks) => {
+                BlockOrMany::Many(blocks, ..) => {
                     info!("📋 BLOCKS RECEIVED: {} blocks", blocks.len());
                 }
             }
@@ -162,8 +168,7 @@ async fn run_selected_chain_syncer() -> anyhow::Result<()> {
     tokio::select! {
         _ = signal::ctrl_c() => {
             warn!("Shutdown signal received");
-            let _ = shutdown_tx.send(());
-            let _ = subscriber_shutdown_tx.send(());
+            shutdown.cancel();
         }
         result = syncer_handle => {
             match result {
@@ -0,0 +1,22 @@
+//! Cuts a recorded fixture down to the blocks of a DAA score range, see `indexer_lib::fixture`.
+//!
+//! `cargo run --example trim_fixture -- <fixture> <trimmed> <from_daa> <to_daa>`
+
+use indexer_lib::fixture::Fixture;
+use std::path::Path;
+
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [fixture, trimmed, from, to] = args.as_slice() else {
+        anyhow::bail!("usage: trim_fixture <fixture> <trimmed> <from_daa> <to_daa>");
+    };
+    let mut entries = Fixture::load(Path::new(fixture))?;
+    let recorded = entries.entries.len();
+    entries.trim(from.parse()?..=to.parse()?);
+    entries.save(Path::new(trimmed))?;
+    println!(
+        "kept {} of {recorded} entries in {trimmed}",
+        entries.entries.len()
+    );
+    Ok(())
+}
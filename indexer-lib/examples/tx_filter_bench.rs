@@ -0,0 +1,110 @@
+//! Looks up transactions of which 99% were never indexed, reading the store for every lookup
+//! and only for those passing the transaction id filter.
+//!
+//! `cargo run --release --example tx_filter_bench`
+
+use indexer_lib::database::processing::{
+    AcceptanceTxKey, AcceptingBlockResolutionData, DEFAULT_TX_FILTER_FP_RATE_PPM,
+    TxIDToAcceptancePartition, TxIdFilter,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const STORED: u64 = 1_000_000;
+const LOOKUPS: u64 = 1_000_000;
+/// Every hundredth lookup is for a stored transaction
+const HIT_EVERY: u64 = 100;
+
+fn main() -> anyhow::Result<()> {
+    let keyspace = fjall::Config::new(std::env::temp_dir().join(format!(
+        "kasia-indexer-tx-filter-bench-{}",
+        std::process::id()
+    )))
+    .temporary(true)
+    .open_transactional()?;
+    let partition = TxIDToAcceptancePartition::new(&keyspace)?;
+    println!("Storing {STORED} transactions");
+    for chunk in (0..STORED).collect::<Vec<_>>().chunks(10_000) {
+        let mut wtx = keyspace.write_tx()?;
+        for &n in chunk {
+            let key = AcceptanceTxKey {
+                tx_id: tx_id(n),
+                accepted_at_daa: n.to_be_bytes(),
+                accepted_by_block_hash: [1; 32],
+                partition_id: 0,
+            };
+            partition.insert_wtx(&mut wtx, &key, AcceptingBlockResolutionData::None);
+        }
+        wtx.commit()??;
+    }
+
+    let start = Instant::now();
+    let filter = Arc::new(TxIdFilter::build(
+        STORED as usize,
+        DEFAULT_TX_FILTER_FP_RATE_PPM,
+        &partition,
+        &keyspace.read_tx(),
+    )?);
+    println!(
+        "Built the filter in {:?}, estimated false positive rate {} ppm",
+        start.elapsed(),
+        filter.stats().fp_estimate_ppm
+    );
+
+    println!("Looking up {LOOKUPS} transactions, one in {HIT_EVERY} stored");
+    let (reads, found, elapsed) = lookups(&keyspace, &partition)?;
+    println!("without filter: {reads} store reads, {found} found, {elapsed:?}");
+    let filtered = partition.clone().with_filter(filter.clone());
+    let (filtered_reads, filtered_found, filtered_elapsed) = lookups(&keyspace, &filtered)?;
+    println!(
+        "with filter: {filtered_reads} store reads, {filtered_found} found, {filtered_elapsed:?} ({:.1}x fewer reads, speedup {:.2}x)",
+        reads as f64 / filtered_reads as f64,
+        elapsed.as_secs_f64() / filtered_elapsed.as_secs_f64()
+    );
+    println!("filter stats: {:?}", filter.stats());
+    assert_eq!(found, filtered_found);
+    Ok(())
+}
+
+/// Store reads, transactions found and the time taken, the lookup of the query API
+fn lookups(
+    keyspace: &fjall::TxKeyspace,
+    partition: &TxIDToAcceptancePartition,
+) -> anyhow::Result<(u64, u64, Duration)> {
+    let (mut reads, mut found) = (0, 0);
+    let start = Instant::now();
+    for i in 0..LOOKUPS {
+        let tx_id = match i % HIT_EVERY {
+            0 => tx_id(i % STORED),
+            _ => tx_id(STORED + i),
+        };
+        if !partition.may_contain(&tx_id) {
+            continue;
+        }
+        reads += 1;
+        let rtx = keyspace.read_tx();
+        match partition.get_by_tx_id(&rtx, &tx_id).next_back() {
+            Some(entry) => {
+                entry?;
+                found += 1;
+            }
+            None => partition.record_false_positive(),
+        }
+    }
+    Ok((reads, found, start.elapsed()))
+}
+
+/// Distinct ids spread like hashes
+fn tx_id(n: u64) -> [u8; 32] {
+    let mut state = n;
+    let mut id = [0u8; 32];
+    for chunk in id.chunks_mut(8) {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    id
+}
@@ -0,0 +1,178 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+/// Throttling is lifted once the latency drops below this share of the target
+const RECOVERY_RATIO: f64 = 0.8;
+/// Weight of the newest sample in the moving average
+const EWMA_ALPHA: f64 = 0.2;
+/// A breach lasting longer than this is reported as an error
+const SUSTAINED_BREACH_ALERT: Duration = Duration::from_secs(60);
+
+pub type SharedAcceptanceSlo = Arc<AcceptanceSlo>;
+
+/// Latency objective for acceptance (VCC) commits.
+///
+/// The virtual chain processor reports every commit latency, backfill work (gap syncers)
+/// waits in [`AcceptanceSlo::wait_until_clear`] while the objective is breached.
+/// Throttling starts when the moving average exceeds the target and stops only once it
+/// drops below `RECOVERY_RATIO` of the target, so enforcement doesn't flap.
+pub struct AcceptanceSlo {
+    target: Duration,
+    throttled: AtomicBool,
+    state: Mutex<SloState>,
+    clear: Notify,
+}
+
+#[derive(Default)]
+struct SloState {
+    average: Option<Duration>,
+    breached_since: Option<Instant>,
+    last_alert: Option<Instant>,
+    samples: u64,
+    samples_over_target: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SloStatus {
+    pub target: Duration,
+    pub average_latency: Option<Duration>,
+    pub throttling: bool,
+    pub samples: u64,
+    pub samples_over_target: u64,
+}
+
+impl SloStatus {
+    /// Share of commits which met the target
+    pub fn compliance(&self) -> f64 {
+        if self.samples == 0 {
+            1.0
+        } else {
+            1.0 - self.samples_over_target as f64 / self.samples as f64
+        }
+    }
+}
+
+impl AcceptanceSlo {
+    pub fn new(target: Duration) -> Self {
+        Self {
+            target,
+            throttled: AtomicBool::new(false),
+            state: Default::default(),
+            clear: Notify::new(),
+        }
+    }
+
+    pub fn is_throttling(&self) -> bool {
+        self.throttled.load(Ordering::Acquire)
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let mut state = self.state.lock();
+        state.samples += 1;
+        if latency > self.target {
+            state.samples_over_target += 1;
+        }
+        let average = match state.average {
+            None => latency,
+            Some(average) => average.mul_f64(1.0 - EWMA_ALPHA) + latency.mul_f64(EWMA_ALPHA),
+        };
+        state.average = Some(average);
+
+        let throttled = self.is_throttling();
+        if !throttled && average > self.target {
+            warn!(?average, target = ?self.target, "Acceptance latency SLO breached, throttling backfill");
+            state.breached_since = Some(Instant::now());
+            self.throttled.store(true, Ordering::Release);
+        } else if throttled && average < self.target.mul_f64(RECOVERY_RATIO) {
+            info!(?average, target = ?self.target, "Acceptance latency recovered, resuming backfill");
+            state.breached_since = None;
+            self.throttled.store(false, Ordering::Release);
+            self.clear.notify_waiters();
+        } else if let Some(since) = state.breached_since
+            && since.elapsed() > SUSTAINED_BREACH_ALERT
+            && state
+                .last_alert
+                .is_none_or(|last| last.elapsed() > SUSTAINED_BREACH_ALERT)
+        {
+            error!(?average, breached_for = ?since.elapsed(), "Acceptance latency SLO breach is sustained");
+            state.last_alert = Some(Instant::now());
+        }
+    }
+
+    /// Resolves immediately unless the objective is breached, otherwise once it recovers
+    pub async fn wait_until_clear(&self) {
+        loop {
+            let notified = self.clear.notified();
+            if !self.is_throttling() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn status(&self) -> SloStatus {
+        let state = self.state.lock();
+        SloStatus {
+            target: self.target,
+            average_latency: state.average,
+            throttling: self.is_throttling(),
+            samples: state.samples,
+            samples_over_target: state.samples_over_target,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hysteresis() {
+        let slo = AcceptanceSlo::new(Duration::from_millis(100));
+        slo.record(Duration::from_millis(50));
+        assert!(!slo.is_throttling());
+
+        slo.record(Duration::from_millis(1000));
+        assert!(slo.is_throttling());
+
+        // back under the target but above the recovery threshold, keeps throttling
+        for _ in 0..50 {
+            slo.record(Duration::from_millis(90));
+            assert!(slo.is_throttling());
+        }
+        for _ in 0..20 {
+            slo.record(Duration::from_millis(10));
+        }
+        assert!(!slo.is_throttling());
+
+        let status = slo.status();
+        assert!(status.compliance() < 1.0);
+        assert_eq!(status.samples_over_target, 1);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_waits_for_recovery() {
+        let slo = Arc::new(AcceptanceSlo::new(Duration::from_millis(100)));
+        slo.wait_until_clear().await;
+
+        slo.record(Duration::from_secs(1));
+        let waiter = tokio::spawn({
+            let slo = slo.clone();
+            async move { slo.wait_until_clear().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        for _ in 0..30 {
+            slo.record(Duration::from_millis(1));
+        }
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("backfill must resume after recovery")
+            .unwrap();
+    }
+}
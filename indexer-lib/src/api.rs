@@ -0,0 +1,2544 @@
+//! HTTP query API over the indexed data.
+//!
+//! [`serve`] answers JSON read from the local database, each request within a single snapshot,
+//! the node is never called:
+//!
+//! - `GET /blocks/{hash}`: the block with its chain index, merge set, stats, miner and
+//!   transactions, see [`Queries::get_block_full`]
+//! - `GET /blocks?daa_from=&daa_to=&limit=&cursor=`: blocks of the DAA range, `daa_to`
+//!   excluded
+//! - `GET /blocks/{hash}/relations`: selected parent and merge set of a block
+//! - `GET /dag/{hash}?depth=`: the block and its past up to `depth` steps, for DAG
+//!   visualization, see [`QueryApi::get_dag_neighborhood`]
+//! - `GET /chain?from=&to=&limit=&offset=&cursor=`: selected chain blocks from `from` to `to`
+//!   or the tip, paged by `offset` from `from` or by `cursor`, see [`QueryApi::get_chain_path`]
+//! - `GET /transactions/{id}`: acceptance and confirmations of an indexed transaction, or the
+//!   mempool entry of a pending one when the mempool is tracked
+//! - `GET /transactions/{id}/acceptance-history`: acceptances and reorgs of an indexed
+//!   transaction, see [`QueryApi::acceptance_history`]
+//! - `GET /mempool`: count and fee rate percentiles of the pending transactions
+//! - `GET /addresses/{address}/transactions?from_daa=&limit=&cursor=`: handshakes, payments
+//!   and contextual messages sent or received by the address
+//! - `GET /addresses/{address}/export?daa_from=&daa_to=`: the whole history of the address as
+//!   a chunked CSV or NDJSON stream depending on `Accept`, see
+//!   [`Queries::stream_address_history`]
+//! - `GET /status`: the [`status::Indexer`] snapshot
+//! - `GET /schema`: layout of every partition for tools reading the database files, see
+//!   [`schema::describe_json`]
+//! - `GET /ws`: WebSocket push stream of newly indexed blocks, chain changes and address
+//!   messages, see [`ws`]
+//! - `/webhooks`: management of the webhook subscriptions when they are enabled, see
+//!   `webhooks`
+//! - `/admin/profile`: CPU and heap profiles when profiling is enabled, see `profile`
+//!
+//! Listings hold up to `limit` entries, [`DEFAULT_LIMIT`] if unset, ordered by DAA score, with
+//! the DAA score the next page starts from. Pages end at a DAA score boundary, a page is only
+//! longer than `limit` when its first DAA score alone holds more entries.
+//!
+//! Paged listings also return a `next_cursor`, passed back as `cursor` it takes precedence
+//! over `daa_from`, `offset` and `from_daa`. A cursor whose position was pruned since it was
+//! issued is answered with 410 `cursor_expired` and the `earliest_cursor` to restart from, see
+//! [`cursor`].
+
+use crate::database::block_stats::{BlockStats, BlockStatsPartition};
+use crate::database::confirmations::Confirmations;
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockRelations, BlockRelationsPartition,
+    ChainIndexByHashPartition, ChainIndexPartition, DaaIndexPartition,
+};
+use crate::database::messages::AddressPayload;
+use crate::database::metadata::{BlocksPruned, MetadataPartition};
+use crate::database::miners::{BlockMiner, BlockMinerPartition};
+use crate::database::processing::{
+    AcceptanceChange, AcceptanceHistoryPartition, AcceptanceHistoryRecord, FinalizedTxPartition,
+    TxIDToAcceptancePartition, TxIdFilter,
+};
+use crate::database::schema;
+use crate::error::IndexerError;
+use crate::ingest_filter::IngestFilterState;
+use crate::mempool::{Mempool, MempoolEntry, MempoolSummary};
+use crate::metrics::SharedMetrics;
+use crate::metrics_exporter::serve_router;
+use crate::queries::{
+    AddressHistoryRecord, AddressHistoryStream, BlockTransaction, FullBlock, Queries, TxAcceptance,
+};
+use crate::shutdown::Shutdown;
+use crate::status;
+use anyhow::Result;
+use axum::body::Body;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{ConnectInfo, FromRequestParts, Path, Query, Request, State};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use cursor::PageCursor;
+use fjall::{ReadTransaction, TxKeyspace};
+use futures_util::{Stream, StreamExt};
+use kaspa_addresses::Prefix;
+use kaspa_rpc_core::{RpcAddress, RpcHash, RpcTransactionId};
+use rate_limit::RateLimiter;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+pub use crate::block_events::MessageKind;
+pub use crate::queries::Direction;
+
+pub mod cursor;
+#[cfg(feature = "profiling")]
+pub mod profile;
+pub mod rate_limit;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+pub mod ws;
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+pub const DEFAULT_MAX_DAA_RANGE: u64 = 100_000;
+pub const DEFAULT_DAG_DEPTH: u32 = 3;
+pub const MAX_DAG_DEPTH: u32 = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockResponse {
+    pub hash: String,
+    pub daa_score: u64,
+    pub blue_work: String,
+    /// Position in the selected chain, none for blocks off the chain
+    pub chain_index: Option<u64>,
+    /// None while the chain membership is not recorded
+    pub is_chain_block: Option<bool>,
+    /// None for blocks indexed without verbose data
+    pub selected_parent: Option<String>,
+    /// None for blocks indexed before their stats were recorded
+    pub stats: Option<BlockStatsResponse>,
+    /// None while unattributed or when the coinbase pays to no address
+    pub miner: Option<String>,
+    /// None when the transactions of the block aren't indexed, e.g. synced header only
+    pub transactions: Option<Vec<BlockTransactionResponse>>,
+    /// Transactions of the block were left out by the ingest filter, not indexed by
+    /// configuration
+    pub filtered: bool,
+}
+
+impl From<FullBlock> for BlockResponse {
+    fn from(block: FullBlock) -> Self {
+        Self {
+            hash: block.hash.to_string(),
+            daa_score: block.daa_score,
+            blue_work: block.blue_work.to_string(),
+            chain_index: block.chain_index,
+            is_chain_block: block.is_chain_block,
+            selected_parent: block
+                .relations
+                .map(|relations| relations.selected_parent.to_string()),
+            stats: block.stats.map(BlockStatsResponse::from),
+            miner: match block.miner {
+                Some(BlockMiner::Parsed { address, .. }) => Some(address.to_string()),
+                Some(BlockMiner::ParseFailed) | None => None,
+            },
+            transactions: block.transactions.map(|transactions| {
+                transactions
+                    .into_iter()
+                    .map(BlockTransactionResponse::from)
+                    .collect()
+            }),
+            filtered: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcceptanceStatus {
+    Accepted,
+    /// A protocol transaction not accepted yet
+    NotAccepted,
+    /// Acceptance is only recorded for protocol transactions
+    Untracked,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockTransactionResponse {
+    pub tx_id: String,
+    pub acceptance: AcceptanceStatus,
+    pub accepting_block_hash: Option<String>,
+    pub accepting_daa_score: Option<u64>,
+}
+
+impl From<BlockTransaction> for BlockTransactionResponse {
+    fn from(tx: BlockTransaction) -> Self {
+        let (acceptance, accepting_block_hash, accepting_daa_score) = match tx.acceptance {
+            TxAcceptance::Accepted {
+                accepting_block_hash,
+                accepting_daa_score,
+            } => (
+                AcceptanceStatus::Accepted,
+                Some(accepting_block_hash.to_string()),
+                Some(accepting_daa_score),
+            ),
+            TxAcceptance::NotAccepted => (AcceptanceStatus::NotAccepted, None, None),
+            TxAcceptance::Untracked => (AcceptanceStatus::Untracked, None, None),
+        };
+        Self {
+            tx_id: tx.tx_id.to_string(),
+            acceptance,
+            accepting_block_hash,
+            accepting_daa_score,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockStatsResponse {
+    pub tx_count: u64,
+    pub total_mass: u64,
+    pub total_fees: u64,
+    /// Some input could not be resolved, the fees are incomplete
+    pub partial: bool,
+    /// Sompi per gram
+    pub fee_rate: Option<f64>,
+}
+
+impl From<BlockStats> for BlockStatsResponse {
+    fn from(stats: BlockStats) -> Self {
+        Self {
+            tx_count: stats.tx_count,
+            total_mass: stats.total_mass,
+            total_fees: stats.total_fees,
+            partial: stats.partial,
+            fee_rate: stats.fee_rate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSummary {
+    pub hash: String,
+    pub daa_score: u64,
+    /// None once the header was pruned
+    pub blue_work: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockListResponse {
+    pub blocks: Vec<BlockSummary>,
+    /// None on the last page
+    pub next_daa_from: Option<u64>,
+    /// [`PageCursor`] of the next page, none on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainBlockSummary {
+    pub hash: String,
+    pub chain_index: u64,
+    /// None once the header was pruned
+    pub daa_score: Option<u64>,
+    pub blue_work: Option<String>,
+    /// None for blocks indexed before their stats were recorded
+    pub tx_count: Option<u64>,
+    /// None while unattributed or when the coinbase pays to no address
+    pub miner: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainPathResponse {
+    pub blocks: Vec<ChainBlockSummary>,
+    /// Offset of the next page, none on the last page
+    pub next_offset: Option<u64>,
+    /// [`PageCursor`] of the next page, none on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRelationsResponse {
+    pub hash: String,
+    pub selected_parent: String,
+    /// Starts with the selected parent
+    pub merge_set_blues: Vec<String>,
+    pub merge_set_reds: Vec<String>,
+}
+
+impl BlockRelationsResponse {
+    fn new(hash: RpcHash, relations: BlockRelations) -> Self {
+        let strings = |hashes: Vec<RpcHash>| hashes.iter().map(RpcHash::to_string).collect();
+        Self {
+            hash: hash.to_string(),
+            selected_parent: relations.selected_parent.to_string(),
+            merge_set_blues: strings(relations.merge_set_blues),
+            merge_set_reds: strings(relations.merge_set_reds),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DagBlock {
+    pub hash: String,
+    /// Steps from the requested block
+    pub depth: u32,
+    /// None once the header was pruned
+    pub daa_score: Option<u64>,
+    /// None for blocks not indexed or pruned, their past is left out
+    pub relations: Option<BlockRelationsResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DagNeighborhoodResponse {
+    /// Breadth-first from the requested block
+    pub blocks: Vec<DagBlock>,
+    /// Blocks were left out to stay within the neighborhood size limit
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// In the node mempool, not indexed in a block yet
+    Pending,
+    /// Indexed in a block, not accepted yet
+    Indexed,
+    Accepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingResponse {
+    pub first_seen_ms: u64,
+    pub fee: u64,
+    /// Sompi per gram
+    pub fee_rate: Option<f64>,
+}
+
+impl From<MempoolEntry> for PendingResponse {
+    fn from(entry: MempoolEntry) -> Self {
+        Self {
+            first_seen_ms: entry.first_seen_ms,
+            fee: entry.fee,
+            fee_rate: entry.fee_rate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionResponse {
+    pub tx_id: String,
+    pub status: TransactionStatus,
+    /// None while pending, mempool transactions aren't parsed
+    pub kind: Option<MessageKind>,
+    /// Mempool entry of a pending transaction
+    pub pending: Option<PendingResponse>,
+    /// None while not accepted
+    pub accepting_block_hash: Option<String>,
+    pub accepting_daa_score: Option<u64>,
+    /// Counted along the chain index, see [`Confirmations`]
+    pub confirmations: Option<u64>,
+    /// Accepted deeper than the finality depth
+    pub finalized: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcceptanceChangeKind {
+    Accepted,
+    /// The accepting block left the selected chain
+    ReorgedOut,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptanceChangeResponse {
+    pub change: AcceptanceChangeKind,
+    /// Accepting block, or the chain block reorged out
+    pub block_hash: String,
+    /// None while unresolved
+    pub daa_score: Option<u64>,
+    /// None for the only acceptance of a transaction never reorged out
+    pub recorded_at_ms: Option<u64>,
+}
+
+impl TryFrom<AcceptanceHistoryRecord> for AcceptanceChangeResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(record: AcceptanceHistoryRecord) -> Result<Self> {
+        Ok(Self {
+            change: match record.change()? {
+                AcceptanceChange::Accepted => AcceptanceChangeKind::Accepted,
+                AcceptanceChange::ReorgedOut => AcceptanceChangeKind::ReorgedOut,
+            },
+            block_hash: RpcHash::from_bytes(record.block_hash).to_string(),
+            daa_score: Some(u64::from_be_bytes(record.daa_score)).filter(|daa| *daa != 0),
+            recorded_at_ms: Some(record.recorded_at_millis()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptanceHistoryResponse {
+    pub tx_id: String,
+    /// Oldest first
+    pub changes: Vec<AcceptanceChangeResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressTransaction {
+    pub tx_id: String,
+    pub kind: MessageKind,
+    pub direction: Direction,
+    pub block_hash: String,
+    pub block_time_ms: u64,
+    pub daa_score: u64,
+}
+
+/// Row of an address history export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressHistoryRow {
+    pub timestamp_ms: u64,
+    pub daa_score: u64,
+    pub block_hash: String,
+    pub tx_id: String,
+    pub kind: MessageKind,
+    pub direction: Direction,
+    pub amount: Option<u64>,
+    pub counterparts: Vec<String>,
+    pub confirmations: Option<u64>,
+}
+
+impl From<AddressHistoryRecord> for AddressHistoryRow {
+    fn from(record: AddressHistoryRecord) -> Self {
+        Self {
+            timestamp_ms: record.timestamp_ms,
+            daa_score: record.daa_score,
+            block_hash: record.block_hash.to_string(),
+            tx_id: record.tx_id.to_string(),
+            kind: record.kind,
+            direction: record.direction,
+            amount: record.amount,
+            counterparts: record
+                .counterparts
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            confirmations: record.confirmations,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Counterparts separated by `;`, empty cells for missing values
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    const CSV_HEADER: &str = "timestamp_ms,daa_score,block_hash,tx_id,kind,direction,amount,\
+                              counterparts,confirmations\n";
+
+    /// CSV when the `Accept` header asks for `text/csv`, NDJSON otherwise
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        let csv = accept.is_some_and(|accept| {
+            accept
+                .split(',')
+                .any(|media| media.split(';').next().unwrap_or_default().trim() == "text/csv")
+        });
+        if csv { Self::Csv } else { Self::Ndjson }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// One line per row
+    pub fn render(self, row: &AddressHistoryRow) -> Result<String> {
+        let optional =
+            |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+        Ok(match self {
+            Self::Csv => format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                row.timestamp_ms,
+                row.daa_score,
+                row.block_hash,
+                row.tx_id,
+                row.kind,
+                row.direction,
+                optional(row.amount),
+                row.counterparts.join(";"),
+                optional(row.confirmations)
+            ),
+            Self::Ndjson => serde_json::to_string(row)? + "\n",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MempoolSummaryResponse {
+    pub count: usize,
+    /// Percentiles of the fee rates in sompi per gram, none while no entry has mass
+    pub fee_rate_p50: Option<f64>,
+    pub fee_rate_p90: Option<f64>,
+    pub fee_rate_p99: Option<f64>,
+}
+
+impl From<MempoolSummary> for MempoolSummaryResponse {
+    fn from(summary: MempoolSummary) -> Self {
+        let percentile = |i: usize| {
+            summary
+                .fee_rate_percentiles
+                .as_ref()
+                .and_then(|rates| rates.get(i).copied())
+        };
+        Self {
+            count: summary.count,
+            fee_rate_p50: percentile(0),
+            fee_rate_p90: percentile(1),
+            fee_rate_p99: percentile(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressTransactionsResponse {
+    pub transactions: Vec<AddressTransaction>,
+    /// None on the last page
+    pub next_from_daa: Option<u64>,
+    /// [`PageCursor`] of the next page, none on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Caps on the work of a single request, checked before the store is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryLimits {
+    pub max_limit: usize,
+    /// Widest `daa_from..daa_to` of a block listing
+    pub max_daa_range: u64,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_limit: MAX_LIMIT,
+            max_daa_range: DEFAULT_MAX_DAA_RANGE,
+        }
+    }
+}
+
+/// Why a request was refused before being answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The rate limit of the client address is used up
+    RateLimited,
+    /// The concurrent requests are at their cap
+    Overloaded,
+    /// `limit` over [`QueryLimits::max_limit`]
+    Limit,
+    /// DAA range wider than [`QueryLimits::max_daa_range`]
+    DaaRange,
+}
+
+impl Rejection {
+    fn code(self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::Overloaded => "overloaded",
+            Self::Limit => "limit",
+            Self::DaaRange => "daa_range",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Rejected(Rejection, String),
+    NotFound(String),
+    /// Left out by the ingest filter
+    NotIndexed(String),
+    /// The block left the selected chain
+    Conflict(String),
+    /// The position of the page cursor was pruned, holds the cursor to restart from
+    CursorExpired(PageCursor),
+    Unauthorized,
+    MethodNotAllowed,
+    /// Left out of this build or setup
+    NotImplemented(String),
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Rejected(Rejection::RateLimited | Rejection::Overloaded, _) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Rejected(Rejection::Limit | Rejection::DaaRange, _) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) | Self::NotIndexed(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::CursorExpired(_) => StatusCode::GONE,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine readable kind of the error, the `code` of error bodies
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Rejected(rejection, _) => rejection.code(),
+            Self::NotFound(_) => "not_found",
+            Self::NotIndexed(_) => "not_indexed",
+            Self::Conflict(_) => "conflict",
+            Self::CursorExpired(_) => "cursor_expired",
+            Self::Unauthorized => "unauthorized",
+            Self::MethodNotAllowed => "method_not_allowed",
+            Self::NotImplemented(_) => "not_implemented",
+            Self::Internal(_) => "internal",
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadRequest(reason)
+            | Self::Rejected(_, reason)
+            | Self::NotFound(reason)
+            | Self::NotIndexed(reason)
+            | Self::Conflict(reason)
+            | Self::NotImplemented(reason) => f.write_str(reason),
+            Self::CursorExpired(earliest) => write!(
+                f,
+                "cursor expired, entries before position {} were pruned",
+                earliest.position
+            ),
+            Self::Unauthorized => f.write_str("unauthorized"),
+            Self::MethodNotAllowed => f.write_str("method not allowed"),
+            Self::Internal(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Internal(err)
+    }
+}
+
+impl From<IndexerError> for ApiError {
+    fn from(err: IndexerError) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+/// Read paths the API answers from
+#[derive(Clone)]
+pub struct QueryApi {
+    tx_keyspace: TxKeyspace,
+    queries: Queries,
+    block_compact_header_partition: BlockCompactHeaderPartition,
+    daa_index_partition: DaaIndexPartition,
+    block_stats_partition: BlockStatsPartition,
+    chain_index_partition: ChainIndexPartition,
+    chain_index_by_hash_partition: ChainIndexByHashPartition,
+    block_relations_partition: BlockRelationsPartition,
+    block_miner_partition: BlockMinerPartition,
+    tx_id_to_acceptance_partition: TxIDToAcceptancePartition,
+    finalized_tx_partition: FinalizedTxPartition,
+    acceptance_history_partition: AcceptanceHistoryPartition,
+    confirmations: Confirmations,
+    /// Pruning state the page cursors are checked against
+    metadata_partition: MetadataPartition,
+    status: Option<status::Indexer>,
+    push: Option<ws::PushStream>,
+    /// Answers transactions not indexed yet as pending
+    mempool: Option<Mempool>,
+    #[cfg(feature = "webhooks")]
+    webhooks: Option<webhooks::WebhookApi>,
+    #[cfg(feature = "profiling")]
+    profile: Option<profile::ProfileApi>,
+    /// Addresses of other networks are refused
+    address_prefix: Prefix,
+    /// Filter the blocks were indexed under, none for databases indexed unfiltered
+    ingest_filter: Option<IngestFilterState>,
+    limits: QueryLimits,
+    /// Counts the rejections and the entries read
+    metrics: Option<SharedMetrics>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Permits of the requests in progress
+    requests: Option<Arc<Semaphore>>,
+}
+
+impl QueryApi {
+    /// `block_compact_header_partition` is the one of the processors, sharing its header cache
+    pub fn new(
+        tx_keyspace: &TxKeyspace,
+        block_compact_header_partition: BlockCompactHeaderPartition,
+        status: Option<status::Indexer>,
+    ) -> Result<Self> {
+        let metadata_partition = MetadataPartition::new(tx_keyspace)?;
+        Ok(Self {
+            tx_keyspace: tx_keyspace.clone(),
+            queries: Queries::new(tx_keyspace, block_compact_header_partition.clone())?,
+            block_compact_header_partition,
+            daa_index_partition: DaaIndexPartition::new(tx_keyspace)?,
+            block_stats_partition: BlockStatsPartition::new(tx_keyspace)?,
+            chain_index_partition: ChainIndexPartition::new(tx_keyspace)?,
+            chain_index_by_hash_partition: ChainIndexByHashPartition::new(tx_keyspace)?,
+            block_relations_partition: BlockRelationsPartition::new(tx_keyspace)?,
+            block_miner_partition: BlockMinerPartition::new(tx_keyspace)?,
+            tx_id_to_acceptance_partition: TxIDToAcceptancePartition::new(tx_keyspace)?,
+            finalized_tx_partition: FinalizedTxPartition::new(tx_keyspace)?,
+            acceptance_history_partition: AcceptanceHistoryPartition::new(tx_keyspace)?,
+            confirmations: Confirmations::new(tx_keyspace)?,
+            ingest_filter: metadata_partition.get_ingest_filter()?,
+            metadata_partition,
+            status,
+            push: None,
+            mempool: None,
+            #[cfg(feature = "webhooks")]
+            webhooks: None,
+            #[cfg(feature = "profiling")]
+            profile: None,
+            address_prefix: Prefix::Mainnet,
+            limits: QueryLimits::default(),
+            metrics: None,
+            rate_limiter: None,
+            requests: None,
+        })
+    }
+
+    /// Serves the push stream at `/ws`, answered with 404 otherwise
+    pub fn with_push_stream(mut self, push: ws::PushStream) -> Self {
+        self.push = Some(push);
+        self
+    }
+
+    /// Serves the webhook endpoints, answered with 404 otherwise
+    #[cfg(feature = "webhooks")]
+    pub fn with_webhooks(mut self, webhooks: webhooks::WebhookApi) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Serves the profile endpoints, answered with 404 otherwise
+    #[cfg(feature = "profiling")]
+    pub fn with_profiling(mut self, profile: profile::ProfileApi) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Reports transactions of the mempool as pending and serves `/mempool`, answered with 404
+    /// otherwise
+    pub fn with_mempool(mut self, mempool: Mempool) -> Self {
+        self.mempool = Some(mempool);
+        self
+    }
+
+    /// Transaction lookups missing the filter are answered without reading the store
+    pub fn with_tx_id_filter(mut self, filter: Arc<TxIdFilter>) -> Self {
+        self.queries = self.queries.with_tx_id_filter(filter.clone());
+        self.tx_id_to_acceptance_partition = self.tx_id_to_acceptance_partition.with_filter(filter);
+        self
+    }
+
+    /// Network of the queried addresses, mainnet by default
+    pub fn with_address_prefix(mut self, prefix: Prefix) -> Self {
+        self.address_prefix = prefix;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: QueryLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Refuses requests of client addresses past `per_second` with bursts of `burst`
+    pub fn with_rate_limit(mut self, per_second: u32, burst: u32) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(per_second, burst)));
+        self
+    }
+
+    /// Refuses requests while `max` are in progress. WebSocket clients are not counted
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.requests = Some(Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    fn reject(&self, rejection: Rejection, reason: String) -> ApiError {
+        if let Some(metrics) = &self.metrics {
+            match rejection {
+                Rejection::RateLimited => metrics.increment_api_rate_limited(),
+                Rejection::Overloaded => metrics.increment_api_overloaded(),
+                Rejection::Limit => metrics.increment_api_limit_rejections(),
+                Rejection::DaaRange => metrics.increment_api_daa_range_rejections(),
+            }
+        }
+        ApiError::Rejected(rejection, reason)
+    }
+
+    fn check_limit(&self, limit: usize) -> Result<(), ApiError> {
+        if limit > self.limits.max_limit {
+            return Err(self.reject(
+                Rejection::Limit,
+                format!("limit {limit} exceeds {}", self.limits.max_limit),
+            ));
+        }
+        Ok(())
+    }
+
+    fn count_entries_read(&self, count: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.add_api_entries_read(count as u64);
+        }
+    }
+
+    pub fn mempool_summary(&self) -> Result<MempoolSummaryResponse, ApiError> {
+        let mempool = self
+            .mempool
+            .as_ref()
+            .ok_or_else(|| ApiError::NotFound("mempool not tracked".to_string()))?;
+        Ok(mempool.mempool_summary().into())
+    }
+
+    pub fn status(&self) -> Result<status::IndexerStatus, ApiError> {
+        let status = self
+            .status
+            .as_ref()
+            .ok_or_else(|| ApiError::NotFound("status not served".to_string()))?;
+        Ok(status.status()?)
+    }
+
+    pub fn block(&self, hash: RpcHash) -> Result<BlockResponse, ApiError> {
+        let block = self
+            .queries
+            .get_block_full(hash)?
+            .ok_or_else(|| ApiError::NotFound(format!("block {hash} not found")))?;
+        let mut response = BlockResponse::from(block);
+        response.filtered = self.ingest_filter.as_ref().is_some_and(|state| {
+            state.filter.watches_addresses()
+                || (state.filter.chain_blocks_only && response.transactions.is_none())
+        });
+        Ok(response)
+    }
+
+    /// Blocks of `daa_from..daa_to`, from the position of `cursor` when set
+    pub fn blocks(
+        &self,
+        daa_from: u64,
+        daa_to: u64,
+        limit: usize,
+        cursor: Option<PageCursor>,
+    ) -> Result<BlockListResponse, ApiError> {
+        if daa_from > daa_to {
+            return Err(ApiError::BadRequest(format!(
+                "daa_from {daa_from} exceeds daa_to {daa_to}"
+            )));
+        }
+        if daa_to - daa_from > self.limits.max_daa_range {
+            return Err(self.reject(
+                Rejection::DaaRange,
+                format!(
+                    "DAA range {daa_from}..{daa_to} is wider than {}",
+                    self.limits.max_daa_range
+                ),
+            ));
+        }
+        self.check_limit(limit)?;
+        let rtx = self.tx_keyspace.read_tx();
+        let pruned = self.metadata_partition.get_blocks_pruned_rtx(&rtx)?;
+        let daa_from = match cursor {
+            Some(cursor) => {
+                resume(cursor, pruned, || Ok(pruned.daa_score))?.clamp(daa_from, daa_to)
+            }
+            None => daa_from,
+        };
+        // reads past the limit until the DAA score changes, see [`paginate`]
+        let mut entries = Vec::new();
+        for entry in self
+            .daa_index_partition
+            .iter_range_rtx(&rtx, daa_from..daa_to)
+        {
+            let (daa_score, hash) = entry?;
+            let past_page = entries.len() >= limit
+                && entries
+                    .first()
+                    .is_some_and(|(first, _)| *first != daa_score);
+            entries.push((daa_score, hash));
+            if past_page {
+                break;
+            }
+        }
+        self.count_entries_read(entries.len());
+        let (entries, next_daa_from) = paginate(entries, limit, |(daa_score, _)| *daa_score);
+        let hashes = entries.iter().map(|(_, hash)| *hash).collect::<Vec<_>>();
+        let headers = self
+            .block_compact_header_partition
+            .get_many_rtx(&rtx, &hashes)?;
+        Ok(BlockListResponse {
+            blocks: entries
+                .into_iter()
+                .zip(headers)
+                .map(|((daa_score, hash), header)| BlockSummary {
+                    hash: hash.to_string(),
+                    daa_score,
+                    blue_work: header.map(|header| header.blue_work.to_string()),
+                })
+                .collect(),
+            next_daa_from,
+            next_cursor: next_cursor(pruned, next_daa_from),
+        })
+    }
+
+    /// Selected chain blocks from `from` to `to`, or to the tip without it, both included. The
+    /// page starts `offset` blocks after `from`, or at the chain index of `cursor`. Pages
+    /// follow the chain as of their own read, a reorg between pages continues the path on the
+    /// new chain as long as `from` stays on it, once `from` or `to` left the chain the request
+    /// fails with [`ApiError::Conflict`]
+    pub fn get_chain_path(
+        &self,
+        from: RpcHash,
+        to: Option<RpcHash>,
+        limit: usize,
+        offset: u64,
+        cursor: Option<PageCursor>,
+    ) -> Result<ChainPathResponse, ApiError> {
+        self.check_limit(limit)?;
+        let rtx = self.tx_keyspace.read_tx();
+        let pruned = self.metadata_partition.get_blocks_pruned_rtx(&rtx)?;
+        let from_index = self.chain_index_of(&rtx, from, "from")?;
+        let end = match to {
+            Some(to) => {
+                let to_index = self.chain_index_of(&rtx, to, "to")?;
+                if to_index < from_index {
+                    return Err(ApiError::BadRequest(format!(
+                        "to block {to} precedes from block {from} on the selected chain"
+                    )));
+                }
+                to_index + 1
+            }
+            None => self
+                .chain_index_partition
+                .chain_tip_index_rtx(&rtx)?
+                .map_or(from_index + 1, |tip| tip + 1),
+        };
+        let start = match cursor {
+            Some(cursor) => {
+                resume(cursor, pruned, || self.first_stored_chain_index(&rtx, end))?.max(from_index)
+            }
+            None => from_index.saturating_add(offset),
+        }
+        .min(end);
+        let page_end = start.saturating_add(limit as u64).min(end);
+        let entries = self
+            .chain_index_partition
+            .iter_chain_blocks_rtx(&rtx, start..page_end)
+            .collect::<Result<Vec<_>>>()?;
+        self.count_entries_read(entries.len());
+        let hashes = entries.iter().map(|(_, hash)| *hash).collect::<Vec<_>>();
+        let headers = self
+            .block_compact_header_partition
+            .get_many_rtx(&rtx, &hashes)?;
+        let mut blocks = Vec::with_capacity(entries.len());
+        for ((chain_index, hash), header) in entries.into_iter().zip(headers) {
+            let tx_count = self
+                .block_stats_partition
+                .get_block_stats_rtx(&rtx, hash)?
+                .map(|stats| stats.tx_count);
+            let miner = match self.block_miner_partition.get_block_miner_rtx(&rtx, hash)? {
+                Some(BlockMiner::Parsed { address, .. }) => Some(address.to_string()),
+                Some(BlockMiner::ParseFailed) | None => None,
+            };
+            blocks.push(ChainBlockSummary {
+                hash: hash.to_string(),
+                chain_index,
+                daa_score: header.map(|header| header.daa_score),
+                blue_work: header.map(|header| header.blue_work.to_string()),
+                tx_count,
+                miner,
+            });
+        }
+        let next = (page_end < end).then_some(page_end);
+        Ok(ChainPathResponse {
+            blocks,
+            next_offset: next.map(|next| next - from_index),
+            next_cursor: next_cursor(pruned, next),
+        })
+    }
+
+    /// Lowest chain index below `end` whose block header is still stored, `end` when none is.
+    /// Headers are pruned by DAA score, which grows along the chain
+    fn first_stored_chain_index(&self, rtx: &ReadTransaction, end: u64) -> Result<u64> {
+        let (mut low, mut high) = (0, end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let stored = match self
+                .chain_index_partition
+                .get_chain_block_by_index_rtx(rtx, mid)?
+            {
+                Some(hash) => self
+                    .block_compact_header_partition
+                    .get_compact_header_rtx(rtx, &hash)?
+                    .is_some(),
+                None => false,
+            };
+            match stored {
+                true => high = mid,
+                false => low = mid + 1,
+            }
+        }
+        Ok(low)
+    }
+
+    pub fn get_block_relations(&self, hash: RpcHash) -> Result<BlockRelationsResponse, ApiError> {
+        let relations = self
+            .block_relations_partition
+            .get_block_relations_rtx(&self.tx_keyspace.read_tx(), hash)?
+            .ok_or_else(|| ApiError::NotFound(format!("relations of block {hash} not found")))?;
+        Ok(BlockRelationsResponse::new(hash, relations))
+    }
+
+    /// The block and the blocks reached from it through selected parents and merge sets, up
+    /// to `depth` steps and [`MAX_NEIGHBORHOOD_BLOCKS`] blocks. Only the past is walked, the
+    /// children of a block aren't stored
+    ///
+    /// [`MAX_NEIGHBORHOOD_BLOCKS`]: crate::database::headers::MAX_NEIGHBORHOOD_BLOCKS
+    pub fn get_dag_neighborhood(
+        &self,
+        hash: RpcHash,
+        depth: u32,
+    ) -> Result<DagNeighborhoodResponse, ApiError> {
+        if depth > MAX_DAG_DEPTH {
+            return Err(ApiError::BadRequest(format!(
+                "depth {depth} exceeds {MAX_DAG_DEPTH}"
+            )));
+        }
+        let rtx = self.tx_keyspace.read_tx();
+        let neighborhood = self
+            .block_relations_partition
+            .get_dag_neighborhood_rtx(&rtx, hash, depth)?;
+        if neighborhood
+            .blocks
+            .first()
+            .is_none_or(|block| block.relations.is_none())
+        {
+            return Err(ApiError::NotFound(format!(
+                "relations of block {hash} not found"
+            )));
+        }
+        let hashes = neighborhood
+            .blocks
+            .iter()
+            .map(|block| block.hash)
+            .collect::<Vec<_>>();
+        let headers = self
+            .block_compact_header_partition
+            .get_many_rtx(&rtx, &hashes)?;
+        Ok(DagNeighborhoodResponse {
+            blocks: neighborhood
+                .blocks
+                .into_iter()
+                .zip(headers)
+                .map(|(block, header)| DagBlock {
+                    hash: block.hash.to_string(),
+                    depth: block.depth,
+                    daa_score: header.map(|header| header.daa_score),
+                    relations: block
+                        .relations
+                        .map(|relations| BlockRelationsResponse::new(block.hash, relations)),
+                })
+                .collect(),
+            truncated: neighborhood.truncated,
+        })
+    }
+
+    /// Chain index of an endpoint of a chain path, `what` names it in the errors
+    fn chain_index_of(
+        &self,
+        rtx: &ReadTransaction,
+        hash: RpcHash,
+        what: &str,
+    ) -> Result<u64, ApiError> {
+        if let Some(index) = self
+            .chain_index_by_hash_partition
+            .get_chain_index_rtx(rtx, &hash)?
+        {
+            return Ok(index);
+        }
+        // an indexed block off the chain was reorged out, or merged without ever being on it
+        match self
+            .block_compact_header_partition
+            .get_compact_header_rtx(rtx, &hash)?
+        {
+            Some(_) => Err(ApiError::Conflict(format!(
+                "{what} block {hash} is not on the selected chain, it was reorged out or never on it"
+            ))),
+            None => Err(ApiError::NotFound(format!("{what} block {hash} not found"))),
+        }
+    }
+
+    /// Indexed transactions first, then the ones pending in the mempool
+    pub fn transaction(&self, tx_id: RpcTransactionId) -> Result<TransactionResponse, ApiError> {
+        let not_found = || self.pending_transaction(tx_id);
+        if !self
+            .tx_id_to_acceptance_partition
+            .may_contain(&tx_id.as_bytes())
+        {
+            return not_found();
+        }
+        let rtx = self.tx_keyspace.read_tx();
+        // ordered by acceptance DAA score, an accepted entry comes last
+        let Some(entry) = self
+            .tx_id_to_acceptance_partition
+            .get_by_tx_id(&rtx, &tx_id.as_bytes())
+            .next_back()
+        else {
+            self.tx_id_to_acceptance_partition.record_false_positive();
+            return not_found();
+        };
+        let (key, _) = entry?;
+        let kind = MessageKind::from_partition_id(key.partition_id)
+            .ok_or_else(|| anyhow::anyhow!("Invalid partition ID: {}", key.partition_id))?;
+        let accepting_block_hash = RpcHash::from_slice(&key.accepted_by_block_hash);
+        let accepted = accepting_block_hash != RpcHash::default();
+        Ok(TransactionResponse {
+            tx_id: tx_id.to_string(),
+            status: match accepted {
+                true => TransactionStatus::Accepted,
+                false => TransactionStatus::Indexed,
+            },
+            kind: Some(kind),
+            pending: None,
+            accepting_block_hash: accepted.then(|| accepting_block_hash.to_string()),
+            accepting_daa_score: accepted.then(|| u64::from_be_bytes(key.accepted_at_daa)),
+            confirmations: self.confirmations.get_confirmations_rtx(&rtx, &tx_id)?,
+            finalized: self.finalized_tx_partition.get_rtx(&rtx, &tx_id)?.is_some(),
+        })
+    }
+
+    /// Acceptance changes of an indexed transaction, oldest first. History is only recorded
+    /// once a transaction is reorged out, until then its current acceptance is the only change
+    pub fn acceptance_history(
+        &self,
+        tx_id: RpcTransactionId,
+    ) -> Result<AcceptanceHistoryResponse, ApiError> {
+        let not_found = || ApiError::NotFound(format!("transaction {tx_id} not found"));
+        if !self
+            .tx_id_to_acceptance_partition
+            .may_contain(&tx_id.as_bytes())
+        {
+            return Err(not_found());
+        }
+        let rtx = self.tx_keyspace.read_tx();
+        let records = self
+            .acceptance_history_partition
+            .get_rtx(&rtx, &tx_id.as_bytes())?;
+        let changes = match records.is_empty() {
+            false => records
+                .into_iter()
+                .map(AcceptanceChangeResponse::try_from)
+                .collect::<Result<Vec<_>>>()?,
+            true => {
+                // ordered by acceptance DAA score, an accepted entry comes last
+                let Some(entry) = self
+                    .tx_id_to_acceptance_partition
+                    .get_by_tx_id(&rtx, &tx_id.as_bytes())
+                    .next_back()
+                else {
+                    self.tx_id_to_acceptance_partition.record_false_positive();
+                    return Err(not_found());
+                };
+                let (key, _) = entry?;
+                let accepting_block_hash = RpcHash::from_slice(&key.accepted_by_block_hash);
+                (accepting_block_hash != RpcHash::default())
+                    .then(|| AcceptanceChangeResponse {
+                        change: AcceptanceChangeKind::Accepted,
+                        block_hash: accepting_block_hash.to_string(),
+                        daa_score: Some(u64::from_be_bytes(key.accepted_at_daa))
+                            .filter(|daa| *daa != 0),
+                        recorded_at_ms: None,
+                    })
+                    .into_iter()
+                    .collect()
+            }
+        };
+        Ok(AcceptanceHistoryResponse {
+            tx_id: tx_id.to_string(),
+            changes,
+        })
+    }
+
+    fn pending_transaction(
+        &self,
+        tx_id: RpcTransactionId,
+    ) -> Result<TransactionResponse, ApiError> {
+        let entry = self
+            .mempool
+            .as_ref()
+            .and_then(|mempool| mempool.get_mempool_entry(&tx_id))
+            .ok_or_else(|| ApiError::NotFound(format!("transaction {tx_id} not found")))?;
+        Ok(TransactionResponse {
+            tx_id: tx_id.to_string(),
+            status: TransactionStatus::Pending,
+            kind: None,
+            pending: Some(entry.into()),
+            accepting_block_hash: None,
+            accepting_daa_score: None,
+            confirmations: None,
+            finalized: false,
+        })
+    }
+
+    /// The message partitions are ordered by block time, every entry of the address is read
+    /// and ordered by the DAA score of its block. Entries of blocks whose header was pruned are
+    /// left out. The page starts at `from_daa`, or at the DAA score of `cursor`
+    pub fn address_transactions(
+        &self,
+        address: &RpcAddress,
+        from_daa: u64,
+        limit: usize,
+        cursor: Option<PageCursor>,
+    ) -> Result<AddressTransactionsResponse, ApiError> {
+        if address.prefix != self.address_prefix {
+            return Err(ApiError::BadRequest(format!(
+                "Address {address} is not a {} address",
+                self.address_prefix
+            )));
+        }
+        self.check_limit(limit)?;
+        let payload = AddressPayload::try_from(address)
+            .map_err(|err| ApiError::BadRequest(format!("Unsupported address: {err}")))?;
+        self.check_indexed(address, &payload)?;
+        let rtx = self.tx_keyspace.read_tx();
+        let pruned = self.metadata_partition.get_blocks_pruned_rtx(&rtx)?;
+        let from_daa = match cursor {
+            Some(cursor) => resume(cursor, pruned, || Ok(pruned.daa_score))?,
+            None => from_daa,
+        };
+        let entries = self
+            .queries
+            .address_entries_rtx(&rtx, &payload)
+            .collect::<Result<Vec<_>>>()?;
+        self.count_entries_read(entries.len());
+        let hashes = entries
+            .iter()
+            .map(|entry| RpcHash::from_bytes(entry.block_hash))
+            .collect::<Vec<_>>();
+        let headers = self
+            .block_compact_header_partition
+            .get_many_rtx(&rtx, &hashes)?;
+        let mut transactions = entries
+            .into_iter()
+            .zip(headers)
+            .filter_map(|(entry, header)| {
+                let daa_score = header?.daa_score;
+                (daa_score >= from_daa).then(|| AddressTransaction {
+                    tx_id: RpcTransactionId::from_bytes(entry.tx_id).to_string(),
+                    kind: entry.kind,
+                    direction: entry.direction,
+                    block_hash: RpcHash::from_bytes(entry.block_hash).to_string(),
+                    block_time_ms: entry.block_time_ms,
+                    daa_score,
+                })
+            })
+            .collect::<Vec<_>>();
+        transactions.sort_by(|a, b| {
+            (a.daa_score, a.block_time_ms, &a.tx_id).cmp(&(b.daa_score, b.block_time_ms, &b.tx_id))
+        });
+        let (transactions, next_from_daa) =
+            paginate(transactions, limit, |transaction| transaction.daa_score);
+        Ok(AddressTransactionsResponse {
+            transactions,
+            next_from_daa,
+            next_cursor: next_cursor(pruned, next_from_daa),
+        })
+    }
+
+    /// History of the address within the DAA range, served by `/addresses/{address}/export`
+    pub fn address_history(
+        &self,
+        address: &RpcAddress,
+        daa_range: Range<u64>,
+    ) -> Result<AddressHistoryStream, ApiError> {
+        let payload = AddressPayload::try_from(address)
+            .map_err(|err| ApiError::BadRequest(format!("Unsupported address: {err}")))?;
+        self.check_indexed(address, &payload)?;
+        Ok(self.queries.stream_address_history(address, daa_range)?)
+    }
+
+    fn check_indexed(
+        &self,
+        address: &RpcAddress,
+        payload: &AddressPayload,
+    ) -> Result<(), ApiError> {
+        match &self.ingest_filter {
+            Some(state) if !state.filter.admits_address(payload) => Err(ApiError::NotIndexed(
+                format!("Address {address} is not indexed by configuration"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_address(&self, address: &str) -> Result<RpcAddress, ApiError> {
+        let address = RpcAddress::try_from(address)
+            .map_err(|err| ApiError::BadRequest(format!("Invalid address: {err}")))?;
+        if address.prefix != self.address_prefix {
+            return Err(ApiError::BadRequest(format!(
+                "Address {address} is not a {} address",
+                self.address_prefix
+            )));
+        }
+        Ok(address)
+    }
+}
+
+/// Cuts `entries`, ordered by DAA score, to `limit` at a DAA score boundary. Returns the page
+/// and the DAA score of the first entry left out
+fn paginate<T>(
+    mut entries: Vec<T>,
+    limit: usize,
+    daa_score: impl Fn(&T) -> u64,
+) -> (Vec<T>, Option<u64>) {
+    let Some(cut) = entries.get(limit).map(&daa_score) else {
+        return (entries, None);
+    };
+    let mut end = entries.partition_point(|entry| daa_score(entry) < cut);
+    if end == 0 {
+        // a single DAA score holds more than the limit, its entries are not split
+        end = entries.partition_point(|entry| daa_score(entry) <= cut);
+    }
+    let next = entries.get(end).map(&daa_score);
+    entries.truncate(end);
+    (entries, next)
+}
+
+/// Position `cursor` resumes at, see [`PageCursor::resume`]
+fn resume(
+    cursor: PageCursor,
+    pruned: BlocksPruned,
+    earliest: impl FnOnce() -> Result<u64>,
+) -> Result<u64, ApiError> {
+    cursor
+        .resume(pruned, earliest)?
+        .map_err(ApiError::CursorExpired)
+}
+
+fn next_cursor(pruned: BlocksPruned, next: Option<u64>) -> Option<String> {
+    next.map(|position| PageCursor::new(pruned, position).to_string())
+}
+
+fn parse<T: FromStr>(value: &str, what: &str) -> Result<T, ApiError>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| ApiError::BadRequest(format!("Invalid {what} {value}: {err}")))
+}
+
+/// Whether the headers carry `Authorization: Bearer {token}`, an empty token authorizes nothing
+#[cfg(any(feature = "webhooks", feature = "profiling"))]
+fn authorize_bearer(headers: &HeaderMap, token: &str) -> Result<(), ApiError> {
+    let authorized = !token.is_empty()
+        && headers
+            .get_all(axum::http::header::AUTHORIZATION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.trim().strip_prefix("Bearer ") == Some(token));
+    match authorized {
+        true => Ok(()),
+        false => Err(ApiError::Unauthorized),
+    }
+}
+
+/// Query parameters of a request, invalid ones are answered with a JSON error
+struct Params(Vec<(String, String)>);
+
+impl<S: Send + Sync> FromRequestParts<S> for Params {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        let Query(pairs) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| ApiError::BadRequest(err.body_text()))?;
+        Ok(Self(pairs))
+    }
+}
+
+impl Params {
+    fn optional<T: FromStr>(&self, name: &str) -> Result<Option<T>, ApiError>
+    where
+        T::Err: Display,
+    {
+        self.0
+            .iter()
+            .find(|(key, value)| key == name && !value.is_empty())
+            .map(|(_, value)| parse(value, name))
+            .transpose()
+    }
+
+    fn required<T: FromStr>(&self, name: &str) -> Result<T, ApiError>
+    where
+        T::Err: Display,
+    {
+        self.optional(name)?
+            .ok_or_else(|| ApiError::BadRequest(format!("Missing {name}")))
+    }
+
+    /// Capped by the [`QueryLimits`] of the queries
+    fn limit(&self) -> Result<usize, ApiError> {
+        match self.optional("limit")?.unwrap_or(DEFAULT_LIMIT) {
+            0 => Err(ApiError::BadRequest("limit must be positive".to_string())),
+            limit => Ok(limit),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(err) = &self {
+            warn!("Query API request failed: {err}");
+        }
+        let mut body = serde_json::json!({ "error": self.to_string(), "code": self.code() });
+        if let ApiError::CursorExpired(earliest) = &self {
+            body["earliest_cursor"] = earliest.to_string().into();
+        }
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl QueryApi {
+    fn router(self, clients_shutdown: CancellationToken) -> Router {
+        let router = Router::new()
+            .route("/blocks", get(blocks))
+            .route("/blocks/{hash}", get(block))
+            .route("/blocks/{hash}/relations", get(block_relations))
+            .route("/dag/{hash}", get(dag_neighborhood))
+            .route("/chain", get(chain_path))
+            .route("/transactions/{id}", get(transaction))
+            .route(
+                "/transactions/{id}/acceptance-history",
+                get(acceptance_history),
+            )
+            .route(
+                "/addresses/{address}/transactions",
+                get(address_transactions),
+            )
+            .route("/addresses/{address}/export", get(address_history_export))
+            .route("/mempool", get(mempool))
+            .route("/status", get(indexer_status))
+            .route("/schema", get(database_schema))
+            .route("/ws", get(push_stream));
+        #[cfg(feature = "webhooks")]
+        let router = match self.webhooks.clone() {
+            Some(webhooks) => router.merge(webhooks.router()),
+            None => router,
+        };
+        #[cfg(feature = "profiling")]
+        let router = match self.profile.clone() {
+            Some(profile) => router.merge(profile.router()),
+            None => router,
+        };
+        router
+            .fallback(|| async { ApiError::NotFound("not found".to_string()) })
+            .method_not_allowed_fallback(|| async { ApiError::MethodNotAllowed })
+            .layer(middleware::from_fn_with_state(self.clone(), admit))
+            .layer(Extension(clients_shutdown))
+            .with_state(self)
+    }
+}
+
+/// Answers queries until shutdown, then stops accepting connections
+pub async fn serve(listener: TcpListener, api: QueryApi, shutdown: Shutdown) -> Result<()> {
+    info!("Query API listening on {}", listener.local_addr()?);
+    // closes the push stream clients
+    let clients_shutdown = CancellationToken::new();
+    let _clients_shutdown = clients_shutdown.clone().drop_guard();
+    serve_router(listener, api.router(clients_shutdown), shutdown).await;
+    info!("Query API listener stopped");
+    Ok(())
+}
+
+/// Refuses requests past the rate limit of the client address or the concurrent requests cap.
+/// The permit is held until the response is written, WebSocket clients are not counted
+async fn admit(
+    State(api): State<QueryApi>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(rate_limiter) = &api.rate_limiter
+        && let Err(wait) = rate_limiter.check(peer.ip())
+    {
+        let reason = format!(
+            "rate limit exceeded, retry in {} ms",
+            wait.as_millis().max(1)
+        );
+        return api.reject(Rejection::RateLimited, reason).into_response();
+    }
+    let permit = match &api.requests {
+        Some(_) if request.uri().path() == "/ws" => None,
+        Some(requests) => match requests.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                let reason = "too many concurrent requests".to_string();
+                return api.reject(Rejection::Overloaded, reason).into_response();
+            }
+        },
+        None => None,
+    };
+    let response = next.run(request).await;
+    match permit {
+        Some(permit) => response.map(|body| {
+            Body::from_stream(body.into_data_stream().inspect(move |_| {
+                let _held = &permit;
+            }))
+        }),
+        None => response,
+    }
+}
+
+/// Runs `query` off the runtime, the store is read synchronously
+async fn blocking<T: Send + 'static>(
+    api: QueryApi,
+    query: impl FnOnce(&QueryApi) -> Result<T, ApiError> + Send + 'static,
+) -> Result<Json<T>, ApiError> {
+    tokio::task::spawn_blocking(move || query(&api))
+        .await
+        .map_err(anyhow::Error::from)?
+        .map(Json)
+}
+
+async fn block(
+    State(api): State<QueryApi>,
+    Path(hash): Path<String>,
+) -> Result<Json<BlockResponse>, ApiError> {
+    let hash = parse(&hash, "block hash")?;
+    blocking(api, move |api| api.block(hash)).await
+}
+
+async fn block_relations(
+    State(api): State<QueryApi>,
+    Path(hash): Path<String>,
+) -> Result<Json<BlockRelationsResponse>, ApiError> {
+    let hash = parse(&hash, "block hash")?;
+    blocking(api, move |api| api.get_block_relations(hash)).await
+}
+
+async fn blocks(
+    State(api): State<QueryApi>,
+    params: Params,
+) -> Result<Json<BlockListResponse>, ApiError> {
+    let (daa_from, daa_to, limit, cursor) = (
+        params.required("daa_from")?,
+        params.required("daa_to")?,
+        params.limit()?,
+        params.optional("cursor")?,
+    );
+    blocking(api, move |api| api.blocks(daa_from, daa_to, limit, cursor)).await
+}
+
+async fn chain_path(
+    State(api): State<QueryApi>,
+    params: Params,
+) -> Result<Json<ChainPathResponse>, ApiError> {
+    let (from, to, limit, offset, cursor) = (
+        params.required("from")?,
+        params.optional("to")?,
+        params.limit()?,
+        params.optional("offset")?.unwrap_or_default(),
+        params.optional("cursor")?,
+    );
+    blocking(api, move |api| {
+        api.get_chain_path(from, to, limit, offset, cursor)
+    })
+    .await
+}
+
+async fn dag_neighborhood(
+    State(api): State<QueryApi>,
+    Path(hash): Path<String>,
+    params: Params,
+) -> Result<Json<DagNeighborhoodResponse>, ApiError> {
+    let hash = parse(&hash, "block hash")?;
+    let depth = params.optional("depth")?.unwrap_or(DEFAULT_DAG_DEPTH);
+    blocking(api, move |api| api.get_dag_neighborhood(hash, depth)).await
+}
+
+async fn transaction(
+    State(api): State<QueryApi>,
+    Path(id): Path<String>,
+) -> Result<Json<TransactionResponse>, ApiError> {
+    let tx_id = parse(&id, "transaction id")?;
+    blocking(api, move |api| api.transaction(tx_id)).await
+}
+
+async fn acceptance_history(
+    State(api): State<QueryApi>,
+    Path(id): Path<String>,
+) -> Result<Json<AcceptanceHistoryResponse>, ApiError> {
+    let tx_id = parse(&id, "transaction id")?;
+    blocking(api, move |api| api.acceptance_history(tx_id)).await
+}
+
+async fn address_transactions(
+    State(api): State<QueryApi>,
+    Path(address): Path<String>,
+    params: Params,
+) -> Result<Json<AddressTransactionsResponse>, ApiError> {
+    let address = api.parse_address(&address)?;
+    let (from_daa, limit, cursor) = (
+        params.optional("from_daa")?.unwrap_or_default(),
+        params.limit()?,
+        params.optional("cursor")?,
+    );
+    blocking(api, move |api| {
+        api.address_transactions(&address, from_daa, limit, cursor)
+    })
+    .await
+}
+
+/// Streams the history as CSV or NDJSON depending on `Accept`. Each row is sent before the
+/// next one is read, so a slow client holds the store iterator. A failure midway ends the
+/// response early, clients see it truncated
+async fn address_history_export(
+    State(api): State<QueryApi>,
+    Path(address): Path<String>,
+    params: Params,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let address = api.parse_address(&address)?;
+    let daa_from = params.optional("daa_from")?.unwrap_or_default();
+    let daa_to = params.optional("daa_to")?.unwrap_or(u64::MAX);
+    let records = api.address_history(&address, daa_from..daa_to)?;
+    let format =
+        ExportFormat::from_accept(headers.get(ACCEPT).and_then(|value| value.to_str().ok()));
+    let body = Body::from_stream(export_rows(records, format));
+    Ok(([(CONTENT_TYPE, format.content_type())], body).into_response())
+}
+
+async fn mempool(State(api): State<QueryApi>) -> Result<Json<MempoolSummaryResponse>, ApiError> {
+    api.mempool_summary().map(Json)
+}
+
+async fn indexer_status(
+    State(api): State<QueryApi>,
+) -> Result<Json<status::IndexerStatus>, ApiError> {
+    blocking(api, QueryApi::status).await
+}
+
+async fn database_schema() -> Response {
+    (
+        [(CONTENT_TYPE, "application/json")],
+        schema::describe_json(),
+    )
+        .into_response()
+}
+
+async fn push_stream(
+    State(api): State<QueryApi>,
+    Extension(shutdown): Extension<CancellationToken>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let push = api
+        .push
+        .ok_or_else(|| ApiError::NotFound("push stream not served".to_string()))?;
+    Ok(upgrade.on_upgrade(move |socket| async move {
+        if let Err(err) = push.accept(socket, shutdown).await {
+            debug!("Push stream client failed: {err}");
+        }
+    }))
+}
+
+/// Rows of the export in `format`, the CSV header first
+fn export_rows(
+    records: AddressHistoryStream,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<String>> + Send {
+    let header = (format == ExportFormat::Csv).then(|| Ok(ExportFormat::CSV_HEADER.to_string()));
+    let rows = records.map(move |record| {
+        let record = record.map_err(|err| {
+            let err = anyhow::Error::from(err);
+            warn!("Address history export failed: {err:#}");
+            err
+        })?;
+        format.render(&AddressHistoryRow::from(record))
+    });
+    futures_util::stream::iter(header).chain(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::block_transactions::BlockTransactionsPartition;
+    use crate::database::headers::BlockGapsPartition;
+    use crate::database::messages::{
+        ContextualMessageBySenderPartition, HandshakeBySenderPartition, HandshakeKeyBySender,
+        PaymentByReceiverPartition, PaymentKeyByReceiver, TxIdToPaymentPartition,
+    };
+    use crate::database::resolution_keys::HandshakeKeyForResolution;
+    use crate::ingest_filter::{FilterChangePolicy, IngestFilter};
+    use crate::metrics::create_shared_metrics;
+    use crate::metrics_exporter::fetch;
+    use crate::queries::EXPORT_BUFFER;
+    use kaspa_addresses::{Prefix, Version};
+    use kaspa_consensus_core::BlueWorkType;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicU64;
+
+    fn hash(byte: u8) -> RpcHash {
+        RpcHash::from_bytes([byte; 32])
+    }
+
+    /// Blocks 1..=4 at DAA scores 10, 11, 12 and 12, block 1 on the chain at index 5 of 6,
+    /// block 4 merging 3 and 2 with 3 merging 1, the address sending a handshake in block 1 and a contextual message in block 2 and
+    /// receiving a payment in block 4
+    fn populate(keyspace: &TxKeyspace, address: &RpcAddress) {
+        let headers = BlockCompactHeaderPartition::new(keyspace).unwrap();
+        let daa_index = DaaIndexPartition::new(keyspace).unwrap();
+        for (byte, daa_score) in [(1, 10), (2, 11), (3, 12), (4, 12)] {
+            headers
+                .insert_compact_header(
+                    &hash(byte),
+                    BlueWorkType::from_u64(daa_score * 2),
+                    daa_score,
+                )
+                .unwrap();
+            daa_index.insert(daa_score, &hash(byte)).unwrap();
+        }
+        let payload = AddressPayload::try_from(address).unwrap();
+        let other = AddressPayload {
+            payload: [9; 33],
+            ..payload
+        };
+        let mut wtx = keyspace.write_tx().unwrap();
+        BlockStatsPartition::new(keyspace).unwrap().insert_wtx(
+            &mut wtx,
+            hash(1),
+            &BlockStats {
+                tx_count: 3,
+                total_mass: 2_000,
+                total_fees: 4_000,
+                partial: false,
+            },
+        );
+        // block 1 holds a coinbase and the handshake, the others were synced header only
+        BlockTransactionsPartition::new(keyspace)
+            .unwrap()
+            .insert_wtx(&mut wtx, hash(1), &[hash(0xc1), hash(0xa1)]);
+        let chain_index = ChainIndexPartition::new(keyspace).unwrap();
+        chain_index.insert_wtx(&mut wtx, 5, &hash(1));
+        chain_index.insert_wtx(&mut wtx, 6, &hash(2));
+        ChainIndexByHashPartition::new(keyspace)
+            .unwrap()
+            .insert_wtx(&mut wtx, &hash(1), 5);
+        let relations = BlockRelationsPartition::new(keyspace).unwrap();
+        for (byte, selected_parent, reds) in [(4, 3, vec![hash(2)]), (3, 1, vec![])] {
+            relations
+                .insert_wtx(
+                    &mut wtx,
+                    hash(byte),
+                    &BlockRelations {
+                        selected_parent: hash(selected_parent),
+                        merge_set_blues: vec![hash(selected_parent)],
+                        merge_set_reds: reds,
+                    },
+                )
+                .unwrap();
+        }
+        TxIDToAcceptancePartition::new(keyspace)
+            .unwrap()
+            .insert_handshake_wtx(
+                &mut wtx,
+                [0xa1; 32],
+                &HandshakeKeyForResolution {
+                    block_time: 1_000u64.to_be_bytes(),
+                    block_hash: [1; 32],
+                    receiver: other,
+                    version: 1,
+                    tx_id: [0xa1; 32],
+                    attempt_count: 0,
+                },
+                Some(10),
+                Some([1; 32]),
+            );
+        HandshakeBySenderPartition::new(keyspace)
+            .unwrap()
+            .insert_wtx(
+                &mut wtx,
+                &HandshakeKeyBySender {
+                    sender: payload,
+                    block_time: 1_000u64.to_be_bytes(),
+                    block_hash: [1; 32],
+                    receiver: other,
+                    version: 1,
+                    tx_id: [0xa1; 32],
+                },
+            )
+            .unwrap();
+        ContextualMessageBySenderPartition::new(keyspace)
+            .unwrap()
+            .insert(
+                &mut wtx, payload, b"chat", 2_000, [2; 32], 1, [0xa2; 32], b"hex",
+            )
+            .unwrap();
+        // received earlier by block time than the message, later by DAA score
+        PaymentByReceiverPartition::new(keyspace)
+            .unwrap()
+            .insert_wtx(
+                &mut wtx,
+                &PaymentKeyByReceiver {
+                    receiver: payload,
+                    block_time: 500u64.to_be_bytes(),
+                    block_hash: [4; 32],
+                    version: 1,
+                    tx_id: [0xa3; 32],
+                },
+                Some(other),
+            );
+        wtx.commit().unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_queries() {
+        let keyspace = crate::database::test_keyspace("api");
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        populate(&keyspace, &address);
+        let status = status::Indexer::builder()
+            .tx_keyspace(keyspace.clone())
+            .metadata_partition(MetadataPartition::new(&keyspace).unwrap())
+            .block_gaps_partition(BlockGapsPartition::new(&keyspace).unwrap())
+            .metrics(create_shared_metrics())
+            .virtual_daa(Arc::new(AtomicU64::new(0)))
+            .build();
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            Some(status),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(listener, api, shutdown.clone()));
+        let get = async |path: &str| fetch(&addr, path).await;
+
+        let block: BlockResponse =
+            serde_json::from_str(&get(&format!("/blocks/{}", hash(1))).await.unwrap()).unwrap();
+        assert_eq!(block.daa_score, 10);
+        assert_eq!(block.blue_work, BlueWorkType::from_u64(20).to_string());
+        assert_eq!(block.chain_index, Some(5));
+        let stats = block.stats.unwrap();
+        assert_eq!((stats.tx_count, stats.fee_rate), (3, Some(2.0)));
+        let transactions = block.transactions.unwrap();
+        assert_eq!(
+            transactions
+                .iter()
+                .map(|tx| (tx.tx_id.clone(), tx.acceptance))
+                .collect::<Vec<_>>(),
+            [
+                (hash(0xc1).to_string(), AcceptanceStatus::Untracked),
+                (hash(0xa1).to_string(), AcceptanceStatus::Accepted)
+            ]
+        );
+        assert_eq!(transactions[1].accepting_daa_score, Some(10));
+        let block: BlockResponse =
+            serde_json::from_str(&get(&format!("/blocks/{}", hash(2))).await.unwrap()).unwrap();
+        assert_eq!((block.chain_index, block.stats), (None, None));
+        // not indexed rather than empty
+        assert_eq!(block.transactions, None);
+        let block: BlockResponse =
+            serde_json::from_str(&get(&format!("/blocks/{}", hash(4))).await.unwrap()).unwrap();
+        assert_eq!(block.selected_parent, Some(hash(3).to_string()));
+        let err = get(&format!("/blocks/{}", hash(9))).await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+        let err = get("/blocks/nothex").await.unwrap_err();
+        assert!(err.to_string().contains("400"), "{err}");
+
+        let page: BlockListResponse =
+            serde_json::from_str(&get("/blocks?daa_from=10&daa_to=13&limit=2").await.unwrap())
+                .unwrap();
+        let daa_scores = page.blocks.iter().map(|b| b.daa_score).collect::<Vec<_>>();
+        assert_eq!(daa_scores, [10, 11]);
+        assert_eq!(page.next_daa_from, Some(12));
+        // both blocks of DAA score 12 are kept on one page
+        let page: BlockListResponse =
+            serde_json::from_str(&get("/blocks?daa_from=12&daa_to=13&limit=1").await.unwrap())
+                .unwrap();
+        assert_eq!(page.blocks.len(), 2);
+        assert_eq!(page.next_daa_from, None);
+        for path in [
+            "/blocks?daa_from=10",
+            "/blocks?daa_from=13&daa_to=10",
+            "/blocks?daa_from=10&daa_to=13&limit=5000",
+        ] {
+            let err = get(path).await.unwrap_err();
+            assert!(err.to_string().contains("400"), "{path}: {err}");
+        }
+
+        let path: ChainPathResponse =
+            serde_json::from_str(&get(&format!("/chain?from={}", hash(1))).await.unwrap()).unwrap();
+        assert_eq!(path.blocks.len(), 2);
+        assert_eq!(
+            (path.blocks[0].chain_index, path.blocks[0].tx_count),
+            (5, Some(3))
+        );
+        assert_eq!(path.blocks[1].daa_score, Some(11));
+        let err = get(&format!("/chain?from={}", hash(3))).await.unwrap_err();
+        assert!(err.to_string().contains("409"), "{err}");
+
+        let relations: BlockRelationsResponse = serde_json::from_str(
+            &get(&format!("/blocks/{}/relations", hash(4)))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(relations.selected_parent, hash(3).to_string());
+        assert_eq!(relations.merge_set_reds, [hash(2).to_string()]);
+        let err = get(&format!("/blocks/{}/relations", hash(1)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+        let dag: DagNeighborhoodResponse =
+            serde_json::from_str(&get(&format!("/dag/{}?depth=1", hash(4))).await.unwrap())
+                .unwrap();
+        let reached = |dag: &DagNeighborhoodResponse| {
+            dag.blocks
+                .iter()
+                .map(|block| (block.hash.clone(), block.depth))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            reached(&dag),
+            [
+                (hash(4).to_string(), 0),
+                (hash(3).to_string(), 1),
+                (hash(2).to_string(), 1)
+            ]
+        );
+        let dag: DagNeighborhoodResponse =
+            serde_json::from_str(&get(&format!("/dag/{}", hash(4))).await.unwrap()).unwrap();
+        assert_eq!(reached(&dag).last(), Some(&(hash(1).to_string(), 2)));
+        assert_eq!((dag.blocks[3].daa_score, dag.truncated), (Some(10), false));
+        assert!(dag.blocks[2].relations.is_none());
+        let err = get(&format!("/dag/{}?depth=100", hash(4)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("400"), "{err}");
+
+        let tx: TransactionResponse = serde_json::from_str(
+            &get(&format!(
+                "/transactions/{}",
+                RpcTransactionId::from_bytes([0xa1; 32])
+            ))
+            .await
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            (tx.status, tx.kind),
+            (TransactionStatus::Accepted, Some(MessageKind::Handshake))
+        );
+        assert_eq!(tx.accepting_block_hash, Some(hash(1).to_string()));
+        assert_eq!(tx.accepting_daa_score, Some(10));
+        assert_eq!(tx.confirmations, Some(2));
+        assert!(!tx.finalized);
+        let history: AcceptanceHistoryResponse = serde_json::from_str(
+            &get(&format!(
+                "/transactions/{}/acceptance-history",
+                RpcTransactionId::from_bytes([0xa1; 32])
+            ))
+            .await
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            history.changes,
+            [AcceptanceChangeResponse {
+                change: AcceptanceChangeKind::Accepted,
+                block_hash: hash(1).to_string(),
+                daa_score: Some(10),
+                recorded_at_ms: None,
+            }]
+        );
+        let err = get(&format!(
+            "/transactions/{}",
+            RpcTransactionId::from_bytes([0xff; 32])
+        ))
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+
+        let path = format!("/addresses/{address}/transactions");
+        let page: AddressTransactionsResponse =
+            serde_json::from_str(&get(&path).await.unwrap()).unwrap();
+        let listed = page
+            .transactions
+            .iter()
+            .map(|tx| (tx.daa_score, tx.kind, tx.direction))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listed,
+            [
+                (10, MessageKind::Handshake, Direction::Sent),
+                (11, MessageKind::ContextualMessage, Direction::Sent),
+                (12, MessageKind::Payment, Direction::Received),
+            ]
+        );
+        assert_eq!(page.next_from_daa, None);
+        let page: AddressTransactionsResponse =
+            serde_json::from_str(&get(&format!("{path}?from_daa=11&limit=1")).await.unwrap())
+                .unwrap();
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].daa_score, 11);
+        assert_eq!(page.next_from_daa, Some(12));
+        let cursor = page.next_cursor.unwrap();
+        let page: AddressTransactionsResponse = serde_json::from_str(
+            &get(&format!("{path}?limit=1&cursor={cursor}"))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(page.transactions[0].daa_score, 12);
+        let err = get(&format!("{path}?cursor=abc")).await.unwrap_err();
+        assert!(err.to_string().contains("400"), "{err}");
+        let err = get("/addresses/kaspa:invalid/transactions")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("400"), "{err}");
+        // same payload, another network
+        let testnet = RpcAddress::new(Prefix::Testnet, Version::PubKey, &[7; 32]);
+        let err = get(&format!("/addresses/{testnet}/transactions"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("400"), "{err}");
+
+        // NDJSON without an Accept header
+        let export = get(&format!("/addresses/{address}/export?daa_from=11"))
+            .await
+            .unwrap();
+        let rows = export
+            .lines()
+            .map(|line| serde_json::from_str::<AddressHistoryRow>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert!(!rows.is_empty());
+        assert!(rows.iter().all(|row| row.daa_score >= 11));
+        let err = get(&format!("/addresses/{testnet}/export"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("400"), "{err}");
+        let err = get("/nothing").await.unwrap_err();
+        assert!(err.to_string().contains(r#""code":"not_found""#), "{err}");
+
+        let status: serde_json::Value =
+            serde_json::from_str(&get("/status").await.unwrap()).unwrap();
+        assert_eq!(status["node_connected"], false);
+        let described: serde_json::Value =
+            serde_json::from_str(&get("/schema").await.unwrap()).unwrap();
+        assert_eq!(described["schema_version"], schema::SCHEMA_VERSION);
+        assert_eq!(
+            described["partitions"].as_array().unwrap().len(),
+            schema::describe_all().len()
+        );
+        assert_eq!(
+            described,
+            serde_json::from_str::<serde_json::Value>(&schema::describe_json()).unwrap()
+        );
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+        assert!(fetch(&addr, "/status").await.is_err());
+    }
+
+    #[test]
+    fn test_chain_path_pages_across_reorg() {
+        let keyspace = crate::database::test_keyspace("api-chain-path");
+        let headers = BlockCompactHeaderPartition::new(&keyspace).unwrap();
+        let chain_index = ChainIndexPartition::new(&keyspace).unwrap();
+        let chain_index_by_hash = ChainIndexByHashPartition::new(&keyspace).unwrap();
+        let extend_chain = |from: u64, bytes: &[u8]| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            for hash in chain_index.truncate_wtx(&mut wtx, from).unwrap() {
+                chain_index_by_hash.remove_wtx(&mut wtx, &hash);
+            }
+            for (index, byte) in (from..).zip(bytes) {
+                chain_index.insert_wtx(&mut wtx, index, &hash(*byte));
+                chain_index_by_hash.insert_wtx(&mut wtx, &hash(*byte), index);
+            }
+            wtx.commit().unwrap().unwrap();
+            for (daa_score, byte) in (from * 10..).zip(bytes) {
+                headers
+                    .insert_compact_header(
+                        &hash(*byte),
+                        BlueWorkType::from_u64(daa_score),
+                        daa_score,
+                    )
+                    .unwrap();
+            }
+        };
+        let api = QueryApi::new(&keyspace, headers.clone(), None).unwrap();
+        let hashes = |page: &ChainPathResponse| {
+            page.blocks
+                .iter()
+                .map(|block| block.hash.clone())
+                .collect::<Vec<_>>()
+        };
+        let expected = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| hash(*b).to_string())
+                .collect::<Vec<_>>()
+        };
+        extend_chain(0, &[10, 11, 12, 13, 14]);
+
+        let first = api.get_chain_path(hash(11), None, 2, 0, None).unwrap();
+        assert_eq!(hashes(&first), expected(&[11, 12]));
+        assert_eq!(first.next_offset, Some(2));
+        // 13 and 14 are reorged out before the next page is read
+        extend_chain(3, &[23, 24, 25]);
+        let second = api
+            .get_chain_path(hash(11), None, 2, first.next_offset.unwrap(), None)
+            .unwrap();
+        assert_eq!(hashes(&second), expected(&[23, 24]));
+        assert_eq!(second.blocks[0].daa_score, Some(30));
+        let last = api
+            .get_chain_path(hash(11), None, 2, second.next_offset.unwrap(), None)
+            .unwrap();
+        assert_eq!(hashes(&last), expected(&[25]));
+        assert_eq!(last.next_offset, None);
+
+        let bounded = api
+            .get_chain_path(hash(10), Some(hash(23)), 10, 0, None)
+            .unwrap();
+        assert_eq!(hashes(&bounded), expected(&[10, 11, 12, 23]));
+        assert_eq!(bounded.next_offset, None);
+        assert!(matches!(
+            api.get_chain_path(hash(23), Some(hash(11)), 10, 0, None),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            api.get_chain_path(hash(10), Some(hash(13)), 10, 0, None),
+            Err(ApiError::Conflict(_))
+        ));
+        assert!(matches!(
+            api.get_chain_path(hash(99), None, 10, 0, None),
+            Err(ApiError::NotFound(_))
+        ));
+
+        // the start of the path itself is reorged out between pages
+        extend_chain(1, &[31, 32]);
+        let err = api.get_chain_path(hash(11), None, 2, 2, None).unwrap_err();
+        assert!(matches!(err, ApiError::Conflict(_)));
+        assert!(err.to_string().contains("reorged out"), "{err}");
+    }
+
+    #[test]
+    fn test_paginate_at_daa_boundary() {
+        let daa = |entry: &u64| *entry;
+        assert_eq!(paginate(vec![1, 2, 2, 3], 2, daa), (vec![1], Some(2)));
+        assert_eq!(paginate(vec![1, 2, 2, 3], 3, daa), (vec![1, 2, 2], Some(3)));
+        assert_eq!(paginate(vec![2, 2, 2, 3], 1, daa), (vec![2, 2, 2], Some(3)));
+        assert_eq!(paginate(vec![1, 2], 2, daa), (vec![1, 2], None));
+    }
+
+    #[test]
+    fn test_cursors_expire_when_pruned_between_pages() {
+        let keyspace = crate::database::test_keyspace("api-cursor-pruning");
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        populate(&keyspace, &address);
+        let headers = BlockCompactHeaderPartition::new(&keyspace).unwrap();
+        let api = QueryApi::new(&keyspace, headers.clone(), None).unwrap();
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        let daa_index = DaaIndexPartition::new(&keyspace).unwrap();
+        // as the block header pruning does, marked before the blocks are removed
+        let prune = |prune_before_daa, blocks: &[(u8, u64)]| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            metadata
+                .mark_blocks_pruned_wtx(&mut wtx, prune_before_daa)
+                .unwrap();
+            wtx.commit().unwrap().unwrap();
+            for (byte, daa_score) in blocks {
+                headers.remove(&hash(*byte)).unwrap();
+                daa_index.delete(*daa_score, &hash(*byte)).unwrap();
+            }
+        };
+        let cursor =
+            |next: &Option<String>| Some(next.as_deref().unwrap().parse::<PageCursor>().unwrap());
+
+        let blocks = api.blocks(0, 100, 1, None).unwrap();
+        assert_eq!(blocks.next_daa_from, Some(11));
+        let chain = api.get_chain_path(hash(1), None, 1, 0, None).unwrap();
+        assert_eq!(chain.blocks[0].chain_index, 5);
+        let transactions = api.address_transactions(&address, 0, 1, None).unwrap();
+        assert_eq!(transactions.next_from_daa, Some(11));
+
+        // pruning short of the positions of the next pages leaves them readable
+        prune(11, &[(1, 10)]);
+        let page = api.blocks(0, 100, 1, cursor(&blocks.next_cursor)).unwrap();
+        assert_eq!(page.blocks[0].hash, hash(2).to_string());
+        let page = api
+            .get_chain_path(hash(1), None, 1, 0, cursor(&chain.next_cursor))
+            .unwrap();
+        assert_eq!(page.blocks[0].hash, hash(2).to_string());
+        let page = api
+            .address_transactions(&address, 0, 1, cursor(&transactions.next_cursor))
+            .unwrap();
+        assert_eq!(page.transactions[0].daa_score, 11);
+
+        // pruning past them expires the cursors, the ones returned restart at DAA score 12
+        prune(12, &[(2, 11)]);
+        let expired = |result: Result<_, ApiError>| match result {
+            Err(ApiError::CursorExpired(earliest)) => Some(earliest),
+            _ => None,
+        };
+        let earliest = expired(api.blocks(0, 100, 1, cursor(&blocks.next_cursor)).map(drop));
+        let page = api.blocks(0, 100, 1, earliest).unwrap();
+        let daa_scores = page.blocks.iter().map(|block| block.daa_score);
+        assert_eq!(daa_scores.collect::<Vec<_>>(), [12, 12]);
+        let earliest = expired(
+            api.get_chain_path(hash(1), None, 1, 0, cursor(&chain.next_cursor))
+                .map(drop),
+        );
+        // no chain block is left with a header
+        assert_eq!(earliest.unwrap().position, 7);
+        let page = api.get_chain_path(hash(1), None, 1, 0, earliest).unwrap();
+        assert!(page.blocks.is_empty());
+        let err = api
+            .address_transactions(&address, 0, 1, cursor(&transactions.next_cursor))
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::GONE);
+        let page = api
+            .address_transactions(&address, 0, 1, expired(Err(err)))
+            .unwrap();
+        assert_eq!(page.transactions[0].kind, MessageKind::Payment);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn test_transaction_lookup_through_filter() {
+        let keyspace = crate::database::test_keyspace("api-filter");
+        populate(
+            &keyspace,
+            &RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]),
+        );
+        let filter = Arc::new(
+            TxIdFilter::build(
+                100,
+                1_000,
+                &TxIDToAcceptancePartition::new(&keyspace).unwrap(),
+                &keyspace.read_tx(),
+            )
+            .unwrap(),
+        );
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap()
+        .with_tx_id_filter(filter.clone());
+
+        let tx = api
+            .transaction(RpcTransactionId::from_bytes([0xa1; 32]))
+            .unwrap();
+        assert_eq!(tx.kind, Some(MessageKind::Handshake));
+        for byte in 0xb0..0xc0 {
+            let missing = api.transaction(RpcTransactionId::from_bytes([byte; 32]));
+            assert!(matches!(missing, Err(ApiError::NotFound(_))));
+        }
+        let stats = filter.stats();
+        assert_eq!(stats.positives, 1 + stats.false_positives);
+        assert_eq!(stats.negatives + stats.false_positives, 16);
+    }
+
+    #[test]
+    fn test_pending_transactions() {
+        let keyspace = crate::database::test_keyspace("api-mempool");
+        populate(
+            &keyspace,
+            &RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]),
+        );
+        let mempool = Mempool::new();
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(api.mempool_summary(), Err(ApiError::NotFound(_))));
+        let api = api.with_mempool(mempool.clone());
+        let indexed = RpcTransactionId::from_bytes([0xa1; 32]);
+        let pending = RpcTransactionId::from_bytes([0xb1; 32]);
+        mempool.observe([(indexed, 0, 1_000), (pending, 3_000, 1_000)], 1_234);
+
+        // the indexed entry wins over the mempool one
+        let tx = api.transaction(indexed).unwrap();
+        assert_eq!((tx.status, tx.pending), (TransactionStatus::Accepted, None));
+        let tx = api.transaction(pending).unwrap();
+        assert_eq!((tx.status, tx.kind), (TransactionStatus::Pending, None));
+        assert_eq!(
+            tx.pending,
+            Some(PendingResponse {
+                first_seen_ms: 1_234,
+                fee: 3_000,
+                fee_rate: Some(3.0),
+            })
+        );
+        let summary = api.mempool_summary().unwrap();
+        assert_eq!((summary.count, summary.fee_rate_p99), (2, Some(3.0)));
+
+        mempool.remove_included(&[pending]);
+        assert!(matches!(
+            api.transaction(pending),
+            Err(ApiError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_address_history_under_backpressure() {
+        let keyspace = crate::database::test_keyspace("api-export");
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        let sender = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[8; 32]);
+        let block = |j: u64| RpcHash::from_u64_word(j + 1);
+        let tx_id = |i: u64| {
+            let mut tx_id = [0u8; 32];
+            tx_id[..8].copy_from_slice(&i.to_be_bytes());
+            tx_id
+        };
+        // 50k payments received in 500 blocks of DAA score 1000 to 1499
+        let headers = BlockCompactHeaderPartition::new(&keyspace).unwrap();
+        for j in 0..500 {
+            headers
+                .insert_compact_header(&block(j), BlueWorkType::from_u64(j), 1_000 + j)
+                .unwrap();
+        }
+        let payments = PaymentByReceiverPartition::new(&keyspace).unwrap();
+        let amounts = TxIdToPaymentPartition::new(&keyspace).unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        for i in 0..50_000 {
+            let j = i / 100;
+            payments.insert_wtx(
+                &mut wtx,
+                &PaymentKeyByReceiver {
+                    receiver: AddressPayload::try_from(&address).unwrap(),
+                    block_time: (j * 1_000).to_be_bytes(),
+                    block_hash: block(j).as_bytes(),
+                    version: 1,
+                    tx_id: tx_id(i),
+                },
+                Some(AddressPayload::try_from(&sender).unwrap()),
+            );
+            amounts.insert_wtx(&mut wtx, &tx_id(i), i, b"").unwrap();
+        }
+        wtx.commit().unwrap().unwrap();
+        let api = QueryApi::new(&keyspace, headers, None).unwrap();
+
+        let records = api.address_history(&address, 0..u64::MAX).unwrap();
+        // nothing is consumed, the reader stops once the buffer is full
+        tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            while records.records_read() <= EXPORT_BUFFER as u64 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(records.records_read(), EXPORT_BUFFER as u64 + 1);
+
+        let rows = export_rows(records, ExportFormat::Csv)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(rows.len(), 50_001);
+        assert_eq!(rows[0], ExportFormat::CSV_HEADER);
+        let row = |i: u64| {
+            let j = i / 100;
+            format!(
+                "{},{},{},{},payment,received,{i},{sender},\n",
+                j * 1_000,
+                1_000 + j,
+                block(j),
+                RpcTransactionId::from_bytes(tx_id(i))
+            )
+        };
+        assert_eq!(rows[1], row(0));
+        assert_eq!(rows[12_346], row(12_345));
+        assert_eq!(rows[50_000], row(49_999));
+
+        // two blocks of the DAA range, as NDJSON
+        let records = api
+            .address_history(&address, 1_100..1_102)
+            .unwrap()
+            .map(|record| AddressHistoryRow::from(record.unwrap()))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(records.len(), 200);
+        assert!(
+            records
+                .iter()
+                .all(|row| (1_100..1_102).contains(&row.daa_score))
+        );
+        let ndjson = ExportFormat::Ndjson.render(&records[0]).unwrap();
+        assert_eq!(
+            serde_json::from_str::<AddressHistoryRow>(&ndjson).unwrap(),
+            records[0]
+        );
+        assert_eq!(records[0].counterparts, [sender.to_string()]);
+        assert_eq!(
+            ExportFormat::from_accept(Some("application/json, text/csv;q=0.9")),
+            ExportFormat::Csv
+        );
+        assert_eq!(ExportFormat::from_accept(None), ExportFormat::Ndjson);
+        assert!(matches!(
+            api.parse_address("nonsense"),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_not_indexed_by_configuration() {
+        let keyspace = crate::database::test_keyspace("api-filtered");
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        populate(&keyspace, &address);
+        let watched = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[8; 32]);
+        let filter =
+            IngestFilter::default().with_addresses([AddressPayload::try_from(&watched).unwrap()]);
+        MetadataPartition::new(&keyspace)
+            .unwrap()
+            .check_ingest_filter(filter, FilterChangePolicy::Fail, 13)
+            .unwrap();
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap();
+
+        let err = api.address_transactions(&address, 0, 10, None).unwrap_err();
+        assert!(matches!(err, ApiError::NotIndexed(_)), "{err}");
+        assert_eq!(
+            (err.status(), err.code()),
+            (StatusCode::NOT_FOUND, "not_indexed")
+        );
+        assert!(
+            api.address_transactions(&watched, 0, 10, None)
+                .unwrap()
+                .transactions
+                .is_empty()
+        );
+        assert!(api.block(hash(1)).unwrap().filtered);
+    }
+
+    #[tokio::test]
+    async fn test_limits_refuse_before_reading() {
+        let keyspace = crate::database::test_keyspace("api-limits");
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        populate(&keyspace, &address);
+        let metrics = create_shared_metrics();
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap()
+        .with_limits(QueryLimits {
+            max_limit: 10,
+            max_daa_range: 100,
+        })
+        .with_metrics(metrics.clone());
+
+        // refused in the query layer, before any entry is read
+        assert!(matches!(
+            api.blocks(0, 1_000_000, 5, None),
+            Err(ApiError::Rejected(Rejection::DaaRange, _))
+        ));
+        assert!(matches!(
+            api.blocks(10, 13, 11, None),
+            Err(ApiError::Rejected(Rejection::Limit, _))
+        ));
+        assert!(matches!(
+            api.get_chain_path(hash(1), None, 11, 0, None),
+            Err(ApiError::Rejected(Rejection::Limit, _))
+        ));
+        assert!(matches!(
+            api.address_transactions(&address, 0, 11, None),
+            Err(ApiError::Rejected(Rejection::Limit, _))
+        ));
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.api_entries_read, 0);
+        assert_eq!(
+            (
+                snapshot.api_limit_rejections,
+                snapshot.api_daa_range_rejections
+            ),
+            (3, 1)
+        );
+        // a page of 2 reads one entry past it, see [`paginate`]
+        assert_eq!(api.blocks(10, 13, 2, None).unwrap().blocks.len(), 2);
+        assert_eq!(metrics.snapshot().api_entries_read, 3);
+
+        let api = api.with_rate_limit(1, 3).with_max_concurrent_requests(1);
+        let held = api.requests.clone().unwrap().try_acquire_owned().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(listener, api, shutdown.clone()));
+        let path = "/blocks?daa_from=10&daa_to=13&limit=2";
+        let err = fetch(&addr, path).await.unwrap_err().to_string();
+        assert!(
+            err.starts_with("429 Too Many Requests") && err.contains(r#""code":"overloaded""#),
+            "{err}"
+        );
+        drop(held);
+        for _ in 0..2 {
+            fetch(&addr, path).await.unwrap();
+        }
+        // the burst of 3 is used up, the overloaded request took a token as well
+        let err = fetch(&addr, path).await.unwrap_err().to_string();
+        assert!(
+            err.starts_with("429 Too Many Requests") && err.contains(r#""code":"rate_limited""#),
+            "{err}"
+        );
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.api_overloaded, snapshot.api_rate_limited), (1, 1));
+        // only the two answered pages read the store
+        assert_eq!(snapshot.api_entries_read, 3 * 3);
+        let registry = crate::metrics_exporter::MetricsRegistry::new();
+        crate::metrics_exporter::register_indexer_metrics(&registry, &metrics);
+        let text = registry.render();
+        for (reason, count) in [
+            ("rate_limited", 1),
+            ("overloaded", 1),
+            ("limit", 3),
+            ("daa_range", 1),
+        ] {
+            assert!(
+                text.contains(&format!(
+                    "indexer_api_rejections_total{{reason=\"{reason}\"}} {count}\n"
+                )),
+                "{reason}"
+            );
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Opaque page cursors of the query API listings.
+//!
+//! A cursor holds the logical position the next page starts at, a DAA score or a chain index,
+//! and the [`BlocksPruned::sequence`] it was issued under. Resuming while the sequence is
+//! unchanged reads the entries the cursor was issued for. Once blocks were pruned since, the
+//! listing resumes as long as its position is still stored, skipping forward over nothing:
+//! entries added behind the position are not returned, entries at and after it are. A
+//! position pruned away fails with [`ApiError::CursorExpired`] carrying a cursor at the
+//! earliest stored position to restart from.
+//!
+//! [`ApiError::CursorExpired`]: super::ApiError::CursorExpired
+
+use crate::database::metadata::BlocksPruned;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageCursor {
+    /// [`BlocksPruned::sequence`] when the cursor was issued
+    pub sequence: u64,
+    /// DAA score or chain index the next page starts at, depending on the listing
+    pub position: u64,
+}
+
+impl PageCursor {
+    pub fn new(pruned: BlocksPruned, position: u64) -> Self {
+        Self {
+            sequence: pruned.sequence,
+            position,
+        }
+    }
+
+    /// Position to resume at, or the cursor to restart from when the blocks at the position
+    /// were pruned since the cursor was issued. `earliest` is the first position still stored
+    pub fn resume(
+        self,
+        pruned: BlocksPruned,
+        earliest: impl FnOnce() -> anyhow::Result<u64>,
+    ) -> anyhow::Result<Result<u64, PageCursor>> {
+        if self.sequence == pruned.sequence {
+            return Ok(Ok(self.position));
+        }
+        let earliest = earliest()?;
+        Ok(match self.position < earliest {
+            true => Err(Self::new(pruned, earliest)),
+            false => Ok(self.position),
+        })
+    }
+}
+
+impl Display for PageCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}{:016x}", self.sequence, self.position)
+    }
+}
+
+impl FromStr for PageCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || "not a page cursor".to_string();
+        if s.len() != 32 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let (sequence, position) = s.split_at(16);
+        Ok(Self {
+            sequence: u64::from_str_radix(sequence, 16).map_err(|_| invalid())?,
+            position: u64::from_str_radix(position, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = PageCursor {
+            sequence: 3,
+            position: u64::MAX,
+        };
+        assert_eq!(cursor.to_string().parse::<PageCursor>(), Ok(cursor));
+        assert!("12".parse::<PageCursor>().is_err());
+        assert!("zz".repeat(16).parse::<PageCursor>().is_err());
+    }
+
+    #[test]
+    fn test_resume_after_pruning() {
+        let pruned = |sequence, daa_score| BlocksPruned {
+            sequence,
+            daa_score,
+        };
+        let cursor = PageCursor::new(pruned(1, 10), 20);
+        // unchanged sequence, the earliest position isn't looked up
+        let resumed = cursor.resume(pruned(1, 10), || unreachable!()).unwrap();
+        assert_eq!(resumed, Ok(20));
+        // pruned since, but not up to the position
+        let resumed = cursor.resume(pruned(2, 15), || Ok(15)).unwrap();
+        assert_eq!(resumed, Ok(20));
+        let resumed = cursor.resume(pruned(3, 25), || Ok(25)).unwrap();
+        assert_eq!(
+            resumed,
+            Err(PageCursor {
+                sequence: 3,
+                position: 25
+            })
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Profiles of the running indexer, served under `/admin/profile` by the query API.
+//!
+//! - `POST /admin/profile/cpu?seconds=`: samples every thread for `seconds`, 30 if unset, and
+//!   answers the stacks in the folded format speedscope and inferno open, rooted at the
+//!   pipeline stage, see [`profiling::cpu_profile`]. One profile runs at a time, requests
+//!   meanwhile are answered with 409
+//! - `GET /admin/profile/heap`: allocations per pipeline stage since startup, 501 unless the
+//!   binary runs on [`profiling::ComponentAllocator`], see [`profiling::heap_stats`]
+//!
+//! Requests have to carry `Authorization: Bearer {admin token}`.
+
+use super::{ApiError, Params, authorize_bearer};
+use crate::profiling::{self, ComponentHeap, CpuProfileRunning, DEFAULT_MAX_CPU_PROFILE};
+use axum::extract::{Request, State};
+use axum::http::HeaderMap;
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use std::time::Duration;
+
+pub const DEFAULT_CPU_PROFILE: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct ProfileApi {
+    /// An empty token authorizes nothing
+    admin_token: String,
+    max_cpu_profile: Duration,
+}
+
+impl ProfileApi {
+    pub fn new(admin_token: String) -> Self {
+        Self {
+            admin_token,
+            max_cpu_profile: DEFAULT_MAX_CPU_PROFILE,
+        }
+    }
+
+    /// Longest CPU profile a request may ask for, [`DEFAULT_MAX_CPU_PROFILE`] by default
+    pub fn with_max_cpu_profile(mut self, max: Duration) -> Self {
+        self.max_cpu_profile = max;
+        self
+    }
+
+    /// The profile endpoints, every one requiring the admin token
+    pub(crate) fn router<S: Clone + Send + Sync + 'static>(self) -> Router<S> {
+        Router::new()
+            .route("/admin/profile/cpu", post(cpu))
+            .route("/admin/profile/heap", get(heap))
+            .route_layer(middleware::from_fn_with_state(self.clone(), authorize))
+            .with_state(self)
+    }
+
+    /// Whether the headers carry the admin token
+    pub fn authorize(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        authorize_bearer(headers, &self.admin_token)
+    }
+
+    /// Duration of a CPU profile asked for with `seconds`
+    pub fn cpu_profile_duration(&self, seconds: Option<u64>) -> Result<Duration, ApiError> {
+        let duration = seconds.map_or(DEFAULT_CPU_PROFILE, Duration::from_secs);
+        if duration.is_zero() || duration > self.max_cpu_profile {
+            return Err(ApiError::BadRequest(format!(
+                "seconds must be between 1 and {}",
+                self.max_cpu_profile.as_secs()
+            )));
+        }
+        Ok(duration)
+    }
+}
+
+async fn authorize(
+    State(api): State<ProfileApi>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    api.authorize(request.headers())?;
+    Ok(next.run(request).await)
+}
+
+async fn cpu(State(api): State<ProfileApi>, params: Params) -> Result<Response, ApiError> {
+    let duration = api.cpu_profile_duration(params.optional("seconds")?)?;
+    let profile = tokio::task::spawn_blocking(move || profiling::cpu_profile(duration))
+        .await
+        .map_err(anyhow::Error::from)?
+        .map_err(|err| match err.downcast_ref::<CpuProfileRunning>() {
+            Some(running) => ApiError::Conflict(running.to_string()),
+            None => ApiError::Internal(err),
+        })?;
+    Ok(([(CONTENT_TYPE, "text/plain; charset=utf-8")], profile).into_response())
+}
+
+async fn heap() -> Result<Json<Vec<ComponentHeap>>, ApiError> {
+    profiling::heap_stats().map(Json).ok_or_else(|| {
+        ApiError::NotImplemented(
+            "heap stats need the component allocator as global allocator".to_string(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{QueryApi, serve};
+    use crate::database::headers::BlockCompactHeaderPartition;
+    use crate::shutdown::Shutdown;
+    use axum::body::Bytes;
+    use axum::http::header::{AUTHORIZATION, HOST};
+    use axum::http::{Method, StatusCode};
+    use http_body_util::{BodyExt, Empty};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn request(addr: &str, method: Method, path: &str, token: &str) -> (StatusCode, String) {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(path)
+            .header(HOST, addr)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_cpu_profile_duration() {
+        let api =
+            ProfileApi::new("t0ken".to_string()).with_max_cpu_profile(Duration::from_secs(40));
+        assert_eq!(api.cpu_profile_duration(None).unwrap(), DEFAULT_CPU_PROFILE);
+        assert_eq!(
+            api.cpu_profile_duration(Some(40)).unwrap(),
+            Duration::from_secs(40)
+        );
+        for seconds in [0, 41] {
+            assert!(matches!(
+                api.cpu_profile_duration(Some(seconds)),
+                Err(ApiError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_serve_profiles() {
+        let keyspace = crate::database::test_keyspace("api-profile");
+        let profile =
+            ProfileApi::new("t0ken".to_string()).with_max_cpu_profile(Duration::from_secs(2));
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap()
+        .with_profiling(profile);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(listener, api, shutdown.clone()));
+
+        let cpu = "/admin/profile/cpu?seconds=1";
+        let (status, _) = request(&addr, Method::POST, cpu, "wrong").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) =
+            request(&addr, Method::POST, "/admin/profile/cpu?seconds=3", "t0ken").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        // the tests do not run on the component allocator
+        let (status, _) = request(&addr, Method::GET, "/admin/profile/heap", "t0ken").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+
+        // a second profile is refused while the first one runs
+        let first = tokio::spawn({
+            let addr = addr.clone();
+            async move { request(&addr, Method::POST, cpu, "t0ken").await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let (status, _) = request(&addr, Method::POST, cpu, "t0ken").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, profile) = first.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        for line in profile.lines() {
+            let (stack, samples) = line.rsplit_once(' ').unwrap();
+            assert!(!stack.is_empty());
+            samples.parse::<u64>().unwrap();
+        }
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
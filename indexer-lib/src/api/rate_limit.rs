@@ -0,0 +1,140 @@
+//! Per address token buckets of the query API.
+//!
+//! Every address gets a bucket of `burst` tokens refilled at `per_second`, a request takes one
+//! token or is refused. Full buckets carry no state, they are dropped once more than
+//! [`MAX_TRACKED_ADDRESSES`] addresses are tracked.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before the full ones are dropped
+pub const MAX_TRACKED_ADDRESSES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            per_second: per_second.max(1) as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token of `addr`, or returns the wait until one is available
+    pub fn check(&self, addr: IpAddr) -> Result<(), Duration> {
+        self.check_at(addr, Instant::now())
+    }
+
+    fn check_at(&self, addr: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_TRACKED_ADDRESSES && !buckets.contains_key(&addr) {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(addr).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = self.refill(bucket, now);
+        if tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - tokens) / self.per_second));
+        }
+        *bucket = Bucket {
+            tokens: tokens - 1.0,
+            updated: now,
+        };
+        Ok(())
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+
+    #[cfg(test)]
+    fn tracked(&self) -> usize {
+        self.buckets.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_token_bucket_per_address() {
+        let limiter = RateLimiter::new(10, 3);
+        let (a, b) = (
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+        );
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(a, start), Ok(()));
+        }
+        let wait = limiter.check_at(a, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(100));
+        // other addresses have their own bucket
+        assert_eq!(limiter.check_at(b, start), Ok(()));
+        // a token every 100 ms, refused requests take none
+        assert!(
+            limiter
+                .check_at(a, start + Duration::from_millis(50))
+                .is_err()
+        );
+        assert_eq!(
+            limiter.check_at(a, start + Duration::from_millis(100)),
+            Ok(())
+        );
+        assert!(
+            limiter
+                .check_at(a, start + Duration::from_millis(150))
+                .is_err()
+        );
+        // refilled up to the burst only
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(a, later), Ok(()));
+        }
+        assert!(limiter.check_at(a, later).is_err());
+    }
+
+    #[test]
+    fn test_full_buckets_are_dropped() {
+        let limiter = RateLimiter::new(1, 2);
+        let start = Instant::now();
+        for i in 0..MAX_TRACKED_ADDRESSES as u32 {
+            limiter
+                .check_at(IpAddr::V4(Ipv4Addr::from(i)), start)
+                .unwrap();
+        }
+        let busy = IpAddr::V4(Ipv4Addr::from(0));
+        limiter.check_at(busy, start).unwrap();
+        assert_eq!(limiter.tracked(), MAX_TRACKED_ADDRESSES);
+        // one second later every bucket is full again but the drained one
+        let new = IpAddr::V4(Ipv4Addr::from(u32::MAX));
+        limiter
+            .check_at(new, start + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(limiter.tracked(), 2);
+        assert!(
+            limiter
+                .check_at(busy, start + Duration::from_secs(1))
+                .is_ok()
+        );
+    }
+}
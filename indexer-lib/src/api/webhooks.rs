@@ -0,0 +1,444 @@
+//! Management of the webhook subscriptions, served under `/webhooks` by the query API.
+//!
+//! - `POST /webhooks` with `{"address", "url", "min_confirmations", "secret"}`: subscribes the
+//!   callback url to payments to the address, answers the subscription
+//! - `GET /webhooks`: all subscriptions, without their secrets
+//! - `GET /webhooks/{id}`, `DELETE /webhooks/{id}`: one subscription
+//! - `GET /webhooks/dead-letters`: deliveries given up on
+//!
+//! Requests have to carry `Authorization: Bearer {admin token}`. Callback urls to loopback,
+//! private and link-local hosts are refused unless private callbacks are allowed. Delivery is
+//! described in [`crate::webhooks`].
+
+use super::{ApiError, authorize_bearer, parse};
+use crate::database::webhooks::{DeadLetter, WebhookSubscription, Webhooks};
+use crate::metrics_exporter::REQUEST_TIMEOUT;
+use crate::webhooks::CallbackUrl;
+use anyhow::Result;
+use axum::body::to_bytes;
+use axum::extract::{Path, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Json, Router};
+use kaspa_addresses::Prefix;
+use kaspa_rpc_core::RpcAddress;
+use serde::{Deserialize, Serialize};
+
+/// Request bodies beyond this are refused
+const MAX_BODY_BYTES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub address: String,
+    pub url: String,
+    /// Confirmations before delivery, the accepting chain block counts as the first
+    #[serde(default = "default_min_confirmations")]
+    pub min_confirmations: u64,
+    /// Key of the delivery signatures
+    pub secret: String,
+}
+
+fn default_min_confirmations() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookResponse {
+    pub id: u64,
+    pub address: String,
+    pub url: String,
+    pub min_confirmations: u64,
+}
+
+impl From<WebhookSubscription> for WebhookResponse {
+    fn from(subscription: WebhookSubscription) -> Self {
+        Self {
+            id: subscription.id,
+            address: subscription.address,
+            url: subscription.url,
+            min_confirmations: subscription.min_confirmations,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetterResponse {
+    pub idempotency_key: String,
+    pub subscription_id: u64,
+    pub failed_at_ms: u64,
+    pub attempts: u32,
+    pub error: String,
+    pub payload: serde_json::Value,
+}
+
+impl TryFrom<DeadLetter> for DeadLetterResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(dead_letter: DeadLetter) -> Result<Self> {
+        Ok(Self {
+            idempotency_key: dead_letter.id.to_string(),
+            subscription_id: dead_letter.id.subscription_id,
+            failed_at_ms: dead_letter.failed_at_ms,
+            attempts: dead_letter.attempts,
+            error: dead_letter.error,
+            payload: serde_json::from_str(&dead_letter.payload)?,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookApi {
+    webhooks: Webhooks,
+    /// An empty token authorizes nothing
+    admin_token: String,
+    allow_private_callbacks: bool,
+    /// Addresses of other networks are refused
+    address_prefix: Prefix,
+}
+
+impl WebhookApi {
+    pub fn new(webhooks: Webhooks, admin_token: String) -> Self {
+        Self {
+            webhooks,
+            admin_token,
+            allow_private_callbacks: false,
+            address_prefix: Prefix::Mainnet,
+        }
+    }
+
+    /// Accepts callback urls to loopback and private hosts
+    pub fn with_private_callbacks(mut self, allowed: bool) -> Self {
+        self.allow_private_callbacks = allowed;
+        self
+    }
+
+    /// Network of the watched addresses, mainnet by default
+    pub fn with_address_prefix(mut self, prefix: Prefix) -> Self {
+        self.address_prefix = prefix;
+        self
+    }
+
+    /// The webhook endpoints, every one requiring the admin token
+    pub(crate) fn router<S: Clone + Send + Sync + 'static>(self) -> Router<S> {
+        Router::new()
+            .route("/webhooks", get(list).post(create))
+            .route("/webhooks/dead-letters", get(dead_letters))
+            .route("/webhooks/{id}", get(subscription).delete(unsubscribe))
+            .route_layer(middleware::from_fn_with_state(self.clone(), authorize))
+            .with_state(self)
+    }
+
+    /// Whether the headers carry the admin token
+    pub fn authorize(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        authorize_bearer(headers, &self.admin_token)
+    }
+
+    pub fn subscribe(&self, request: CreateWebhookRequest) -> Result<WebhookResponse, ApiError> {
+        let address = RpcAddress::try_from(request.address.as_str())
+            .map_err(|err| ApiError::BadRequest(format!("Invalid address: {err}")))?;
+        if address.prefix != self.address_prefix {
+            return Err(ApiError::BadRequest(format!(
+                "Address {address} is not a {} address",
+                self.address_prefix
+            )));
+        }
+        let url = request
+            .url
+            .parse::<CallbackUrl>()
+            .map_err(|err| ApiError::BadRequest(err.to_string()))?;
+        if !self.allow_private_callbacks {
+            url.ensure_public()
+                .map_err(|err| ApiError::BadRequest(err.to_string()))?;
+        }
+        if request.secret.is_empty() || request.secret.contains('\n') {
+            return Err(ApiError::BadRequest(
+                "secret must be a non-empty single line".to_string(),
+            ));
+        }
+        if request.min_confirmations == 0 {
+            return Err(ApiError::BadRequest(
+                "min_confirmations must be positive".to_string(),
+            ));
+        }
+        let subscription = self.webhooks.subscribe(
+            &address,
+            &request.url,
+            request.min_confirmations,
+            &request.secret,
+        )?;
+        Ok(subscription.into())
+    }
+
+    pub fn subscriptions(&self) -> Result<Vec<WebhookResponse>, ApiError> {
+        Ok(self
+            .webhooks
+            .subscriptions()?
+            .into_iter()
+            .map(WebhookResponse::from)
+            .collect())
+    }
+
+    pub fn subscription(&self, id: u64) -> Result<WebhookResponse, ApiError> {
+        self.webhooks
+            .get_subscription(id)?
+            .map(WebhookResponse::from)
+            .ok_or_else(|| ApiError::NotFound(format!("webhook {id} not found")))
+    }
+
+    pub fn unsubscribe(&self, id: u64) -> Result<(), ApiError> {
+        match self.webhooks.unsubscribe(id)? {
+            true => Ok(()),
+            false => Err(ApiError::NotFound(format!("webhook {id} not found"))),
+        }
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetterResponse>, ApiError> {
+        Ok(self
+            .webhooks
+            .dead_letters()?
+            .into_iter()
+            .map(DeadLetterResponse::try_from)
+            .collect::<Result<Vec<_>>>()?)
+    }
+}
+
+async fn authorize(
+    State(api): State<WebhookApi>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    api.authorize(request.headers())?;
+    Ok(next.run(request).await)
+}
+
+/// Runs `call` off the runtime, the store is written synchronously
+async fn blocking<T: Send + 'static>(
+    api: WebhookApi,
+    call: impl FnOnce(&WebhookApi) -> Result<T, ApiError> + Send + 'static,
+) -> Result<T, ApiError> {
+    tokio::task::spawn_blocking(move || call(&api))
+        .await
+        .map_err(anyhow::Error::from)?
+}
+
+async fn create(
+    State(api): State<WebhookApi>,
+    request: Request,
+) -> Result<(StatusCode, Json<WebhookResponse>), ApiError> {
+    let body = tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        to_bytes(request.into_body(), MAX_BODY_BYTES),
+    )
+    .await
+    .map_err(|_| ApiError::BadRequest("Request body not received in time".to_string()))?
+    .map_err(|err| ApiError::BadRequest(format!("Invalid request body: {err}")))?;
+    let request = serde_json::from_slice::<CreateWebhookRequest>(&body)
+        .map_err(|err| ApiError::BadRequest(format!("Invalid request: {err}")))?;
+    let subscription = blocking(api, move |api| api.subscribe(request)).await?;
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+async fn list(State(api): State<WebhookApi>) -> Result<Json<Vec<WebhookResponse>>, ApiError> {
+    blocking(api, WebhookApi::subscriptions).await.map(Json)
+}
+
+async fn dead_letters(
+    State(api): State<WebhookApi>,
+) -> Result<Json<Vec<DeadLetterResponse>>, ApiError> {
+    blocking(api, WebhookApi::dead_letters).await.map(Json)
+}
+
+async fn subscription(
+    State(api): State<WebhookApi>,
+    Path(id): Path<String>,
+) -> Result<Json<WebhookResponse>, ApiError> {
+    let id = parse(&id, "webhook id")?;
+    blocking(api, move |api| api.subscription(id))
+        .await
+        .map(Json)
+}
+
+async fn unsubscribe(
+    State(api): State<WebhookApi>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let id = parse(&id, "webhook id")?;
+    blocking(api, move |api| api.unsubscribe(id)).await?;
+    Ok(Json(serde_json::json!({ "deleted": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{QueryApi, serve};
+    use crate::database::headers::BlockCompactHeaderPartition;
+    use crate::shutdown::Shutdown;
+    use axum::http::HeaderValue;
+    use axum::http::header::AUTHORIZATION;
+    use kaspa_addresses::Version;
+    use tokio::net::TcpListener;
+
+    fn keyspace(name: &str) -> fjall::TxKeyspace {
+        crate::database::test_keyspace(&format!("api-webhooks-{name}"))
+    }
+
+    fn api(name: &str) -> WebhookApi {
+        WebhookApi::new(Webhooks::new(&keyspace(name)).unwrap(), "t0ken".to_string())
+    }
+
+    fn create(address: &RpcAddress, url: &str) -> CreateWebhookRequest {
+        CreateWebhookRequest {
+            address: address.to_string(),
+            url: url.to_string(),
+            min_confirmations: 10,
+            secret: "secret".to_string(),
+        }
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_manage_subscriptions() {
+        let api = api("manage");
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[3; 32]);
+
+        let created = api
+            .subscribe(create(&address, "https://example.com:9000/hook"))
+            .unwrap();
+        assert!(!serde_json::to_string(&created).unwrap().contains("secret"));
+        assert_eq!(
+            (created.address.as_str(), created.min_confirmations),
+            (address.to_string().as_str(), 10)
+        );
+
+        assert_eq!(api.subscriptions().unwrap(), [created.clone()]);
+        assert_eq!(api.subscription(created.id).unwrap(), created);
+        assert!(api.dead_letters().unwrap().is_empty());
+
+        api.unsubscribe(created.id).unwrap();
+        assert!(matches!(
+            api.unsubscribe(created.id),
+            Err(ApiError::NotFound(_))
+        ));
+        assert!(matches!(
+            api.subscription(created.id),
+            Err(ApiError::NotFound(_))
+        ));
+
+        let testnet = RpcAddress::new(Prefix::Testnet, Version::PubKey, &[3; 32]);
+        for request in [
+            create(&address, "ftp://example.com/hook"),
+            create(&address, "http://localhost:9000/hook"),
+            create(&address, "http://10.0.0.1/hook"),
+            create(&testnet, "https://example.com/hook"),
+            CreateWebhookRequest {
+                secret: String::new(),
+                ..create(&address, "https://example.com/hook")
+            },
+        ] {
+            assert!(
+                matches!(api.subscribe(request.clone()), Err(ApiError::BadRequest(_))),
+                "{request:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_admin_token() {
+        let api = api("token");
+        assert!(matches!(
+            api.authorize(&HeaderMap::new()),
+            Err(ApiError::Unauthorized)
+        ));
+        assert!(matches!(
+            api.authorize(&bearer("wrong")),
+            Err(ApiError::Unauthorized)
+        ));
+        assert!(api.authorize(&bearer("t0ken")).is_ok());
+
+        // an empty token never authorizes
+        let api = WebhookApi::new(
+            Webhooks::new(&keyspace("empty-token")).unwrap(),
+            String::new(),
+        );
+        assert!(matches!(
+            api.authorize(&bearer("")),
+            Err(ApiError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_private_callbacks() {
+        let api = api("private").with_private_callbacks(true);
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[3; 32]);
+        api.subscribe(create(&address, "http://127.0.0.1:9000/hook"))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_webhooks() {
+        let keyspace = keyspace("serve");
+        let webhooks = WebhookApi::new(Webhooks::new(&keyspace).unwrap(), "t0ken".to_string());
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap()
+        .with_webhooks(webhooks);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/webhooks", listener.local_addr().unwrap());
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(listener, api, shutdown.clone()));
+        let client = reqwest::Client::new();
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[3; 32]);
+        let request = serde_json::to_string(&create(&address, "https://example.com/hook")).unwrap();
+
+        let response = client
+            .post(&url)
+            .body(request.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = client
+            .post(&url)
+            .bearer_auth("t0ken")
+            .body(request)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created = response.json::<WebhookResponse>().await.unwrap();
+        let response = client.get(&url).bearer_auth("t0ken").send().await.unwrap();
+        assert_eq!(
+            response.json::<Vec<WebhookResponse>>().await.unwrap(),
+            [created]
+        );
+
+        let response = client.put(&url).bearer_auth("t0ken").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = client
+            .post(&url)
+            .bearer_auth("t0ken")
+            .body(vec![b' '; MAX_BODY_BYTES + 1])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error = response.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(error["code"], "bad_request");
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
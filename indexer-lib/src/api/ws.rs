@@ -0,0 +1,587 @@
+//! WebSocket push stream of the indexed data, served at `/ws` by the query API.
+//!
+//! Clients send `{"subscribe": [..]}` and `{"unsubscribe": [..]}` with the topics `blocks`,
+//! `chain_blocks` and `address:{address}`, acknowledged with the topics subscribed to. Every
+//! client reads the [`IndexedBlocks`] broadcast on its own and queues what its topics match up to
+//! a bound. A client whose queue stays full for the slow client timeout, or which lags behind
+//! the broadcast, is closed with code 1008 (policy violation), the processors never wait for
+//! clients.
+
+use crate::block_events::{IndexEvent, IndexedBlocks, MessageKind};
+use crate::database::messages::AddressPayload;
+use anyhow::Result;
+use axum::extract::ws::{CloseCode, CloseFrame, Message, Utf8Bytes, WebSocket, close_code};
+use futures_util::{SinkExt, StreamExt};
+use kaspa_addresses::Prefix;
+use kaspa_rpc_core::RpcAddress;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+pub const DEFAULT_CLIENT_QUEUE_CAPACITY: usize = 256;
+pub const DEFAULT_SLOW_CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Address topics a single client may subscribe to
+pub const MAX_ADDRESS_TOPICS: usize = 100;
+/// Closing gives up on a client not reading the close frame within this
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Topic {
+    Blocks,
+    ChainBlocks,
+    Address(String),
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(topic: &str) -> Result<Self, Self::Err> {
+        match topic {
+            "blocks" => Ok(Self::Blocks),
+            "chain_blocks" => Ok(Self::ChainBlocks),
+            _ => match topic.strip_prefix("address:") {
+                Some(address) => RpcAddress::try_from(address)
+                    .map(|address| Self::Address(address.to_string()))
+                    .map_err(|err| format!("Invalid address {address}: {err}")),
+                None => Err(format!("Unknown topic {topic}")),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Topic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blocks => f.write_str("blocks"),
+            Self::ChainBlocks => f.write_str("chain_blocks"),
+            Self::Address(address) => write!(f, "address:{address}"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Command {
+    pub subscribe: Vec<String>,
+    pub unsubscribe: Vec<String>,
+}
+
+/// Messages pushed to clients, tagged by `type`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PushMessage {
+    /// Answers every command with the topics subscribed to afterwards
+    Subscribed { topics: Vec<String> },
+    /// Answers a command that was not applied
+    Error { error: String },
+    Block {
+        hash: String,
+        daa_score: u64,
+        blue_work: String,
+        tx_count: u64,
+        is_chain_block: bool,
+    },
+    ChainBlock {
+        hash: String,
+        /// Removed by a reorg otherwise
+        added: bool,
+        daa_score: Option<u64>,
+        accepted_tx_count: u64,
+    },
+    /// Of an address topic, received by the address
+    Message {
+        address: String,
+        tx_id: String,
+        kind: MessageKind,
+        block_hash: String,
+        daa_score: u64,
+        block_time_ms: u64,
+    },
+}
+
+/// Topics of a single client
+struct Subscriptions {
+    /// Addresses of other networks are refused
+    prefix: Prefix,
+    blocks: bool,
+    chain_blocks: bool,
+    addresses: Vec<(String, AddressPayload)>,
+}
+
+impl Subscriptions {
+    fn new(prefix: Prefix) -> Self {
+        Self {
+            prefix,
+            blocks: false,
+            chain_blocks: false,
+            addresses: Vec::new(),
+        }
+    }
+
+    fn topics(&self) -> Vec<String> {
+        let mut topics = Vec::new();
+        if self.blocks {
+            topics.push(Topic::Blocks.to_string());
+        }
+        if self.chain_blocks {
+            topics.push(Topic::ChainBlocks.to_string());
+        }
+        topics.extend(
+            self.addresses
+                .iter()
+                .map(|(address, _)| Topic::Address(address.clone()).to_string()),
+        );
+        topics
+    }
+
+    /// Applies the whole command or nothing of it
+    fn apply(&mut self, command: &str) -> PushMessage {
+        match self.try_apply(command) {
+            Ok(()) => PushMessage::Subscribed {
+                topics: self.topics(),
+            },
+            Err(error) => PushMessage::Error { error },
+        }
+    }
+
+    fn try_apply(&mut self, command: &str) -> Result<(), String> {
+        let command = serde_json::from_str::<Command>(command)
+            .map_err(|err| format!("Invalid command: {err}"))?;
+        let parse = |topics: &[String]| {
+            topics
+                .iter()
+                .map(|topic| topic.parse::<Topic>())
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let (subscribe, unsubscribe) = (parse(&command.subscribe)?, parse(&command.unsubscribe)?);
+        let mut next = Self {
+            prefix: self.prefix,
+            blocks: self.blocks,
+            chain_blocks: self.chain_blocks,
+            addresses: self.addresses.clone(),
+        };
+        for topic in subscribe {
+            match topic {
+                Topic::Blocks => next.blocks = true,
+                Topic::ChainBlocks => next.chain_blocks = true,
+                Topic::Address(address) => {
+                    if next.addresses.iter().all(|(known, _)| *known != address) {
+                        let payload = RpcAddress::try_from(address.as_str())
+                            .map_err(|err| err.to_string())
+                            .and_then(|rpc_address| {
+                                if rpc_address.prefix != self.prefix {
+                                    return Err(format!("not a {} address", self.prefix));
+                                }
+                                AddressPayload::try_from(&rpc_address)
+                                    .map_err(|err| err.to_string())
+                            })
+                            .map_err(|err| format!("Unsupported address {address}: {err}"))?;
+                        next.addresses.push((address, payload));
+                    }
+                }
+            }
+        }
+        for topic in unsubscribe {
+            match topic {
+                Topic::Blocks => next.blocks = false,
+                Topic::ChainBlocks => next.chain_blocks = false,
+                Topic::Address(address) => next.addresses.retain(|(known, _)| *known != address),
+            }
+        }
+        if next.addresses.len() > MAX_ADDRESS_TOPICS {
+            return Err(format!(
+                "At most {MAX_ADDRESS_TOPICS} address topics per client"
+            ));
+        }
+        *self = next;
+        Ok(())
+    }
+
+    /// What the subscribed topics match of the event
+    fn filter(&self, event: &IndexEvent) -> Vec<PushMessage> {
+        match event {
+            IndexEvent::BlockIndexed(block) if self.blocks => vec![PushMessage::Block {
+                hash: block.hash.to_string(),
+                daa_score: block.daa_score,
+                blue_work: block.blue_work.to_string(),
+                tx_count: block.tx_count as u64,
+                is_chain_block: block.is_chain_block,
+            }],
+            IndexEvent::ChainBlockChanged(change) if self.chain_blocks => {
+                vec![PushMessage::ChainBlock {
+                    hash: change.hash.to_string(),
+                    added: change.added,
+                    daa_score: change.daa_score,
+                    accepted_tx_count: change.accepted_tx_count as u64,
+                }]
+            }
+            IndexEvent::MessageIndexed(message) => self
+                .addresses
+                .iter()
+                .filter(|(_, payload)| message.receiver == Some(*payload))
+                .map(|(address, _)| PushMessage::Message {
+                    address: address.clone(),
+                    tx_id: message.tx_id.to_string(),
+                    kind: message.kind,
+                    block_hash: message.block_hash.to_string(),
+                    daa_score: message.daa_score,
+                    block_time_ms: message.block_time_ms,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Pushes the events of the processors to the connected clients
+#[derive(Clone)]
+pub struct PushStream {
+    events: IndexedBlocks,
+    queue_capacity: usize,
+    slow_client_timeout: Duration,
+    address_prefix: Prefix,
+    clients: Arc<AtomicUsize>,
+}
+
+impl PushStream {
+    pub fn new(events: IndexedBlocks) -> Self {
+        Self {
+            events,
+            queue_capacity: DEFAULT_CLIENT_QUEUE_CAPACITY,
+            slow_client_timeout: DEFAULT_SLOW_CLIENT_TIMEOUT,
+            address_prefix: Prefix::Mainnet,
+            clients: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Messages queued per client before pushing waits on it
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// How long pushing waits on a full queue before the client is closed
+    pub fn with_slow_client_timeout(mut self, timeout: Duration) -> Self {
+        self.slow_client_timeout = timeout;
+        self
+    }
+
+    /// Network of the address topics, mainnet by default
+    pub fn with_address_prefix(mut self, prefix: Prefix) -> Self {
+        self.address_prefix = prefix;
+        self
+    }
+
+    /// Clients connected
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    /// Serves the upgraded client until either side closes or `shutdown` is cancelled
+    pub(crate) async fn accept(
+        &self,
+        socket: WebSocket,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+        let result = self.serve_client(socket, shutdown).await;
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    async fn serve_client(&self, socket: WebSocket, shutdown: CancellationToken) -> Result<()> {
+        let (sink, mut incoming) = socket.split();
+        let (queue_tx, queue_rx) = mpsc::channel(self.queue_capacity);
+        let (close_tx, close_rx) = oneshot::channel();
+        let writer = tokio::spawn(write(sink, queue_rx, close_rx));
+        let mut events = self.events.subscribe();
+        let mut subscriptions = Subscriptions::new(self.address_prefix);
+        let close = 'client: loop {
+            let outgoing = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    break close_frame(close_code::AWAY, "indexer shutting down");
+                }
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        break close_frame(close_code::AWAY, "indexer stopped");
+                    };
+                    if events.dropped() > 0 {
+                        break close_frame(close_code::POLICY, "client too slow");
+                    }
+                    subscriptions.filter(&event)
+                }
+                message = incoming.next() => match message {
+                    Some(Ok(Message::Text(command))) => vec![subscriptions.apply(&command)],
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                    // pings are answered by the socket
+                    Some(Ok(_)) => continue,
+                },
+            };
+            for message in outgoing {
+                let text = Message::Text(serde_json::to_string(&message)?.into());
+                match tokio::time::timeout(self.slow_client_timeout, queue_tx.send(text)).await {
+                    Ok(Ok(())) => {}
+                    // the writer failed
+                    Ok(Err(_)) => break 'client None,
+                    Err(_) => break 'client close_frame(close_code::POLICY, "client too slow"),
+                }
+            }
+        };
+        if let Some(frame) = close {
+            debug!(code = frame.code, reason = %frame.reason, "Closing push stream client");
+            _ = close_tx.send(frame);
+        }
+        drop(queue_tx);
+        writer.await?
+    }
+}
+
+fn close_frame(code: CloseCode, reason: &'static str) -> Option<CloseFrame> {
+    Some(CloseFrame {
+        code,
+        reason: Utf8Bytes::from_static(reason),
+    })
+}
+
+/// Sends the queued messages until closed, the messages still queued are dropped then
+async fn write(
+    mut sink: futures_util::stream::SplitSink<WebSocket, Message>,
+    mut queue: mpsc::Receiver<Message>,
+    mut close: oneshot::Receiver<CloseFrame>,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            biased;
+            frame = &mut close => {
+                if let Ok(frame) = frame {
+                    _ = tokio::time::timeout(CLOSE_TIMEOUT, sink.send(Message::Close(Some(frame)))).await;
+                }
+                return Ok(());
+            }
+            message = queue.recv() => match message {
+                Some(message) => sink.send(message).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{QueryApi, serve};
+    use crate::block_events::{BlockIndexed, ChainBlockChanged, MessageIndexed};
+    use crate::database::headers::BlockCompactHeaderPartition;
+    use crate::shutdown::Shutdown;
+    use kaspa_addresses::{Prefix, Version};
+    use kaspa_consensus_core::BlueWorkType;
+    use kaspa_rpc_core::{RpcHash, RpcTransactionId};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn block(i: u64) -> IndexEvent {
+        BlockIndexed {
+            hash: RpcHash::from_u64_word(i),
+            daa_score: i,
+            blue_work: BlueWorkType::from_u64(i),
+            tx_count: 1,
+            is_chain_block: false,
+        }
+        .into()
+    }
+
+    fn chain_block(i: u64) -> IndexEvent {
+        ChainBlockChanged {
+            hash: RpcHash::from_u64_word(i),
+            added: true,
+            daa_score: Some(i),
+            accepted_tx_count: 1,
+        }
+        .into()
+    }
+
+    fn message(i: u64, receiver: &RpcAddress) -> IndexEvent {
+        MessageIndexed {
+            tx_id: RpcTransactionId::from_u64_word(i),
+            kind: MessageKind::Payment,
+            block_hash: RpcHash::from_u64_word(i),
+            daa_score: i,
+            block_time_ms: i * 100,
+            receiver: Some(AddressPayload::try_from(receiver).unwrap()),
+        }
+        .into()
+    }
+
+    /// Serves the push stream only, the keyspace is left empty
+    async fn start(name: &str, push: PushStream) -> (String, Shutdown) {
+        let keyspace = crate::database::test_keyspace(&format!("ws-{name}"));
+        let api = QueryApi::new(
+            &keyspace,
+            BlockCompactHeaderPartition::new(&keyspace).unwrap(),
+            None,
+        )
+        .unwrap()
+        .with_push_stream(push);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve(listener, api, shutdown.clone()));
+        (addr, shutdown)
+    }
+
+    async fn send(client: &mut Client, command: &str) -> PushMessage {
+        client.send(Message::Text(command.into())).await.unwrap();
+        next(client).await.unwrap()
+    }
+
+    /// Next pushed message, none once closed
+    async fn next(client: &mut Client) -> Option<PushMessage> {
+        match client.next().await? {
+            Ok(Message::Text(text)) => Some(serde_json::from_str(&text).unwrap()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_subscribed_topics_in_order() {
+        let events = IndexedBlocks::default();
+        let (addr, shutdown) = start("order", PushStream::new(events.clone())).await;
+        let address = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        let other = RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[8; 32]);
+
+        let (mut all, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let (mut chain, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        let subscribed = send(
+            &mut all,
+            &format!(r#"{{"subscribe": ["blocks", "chain_blocks", "address:{address}"]}}"#),
+        )
+        .await;
+        assert_eq!(
+            subscribed,
+            PushMessage::Subscribed {
+                topics: vec![
+                    "blocks".to_string(),
+                    "chain_blocks".to_string(),
+                    format!("address:{address}")
+                ]
+            }
+        );
+        // nothing is applied of a command with an invalid topic
+        let rejected = send(&mut chain, r#"{"subscribe": ["chain_blocks", "mempool"]}"#).await;
+        assert!(
+            matches!(rejected, PushMessage::Error { .. }),
+            "{rejected:?}"
+        );
+        let subscribed = send(&mut chain, r#"{"subscribe": ["chain_blocks"]}"#).await;
+        assert_eq!(
+            subscribed,
+            PushMessage::Subscribed {
+                topics: vec!["chain_blocks".to_string()]
+            }
+        );
+
+        for i in 1..=3 {
+            events.publish(block(i));
+            events.publish(message(i, &address));
+            events.publish(message(i, &other));
+            events.publish(chain_block(i));
+        }
+
+        let mut pushed = Vec::new();
+        for _ in 0..9 {
+            pushed.push(match next(&mut all).await.unwrap() {
+                PushMessage::Block { daa_score, .. } => ("block", daa_score),
+                PushMessage::Message {
+                    daa_score,
+                    address: pushed_address,
+                    ..
+                } => {
+                    assert_eq!(pushed_address, address.to_string());
+                    ("message", daa_score)
+                }
+                PushMessage::ChainBlock { daa_score, .. } => ("chain_block", daa_score.unwrap()),
+                message => panic!("unexpected {message:?}"),
+            });
+        }
+        assert_eq!(
+            pushed,
+            (1..=3)
+                .flat_map(|i| [("block", i), ("message", i), ("chain_block", i)])
+                .collect::<Vec<_>>()
+        );
+        for i in 1..=3 {
+            assert!(matches!(
+                next(&mut chain).await.unwrap(),
+                PushMessage::ChainBlock { daa_score: Some(daa_score), added: true, .. } if daa_score == i
+            ));
+        }
+
+        let unsubscribed = send(&mut all, r#"{"unsubscribe": ["blocks", "chain_blocks"]}"#).await;
+        assert_eq!(
+            unsubscribed,
+            PushMessage::Subscribed {
+                topics: vec![format!("address:{address}")]
+            }
+        );
+        events.publish(block(4));
+        events.publish(message(4, &address));
+        assert!(matches!(
+            next(&mut all).await.unwrap(),
+            PushMessage::Message { daa_score: 4, .. }
+        ));
+        shutdown.cancel();
+        // open clients are closed on shutdown
+        assert_eq!(next(&mut all).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_is_closed() {
+        let events = IndexedBlocks::new(1 << 16);
+        let push = PushStream::new(events.clone())
+            .with_queue_capacity(8)
+            .with_slow_client_timeout(Duration::from_millis(50));
+        let (addr, _shutdown) = start("slow", push.clone()).await;
+
+        let (mut slow, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+        send(&mut slow, r#"{"subscribe": ["blocks"]}"#).await;
+        assert_eq!(push.clients(), 1);
+        // the client stops reading until its socket buffers and its queue are full
+        let mut published = 0;
+        while push.clients() > 0 {
+            assert!(published < 10_000_000, "slow client was never closed");
+            for _ in 0..1_000 {
+                published += 1;
+                events.publish(block(published));
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // what was sent before is still delivered, followed by the close frame
+        let mut last = 0;
+        let frame = loop {
+            match slow.next().await.unwrap().unwrap() {
+                Message::Text(text) => {
+                    let PushMessage::Block { daa_score, .. } = serde_json::from_str(&text).unwrap()
+                    else {
+                        panic!("unexpected {text}");
+                    };
+                    assert_eq!(daa_score, last + 1, "pushed out of order");
+                    last = daa_score;
+                }
+                Message::Close(frame) => break frame.unwrap(),
+                message => panic!("unexpected {message:?}"),
+            }
+        };
+        assert_eq!(frame.code, CloseCode::Policy);
+        assert!(last < published);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::database::PartitionId;
+use crate::database::messages::AddressPayload;
+use crate::metrics::SharedMetrics;
+use kaspa_consensus_core::BlueWorkType;
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransactionId};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+pub const DEFAULT_INDEXED_BLOCKS_CAPACITY: usize = 1024;
+
+/// Published by the block processor once everything derived from the block is committed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockIndexed {
+    pub hash: RpcHash,
+    pub daa_score: u64,
+    pub blue_work: BlueWorkType,
+    pub tx_count: usize,
+    /// Selected chain membership when indexed, a virtual chain change seen before the block
+    /// overrides its verbose data
+    pub is_chain_block: bool,
+}
+
+impl From<&RpcBlock> for BlockIndexed {
+    fn from(block: &RpcBlock) -> Self {
+        Self {
+            hash: block.header.hash,
+            daa_score: block.header.daa_score,
+            blue_work: block.header.blue_work,
+            tx_count: block.transactions.len(),
+            is_chain_block: block
+                .verbose_data
+                .as_ref()
+                .is_some_and(|data| data.is_chain_block),
+        }
+    }
+}
+
+/// Published by the virtual chain processor once the accepting chain block of the
+/// transaction is buried at finality depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionFinalized {
+    pub tx_id: RpcTransactionId,
+    pub accepting_block_hash: RpcHash,
+    pub chain_index: u64,
+}
+
+/// Published by the virtual chain processor once a virtual chain change is committed, the
+/// removed blocks before the added ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainBlockChanged {
+    pub hash: RpcHash,
+    /// Removed from the selected chain by a reorg otherwise
+    pub added: bool,
+    /// Known for added blocks with a stored header
+    pub daa_score: Option<u64>,
+    pub accepted_tx_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Handshake,
+    Payment,
+    ContextualMessage,
+}
+
+impl MessageKind {
+    /// Kind of the acceptance entries recorded under `partition_id`
+    pub fn from_partition_id(partition_id: u8) -> Option<Self> {
+        match partition_id {
+            x if x == PartitionId::HandshakeBySender as u8 => Some(Self::Handshake),
+            x if x == PartitionId::PaymentBySender as u8 => Some(Self::Payment),
+            x if x == PartitionId::ContextualMessageBySender as u8 => Some(Self::ContextualMessage),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Handshake => "handshake",
+            Self::Payment => "payment",
+            Self::ContextualMessage => "contextual_message",
+        })
+    }
+}
+
+/// Published by the block processor right after the [`BlockIndexed`] of the message's block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageIndexed {
+    pub tx_id: RpcTransactionId,
+    pub kind: MessageKind,
+    pub block_hash: RpcHash,
+    pub daa_score: u64,
+    pub block_time_ms: u64,
+    /// Of handshakes and payments, senders are only known once resolved
+    pub receiver: Option<AddressPayload>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexEvent {
+    BlockIndexed(BlockIndexed),
+    TransactionFinalized(TransactionFinalized),
+    ChainBlockChanged(ChainBlockChanged),
+    MessageIndexed(MessageIndexed),
+}
+
+impl From<BlockIndexed> for IndexEvent {
+    fn from(event: BlockIndexed) -> Self {
+        Self::BlockIndexed(event)
+    }
+}
+
+impl From<TransactionFinalized> for IndexEvent {
+    fn from(event: TransactionFinalized) -> Self {
+        Self::TransactionFinalized(event)
+    }
+}
+
+impl From<ChainBlockChanged> for IndexEvent {
+    fn from(event: ChainBlockChanged) -> Self {
+        Self::ChainBlockChanged(event)
+    }
+}
+
+impl From<MessageIndexed> for IndexEvent {
+    fn from(event: MessageIndexed) -> Self {
+        Self::MessageIndexed(event)
+    }
+}
+
+/// Sending side, shared by the block and the virtual chain processor. Publishing never blocks
+/// the processors and never fails, events sent without subscribers are dropped
+#[derive(Clone)]
+pub struct IndexedBlocks {
+    tx: broadcast::Sender<IndexEvent>,
+    metrics: Option<SharedMetrics>,
+}
+
+impl IndexedBlocks {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity.max(1)).0,
+            metrics: None,
+        }
+    }
+
+    /// Receivers count the events they lagged behind on into these metrics
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn publish(&self, event: impl Into<IndexEvent>) {
+        _ = self.tx.send(event.into());
+    }
+
+    pub fn subscribe(&self) -> IndexedBlocksReceiver {
+        IndexedBlocksReceiver {
+            rx: self.tx.subscribe(),
+            metrics: self.metrics.clone(),
+            dropped: 0,
+        }
+    }
+}
+
+impl Default for IndexedBlocks {
+    fn default() -> Self {
+        Self::new(DEFAULT_INDEXED_BLOCKS_CAPACITY)
+    }
+}
+
+/// Receiver skipping past the events it lagged behind on instead of failing
+pub struct IndexedBlocksReceiver {
+    rx: broadcast::Receiver<IndexEvent>,
+    metrics: Option<SharedMetrics>,
+    dropped: u64,
+}
+
+impl IndexedBlocksReceiver {
+    /// Next event, none once the processors are gone
+    pub async fn recv(&mut self) -> Option<IndexEvent> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => self.lagged(skipped),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Events this receiver missed because it fell behind by more than the capacity
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn lagged(&mut self, skipped: u64) {
+        warn!(
+            skipped,
+            "Indexed block subscriber lagged behind, events dropped"
+        );
+        self.dropped += skipped;
+        if let Some(metrics) = &self.metrics {
+            metrics.add_indexed_block_events_dropped(skipped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::create_shared_metrics;
+
+    fn event(i: u64) -> BlockIndexed {
+        BlockIndexed {
+            hash: RpcHash::from_u64_word(i),
+            daa_score: i,
+            blue_work: BlueWorkType::from_u64(i),
+            tx_count: 1,
+            is_chain_block: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagging_receiver_counts_drops() {
+        let metrics = create_shared_metrics();
+        let indexed = IndexedBlocks::new(2).with_metrics(metrics.clone());
+        let mut rx = indexed.subscribe();
+        for i in 0..5 {
+            indexed.publish(event(i));
+        }
+        drop(indexed);
+
+        // only the newest two are still buffered
+        assert_eq!(rx.recv().await, Some(event(3).into()));
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(rx.recv().await, Some(event(4).into()));
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(metrics.snapshot().indexed_block_events_dropped, 3);
+    }
+}
@@ -1,35 +1,119 @@
 use crate::BlockOrMany;
-use crate::database::headers::{BlockCompactHeaderPartition, DaaIndexPartition};
+use crate::block_events::{
+    BlockIndexed, IndexEvent, IndexedBlocks, IndexedBlocksReceiver, MessageIndexed, MessageKind,
+};
+use crate::coinbase;
+use crate::database::aggregates::Aggregates;
+use crate::database::block_stats::{BlockStats, BlockStatsPartition};
+use crate::database::block_transactions::BlockTransactionsPartition;
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockGap, BlockRelations, BlockRelationsPartition,
+    ChainMembershipPartition, DaaIndexPartition,
+};
 use crate::database::messages::{
     AddressPayload, ContextualMessageBySenderPartition, HandshakeByReceiverPartition,
     HandshakeKeyByReceiver, PaymentByReceiverPartition, PaymentKeyByReceiver,
     TxIdToHandshakePartition, TxIdToPaymentPartition,
 };
 use crate::database::metadata::MetadataPartition;
+use crate::database::miners::{BlockMiner, BlockMinerPartition, MinerBlocksPartition};
 use crate::database::processing::{
-    SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition,
+    IndexedOutput, OrphanPoolPartition, OutpointPartition, PendingSpendPartition,
+    ProcessedBlockPartition, SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition,
+    TxInputPartition,
 };
 use crate::database::resolution_keys::{
     ContextualMessageKeyForResolution, HandshakeKeyForResolution, PaymentKeyForResolution,
 };
+use crate::database::supply::Supply;
+use crate::database::token_operations::TokenOperationPartition;
 use crate::fifo_set::FifoSet;
+use crate::header_validation::{HeaderHashMismatch, verify_header_hash};
 use crate::historical_syncer::Cursor;
+use crate::ingest_filter::IngestFilter;
+use crate::ingest_trace::TRACE_TARGET;
+use crate::mempool::Mempool;
 use crate::metrics::SharedMetrics;
-use fjall::{TxKeyspace, WriteTransaction};
+use crate::node_pool::NodePool;
+use crate::protocols::kasplex;
+use crate::shutdown::Shutdown;
+use fjall::{ReadTransaction, TxKeyspace, WriteTransaction};
+use kaspa_addresses::Prefix;
 use kaspa_consensus_core::tx::{Transaction, TransactionId};
-use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransaction};
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransaction, RpcTransactionOutpoint};
 use protocol::operation::{
     SealedContextualMessageV1, SealedMessageOrSealedHandshakeVNone, SealedOperation,
     SealedPaymentV1, deserializer::parse_sealed_operation,
 };
-use tracing::{debug, info, trace};
+use std::collections::{HashSet, VecDeque};
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tracing::{Span, debug, debug_span, error, info, trace, warn};
+
+/// Orphans further than this from the sink are no longer waited for
+pub const DEFAULT_ORPHAN_MAX_DAA_DISTANCE: u64 = 600;
+/// Orphans this far from the sink get a backfill of their missing parents
+pub const DEFAULT_ORPHAN_BACKFILL_DAA_DISTANCE: u64 = 100;
+pub const DEFAULT_MAX_ORPHAN_BLOCKS: usize = 10_000;
+/// Spends parked this far from the sink are not going to find their output
+pub const DEFAULT_PENDING_SPEND_MAX_DAA_DISTANCE: u64 = 600;
+const ESCALATED_ORPHANS_CAPACITY: usize = 1024;
+/// Stale orphans and pending spends are looked for at most this often while idle
+const EVICTION_INTERVAL: Duration = Duration::from_secs(10);
+/// A batch conflicting with the virtual chain processor is written again this many times
+const MAX_BATCH_COMMIT_ATTEMPTS: usize = 5;
+pub const DEFAULT_BLOCK_WORKERS: usize = 1;
+pub const DEFAULT_FLUSH_MAX_BYTES: usize = 64 * 1024 * 1024;
+pub const DEFAULT_FLUSH_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// When the blocks written so far are committed, whichever limit is reached first. Pending
+/// blocks are committed as well once the intake is idle or on shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPolicy {
+    pub max_blocks: usize,
+    /// Approximate size of the data derived from the pending blocks
+    pub max_bytes: usize,
+    /// Age of the oldest pending block
+    pub max_delay: Duration,
+}
+
+impl FlushPolicy {
+    /// Every block is committed on its own
+    pub const PER_BLOCK: Self = Self {
+        max_blocks: 1,
+        max_bytes: usize::MAX,
+        max_delay: Duration::MAX,
+    };
+
+    /// Commits every `max_blocks` blocks with the default size and delay limits
+    pub fn batched(max_blocks: usize) -> Self {
+        Self {
+            max_blocks: max_blocks.max(1),
+            max_bytes: DEFAULT_FLUSH_MAX_BYTES,
+            max_delay: DEFAULT_FLUSH_MAX_DELAY,
+        }
+    }
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self::PER_BLOCK
+    }
+}
 
 #[derive(bon::Builder)]
 pub struct BlockProcessor {
     processed_blocks: FifoSet<RpcHash>,
     processed_txs: FifoSet<TransactionId>,
     intake: flume::Receiver<BlockOrMany>,
-    shutdown: flume::Receiver<()>,
+    /// Batches of the historical syncers, kept apart so they can't take the room of notified blocks
+    historical_intake: Option<flume::Receiver<BlockOrMany>>,
+    /// Of the processors stage, the intake left is drained once cancelled
+    #[builder(default)]
+    shutdown: Shutdown,
 
     tx_keyspace: TxKeyspace,
 
@@ -49,119 +133,1006 @@ pub struct BlockProcessor {
     skip_tx_by_block_partition: SkipTxByBlockPartition,
     block_compact_header_partition: BlockCompactHeaderPartition,
     block_daa_index: DaaIndexPartition,
+    chain_membership_partition: ChainMembershipPartition,
+    block_relations_partition: BlockRelationsPartition,
+    orphan_pool_partition: OrphanPoolPartition,
+    block_miner_partition: BlockMinerPartition,
+    miner_blocks_partition: MinerBlocksPartition,
+    outpoint_partition: OutpointPartition,
+    pending_spend_partition: PendingSpendPartition,
+    tx_input_partition: TxInputPartition,
+    block_stats_partition: BlockStatsPartition,
+    block_transactions_partition: BlockTransactionsPartition,
+    /// Per DAA bucket totals, none disables them
+    aggregates: Option<Aggregates>,
+    /// Records the coinbase reward of every block, none disables the supply tracking
+    supply: Option<Supply>,
+    /// Pending transactions, forgotten once their block is committed
+    mempool: Option<Mempool>,
+    /// Survives restarts, unlike `processed_blocks`
+    processed_block_partition: ProcessedBlockPartition,
+    /// Indexes every output and links inputs to the outputs they spend
+    #[builder(default)]
+    index_outpoints: bool,
+    token_operation_partition: TokenOperationPartition,
+    /// Detects KRC-20 envelopes in transaction inputs
+    #[builder(default)]
+    index_token_operations: bool,
+    /// Leaves out the transactions and payloads a deployment doesn't query
+    #[builder(default)]
+    ingest_filter: Arc<IngestFilter>,
+    /// Miner addresses are derived for this network
+    #[builder(default = Prefix::Mainnet)]
+    address_prefix: Prefix,
     metrics: SharedMetrics,
+    /// Notified after each block commit
+    #[builder(default)]
+    indexed_blocks: IndexedBlocks,
+
+    virtual_daa: Arc<AtomicU64>,
+    /// Blocks whose parents are still missing this far from the sink are processed without them
+    #[builder(default = DEFAULT_ORPHAN_MAX_DAA_DISTANCE)]
+    orphan_max_daa_distance: u64,
+    /// Orphans kept at most, blocks beyond it are processed without their missing parents
+    #[builder(default = DEFAULT_MAX_ORPHAN_BLOCKS)]
+    max_orphan_blocks: usize,
+    /// Orphans still waiting this far from the sink get a backfill of their missing parents
+    #[builder(default = DEFAULT_ORPHAN_BACKFILL_DAA_DISTANCE)]
+    orphan_backfill_daa_distance: u64,
+    /// Spends parked this far from the sink are dropped, they spend outputs created before
+    /// indexing started
+    #[builder(default = DEFAULT_PENDING_SPEND_MAX_DAA_DISTANCE)]
+    pending_spend_max_daa_distance: u64,
+    /// Receives the backfills, none disables them
+    backfill_requests: Option<tokio::sync::mpsc::Sender<BlockGap>>,
+    /// Distinct blocks in the orphan pool, counted once and kept up to date from then on
+    #[builder(skip)]
+    orphan_blocks: Option<usize>,
+    /// Whether blocks of the message being handled are parked while parents are missing. The
+    /// historical syncers deliver in order, their blocks miss parents outside of the synced range
+    #[builder(skip = true)]
+    park_orphans: bool,
+    /// Committed blocks whose parked children were not released yet
+    #[builder(skip)]
+    committed_parents: Vec<RpcHash>,
+    #[builder(skip)]
+    releasing_orphans: bool,
+    /// Last time stale orphans and pending spends were looked for
+    #[builder(skip)]
+    last_eviction: Option<Instant>,
+    /// Orphans a backfill was already requested for
+    #[builder(skip = FifoSet::new(ESCALATED_ORPHANS_CAPACITY))]
+    escalated_orphans: FifoSet<RpcHash>,
+    /// Threads decoding the blocks of a batch, commits stay sequential in intake order
+    #[builder(default = DEFAULT_BLOCK_WORKERS)]
+    workers: usize,
+    #[builder(default)]
+    flush_policy: FlushPolicy,
+    /// Recompute the header hashes of the historical syncer blocks on the decoding threads
+    #[builder(default)]
+    verify_historical_hashes: bool,
+    /// Recompute the header hashes of notified blocks on the decoding threads
+    #[builder(default)]
+    verify_realtime_hashes: bool,
+    /// Nodes serving blocks whose headers hash differently are demoted in it
+    node_pool: Option<NodePool>,
+    /// Blocks written but not committed yet
+    #[builder(skip)]
+    pending: Option<PendingBatch>,
+    /// Blocks of the pending batch, written again if its commit conflicts and handled again by
+    /// the next run if this one fails
+    #[builder(skip)]
+    uncommitted: Vec<RpcBlock>,
+    /// Message being handled, a run failing on it leaves it to the next
+    #[builder(skip)]
+    in_flight: Option<Arc<BlockOrMany>>,
+    /// Orphans taken out of the pool and not handled yet, with whether they were evicted
+    #[builder(skip)]
+    released_orphans: VecDeque<(RpcBlock, bool)>,
+    /// Shutdown was received, later runs only drain the intake
+    #[builder(skip)]
+    draining: bool,
+    /// Transactions written by the pending batch, they count as processed once it is committed
+    #[builder(skip)]
+    pending_txs: HashSet<TransactionId>,
+    /// Spends parked by the pending batch minus the ones it linked
+    #[builder(skip)]
+    pending_spends_delta: i64,
+    /// Parked spends, counted at the first eviction and kept up to date from then on
+    #[builder(skip)]
+    pending_spends: Option<u64>,
+    /// Notified block of the message being handled and when it was received
+    #[builder(skip)]
+    received: Option<(RpcHash, Instant)>,
+    /// Root span of the message being handled
+    #[builder(skip = Span::none())]
+    trace_span: Span,
+    /// Node of the message being handled, set while its header hashes are verified
+    #[builder(skip)]
+    verified_node: Option<Arc<str>>,
+    /// Highest daa score processed so far, stands in for the sink until it is known
+    #[builder(skip)]
+    highest_daa_score: u64,
 }
 
 impl BlockProcessor {
+    /// Events of the blocks indexed from now on
+    pub fn subscribe_indexed_blocks(&self) -> IndexedBlocksReceiver {
+        self.indexed_blocks.subscribe()
+    }
+
     pub fn process(&mut self) -> anyhow::Result<()> {
         info!("Block worker started");
-        loop {
+        self.recover()?;
+        while !self.draining {
+            // nothing queued, the batch is committed instead of waiting for it to fill up
+            if self.pending.is_some() && self.intake_is_empty() {
+                self.flush()?;
+                self.evict_stale()?;
+            }
             match self.select_input()? {
-                BlocksOrShutdown::Shutdown(_) => {
+                BlocksOrShutdown::Shutdown => {
                     info!("Block worker received shutdown signal, draining notifications first");
-                    let rx = std::mem::replace(&mut self.intake, flume::unbounded().1);
-                    rx.drain().try_for_each(|blocks| -> anyhow::Result<()> {
-                        self.handle_blocks(&blocks)?;
-                        Ok(())
-                    })?;
-                    info!("Draining is done, stopping block worker");
-                    return Ok(());
+                    self.draining = true;
                 }
                 BlocksOrShutdown::Blocks(blocks) => {
-                    self.handle_blocks(&blocks)?;
+                    self.handle_intake(blocks)?;
                 }
             }
         }
+        while let Some(blocks) = self.try_recv_intake() {
+            self.handle_intake(blocks)?;
+        }
+        self.intake = flume::unbounded().1;
+        self.historical_intake = None;
+        self.flush()?;
+        info!("Draining is done, stopping block worker");
+        Ok(())
+    }
+
+    /// Handles what a failed run left behind again: the blocks of the batch it did not commit,
+    /// the orphans it released and the message it was handling
+    fn recover(&mut self) -> anyhow::Result<()> {
+        self.releasing_orphans = false;
+        let uncommitted = std::mem::take(&mut self.uncommitted);
+        if uncommitted.is_empty() && self.released_orphans.is_empty() && self.in_flight.is_none() {
+            return Ok(());
+        }
+        warn!(
+            uncommitted = uncommitted.len(),
+            orphans = self.released_orphans.len(),
+            "Handling the blocks the last run failed on again"
+        );
+        self.pending = None;
+        self.discard_batch_effects();
+        // their parents were handled before them
+        self.park_orphans = false;
+        self.verified_node = None;
+        self.handle_blocks(&uncommitted)?;
+        self.release_orphans()?;
+        if let Some(blocks) = self.in_flight.take() {
+            self.handle_message(blocks)?;
+        }
+        Ok(())
+    }
+
+    fn try_recv_intake(&self) -> Option<BlockOrMany> {
+        self.intake
+            .try_recv()
+            .ok()
+            .or_else(|| self.historical_intake.as_ref()?.try_recv().ok())
+    }
+
+    fn intake_is_empty(&self) -> bool {
+        self.intake.is_empty()
+            && self
+                .historical_intake
+                .as_ref()
+                .is_none_or(|rx| rx.is_empty())
     }
 
     fn select_input(&self) -> anyhow::Result<BlocksOrShutdown> {
         trace!("Waiting for new blocks or shutdown signal");
-        Ok(flume::Selector::new()
-            .recv(&self.intake, |r| r.map(BlocksOrShutdown::from))
-            .recv(&self.shutdown, |r| r.map(BlocksOrShutdown::from))
-            .wait()?)
+        let historical = async {
+            match &self.historical_intake {
+                Some(historical_intake) => historical_intake.recv_async().await,
+                None => std::future::pending().await,
+            }
+        };
+        // runs on a blocking thread of the runtime, see `Shutdown::spawn_blocking`
+        Handle::current().block_on(async {
+            tokio::select! {
+                biased;
+                _ = self.shutdown.cancelled() => Ok(BlocksOrShutdown::Shutdown),
+                blocks = self.intake.recv_async() => Ok(blocks?.into()),
+                blocks = historical => Ok(blocks?.into()),
+            }
+        })
+    }
+
+    /// Takes the message off the intake depth gauges, a notified block keeps its receive
+    /// time and the message its span until committed
+    fn handle_intake(&mut self, mut blocks: BlockOrMany) -> anyhow::Result<()> {
+        blocks.trace_mut().dequeued();
+        self.metrics.remove_intake_blocks(&blocks);
+        self.handle_message(Arc::new(blocks))
+    }
+
+    /// Keeps the message until it is handled, a run failing on it leaves it to the next
+    fn handle_message(&mut self, blocks: Arc<BlockOrMany>) -> anyhow::Result<()> {
+        self.in_flight = Some(blocks.clone());
+        self.received = match &blocks {
+            BlockOrMany::Block(block, Some(received_at), ..) => {
+                Some((block.header.hash, *received_at))
+            }
+            _ => None,
+        };
+        self.trace_span = blocks.trace().span.clone();
+        let verify = match &blocks {
+            BlockOrMany::Many(..) => self.verify_historical_hashes,
+            BlockOrMany::Block(..) => self.verify_realtime_hashes,
+        };
+        self.verified_node = verify.then(|| blocks.node().clone());
+        self.park_orphans = matches!(blocks, BlockOrMany::Block(..));
+        let _process = debug_span!(
+            target: TRACE_TARGET,
+            parent: &self.trace_span,
+            "process",
+            blocks = blocks.len()
+        )
+        .entered();
+        self.handle_blocks(blocks.as_slice())?;
+        self.in_flight = None;
+        Ok(())
     }
 
     fn handle_blocks(&mut self, blocks: &[RpcBlock]) -> anyhow::Result<()> {
         debug!("Received {} blocks for processing", blocks.len());
-        for block in blocks {
+        let decode_started = Instant::now();
+        let prepared = debug_span!(target: TRACE_TARGET, "decode")
+            .in_scope(|| prepare_blocks(blocks, self.workers, self.verified_node.is_some()));
+        self.metrics
+            .observe_block_decode_time(decode_started.elapsed(), blocks.len());
+        for verification in prepared
+            .iter()
+            .filter_map(|prepared| prepared.as_ref().ok()?.hash_verification)
+        {
+            self.metrics
+                .observe_header_hash_verification_time(verification);
+        }
+        for (block, prepared) in blocks.iter().zip(prepared) {
+            if let Err(err) = &prepared
+                && let Some(mismatch) = err.downcast_ref::<HeaderHashMismatch>()
+            {
+                self.reject_block(mismatch);
+                continue;
+            }
             let hash = &block.header.hash;
-            if self.processed_blocks.contains(hash) {
+            if self.is_processed(hash)? {
                 debug!(%hash, "Skipping already processed block");
                 continue;
             }
-            self.block_compact_header_partition.insert_compact_header(
-                hash,
-                block.header.blue_work,
-                block.header.daa_score,
-            )?;
-            self.block_daa_index.insert(block.header.daa_score, hash)?;
-            let mut wtx = self.tx_keyspace.write_tx()?;
-            debug!(%hash, "Processing block with {} transactions", block.transactions.len());
-
-            let mut skipped_tx_ids = Vec::with_capacity(block.transactions.len());
-            for tx in &block.transactions {
-                if let Some(skipped_tx_id) = self.handle_transaction(&mut wtx, block, tx)? {
-                    skipped_tx_ids.push(skipped_tx_id);
+            let orphan_blocks = self.orphan_blocks()?;
+            // released once its parents are committed or evicted
+            if orphan_blocks > 0 && self.orphan_pool_partition.is_parked(block)? {
+                debug!(%hash, "Skipping block parked before");
+                continue;
+            }
+            let missing_parents = self.missing_parents(block)?;
+            if self.park_orphans
+                && !missing_parents.is_empty()
+                && !self.is_stale(block.header.daa_score)
+            {
+                if orphan_blocks < self.max_orphan_blocks {
+                    debug!(%hash, ?missing_parents, "Parking block until its parents are processed");
+                    let mut wtx = self.tx_keyspace.write_tx()?;
+                    for parent in &missing_parents {
+                        self.orphan_pool_partition
+                            .park_wtx(&mut wtx, parent, block)?;
+                    }
+                    wtx.commit()??;
+                    self.set_orphan_blocks(orphan_blocks + 1);
+                    continue;
                 }
+                warn!(
+                    %hash,
+                    ?missing_parents,
+                    "Orphan pool is full, processing block without its missing parents"
+                );
+                self.metrics.increment_orphans_over_capacity();
             }
+            self.commit_block(prepared?)?;
+        }
+        if self.pending.is_some() {
+            return Ok(());
+        }
+        self.evict_stale()
+    }
 
-            // Add skipped transactions to the block-organized partition
-            if !skipped_tx_ids.is_empty() {
-                debug!(%hash, skipped_count = skipped_tx_ids.len(), "Adding skipped transactions to block partition");
-                self.skip_tx_by_block_partition.add_skip_for_block(
-                    &mut wtx,
-                    block.header.daa_score,
-                    *block.header.hash.as_ref(),
-                    &skipped_tx_ids,
+    /// Drops a block whose header hashes differently than its node reported, the node is
+    /// ranked last by the resolver from now on
+    fn reject_block(&self, mismatch: &HeaderHashMismatch) {
+        let node = self.verified_node.as_deref().unwrap_or_default();
+        error!(
+            node,
+            reported = %mismatch.reported,
+            computed = %mismatch.computed,
+            "Rejecting block whose header hashes differently than reported"
+        );
+        self.metrics.increment_header_hash_mismatches();
+        if let Some(node_pool) = &self.node_pool {
+            node_pool.demote(node);
+        }
+    }
+
+    /// Evictions write outside of the batch, they only run while no block is pending and at
+    /// most once per [`EVICTION_INTERVAL`]. The intake goes idle after nearly every notified
+    /// block, scanning the pools that often would cost more than indexing the block
+    fn evict_stale(&mut self) -> anyhow::Result<()> {
+        if self
+            .last_eviction
+            .is_some_and(|last| last.elapsed() < EVICTION_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.last_eviction = Some(Instant::now());
+        self.evict_stale_pending_spends()?;
+        self.evict_stale_orphans()?;
+        // evicted orphans are processed without their parents
+        self.flush()
+    }
+
+    /// Processed by this worker, pending in the current batch or processed before the last
+    /// restart
+    fn is_processed(&self, hash: &RpcHash) -> anyhow::Result<bool> {
+        Ok(self.processed_blocks.contains(hash)
+            || self.is_pending(hash)
+            || self.processed_block_partition.is_processed(*hash)?)
+    }
+
+    fn is_pending(&self, hash: &RpcHash) -> bool {
+        self.pending
+            .as_ref()
+            .is_some_and(|batch| batch.hashes.contains(hash))
+    }
+
+    /// Direct parents which were not processed yet
+    fn missing_parents(&self, block: &RpcBlock) -> anyhow::Result<Vec<RpcHash>> {
+        let unseen = block
+            .header
+            .parents_by_level
+            .first()
+            .into_iter()
+            .flatten()
+            .filter(|parent| !self.processed_blocks.contains(parent) && !self.is_pending(parent))
+            .copied()
+            .collect::<Vec<_>>();
+        if unseen.is_empty() {
+            return Ok(unseen);
+        }
+        let headers = self
+            .block_compact_header_partition
+            .get_many_rtx(&self.tx_keyspace.read_tx(), &unseen)?;
+        Ok(unseen
+            .into_iter()
+            .zip(headers)
+            .filter_map(|(parent, header)| header.is_none().then_some(parent))
+            .collect())
+    }
+
+    fn sink_daa_score(&self) -> u64 {
+        self.virtual_daa
+            .load(Ordering::Relaxed)
+            .max(self.highest_daa_score)
+    }
+
+    /// Parents of a block this deep are not going to arrive through notifications,
+    /// historical sync delivers blocks in order
+    fn is_stale(&self, daa_score: u64) -> bool {
+        daa_score + self.orphan_max_daa_distance < self.sink_daa_score()
+    }
+
+    /// Distinct parked blocks, the pool is only scanned the first time
+    fn orphan_blocks(&mut self) -> anyhow::Result<usize> {
+        if let Some(count) = self.orphan_blocks {
+            return Ok(count);
+        }
+        let count = self
+            .orphan_pool_partition
+            .count_blocks_rtx(&self.tx_keyspace.read_tx())?;
+        self.set_orphan_blocks(count);
+        Ok(count)
+    }
+
+    fn set_orphan_blocks(&mut self, count: usize) {
+        self.orphan_blocks = Some(count);
+        self.metrics.set_orphan_blocks(count as u64);
+    }
+
+    /// Processes the parked children of the committed blocks which have no missing parents
+    /// anymore. Their own children follow once the batch they were written into is committed
+    fn release_orphans(&mut self) -> anyhow::Result<()> {
+        // flushes of the released blocks only queue their parents
+        if self.releasing_orphans {
+            return Ok(());
+        }
+        self.releasing_orphans = true;
+        let released = self
+            .handle_released_orphans()
+            .and_then(|()| self.release_committed_parents());
+        self.releasing_orphans = false;
+        released
+    }
+
+    fn release_committed_parents(&mut self) -> anyhow::Result<()> {
+        while let Some(&parent) = self.committed_parents.last() {
+            if self.orphan_pool_partition.has_children(&parent)? {
+                let mut wtx = self.tx_keyspace.write_tx()?;
+                let children = self
+                    .orphan_pool_partition
+                    .take_children_wtx(&mut wtx, &parent)?;
+                wtx.commit()??;
+                self.released_orphans
+                    .extend(children.into_iter().map(|block| (block, false)));
+            }
+            self.committed_parents.pop();
+            self.handle_released_orphans()?;
+        }
+        Ok(())
+    }
+
+    /// Handles the orphans taken out of the pool in order, each one is dropped from the queue
+    /// once handled
+    fn handle_released_orphans(&mut self) -> anyhow::Result<()> {
+        while let Some((block, evicted)) = self.released_orphans.front().cloned() {
+            let hash = block.header.hash;
+            // a released block still parked under its other missing parents waits for them
+            if self.is_processed(&hash)? || (!evicted && !self.missing_parents(&block)?.is_empty())
+            {
+                self.released_orphans.pop_front();
+                continue;
+            }
+            if evicted {
+                warn!(
+                    %hash,
+                    daa_score = block.header.daa_score,
+                    "Evicting orphan block, processing it without its missing parents"
                 );
+            } else {
+                debug!(%hash, "Processing orphan block after its parents arrived");
+            }
+            self.handle_block(&block)?;
+            if !evicted {
+                let count = self.orphan_blocks()?;
+                self.set_orphan_blocks(count.saturating_sub(1));
+                self.metrics.increment_orphans_reprocessed();
             }
+            self.released_orphans.pop_front();
+        }
+        Ok(())
+    }
 
-            self.metadata_partition.set_latest_block_cursor(
-                &mut wtx,
-                Cursor {
-                    daa_score: block.header.daa_score,
-                    blue_work: block.header.blue_work,
-                    hash: block.header.hash,
-                },
-            )?;
+    /// Stops waiting for parents of orphans which fell too far behind the sink
+    fn evict_stale_orphans(&mut self) -> anyhow::Result<()> {
+        let count = self.orphan_blocks()?;
+        if count == 0 {
+            return Ok(());
+        }
+        self.request_orphan_backfills()?;
+        let threshold = self
+            .sink_daa_score()
+            .saturating_sub(self.orphan_max_daa_distance);
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        let evicted = self
+            .orphan_pool_partition
+            .take_older_than_wtx(&mut wtx, threshold)?;
+        wtx.commit()??;
+        self.set_orphan_blocks(count.saturating_sub(evicted.len()));
+        self.released_orphans
+            .extend(evicted.into_iter().map(|block| (block, true)));
+        // evicted while orphans are released, the release handles them
+        self.release_orphans()
+    }
 
-            wtx.commit()??;
+    /// Asks for a backfill of the missing parents of orphans which are still waiting
+    /// `orphan_backfill_daa_distance` behind the sink, once per orphan
+    fn request_orphan_backfills(&mut self) -> anyhow::Result<()> {
+        let Some(backfill_requests) = self.backfill_requests.clone() else {
+            return Ok(());
+        };
+        let threshold = self
+            .sink_daa_score()
+            .saturating_sub(self.orphan_backfill_daa_distance);
+        let rtx = self.tx_keyspace.read_tx();
+        for block in self
+            .orphan_pool_partition
+            .parked_older_than_rtx(&rtx, threshold)?
+        {
+            let hash = block.header.hash;
+            if self.escalated_orphans.contains(&hash) {
+                continue;
+            }
+            let Some(gap) = self.orphan_backfill_gap(&rtx, &block)? else {
+                continue;
+            };
+            if let Err(err) = backfill_requests.try_send(gap) {
+                debug!(%hash, "Backfill request not sent, retrying on the next eviction: {err}");
+                break;
+            }
+            warn!(
+                %hash,
+                daa_score = block.header.daa_score,
+                "Parents of orphan block are still missing, requesting a backfill"
+            );
+            self.escalated_orphans.insert(hash);
+            self.metrics.increment_orphan_backfills();
+        }
+        Ok(())
+    }
+
+    /// Gap from the latest stored block `orphan_backfill_daa_distance` before the orphan up to
+    /// the orphan, none if no block is stored that far back
+    fn orphan_backfill_gap(
+        &self,
+        rtx: &ReadTransaction,
+        block: &RpcBlock,
+    ) -> anyhow::Result<Option<BlockGap>> {
+        let before = block
+            .header
+            .daa_score
+            .saturating_sub(self.orphan_backfill_daa_distance);
+        let Some((daa_score, anchor)) = self.block_daa_index.last_before_rtx(rtx, before)? else {
+            return Ok(None);
+        };
+        let Some(blue_work) = self
+            .block_compact_header_partition
+            .get_blue_work_rtx(rtx, &anchor)?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(BlockGap::from_cursors(
+            Cursor::new(daa_score, blue_work, anchor),
+            Cursor::new(
+                block.header.daa_score,
+                block.header.blue_work,
+                block.header.hash,
+            ),
+        )))
+    }
+
+    /// Spends parked `pending_spend_max_daa_distance` behind the sink reference outputs created
+    /// before indexing started, which are never going to be indexed
+    fn evict_stale_pending_spends(&mut self) -> anyhow::Result<()> {
+        if !self.index_outpoints {
+            return Ok(());
+        }
+        let threshold = self
+            .sink_daa_score()
+            .saturating_sub(self.pending_spend_max_daa_distance);
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        let evicted = self
+            .pending_spend_partition
+            .evict_older_than_wtx(&mut wtx, threshold)?;
+        wtx.commit()??;
+        if evicted > 0 {
+            debug!(
+                evicted,
+                "Evicted spends of outputs which were never indexed"
+            );
+        }
+        let count = match self.pending_spends {
+            Some(count) => count.saturating_sub(evicted as u64),
+            None => self
+                .pending_spend_partition
+                .len_rtx(&self.tx_keyspace.read_tx())? as u64,
+        };
+        self.pending_spends = Some(count);
+        self.metrics.set_pending_spends(count);
+        Ok(())
+    }
+
+    fn handle_block(&mut self, block: &RpcBlock) -> anyhow::Result<()> {
+        self.commit_block(PreparedBlock::new(block)?)
+    }
+
+    /// Writes the block and its processed marker into the pending batch, a block marked
+    /// before is skipped. Commits the batch once the flush policy says so
+    fn commit_block(&mut self, prepared: PreparedBlock) -> anyhow::Result<()> {
+        let block = prepared.block();
+        let hash = block.header.hash;
+        let bytes = approx_write_size(block);
+        let mut batch = match self.pending.take() {
+            Some(batch) => batch,
+            None => PendingBatch::new(self.tx_keyspace.write_tx()?),
+        };
+        if self
+            .processed_block_partition
+            .is_processed_wtx(&mut batch.wtx, hash)?
+        {
+            debug!(%hash, "Skipping block processed before");
+            if !batch.hashes.contains(&hash) {
+                self.processed_blocks.insert(hash);
+            }
+            self.pending = Some(batch);
+            return Ok(());
+        }
+        if self.mempool.is_some() {
+            batch.tx_ids.extend(prepared.txs.iter().map(|tx| tx.tx_id));
+        }
+        self.uncommitted.push(block.clone());
+        let started = Instant::now();
+        let indexed = debug_span!(target: TRACE_TARGET, "write", %hash)
+            .in_scope(|| self.write_block_wtx(&mut batch.wtx, prepared, false))?;
+        self.metrics
+            .observe_block_processing_time(started.elapsed());
+        self.highest_daa_score = self.highest_daa_score.max(block.header.daa_score);
+        if let Some((_, received_at)) = self.received.filter(|(received, _)| *received == hash) {
+            batch.received_at.push(received_at);
+        }
+        if let Some(id) = self.trace_span.id()
+            && batch.spans.last().and_then(Span::id) != Some(id)
+        {
+            batch.spans.push(self.trace_span.clone());
+        }
+        batch.hashes.push(hash);
+        batch.events.extend(indexed);
+        batch.bytes += bytes;
+        let due = batch.is_due(&self.flush_policy);
+        self.pending = Some(batch);
+        if due {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Commits the pending batch. The blocks only count as processed and are only published
+    /// once committed, the persisted marker and the tip cursor are part of the batch
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let Some(batch) = self.pending.take() else {
+            return Ok(());
+        };
+        // the commit belongs to the message that started the batch, the others follow from it
+        let commit = debug_span!(
+            target: TRACE_TARGET,
+            parent: batch.spans.first().and_then(Span::id),
+            "db_commit",
+            blocks = batch.hashes.len()
+        );
+        for span in batch.spans.iter().skip(1) {
+            commit.follows_from(span);
+        }
+        let commit_started = Instant::now();
+        let (mut wtx, mut events) = (batch.wtx, batch.events);
+        let mut attempt = 1;
+        // reads of the batch were overwritten by another writer, it is written again on top
+        while let Err(conflict) = commit.in_scope(|| wtx.commit())? {
+            if attempt == MAX_BATCH_COMMIT_ATTEMPTS {
+                self.discard_batch_effects();
+                return Err(anyhow::Error::new(conflict).context(format!(
+                    "failed to commit, conflict block batch after {attempt} attempts"
+                )));
+            }
+            warn!(
+                blocks = batch.hashes.len(),
+                attempt, "Block batch conflicted, writing it again"
+            );
+            attempt += 1;
+            let blocks = self.uncommitted.clone();
+            (wtx, events) = commit.in_scope(|| self.rewrite_batch(&blocks))?;
+        }
+        self.metrics
+            .observe_block_commit_time(commit_started.elapsed(), batch.hashes.len());
+        debug!(
+            blocks = batch.hashes.len(),
+            bytes = batch.bytes,
+            "Committed block batch"
+        );
+        for hash in &batch.hashes {
             self.processed_blocks.insert(*hash);
-            self.metrics.increment_blocks_processed();
+        }
+        self.uncommitted.clear();
+        self.apply_batch_effects();
+        if let Some(mempool) = &self.mempool {
+            mempool.remove_included(&batch.tx_ids);
+        }
+        for event in events {
+            match &event {
+                IndexEvent::BlockIndexed(_) => self.metrics.increment_blocks_processed(),
+                IndexEvent::MessageIndexed(MessageIndexed {
+                    kind: MessageKind::ContextualMessage,
+                    ..
+                }) => self.metrics.increment_contextual_messages_count(),
+                _ => {}
+            }
+            self.indexed_blocks.publish(event);
+        }
+        for received_at in batch.received_at {
+            self.metrics.record_subscriber_block_processed();
+            self.metrics
+                .observe_block_e2e_latency(received_at.elapsed());
+        }
+        if self.orphan_blocks()? > 0 {
+            self.committed_parents.extend(batch.hashes);
+            self.release_orphans()?;
         }
         Ok(())
     }
 
+    /// Transactions of the committed batch count as processed from now on and its parked spends
+    /// are counted
+    fn apply_batch_effects(&mut self) {
+        for tx_id in self.pending_txs.drain() {
+            self.processed_txs.insert(tx_id);
+        }
+        if let Some(count) = &mut self.pending_spends {
+            *count = count.saturating_add_signed(self.pending_spends_delta);
+            self.metrics.set_pending_spends(*count);
+        }
+        self.pending_spends_delta = 0;
+    }
+
+    fn discard_batch_effects(&mut self) {
+        self.pending_txs.clear();
+        self.pending_spends_delta = 0;
+    }
+
+    /// Writes the blocks of a conflicting batch into a new transaction. Returns it with the
+    /// events of the rewrite, flags read from the other writers may have changed
+    fn rewrite_batch(
+        &mut self,
+        blocks: &[RpcBlock],
+    ) -> anyhow::Result<(WriteTransaction, Vec<IndexEvent>)> {
+        self.discard_batch_effects();
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        let mut events = Vec::new();
+        for block in blocks {
+            events.extend(self.write_block_wtx(&mut wtx, PreparedBlock::new(block)?, false)?);
+        }
+        Ok((wtx, events))
+    }
+
+    /// Maintenance entry, drops the data derived from the block and processes it again
+    /// within the same transaction. Records keyed by transaction are rewritten in place
+    pub fn force_reprocess(&mut self, block: &RpcBlock) -> anyhow::Result<()> {
+        self.force_reprocess_all(slice::from_ref(block), |_| Ok(()), |_| Ok(()))
+    }
+
+    /// Same as [`Self::force_reprocess`] for all `blocks` in one transaction, written between
+    /// `before` and `after`. Nothing is committed unless every block is written
+    pub fn force_reprocess_all<T>(
+        &mut self,
+        blocks: &[RpcBlock],
+        before: impl FnOnce(&mut WriteTransaction) -> anyhow::Result<T>,
+        after: impl FnOnce(&mut WriteTransaction) -> anyhow::Result<()>,
+    ) -> anyhow::Result<T> {
+        self.flush()?;
+        let prepared = blocks
+            .iter()
+            .map(PreparedBlock::new)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        let written = before(&mut wtx).and_then(|output| {
+            let mut events = Vec::new();
+            for prepared in prepared {
+                events.extend(self.reprocess_wtx(&mut wtx, prepared)?);
+            }
+            after(&mut wtx)?;
+            wtx.commit()??;
+            Ok((output, events))
+        });
+        let (output, events) = match written {
+            Ok(written) => written,
+            Err(err) => {
+                self.discard_batch_effects();
+                return Err(err);
+            }
+        };
+        for block in blocks {
+            self.processed_blocks.insert(block.header.hash);
+        }
+        self.apply_batch_effects();
+        for event in events {
+            self.indexed_blocks.publish(event);
+        }
+        Ok(output)
+    }
+
+    fn reprocess_wtx(
+        &mut self,
+        wtx: &mut WriteTransaction,
+        prepared: PreparedBlock,
+    ) -> anyhow::Result<Vec<IndexEvent>> {
+        let hash = prepared.block().header.hash;
+        let daa_score = prepared.block().header.daa_score;
+        self.processed_block_partition.unmark_wtx(wtx, hash);
+        let old_fees = self
+            .block_stats_partition
+            .get_block_stats_wtx(wtx, hash)?
+            .map_or(0, |stats| stats.total_fees);
+        self.block_stats_partition.remove_wtx(wtx, hash);
+        if let Some(BlockMiner::Parsed { address, .. }) =
+            self.block_miner_partition.take_wtx(wtx, hash)?
+        {
+            self.miner_blocks_partition
+                .remove_wtx(wtx, &address, daa_score, hash)?;
+        }
+        self.skip_tx_by_block_partition
+            .remove_block(wtx, daa_score, hash.as_bytes());
+        info!(%hash, "Reprocessing block");
+        let events = self.write_block_wtx(wtx, prepared, true)?;
+        if let Some(aggregates) = &self.aggregates {
+            let new_fees = self
+                .block_stats_partition
+                .get_block_stats_wtx(wtx, hash)?
+                .map_or(0, |stats| stats.total_fees);
+            aggregates.replace_fees_wtx(wtx, daa_score, old_fees, new_fees)?;
+        }
+        Ok(events)
+    }
+
+    /// `reprocess` writes transactions this worker processed already as well. Returns the
+    /// events to publish once committed, the block's followed by its messages'
+    fn write_block_wtx(
+        &mut self,
+        wtx: &mut WriteTransaction,
+        prepared: PreparedBlock,
+        reprocess: bool,
+    ) -> anyhow::Result<Vec<IndexEvent>> {
+        let PreparedBlock { block, txs, .. } = prepared;
+        let hash = &block.header.hash;
+        self.block_compact_header_partition
+            .insert_header_wtx(wtx, &block.header)?;
+        self.block_daa_index
+            .insert_wtx(wtx, block.header.daa_score, hash);
+        debug!(%hash, "Processing block with {} transactions", block.transactions.len());
+
+        let index_transactions = self.ingest_filter.admits_block_transactions(block);
+        let mut tx_ids = Vec::with_capacity(txs.len());
+        let mut skipped_tx_ids = Vec::with_capacity(txs.len());
+        let mut messages = Vec::new();
+        for tx in txs {
+            if !index_transactions || !self.admits_transaction_wtx(wtx, tx.tx)? {
+                trace!(tx_id = %tx.tx_id, "Transaction left out by the ingest filter");
+                self.skip_tx_partition.mark_skip(wtx, tx.tx_id.as_bytes());
+                skipped_tx_ids.push(tx.tx_id.as_bytes());
+                continue;
+            }
+            tx_ids.push(tx.tx_id);
+            if let Some(skipped_tx_id) =
+                self.handle_transaction(wtx, block, tx, reprocess, &mut messages)?
+            {
+                skipped_tx_ids.push(skipped_tx_id);
+            }
+        }
+        // a block received without its transactions or with all of them filtered out is left
+        // out, not recorded as empty
+        if !tx_ids.is_empty() {
+            self.block_transactions_partition
+                .insert_wtx(wtx, *hash, &tx_ids);
+        }
+
+        // Add skipped transactions to the block-organized partition
+        if !skipped_tx_ids.is_empty() {
+            debug!(%hash, skipped_count = skipped_tx_ids.len(), "Adding skipped transactions to block partition");
+            self.skip_tx_by_block_partition.add_skip_for_block(
+                wtx,
+                block.header.daa_score,
+                *block.header.hash.as_ref(),
+                &skipped_tx_ids,
+            );
+        }
+
+        let stats = self.block_stats_wtx(wtx, block)?;
+        self.block_stats_partition.insert_wtx(wtx, *hash, &stats);
+        // a reprocessed block only gets its fees replaced
+        if !reprocess && let Some(aggregates) = &self.aggregates {
+            aggregates.add_block_wtx(wtx, block, &stats)?;
+        }
+        if let Some(supply) = &self.supply {
+            supply.add_block_wtx(wtx, block)?;
+        }
+
+        let miner = coinbase::block_miner(block, self.address_prefix);
+        if let BlockMiner::Parsed { address, reward } = &miner {
+            self.miner_blocks_partition.insert_wtx(
+                wtx,
+                address,
+                block.header.daa_score,
+                *hash,
+                *reward,
+            )?;
+        }
+        self.block_miner_partition
+            .insert_wtx(wtx, *hash, block.header.daa_score, &miner);
+
+        self.metadata_partition.set_block_tip(
+            wtx,
+            Cursor {
+                daa_score: block.header.daa_score,
+                blue_work: block.header.blue_work,
+                hash: block.header.hash,
+            },
+        )?;
+        self.processed_block_partition
+            .mark_wtx(wtx, *hash, block.header.daa_score);
+
+        let mut indexed = BlockIndexed::from(block);
+        if let Some(verbose_data) = &block.verbose_data {
+            self.block_relations_partition.insert_wtx(
+                wtx,
+                *hash,
+                &BlockRelations::from(verbose_data),
+            )?;
+            indexed.is_chain_block = self.chain_membership_partition.set_from_verbose_wtx(
+                wtx,
+                *hash,
+                verbose_data.is_chain_block,
+            )?;
+        }
+        Ok(std::iter::once(indexed.into())
+            .chain(messages.into_iter().map(IndexEvent::from))
+            .collect())
+    }
+
+    /// Messages written are added to `messages`, returns the id of a skipped transaction
     fn handle_transaction(
         &mut self,
         wtx: &mut WriteTransaction,
         block: &RpcBlock,
-        tx: &RpcTransaction,
+        tx: PreparedTx,
+        reprocess: bool,
+        messages: &mut Vec<MessageIndexed>,
     ) -> anyhow::Result<Option<[u8; 32]>> {
-        let tx_id = match &tx.verbose_data {
-            Some(data) => data.transaction_id,
-            None => Transaction::try_from(tx.clone())?.id(),
-        };
-        if self.processed_txs.contains(&tx_id) {
+        let PreparedTx { tx, tx_id, op } = tx;
+        if !reprocess && (self.processed_txs.contains(&tx_id) || self.pending_txs.contains(&tx_id))
+        {
             debug!(%tx_id, "Skipping already processed transaction");
             return Ok(None);
         }
+        if self.index_outpoints {
+            self.index_outpoints_wtx(wtx, block, tx_id, tx)?;
+        }
+        if self.index_token_operations
+            && let Some(envelope) = kasplex::find_envelope(tx)
+        {
+            trace!(%tx_id, tick = %envelope.tick, "Found token operation");
+            self.token_operation_partition.insert_wtx(
+                wtx,
+                &envelope.tick,
+                block.header.daa_score,
+                tx_id,
+                &envelope.operation,
+            )?;
+        }
 
         trace!(%tx_id, "Processing transaction");
-        let skipped_tx_id = match parse_sealed_operation(&tx.payload).inspect(|op| {
-            trace!(%tx_id, kind = op.op_type_name(), "Parsed sealed operation");
-        }) {
-            Some(SealedOperation::SealedMessageOrSealedHandshakeVNone(op)) => {
-                self.handle_handshake(wtx, block, tx, &tx_id, op)?;
+        let message = |kind, receiver| MessageIndexed {
+            tx_id,
+            kind,
+            block_hash: block.header.hash,
+            daa_score: block.header.daa_score,
+            block_time_ms: block.header.timestamp,
+            receiver,
+        };
+        let skipped_tx_id = match op {
+            Some(PreparedOp::Handshake { op, receiver }) => {
+                self.handle_handshake(wtx, block, &tx_id, op, receiver)?;
+                messages.push(message(MessageKind::Handshake, Some(receiver)));
                 None
             }
-            Some(SealedOperation::ContextualMessageV1(op)) => {
+            Some(PreparedOp::ContextualMessage(op)) => {
                 self.handle_contextual_message(wtx, block, &tx_id, op)?;
+                // counted once committed
+                messages.push(message(MessageKind::ContextualMessage, None));
                 None
             }
-            Some(SealedOperation::PaymentV1(op)) => {
-                self.handle_payment(wtx, block, tx, &tx_id, op)?;
+            Some(PreparedOp::Payment {
+                op,
+                amount,
+                receiver,
+            }) => {
+                self.handle_payment(wtx, block, &tx_id, op, amount, receiver)?;
+                messages.push(message(MessageKind::Payment, Some(receiver)));
                 None
             }
             None => {
@@ -170,28 +1141,124 @@ impl BlockProcessor {
                 Some(tx_id.as_bytes())
             }
         };
-        self.processed_txs.insert(tx_id);
+        self.pending_txs.insert(tx_id);
 
         Ok(skipped_tx_id)
     }
-    fn handle_handshake(
+
+    /// Inputs are matched against the watched addresses through the outpoint index
+    fn admits_transaction_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx: &RpcTransaction,
+    ) -> anyhow::Result<bool> {
+        self.ingest_filter.admits_transaction(tx, |outpoint| {
+            if !self.index_outpoints {
+                return Ok(None);
+            }
+            Ok(self
+                .outpoint_partition
+                .get_wtx(wtx, outpoint.transaction_id, outpoint.index)?
+                .and_then(|output| AddressPayload::try_from(&output.script_public_key).ok()))
+        })
+    }
+
+    /// Sealed payload as stored, left out with `skip_payloads`
+    fn stored_payload<'a>(&self, sealed_hex: &'a [u8]) -> &'a [u8] {
+        match self.ingest_filter.skip_payloads {
+            true => &[],
+            false => sealed_hex,
+        }
+    }
+
+    /// Fees are only known with the outpoint index, outputs created in the same block are
+    /// visible through the write transaction
+    fn block_stats_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block: &RpcBlock,
+    ) -> anyhow::Result<BlockStats> {
+        let resolve = self
+            .index_outpoints
+            .then_some(|outpoint: &RpcTransactionOutpoint| {
+                Ok(self
+                    .outpoint_partition
+                    .get_wtx(wtx, outpoint.transaction_id, outpoint.index)?
+                    .map(|output| output.value))
+            });
+        BlockStats::compute(block, resolve)
+    }
+
+    /// Links the inputs to the outputs they spend both ways, parking those spending an output
+    /// not indexed yet, then indexes the outputs. A spend recorded later replaces an earlier one,
+    /// conflicting spends of the same outpoint are only told apart by acceptance
+    fn index_outpoints_wtx(
         &mut self,
         wtx: &mut WriteTransaction,
         block: &RpcBlock,
+        tx_id: TransactionId,
         tx: &RpcTransaction,
+    ) -> anyhow::Result<()> {
+        for (index, input) in tx.inputs.iter().enumerate() {
+            let outpoint = &input.previous_outpoint;
+            self.tx_input_partition.insert_wtx(
+                wtx,
+                tx_id,
+                index as u32,
+                outpoint.transaction_id,
+                outpoint.index,
+            );
+            let spent = self.outpoint_partition.mark_spent_wtx(
+                wtx,
+                outpoint.transaction_id,
+                outpoint.index,
+                tx_id,
+            )?;
+            if spent.is_none() {
+                trace!(%tx_id, outpoint = %outpoint.transaction_id, index = outpoint.index, "Spent output not indexed yet");
+                if self.pending_spend_partition.park_wtx(
+                    wtx,
+                    outpoint.transaction_id,
+                    outpoint.index,
+                    tx_id,
+                    block.header.daa_score,
+                )? {
+                    self.pending_spends_delta += 1;
+                }
+            }
+        }
+        for (index, output) in tx.outputs.iter().enumerate() {
+            let index = index as u32;
+            let mut spenders = self
+                .pending_spend_partition
+                .take_spenders_wtx(wtx, tx_id, index)?;
+            self.pending_spends_delta -= spenders.len() as i64;
+            let spent_by = spenders.pop();
+            self.outpoint_partition.insert_wtx(
+                wtx,
+                tx_id,
+                index,
+                &IndexedOutput::new(output.value, output.script_public_key.clone(), spent_by),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn handle_handshake(
+        &mut self,
+        wtx: &mut WriteTransaction,
+        block: &RpcBlock,
         tx_id: &TransactionId,
         op: SealedMessageOrSealedHandshakeVNone,
+        receiver: AddressPayload,
     ) -> anyhow::Result<()> {
         debug!(%tx_id, "Handling HandshakeVNone");
-        self.tx_id_to_handshake_partition
-            .insert_wtx(wtx, tx_id.as_ref(), op.sealed_hex);
+        self.tx_id_to_handshake_partition.insert_wtx(
+            wtx,
+            tx_id.as_ref(),
+            self.stored_payload(op.sealed_hex),
+        );
 
-        let receiver = tx
-            .outputs
-            .first()
-            .map(|o| AddressPayload::try_from(&o.script_public_key))
-            .transpose()?
-            .unwrap_or_default();
         debug!(receiver=?receiver, "Inserting handshake by receiver");
 
         self.handshake_by_receiver_partition.insert_wtx(
@@ -242,7 +1309,7 @@ impl BlockProcessor {
             block.header.hash.as_bytes(),
             1,
             tx_id.as_bytes(),
-            op.sealed_hex,
+            self.stored_payload(op.sealed_hex),
         )?;
 
         let mut alias = [0u8; 16];
@@ -257,7 +1324,6 @@ impl BlockProcessor {
         };
         self.tx_id_to_acceptance_partition
             .insert_contextual_message_wtx(wtx, tx_id.as_bytes(), &cmk_for_resolution, None, None);
-        self.metrics.increment_contextual_messages_count();
         Ok(())
     }
 
@@ -265,21 +1331,19 @@ impl BlockProcessor {
         &mut self,
         wtx: &mut WriteTransaction,
         block: &RpcBlock,
-        tx: &RpcTransaction,
         tx_id: &TransactionId,
         op: SealedPaymentV1,
+        amount: u64,
+        receiver: AddressPayload,
     ) -> anyhow::Result<()> {
         debug!(%tx_id, "Handling PaymentV1");
-        let (amount, receiver) = tx
-            .outputs
-            .first()
-            .map(|o| AddressPayload::try_from(&o.script_public_key).map(|addr| (o.value, addr)))
-            .transpose()?
-            .unwrap_or_default();
-
         debug!(receiver=?receiver, amount, "Inserting payment by receiver");
-        self.tx_id_to_payment_partition
-            .insert_wtx(wtx, tx_id.as_ref(), amount, op.sealed_hex)?;
+        self.tx_id_to_payment_partition.insert_wtx(
+            wtx,
+            tx_id.as_ref(),
+            amount,
+            self.stored_payload(op.sealed_hex),
+        )?;
         self.payment_by_receiver_partition.insert_wtx(
             wtx,
             &PaymentKeyByReceiver {
@@ -311,9 +1375,204 @@ impl BlockProcessor {
     }
 }
 
+/// Decodes the blocks of a batch on up to `workers` threads, recomputing their header hashes
+/// with `verify_hashes`. Results keep the order of `blocks`, a block failing to decode or
+/// hashing differently only fails its own entry
+pub fn prepare_blocks<'a>(
+    blocks: &'a [RpcBlock],
+    workers: usize,
+    verify_hashes: bool,
+) -> Vec<anyhow::Result<PreparedBlock<'a>>> {
+    let prepare = move |block: &'a RpcBlock| match verify_hashes {
+        true => PreparedBlock::verified(block),
+        false => PreparedBlock::new(block),
+    };
+    if workers <= 1 || blocks.len() < 2 {
+        return blocks.iter().map(prepare).collect();
+    }
+    let chunk_size = blocks.len().div_ceil(workers);
+    std::thread::scope(|scope| {
+        let workers = blocks
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(prepare).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        // joined in spawn order, which is the chunk order
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+/// Block with every transaction id computed and payload parsed, ready to be committed
+pub struct PreparedBlock<'a> {
+    block: &'a RpcBlock,
+    txs: Vec<PreparedTx<'a>>,
+    /// Time taken to recompute the header hash, if it was
+    hash_verification: Option<Duration>,
+}
+
+impl<'a> PreparedBlock<'a> {
+    pub fn new(block: &'a RpcBlock) -> anyhow::Result<Self> {
+        Ok(Self {
+            block,
+            txs: block
+                .transactions
+                .iter()
+                .map(PreparedTx::new)
+                .collect::<anyhow::Result<_>>()?,
+            hash_verification: None,
+        })
+    }
+
+    /// Fails with [`HeaderHashMismatch`] unless the header hashes to the reported hash
+    pub fn verified(block: &'a RpcBlock) -> anyhow::Result<Self> {
+        let started = Instant::now();
+        verify_header_hash(&block.header)?;
+        let hash_verification = started.elapsed();
+        Ok(Self {
+            hash_verification: Some(hash_verification),
+            ..Self::new(block)?
+        })
+    }
+
+    pub fn block(&self) -> &'a RpcBlock {
+        self.block
+    }
+
+    pub fn operations(&self) -> usize {
+        self.txs.iter().filter(|tx| tx.op.is_some()).count()
+    }
+}
+
+struct PendingBatch {
+    wtx: WriteTransaction,
+    hashes: Vec<RpcHash>,
+    events: Vec<IndexEvent>,
+    /// Transactions of the blocks, collected while a mempool is tracked
+    tx_ids: Vec<TransactionId>,
+    /// Receive times of the notified blocks
+    received_at: Vec<Instant>,
+    /// Root spans of the traced messages with blocks in the batch
+    spans: Vec<Span>,
+    bytes: usize,
+    started: Instant,
+}
+
+impl PendingBatch {
+    fn new(wtx: WriteTransaction) -> Self {
+        Self {
+            wtx,
+            hashes: Vec::new(),
+            events: Vec::new(),
+            tx_ids: Vec::new(),
+            received_at: Vec::new(),
+            spans: Vec::new(),
+            bytes: 0,
+            started: Instant::now(),
+        }
+    }
+
+    fn is_due(&self, policy: &FlushPolicy) -> bool {
+        self.hashes.len() >= policy.max_blocks
+            || self.bytes >= policy.max_bytes
+            || self.started.elapsed() >= policy.max_delay
+    }
+}
+
+/// Rough size of the data written for the block, dominated by payloads and scripts
+fn approx_write_size(block: &RpcBlock) -> usize {
+    const HEADER: usize = 256;
+    const PER_TX: usize = 128;
+    const PER_INPUT: usize = 48;
+    const PER_OUTPUT: usize = 48;
+    HEADER
+        + block
+            .transactions
+            .iter()
+            .map(|tx| {
+                PER_TX
+                    + tx.payload.len()
+                    + tx.inputs
+                        .iter()
+                        .map(|input| PER_INPUT + input.signature_script.len())
+                        .sum::<usize>()
+                    + tx.outputs
+                        .iter()
+                        .map(|output| PER_OUTPUT + output.script_public_key.script().len())
+                        .sum::<usize>()
+            })
+            .sum::<usize>()
+}
+
+struct PreparedTx<'a> {
+    tx: &'a RpcTransaction,
+    tx_id: TransactionId,
+    op: Option<PreparedOp<'a>>,
+}
+
+enum PreparedOp<'a> {
+    Handshake {
+        op: SealedMessageOrSealedHandshakeVNone<'a>,
+        receiver: AddressPayload,
+    },
+    ContextualMessage(SealedContextualMessageV1<'a>),
+    Payment {
+        op: SealedPaymentV1<'a>,
+        amount: u64,
+        receiver: AddressPayload,
+    },
+}
+
+impl<'a> PreparedTx<'a> {
+    fn new(tx: &'a RpcTransaction) -> anyhow::Result<Self> {
+        let tx_id = match &tx.verbose_data {
+            Some(data) => data.transaction_id,
+            None => Transaction::try_from(tx.clone())?.id(),
+        };
+        let op = match parse_sealed_operation(&tx.payload).inspect(|op| {
+            trace!(%tx_id, kind = op.op_type_name(), "Parsed sealed operation");
+        }) {
+            Some(SealedOperation::SealedMessageOrSealedHandshakeVNone(op)) => {
+                let receiver = tx
+                    .outputs
+                    .first()
+                    .map(|o| AddressPayload::try_from(&o.script_public_key))
+                    .transpose()?
+                    .unwrap_or_default();
+                Some(PreparedOp::Handshake { op, receiver })
+            }
+            Some(SealedOperation::ContextualMessageV1(op)) => {
+                Some(PreparedOp::ContextualMessage(op))
+            }
+            Some(SealedOperation::PaymentV1(op)) => {
+                let (amount, receiver) = tx
+                    .outputs
+                    .first()
+                    .map(|o| {
+                        AddressPayload::try_from(&o.script_public_key).map(|addr| (o.value, addr))
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                Some(PreparedOp::Payment {
+                    op,
+                    amount,
+                    receiver,
+                })
+            }
+            None => None,
+        };
+        Ok(Self { tx, tx_id, op })
+    }
+}
+
 enum BlocksOrShutdown {
     Blocks(BlockOrMany),
-    Shutdown(()),
+    Shutdown,
 }
 
 impl From<BlockOrMany> for BlocksOrShutdown {
@@ -322,8 +1581,831 @@ impl From<BlockOrMany> for BlocksOrShutdown {
     }
 }
 
-impl From<()> for BlocksOrShutdown {
-    fn from(value: ()) -> Self {
-        Self::Shutdown(value)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_events::IndexEvent;
+    use crate::database::schema::DescribePartition;
+    use crate::metrics::create_shared_metrics;
+    use crate::supervisor::{RestartPolicy, Supervised, Supervisor};
+    use kaspa_consensus_core::BlueWorkType;
+    use kaspa_consensus_core::header::Header;
+    use kaspa_consensus_core::subnets::SUBNETWORK_ID_NATIVE;
+    use kaspa_consensus_core::tx::{
+        ScriptPublicKey, TransactionInput, TransactionOutpoint, TransactionOutput,
+    };
+
+    fn block(i: u64, txs: u64) -> RpcBlock {
+        let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(i), vec![]);
+        header.daa_score = i;
+        let mut script = vec![0x20];
+        script.extend_from_slice(&[7; 32]);
+        script.push(0xac);
+        let transactions = (0..txs)
+            .map(|j| {
+                let tx = Transaction::new(
+                    0,
+                    vec![TransactionInput::new(
+                        TransactionOutpoint::new(RpcHash::from_u64_word(i), j as u32),
+                        vec![],
+                        0,
+                        1,
+                    )],
+                    vec![TransactionOutput::new(
+                        j,
+                        ScriptPublicKey::from_vec(0, script.clone()),
+                    )],
+                    0,
+                    SUBNETWORK_ID_NATIVE,
+                    0,
+                    format!("ciph_msg:1:payment:{i:x}{j:x}").into_bytes(),
+                );
+                RpcTransaction::from(&tx)
+            })
+            .collect();
+        RpcBlock {
+            header: (&header).into(),
+            transactions,
+            verbose_data: None,
+        }
+    }
+
+    #[test]
+    fn test_parallel_preparation_keeps_intake_order() {
+        let blocks = (0..50).map(|i| block(i, 5)).collect::<Vec<_>>();
+        let ids = |prepared: Vec<anyhow::Result<PreparedBlock>>| {
+            prepared
+                .into_iter()
+                .map(|prepared| {
+                    let prepared = prepared.unwrap();
+                    assert_eq!(prepared.operations(), 5);
+                    let tx_ids = prepared.txs.iter().map(|tx| tx.tx_id).collect::<Vec<_>>();
+                    (prepared.block().header.hash, tx_ids)
+                })
+                .collect::<Vec<_>>()
+        };
+        let sequential = ids(prepare_blocks(&blocks, 1, false));
+        assert_eq!(sequential.len(), blocks.len());
+        assert_eq!(ids(prepare_blocks(&blocks, 4, false)), sequential);
+        // more workers than blocks
+        assert_eq!(ids(prepare_blocks(&blocks, 64, false)), sequential);
+    }
+
+    fn processor(keyspace: &TxKeyspace, metrics: SharedMetrics) -> BlockProcessor {
+        BlockProcessor::builder()
+            .processed_blocks(FifoSet::new(16))
+            .processed_txs(FifoSet::new(1024))
+            .intake(flume::unbounded().1)
+            .tx_keyspace(keyspace.clone())
+            .metadata_partition(MetadataPartition::new(keyspace).unwrap())
+            .handshake_by_receiver_partition(HandshakeByReceiverPartition::new(keyspace).unwrap())
+            .tx_id_to_handshake_partition(TxIdToHandshakePartition::new(keyspace).unwrap())
+            .contextual_message_partition(
+                ContextualMessageBySenderPartition::new(keyspace).unwrap(),
+            )
+            .payment_by_receiver_partition(PaymentByReceiverPartition::new(keyspace).unwrap())
+            .tx_id_to_payment_partition(TxIdToPaymentPartition::new(keyspace).unwrap())
+            .tx_id_to_acceptance_partition(TxIDToAcceptancePartition::new(keyspace).unwrap())
+            .skip_tx_partition(SkipTxPartition::new(keyspace).unwrap())
+            .skip_tx_by_block_partition(SkipTxByBlockPartition::new(keyspace).unwrap())
+            .block_compact_header_partition(BlockCompactHeaderPartition::new(keyspace).unwrap())
+            .block_daa_index(DaaIndexPartition::new(keyspace).unwrap())
+            .chain_membership_partition(ChainMembershipPartition::new(keyspace).unwrap())
+            .block_relations_partition(BlockRelationsPartition::new(keyspace).unwrap())
+            .orphan_pool_partition(OrphanPoolPartition::new(keyspace).unwrap())
+            .block_miner_partition(BlockMinerPartition::new(keyspace).unwrap())
+            .miner_blocks_partition(MinerBlocksPartition::new(keyspace).unwrap())
+            .outpoint_partition(OutpointPartition::new(keyspace).unwrap())
+            .pending_spend_partition(PendingSpendPartition::new(keyspace).unwrap())
+            .tx_input_partition(TxInputPartition::new(keyspace).unwrap())
+            .block_stats_partition(BlockStatsPartition::new(keyspace).unwrap())
+            .block_transactions_partition(BlockTransactionsPartition::new(keyspace).unwrap())
+            .processed_block_partition(ProcessedBlockPartition::new(keyspace).unwrap())
+            .token_operation_partition(TokenOperationPartition::new(keyspace).unwrap())
+            .metrics(metrics)
+            .virtual_daa(Default::default())
+            .build()
+    }
+
+    #[test]
+    fn test_blocks_hashing_differently_are_rejected() {
+        let keyspace = crate::database::test_keyspace("hash-verification");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.verify_historical_hashes = true;
+        let mut valid = block(1, 2);
+        let mut header = Header::from(&valid.header);
+        header.finalize();
+        valid.header = (&header).into();
+        // the test blocks carry made up hashes
+        let forged = block(2, 2);
+
+        processor
+            .handle_intake(BlockOrMany::Many(
+                vec![valid.clone(), forged.clone()],
+                "node".into(),
+                Default::default(),
+            ))
+            .unwrap();
+        processor.flush().unwrap();
+        assert!(processor.is_processed(&valid.header.hash).unwrap());
+        assert!(!processor.is_processed(&forged.header.hash).unwrap());
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.header_hash_mismatches, 1);
+        assert_eq!(snapshot.header_hash_verification_time.count, 1);
+    }
+
+    #[test]
+    fn test_duplicate_block_is_not_counted_twice() {
+        let keyspace = crate::database::test_keyspace("block-processor");
+        let metrics = create_shared_metrics();
+        let mut duplicated = block(1, 3);
+        duplicated
+            .transactions
+            .push(RpcTransaction::from(&Transaction::new(
+                0,
+                vec![],
+                vec![],
+                0,
+                SUBNETWORK_ID_NATIVE,
+                0,
+                b"ciph_msg:1:comm:alias:abc123".to_vec(),
+            )));
+        let hash = duplicated.header.hash;
+
+        // overlapping notifications within one session
+        let mut first = processor(&keyspace, metrics.clone());
+        first
+            .handle_blocks(&[duplicated.clone(), duplicated.clone()])
+            .unwrap();
+        // delivered again after a restart, the in memory set is empty
+        let mut restarted = processor(&keyspace, metrics.clone());
+        restarted.handle_blocks(&[duplicated.clone()]).unwrap();
+        assert_eq!(metrics.get_blocks_processed(), 1);
+        assert_eq!(metrics.get_contextual_messages(), 1);
+
+        restarted.force_reprocess(&duplicated).unwrap();
+        assert_eq!(metrics.get_blocks_processed(), 1);
+        assert_eq!(metrics.get_contextual_messages(), 1);
+        assert!(
+            restarted
+                .processed_block_partition
+                .is_processed(hash)
+                .unwrap()
+        );
+        let stats = restarted
+            .block_stats_partition
+            .get_block_stats(hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats.tx_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_indexed_block_events() {
+        let keyspace = crate::database::test_keyspace("block-events");
+        let mut processor = processor(&keyspace, create_shared_metrics());
+        // downstream consumer, e.g. a websocket push
+        let mut indexed = processor.subscribe_indexed_blocks();
+        let consumer = tokio::spawn(async move {
+            let mut events = Vec::new();
+            while let Some(event) = indexed.recv().await {
+                if let IndexEvent::BlockIndexed(event) = event {
+                    events.push(event);
+                }
+            }
+            events
+        });
+
+        let blocks = (1..=3).map(|i| block(i, 2)).collect::<Vec<_>>();
+        processor.handle_blocks(&blocks).unwrap();
+        // not indexed again, not published again
+        processor.handle_blocks(&blocks[..1]).unwrap();
+        drop(processor);
+
+        let events = consumer.await.unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.hash).collect::<Vec<_>>(),
+            blocks
+                .iter()
+                .map(|block| block.header.hash)
+                .collect::<Vec<_>>()
+        );
+        assert!(
+            events
+                .iter()
+                .all(|event| event.tx_count == 2 && !event.is_chain_block)
+        );
+    }
+
+    #[test]
+    fn test_reindex_restores_corrupted_range() {
+        use crate::database::difftest;
+        use crate::reindex::{Reindex, ReindexTarget};
+
+        let open = |name: &str| crate::database::test_keyspace(&format!("reindex-{name}"));
+        let blocks = (1..=6).map(|i| block(i, 2)).collect::<Vec<_>>();
+        let clean = open("clean");
+        processor(&clean, create_shared_metrics())
+            .handle_blocks(&blocks)
+            .unwrap();
+        let corrupted = open("corrupted");
+        let mut processor = processor(&corrupted, create_shared_metrics());
+        processor.handle_blocks(&blocks).unwrap();
+
+        // stats of block 2 lost, block 3 given the stats of block 5
+        let stats = processor
+            .block_stats_partition
+            .get_block_stats(blocks[4].header.hash)
+            .unwrap()
+            .unwrap();
+        let mut wtx = corrupted.write_tx().unwrap();
+        processor
+            .block_stats_partition
+            .remove_wtx(&mut wtx, blocks[1].header.hash);
+        processor
+            .block_stats_partition
+            .insert_wtx(&mut wtx, blocks[2].header.hash, &stats);
+        wtx.commit().unwrap().unwrap();
+        assert!(
+            !difftest::compare(&clean, &corrupted, &Default::default())
+                .unwrap()
+                .is_clean()
+        );
+
+        let reindex = Reindex::new(&corrupted).unwrap();
+        let daa_range = 2..4;
+        // the whole range is within the live margin of the tip
+        assert!(reindex.check_range(&daa_range, 3).is_err());
+        assert!(reindex.check_range(&daa_range, 2).is_ok());
+        let targets = [ReindexTarget::Fees, ReindexTarget::Miners];
+        let range_blocks = reindex.blocks(&daa_range).unwrap();
+        // a failed reindex leaves the range as it was
+        let counts = reindex.count(&daa_range, &targets).unwrap();
+        processor
+            .force_reprocess_all(
+                &blocks[1..3],
+                |wtx| reindex.delete_wtx(wtx, &daa_range, &range_blocks, &targets),
+                |_| anyhow::bail!("interrupted"),
+            )
+            .unwrap_err();
+        assert_eq!(reindex.count(&daa_range, &targets).unwrap(), counts);
+
+        let deleted = processor
+            .force_reprocess_all(
+                &blocks[1..3],
+                |wtx| reindex.delete_wtx(wtx, &daa_range, &range_blocks, &targets),
+                |wtx| reindex.restore_senders_wtx(wtx, &range_blocks, &targets),
+            )
+            .unwrap();
+        assert_eq!(deleted[&ReindexTarget::Fees], 1);
+        assert_eq!(
+            reindex.count(&daa_range, &targets).unwrap()[&ReindexTarget::Fees],
+            2
+        );
+        assert!(
+            difftest::compare(&clean, &corrupted, &Default::default())
+                .unwrap()
+                .is_clean()
+        );
+    }
+
+    #[test]
+    fn test_testnet_miner_addresses() {
+        use kaspa_addresses::{Address, Version};
+        use kaspa_consensus_core::subnets::SUBNETWORK_ID_COINBASE;
+
+        let keyspace = crate::database::test_keyspace("testnet");
+        let mut processor = processor(&keyspace, create_shared_metrics());
+        processor.address_prefix = Prefix::Testnet;
+        let mut mined = block(1, 1);
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&1u64.to_le_bytes());
+        payload.extend_from_slice(&50_000_000u64.to_le_bytes());
+        payload.extend_from_slice(&0u16.to_le_bytes());
+        payload.push(34);
+        payload.push(0x20);
+        payload.extend_from_slice(&[9; 32]);
+        payload.push(0xac);
+        let coinbase = Transaction::new(0, vec![], vec![], 0, SUBNETWORK_ID_COINBASE, 0, payload);
+        mined
+            .transactions
+            .insert(0, RpcTransaction::from(&coinbase));
+        processor.handle_blocks(&[mined.clone()]).unwrap();
+
+        let expected = Address::new(Prefix::Testnet, Version::PubKey, &[9; 32]);
+        assert!(expected.to_string().starts_with("kaspatest:"));
+        assert_eq!(
+            processor
+                .block_miner_partition
+                .get_block_miner(mined.header.hash)
+                .unwrap(),
+            Some(BlockMiner::Parsed {
+                address: expected.clone(),
+                reward: 50_000_000,
+            })
+        );
+        let by_miner = processor
+            .miner_blocks_partition
+            .blocks_mined_by(&expected, 0..10)
+            .unwrap();
+        assert_eq!(by_miner.blocks, 1);
+    }
+
+    #[test]
+    fn test_ingest_filter() {
+        use kaspa_rpc_core::RpcBlockVerboseData;
+
+        let keyspace = crate::database::test_keyspace("ingest-filter");
+        let mut processor = processor(&keyspace, create_shared_metrics());
+        let script = |byte: u8| {
+            let mut script = vec![0x20];
+            script.extend_from_slice(&[byte; 32]);
+            script.push(0xac);
+            ScriptPublicKey::from_vec(0, script)
+        };
+        let watched = AddressPayload::try_from(&script(7)).unwrap();
+        processor.ingest_filter = Arc::new(
+            IngestFilter {
+                chain_blocks_only: true,
+                skip_payloads: true,
+                ..Default::default()
+            }
+            .with_addresses([watched]),
+        );
+        // the test blocks pay to the watched address
+        let paying = block(1, 2);
+        let mut unwatched = block(2, 2);
+        for tx in &mut unwatched.transactions {
+            tx.outputs[0].script_public_key = script(8);
+        }
+        let mut off_chain = block(3, 2);
+        off_chain.verbose_data = Some(RpcBlockVerboseData {
+            hash: off_chain.header.hash,
+            difficulty: 0.0,
+            selected_parent_hash: paying.header.hash,
+            transaction_ids: vec![],
+            is_header_only: false,
+            blue_score: 3,
+            children_hashes: vec![],
+            merge_set_blues_hashes: vec![],
+            merge_set_reds_hashes: vec![],
+            is_chain_block: false,
+        });
+        processor
+            .handle_blocks(&[paying.clone(), unwatched.clone(), off_chain.clone()])
+            .unwrap();
+
+        let rtx = keyspace.read_tx();
+        let tx_ids = |block: &RpcBlock| {
+            block
+                .transactions
+                .iter()
+                .map(|tx| Transaction::try_from(tx.clone()).unwrap().id())
+                .collect::<Vec<_>>()
+        };
+        let transactions = &processor.block_transactions_partition;
+        assert_eq!(
+            transactions
+                .get_block_transactions_rtx(&rtx, paying.header.hash)
+                .unwrap(),
+            Some(tx_ids(&paying))
+        );
+        for filtered in [&unwatched, &off_chain] {
+            let hash = filtered.header.hash;
+            assert_eq!(
+                transactions.get_block_transactions_rtx(&rtx, hash).unwrap(),
+                None
+            );
+            // headers of every block are indexed
+            assert!(
+                processor
+                    .block_compact_header_partition
+                    .get_compact_header_rtx(&rtx, &hash)
+                    .unwrap()
+                    .is_some()
+            );
+            for tx_id in tx_ids(filtered) {
+                assert!(
+                    processor
+                        .skip_tx_partition
+                        .should_skip(&rtx, &tx_id.as_bytes())
+                        .unwrap()
+                );
+                assert_eq!(
+                    processor
+                        .tx_id_to_payment_partition
+                        .get_tx_id(tx_id)
+                        .unwrap(),
+                    None
+                );
+            }
+        }
+        let (amount, payload) = processor
+            .tx_id_to_payment_partition
+            .get_tx_id(tx_ids(&paying)[1])
+            .unwrap()
+            .unwrap();
+        assert_eq!((amount, payload), (1, vec![]));
+    }
+
+    #[test]
+    fn test_batched_commits() {
+        let keyspace = crate::database::test_keyspace("batched");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.flush_policy = FlushPolicy {
+            max_delay: Duration::MAX,
+            ..FlushPolicy::batched(3)
+        };
+        let blocks = (1..=5).map(|i| block(i, 2)).collect::<Vec<_>>();
+        let committed = |processor: &BlockProcessor| {
+            blocks
+                .iter()
+                .filter(|block| {
+                    processor
+                        .processed_block_partition
+                        .is_processed(block.header.hash)
+                        .unwrap()
+                })
+                .count()
+        };
+
+        processor.handle_blocks(&blocks).unwrap();
+        assert_eq!(committed(&processor), 3);
+        assert_eq!(metrics.get_blocks_processed(), 3);
+        let tip = processor
+            .metadata_partition
+            .get_latest_block_cursor()
+            .unwrap()
+            .unwrap();
+        assert_eq!(tip.daa_score, 3);
+
+        // pending blocks are not written into the batch twice
+        processor.handle_blocks(&blocks[3..]).unwrap();
+        assert_eq!(committed(&processor), 3);
+        processor.flush().unwrap();
+        assert_eq!(committed(&processor), 5);
+        assert_eq!(metrics.get_blocks_processed(), 5);
+        let tip = processor
+            .metadata_partition
+            .get_latest_block_cursor()
+            .unwrap()
+            .unwrap();
+        assert_eq!(tip.daa_score, 5);
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_batch_is_written_again() {
+        let keyspace = crate::database::test_keyspace("conflict");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.flush_policy = FlushPolicy {
+            max_delay: Duration::MAX,
+            ..FlushPolicy::batched(10)
+        };
+        let mut indexed = processor.subscribe_indexed_blocks();
+        let consumer = tokio::spawn(async move {
+            let mut events = Vec::new();
+            while let Some(event) = indexed.recv().await {
+                events.push(event);
+            }
+            events
+        });
+        let blocks = (1..=2).map(|i| block(i, 2)).collect::<Vec<_>>();
+        processor.handle_blocks(&blocks).unwrap();
+
+        // another writer moves the tip the batch read before it commits
+        let mut wtx = keyspace.write_tx().unwrap();
+        processor
+            .metadata_partition
+            .set_block_tip(&mut wtx, Cursor::default())
+            .unwrap();
+        wtx.commit().unwrap().unwrap();
+        processor.flush().unwrap();
+
+        for block in &blocks {
+            assert!(processor.is_processed(&block.header.hash).unwrap());
+        }
+        let tip = processor
+            .metadata_partition
+            .get_latest_block_cursor()
+            .unwrap()
+            .unwrap();
+        assert_eq!(tip.daa_score, 2);
+        assert_eq!(metrics.get_blocks_processed(), 2);
+        drop(processor);
+        // the transactions were written again, not skipped as processed by the first attempt
+        let events = consumer.await.unwrap();
+        let count = |f: fn(&IndexEvent) -> bool| events.iter().filter(|event| f(event)).count();
+        assert_eq!(
+            count(|event| matches!(event, IndexEvent::BlockIndexed(_))),
+            2
+        );
+        assert_eq!(
+            count(|event| matches!(event, IndexEvent::MessageIndexed(_))),
+            4
+        );
+    }
+
+    #[test]
+    fn test_block_latency_metrics() {
+        let keyspace = crate::database::test_keyspace("latency");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        let notified = BlockOrMany::Block(
+            Arc::new(block(1, 2)),
+            Some(Instant::now() - Duration::from_millis(30)),
+            "node".into(),
+            Default::default(),
+        );
+        let synced = BlockOrMany::Many(
+            (2..=4).map(|i| block(i, 2)).collect(),
+            "node".into(),
+            Default::default(),
+        );
+        metrics.add_intake_blocks(&notified);
+        metrics.add_intake_blocks(&synced);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.subscriber_intake_depth, 1);
+        assert_eq!(snapshot.historical_intake_depth, 3);
+
+        processor.handle_intake(notified).unwrap();
+        processor.handle_intake(synced).unwrap();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.subscriber_intake_depth, 0);
+        assert_eq!(snapshot.historical_intake_depth, 0);
+        // only the notified block has a receive time
+        assert_eq!(snapshot.block_e2e_latency.count, 1);
+        assert!(snapshot.block_e2e_latency.quantile(0.5).unwrap() >= Duration::from_millis(30));
+        assert_eq!(snapshot.block_processing_time.count, 4);
+    }
+
+    #[test]
+    fn test_ingestion_spans() {
+        use crate::ingest_trace::TraceContext;
+        use opentelemetry::trace::{SpanId, TracerProvider as _};
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::Layer;
+        use tracing_subscriber::filter::Targets;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer("test"))
+                .with_filter(Targets::new().with_target(TRACE_TARGET, tracing::Level::DEBUG)),
+        );
+        let keyspace = crate::database::test_keyspace("spans");
+        tracing::subscriber::with_default(subscriber, || {
+            let mut processor = processor(&keyspace, create_shared_metrics());
+            let span = debug_span!(target: TRACE_TARGET, "ingest", source = "historical");
+            let blocks = BlockOrMany::Many(
+                (1..=2).map(|i| block(i, 2)).collect(),
+                "node".into(),
+                TraceContext::new(span),
+            );
+            processor.handle_intake(blocks).unwrap();
+            processor.flush().unwrap();
+            // the processor holds on to the span of the last message
+            drop(processor);
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let named = |name: &'static str| spans.iter().filter(move |span| span.name == name);
+        let id = |name: &'static str| named(name).next().unwrap().span_context.span_id();
+        let ingest = named("ingest").next().unwrap();
+        assert_eq!(ingest.parent_span_id, SpanId::INVALID);
+        for name in ["queued", "process", "db_commit"] {
+            assert_eq!(named(name).count(), 1);
+            assert_eq!(named(name).next().unwrap().parent_span_id, id("ingest"));
+        }
+        assert_eq!(
+            named("decode").next().unwrap().parent_span_id,
+            id("process")
+        );
+        assert_eq!(named("write").count(), 2);
+        assert!(named("write").all(|span| span.parent_span_id == id("process")));
+        let trace_id = ingest.span_context.trace_id();
+        assert!(
+            spans
+                .iter()
+                .all(|span| span.span_context.trace_id() == trace_id)
+        );
+    }
+
+    fn child(i: u64, parent: u64) -> RpcBlock {
+        let mut header = Header::from_precomputed_hash(
+            RpcHash::from_u64_word(i),
+            vec![RpcHash::from_u64_word(parent)],
+        );
+        header.daa_score = i;
+        RpcBlock {
+            header: (&header).into(),
+            transactions: vec![],
+            verbose_data: None,
+        }
+    }
+
+    #[test]
+    fn test_orphans_wait_for_their_parents_to_be_committed() {
+        let keyspace = crate::database::test_keyspace("release");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.flush_policy = FlushPolicy {
+            max_delay: Duration::MAX,
+            ..FlushPolicy::batched(10)
+        };
+        let notified =
+            |block| BlockOrMany::Block(Arc::new(block), None, "node".into(), Default::default());
+        let synced = |block| BlockOrMany::Many(vec![block], "node".into(), Default::default());
+        let processed = |processor: &BlockProcessor, i| {
+            processor
+                .processed_block_partition
+                .is_processed(RpcHash::from_u64_word(i))
+                .unwrap()
+        };
+
+        // notified before their parents
+        processor.handle_intake(notified(child(12, 11))).unwrap();
+        processor.handle_intake(notified(child(11, 10))).unwrap();
+        // delivered again while parked
+        processor.handle_intake(notified(child(12, 11))).unwrap();
+        assert_eq!(metrics.snapshot().orphan_blocks, 2);
+        // the historical syncers deliver in order, a missing parent is outside the synced range
+        processor.handle_intake(synced(child(20, 19))).unwrap();
+        processor.handle_intake(synced(block(10, 0))).unwrap();
+        assert_eq!(metrics.snapshot().orphan_blocks, 2);
+
+        // released once the parent is committed, not while it is pending
+        processor.flush().unwrap();
+        assert!(processed(&processor, 10) && processed(&processor, 20));
+        assert!(!processed(&processor, 11));
+        assert_eq!(metrics.snapshot().orphan_blocks, 1);
+        processor.flush().unwrap();
+        assert!(processed(&processor, 11) && !processed(&processor, 12));
+        processor.flush().unwrap();
+        assert!(processed(&processor, 12));
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.orphan_blocks, 0);
+        assert_eq!(snapshot.orphans_reprocessed, 2);
+        assert!(
+            !processor
+                .orphan_pool_partition
+                .has_children(&RpcHash::from_u64_word(11))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pending_spends_are_counted_and_evicted() {
+        let keyspace = crate::database::test_keyspace("pending");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.index_outpoints = true;
+        processor.pending_spend_max_daa_distance = 10;
+
+        // the outputs spent by the test blocks are never indexed, counted once while idle
+        processor.handle_blocks(&[block(1, 2)]).unwrap();
+        assert_eq!(metrics.snapshot().pending_spends, 2);
+        // and kept up to date by the committed blocks from then on
+        processor.handle_blocks(&[block(5, 1)]).unwrap();
+        assert_eq!(metrics.snapshot().pending_spends, 3);
+
+        processor.virtual_daa.store(13, Ordering::Relaxed);
+        processor.evict_stale_pending_spends().unwrap();
+        assert_eq!(metrics.snapshot().pending_spends, 1);
+        assert_eq!(
+            processor
+                .pending_spend_partition
+                .len_rtx(&keyspace.read_tx())
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_orphan_capacity_and_backfill() {
+        let keyspace = crate::database::test_keyspace("orphans");
+        let metrics = create_shared_metrics();
+        let (backfill_tx, mut backfill_rx) = tokio::sync::mpsc::channel(4);
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.max_orphan_blocks = 1;
+        processor.orphan_backfill_daa_distance = 10;
+        processor.backfill_requests = Some(backfill_tx);
+        let processed = |processor: &BlockProcessor, i| {
+            processor
+                .processed_block_partition
+                .is_processed(RpcHash::from_u64_word(i))
+                .unwrap()
+        };
+
+        let stored = (1..=5).map(|i| block(i, 1)).collect::<Vec<_>>();
+        processor.handle_blocks(&stored).unwrap();
+        processor.handle_blocks(&[child(50, 40)]).unwrap();
+        assert!(!processed(&processor, 50));
+        // the pool is full
+        processor.handle_blocks(&[child(51, 41)]).unwrap();
+        assert!(processed(&processor, 51));
+        assert_eq!(metrics.snapshot().orphans_over_capacity, 1);
+
+        processor.virtual_daa.store(70, Ordering::Relaxed);
+        processor.evict_stale_orphans().unwrap();
+        let gap = backfill_rx.try_recv().unwrap();
+        assert_eq!(gap.from_block_hash, RpcHash::from_u64_word(5));
+        assert_eq!(gap.from_daa_score, 5);
+        assert_eq!(gap.to_block_hash, RpcHash::from_u64_word(50));
+        // requested once per orphan
+        processor.evict_stale_orphans().unwrap();
+        assert!(backfill_rx.try_recv().is_err());
+        assert_eq!(metrics.snapshot().orphan_backfills, 1);
+
+        // delivered by the backfill
+        processor.handle_blocks(&[block(40, 1)]).unwrap();
+        assert!(processed(&processor, 50));
+        assert_eq!(metrics.snapshot().orphans_reprocessed, 1);
+    }
+
+    /// Fails its first run on an unreadable header of block 9, which it repairs afterwards
+    struct FailingOnce {
+        processor: BlockProcessor,
+        repaired: bool,
+    }
+
+    impl Supervised for FailingOnce {
+        fn run(&mut self) -> anyhow::Result<()> {
+            let result = self.processor.run();
+            if !self.repaired {
+                self.repaired = true;
+                self.processor
+                    .block_compact_header_partition
+                    .insert_compact_header(
+                        &RpcHash::from_u64_word(9),
+                        BlueWorkType::from_u64(9),
+                        9,
+                    )?;
+            }
+            result
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_blocks_are_handled_after_restart() {
+        let keyspace = crate::database::test_keyspace("restart");
+        let metrics = create_shared_metrics();
+        let mut processor = processor(&keyspace, metrics.clone());
+        processor.flush_policy = FlushPolicy {
+            max_delay: Duration::MAX,
+            ..FlushPolicy::batched(10)
+        };
+        keyspace
+            .open_partition(
+                BlockCompactHeaderPartition::DESCRIPTION.name,
+                Default::default(),
+            )
+            .unwrap()
+            .insert(RpcHash::from_u64_word(9).as_bytes(), [0; 3])
+            .unwrap();
+        let synced = |blocks| BlockOrMany::Many(blocks, "node".into(), Default::default());
+        let (intake_tx, intake_rx) = flume::unbounded();
+        // the first message is still in the batch when the second one fails
+        intake_tx
+            .send(synced(vec![block(1, 2), block(2, 2)]))
+            .unwrap();
+        intake_tx
+            .send(synced(vec![child(10, 9), block(3, 2)]))
+            .unwrap();
+        processor.shutdown.cancel();
+        processor.intake = intake_rx;
+        let supervisor = Supervisor::new(
+            RestartPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            metrics.clone(),
+        );
+        let (stage, stop) = (Shutdown::new(), Shutdown::new());
+        let component = FailingOnce {
+            processor,
+            repaired: false,
+        };
+
+        supervisor
+            .spawn(&stage, &stop, "block worker", component)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(supervisor.health()["block worker"].restarts, 1);
+        assert!(!stop.is_cancelled());
+        let processed = ProcessedBlockPartition::new(&keyspace).unwrap();
+        for i in [1, 2, 3, 10] {
+            assert!(processed.is_processed(RpcHash::from_u64_word(i)).unwrap());
+        }
+        assert_eq!(metrics.get_blocks_processed(), 4);
     }
 }
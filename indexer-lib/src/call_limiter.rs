@@ -0,0 +1,199 @@
+//! Per node concurrency limit and circuit breaker.
+//!
+//! Gap syncers, the selected chain syncer and the resolver share the nodes they fetch from. Every
+//! call holds one of a fixed number of permits of its node, so bursts queue up here instead of
+//! timing out at the node. After a number of consecutive transport failures the breaker opens and
+//! calls fail right away for a cooldown, the resolver moves to another node meanwhile. Once the
+//! cooldown is over calls go through again, the first outcome closes or reopens the breaker.
+
+use crate::metrics::SharedMetrics;
+use crate::rpc_transport::TransportError;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+pub const DEFAULT_PERMITS_PER_NODE: usize = 16;
+pub const DEFAULT_BREAKER_FAILURES: u32 = 5;
+pub const DEFAULT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    /// Calls are refused until the cooldown is over
+    Open,
+    /// Cooldown over, the next outcome decides
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    /// Set while not closed
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            consecutive_failures: 0,
+            open_until: None,
+        }
+    }
+
+    fn state(&self, now: Instant) -> BreakerState {
+        match self.open_until {
+            None => BreakerState::Closed,
+            Some(until) if now < until => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Returns whether the breaker closed
+    fn record_success(&mut self) -> bool {
+        self.consecutive_failures = 0;
+        self.open_until.take().is_some()
+    }
+
+    /// Returns whether the breaker opened from closed
+    fn record_failure(&mut self, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+        match self.state(now) {
+            BreakerState::Closed if self.consecutive_failures >= self.failure_threshold => {
+                self.open_until = Some(now + self.cooldown);
+                true
+            }
+            BreakerState::HalfOpen => {
+                self.open_until = Some(now + self.cooldown);
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Permit of a single call, released on drop
+pub struct CallPermit {
+    _permit: OwnedSemaphorePermit,
+    metrics: SharedMetrics,
+}
+
+impl Drop for CallPermit {
+    fn drop(&mut self) {
+        self.metrics.decrement_rpc_permits_in_use();
+    }
+}
+
+/// Limiter of one node, clones share permits and breaker
+#[derive(Clone)]
+pub struct CallLimiter {
+    node: Arc<str>,
+    semaphore: Arc<Semaphore>,
+    breaker: Arc<Mutex<Breaker>>,
+    metrics: SharedMetrics,
+}
+
+impl CallLimiter {
+    pub fn new(node: &str, permits: usize) -> Self {
+        Self {
+            node: Arc::from(node),
+            semaphore: Arc::new(Semaphore::new(permits.max(1))),
+            breaker: Arc::new(Mutex::new(Breaker::new(
+                DEFAULT_BREAKER_FAILURES,
+                DEFAULT_BREAKER_COOLDOWN,
+            ))),
+            metrics: Default::default(),
+        }
+    }
+
+    /// Opens the breaker after `failure_threshold` consecutive failures for `cooldown`
+    pub fn with_breaker(self, failure_threshold: u32, cooldown: Duration) -> Self {
+        *self.breaker.lock() = Breaker::new(failure_threshold, cooldown);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.breaker.lock().state(Instant::now())
+    }
+
+    /// Waits for a permit, refused right away while the breaker is open
+    pub async fn acquire(&self) -> Result<CallPermit, TransportError> {
+        if self.state() == BreakerState::Open {
+            return Err(TransportError::CircuitOpen);
+        }
+        let started = Instant::now();
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        self.metrics
+            .add_rpc_permit_wait_ms(started.elapsed().as_millis() as u64);
+        self.metrics.increment_rpc_permits_in_use();
+        Ok(CallPermit {
+            _permit: permit,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    /// Only transport failures count against the node, a node rejecting a request answered
+    pub fn record<T>(&self, result: &Result<T, TransportError>) {
+        match result {
+            Err(err) if err.is_transient() => {
+                if self.breaker.lock().record_failure(Instant::now()) {
+                    warn!(node = %self.node, "Circuit breaker opened");
+                    self.metrics.increment_rpc_open_breakers();
+                }
+            }
+            _ => {
+                if self.breaker.lock().record_success() {
+                    info!(node = %self.node, "Circuit breaker closed");
+                    self.metrics.decrement_rpc_open_breakers();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_consecutive_failures() {
+        let cooldown = Duration::from_secs(30);
+        let mut breaker = Breaker::new(3, cooldown);
+        let now = Instant::now();
+
+        assert!(!breaker.record_failure(now));
+        assert!(!breaker.record_failure(now));
+        breaker.record_success();
+        assert!(!breaker.record_failure(now));
+        assert!(!breaker.record_failure(now));
+        assert_eq!(breaker.state(now), BreakerState::Closed);
+        assert!(breaker.record_failure(now));
+        assert_eq!(breaker.state(now), BreakerState::Open);
+
+        // a failed call after the cooldown reopens it right away
+        let later = now + cooldown;
+        assert_eq!(breaker.state(later), BreakerState::HalfOpen);
+        assert!(!breaker.record_failure(later));
+        assert_eq!(breaker.state(later), BreakerState::Open);
+
+        let later = later + cooldown;
+        assert!(breaker.record_success());
+        assert_eq!(breaker.state(later), BreakerState::Closed);
+        assert!(!breaker.record_success());
+    }
+}
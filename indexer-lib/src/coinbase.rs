@@ -0,0 +1,126 @@
+//! Coinbase payload decoding and miner attribution.
+//!
+//! Payload layout: `[blue_score (8 LE)] [subsidy (8 LE)] [script_public_key version (2 LE)]
+//! [script length (1)] [script] [extra data]`. The extra data is free form, usually the miner
+//! software version.
+
+use crate::database::miners::BlockMiner;
+use anyhow::{Context, Result, bail};
+use kaspa_addresses::{Address, Prefix};
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_COINBASE;
+use kaspa_consensus_core::tx::ScriptPublicKey;
+use kaspa_rpc_core::RpcBlock;
+use kaspa_txscript::extract_script_pub_key_address;
+use tracing::debug;
+
+const HEADER_LEN: usize = 8 + 8 + 2 + 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoinbasePayload<'a> {
+    pub blue_score: u64,
+    pub subsidy: u64,
+    pub script_public_key: ScriptPublicKey,
+    pub extra_data: &'a [u8],
+}
+
+pub fn decode_payload(payload: &[u8]) -> Result<CoinbasePayload<'_>> {
+    if payload.len() < HEADER_LEN {
+        bail!("Coinbase payload too short: {} bytes", payload.len());
+    }
+    let (header, rest) = payload.split_at(HEADER_LEN);
+    let script_len = header[18] as usize;
+    if rest.len() < script_len {
+        bail!(
+            "Coinbase script of {script_len} bytes exceeds the {} remaining",
+            rest.len()
+        );
+    }
+    let (script, extra_data) = rest.split_at(script_len);
+    Ok(CoinbasePayload {
+        blue_score: u64::from_le_bytes(header[..8].try_into()?),
+        subsidy: u64::from_le_bytes(header[8..16].try_into()?),
+        script_public_key: ScriptPublicKey::from_vec(
+            u16::from_le_bytes(header[16..18].try_into()?),
+            script.to_vec(),
+        ),
+        extra_data,
+    })
+}
+
+pub fn miner_address(script_public_key: &ScriptPublicKey, prefix: Prefix) -> Result<Address> {
+    extract_script_pub_key_address(script_public_key, prefix)
+        .map_err(|err| anyhow::anyhow!("Miner script has no address: {err}"))
+}
+
+/// Miner of the block, taken from its coinbase transaction. Never fails, blocks whose
+/// coinbase can't be attributed are marked [`BlockMiner::ParseFailed`]
+pub fn block_miner(block: &RpcBlock, prefix: Prefix) -> BlockMiner {
+    let attributed = || -> Result<BlockMiner> {
+        let coinbase = block
+            .transactions
+            .first()
+            .filter(|tx| tx.subnetwork_id == SUBNETWORK_ID_COINBASE)
+            .context("Block has no coinbase transaction")?;
+        let payload = decode_payload(&coinbase.payload)?;
+        Ok(BlockMiner::Parsed {
+            address: miner_address(&payload.script_public_key, prefix)?,
+            reward: payload.subsidy,
+        })
+    };
+    attributed().unwrap_or_else(|err| {
+        debug!(hash = %block.header.hash, "Coinbase not attributed: {err}");
+        BlockMiner::ParseFailed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_addresses::Version;
+
+    fn encode(script: &[u8], extra_data: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&42u64.to_le_bytes());
+        payload.extend_from_slice(&50_000_000u64.to_le_bytes());
+        payload.extend_from_slice(&0u16.to_le_bytes());
+        payload.push(script.len() as u8);
+        payload.extend_from_slice(script);
+        payload.extend_from_slice(extra_data);
+        payload
+    }
+
+    fn p2pk_script(key: [u8; 32]) -> Vec<u8> {
+        let mut script = vec![0x20];
+        script.extend_from_slice(&key);
+        script.push(0xac);
+        script
+    }
+
+    #[test]
+    fn test_decode_payload() {
+        let script = p2pk_script([7; 32]);
+        let payload = encode(&script, b"0.14.1/miner");
+        let decoded = decode_payload(&payload).unwrap();
+        assert_eq!(decoded.blue_score, 42);
+        assert_eq!(decoded.subsidy, 50_000_000);
+        assert_eq!(decoded.script_public_key.script(), script.as_slice());
+        assert_eq!(decoded.extra_data, b"0.14.1/miner");
+
+        let address = miner_address(&decoded.script_public_key, Prefix::Mainnet).unwrap();
+        assert_eq!(
+            address,
+            Address::new(Prefix::Mainnet, Version::PubKey, &[7; 32])
+        );
+    }
+
+    #[test]
+    fn test_malformed_payload_is_rejected() {
+        let payload = encode(&p2pk_script([7; 32]), b"");
+        assert!(decode_payload(&payload[..10]).is_err());
+        // script length past the end of the payload
+        assert!(decode_payload(&payload[..HEADER_LEN + 5]).is_err());
+        // decodes, but the script pays to no address
+        let garbage = decode_payload(&encode(&[0xff, 0x00, 0x13], b"")).unwrap();
+        assert!(miner_address(&garbage.script_public_key, Prefix::Mainnet).is_err());
+    }
+}
@@ -0,0 +1,1183 @@
+//! Tunables of the indexer, read from a TOML file and the environment.
+//!
+//! Precedence, lowest first: the defaults below, the file named by `KASIA_INDEXER_CONFIG`, then
+//! the `KASIA_INDEXER_*` environment variables (a `.env` file included). Every field of the file
+//! is optional, see `config.example.toml` for all of them. Empty environment variables count as
+//! unset.
+
+use crate::block_events::DEFAULT_INDEXED_BLOCKS_CAPACITY;
+use crate::block_processor::{
+    DEFAULT_BLOCK_WORKERS, DEFAULT_FLUSH_MAX_BYTES, DEFAULT_FLUSH_MAX_DELAY,
+    DEFAULT_MAX_ORPHAN_BLOCKS, DEFAULT_ORPHAN_BACKFILL_DAA_DISTANCE,
+    DEFAULT_ORPHAN_MAX_DAA_DISTANCE, DEFAULT_PENDING_SPEND_MAX_DAA_DISTANCE, FlushPolicy,
+};
+use crate::call_limiter::{
+    DEFAULT_BREAKER_COOLDOWN, DEFAULT_BREAKER_FAILURES, DEFAULT_PERMITS_PER_NODE,
+};
+use crate::database::aggregates::DEFAULT_AGGREGATE_BUCKET_WIDTH;
+use crate::database::compaction::DEFAULT_COMPACTION_MAX_LAG_DAA;
+use crate::database::headers::{DEFAULT_HEADER_CACHE_CAPACITY, HeaderStorageMode};
+use crate::database::processing::DEFAULT_TX_FILTER_FP_RATE_PPM;
+use crate::database::supply::DEFAULT_SUPPLY_TOLERANCE_SOMPI;
+use crate::database::webhooks::{
+    DEFAULT_WEBHOOK_INITIAL_BACKOFF, DEFAULT_WEBHOOK_MAX_ATTEMPTS, DEFAULT_WEBHOOK_MAX_BACKOFF,
+    DEFAULT_WEBHOOK_REQUEST_TIMEOUT,
+};
+use crate::gap_rescan::DEFAULT_MAX_GAP_SYNCERS;
+use crate::header_validation::DEFAULT_VALIDATION_DENSITY_PERCENT;
+use crate::historical_syncer::DEFAULT_INTAKE_STALL_WARNING;
+use crate::ingest_filter::{FilterChangePolicy, IngestFilter};
+use crate::mempool::{DEFAULT_MEMPOOL_POLL_INTERVAL, DEFAULT_MEMPOOL_TTL};
+use crate::node_pool::DEFAULT_HEALTH_CHECK_INTERVAL;
+use crate::periodic_processor::DEFAULT_PRUNING_DEPTH;
+use crate::profiling::DEFAULT_MAX_CPU_PROFILE;
+use crate::reorder_buffer::DEFAULT_REORDER_WINDOW;
+use crate::rpc_transport::Transport;
+use crate::scheduler;
+use crate::selected_chain_syncer::DEFAULT_MAX_CHAIN_BLOCKS_PER_STEP;
+use crate::subscriber::{
+    DEFAULT_HISTORICAL_INTAKE_CAPACITY, DEFAULT_REALTIME_INTAKE_CAPACITY,
+    DEFAULT_STALENESS_THRESHOLD, MIN_STALENESS_THRESHOLD,
+};
+use crate::supply_check::DEFAULT_SUPPLY_CHECK_INTERVAL;
+use crate::virtual_chain_processor::{
+    DEFAULT_DEEP_REORG_DEPTH, DEFAULT_UNINDEXED_ACCEPTANCE_THRESHOLD,
+};
+use anyhow::{Context, Result, bail};
+use kaspa_addresses::Prefix;
+use kaspa_consensus_core::network::NetworkId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Names the config file
+pub const CONFIG_PATH_VAR: &str = "KASIA_INDEXER_CONFIG";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexerConfig {
+    /// Defaults to `~/.kasia-indexer/{node.network}`
+    pub db_path: Option<PathBuf>,
+    /// Hot snapshots on SIGUSR1, defaults to `{db_path}-snapshots` next to the database
+    pub snapshot_dir: Option<PathBuf>,
+    /// Runs `fsck --repair` before starting
+    pub startup_fsck: bool,
+    pub node: NodeConfig,
+    pub rpc: RpcConfig,
+    pub storage: StorageConfig,
+    pub processing: ProcessingConfig,
+    pub chain: ChainConfig,
+    pub sync: SyncConfig,
+    pub maintenance: MaintenanceConfig,
+    pub telemetry: TelemetryConfig,
+    pub api: ApiConfig,
+    pub webhooks: WebhooksConfig,
+    pub profiling: ProfilingConfig,
+    pub mempool: MempoolConfig,
+    pub supply: SupplyConfig,
+    pub ingest_filter: IngestFilterConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Network id like `mainnet` or `testnet-10`, the database and the nodes must be on it
+    pub network: String,
+    /// wRPC borsh url of the node, the public resolver picks one if unset
+    pub url: Option<String>,
+    /// Additional nodes feeding added blocks
+    pub mirror_urls: Vec<String>,
+    /// Additional nodes the resolver fails over to, `grpc://` urls connect over gRPC
+    pub resolver_urls: Vec<String>,
+    pub resolver_public_node: bool,
+    pub health_interval_secs: u64,
+    /// Records the node calls and notifications into this fixture file, see [`crate::fixture`]
+    pub record_fixture: Option<PathBuf>,
+}
+
+impl NodeConfig {
+    pub fn network_id(&self) -> Result<NetworkId> {
+        NetworkId::from_str(&self.network)
+            .map_err(|err| anyhow::anyhow!("Invalid node.network {}: {err}", self.network))
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            network: "mainnet".to_string(),
+            url: None,
+            mirror_urls: Vec::new(),
+            resolver_urls: Vec::new(),
+            resolver_public_node: false,
+            health_interval_secs: DEFAULT_HEALTH_CHECK_INTERVAL.as_secs(),
+            record_fixture: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    pub permits_per_node: usize,
+    pub breaker_failures: u32,
+    pub breaker_cooldown_secs: u64,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            permits_per_node: DEFAULT_PERMITS_PER_NODE,
+            breaker_failures: DEFAULT_BREAKER_FAILURES,
+            breaker_cooldown_secs: DEFAULT_BREAKER_COOLDOWN.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub header_storage: HeaderStorageMode,
+    pub header_cache_size: usize,
+    /// Percentage of stored full headers re-hashed after a consensus crate upgrade
+    pub header_validation_density: u8,
+    pub outpoint_index: bool,
+    /// Confirmed balance per address, built on the outpoint index
+    pub address_balances: bool,
+    pub token_operations: bool,
+    pub indexed_blocks_capacity: usize,
+    /// Block, transaction, fee and address totals per DAA score bucket
+    pub aggregates: bool,
+    pub aggregate_bucket_width: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            header_storage: HeaderStorageMode::Compact,
+            header_cache_size: DEFAULT_HEADER_CACHE_CAPACITY,
+            header_validation_density: DEFAULT_VALIDATION_DENSITY_PERCENT,
+            outpoint_index: false,
+            address_balances: false,
+            token_operations: false,
+            indexed_blocks_capacity: DEFAULT_INDEXED_BLOCKS_CAPACITY,
+            aggregates: false,
+            aggregate_bucket_width: DEFAULT_AGGREGATE_BUCKET_WIDTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessingConfig {
+    pub block_workers: usize,
+    /// 1 commits every block on its own
+    pub flush_max_blocks: usize,
+    pub flush_max_bytes: usize,
+    pub flush_max_delay_ms: u64,
+    pub orphan_max_daa_distance: u64,
+    pub max_orphan_blocks: usize,
+    pub orphan_backfill_daa_distance: u64,
+    /// Inputs whose output is not indexed are dropped this far behind the sink
+    pub pending_spend_max_daa_distance: u64,
+    /// Recompute the hash of every block from the historical syncers
+    pub verify_historical_hashes: bool,
+    /// Recompute the hash of every notified block
+    pub verify_realtime_hashes: bool,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            block_workers: DEFAULT_BLOCK_WORKERS,
+            flush_max_blocks: 1,
+            flush_max_bytes: DEFAULT_FLUSH_MAX_BYTES,
+            flush_max_delay_ms: DEFAULT_FLUSH_MAX_DELAY.as_millis() as u64,
+            orphan_max_daa_distance: DEFAULT_ORPHAN_MAX_DAA_DISTANCE,
+            max_orphan_blocks: DEFAULT_MAX_ORPHAN_BLOCKS,
+            orphan_backfill_daa_distance: DEFAULT_ORPHAN_BACKFILL_DAA_DISTANCE,
+            pending_spend_max_daa_distance: DEFAULT_PENDING_SPEND_MAX_DAA_DISTANCE,
+            verify_historical_hashes: true,
+            verify_realtime_hashes: false,
+        }
+    }
+}
+
+impl ProcessingConfig {
+    pub fn flush_policy(&self) -> FlushPolicy {
+        if self.flush_max_blocks <= 1 {
+            return FlushPolicy::PER_BLOCK;
+        }
+        FlushPolicy {
+            max_blocks: self.flush_max_blocks,
+            max_bytes: self.flush_max_bytes,
+            max_delay: Duration::from_millis(self.flush_max_delay_ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainConfig {
+    pub acceptance_slo_ms: u64,
+    pub deep_reorg_depth: usize,
+    /// Finality is off when unset
+    pub finality_depth: Option<u64>,
+    pub unindexed_acceptance_threshold: u64,
+    /// Entries deeper below the virtual DAA score are pruned
+    pub pruning_depth: u64,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        Self {
+            acceptance_slo_ms: 500,
+            deep_reorg_depth: DEFAULT_DEEP_REORG_DEPTH,
+            finality_depth: None,
+            unindexed_acceptance_threshold: DEFAULT_UNINDEXED_ACCEPTANCE_THRESHOLD,
+            pruning_depth: DEFAULT_PRUNING_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    pub max_chain_blocks_per_step: usize,
+    pub max_gap_syncers: usize,
+    pub reorder_window_ms: u64,
+    pub staleness_threshold_secs: u64,
+    /// Blocks from notifications the block processor intake holds
+    pub realtime_intake_capacity: usize,
+    /// Batches from the historical syncers the block processor intake holds
+    pub historical_intake_capacity: usize,
+    /// A historical syncer waiting this long on the full intake warns, 0 never warns
+    pub intake_stall_warning_secs: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            max_chain_blocks_per_step: DEFAULT_MAX_CHAIN_BLOCKS_PER_STEP,
+            max_gap_syncers: DEFAULT_MAX_GAP_SYNCERS,
+            reorder_window_ms: DEFAULT_REORDER_WINDOW.as_millis() as u64,
+            staleness_threshold_secs: DEFAULT_STALENESS_THRESHOLD.as_secs(),
+            realtime_intake_capacity: DEFAULT_REALTIME_INTAKE_CAPACITY,
+            historical_intake_capacity: DEFAULT_HISTORICAL_INTAKE_CAPACITY,
+            intake_stall_warning_secs: DEFAULT_INTAKE_STALL_WARNING.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    pub compaction_max_lag_daa: u64,
+    /// Interval overrides of the periodic tasks in seconds, by task name
+    pub task_intervals: BTreeMap<String, u64>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            compaction_max_lag_daa: DEFAULT_COMPACTION_MAX_LAG_DAA,
+            task_intervals: BTreeMap::new(),
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    pub fn task_intervals(&self) -> Vec<(String, Duration)> {
+        self.task_intervals
+            .iter()
+            .map(|(name, secs)| (name.clone(), Duration::from_secs(*secs)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Serves metrics, health and status, off if unset
+    pub metrics_addr: Option<String>,
+    /// OTLP/HTTP traces endpoint, off if unset
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Serves the query API, off if unset. Needs the `api` feature
+    pub addr: Option<String>,
+    /// Transactions the filter of unknown transaction ids is sized for, off if 0
+    pub tx_filter_capacity: usize,
+    /// Lookups of unknown transactions passing the filter per million
+    pub tx_filter_fp_rate_ppm: u32,
+    /// Requests per second of each client address, unlimited if 0
+    pub rate_limit_per_second: u32,
+    /// Requests a client address can make at once before the rate applies
+    pub rate_limit_burst: u32,
+    /// Requests answered at once, WebSocket clients aside, unlimited if 0
+    pub max_concurrent_requests: usize,
+    /// Largest `limit` of the listings
+    pub max_limit: usize,
+    /// Widest DAA range of the block listings
+    pub max_daa_range: u64,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            tx_filter_capacity: 0,
+            tx_filter_fp_rate_ppm: DEFAULT_TX_FILTER_FP_RATE_PPM,
+            rate_limit_per_second: 20,
+            rate_limit_burst: 40,
+            max_concurrent_requests: 64,
+            max_limit: 1000,
+            max_daa_range: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    /// Delivers webhooks for payments to watched addresses, managed through the query API.
+    /// Needs the `webhooks` feature and the outpoint index
+    pub enabled: bool,
+    /// Bearer token required by the management endpoints, needed with webhooks enabled
+    pub admin_token: Option<String>,
+    /// Accepts callbacks to loopback, private and link-local hosts, e.g. receivers in the
+    /// same network
+    pub allow_private_callbacks: bool,
+    /// Attempts before a delivery is dead lettered
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt, doubled after every further one
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub request_timeout_secs: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            admin_token: None,
+            allow_private_callbacks: false,
+            max_attempts: DEFAULT_WEBHOOK_MAX_ATTEMPTS,
+            initial_backoff_secs: DEFAULT_WEBHOOK_INITIAL_BACKOFF.as_secs(),
+            max_backoff_secs: DEFAULT_WEBHOOK_MAX_BACKOFF.as_secs(),
+            request_timeout_secs: DEFAULT_WEBHOOK_REQUEST_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfilingConfig {
+    /// Serves CPU and heap profiles under `/admin/profile` of the query API. Needs the
+    /// `profiling` feature
+    pub enabled: bool,
+    /// Bearer token required by the profile endpoints, needed with profiling enabled
+    pub admin_token: Option<String>,
+    /// Longest CPU profile a request may ask for
+    pub max_cpu_secs: u64,
+}
+
+impl Default for ProfilingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            admin_token: None,
+            max_cpu_secs: DEFAULT_MAX_CPU_PROFILE.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    /// Tracks the pending transactions of the node mempool, reported by the query API
+    pub enabled: bool,
+    pub poll_interval_ms: u64,
+    /// Pending transactions not indexed within this are dropped as evicted
+    pub ttl_secs: u64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval_ms: DEFAULT_MEMPOOL_POLL_INTERVAL.as_millis() as u64,
+            ttl_secs: DEFAULT_MEMPOOL_TTL.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupplyConfig {
+    /// Tracks the coinbase rewards and the circulating supply of accepted blocks
+    pub enabled: bool,
+    /// Interval of the cross-check against the node supply
+    pub check_interval_secs: u64,
+    /// Divergence from the node supply reported as alarm
+    pub tolerance_sompi: u64,
+}
+
+impl Default for SupplyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_interval_secs: DEFAULT_SUPPLY_CHECK_INTERVAL.as_secs(),
+            tolerance_sompi: DEFAULT_SUPPLY_TOLERANCE_SOMPI,
+        }
+    }
+}
+
+/// Data left out of the index, see [`crate::ingest_filter`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestFilterConfig {
+    /// Only transactions touching these addresses are indexed, all if empty
+    pub addresses: Vec<String>,
+    /// Only the transactions of selected chain blocks are indexed
+    pub chain_blocks_only: bool,
+    /// Messages are stored without their sealed payload
+    pub skip_payloads: bool,
+    /// On a filter admitting data the recorded one left out: fail, accept or replay
+    pub on_change: FilterChangePolicy,
+}
+
+#[cfg(feature = "webhooks")]
+impl WebhooksConfig {
+    pub fn retry_policy(&self) -> crate::webhooks::RetryPolicy {
+        crate::webhooks::RetryPolicy {
+            max_attempts: self.max_attempts,
+            initial_backoff: Duration::from_secs(self.initial_backoff_secs),
+            max_backoff: Duration::from_secs(self.max_backoff_secs),
+            request_timeout: Duration::from_secs(self.request_timeout_secs),
+        }
+    }
+}
+
+impl IndexerConfig {
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        Self::from_toml(&toml).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    /// The file at `path` or the defaults, overridden by the environment and validated
+    pub fn resolve(path: Option<&Path>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides the fields set in the environment, `var` looks up a variable by name
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+        let env = Env(&var);
+
+        if let Some(path) = var("KASIA_INDEXER_DB_PATH") {
+            self.db_path = Some(path.into());
+        }
+        if let Some(path) = var("KASIA_INDEXER_SNAPSHOT_DIR") {
+            self.snapshot_dir = Some(path.into());
+        }
+        env.flag("KASIA_INDEXER_STARTUP_FSCK", &mut self.startup_fsck);
+
+        let node = &mut self.node;
+        env.value("KASIA_INDEXER_NETWORK", &mut node.network)?;
+        env.optional("KASPA_NODE_WBORSH_URL", &mut node.url)?;
+        env.list("KASIA_INDEXER_MIRROR_NODE_URLS", &mut node.mirror_urls);
+        env.list("KASIA_INDEXER_RESOLVER_NODE_URLS", &mut node.resolver_urls);
+        env.flag(
+            "KASIA_INDEXER_RESOLVER_PUBLIC_NODE",
+            &mut node.resolver_public_node,
+        );
+        env.value(
+            "KASIA_INDEXER_NODE_HEALTH_INTERVAL_SECS",
+            &mut node.health_interval_secs,
+        )?;
+        env.optional("KASIA_INDEXER_RECORD_FIXTURE", &mut node.record_fixture)?;
+
+        let rpc = &mut self.rpc;
+        env.value(
+            "KASIA_INDEXER_RPC_PERMITS_PER_NODE",
+            &mut rpc.permits_per_node,
+        )?;
+        env.value(
+            "KASIA_INDEXER_RPC_BREAKER_FAILURES",
+            &mut rpc.breaker_failures,
+        )?;
+        env.value(
+            "KASIA_INDEXER_RPC_BREAKER_COOLDOWN_SECS",
+            &mut rpc.breaker_cooldown_secs,
+        )?;
+
+        let storage = &mut self.storage;
+        match var("KASIA_INDEXER_HEADER_STORAGE").as_deref() {
+            None => {}
+            Some("compact") => storage.header_storage = HeaderStorageMode::Compact,
+            Some("full") => storage.header_storage = HeaderStorageMode::Full,
+            Some(other) => {
+                bail!("KASIA_INDEXER_HEADER_STORAGE must be compact or full, got {other}")
+            }
+        }
+        env.value(
+            "KASIA_INDEXER_HEADER_CACHE_SIZE",
+            &mut storage.header_cache_size,
+        )?;
+        env.value(
+            "KASIA_INDEXER_HEADER_VALIDATION_DENSITY",
+            &mut storage.header_validation_density,
+        )?;
+        env.flag("KASIA_INDEXER_OUTPOINT_INDEX", &mut storage.outpoint_index);
+        env.flag(
+            "KASIA_INDEXER_ADDRESS_BALANCES",
+            &mut storage.address_balances,
+        );
+        env.flag(
+            "KASIA_INDEXER_TOKEN_OPERATIONS",
+            &mut storage.token_operations,
+        );
+        env.value(
+            "KASIA_INDEXER_INDEXED_BLOCKS_CAPACITY",
+            &mut storage.indexed_blocks_capacity,
+        )?;
+        env.flag("KASIA_INDEXER_AGGREGATES", &mut storage.aggregates);
+        env.value(
+            "KASIA_INDEXER_AGGREGATE_BUCKET_WIDTH",
+            &mut storage.aggregate_bucket_width,
+        )?;
+
+        let processing = &mut self.processing;
+        env.value("KASIA_INDEXER_BLOCK_WORKERS", &mut processing.block_workers)?;
+        env.value(
+            "KASIA_INDEXER_FLUSH_MAX_BLOCKS",
+            &mut processing.flush_max_blocks,
+        )?;
+        env.value(
+            "KASIA_INDEXER_FLUSH_MAX_BYTES",
+            &mut processing.flush_max_bytes,
+        )?;
+        env.value(
+            "KASIA_INDEXER_FLUSH_MAX_DELAY_MS",
+            &mut processing.flush_max_delay_ms,
+        )?;
+        env.value(
+            "KASIA_INDEXER_ORPHAN_MAX_DAA_DISTANCE",
+            &mut processing.orphan_max_daa_distance,
+        )?;
+        env.value(
+            "KASIA_INDEXER_MAX_ORPHAN_BLOCKS",
+            &mut processing.max_orphan_blocks,
+        )?;
+        env.value(
+            "KASIA_INDEXER_ORPHAN_BACKFILL_DAA_DISTANCE",
+            &mut processing.orphan_backfill_daa_distance,
+        )?;
+        env.value(
+            "KASIA_INDEXER_PENDING_SPEND_MAX_DAA_DISTANCE",
+            &mut processing.pending_spend_max_daa_distance,
+        )?;
+        env.flag(
+            "KASIA_INDEXER_VERIFY_HISTORICAL_HASHES",
+            &mut processing.verify_historical_hashes,
+        );
+        env.flag(
+            "KASIA_INDEXER_VERIFY_REALTIME_HASHES",
+            &mut processing.verify_realtime_hashes,
+        );
+
+        let chain = &mut self.chain;
+        env.value(
+            "KASIA_INDEXER_ACCEPTANCE_SLO_MS",
+            &mut chain.acceptance_slo_ms,
+        )?;
+        env.value(
+            "KASIA_INDEXER_DEEP_REORG_DEPTH",
+            &mut chain.deep_reorg_depth,
+        )?;
+        env.optional("KASIA_INDEXER_FINALITY_DEPTH", &mut chain.finality_depth)?;
+        env.value(
+            "KASIA_INDEXER_UNINDEXED_ACCEPTANCE_THRESHOLD",
+            &mut chain.unindexed_acceptance_threshold,
+        )?;
+        env.value("KASIA_INDEXER_PRUNING_DEPTH", &mut chain.pruning_depth)?;
+
+        let sync = &mut self.sync;
+        env.value(
+            "KASIA_INDEXER_MAX_CHAIN_BLOCKS_PER_STEP",
+            &mut sync.max_chain_blocks_per_step,
+        )?;
+        env.value("KASIA_INDEXER_MAX_GAP_SYNCERS", &mut sync.max_gap_syncers)?;
+        env.value(
+            "KASIA_INDEXER_REORDER_WINDOW_MS",
+            &mut sync.reorder_window_ms,
+        )?;
+        env.value(
+            "KASIA_INDEXER_STALENESS_THRESHOLD_SECS",
+            &mut sync.staleness_threshold_secs,
+        )?;
+        env.value(
+            "KASIA_INDEXER_REALTIME_INTAKE_CAPACITY",
+            &mut sync.realtime_intake_capacity,
+        )?;
+        env.value(
+            "KASIA_INDEXER_HISTORICAL_INTAKE_CAPACITY",
+            &mut sync.historical_intake_capacity,
+        )?;
+        env.value(
+            "KASIA_INDEXER_INTAKE_STALL_WARNING_SECS",
+            &mut sync.intake_stall_warning_secs,
+        )?;
+
+        let maintenance = &mut self.maintenance;
+        env.value(
+            "KASIA_INDEXER_COMPACTION_MAX_LAG_DAA",
+            &mut maintenance.compaction_max_lag_daa,
+        )?;
+        // extends the intervals of the file, a task named in both gets the one set here
+        if let Some(intervals) = var("KASIA_INDEXER_TASK_INTERVALS") {
+            for (name, interval) in scheduler::parse_intervals(&intervals)? {
+                maintenance.task_intervals.insert(name, interval.as_secs());
+            }
+        }
+
+        let telemetry = &mut self.telemetry;
+        env.optional("KASIA_INDEXER_METRICS_ADDR", &mut telemetry.metrics_addr)?;
+        env.optional("KASIA_INDEXER_OTLP_ENDPOINT", &mut telemetry.otlp_endpoint)?;
+
+        let api = &mut self.api;
+        env.optional("KASIA_INDEXER_API_ADDR", &mut api.addr)?;
+        env.value(
+            "KASIA_INDEXER_API_TX_FILTER_CAPACITY",
+            &mut api.tx_filter_capacity,
+        )?;
+        env.value(
+            "KASIA_INDEXER_API_TX_FILTER_FP_RATE_PPM",
+            &mut api.tx_filter_fp_rate_ppm,
+        )?;
+        env.value(
+            "KASIA_INDEXER_API_RATE_LIMIT_PER_SECOND",
+            &mut api.rate_limit_per_second,
+        )?;
+        env.value(
+            "KASIA_INDEXER_API_RATE_LIMIT_BURST",
+            &mut api.rate_limit_burst,
+        )?;
+        env.value(
+            "KASIA_INDEXER_API_MAX_CONCURRENT_REQUESTS",
+            &mut api.max_concurrent_requests,
+        )?;
+        env.value("KASIA_INDEXER_API_MAX_LIMIT", &mut api.max_limit)?;
+        env.value("KASIA_INDEXER_API_MAX_DAA_RANGE", &mut api.max_daa_range)?;
+
+        let webhooks = &mut self.webhooks;
+        env.flag("KASIA_INDEXER_WEBHOOKS", &mut webhooks.enabled);
+        env.optional(
+            "KASIA_INDEXER_WEBHOOKS_ADMIN_TOKEN",
+            &mut webhooks.admin_token,
+        )?;
+        env.flag(
+            "KASIA_INDEXER_WEBHOOKS_ALLOW_PRIVATE_CALLBACKS",
+            &mut webhooks.allow_private_callbacks,
+        );
+        env.value(
+            "KASIA_INDEXER_WEBHOOKS_MAX_ATTEMPTS",
+            &mut webhooks.max_attempts,
+        )?;
+        env.value(
+            "KASIA_INDEXER_WEBHOOKS_INITIAL_BACKOFF_SECS",
+            &mut webhooks.initial_backoff_secs,
+        )?;
+        env.value(
+            "KASIA_INDEXER_WEBHOOKS_MAX_BACKOFF_SECS",
+            &mut webhooks.max_backoff_secs,
+        )?;
+        env.value(
+            "KASIA_INDEXER_WEBHOOKS_REQUEST_TIMEOUT_SECS",
+            &mut webhooks.request_timeout_secs,
+        )?;
+
+        let profiling = &mut self.profiling;
+        env.flag("KASIA_INDEXER_PROFILING", &mut profiling.enabled);
+        env.optional(
+            "KASIA_INDEXER_PROFILING_ADMIN_TOKEN",
+            &mut profiling.admin_token,
+        )?;
+        env.value(
+            "KASIA_INDEXER_PROFILING_MAX_CPU_SECS",
+            &mut profiling.max_cpu_secs,
+        )?;
+
+        let mempool = &mut self.mempool;
+        env.flag("KASIA_INDEXER_MEMPOOL", &mut mempool.enabled);
+        env.value(
+            "KASIA_INDEXER_MEMPOOL_POLL_INTERVAL_MS",
+            &mut mempool.poll_interval_ms,
+        )?;
+        env.value("KASIA_INDEXER_MEMPOOL_TTL_SECS", &mut mempool.ttl_secs)?;
+
+        let supply = &mut self.supply;
+        env.flag("KASIA_INDEXER_SUPPLY", &mut supply.enabled);
+        env.value(
+            "KASIA_INDEXER_SUPPLY_CHECK_INTERVAL_SECS",
+            &mut supply.check_interval_secs,
+        )?;
+        env.value(
+            "KASIA_INDEXER_SUPPLY_TOLERANCE_SOMPI",
+            &mut supply.tolerance_sompi,
+        )?;
+
+        let ingest_filter = &mut self.ingest_filter;
+        env.list(
+            "KASIA_INDEXER_INGEST_ADDRESSES",
+            &mut ingest_filter.addresses,
+        );
+        env.flag(
+            "KASIA_INDEXER_INGEST_CHAIN_BLOCKS_ONLY",
+            &mut ingest_filter.chain_blocks_only,
+        );
+        env.flag(
+            "KASIA_INDEXER_INGEST_SKIP_PAYLOADS",
+            &mut ingest_filter.skip_payloads,
+        );
+        match var("KASIA_INDEXER_INGEST_FILTER_ON_CHANGE").as_deref() {
+            None => {}
+            Some("fail") => ingest_filter.on_change = FilterChangePolicy::Fail,
+            Some("accept") => ingest_filter.on_change = FilterChangePolicy::Accept,
+            Some("replay") => ingest_filter.on_change = FilterChangePolicy::Replay,
+            Some(other) => bail!(
+                "KASIA_INDEXER_INGEST_FILTER_ON_CHANGE must be fail, accept or replay, got {other}"
+            ),
+        }
+        Ok(())
+    }
+
+    /// Fails with every problem found
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if let Some(finality_depth) = self.chain.finality_depth
+            && finality_depth > self.chain.pruning_depth
+        {
+            problems.push(format!(
+                "chain.finality_depth {finality_depth} exceeds chain.pruning_depth {}, transactions would be pruned before becoming final",
+                self.chain.pruning_depth
+            ));
+        }
+        if let Some(snapshot_dir) = &self.snapshot_dir
+            && snapshot_dir.starts_with(self.db_path())
+        {
+            problems.push(format!(
+                "snapshot_dir {} is inside db_path, snapshots would be counted and removed with the database",
+                snapshot_dir.display()
+            ));
+        }
+        if self.storage.address_balances && !self.storage.outpoint_index {
+            problems.push("storage.address_balances requires storage.outpoint_index".to_string());
+        }
+        if self.chain.pruning_depth == 0 {
+            problems.push("chain.pruning_depth must be positive".to_string());
+        }
+        if self.storage.aggregate_bucket_width == 0 {
+            problems.push("storage.aggregate_bucket_width must be positive".to_string());
+        }
+        if self.storage.header_validation_density > 100 {
+            problems.push(format!(
+                "storage.header_validation_density is a percentage, got {}",
+                self.storage.header_validation_density
+            ));
+        }
+        for (name, value) in [
+            ("processing.block_workers", self.processing.block_workers),
+            (
+                "processing.flush_max_blocks",
+                self.processing.flush_max_blocks,
+            ),
+            ("rpc.permits_per_node", self.rpc.permits_per_node),
+            (
+                "sync.max_chain_blocks_per_step",
+                self.sync.max_chain_blocks_per_step,
+            ),
+            (
+                "sync.realtime_intake_capacity",
+                self.sync.realtime_intake_capacity,
+            ),
+            (
+                "sync.historical_intake_capacity",
+                self.sync.historical_intake_capacity,
+            ),
+        ] {
+            if value == 0 {
+                problems.push(format!("{name} must be positive"));
+            }
+        }
+        if self.sync.staleness_threshold_secs < MIN_STALENESS_THRESHOLD.as_secs() {
+            problems.push(format!(
+                "sync.staleness_threshold_secs must be at least {}, got {}",
+                MIN_STALENESS_THRESHOLD.as_secs(),
+                self.sync.staleness_threshold_secs
+            ));
+        }
+        for (name, value) in [
+            ("mempool.poll_interval_ms", self.mempool.poll_interval_ms),
+            ("mempool.ttl_secs", self.mempool.ttl_secs),
+            (
+                "supply.check_interval_secs",
+                self.supply.check_interval_secs,
+            ),
+        ] {
+            if value == 0 {
+                problems.push(format!("{name} must be positive"));
+            }
+        }
+        match self.node.network_id() {
+            Ok(network_id) => {
+                let prefix = Prefix::from(network_id);
+                if let Err(err) = IngestFilter::from_config(&self.ingest_filter, prefix) {
+                    problems.push(err.to_string());
+                }
+            }
+            Err(err) => problems.push(err.to_string()),
+        }
+        if self
+            .node
+            .url
+            .as_deref()
+            .is_some_and(|url| Transport::from_url(url) == Transport::Grpc)
+        {
+            problems.push(
+                "node.url must be a wRPC url, gRPC nodes are supported as resolver nodes only"
+                    .to_string(),
+            );
+        }
+        for url in &self.node.mirror_urls {
+            if Transport::from_url(url) == Transport::Grpc {
+                problems.push(format!("node.mirror_urls must be wRPC urls, got {url}"));
+            }
+        }
+        for (name, secs) in &self.maintenance.task_intervals {
+            if *secs == 0 {
+                problems.push(format!(
+                    "maintenance.task_intervals.{name} must be positive"
+                ));
+            }
+        }
+        if self.api.addr.is_some() && !cfg!(feature = "api") {
+            problems.push(
+                "api.addr is set but the indexer was built without the api feature".to_string(),
+            );
+        }
+        if !(1..1_000_000).contains(&self.api.tx_filter_fp_rate_ppm) {
+            problems.push("api.tx_filter_fp_rate_ppm must be between 1 and 999999".to_string());
+        }
+        if self.api.rate_limit_per_second > 0 && self.api.rate_limit_burst == 0 {
+            problems.push(
+                "api.rate_limit_burst must be positive while api.rate_limit_per_second is set"
+                    .to_string(),
+            );
+        }
+        if self.api.max_limit == 0 {
+            problems.push("api.max_limit must be positive".to_string());
+        }
+        if self.api.max_daa_range == 0 {
+            problems.push("api.max_daa_range must be positive".to_string());
+        }
+        if self.webhooks.enabled {
+            if !cfg!(feature = "webhooks") {
+                problems.push(
+                    "webhooks.enabled is set but the indexer was built without the webhooks feature"
+                        .to_string(),
+                );
+            }
+            if !self.storage.outpoint_index {
+                problems.push("webhooks.enabled requires storage.outpoint_index".to_string());
+            }
+            if self
+                .webhooks
+                .admin_token
+                .as_deref()
+                .is_none_or(str::is_empty)
+            {
+                problems.push("webhooks.enabled requires webhooks.admin_token".to_string());
+            }
+            if self.api.addr.is_none() {
+                problems.push(
+                    "webhooks.enabled requires api.addr, subscriptions are managed through the query API"
+                        .to_string(),
+                );
+            }
+        }
+        if self.webhooks.max_attempts == 0 {
+            problems.push("webhooks.max_attempts must be positive".to_string());
+        }
+        if self.webhooks.request_timeout_secs == 0 {
+            problems.push("webhooks.request_timeout_secs must be positive".to_string());
+        }
+        if self.profiling.enabled {
+            if !cfg!(feature = "profiling") {
+                problems.push(
+                    "profiling.enabled is set but the indexer was built without the profiling feature"
+                        .to_string(),
+                );
+            }
+            if self
+                .profiling
+                .admin_token
+                .as_deref()
+                .is_none_or(str::is_empty)
+            {
+                problems.push("profiling.enabled requires profiling.admin_token".to_string());
+            }
+            if self.api.addr.is_none() {
+                problems.push(
+                    "profiling.enabled requires api.addr, profiles are served by the query API"
+                        .to_string(),
+                );
+            }
+        }
+        if self.profiling.max_cpu_secs == 0 {
+            problems.push("profiling.max_cpu_secs must be positive".to_string());
+        }
+        if !problems.is_empty() {
+            bail!("Invalid configuration:\n  {}", problems.join("\n  "));
+        }
+        Ok(())
+    }
+
+    /// Every network gets its own directory unless the path is set
+    pub fn db_path(&self) -> PathBuf {
+        self.db_path.clone().unwrap_or_else(|| {
+            std::env::home_dir()
+                .unwrap()
+                .join(".kasia-indexer")
+                .join(&self.node.network)
+        })
+    }
+
+    /// Outside of the database directory unless set
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.snapshot_dir.clone().unwrap_or_else(|| {
+            let db_path = self.db_path();
+            let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+            name.push("-snapshots");
+            db_path.with_file_name(name)
+        })
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+/// Typed lookups of the environment overrides
+struct Env<'a>(&'a dyn Fn(&str) -> Option<String>);
+
+impl Env<'_> {
+    fn value<T: FromStr>(&self, name: &str, target: &mut T) -> Result<()>
+    where
+        T::Err: Display,
+    {
+        if let Some(value) = (self.0)(name) {
+            *target = parse(name, &value)?;
+        }
+        Ok(())
+    }
+
+    fn optional<T: FromStr>(&self, name: &str, target: &mut Option<T>) -> Result<()>
+    where
+        T::Err: Display,
+    {
+        if let Some(value) = (self.0)(name) {
+            *target = Some(parse(name, &value)?);
+        }
+        Ok(())
+    }
+
+    /// `1` and `true` enable, anything else disables
+    fn flag(&self, name: &str, target: &mut bool) {
+        if let Some(value) = (self.0)(name) {
+            *target = value == "1" || value == "true";
+        }
+    }
+
+    /// Comma separated
+    fn list(&self, name: &str, target: &mut Vec<String>) {
+        if let Some(value) = (self.0)(name) {
+            *target = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect();
+        }
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| anyhow::anyhow!("invalid {name} {value:?}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_addresses::{Address, Version};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_defaults_match_example_and_roundtrip() {
+        let example = IndexerConfig::from_toml(include_str!("../../config.example.toml")).unwrap();
+        assert_eq!(example, IndexerConfig::default());
+        let config = IndexerConfig::default();
+        assert_eq!(
+            IndexerConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        assert_eq!(config.processing.flush_policy(), FlushPolicy::PER_BLOCK);
+        config.validate().unwrap();
+        assert!(IndexerConfig::from_toml("[sync]\nmax_gap_syncer = 2").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let mut config = IndexerConfig::from_toml(
+            r#"
+            [processing]
+            flush_max_blocks = 64
+            flush_max_delay_ms = 250
+
+            [chain]
+            finality_depth = 100
+
+            [maintenance.task_intervals]
+            database_stats = 60
+            gap_rescan = 120
+            "#,
+        )
+        .unwrap();
+        let env = HashMap::from([
+            ("KASIA_INDEXER_FINALITY_DEPTH", "200"),
+            ("KASIA_INDEXER_TASK_INTERVALS", "gap_rescan=30"),
+            ("KASIA_INDEXER_OUTPOINT_INDEX", "true"),
+            ("KASIA_INDEXER_HEADER_STORAGE", "full"),
+            (
+                "KASIA_INDEXER_RESOLVER_NODE_URLS",
+                "grpc://a:16110, ws://b:17110,",
+            ),
+            ("KASIA_INDEXER_METRICS_ADDR", ""),
+            ("KASIA_INDEXER_NETWORK", "testnet-10"),
+            ("KASIA_INDEXER_INGEST_CHAIN_BLOCKS_ONLY", "1"),
+            ("KASIA_INDEXER_INGEST_FILTER_ON_CHANGE", "replay"),
+        ]);
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.chain.finality_depth, Some(200));
+        assert_eq!(
+            config.maintenance.task_intervals(),
+            vec![
+                ("database_stats".to_string(), Duration::from_secs(60)),
+                ("gap_rescan".to_string(), Duration::from_secs(30)),
+            ]
+        );
+        assert!(config.storage.outpoint_index);
+        assert!(config.db_path().ends_with(".kasia-indexer/testnet-10"));
+        assert!(
+            config
+                .snapshot_dir()
+                .ends_with(".kasia-indexer/testnet-10-snapshots")
+        );
+        assert_eq!(config.storage.header_storage, HeaderStorageMode::Full);
+        assert_eq!(
+            config.node.resolver_urls,
+            vec!["grpc://a:16110", "ws://b:17110"]
+        );
+        assert_eq!(config.telemetry.metrics_addr, None);
+        assert!(config.ingest_filter.chain_blocks_only);
+        assert_eq!(config.ingest_filter.on_change, FilterChangePolicy::Replay);
+        let policy = config.processing.flush_policy();
+        assert_eq!(policy.max_blocks, 64);
+        assert_eq!(policy.max_bytes, DEFAULT_FLUSH_MAX_BYTES);
+        assert_eq!(policy.max_delay, Duration::from_millis(250));
+
+        let invalid = HashMap::from([("KASIA_INDEXER_MAX_GAP_SYNCERS", "many")]);
+        let err = config
+            .apply_env(|name| invalid.get(name).map(|value| value.to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("KASIA_INDEXER_MAX_GAP_SYNCERS"));
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let mut config = IndexerConfig::default();
+        config.chain.pruning_depth = 100;
+        config.chain.finality_depth = Some(100);
+        config.validate().unwrap();
+
+        config.chain.finality_depth = Some(101);
+        config.storage.header_validation_density = 101;
+        config.processing.block_workers = 0;
+        config.node.url = Some("grpc://localhost:16110".to_string());
+        config.storage.address_balances = true;
+        config.node.network = "moonnet-1".to_string();
+        config.webhooks.enabled = true;
+        config.webhooks.max_attempts = 0;
+        config.profiling.enabled = true;
+        config.profiling.max_cpu_secs = 0;
+        config.api.max_daa_range = 0;
+        config.sync.staleness_threshold_secs = 2;
+        config.snapshot_dir = Some(config.db_path().join("snapshots"));
+        let err = config.validate().unwrap_err().to_string();
+        for field in [
+            "node.network",
+            "chain.finality_depth",
+            "storage.header_validation_density",
+            "storage.address_balances",
+            "processing.block_workers",
+            "node.url",
+            "webhooks.enabled requires storage.outpoint_index",
+            "webhooks.enabled requires api.addr",
+            "webhooks.enabled requires webhooks.admin_token",
+            "webhooks.max_attempts",
+            "profiling.enabled requires api.addr",
+            "profiling.enabled requires profiling.admin_token",
+            "profiling.max_cpu_secs",
+            "api.max_daa_range",
+            "sync.staleness_threshold_secs",
+            "snapshot_dir",
+        ] {
+            assert!(err.contains(field), "{field} missing from {err}");
+        }
+
+        let mut config = IndexerConfig::default();
+        let address = Address::new(Prefix::Mainnet, Version::PubKey, &[7; 32]);
+        config.ingest_filter.addresses = vec![address.to_string()];
+        config.validate().unwrap();
+        config.node.network = "testnet-10".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("ingest_filter.addresses"), "{err}");
+    }
+}
@@ -0,0 +1,103 @@
+use crate::database::crash_reports::CrashReportsPartition;
+use crate::metrics::SharedMetrics;
+use crate::shutdown::Shutdown;
+use std::backtrace::Backtrace;
+use std::fmt::Write;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory inside the data directory receiving crash files
+pub const CRASH_DIR: &str = "crash_reports";
+
+static CONTEXT: OnceLock<CrashContext> = OnceLock::new();
+/// Set while a report is being captured, a panic during capture skips capturing
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// State captured into crash reports
+pub struct CrashContext {
+    pub data_dir: PathBuf,
+    pub partition: Option<CrashReportsPartition>,
+    pub metrics: Option<SharedMetrics>,
+    /// Cancelled once the indexer shuts down, see [`crate::indexer::Indexer::shutdown_signal`]
+    pub shutdown: Option<Shutdown>,
+}
+
+/// Installs a panic hook writing a crash report before the previous hook runs.
+/// Only the first call has an effect
+pub fn install(context: CrashContext) {
+    if CONTEXT.set(context).is_err() {
+        return;
+    }
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        capture(|| panic_report(info));
+        previous(info);
+    }));
+}
+
+/// Records a component giving up with an error, the same way panics are recorded
+pub fn report_error(component: &str, err: &anyhow::Error) {
+    capture(|| {
+        let mut report = format!("{component} stopped with error: {err}\n");
+        _ = writeln!(report, "{err:?}");
+        report
+    });
+}
+
+/// Best effort, failures are printed to stderr only since logging may be what crashed
+fn capture(report: impl FnOnce() -> String) {
+    let Some(context) = CONTEXT.get() else {
+        return;
+    };
+    if CAPTURING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let recorded_at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut report = report();
+    append_state(&mut report, context);
+
+    let dir = context.data_dir.join(CRASH_DIR);
+    let file = dir.join(format!("crash-{recorded_at_ms}.txt"));
+    if let Err(err) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&file, &report)) {
+        eprintln!("Failed to write crash file {}: {err}", file.display());
+    }
+    if let Some(partition) = &context.partition
+        && let Err(err) = partition.insert(recorded_at_ms, &report)
+    {
+        eprintln!("Failed to store crash report: {err}");
+    }
+    CAPTURING.store(false, Ordering::Release);
+}
+
+fn panic_report(info: &PanicHookInfo) -> String {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>");
+    let thread = std::thread::current();
+    let mut report = format!(
+        "thread '{}' panicked at {}: {message}\n",
+        thread.name().unwrap_or("<unnamed>"),
+        info.location()
+            .map_or_else(|| "<unknown>".to_string(), ToString::to_string),
+    );
+    _ = writeln!(report, "\nBacktrace:\n{}", Backtrace::force_capture());
+    report
+}
+
+fn append_state(report: &mut String, context: &CrashContext) {
+    if let Some(shutdown) = &context.shutdown {
+        _ = writeln!(report, "\nApp running: {}", !shutdown.is_cancelled());
+    }
+    if let Some(metrics) = &context.metrics {
+        _ = writeln!(report, "\n{}", metrics.snapshot());
+    }
+}
@@ -11,9 +11,28 @@ pub mod messages;
 pub mod processing;
 
 // Standalone modules
+pub mod aggregates;
+pub mod balances;
+pub mod block_stats;
+pub mod block_transactions;
+pub mod compaction;
+pub mod confirmations;
+pub mod crash_reports;
+pub mod difftest;
+pub mod export;
+pub mod integrity;
 pub mod metadata;
+pub mod miners;
+pub mod provenance;
 pub mod resolution_keys;
+pub mod schema;
+pub mod script_class;
+pub mod snapshot;
+pub mod stats;
+pub mod supply;
+pub mod token_operations;
 pub mod util;
+pub mod webhooks;
 
 /// Database partition identifiers.
 ///
@@ -51,8 +70,29 @@ pub enum PartitionId {
     TxIdToPayment,
 }
 
-use fjall::UserValue;
+use crate::error::IndexerResult;
+use fjall::{Config, TxKeyspace, UserValue};
 use std::marker::PhantomData;
+use std::path::Path;
+
+/// Write buffer of the keyspace, shared by all partitions
+pub const MAX_WRITE_BUFFER_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Opens the keyspace at `path`, created when missing
+pub fn open(path: &Path) -> IndexerResult<TxKeyspace> {
+    Ok(Config::new(path)
+        .max_write_buffer_size(MAX_WRITE_BUFFER_SIZE)
+        .open_transactional()?)
+}
+
+/// Temporary keyspace of a test, removed once dropped. `name` keeps concurrent tests apart
+#[cfg(test)]
+pub fn test_keyspace(name: &str) -> TxKeyspace {
+    Config::new(std::env::temp_dir().join(format!("kasia-indexer-{name}-{}", std::process::id())))
+        .temporary(true)
+        .open_transactional()
+        .unwrap()
+}
 
 /// Byte view wrapper for transaction ID arrays, optimized for fjall storage.
 ///
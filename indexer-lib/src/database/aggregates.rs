@@ -0,0 +1,811 @@
+use crate::database::block_stats::BlockStats;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use crate::database::script_class::{ScriptClassCounts, script_class};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, TxKeyspace, WriteTransaction};
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_COINBASE;
+use kaspa_consensus_core::tx::ScriptPublicKey;
+use kaspa_rpc_core::{RpcBlock, RpcHash};
+use std::ops::Range;
+
+/// About one day of blocks at 10 blocks per second
+pub const DEFAULT_AGGREGATE_BUCKET_WIDTH: u64 = 864_000;
+const HLL_PRECISION: u32 = 10;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+const COUNTERS_LEN: usize = 5 * 8;
+/// Values written before the script classes were counted
+const LEGACY_VALUE_LEN: usize = COUNTERS_LEN + HLL_REGISTERS;
+const VALUE_LEN: usize = LEGACY_VALUE_LEN + ScriptClassCounts::ENCODED_LEN;
+
+/// HyperLogLog sketch estimating distinct items within about 3%. Registers only grow, items
+/// can't be removed
+#[derive(Clone, PartialEq, Eq)]
+pub struct HyperLogLog(Box<[u8; HLL_REGISTERS]>);
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self(Box::new([0; HLL_REGISTERS]))
+    }
+}
+
+impl std::fmt::Debug for HyperLogLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HyperLogLog({})", self.estimate())
+    }
+}
+
+impl HyperLogLog {
+    pub fn insert(&mut self, item: &[u8]) {
+        let hash = hash64(item);
+        let register = (hash >> (64 - HLL_PRECISION)) as usize;
+        // the guard bit bounds the rank when the remaining bits are zero
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() + 1;
+        self.0[register] = self.0[register].max(rank as u8);
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .0
+            .iter()
+            .map(|rank| 2f64.powi(-(*rank as i32)))
+            .sum::<f64>();
+        let raw = alpha * m * m / sum;
+        let zeros = self.0.iter().filter(|rank| **rank == 0).count();
+        let estimate = match raw <= 2.5 * m && zeros > 0 {
+            // linear counting is more precise while registers are empty
+            true => m * (m / zeros as f64).ln(),
+            false => raw,
+        };
+        estimate.round() as u64
+    }
+}
+
+/// FNV-1a with a splitmix64 finalizer, stable across releases as sketches are stored
+fn hash64(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Totals of the blocks whose DAA score falls in a bucket
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BucketAggregates {
+    /// Covers DAA scores from `bucket * width` up to the next bucket
+    pub bucket: u64,
+    pub block_count: u64,
+    pub tx_count: u64,
+    /// Transactions accepted by chain blocks of the bucket
+    pub accepted_tx_count: u64,
+    /// Fees known from the outpoint index, see [`BlockStats::total_fees`]
+    pub fee_sum: u64,
+    /// Outputs of the coinbase transactions
+    pub coinbase_sum: u64,
+    /// Addresses receiving outputs
+    pub addresses: HyperLogLog,
+    /// Outputs by script class
+    pub script_classes: ScriptClassCounts,
+}
+
+impl BucketAggregates {
+    pub fn distinct_addresses(&self) -> u64 {
+        self.addresses.estimate()
+    }
+
+    fn encode(&self) -> [u8; VALUE_LEN] {
+        let mut value = [0u8; VALUE_LEN];
+        for (i, counter) in [
+            self.block_count,
+            self.tx_count,
+            self.accepted_tx_count,
+            self.fee_sum,
+            self.coinbase_sum,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            value[i * 8..i * 8 + 8].copy_from_slice(&counter.to_be_bytes());
+        }
+        value[COUNTERS_LEN..LEGACY_VALUE_LEN].copy_from_slice(&self.addresses.0[..]);
+        value[LEGACY_VALUE_LEN..].copy_from_slice(&self.script_classes.encode());
+        value
+    }
+
+    fn decode(bucket: u64, value: &[u8]) -> Result<Self> {
+        if value.len() != VALUE_LEN && value.len() != LEGACY_VALUE_LEN {
+            bail!("Invalid aggregates length");
+        }
+        let counter = |i: usize| -> Result<u64> {
+            Ok(u64::from_be_bytes(value[i * 8..i * 8 + 8].try_into()?))
+        };
+        Ok(Self {
+            bucket,
+            block_count: counter(0)?,
+            tx_count: counter(1)?,
+            accepted_tx_count: counter(2)?,
+            fee_sum: counter(3)?,
+            coinbase_sum: counter(4)?,
+            addresses: HyperLogLog(Box::new(value[COUNTERS_LEN..LEGACY_VALUE_LEN].try_into()?)),
+            script_classes: match value.len() {
+                VALUE_LEN => ScriptClassCounts::decode(&value[LEGACY_VALUE_LEN..])?,
+                _ => ScriptClassCounts::default(),
+            },
+        })
+    }
+}
+
+/// Partition keeping [`BucketAggregates`] per DAA score bucket.
+///
+/// **Key:** [bucket (8 bytes BE)]
+/// **Value:** [block_count (8 bytes BE)] + [tx_count (8 bytes BE)] +
+/// [accepted_tx_count (8 bytes BE)] + [fee_sum (8 bytes BE)] + [coinbase_sum (8 bytes BE)] +
+/// [address registers (1024 bytes)] + [outputs per script class (5 * 8 bytes BE)]
+///
+/// Values written before the script classes were counted end after the address registers.
+/// Accepted transactions are counted in [`AcceptedAggregatesPartition`], the accepted count of
+/// these values stays zero.
+#[derive(Clone)]
+pub struct AggregatesPartition(fjall::TxPartition);
+
+impl DescribePartition for AggregatesPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "aggregates",
+        key: &[field("bucket", FieldType::U64Be)],
+        value: &[
+            field("block_count", FieldType::U64Be),
+            field("tx_count", FieldType::U64Be),
+            field("accepted_tx_count", FieldType::U64Be),
+            field("fee_sum", FieldType::U64Be),
+            field("coinbase_sum", FieldType::U64Be),
+            field("address_registers", FieldType::Bytes(HLL_REGISTERS)),
+            field("non_standard_outputs", FieldType::U64Be),
+            field("pubkey_outputs", FieldType::U64Be),
+            field("pubkey_ecdsa_outputs", FieldType::U64Be),
+            field("script_hash_outputs", FieldType::U64Be),
+            field("unknown_outputs", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl AggregatesPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn update_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        bucket: u64,
+        update: impl FnOnce(&mut BucketAggregates),
+    ) -> Result<()> {
+        let mut aggregates = match wtx.get(&self.0, bucket.to_be_bytes())? {
+            Some(value) => BucketAggregates::decode(bucket, &value)?,
+            None => BucketAggregates {
+                bucket,
+                ..Default::default()
+            },
+        };
+        update(&mut aggregates);
+        wtx.insert(&self.0, bucket.to_be_bytes(), aggregates.encode());
+        Ok(())
+    }
+
+    pub fn get_range_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        buckets: Range<u64>,
+    ) -> Result<Vec<BucketAggregates>> {
+        rtx.range(
+            &self.0,
+            buckets.start.to_be_bytes()..buckets.end.to_be_bytes(),
+        )
+        .map(|item| {
+            let (key, value) = item?;
+            BucketAggregates::decode(u64::from_be_bytes(key[..].try_into()?), &value)
+        })
+        .collect()
+    }
+}
+
+/// Partition keeping the transactions accepted by the chain blocks of a DAA score bucket. Kept
+/// apart from [`AggregatesPartition`] so the two processors never write the same key, the
+/// counts are summed when read.
+///
+/// **Key:** [bucket (8 bytes BE)]
+/// **Value:** [accepted_tx_count (8 bytes BE)]
+#[derive(Clone)]
+pub struct AcceptedAggregatesPartition(fjall::TxPartition);
+
+impl DescribePartition for AcceptedAggregatesPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "aggregates_accepted",
+        key: &[field("bucket", FieldType::U64Be)],
+        value: &[field("accepted_tx_count", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl AcceptedAggregatesPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn update_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        bucket: u64,
+        update: impl FnOnce(u64) -> u64,
+    ) -> Result<()> {
+        let count = match wtx.get(&self.0, bucket.to_be_bytes())? {
+            Some(value) => u64::from_be_bytes(value[..].try_into()?),
+            None => 0,
+        };
+        wtx.insert(&self.0, bucket.to_be_bytes(), update(count).to_be_bytes());
+        Ok(())
+    }
+
+    /// Accepted transaction counts by bucket
+    fn get_range_rtx(&self, rtx: &ReadTransaction, buckets: Range<u64>) -> Result<Vec<(u64, u64)>> {
+        rtx.range(
+            &self.0,
+            buckets.start.to_be_bytes()..buckets.end.to_be_bytes(),
+        )
+        .map(|item| {
+            let (key, value) = item?;
+            Ok((
+                u64::from_be_bytes(key[..].try_into()?),
+                u64::from_be_bytes(value[..].try_into()?),
+            ))
+        })
+        .collect()
+    }
+}
+
+/// Partition keeping the accepted transaction count each chain block added to its bucket,
+/// until the block is finalized.
+///
+/// **Key:** [accepting block hash (32 bytes)]
+/// **Value:** [daa_score (8 bytes BE)] + [accepted_tx_count (8 bytes BE)]
+#[derive(Clone)]
+pub struct BlockAcceptedCountPartition(fjall::TxPartition);
+
+impl DescribePartition for BlockAcceptedCountPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_accepted_counts",
+        key: &[field("accepting_block_hash", FieldType::Hash)],
+        value: &[
+            field("daa_score", FieldType::U64Be),
+            field("accepted_tx_count", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockAcceptedCountPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn contains_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) -> Result<bool> {
+        Ok(wtx.get(&self.0, block_hash.as_bytes())?.is_some())
+    }
+
+    fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        daa_score: u64,
+        count: u64,
+    ) {
+        let mut value = [0u8; 16];
+        value[..8].copy_from_slice(&daa_score.to_be_bytes());
+        value[8..].copy_from_slice(&count.to_be_bytes());
+        wtx.insert(&self.0, block_hash.as_bytes(), value);
+    }
+
+    /// Removes and returns the DAA score and count of the block
+    fn take_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<(u64, u64)>> {
+        let Some(value) = wtx.fetch_update(&self.0, block_hash.as_bytes(), |_| None)? else {
+            return Ok(None);
+        };
+        if value.len() != 16 {
+            bail!("Invalid block accepted count length");
+        }
+        Ok(Some((
+            u64::from_be_bytes(value[..8].try_into()?),
+            u64::from_be_bytes(value[8..].try_into()?),
+        )))
+    }
+}
+
+/// Per bucket totals for charts, updated as blocks are indexed and accepted.
+///
+/// The block processor adds the totals of every block it writes for the first time to the
+/// bucket of its DAA score. Blocks stay in the DAG, so these are never taken back; reprocessing
+/// a block only replaces its fees. The virtual chain processor adds the transactions a chain
+/// block accepts to the bucket of the chain block, in a partition of its own, and takes them
+/// back when a reorg removes it. Blocks indexed before the aggregates were enabled are not
+/// counted.
+#[derive(Clone)]
+pub struct Aggregates {
+    keyspace: TxKeyspace,
+    bucket_width: u64,
+    aggregates_partition: AggregatesPartition,
+    accepted_aggregates_partition: AcceptedAggregatesPartition,
+    block_accepted_count_partition: BlockAcceptedCountPartition,
+}
+
+impl Aggregates {
+    /// `bucket_width` in DAA scores, the database keeps the width it was built with
+    pub fn new(keyspace: &TxKeyspace, bucket_width: u64) -> Result<Self> {
+        if bucket_width == 0 {
+            bail!("Aggregate bucket width must be positive");
+        }
+        let aggregates = Self {
+            keyspace: keyspace.clone(),
+            bucket_width,
+            aggregates_partition: AggregatesPartition::new(keyspace)?,
+            accepted_aggregates_partition: AcceptedAggregatesPartition::new(keyspace)?,
+            block_accepted_count_partition: BlockAcceptedCountPartition::new(keyspace)?,
+        };
+        aggregates.move_legacy_accepted_counts()?;
+        Ok(aggregates)
+    }
+
+    /// Moves the accepted counts written into the block totals before they had a partition of
+    /// their own
+    fn move_legacy_accepted_counts(&self) -> Result<()> {
+        if !self.accepted_aggregates_partition.0.inner().is_empty()? {
+            return Ok(());
+        }
+        let rtx = self.keyspace.read_tx();
+        let mut wtx = self.keyspace.write_tx()?;
+        for mut aggregates in self.aggregates_partition.get_range_rtx(&rtx, 0..u64::MAX)? {
+            if aggregates.accepted_tx_count == 0 {
+                continue;
+            }
+            let bucket = aggregates.bucket.to_be_bytes();
+            wtx.insert(
+                &self.accepted_aggregates_partition.0,
+                bucket,
+                aggregates.accepted_tx_count.to_be_bytes(),
+            );
+            aggregates.accepted_tx_count = 0;
+            wtx.insert(&self.aggregates_partition.0, bucket, aggregates.encode());
+        }
+        wtx.commit()??;
+        Ok(())
+    }
+
+    pub fn bucket_width(&self) -> u64 {
+        self.bucket_width
+    }
+
+    pub fn bucket_of(&self, daa_score: u64) -> u64 {
+        daa_score / self.bucket_width
+    }
+
+    /// Buckets within `buckets` holding any block or accepted transaction, by bucket
+    pub fn get_aggregates(&self, buckets: Range<u64>) -> Result<Vec<BucketAggregates>> {
+        let rtx = self.keyspace.read_tx();
+        let mut aggregates = self
+            .aggregates_partition
+            .get_range_rtx(&rtx, buckets.clone())?;
+        for (bucket, accepted_tx_count) in self
+            .accepted_aggregates_partition
+            .get_range_rtx(&rtx, buckets)?
+        {
+            match aggregates.binary_search_by_key(&bucket, |aggregates| aggregates.bucket) {
+                Ok(i) => aggregates[i].accepted_tx_count += accepted_tx_count,
+                Err(i) => aggregates.insert(
+                    i,
+                    BucketAggregates {
+                        bucket,
+                        accepted_tx_count,
+                        ..Default::default()
+                    },
+                ),
+            }
+        }
+        Ok(aggregates)
+    }
+
+    /// Outputs by script class of the blocks in the buckets overlapping `daa_range`, the
+    /// range is widened to whole buckets
+    pub fn script_class_distribution(&self, daa_range: Range<u64>) -> Result<ScriptClassCounts> {
+        if daa_range.is_empty() {
+            return Ok(ScriptClassCounts::default());
+        }
+        let buckets = self.bucket_of(daa_range.start)..self.bucket_of(daa_range.end - 1) + 1;
+        let mut distribution = ScriptClassCounts::default();
+        for aggregates in self.get_aggregates(buckets)? {
+            distribution.merge(&aggregates.script_classes);
+        }
+        Ok(distribution)
+    }
+
+    /// Adds the totals of a block written for the first time, `stats` are its [`BlockStats`]
+    pub fn add_block_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block: &RpcBlock,
+        stats: &BlockStats,
+    ) -> Result<()> {
+        let coinbase_sum = coinbase_sum(block);
+        self.aggregates_partition.update_wtx(
+            wtx,
+            self.bucket_of(block.header.daa_score),
+            |aggregates| {
+                aggregates.block_count += 1;
+                aggregates.tx_count += stats.tx_count;
+                aggregates.fee_sum += stats.total_fees;
+                aggregates.coinbase_sum += coinbase_sum;
+                for tx in &block.transactions {
+                    for output in &tx.outputs {
+                        insert_address(&mut aggregates.addresses, &output.script_public_key);
+                        aggregates
+                            .script_classes
+                            .add(script_class(&output.script_public_key));
+                    }
+                }
+            },
+        )
+    }
+
+    /// Replaces the fees of a block whose stats were rewritten, the rest of its totals stays
+    pub fn replace_fees_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        daa_score: u64,
+        old_fees: u64,
+        new_fees: u64,
+    ) -> Result<()> {
+        self.aggregates_partition
+            .update_wtx(wtx, self.bucket_of(daa_score), |aggregates| {
+                aggregates.fee_sum = aggregates.fee_sum.saturating_sub(old_fees) + new_fees;
+            })
+    }
+
+    /// Adds the transactions accepted by the chain block, once per block
+    pub fn apply_accepted_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+        daa_score: u64,
+        accepted_tx_count: u64,
+    ) -> Result<()> {
+        if self
+            .block_accepted_count_partition
+            .contains_wtx(wtx, accepting_block_hash)?
+        {
+            return Ok(());
+        }
+        self.block_accepted_count_partition.insert_wtx(
+            wtx,
+            accepting_block_hash,
+            daa_score,
+            accepted_tx_count,
+        );
+        self.accepted_aggregates_partition
+            .update_wtx(wtx, self.bucket_of(daa_score), |count| {
+                count + accepted_tx_count
+            })
+    }
+
+    /// Takes back the transactions the chain block removed by a reorg accepted
+    pub fn revert_accepted_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+    ) -> Result<()> {
+        let Some((daa_score, accepted_tx_count)) = self
+            .block_accepted_count_partition
+            .take_wtx(wtx, accepting_block_hash)?
+        else {
+            return Ok(());
+        };
+        self.accepted_aggregates_partition
+            .update_wtx(wtx, self.bucket_of(daa_score), |count| {
+                count.saturating_sub(accepted_tx_count)
+            })
+    }
+
+    /// Drops the count of the finalized chain block, its acceptance can't be reverted anymore
+    pub fn finalize_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+    ) -> Result<()> {
+        self.block_accepted_count_partition
+            .take_wtx(wtx, accepting_block_hash)?;
+        Ok(())
+    }
+}
+
+fn coinbase_sum(block: &RpcBlock) -> u64 {
+    block
+        .transactions
+        .iter()
+        .filter(|tx| tx.subnetwork_id == SUBNETWORK_ID_COINBASE)
+        .flat_map(|tx| &tx.outputs)
+        .map(|output| output.value)
+        .sum()
+}
+
+fn insert_address(addresses: &mut HyperLogLog, script_public_key: &ScriptPublicKey) {
+    let mut item = script_public_key.version().to_be_bytes().to_vec();
+    item.extend_from_slice(script_public_key.script());
+    addresses.insert(&item);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_consensus_core::header::Header;
+    use kaspa_consensus_core::subnets::SUBNETWORK_ID_NATIVE;
+    use kaspa_consensus_core::tx::{Transaction, TransactionOutput};
+    use kaspa_rpc_core::RpcTransaction;
+
+    fn keyspace(name: &str) -> TxKeyspace {
+        crate::database::test_keyspace(&format!("aggregates-{name}"))
+    }
+
+    /// Block with a coinbase of 500 and a transaction paying 100 to `receiver`
+    fn block(i: u64, daa_score: u64, receiver: u8) -> RpcBlock {
+        let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(i), vec![]);
+        header.daa_score = daa_score;
+        let output = |value, byte| {
+            TransactionOutput::new(value, ScriptPublicKey::from_vec(0, vec![byte; 34]))
+        };
+        let coinbase = Transaction::new(
+            0,
+            vec![],
+            vec![output(500, 0xff)],
+            0,
+            SUBNETWORK_ID_COINBASE,
+            0,
+            vec![],
+        );
+        let payment = Transaction::new(
+            0,
+            vec![],
+            vec![output(100, receiver)],
+            0,
+            SUBNETWORK_ID_NATIVE,
+            0,
+            vec![],
+        );
+        RpcBlock {
+            header: (&header).into(),
+            transactions: vec![
+                RpcTransaction::from(&coinbase),
+                RpcTransaction::from(&payment),
+            ],
+            verbose_data: None,
+        }
+    }
+
+    fn add(keyspace: &TxKeyspace, aggregates: &Aggregates, block: &RpcBlock, fees: u64) {
+        let mut wtx = keyspace.write_tx().unwrap();
+        let stats = BlockStats {
+            tx_count: block.transactions.len() as u64,
+            total_fees: fees,
+            ..Default::default()
+        };
+        aggregates.add_block_wtx(&mut wtx, block, &stats).unwrap();
+        wtx.commit().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_blocks_fall_into_buckets_by_daa_score() {
+        let keyspace = keyspace("buckets");
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        add(&keyspace, &aggregates, &block(1, 0, 1), 10);
+        add(&keyspace, &aggregates, &block(2, 99, 2), 20);
+        add(&keyspace, &aggregates, &block(3, 100, 2), 30);
+        add(&keyspace, &aggregates, &block(4, 250, 3), 40);
+
+        let buckets = aggregates.get_aggregates(0..3).unwrap();
+        let totals = buckets
+            .iter()
+            .map(|b| {
+                (
+                    b.bucket,
+                    b.block_count,
+                    b.tx_count,
+                    b.fee_sum,
+                    b.coinbase_sum,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            totals,
+            [(0, 2, 4, 30, 1_000), (1, 1, 2, 30, 500), (2, 1, 2, 40, 500)]
+        );
+        // the miner and the receivers
+        let addresses = buckets.iter().map(BucketAggregates::distinct_addresses);
+        assert_eq!(addresses.collect::<Vec<_>>(), [3, 2, 2]);
+        // the end of the range is excluded
+        assert_eq!(aggregates.get_aggregates(1..2).unwrap().len(), 1);
+        assert!(aggregates.get_aggregates(3..10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reorg_takes_back_accepted_transactions() {
+        let keyspace = keyspace("reorg");
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        let (a, b) = (RpcHash::from_u64_word(1), RpcHash::from_u64_word(2));
+        let mut wtx = keyspace.write_tx().unwrap();
+        aggregates.apply_accepted_wtx(&mut wtx, a, 150, 7).unwrap();
+        aggregates.apply_accepted_wtx(&mut wtx, b, 199, 3).unwrap();
+        // accepted again after a restart
+        aggregates.apply_accepted_wtx(&mut wtx, b, 199, 3).unwrap();
+        wtx.commit().unwrap().unwrap();
+        let accepted = || {
+            aggregates
+                .get_aggregates(0..10)
+                .unwrap()
+                .iter()
+                .map(|b| (b.bucket, b.accepted_tx_count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(accepted(), [(1, 10)]);
+
+        let mut wtx = keyspace.write_tx().unwrap();
+        aggregates.revert_accepted_wtx(&mut wtx, b).unwrap();
+        aggregates.apply_accepted_wtx(&mut wtx, b, 200, 4).unwrap();
+        aggregates.finalize_wtx(&mut wtx, a).unwrap();
+        wtx.commit().unwrap().unwrap();
+        assert_eq!(accepted(), [(1, 7), (2, 4)]);
+
+        // finalized blocks are not reverted
+        let mut wtx = keyspace.write_tx().unwrap();
+        aggregates.revert_accepted_wtx(&mut wtx, a).unwrap();
+        wtx.commit().unwrap().unwrap();
+        assert_eq!(accepted(), [(1, 7), (2, 4)]);
+    }
+
+    #[test]
+    fn test_block_totals_and_acceptance_do_not_conflict() {
+        let keyspace = keyspace("writers");
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        let block = block(1, 50, 1);
+        let stats = BlockStats {
+            tx_count: 2,
+            total_fees: 10,
+            ..Default::default()
+        };
+        // both processors update the same bucket at the same time
+        let mut block_wtx = keyspace.write_tx().unwrap();
+        let mut accepting_wtx = keyspace.write_tx().unwrap();
+        aggregates
+            .add_block_wtx(&mut block_wtx, &block, &stats)
+            .unwrap();
+        aggregates
+            .apply_accepted_wtx(&mut accepting_wtx, block.header.hash, 50, 2)
+            .unwrap();
+        block_wtx.commit().unwrap().unwrap();
+        accepting_wtx.commit().unwrap().unwrap();
+
+        let bucket = &aggregates.get_aggregates(0..1).unwrap()[0];
+        assert_eq!((bucket.block_count, bucket.accepted_tx_count), (1, 2));
+    }
+
+    #[test]
+    fn test_legacy_accepted_counts_are_moved() {
+        let keyspace = keyspace("legacy-accepted");
+        let partition = AggregatesPartition::new(&keyspace).unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        partition
+            .update_wtx(&mut wtx, 1, |aggregates| {
+                aggregates.block_count = 3;
+                aggregates.accepted_tx_count = 7;
+            })
+            .unwrap();
+        wtx.commit().unwrap().unwrap();
+
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        let stored = partition.get_range_rtx(&keyspace.read_tx(), 0..2).unwrap();
+        assert_eq!(stored[0].accepted_tx_count, 0);
+        let mut wtx = keyspace.write_tx().unwrap();
+        aggregates
+            .apply_accepted_wtx(&mut wtx, RpcHash::from_u64_word(2), 150, 1)
+            .unwrap();
+        wtx.commit().unwrap().unwrap();
+        // moved once, opening again keeps the counts
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        let buckets = aggregates.get_aggregates(0..2).unwrap();
+        assert_eq!(
+            (buckets[0].block_count, buckets[0].accepted_tx_count),
+            (3, 8)
+        );
+    }
+
+    #[test]
+    fn test_replace_fees_of_reprocessed_block() {
+        let keyspace = keyspace("reprocess");
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        add(&keyspace, &aggregates, &block(1, 5, 1), 10);
+        add(&keyspace, &aggregates, &block(2, 6, 2), 20);
+        let mut wtx = keyspace.write_tx().unwrap();
+        aggregates.replace_fees_wtx(&mut wtx, 5, 10, 15).unwrap();
+        wtx.commit().unwrap().unwrap();
+        let bucket = &aggregates.get_aggregates(0..1).unwrap()[0];
+        assert_eq!((bucket.block_count, bucket.fee_sum), (2, 35));
+    }
+
+    #[test]
+    fn test_script_class_distribution() {
+        let keyspace = keyspace("script-classes");
+        let aggregates = Aggregates::new(&keyspace, 100).unwrap();
+        let mut p2pk = block(1, 10, 1);
+        let mut script = vec![0x20];
+        script.extend_from_slice(&[9; 32]);
+        script.push(0xac);
+        p2pk.transactions[1].outputs[0].script_public_key = ScriptPublicKey::from_vec(0, script);
+        // a script version unknown to this build
+        let mut future = block(2, 150, 2);
+        future.transactions[1].outputs[0].script_public_key =
+            ScriptPublicKey::from_vec(1, vec![0x51]);
+        add(&keyspace, &aggregates, &p2pk, 0);
+        add(&keyspace, &aggregates, &future, 0);
+        add(&keyspace, &aggregates, &block(3, 250, 3), 0);
+
+        let counts = |range| {
+            let counts = aggregates.script_class_distribution(range).unwrap();
+            (counts.pubkey, counts.non_standard, counts.unknown)
+        };
+        assert_eq!(counts(0..100), (1, 1, 0));
+        // widened to whole buckets
+        assert_eq!(counts(50..151), (1, 2, 1));
+        assert_eq!(counts(0..300), (1, 4, 1));
+        assert_eq!(counts(300..300), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_decode_legacy_value() {
+        let mut value = BucketAggregates {
+            bucket: 4,
+            block_count: 3,
+            ..Default::default()
+        }
+        .encode()
+        .to_vec();
+        value.truncate(LEGACY_VALUE_LEN);
+        let aggregates = BucketAggregates::decode(4, &value).unwrap();
+        assert_eq!(aggregates.block_count, 3);
+        assert_eq!(aggregates.script_classes, ScriptClassCounts::default());
+        assert!(BucketAggregates::decode(4, &value[1..]).is_err());
+    }
+
+    #[test]
+    fn test_hyperloglog_estimate() {
+        let mut sketch = HyperLogLog::default();
+        for i in 0..100_000u64 {
+            sketch.insert(&i.to_be_bytes());
+            // duplicates don't count
+            sketch.insert(&(i / 2).to_be_bytes());
+        }
+        let estimate = sketch.estimate();
+        assert!((90_000..110_000).contains(&estimate), "{estimate}");
+        assert_eq!(HyperLogLog::default().estimate(), 0);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::database::messages::AddressPayload;
+use crate::database::metadata::MetadataPartition;
+use crate::database::processing::{OutpointPartition, TxInputPartition};
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, TxKeyspace, WriteTransaction};
+use kaspa_rpc_core::{RpcAddress, RpcHash, RpcTransactionId};
+use std::collections::BTreeMap;
+
+type AddressKey = [u8; 34];
+
+fn address_key(address: &AddressPayload) -> AddressKey {
+    bytemuck::cast(*address)
+}
+
+/// Partition keeping the confirmed balance of every address, in sompi.
+///
+/// **Key:** [address payload (34 bytes)]
+/// **Value:** [balance (8 bytes BE, signed)]
+#[derive(Clone)]
+pub struct AddressBalancePartition(fjall::TxPartition);
+
+impl DescribePartition for AddressBalancePartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "address_balances",
+        key: &[field("address", FieldType::AddressPayload)],
+        value: &[field("balance", FieldType::I64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl AddressBalancePartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn get_rtx(&self, rtx: &ReadTransaction, address: &AddressPayload) -> Result<i64> {
+        rtx.get(&self.0, address_key(address))?
+            .map_or(Ok(0), |value| decode_i64(&value))
+    }
+
+    /// Adds `delta`, the entry is dropped once the balance is back to zero
+    fn add_wtx(&self, wtx: &mut WriteTransaction, address: AddressKey, delta: i64) -> Result<()> {
+        let balance = wtx
+            .get(&self.0, address)?
+            .map_or(Ok(0), |value| decode_i64(&value))?
+            + delta;
+        if balance == 0 {
+            wtx.remove(&self.0, address);
+        } else {
+            wtx.insert(&self.0, address, balance.to_be_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Partition keeping the balance changes each accepting chain block applied, until the block
+/// is finalized.
+///
+/// **Key:** [accepting block hash (32 bytes)] + [address payload (34 bytes)]
+/// **Value:** [daa_score (8 bytes BE)] + [delta (8 bytes BE, signed)]
+#[derive(Clone)]
+pub struct BlockBalanceDeltaPartition(fjall::TxPartition);
+
+impl DescribePartition for BlockBalanceDeltaPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_balance_deltas",
+        key: &[
+            field("accepting_block_hash", FieldType::Hash),
+            field("address", FieldType::AddressPayload),
+        ],
+        value: &[
+            field("daa_score", FieldType::U64Be),
+            field("delta", FieldType::I64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockBalanceDeltaPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn contains_block_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) -> Result<bool> {
+        Ok(wtx
+            .prefix(&self.0, block_hash.as_bytes())
+            .next()
+            .transpose()?
+            .is_some())
+    }
+
+    fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        address: AddressKey,
+        daa_score: u64,
+        delta: i64,
+    ) {
+        let mut key = [0u8; 32 + 34];
+        key[..32].copy_from_slice(&block_hash.as_bytes());
+        key[32..].copy_from_slice(&address);
+        let mut value = [0u8; 16];
+        value[..8].copy_from_slice(&daa_score.to_be_bytes());
+        value[8..].copy_from_slice(&delta.to_be_bytes());
+        wtx.insert(&self.0, key, value);
+    }
+
+    /// Removes and returns the deltas of the block as (address, daa_score, delta)
+    fn take_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Vec<(AddressKey, u64, i64)>> {
+        let mut deltas = Vec::new();
+        let mut taken = Vec::new();
+        for item in wtx.prefix(&self.0, block_hash.as_bytes()) {
+            let (key, value) = item?;
+            if key.len() != 32 + 34 || value.len() != 16 {
+                bail!("Invalid block balance delta length");
+            }
+            deltas.push((
+                key[32..].try_into()?,
+                u64::from_be_bytes(value[..8].try_into()?),
+                decode_i64(&value[8..])?,
+            ));
+            taken.push(key);
+        }
+        for key in taken {
+            wtx.remove(&self.0, key);
+        }
+        Ok(deltas)
+    }
+}
+
+/// Partition keeping the deltas of [`BlockBalanceDeltaPartition`] by address, for the balance
+/// at a past DAA score.
+///
+/// **Key:** [address payload (34 bytes)] + [daa_score (8 bytes BE)] + [accepting block hash (32 bytes)]
+/// **Value:** [delta (8 bytes BE, signed)]
+#[derive(Clone)]
+pub struct AddressBalanceDeltaPartition(fjall::TxPartition);
+
+impl DescribePartition for AddressBalanceDeltaPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "address_balance_deltas",
+        key: &[
+            field("address", FieldType::AddressPayload),
+            field("daa_score", FieldType::U64Be),
+            field("accepting_block_hash", FieldType::Hash),
+        ],
+        value: &[field("delta", FieldType::I64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl AddressBalanceDeltaPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn key(address: AddressKey, daa_score: u64, block_hash: RpcHash) -> [u8; 34 + 8 + 32] {
+        let mut key = [0u8; 34 + 8 + 32];
+        key[..34].copy_from_slice(&address);
+        key[34..42].copy_from_slice(&daa_score.to_be_bytes());
+        key[42..].copy_from_slice(&block_hash.as_bytes());
+        key
+    }
+
+    fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        address: AddressKey,
+        daa_score: u64,
+        block_hash: RpcHash,
+        delta: i64,
+    ) {
+        wtx.insert(
+            &self.0,
+            Self::key(address, daa_score, block_hash),
+            delta.to_be_bytes(),
+        );
+    }
+
+    fn remove_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        address: AddressKey,
+        daa_score: u64,
+        block_hash: RpcHash,
+    ) {
+        wtx.remove(&self.0, Self::key(address, daa_score, block_hash));
+    }
+
+    /// Sum of the deltas applied by blocks above `daa_score`
+    fn sum_above_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        address: AddressKey,
+        daa_score: u64,
+    ) -> Result<i64> {
+        let Some(from) = daa_score.checked_add(1) else {
+            return Ok(0);
+        };
+        let start = Self::key(address, from, RpcHash::default());
+        rtx.range(&self.0, start..)
+            .take_while(|item| {
+                item.as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&address))
+            })
+            .try_fold(0, |sum, item| {
+                let (_, value) = item?;
+                Ok(sum + decode_i64(&value)?)
+            })
+    }
+}
+
+fn decode_i64(value: &[u8]) -> Result<i64> {
+    match value.try_into() {
+        Ok(bytes) => Ok(i64::from_be_bytes(bytes)),
+        Err(_) => bail!("Invalid balance size"),
+    }
+}
+
+/// Confirmed balances of addresses, following the selected chain.
+///
+/// The virtual chain processor credits the outputs and debits the spent outputs of the
+/// transactions a chain block accepts when the block is added, and reverses the exact deltas
+/// when a reorg removes it. The deltas are kept per chain block until it is finalized, which
+/// bounds the window [`Balances::get_balance_at_daa`] answers for. Without a finality depth
+/// they are kept forever.
+///
+/// Only outputs in the outpoint index count. Outputs of transactions accepted before their
+/// block was indexed are never credited while their spends are debited, so balances of
+/// addresses touched during a sync may be off and even negative.
+#[derive(Clone)]
+pub struct Balances {
+    keyspace: TxKeyspace,
+    metadata_partition: MetadataPartition,
+    outpoint_partition: OutpointPartition,
+    tx_input_partition: TxInputPartition,
+    address_balance_partition: AddressBalancePartition,
+    block_balance_delta_partition: BlockBalanceDeltaPartition,
+    address_balance_delta_partition: AddressBalanceDeltaPartition,
+}
+
+impl Balances {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self {
+            keyspace: keyspace.clone(),
+            metadata_partition: MetadataPartition::new(keyspace)?,
+            outpoint_partition: OutpointPartition::new(keyspace)?,
+            tx_input_partition: TxInputPartition::new(keyspace)?,
+            address_balance_partition: AddressBalancePartition::new(keyspace)?,
+            block_balance_delta_partition: BlockBalanceDeltaPartition::new(keyspace)?,
+            address_balance_delta_partition: AddressBalanceDeltaPartition::new(keyspace)?,
+        })
+    }
+
+    pub fn get_balance(&self, address: &RpcAddress) -> Result<i64> {
+        self.address_balance_partition.get_rtx(
+            &self.keyspace.read_tx(),
+            &AddressPayload::try_from(address)?,
+        )
+    }
+
+    /// Balance once every chain block up to `daa_score` was accepted, none below the window of
+    /// retained deltas
+    pub fn get_balance_at_daa(&self, address: &RpcAddress, daa_score: u64) -> Result<Option<i64>> {
+        self.get_balance_at_daa_rtx(
+            &self.keyspace.read_tx(),
+            &AddressPayload::try_from(address)?,
+            daa_score,
+        )
+    }
+
+    pub fn get_balance_at_daa_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        address: &AddressPayload,
+        daa_score: u64,
+    ) -> Result<Option<i64>> {
+        if self
+            .metadata_partition
+            .get_balance_deltas_pruned_daa_rtx(rtx)?
+            .is_some_and(|pruned| daa_score < pruned)
+        {
+            return Ok(None);
+        }
+        let balance = self.address_balance_partition.get_rtx(rtx, address)?;
+        let above = self.address_balance_delta_partition.sum_above_rtx(
+            rtx,
+            address_key(address),
+            daa_score,
+        )?;
+        Ok(Some(balance - above))
+    }
+
+    /// Applies the transactions accepted by the chain block, once per block. Returns the
+    /// number of addresses whose balance changed
+    pub fn apply_accepted_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+        daa_score: u64,
+        tx_ids: &[RpcTransactionId],
+    ) -> Result<usize> {
+        if self
+            .block_balance_delta_partition
+            .contains_block_wtx(wtx, accepting_block_hash)?
+        {
+            return Ok(0);
+        }
+        let mut deltas = BTreeMap::<AddressKey, i64>::new();
+        for tx_id in tx_ids {
+            for (_, output) in self.outpoint_partition.get_outputs_wtx(wtx, *tx_id)? {
+                if let Ok(address) = AddressPayload::try_from(&output.script_public_key) {
+                    *deltas.entry(address_key(&address)).or_default() += output.value as i64;
+                }
+            }
+            for (outpoint_tx_id, outpoint_index) in self
+                .tx_input_partition
+                .get_spent_outpoints_wtx(wtx, *tx_id)?
+            {
+                let Some(output) =
+                    self.outpoint_partition
+                        .get_wtx(wtx, outpoint_tx_id, outpoint_index)?
+                else {
+                    continue;
+                };
+                if let Ok(address) = AddressPayload::try_from(&output.script_public_key) {
+                    *deltas.entry(address_key(&address)).or_default() -= output.value as i64;
+                }
+            }
+        }
+        deltas.retain(|_, delta| *delta != 0);
+        for (address, delta) in &deltas {
+            self.block_balance_delta_partition.insert_wtx(
+                wtx,
+                accepting_block_hash,
+                *address,
+                daa_score,
+                *delta,
+            );
+            self.address_balance_delta_partition.insert_wtx(
+                wtx,
+                *address,
+                daa_score,
+                accepting_block_hash,
+                *delta,
+            );
+            self.address_balance_partition
+                .add_wtx(wtx, *address, *delta)?;
+        }
+        Ok(deltas.len())
+    }
+
+    /// Reverses the deltas the chain block applied, returns the number of addresses changed
+    pub fn revert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+    ) -> Result<usize> {
+        let deltas = self
+            .block_balance_delta_partition
+            .take_wtx(wtx, accepting_block_hash)?;
+        for (address, daa_score, delta) in &deltas {
+            self.address_balance_delta_partition.remove_wtx(
+                wtx,
+                *address,
+                *daa_score,
+                accepting_block_hash,
+            );
+            self.address_balance_partition
+                .add_wtx(wtx, *address, -delta)?;
+        }
+        Ok(deltas.len())
+    }
+
+    /// Drops the deltas of the finalized chain block, its balance changes can't be reverted
+    /// anymore
+    pub fn finalize_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+    ) -> Result<()> {
+        let deltas = self
+            .block_balance_delta_partition
+            .take_wtx(wtx, accepting_block_hash)?;
+        for (address, daa_score, _) in &deltas {
+            self.address_balance_delta_partition.remove_wtx(
+                wtx,
+                *address,
+                *daa_score,
+                accepting_block_hash,
+            );
+        }
+        if let Some((_, daa_score, _)) = deltas.first() {
+            self.metadata_partition
+                .set_balance_deltas_pruned_daa_wtx(wtx, *daa_score);
+        }
+        Ok(())
+    }
+}
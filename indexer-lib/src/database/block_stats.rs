@@ -0,0 +1,293 @@
+use crate::database::headers::DaaIndexPartition;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_COINBASE;
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransactionOutpoint};
+use std::ops::Range;
+
+const VALUE_LEN: usize = 8 + 8 + 8 + 1;
+
+/// Partition keeping fee and mass totals per block.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [tx_count (8 bytes BE)] + [total_mass (8 bytes BE)] + [total_fees (8 bytes BE)] +
+/// [partial (1 byte)]
+///
+/// Range queries go through the daa index, which lists the blocks of a daa range.
+#[derive(Clone)]
+pub struct BlockStatsPartition(fjall::TxPartition);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    pub tx_count: u64,
+    pub total_mass: u64,
+    /// Fees of the transactions whose inputs all resolved
+    pub total_fees: u64,
+    /// Some input could not be resolved, the fees are incomplete
+    pub partial: bool,
+}
+
+impl BlockStats {
+    /// Totals of the block. `resolve` returns the value of the output an input spends, none if
+    /// it is unknown. Fees are left at zero when `resolve` is none
+    pub fn compute(
+        block: &RpcBlock,
+        mut resolve: Option<impl FnMut(&RpcTransactionOutpoint) -> Result<Option<u64>>>,
+    ) -> Result<Self> {
+        let mut stats = Self {
+            tx_count: block.transactions.len() as u64,
+            partial: resolve.is_none(),
+            ..Default::default()
+        };
+        'txs: for tx in &block.transactions {
+            stats.total_mass += tx.mass;
+            if tx.subnetwork_id == SUBNETWORK_ID_COINBASE {
+                continue;
+            }
+            let Some(resolve) = resolve.as_mut() else {
+                continue;
+            };
+            let mut inputs = 0u64;
+            for input in &tx.inputs {
+                let Some(value) = resolve(&input.previous_outpoint)? else {
+                    stats.partial = true;
+                    continue 'txs;
+                };
+                inputs += value;
+            }
+            let outputs = tx.outputs.iter().map(|output| output.value).sum::<u64>();
+            stats.total_fees += inputs.saturating_sub(outputs);
+        }
+        Ok(stats)
+    }
+
+    /// Average fee rate in sompi per gram, none for a block without mass
+    pub fn fee_rate(&self) -> Option<f64> {
+        (self.total_mass > 0).then(|| self.total_fees as f64 / self.total_mass as f64)
+    }
+
+    fn encode(&self) -> [u8; VALUE_LEN] {
+        let mut value = [0u8; VALUE_LEN];
+        value[..8].copy_from_slice(&self.tx_count.to_be_bytes());
+        value[8..16].copy_from_slice(&self.total_mass.to_be_bytes());
+        value[16..24].copy_from_slice(&self.total_fees.to_be_bytes());
+        value[24] = self.partial as u8;
+        value
+    }
+
+    fn decode(value: &[u8]) -> Result<Self> {
+        if value.len() != VALUE_LEN {
+            bail!("Invalid block stats length");
+        }
+        Ok(Self {
+            tx_count: u64::from_be_bytes(value[..8].try_into()?),
+            total_mass: u64::from_be_bytes(value[8..16].try_into()?),
+            total_fees: u64::from_be_bytes(value[16..24].try_into()?),
+            partial: value[24] != 0,
+        })
+    }
+}
+
+impl DescribePartition for BlockStatsPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_stats",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[
+            field("tx_count", FieldType::U64Be),
+            field("total_mass", FieldType::U64Be),
+            field("total_fees", FieldType::U64Be),
+            field("partial", FieldType::U8),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockStatsPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash, stats: &BlockStats) {
+        wtx.insert(&self.0, block_hash.as_bytes(), stats.encode());
+    }
+
+    pub fn remove_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) {
+        wtx.remove(&self.0, block_hash.as_bytes());
+    }
+
+    pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
+        self.0.remove(block_hash.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn get_block_stats(&self, block_hash: RpcHash) -> Result<Option<BlockStats>> {
+        self.0
+            .get(block_hash.as_bytes())?
+            .map(|value| BlockStats::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_block_stats_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockStats>> {
+        wtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| BlockStats::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_block_stats_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockStats>> {
+        rtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| BlockStats::decode(&value))
+            .transpose()
+    }
+
+    /// Percentiles of the average fee rates of blocks within `daa_range`. Partial blocks and
+    /// blocks without stats are left out, none if no block is left
+    pub fn fee_rate_percentiles_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        daa_index: &DaaIndexPartition,
+        daa_range: Range<u64>,
+        percentiles: &[u8],
+    ) -> Result<Option<Vec<f64>>> {
+        let mut rates = Vec::new();
+        for r in daa_index.iter_range_rtx(rtx, daa_range) {
+            let (_, hash) = r?;
+            if let Some(stats) = self.get_block_stats_rtx(rtx, hash)?
+                && !stats.partial
+                && let Some(rate) = stats.fee_rate()
+            {
+                rates.push(rate);
+            }
+        }
+        Ok(nearest_rank(&mut rates, percentiles))
+    }
+}
+
+/// Nearest-rank percentiles, none for no values
+pub(crate) fn nearest_rank(values: &mut [f64], percentiles: &[u8]) -> Option<Vec<f64>> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    Some(
+        percentiles
+            .iter()
+            .map(|&p| {
+                let rank = (p.min(100) as usize * values.len()).div_ceil(100);
+                values[rank.saturating_sub(1)]
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_consensus_core::header::Header;
+    use kaspa_consensus_core::subnets::SUBNETWORK_ID_NATIVE;
+    use kaspa_consensus_core::tx::{
+        ScriptPublicKey, Transaction, TransactionInput, TransactionOutpoint, TransactionOutput,
+    };
+    use kaspa_rpc_core::RpcTransaction;
+    use std::collections::HashMap;
+
+    fn tx(spends: &[(u64, u32)], outputs: &[u64], mass: u64) -> RpcTransaction {
+        let tx = Transaction::new(
+            0,
+            spends
+                .iter()
+                .map(|&(tx_id, index)| {
+                    TransactionInput::new(
+                        TransactionOutpoint::new(RpcHash::from_u64_word(tx_id), index),
+                        vec![],
+                        0,
+                        1,
+                    )
+                })
+                .collect(),
+            outputs
+                .iter()
+                .map(|&value| TransactionOutput::new(value, ScriptPublicKey::from_vec(0, vec![])))
+                .collect(),
+            0,
+            SUBNETWORK_ID_NATIVE,
+            0,
+            vec![],
+        );
+        let mut tx = RpcTransaction::from(&tx);
+        tx.mass = mass;
+        tx
+    }
+
+    fn crafted_block(transactions: Vec<RpcTransaction>) -> RpcBlock {
+        RpcBlock {
+            header: (&Header::from_precomputed_hash(RpcHash::from_u64_word(1), vec![])).into(),
+            transactions,
+            verbose_data: None,
+        }
+    }
+
+    #[test]
+    fn test_compute() {
+        let outpoint = |tx_id, index| (RpcHash::from_u64_word(tx_id), index);
+        let outputs = HashMap::from([
+            (outpoint(1, 0), 1_000),
+            (outpoint(1, 1), 500),
+            (outpoint(2, 0), 300),
+        ]);
+        let resolve = |spent: &RpcTransactionOutpoint| -> Result<Option<u64>> {
+            Ok(outputs.get(&(spent.transaction_id, spent.index)).copied())
+        };
+        let block = crafted_block(vec![
+            tx(&[(1, 0), (1, 1)], &[1_400], 2_000),
+            tx(&[(2, 0)], &[250], 1_000),
+        ]);
+        let stats = BlockStats::compute(&block, Some(resolve)).unwrap();
+        assert_eq!(
+            stats,
+            BlockStats {
+                tx_count: 2,
+                total_mass: 3_000,
+                total_fees: 150,
+                partial: false,
+            }
+        );
+        assert_eq!(stats.fee_rate(), Some(0.05));
+        assert_eq!(BlockStats::decode(&stats.encode()).unwrap(), stats);
+
+        // the second transaction spends an output nobody indexed
+        let block = crafted_block(vec![
+            tx(&[(1, 0)], &[900], 1_000),
+            tx(&[(3, 0)], &[1], 1_000),
+        ]);
+        let stats = BlockStats::compute(&block, Some(resolve)).unwrap();
+        assert!(stats.partial);
+        assert_eq!((stats.total_fees, stats.total_mass), (100, 2_000));
+
+        let no_index = None::<fn(&RpcTransactionOutpoint) -> Result<Option<u64>>>;
+        let stats = BlockStats::compute(&block, no_index).unwrap();
+        assert!(stats.partial);
+        assert_eq!(stats.total_fees, 0);
+    }
+
+    #[test]
+    fn test_nearest_rank() {
+        let mut rates = (1..=10).rev().map(f64::from).collect::<Vec<_>>();
+        assert_eq!(
+            nearest_rank(&mut rates, &[0, 10, 50, 90, 100]),
+            Some(vec![1.0, 1.0, 5.0, 9.0, 10.0])
+        );
+        assert_eq!(nearest_rank(&mut [], &[50]), None);
+    }
+}
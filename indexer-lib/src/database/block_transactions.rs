@@ -0,0 +1,71 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::{RpcHash, RpcTransactionId};
+
+/// Partition listing the transactions of each block.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [tx_id (32 bytes each)], in block order, the coinbase first
+///
+/// Written at ingest for blocks received with their transactions and pruned together with the
+/// headers. Blocks synced header only have no entry.
+#[derive(Clone)]
+pub struct BlockTransactionsPartition(fjall::TxPartition);
+
+impl DescribePartition for BlockTransactionsPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_transactions",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[field("tx_ids", FieldType::Tail("hash[]"))],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockTransactionsPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        tx_ids: &[RpcTransactionId],
+    ) {
+        let value = tx_ids
+            .iter()
+            .flat_map(|tx_id| tx_id.as_bytes())
+            .collect::<Vec<_>>();
+        wtx.insert(&self.0, block_hash.as_bytes(), value);
+    }
+
+    /// None for blocks stored without their transactions
+    pub fn get_block_transactions_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<Vec<RpcTransactionId>>> {
+        rtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+
+    pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
+        self.0.remove(block_hash.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn decode(value: &[u8]) -> Result<Vec<RpcTransactionId>> {
+    if value.len() % 32 != 0 {
+        bail!("Invalid block transactions length");
+    }
+    Ok(value
+        .chunks_exact(32)
+        .map(RpcTransactionId::from_slice)
+        .collect())
+}
@@ -0,0 +1,204 @@
+//! Major compaction of single partitions, dropping the tombstones pruning leaves behind.
+//!
+//! The scheduled task compacts one partition per run, in name order, and only while the
+//! indexer keeps up with the node: a major compaction rewrites the whole partition and competes
+//! with the block processor for disk bandwidth and write buffer space.
+
+use crate::metrics::SharedMetrics;
+use crate::scheduler::{PeriodicTask, TaskContext};
+use anyhow::Result;
+use fjall::{PartitionCreateOptions, TxKeyspace};
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Compaction is deferred while the block tip is further behind the node
+pub const DEFAULT_COMPACTION_MAX_LAG_DAA: u64 = 100;
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Smaller partitions are skipped, rewriting them reclaims next to nothing
+const MIN_COMPACTION_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionRun {
+    pub partition: String,
+    pub started_at_unix_ms: u64,
+    pub duration: Duration,
+    /// Disk bytes before minus after, zero if the partition grew meanwhile
+    pub reclaimed_bytes: u64,
+}
+
+impl fmt::Display for CompactionRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes reclaimed in {:?}",
+            self.partition, self.reclaimed_bytes, self.duration
+        )
+    }
+}
+
+/// Runs a major compaction of the partition
+pub fn compact_partition(keyspace: &TxKeyspace, name: &str) -> Result<CompactionRun> {
+    if !keyspace.partition_exists(name) {
+        anyhow::bail!("No partition {name}");
+    }
+    // returns the already opened handle, options are ignored for existing partitions
+    let partition = keyspace.open_partition(name, PartitionCreateOptions::default())?;
+    let inner = partition.inner();
+    let started_at_unix_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let started = Instant::now();
+    let before = inner.disk_space();
+    inner.major_compact()?;
+    Ok(CompactionRun {
+        partition: name.to_string(),
+        started_at_unix_ms,
+        duration: started.elapsed(),
+        reclaimed_bytes: before.saturating_sub(inner.disk_space()),
+    })
+}
+
+/// Compacts every partition in turn, for the maintenance command
+pub fn compact_all(keyspace: &TxKeyspace) -> Result<Vec<CompactionRun>> {
+    let mut names = keyspace
+        .list_partitions()
+        .into_iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+        .iter()
+        .map(|name| compact_partition(keyspace, name))
+        .collect()
+}
+
+/// Compacts the next partition in name order on every run
+pub struct PartitionCompaction {
+    tx_keyspace: TxKeyspace,
+    metrics: SharedMetrics,
+    max_lag_daa: u64,
+    /// Last partition compacted
+    last: Option<String>,
+}
+
+impl PartitionCompaction {
+    pub fn new(tx_keyspace: TxKeyspace, metrics: SharedMetrics, max_lag_daa: u64) -> Self {
+        Self {
+            tx_keyspace,
+            metrics,
+            max_lag_daa,
+            last: None,
+        }
+    }
+
+    /// The partition after the last compacted one big enough to compact, wrapping around
+    fn next_partition(&self) -> Option<String> {
+        let mut names = self
+            .tx_keyspace
+            .list_partitions()
+            .into_iter()
+            .map(|name| name.to_string())
+            .filter(|name| {
+                self.tx_keyspace
+                    .open_partition(name, PartitionCreateOptions::default())
+                    .is_ok_and(|partition| partition.inner().disk_space() >= MIN_COMPACTION_BYTES)
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        let after = self.last.as_ref();
+        names
+            .iter()
+            .find(|name| after.is_none_or(|last| *name > last))
+            .or(names.first())
+            .cloned()
+    }
+}
+
+impl PeriodicTask for PartitionCompaction {
+    fn name(&self) -> &'static str {
+        "compact_partitions"
+    }
+
+    fn interval(&self) -> Duration {
+        COMPACTION_INTERVAL
+    }
+
+    /// A major compaction can't be interrupted, only checked before starting
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    fn run(&mut self, ctx: &TaskContext) -> Result<()> {
+        let lag = self.metrics.block_lag_daa.load(Ordering::Relaxed);
+        if lag > self.max_lag_daa {
+            debug!(lag, "Indexer is behind the node, compaction deferred");
+            self.metrics.increment_compactions_deferred();
+            return Ok(());
+        }
+        let Some(name) = self.next_partition() else {
+            return Ok(());
+        };
+        if ctx.is_cancelled() {
+            return Ok(());
+        }
+        let run = compact_partition(&self.tx_keyspace, &name)?;
+        info!("Compacted {run}");
+        self.metrics.record_compaction(&run);
+        self.last = Some(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::create_shared_metrics;
+
+    #[test]
+    fn test_compaction_reclaims_deleted_keys() {
+        let keyspace = crate::database::test_keyspace("compaction");
+        let partition = keyspace
+            .open_partition("deletes", PartitionCreateOptions::default())
+            .unwrap();
+        keyspace
+            .open_partition("empty", PartitionCreateOptions::default())
+            .unwrap();
+        // values hardly compress, so the data takes its size on disk
+        let value = |key: u64| {
+            (0..256u64)
+                .map(|i| ((key * 256 + i).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 56) as u8)
+                .collect::<Vec<_>>()
+        };
+        // flushed in several segments, followed by one of tombstones only
+        for batch in 0..4u64 {
+            for i in 0..5_000u64 {
+                let key = batch * 5_000 + i;
+                partition.insert(key.to_be_bytes(), value(key)).unwrap();
+            }
+            partition.inner().rotate_memtable_and_wait().unwrap();
+        }
+        for i in 0..20_000u64 {
+            partition.remove(i.to_be_bytes()).unwrap();
+        }
+        partition.inner().rotate_memtable_and_wait().unwrap();
+        let before = partition.inner().disk_space();
+        assert!(before >= MIN_COMPACTION_BYTES);
+
+        let metrics = create_shared_metrics();
+        metrics.block_lag_daa.store(1_000, Ordering::Relaxed);
+        let mut task = PartitionCompaction::new(keyspace.clone(), metrics.clone(), 100);
+        task.run(&TaskContext::default()).unwrap();
+        assert_eq!(partition.inner().disk_space(), before);
+        assert_eq!(metrics.snapshot().compactions_deferred, 1);
+
+        metrics.block_lag_daa.store(0, Ordering::Relaxed);
+        task.run(&TaskContext::default()).unwrap();
+        assert!(partition.inner().disk_space() < before);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.compactions, 1);
+        assert!(snapshot.compaction_reclaimed_bytes > 0);
+        assert_eq!(task.last.as_deref(), Some("deletes"));
+        // the empty partition is too small to compact
+        assert_eq!(task.next_partition(), None);
+    }
+}
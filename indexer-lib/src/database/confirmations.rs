@@ -0,0 +1,99 @@
+use crate::database::headers::{
+    BlockCompactHeaderPartition, ChainIndexByHashPartition, ChainIndexPartition,
+};
+use crate::database::processing::{
+    AcceptanceGapsPartition, AcceptingBlockResolutionData, TxIDToAcceptancePartition,
+};
+use anyhow::Result;
+use fjall::{ReadTransaction, TxKeyspace};
+use kaspa_rpc_core::{RpcHash, RpcTransactionId};
+
+/// Confirmation counts of indexed transactions, measured along the chain index.
+///
+/// The accepting chain block counts as the first confirmation. The chain index and the
+/// acceptance are committed together by the virtual chain processor, so counts drop back
+/// as soon as a reorg removes the accepting block. Acceptance of transactions in blocks within
+/// an acceptance gap is unknown rather than missing.
+#[derive(Clone)]
+pub struct Confirmations {
+    keyspace: TxKeyspace,
+    tx_id_to_acceptance_partition: TxIDToAcceptancePartition,
+    chain_index_partition: ChainIndexPartition,
+    chain_index_by_hash_partition: ChainIndexByHashPartition,
+    block_compact_header_partition: BlockCompactHeaderPartition,
+    acceptance_gaps_partition: AcceptanceGapsPartition,
+}
+
+impl Confirmations {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self {
+            keyspace: keyspace.clone(),
+            tx_id_to_acceptance_partition: TxIDToAcceptancePartition::new(keyspace)?,
+            chain_index_partition: ChainIndexPartition::new(keyspace)?,
+            chain_index_by_hash_partition: ChainIndexByHashPartition::new(keyspace)?,
+            block_compact_header_partition: BlockCompactHeaderPartition::new(keyspace)?,
+            acceptance_gaps_partition: AcceptanceGapsPartition::new(keyspace)?,
+        })
+    }
+
+    /// None for transactions which were not indexed, whose accepting block is missing from
+    /// the chain index or whose block lies within an acceptance gap, zero for transactions
+    /// seen in blocks but not accepted yet
+    pub fn get_confirmations(&self, tx_id: &RpcTransactionId) -> Result<Option<u64>> {
+        self.get_confirmations_rtx(&self.keyspace.read_tx(), tx_id)
+    }
+
+    pub fn get_confirmations_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tx_id: &RpcTransactionId,
+    ) -> Result<Option<u64>> {
+        let Some(entry) = self
+            .tx_id_to_acceptance_partition
+            .get_by_tx_id(rtx, &tx_id.as_bytes())
+            .next()
+        else {
+            return Ok(None);
+        };
+        let (key, resolution) = entry?;
+        let accepting_block_hash = RpcHash::from_slice(&key.accepted_by_block_hash);
+        if accepting_block_hash == RpcHash::default() {
+            return if self.in_acceptance_gap_rtx(rtx, &resolution)? {
+                Ok(None)
+            } else {
+                Ok(Some(0))
+            };
+        }
+        let Some(accepting_index) = self
+            .chain_index_by_hash_partition
+            .get_chain_index_rtx(rtx, &accepting_block_hash)?
+        else {
+            return Ok(None);
+        };
+        let Some(tip_index) = self.chain_index_partition.chain_tip_index_rtx(rtx)? else {
+            return Ok(None);
+        };
+        Ok(Some(tip_index.saturating_sub(accepting_index) + 1))
+    }
+
+    fn in_acceptance_gap_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        resolution: &AcceptingBlockResolutionData,
+    ) -> Result<bool> {
+        let block_hash = match resolution {
+            AcceptingBlockResolutionData::HandshakeKey(key) => key.block_hash,
+            AcceptingBlockResolutionData::ContextualMessageKey(key) => key.block_hash,
+            AcceptingBlockResolutionData::PaymentKey(key) => key.block_hash,
+            AcceptingBlockResolutionData::None => return Ok(false),
+        };
+        let Some(daa_score) = self
+            .block_compact_header_partition
+            .get_daa_score_rtx(rtx, &RpcHash::from_bytes(block_hash))?
+        else {
+            return Ok(false);
+        };
+        self.acceptance_gaps_partition
+            .covers_daa_rtx(rtx, daa_score)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::PartitionCreateOptions;
+use std::fmt;
+
+/// Reports kept in the partition, older ones are dropped on insert
+pub const MAX_CRASH_REPORTS: usize = 20;
+
+/// Partition keeping the last [`MAX_CRASH_REPORTS`] crash reports.
+///
+/// Key: recorded_at unix millis (BE).
+/// Value: viewed flag (u8) followed by the utf-8 report.
+///
+/// Reports are written from the panic hook, possibly while the panicking thread holds the
+/// keyspace write lock, so all writes bypass transactions and go to the partition directly.
+#[derive(Clone)]
+pub struct CrashReportsPartition(fjall::TxPartition);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub recorded_at_ms: u64,
+    pub viewed: bool,
+    pub report: String,
+}
+
+impl CrashReport {
+    /// First line of the report, the panic message or the error
+    pub fn summary(&self) -> &str {
+        self.report.lines().next().unwrap_or_default()
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<Self> {
+        if key.len() != 8 || value.is_empty() {
+            bail!("Invalid crash report length");
+        }
+        Ok(Self {
+            recorded_at_ms: u64::from_be_bytes(key.try_into()?),
+            viewed: value[0] != 0,
+            report: String::from_utf8_lossy(&value[1..]).into_owned(),
+        })
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}: {}",
+            self.recorded_at_ms,
+            if self.viewed { "" } else { " (new)" },
+            self.summary()
+        )
+    }
+}
+
+impl DescribePartition for CrashReportsPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "crash_reports",
+        key: &[field("recorded_at_ms", FieldType::U64Be)],
+        value: &[
+            field("viewed", FieldType::U8),
+            field("report", FieldType::Tail("utf8")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl CrashReportsPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert(&self, recorded_at_ms: u64, report: &str) -> Result<()> {
+        let partition = self.0.inner();
+        let mut value = Vec::with_capacity(1 + report.len());
+        value.push(0);
+        value.extend_from_slice(report.as_bytes());
+        partition.insert(recorded_at_ms.to_be_bytes(), value)?;
+
+        let excess = partition.len()?.saturating_sub(MAX_CRASH_REPORTS);
+        let oldest = partition
+            .keys()
+            .take(excess)
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in oldest {
+            partition.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// All reports, oldest first
+    pub fn list(&self) -> Result<Vec<CrashReport>> {
+        self.0
+            .inner()
+            .iter()
+            .map(|kv| {
+                let (key, value) = kv?;
+                CrashReport::decode(&key, &value)
+            })
+            .collect()
+    }
+
+    pub fn get(&self, recorded_at_ms: u64) -> Result<Option<CrashReport>> {
+        let key = recorded_at_ms.to_be_bytes();
+        self.0
+            .inner()
+            .get(key)?
+            .map(|value| CrashReport::decode(&key, &value))
+            .transpose()
+    }
+
+    pub fn mark_viewed(&self, recorded_at_ms: u64) -> Result<()> {
+        let partition = self.0.inner();
+        let key = recorded_at_ms.to_be_bytes();
+        if let Some(value) = partition.get(key)? {
+            let mut value = value.to_vec();
+            value[0] = 1;
+            partition.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Removes all reports, returns the amount removed
+    pub fn clear(&self) -> Result<usize> {
+        let partition = self.0.inner();
+        let keys = partition.keys().collect::<Result<Vec<_>, _>>()?;
+        for key in &keys {
+            partition.remove(key.clone())?;
+        }
+        Ok(keys.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut value = vec![0];
+        value.extend_from_slice(b"panicked at 'boom'\nbacktrace");
+        let report = CrashReport::decode(&42u64.to_be_bytes(), &value).unwrap();
+        assert_eq!(report.recorded_at_ms, 42);
+        assert!(!report.viewed);
+        assert_eq!(report.summary(), "panicked at 'boom'");
+        assert_eq!(report.to_string(), "42 (new): panicked at 'boom'");
+
+        assert!(CrashReport::decode(&[1, 2], &value).is_err());
+    }
+}
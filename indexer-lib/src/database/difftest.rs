@@ -0,0 +1,330 @@
+use crate::database::schema::{self, PartitionDescription};
+use anyhow::{Context, Result};
+use fjall::{PartitionCreateOptions, ReadTransaction, TxKeyspace, UserKey, UserValue};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::hash::{DefaultHasher, Hasher};
+use std::iter::Peekable;
+use std::path::Path;
+use tracing::debug;
+
+/// Amount of differing keys kept per diverged partition
+const MAX_SAMPLES: usize = 10;
+
+/// Partitions whose differences are intentional, e.g. added by a migration.
+///
+/// Manifest format: one partition name per line, `#` starts a comment.
+#[derive(Debug, Clone, Default)]
+pub struct Whitelist(HashSet<String>);
+
+impl Whitelist {
+    pub fn parse(manifest: &str) -> Self {
+        Self(
+            manifest
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        Ok(Self::parse(&std::fs::read_to_string(path).with_context(
+            || format!("Failed to read whitelist {}", path.display()),
+        )?))
+    }
+
+    pub fn contains(&self, partition: &str) -> bool {
+        self.0.contains(partition)
+    }
+}
+
+/// Summary of a partition's content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PartitionDigest {
+    pub entries: u64,
+    pub digest: u64,
+}
+
+/// Entries are visited in key order, so equal content yields an equal digest
+pub fn digest_partition(keyspace: &TxKeyspace, name: &str) -> Result<PartitionDigest> {
+    digest_partition_rtx(keyspace, &keyspace.read_tx(), name)
+}
+
+/// Digest of the partition as seen by `rtx`
+pub fn digest_partition_rtx(
+    keyspace: &TxKeyspace,
+    rtx: &ReadTransaction,
+    name: &str,
+) -> Result<PartitionDigest> {
+    let partition = keyspace.open_partition(name, PartitionCreateOptions::default())?;
+    let mut hasher = DefaultHasher::new();
+    let mut entries = 0;
+    for kv in rtx.iter(&partition) {
+        let (key, value) = kv?;
+        hasher.write_usize(key.len());
+        hasher.write(&key);
+        hasher.write_usize(value.len());
+        hasher.write(&value);
+        entries += 1;
+    }
+    Ok(PartitionDigest {
+        entries,
+        digest: hasher.finish(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyDiff {
+    OnlyLeft(String),
+    OnlyRight(String),
+    ValueDiffers {
+        key: String,
+        left: String,
+        right: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionStatus {
+    Equal,
+    /// Differs, but listed in the whitelist
+    Whitelisted,
+    OnlyLeft,
+    OnlyRight,
+    Diverged {
+        left: PartitionDigest,
+        right: PartitionDigest,
+        samples: Vec<KeyDiff>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionDiff {
+    pub name: String,
+    pub status: PartitionStatus,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffReport {
+    pub partitions: Vec<PartitionDiff>,
+}
+
+impl DiffReport {
+    /// True when every difference is whitelisted
+    pub fn is_clean(&self) -> bool {
+        self.partitions.iter().all(|p| {
+            matches!(
+                p.status,
+                PartitionStatus::Equal | PartitionStatus::Whitelisted
+            )
+        })
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for partition in &self.partitions {
+            match &partition.status {
+                PartitionStatus::Equal => writeln!(f, "{}: equal", partition.name)?,
+                PartitionStatus::Whitelisted => {
+                    writeln!(f, "{}: differs (whitelisted)", partition.name)?
+                }
+                PartitionStatus::OnlyLeft => writeln!(f, "{}: only in left", partition.name)?,
+                PartitionStatus::OnlyRight => writeln!(f, "{}: only in right", partition.name)?,
+                PartitionStatus::Diverged {
+                    left,
+                    right,
+                    samples,
+                } => {
+                    writeln!(
+                        f,
+                        "{}: DIVERGED, left {} entries, right {} entries",
+                        partition.name, left.entries, right.entries
+                    )?;
+                    for sample in samples {
+                        match sample {
+                            KeyDiff::OnlyLeft(key) => writeln!(f, "  only left:  {key}")?,
+                            KeyDiff::OnlyRight(key) => writeln!(f, "  only right: {key}")?,
+                            KeyDiff::ValueDiffers { key, left, right } => {
+                                writeln!(f, "  value differs: {key}")?;
+                                writeln!(f, "    left:  {left}")?;
+                                writeln!(f, "    right: {right}")?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two databases partition by partition.
+///
+/// Digests are compared first, only diverged partitions are walked again to sample
+/// differing keys, which are decoded through the partition's schema description.
+pub fn compare(left: &TxKeyspace, right: &TxKeyspace, whitelist: &Whitelist) -> Result<DiffReport> {
+    let names = left
+        .list_partitions()
+        .into_iter()
+        .chain(right.list_partitions())
+        .map(|name| name.to_string())
+        .collect::<BTreeSet<_>>();
+    let descriptions = schema::describe_all();
+
+    let mut report = DiffReport::default();
+    for name in names {
+        let status = match (left.partition_exists(&name), right.partition_exists(&name)) {
+            (true, true) => {
+                let left_digest = digest_partition(left, &name)?;
+                let right_digest = digest_partition(right, &name)?;
+                if left_digest == right_digest {
+                    PartitionStatus::Equal
+                } else if whitelist.contains(&name) {
+                    PartitionStatus::Whitelisted
+                } else {
+                    let description = descriptions.iter().find(|d| d.name == name);
+                    PartitionStatus::Diverged {
+                        left: left_digest,
+                        right: right_digest,
+                        samples: sample_differences(left, right, &name, description)?,
+                    }
+                }
+            }
+            _ if whitelist.contains(&name) => PartitionStatus::Whitelisted,
+            (true, false) => PartitionStatus::OnlyLeft,
+            _ => PartitionStatus::OnlyRight,
+        };
+        debug!(partition = %name, ?status, "Partition compared");
+        report.partitions.push(PartitionDiff { name, status });
+    }
+    Ok(report)
+}
+
+/// Opens both databases and compares them, see [`compare`]
+pub fn compare_paths(
+    left: impl AsRef<Path>,
+    right: impl AsRef<Path>,
+    whitelist: &Whitelist,
+) -> Result<DiffReport> {
+    let left = fjall::Config::new(left).open_transactional()?;
+    let right = fjall::Config::new(right).open_transactional()?;
+    compare(&left, &right, whitelist)
+}
+
+fn sample_differences(
+    left: &TxKeyspace,
+    right: &TxKeyspace,
+    name: &str,
+    description: Option<&PartitionDescription>,
+) -> Result<Vec<KeyDiff>> {
+    let left_partition = left.open_partition(name, PartitionCreateOptions::default())?;
+    let right_partition = right.open_partition(name, PartitionCreateOptions::default())?;
+    let left_rtx = left.read_tx();
+    let right_rtx = right.read_tx();
+    merge_diff(
+        left_rtx.iter(&left_partition),
+        right_rtx.iter(&right_partition),
+        |key| render(description.map(|d| d.key), key),
+        |value| render(description.map(|d| d.value), value),
+    )
+}
+
+fn render(fields: Option<&[schema::Field]>, bytes: &[u8]) -> String {
+    schema::render_fields(fields.unwrap_or_default(), bytes)
+}
+
+type Entry = fjall::Result<(UserKey, UserValue)>;
+
+/// Walks two key-ordered streams side by side and collects up to [`MAX_SAMPLES`] differences
+fn merge_diff(
+    left: impl Iterator<Item = Entry>,
+    right: impl Iterator<Item = Entry>,
+    render_key: impl Fn(&[u8]) -> String,
+    render_value: impl Fn(&[u8]) -> String,
+) -> Result<Vec<KeyDiff>> {
+    let mut left = left.peekable();
+    let mut right = right.peekable();
+    let mut samples = Vec::new();
+    while samples.len() < MAX_SAMPLES {
+        let order = match (peek_key(&mut left)?, peek_key(&mut right)?) {
+            (None, None) => break,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(l), Some(r)) => l.cmp(&r),
+        };
+        match order {
+            std::cmp::Ordering::Less => {
+                let (key, _) = left.next().unwrap()?;
+                samples.push(KeyDiff::OnlyLeft(render_key(&key)));
+            }
+            std::cmp::Ordering::Greater => {
+                let (key, _) = right.next().unwrap()?;
+                samples.push(KeyDiff::OnlyRight(render_key(&key)));
+            }
+            std::cmp::Ordering::Equal => {
+                let (key, left_value) = left.next().unwrap()?;
+                let (_, right_value) = right.next().unwrap()?;
+                if left_value != right_value {
+                    samples.push(KeyDiff::ValueDiffers {
+                        key: render_key(&key),
+                        left: render_value(&left_value),
+                        right: render_value(&right_value),
+                    });
+                }
+            }
+        }
+    }
+    Ok(samples)
+}
+
+fn peek_key(iter: &mut Peekable<impl Iterator<Item = Entry>>) -> Result<Option<UserKey>> {
+    match iter.peek() {
+        None => Ok(None),
+        Some(Ok((key, _))) => Ok(Some(key.clone())),
+        Some(Err(_)) => Err(iter.next().unwrap().unwrap_err().into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(kvs: &[(&[u8], &[u8])]) -> Vec<Entry> {
+        kvs.iter()
+            .map(|(k, v)| Ok((UserKey::from(*k), UserValue::from(*v))))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_diff() {
+        let left = entries(&[(b"a", b"1"), (b"b", b"1"), (b"d", b"1")]);
+        let right = entries(&[(b"a", b"1"), (b"b", b"2"), (b"c", b"1")]);
+        let hex = |bytes: &[u8]| schema::render_fields(&[], bytes);
+        let samples = merge_diff(left.into_iter(), right.into_iter(), hex, hex).unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                KeyDiff::ValueDiffers {
+                    key: "rest=62".to_string(),
+                    left: "rest=31".to_string(),
+                    right: "rest=32".to_string(),
+                },
+                KeyDiff::OnlyRight("rest=63".to_string()),
+                KeyDiff::OnlyLeft("rest=64".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitelist_manifest() {
+        let whitelist =
+            Whitelist::parse("# added in 0.2\norphan_pool\n\n  provenance # migrated\n");
+        assert!(whitelist.contains("orphan_pool"));
+        assert!(whitelist.contains("provenance"));
+        assert!(!whitelist.contains("metadata"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Portable dumps of single partitions.
+//!
+//! Stream layout, all integers BE:
+//! - header: magic `KIDXDUMP`, schema version (u32), partition name length (u16) + name,
+//!   record count (u64)
+//! - records: key length (u16) + key, value length (u32) + value
+
+use crate::database::schema::{self, SCHEMA_VERSION};
+use anyhow::{Context, Result, bail, ensure};
+use fjall::{PartitionCreateOptions, TxKeyspace};
+use std::io::{ErrorKind, Read, Write};
+use tracing::info;
+
+const MAGIC: &[u8; 8] = b"KIDXDUMP";
+/// Amount of records written per write transaction on import
+const IMPORT_BATCH_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpHeader {
+    pub schema_version: u32,
+    pub partition: String,
+    pub records: u64,
+}
+
+impl DumpHeader {
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.schema_version.to_be_bytes())?;
+        writer.write_all(&u16::try_from(self.partition.len())?.to_be_bytes())?;
+        writer.write_all(self.partition.as_bytes())?;
+        writer.write_all(&self.records.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self> {
+        let magic: [u8; 8] = read_array(reader).context("Failed to read dump header")?;
+        ensure!(&magic == MAGIC, "Not a partition dump");
+        let schema_version = u32::from_be_bytes(read_array(reader)?);
+        let name_len = u16::from_be_bytes(read_array(reader)?);
+        let partition = String::from_utf8(read_vec(reader, name_len as usize)?)
+            .context("Invalid partition name")?;
+        let records = u64::from_be_bytes(read_array(reader)?);
+        Ok(Self {
+            schema_version,
+            partition,
+            records,
+        })
+    }
+}
+
+/// Writes every entry of partition `name` as seen by a single read snapshot
+pub fn export_partition(
+    keyspace: &TxKeyspace,
+    name: &str,
+    writer: &mut impl Write,
+) -> Result<DumpHeader> {
+    ensure_registered(name)?;
+    if !keyspace.partition_exists(name) {
+        bail!("Partition {name} does not exist");
+    }
+    let partition = keyspace.open_partition(name, PartitionCreateOptions::default())?;
+    let rtx = keyspace.read_tx();
+    let header = DumpHeader {
+        schema_version: SCHEMA_VERSION,
+        partition: name.to_string(),
+        records: rtx.len(&partition)? as u64,
+    };
+    header.write(writer)?;
+
+    let mut records = 0;
+    for kv in rtx.iter(&partition) {
+        let (key, value) = kv?;
+        writer.write_all(&u16::try_from(key.len())?.to_be_bytes())?;
+        writer.write_all(&key)?;
+        writer.write_all(&u32::try_from(value.len())?.to_be_bytes())?;
+        writer.write_all(&value)?;
+        records += 1;
+    }
+    writer.flush()?;
+    ensure!(records == header.records, "Partition changed during export");
+    info!(partition = %name, records, "Partition exported");
+    Ok(header)
+}
+
+/// Loads a dump produced by [`export_partition`] into the partition named in its header.
+///
+/// Dumps of another schema version are rejected before anything is written.
+/// Existing entries with the same keys are overwritten.
+pub fn import_partition(keyspace: &TxKeyspace, reader: &mut impl Read) -> Result<DumpHeader> {
+    let header = DumpHeader::read(reader)?;
+    if header.schema_version != SCHEMA_VERSION {
+        bail!(
+            "Dump of partition {} has schema version {}, database expects {SCHEMA_VERSION}",
+            header.partition,
+            header.schema_version
+        );
+    }
+    ensure_registered(&header.partition)?;
+    let partition =
+        keyspace.open_partition(&header.partition, PartitionCreateOptions::default())?;
+
+    let mut wtx = keyspace.write_tx()?;
+    let mut batch_len = 0;
+    for record in 0..header.records {
+        let (key, value) = read_record(reader)
+            .with_context(|| format!("Dump truncated at record {record} of {}", header.records))?;
+        wtx.insert(&partition, key, value);
+        batch_len += 1;
+        if batch_len == IMPORT_BATCH_SIZE {
+            std::mem::replace(&mut wtx, keyspace.write_tx()?).commit()??;
+            batch_len = 0;
+        }
+    }
+    wtx.commit()??;
+    ensure!(
+        read_array::<1>(reader).is_err_and(|err| err.kind() == ErrorKind::UnexpectedEof),
+        "Dump has trailing data after {} records",
+        header.records
+    );
+    info!(partition = %header.partition, records = header.records, "Partition imported");
+    Ok(header)
+}
+
+fn ensure_registered(name: &str) -> Result<()> {
+    if !schema::describe_all().iter().any(|d| d.name == name) {
+        bail!("Unknown partition {name}");
+    }
+    Ok(())
+}
+
+fn read_record(reader: &mut impl Read) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let key_len = u16::from_be_bytes(read_array(reader)?);
+    let key = read_vec(reader, key_len as usize)?;
+    let value_len = u32::from_be_bytes(read_array(reader)?);
+    let value = read_vec(reader, value_len as usize)?;
+    Ok((key, value))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_vec(reader: &mut impl Read, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = DumpHeader {
+            schema_version: SCHEMA_VERSION,
+            partition: "block_gaps".to_string(),
+            records: 42,
+        };
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(DumpHeader::read(&mut bytes.as_slice()).unwrap(), header);
+
+        bytes[0] = b'X';
+        assert!(DumpHeader::read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_read_record() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u16.to_be_bytes());
+        bytes.extend_from_slice(b"ab");
+        bytes.extend_from_slice(&3u32.to_be_bytes());
+        bytes.extend_from_slice(b"xyz");
+        let (key, value) = read_record(&mut bytes.as_slice()).unwrap();
+        assert_eq!(
+            (key.as_slice(), value.as_slice()),
+            (&b"ab"[..], &b"xyz"[..])
+        );
+
+        // truncated value
+        assert!(read_record(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -1,9 +1,16 @@
 use crate::CompactHeader;
-use anyhow::{Result, bail};
+use crate::database::headers::header_cache::{
+    DEFAULT_HEADER_CACHE_CAPACITY, HeaderCache, HeaderCacheStats,
+};
+use crate::database::headers::header_codec::{self, HeaderStorageMode, StoredHeader};
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::Result;
 use bytemuck::{AnyBitPattern, NoUninit};
 use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
 use kaspa_consensus_core::BlueWorkType;
-use kaspa_rpc_core::RpcHash;
+use kaspa_rpc_core::{RpcHash, RpcHeader};
+use std::ops::Bound;
+use std::sync::Arc;
 
 /// FIFO partition for storing block hash to compact header data (blue work + DAA score)
 /// Can store both Blue Work and DAA score for any block
@@ -12,10 +19,16 @@ use kaspa_rpc_core::RpcHash;
 /// Uses FIFO compaction strategy because:
 /// - Block header data is temporary - older blocks become irrelevant
 /// - Self-balancing: automatically removes old entries when size limit reached
+///
+/// Values use the versioned [`header_codec`], the storage mode only affects new writes,
+/// reads decode values of any mode and version.
+///
+/// Compact header lookups go through a [`HeaderCache`] shared by all clones. Headers are
+/// immutable per hash, so reads within a transaction may be served from the cache as well.
 #[derive(Clone)]
-pub struct BlockCompactHeaderPartition(fjall::TxPartition);
+pub struct BlockCompactHeaderPartition(fjall::TxPartition, HeaderStorageMode, Arc<HeaderCache>);
 
-/// Compact header data containing blue work and DAA score
+/// Compact header record of the header values written before the codec was versioned, see [`header_codec`]
 #[derive(Clone, Copy, Debug, AnyBitPattern, NoUninit, PartialEq, Eq)]
 #[repr(C)]
 pub struct CompactHeaderDb {
@@ -32,11 +45,29 @@ impl From<CompactHeaderDb> for CompactHeader {
     }
 }
 
+impl DescribePartition for BlockCompactHeaderPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_compact_header",
+        key: &[field("block_hash", FieldType::Hash)],
+        // the layout depends on the storage mode, see header_codec
+        value: &[field(
+            "header",
+            FieldType::Tail("header_codec(compact_record|full_header)"),
+        )],
+        value_version: header_codec::HEADER_CODEC_VERSION,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl BlockCompactHeaderPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Self::new_with_mode(keyspace, HeaderStorageMode::Compact)
+    }
+
+    pub fn new_with_mode(keyspace: &fjall::TxKeyspace, mode: HeaderStorageMode) -> Result<Self> {
         Ok(Self(
-            keyspace.open_partition(
-                "block_compact_header",
+            schema::open_partition::<Self>(
+                keyspace,
                 PartitionCreateOptions::default()
                     .block_size(64 * 1024)
                     .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
@@ -46,21 +77,74 @@ impl BlockCompactHeaderPartition {
                         },
                     )),
             )?,
+            mode,
+            Arc::new(HeaderCache::new(DEFAULT_HEADER_CACHE_CAPACITY)),
         ))
     }
 
+    /// Replaces the header cache, a zero capacity disables caching
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.2 = Arc::new(HeaderCache::new(capacity));
+        self
+    }
+
+    pub fn mode(&self) -> HeaderStorageMode {
+        self.1
+    }
+
+    pub fn cache_stats(&self) -> HeaderCacheStats {
+        self.2.stats()
+    }
+
+    /// Stores the header according to the partition's storage mode
+    pub fn insert_header(&self, header: &RpcHeader) -> Result<()> {
+        self.0.insert(
+            header.hash.as_bytes(),
+            header_codec::encode(self.1, header)?,
+        )?;
+        self.2.insert(
+            header.hash,
+            CompactHeader {
+                blue_work: header.blue_work,
+                daa_score: header.daa_score,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stores the header as part of `wtx`. The cache is filled right away, headers are
+    /// immutable per hash
+    pub fn insert_header_wtx(&self, wtx: &mut WriteTransaction, header: &RpcHeader) -> Result<()> {
+        wtx.insert(
+            &self.0,
+            header.hash.as_bytes(),
+            header_codec::encode(self.1, header)?,
+        );
+        self.2.insert(
+            header.hash,
+            CompactHeader {
+                blue_work: header.blue_work,
+                daa_score: header.daa_score,
+            },
+        );
+        Ok(())
+    }
+
     pub fn insert_compact_header(
         &self,
         block_hash: &RpcHash,
         blue_work: BlueWorkType,
         daa_score: u64,
     ) -> Result<()> {
-        let header = CompactHeaderDb {
-            blue_work: blue_work.to_le_bytes(),
-            daa_score: daa_score.to_le_bytes(),
+        let header = CompactHeader {
+            blue_work,
+            daa_score,
         };
-        self.0
-            .insert(block_hash.as_bytes(), bytemuck::bytes_of(&header))?;
+        self.0.insert(
+            block_hash.as_bytes(),
+            header_codec::encode_compact(&header)?,
+        )?;
+        self.2.insert(*block_hash, header);
         Ok(())
     }
 
@@ -68,34 +152,24 @@ impl BlockCompactHeaderPartition {
         &self,
         rtx: &ReadTransaction,
         block_hash: &RpcHash,
-    ) -> Result<Option<CompactHeaderDb>> {
-        if let Some(bytes) = rtx.get(&self.0, block_hash.as_bytes())? {
-            if bytes.len() == size_of::<CompactHeaderDb>() {
-                let header: CompactHeaderDb = *bytemuck::from_bytes(&bytes);
-                Ok(Some(header))
-            } else {
-                bail!("Invalid CompactHeader length")
-            }
-        } else {
-            Ok(None)
-        }
+    ) -> Result<Option<CompactHeader>> {
+        self.2.get_or_load(block_hash, || {
+            rtx.get(&self.0, block_hash.as_bytes())?
+                .map(|bytes| header_codec::decode_compact(&bytes))
+                .transpose()
+        })
     }
 
     pub fn get_compact_header_wtx(
         &self,
         wtx: &mut WriteTransaction,
         block_hash: RpcHash,
-    ) -> Result<Option<CompactHeaderDb>> {
-        if let Some(bytes) = wtx.get(&self.0, block_hash.as_bytes())? {
-            if bytes.len() == size_of::<CompactHeaderDb>() {
-                let header: CompactHeaderDb = *bytemuck::from_bytes(&bytes);
-                Ok(Some(header))
-            } else {
-                bail!("Invalid CompactHeader length")
-            }
-        } else {
-            Ok(None)
-        }
+    ) -> Result<Option<CompactHeader>> {
+        self.2.get_or_load(&block_hash, || {
+            wtx.get(&self.0, block_hash.as_bytes())?
+                .map(|bytes| header_codec::decode_compact(&bytes))
+                .transpose()
+        })
     }
 
     pub fn get_blue_work_rtx(
@@ -104,7 +178,7 @@ impl BlockCompactHeaderPartition {
         block_hash: &RpcHash,
     ) -> Result<Option<BlueWorkType>> {
         if let Some(header) = self.get_compact_header_rtx(rtx, block_hash)? {
-            Ok(Some(BlueWorkType::from_le_bytes(header.blue_work)))
+            Ok(Some(header.blue_work))
         } else {
             Ok(None)
         }
@@ -116,23 +190,78 @@ impl BlockCompactHeaderPartition {
         block_hash: &RpcHash,
     ) -> Result<Option<u64>> {
         if let Some(header) = self.get_compact_header_rtx(rtx, block_hash)? {
-            Ok(Some(u64::from_le_bytes(header.daa_score)))
+            Ok(Some(header.daa_score))
         } else {
             Ok(None)
         }
     }
 
     pub fn get_compact_header(&self, block_hash: RpcHash) -> Result<Option<CompactHeader>> {
-        if let Some(bytes) = self.0.get(block_hash.as_bytes())? {
-            if bytes.len() == size_of::<CompactHeaderDb>() {
-                let header: CompactHeaderDb = *bytemuck::from_bytes(&bytes);
-                Ok(Some(header.into()))
-            } else {
-                bail!("Invalid CompactHeader length")
-            }
-        } else {
-            Ok(None)
-        }
+        self.2.get_or_load(&block_hash, || {
+            self.0
+                .get(block_hash.as_bytes())?
+                .map(|bytes| header_codec::decode_compact(&bytes))
+                .transpose()
+        })
+    }
+
+    /// Full header when stored in full mode, compact header otherwise
+    pub fn get_stored_header(&self, block_hash: RpcHash) -> Result<Option<StoredHeader>> {
+        self.0
+            .get(block_hash.as_bytes())?
+            .map(|bytes| header_codec::decode(&bytes))
+            .transpose()
+    }
+
+    /// Raw stored values in hash order, starting after `after`
+    pub fn scan_after_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        after: Option<RpcHash>,
+    ) -> impl Iterator<Item = Result<(RpcHash, fjall::Slice)>> {
+        let start = match after {
+            Some(hash) => Bound::Excluded(hash.as_bytes().to_vec()),
+            None => Bound::Unbounded,
+        };
+        rtx.range(&self.0, (start, Bound::Unbounded)).map(|r| {
+            let (key, value) = r?;
+            Ok((RpcHash::from_slice(&key), value))
+        })
+    }
+
+    /// Batched lookup of compact headers within a single read snapshot.
+    /// Every distinct hash is looked up once, cache misses are read in key order so
+    /// neighbouring keys share the loaded blocks.
+    /// Output preserves the order of `block_hashes`, missing blocks yield `None`
+    pub fn get_many_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hashes: &[RpcHash],
+    ) -> Result<Vec<Option<CompactHeader>>> {
+        get_many_sorted(block_hashes, |hash| self.get_compact_header_rtx(rtx, hash))
+    }
+
+    /// Same as [`Self::get_many_rtx`] for the stored headers, full ones in full mode. The
+    /// cache only holds compact headers, every distinct hash is read
+    pub fn get_many_stored_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hashes: &[RpcHash],
+    ) -> Result<Vec<Option<StoredHeader>>> {
+        get_many_sorted(block_hashes, |hash| {
+            rtx.get(&self.0, hash.as_bytes())?
+                .map(|bytes| header_codec::decode(&bytes))
+                .transpose()
+        })
+    }
+
+    /// Same as [`Self::get_many_rtx`] but opens its own read snapshot
+    pub fn get_many(
+        &self,
+        keyspace: &fjall::TxKeyspace,
+        block_hashes: &[RpcHash],
+    ) -> Result<Vec<Option<CompactHeader>>> {
+        self.get_many_rtx(&keyspace.read_tx(), block_hashes)
     }
 
     pub fn get_blue_work(&self, block_hash: RpcHash) -> Result<Option<BlueWorkType>> {
@@ -159,6 +288,21 @@ impl BlockCompactHeaderPartition {
         }
     }
 
+    /// Iterates over all stored headers in block hash order
+    pub fn iter_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+    ) -> impl Iterator<Item = Result<(RpcHash, CompactHeader)>> + 'a {
+        rtx.iter(&self.0).map(|item| {
+            let (key, value) = item?;
+            if key.len() != 32 {
+                anyhow::bail!("Invalid block hash length")
+            }
+            let header = header_codec::decode_compact(&value)?;
+            Ok((RpcHash::from_slice(&key), header))
+        })
+    }
+
     pub fn len(&self) -> Result<usize> {
         Ok(self.0.inner().len()?)
     }
@@ -169,10 +313,31 @@ impl BlockCompactHeaderPartition {
 
     pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
         self.0.remove(block_hash.as_bytes())?;
+        self.2.remove(block_hash);
         Ok(())
     }
 }
 
+/// Looks every distinct hash up once in key order, the output follows `block_hashes`
+fn get_many_sorted<T: Clone>(
+    block_hashes: &[RpcHash],
+    mut lookup: impl FnMut(&RpcHash) -> Result<Option<T>>,
+) -> Result<Vec<Option<T>>> {
+    let mut sorted = block_hashes.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let found = sorted.iter().map(&mut lookup).collect::<Result<Vec<_>>>()?;
+    Ok(block_hashes
+        .iter()
+        .map(|hash| {
+            sorted
+                .binary_search(hash)
+                .ok()
+                .and_then(|index| found[index].clone())
+        })
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,4 +347,132 @@ mod tests {
         let block_hash = RpcHash::from_slice(&[1u8; 32]);
         assert_eq!(block_hash.as_bytes().len(), 32);
     }
+
+    #[test]
+    fn test_compact_header_db_roundtrip() {
+        let db = CompactHeaderDb {
+            blue_work: BlueWorkType::from_u64(12345).to_le_bytes(),
+            daa_score: 42u64.to_le_bytes(),
+        };
+        let header: CompactHeader = db.into();
+        assert_eq!(header.blue_work, BlueWorkType::from_u64(12345));
+        assert_eq!(header.daa_score, 42);
+    }
+
+    #[test]
+    fn test_get_many() {
+        let keyspace = crate::database::test_keyspace("header-get-many");
+        let stored = (1..=5).map(RpcHash::from_u64_word).collect::<Vec<_>>();
+        let header = |i: u64| CompactHeader {
+            blue_work: BlueWorkType::from_u64(i * 10),
+            daa_score: i,
+        };
+        // cached and uncached partitions read the same values
+        for capacity in [0, DEFAULT_HEADER_CACHE_CAPACITY] {
+            let partition = BlockCompactHeaderPartition::new(&keyspace)
+                .unwrap()
+                .with_cache_capacity(capacity);
+            for (i, hash) in (1..).zip(&stored) {
+                let header = header(i);
+                partition
+                    .insert_compact_header(hash, header.blue_work, header.daa_score)
+                    .unwrap();
+            }
+            let missing = RpcHash::from_u64_word(9);
+            let hashes = [stored[3], missing, stored[0], stored[3], stored[2]];
+            let expected = vec![
+                Some(header(4)),
+                None,
+                Some(header(1)),
+                Some(header(4)),
+                Some(header(3)),
+            ];
+            assert_eq!(partition.get_many(&keyspace, &hashes).unwrap(), expected);
+            assert_eq!(
+                partition
+                    .get_many_rtx(&keyspace.read_tx(), &hashes)
+                    .unwrap(),
+                expected
+            );
+            assert!(partition.get_many(&keyspace, &[]).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_get_many_stored() {
+        use kaspa_consensus_core::header::Header;
+
+        let header = |i: u64| {
+            let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(i), vec![]);
+            header.daa_score = i;
+            RpcHeader::from(&header)
+        };
+        for mode in [HeaderStorageMode::Compact, HeaderStorageMode::Full] {
+            let keyspace = crate::database::test_keyspace(&format!("header-get-many-{mode:?}"));
+            let partition = BlockCompactHeaderPartition::new_with_mode(&keyspace, mode).unwrap();
+            for i in 1..=3 {
+                partition.insert_header(&header(i)).unwrap();
+            }
+            let hashes = [3, 9, 1, 3].map(RpcHash::from_u64_word);
+            let stored = partition
+                .get_many_stored_rtx(&keyspace.read_tx(), &hashes)
+                .unwrap();
+            assert_eq!(
+                stored
+                    .iter()
+                    .map(|header| header.as_ref().map(|header| header.compact().daa_score))
+                    .collect::<Vec<_>>(),
+                vec![Some(3), None, Some(1), Some(3)]
+            );
+            let full = stored
+                .iter()
+                .flatten()
+                .all(|header| matches!(header, StoredHeader::Full(_)));
+            assert_eq!(full, mode == HeaderStorageMode::Full);
+        }
+    }
+
+    #[test]
+    fn test_compact_mode_size() {
+        use kaspa_consensus_core::header::Header;
+
+        const HEADERS: u64 = 20_000;
+        let headers = (0..HEADERS).map(|i| {
+            let parents = (1..=3).map(|p| RpcHash::from_u64_word(i + p)).collect();
+            let mut header =
+                Header::from_precomputed_hash(RpcHash::from_u64_word(i + 1_000_000), parents);
+            header.daa_score = i;
+            header.blue_work = BlueWorkType::from_u64(i * 1_000);
+            RpcHeader::from(&header)
+        });
+        let stored_size = |mode: HeaderStorageMode| {
+            let keyspace = crate::database::test_keyspace(&format!("header-size-{mode:?}"));
+            let partition = BlockCompactHeaderPartition::new_with_mode(&keyspace, mode).unwrap();
+            let mut value_bytes = 0;
+            for header in headers.clone() {
+                partition.insert_header(&header).unwrap();
+                value_bytes += partition
+                    .0
+                    .get(header.hash.as_bytes())
+                    .unwrap()
+                    .unwrap()
+                    .len();
+            }
+            partition.0.inner().rotate_memtable_and_wait().unwrap();
+            (value_bytes, partition.0.inner().disk_space())
+        };
+
+        let (compact_values, compact_disk) = stored_size(HeaderStorageMode::Compact);
+        let (full_values, full_disk) = stored_size(HeaderStorageMode::Full);
+        // fixed size records, no larger than the values from before versioning
+        assert_eq!(
+            compact_values,
+            HEADERS as usize * header_codec::COMPACT_RECORD_LEN
+        );
+        assert!(
+            full_values > 5 * compact_values && full_disk > compact_disk,
+            "{HEADERS} headers, compact: {compact_values} value bytes, {compact_disk} on disk, \
+             full: {full_values} value bytes, {full_disk} on disk"
+        );
+    }
 }
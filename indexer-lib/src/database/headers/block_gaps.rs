@@ -1,3 +1,4 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use crate::historical_syncer::Cursor;
 use anyhow::Result;
 use bytemuck::{AnyBitPattern, NoUninit};
@@ -5,9 +6,16 @@ use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
 use kaspa_math::Uint192;
 use kaspa_rpc_core::RpcHash;
 
+/// Ranges of blocks missed while disconnected, keyed by [`BlockGapKey`].
+///
+/// The value is empty while the gap can be synced and [`UNRECOVERABLE_GAP`] once the node
+/// pruning point moved past its start. Unrecoverable gaps are kept for inspection but no
+/// longer handed out to syncers.
 #[derive(Clone)]
 pub struct BlockGapsPartition(fjall::TxPartition);
 
+pub const UNRECOVERABLE_GAP: &[u8] = &[1];
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, AnyBitPattern, NoUninit, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BlockGapKey {
@@ -53,10 +61,51 @@ impl BlockGap {
     }
 }
 
+impl From<&BlockGap> for BlockGapKey {
+    fn from(gap: &BlockGap) -> Self {
+        Self {
+            from_daa_score: gap.from_daa_score.to_be_bytes(),
+            from_blue_work: gap.from_blue_work.to_be_bytes(),
+            from_block_hash: gap.from_block_hash.as_bytes(),
+            to_blue_work: gap.to_blue_work.to_be_bytes(),
+            to_block_hash: gap.to_block_hash.as_bytes(),
+            to_daa_score: gap.to_daa_score.to_be_bytes(),
+        }
+    }
+}
+
+impl From<BlockGapKey> for BlockGap {
+    fn from(key: BlockGapKey) -> Self {
+        Self {
+            from_daa_score: u64::from_be_bytes(key.from_daa_score),
+            from_blue_work: Uint192::from_be_bytes(key.from_blue_work),
+            from_block_hash: RpcHash::from_slice(&key.from_block_hash),
+            to_blue_work: Uint192::from_be_bytes(key.to_blue_work),
+            to_block_hash: RpcHash::from_slice(&key.to_block_hash),
+            to_daa_score: u64::from_be_bytes(key.to_daa_score),
+        }
+    }
+}
+
+impl DescribePartition for BlockGapsPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_gaps",
+        key: &[
+            field("from_daa_score", FieldType::U64Be),
+            field("from_blue_work", FieldType::Uint192Be),
+            field("from_block_hash", FieldType::Hash),
+            field("to_blue_work", FieldType::Uint192Be),
+            field("to_block_hash", FieldType::Hash),
+            field("to_daa_score", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl BlockGapsPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "block_gaps",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default().block_size(64 * 1024),
         )?))
     }
@@ -88,6 +137,15 @@ impl BlockGapsPartition {
         Ok(())
     }
 
+    /// Records a gap the node can't serve anymore, listed for inspection only
+    pub fn add_unrecoverable_gap(&self, gap: &BlockGap) -> Result<()> {
+        self.0.insert(
+            bytemuck::bytes_of(&BlockGapKey::from(gap)),
+            UNRECOVERABLE_GAP,
+        )?;
+        Ok(())
+    }
+
     /// Remove a gap (when it's been filled)
     pub fn remove_gap_wtx(&self, wtx: &mut WriteTransaction, gap: BlockGap) {
         let key = BlockGapKey {
@@ -141,31 +199,47 @@ impl BlockGapsPartition {
         })
     }
 
-    /// Get all block gaps that need to be filled
+    /// Get all block gaps that need to be filled, unrecoverable ones are skipped
     pub fn get_all_gaps_since_daa(
         &self,
         since_daa: u64,
     ) -> impl DoubleEndedIterator<Item = Result<BlockGap>> + '_ {
-        self.0.inner().range(since_daa.to_be_bytes()..).map(|item| {
-            let (key_bytes, _) = item?;
-            if key_bytes.len() == 128 {
-                // 8 + 24 + 32 + 24 + 32 +8
-                let key: BlockGapKey = *bytemuck::from_bytes(&key_bytes);
+        self.0
+            .inner()
+            .range(since_daa.to_be_bytes()..)
+            .filter(|item| !matches!(item, Ok((_, value)) if value.as_ref() == UNRECOVERABLE_GAP))
+            .map(|item| Self::decode_key(&item?.0))
+    }
 
-                Ok(BlockGap {
-                    from_daa_score: u64::from_be_bytes(key.from_daa_score),
-                    from_blue_work: Uint192::from_be_bytes(key.from_blue_work),
-                    from_block_hash: RpcHash::from_slice(&key.from_block_hash),
-                    to_blue_work: Uint192::from_be_bytes(key.to_blue_work),
-                    to_block_hash: RpcHash::from_slice(&key.to_block_hash),
-                    to_daa_score: u64::from_be_bytes(key.to_daa_score),
-                })
-            } else {
-                Err(anyhow::anyhow!(
-                    "Invalid key length in block_gaps partition"
-                ))
-            }
-        })
+    /// Marks pending gaps starting below the node pruning point as unrecoverable,
+    /// returns the newly marked gaps
+    pub fn mark_unrecoverable_before_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        pruning_point_daa: u64,
+    ) -> Result<Vec<BlockGap>> {
+        let pending = wtx
+            .range(&self.0, ..pruning_point_daa.to_be_bytes())
+            .filter(|item| !matches!(item, Ok((_, value)) if value.as_ref() == UNRECOVERABLE_GAP))
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        pending
+            .into_iter()
+            .map(|key| {
+                let gap = Self::decode_key(&key)?;
+                wtx.insert(&self.0, key, UNRECOVERABLE_GAP);
+                Ok(gap)
+            })
+            .collect()
+    }
+
+    fn decode_key(key_bytes: &[u8]) -> Result<BlockGap> {
+        if key_bytes.len() != size_of::<BlockGapKey>() {
+            anyhow::bail!("Invalid key length in block_gaps partition");
+        }
+        Ok(BlockGap::from(*bytemuck::from_bytes::<BlockGapKey>(
+            key_bytes,
+        )))
     }
 
     /// Add multiple gaps in batch
@@ -201,6 +275,43 @@ mod tests {
         assert_eq!(deserialized, key);
     }
 
+    #[test]
+    fn test_mark_unrecoverable() {
+        let keyspace = crate::database::test_keyspace("block-gaps");
+        let gaps = BlockGapsPartition::new(&keyspace).unwrap();
+        let gap = |from: u64, to: u64| BlockGap {
+            from_daa_score: from,
+            from_blue_work: Uint192::from_u64(from),
+            from_block_hash: RpcHash::from_u64_word(from),
+            to_blue_work: Uint192::from_u64(to),
+            to_block_hash: RpcHash::from_u64_word(to),
+            to_daa_score: to,
+        };
+        gaps.add_gap(gap(10, 20)).unwrap();
+        gaps.add_gap(gap(100, 200)).unwrap();
+
+        let mut wtx = keyspace.write_tx().unwrap();
+        let marked = gaps.mark_unrecoverable_before_wtx(&mut wtx, 50).unwrap();
+        wtx.commit().unwrap().unwrap();
+        assert_eq!(marked, vec![gap(10, 20)]);
+
+        let pending = gaps
+            .get_all_gaps_since_daa(0)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(pending, vec![gap(100, 200)]);
+        // still listed for inspection
+        assert_eq!(gaps.get_all_gaps_rtx(&keyspace.read_tx()).count(), 2);
+
+        // marking is not repeated
+        let mut wtx = keyspace.write_tx().unwrap();
+        assert!(
+            gaps.mark_unrecoverable_before_wtx(&mut wtx, 50)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
     #[test]
     fn test_blue_work_conversion() {
         let blue_work = Uint192::from_be_bytes([1u8; 24]);
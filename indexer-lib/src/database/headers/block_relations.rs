@@ -0,0 +1,294 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::{RpcBlockVerboseData, RpcHash};
+use std::collections::{HashSet, VecDeque};
+
+/// Blocks a neighborhood holds at most, blocks beyond it are left out
+pub const MAX_NEIGHBORHOOD_BLOCKS: usize = 1000;
+
+/// Merge set of a block as reported in its verbose data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRelations {
+    pub selected_parent: RpcHash,
+    /// Starts with the selected parent
+    pub merge_set_blues: Vec<RpcHash>,
+    pub merge_set_reds: Vec<RpcHash>,
+}
+
+impl From<&RpcBlockVerboseData> for BlockRelations {
+    fn from(verbose_data: &RpcBlockVerboseData) -> Self {
+        Self {
+            selected_parent: verbose_data.selected_parent_hash,
+            merge_set_blues: verbose_data.merge_set_blues_hashes.clone(),
+            merge_set_reds: verbose_data.merge_set_reds_hashes.clone(),
+        }
+    }
+}
+
+impl BlockRelations {
+    /// Selected parent first, then the merge set, without repetitions
+    pub fn parents(&self) -> impl Iterator<Item = RpcHash> + '_ {
+        std::iter::once(self.selected_parent).chain(
+            self.merge_set_blues
+                .iter()
+                .chain(&self.merge_set_reds)
+                .copied()
+                .filter(|hash| *hash != self.selected_parent),
+        )
+    }
+
+    /// `[selected_parent (32 bytes)] + [blues count (2 bytes BE)] + [blues (32 bytes each)] +
+    /// [reds (32 bytes each)]`
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let Ok(blues) = u16::try_from(self.merge_set_blues.len()) else {
+            bail!("Merge set too large: {} blues", self.merge_set_blues.len());
+        };
+        let mut value = Vec::with_capacity(
+            32 + 2 + 32 * (self.merge_set_blues.len() + self.merge_set_reds.len()),
+        );
+        value.extend_from_slice(&self.selected_parent.as_bytes());
+        value.extend_from_slice(&blues.to_be_bytes());
+        for hash in self.merge_set_blues.iter().chain(&self.merge_set_reds) {
+            value.extend_from_slice(&hash.as_bytes());
+        }
+        Ok(value)
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self> {
+        if value.len() < 34 || (value.len() - 34) % 32 != 0 {
+            bail!("Invalid block relations length");
+        }
+        let blues = u16::from_be_bytes([value[32], value[33]]) as usize;
+        let mut hashes = value[34..]
+            .chunks_exact(32)
+            .map(RpcHash::from_slice)
+            .collect::<Vec<_>>();
+        if blues > hashes.len() {
+            bail!("Invalid block relations blues count");
+        }
+        let merge_set_reds = hashes.split_off(blues);
+        Ok(Self {
+            selected_parent: RpcHash::from_slice(&value[..32]),
+            merge_set_blues: hashes,
+            merge_set_reds,
+        })
+    }
+}
+
+/// Block of a [`DagNeighborhood`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeighborhoodBlock {
+    pub hash: RpcHash,
+    /// Steps from the block the neighborhood was taken around
+    pub depth: u32,
+    /// None for blocks not indexed or already pruned, they end their path
+    pub relations: Option<BlockRelations>,
+}
+
+/// Past of a block up to a depth, in breadth-first order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DagNeighborhood {
+    pub blocks: Vec<NeighborhoodBlock>,
+    /// Blocks were left out to stay within [`MAX_NEIGHBORHOOD_BLOCKS`]
+    pub truncated: bool,
+}
+
+/// Partition holding the merge set of each block, for DAG visualization.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [selected_parent (32 bytes)] + [blues count (2 bytes BE)] + [blues and reds (32
+/// bytes each)]
+///
+/// Written from the verbose data at ingest and pruned together with the headers.
+#[derive(Clone)]
+pub struct BlockRelationsPartition(fjall::TxPartition);
+
+impl DescribePartition for BlockRelationsPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_relations",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[
+            field("selected_parent", FieldType::Hash),
+            field("blues_count", FieldType::Bytes(2)),
+            field("merge_set", FieldType::Tail("hash[]")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockRelationsPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        relations: &BlockRelations,
+    ) -> Result<()> {
+        wtx.insert(&self.0, block_hash.as_bytes(), relations.encode()?);
+        Ok(())
+    }
+
+    pub fn get_block_relations(&self, block_hash: RpcHash) -> Result<Option<BlockRelations>> {
+        self.0
+            .get(block_hash.as_bytes())?
+            .map(|value| BlockRelations::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_block_relations_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockRelations>> {
+        rtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| BlockRelations::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_block_relations_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockRelations>> {
+        wtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| BlockRelations::decode(&value))
+            .transpose()
+    }
+
+    /// The block and its past up to `depth` steps through selected parents and merge sets,
+    /// each block at the depth it is first reached at
+    pub fn get_dag_neighborhood_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+        depth: u32,
+    ) -> Result<DagNeighborhood> {
+        let mut neighborhood = DagNeighborhood::default();
+        let mut reached = HashSet::from([block_hash]);
+        let mut queue = VecDeque::from([(block_hash, 0)]);
+        while let Some((hash, hash_depth)) = queue.pop_front() {
+            let relations = self.get_block_relations_rtx(rtx, hash)?;
+            if hash_depth < depth
+                && let Some(relations) = &relations
+            {
+                for parent in relations.parents() {
+                    if reached.contains(&parent) {
+                        continue;
+                    }
+                    if reached.len() >= MAX_NEIGHBORHOOD_BLOCKS {
+                        neighborhood.truncated = true;
+                        break;
+                    }
+                    reached.insert(parent);
+                    queue.push_back((parent, hash_depth + 1));
+                }
+            }
+            neighborhood.blocks.push(NeighborhoodBlock {
+                hash,
+                depth: hash_depth,
+                relations,
+            });
+        }
+        Ok(neighborhood)
+    }
+
+    pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
+        self.0.remove(block_hash.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: u64) -> RpcHash {
+        RpcHash::from_u64_word(n)
+    }
+
+    fn relations(selected_parent: u64, blues: &[u64], reds: &[u64]) -> BlockRelations {
+        BlockRelations {
+            selected_parent: hash(selected_parent),
+            merge_set_blues: std::iter::once(selected_parent)
+                .chain(blues.iter().copied())
+                .map(hash)
+                .collect(),
+            merge_set_reds: reds.iter().copied().map(hash).collect(),
+        }
+    }
+
+    #[test]
+    fn test_large_merge_set_stays_compact() {
+        // the mainnet blue limit and 182 reds, 200 members with the selected parent
+        let blues = (1..18).collect::<Vec<_>>();
+        let reds = (100..282).collect::<Vec<_>>();
+        let relations = relations(0, &blues, &reds);
+        assert_eq!(
+            relations.merge_set_blues.len() + relations.merge_set_reds.len(),
+            200
+        );
+        let value = relations.encode().unwrap();
+        assert_eq!(value.len(), 32 + 2 + 200 * 32);
+        assert_eq!(BlockRelations::decode(&value).unwrap(), relations);
+        assert!(BlockRelations::decode(&value[..value.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_neighborhood_is_bounded_by_depth() {
+        let keyspace = crate::database::test_keyspace("block-relations");
+        let partition = BlockRelationsPartition::new(&keyspace).unwrap();
+        // 5 merges 4 and 3, both on top of 2, which sits on 1
+        let mut wtx = keyspace.write_tx().unwrap();
+        for (block, relations) in [
+            (5, relations(4, &[], &[3])),
+            (4, relations(2, &[], &[])),
+            (3, relations(2, &[], &[])),
+            (2, relations(1, &[], &[])),
+        ] {
+            partition
+                .insert_wtx(&mut wtx, hash(block), &relations)
+                .unwrap();
+        }
+        wtx.commit().unwrap().unwrap();
+        assert_eq!(
+            partition.get_block_relations(hash(5)).unwrap(),
+            Some(relations(4, &[], &[3]))
+        );
+
+        let rtx = keyspace.read_tx();
+        let depths = |depth| {
+            partition
+                .get_dag_neighborhood_rtx(&rtx, hash(5), depth)
+                .unwrap()
+                .blocks
+                .into_iter()
+                .map(|block| (block.hash, block.depth))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(depths(0), [(hash(5), 0)]);
+        assert_eq!(depths(1), [(hash(5), 0), (hash(4), 1), (hash(3), 1)]);
+        // 2 is reached once, 1 is not indexed and ends the walk
+        assert_eq!(
+            depths(10),
+            [
+                (hash(5), 0),
+                (hash(4), 1),
+                (hash(3), 1),
+                (hash(2), 2),
+                (hash(1), 3)
+            ]
+        );
+        let neighborhood = partition
+            .get_dag_neighborhood_rtx(&rtx, hash(5), 10)
+            .unwrap();
+        assert!(!neighborhood.truncated);
+        assert_eq!(neighborhood.blocks[4].relations, None);
+    }
+}
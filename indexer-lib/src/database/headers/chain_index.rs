@@ -0,0 +1,199 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::RpcHash;
+use std::ops::Range;
+
+/// Partition numbering the selected chain blocks in chain order.
+///
+/// **Key:** [chain_index (8 bytes BE)]
+/// **Value:** [block_hash (32 bytes)]
+///
+/// Indexes count from the first chain block the indexer saw and stay dense: a reorg truncates
+/// the removed tail and the added blocks continue right after the last remaining one.
+#[derive(Clone)]
+pub struct ChainIndexPartition(fjall::TxPartition);
+
+/// Reverse of `ChainIndexPartition`.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [chain_index (8 bytes BE)]
+#[derive(Clone)]
+pub struct ChainIndexByHashPartition(fjall::TxPartition);
+
+impl DescribePartition for ChainIndexPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "chain_index",
+        key: &[field("chain_index", FieldType::U64Be)],
+        value: &[field("block_hash", FieldType::Hash)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl DescribePartition for ChainIndexByHashPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "chain_index_by_hash",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[field("chain_index", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl ChainIndexPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(&self, wtx: &mut WriteTransaction, index: u64, block_hash: &RpcHash) {
+        wtx.insert(&self.0, index.to_be_bytes(), block_hash.as_bytes());
+    }
+
+    /// Removes the blocks from `index` on, returns them in chain order
+    pub fn truncate_wtx(&self, wtx: &mut WriteTransaction, index: u64) -> Result<Vec<RpcHash>> {
+        let mut removed = Vec::new();
+        for item in wtx.range(&self.0, index.to_be_bytes()..) {
+            removed.push(decode(&item?)?);
+        }
+        for (index, _) in &removed {
+            wtx.remove(&self.0, index.to_be_bytes());
+        }
+        Ok(removed.into_iter().map(|(_, hash)| hash).collect())
+    }
+
+    pub fn get_chain_block_by_index(&self, index: u64) -> Result<Option<RpcHash>> {
+        self.0
+            .get(index.to_be_bytes())?
+            .map(|value| decode_hash(&value))
+            .transpose()
+    }
+
+    pub fn get_chain_block_by_index_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        index: u64,
+    ) -> Result<Option<RpcHash>> {
+        rtx.get(&self.0, index.to_be_bytes())?
+            .map(|value| decode_hash(&value))
+            .transpose()
+    }
+
+    /// Index of the last chain block, none before the first one was added
+    pub fn chain_tip_index(&self) -> Result<Option<u64>> {
+        self.0
+            .inner()
+            .last_key_value()?
+            .map(|kv| decode(&kv).map(|(index, _)| index))
+            .transpose()
+    }
+
+    pub fn chain_tip_index_rtx(&self, rtx: &ReadTransaction) -> Result<Option<u64>> {
+        rtx.iter(&self.0)
+            .next_back()
+            .map(|kv| decode(&kv?).map(|(index, _)| index))
+            .transpose()
+    }
+
+    pub fn chain_tip_index_wtx(&self, wtx: &mut WriteTransaction) -> Result<Option<u64>> {
+        wtx.iter(&self.0)
+            .next_back()
+            .map(|kv| decode(&kv?).map(|(index, _)| index))
+            .transpose()
+    }
+
+    /// (chain_index, block_hash) of the chain blocks within `range`, in chain order
+    pub fn iter_chain_blocks(
+        &self,
+        range: Range<u64>,
+    ) -> impl DoubleEndedIterator<Item = Result<(u64, RpcHash)>> + '_ {
+        self.0
+            .inner()
+            .range(range.start.to_be_bytes()..range.end.to_be_bytes())
+            .map(|kv| decode(&kv?))
+    }
+
+    pub fn iter_chain_blocks_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        range: Range<u64>,
+    ) -> impl DoubleEndedIterator<Item = Result<(u64, RpcHash)>> + 'a {
+        rtx.range(&self.0, range.start.to_be_bytes()..range.end.to_be_bytes())
+            .map(|kv| decode(&kv?))
+    }
+
+    /// Collected, reading through the write transaction borrows it
+    pub fn chain_blocks_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        range: Range<u64>,
+    ) -> Result<Vec<(u64, RpcHash)>> {
+        wtx.range(&self.0, range.start.to_be_bytes()..range.end.to_be_bytes())
+            .map(|kv| decode(&kv?))
+            .collect()
+    }
+}
+
+impl ChainIndexByHashPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(&self, wtx: &mut WriteTransaction, block_hash: &RpcHash, index: u64) {
+        wtx.insert(&self.0, block_hash.as_bytes(), index.to_be_bytes());
+    }
+
+    pub fn remove_wtx(&self, wtx: &mut WriteTransaction, block_hash: &RpcHash) {
+        wtx.remove(&self.0, block_hash.as_bytes());
+    }
+
+    /// None if the block is not on the indexed chain
+    pub fn get_chain_index(&self, block_hash: &RpcHash) -> Result<Option<u64>> {
+        self.0
+            .get(block_hash.as_bytes())?
+            .map(|value| decode_index(&value))
+            .transpose()
+    }
+
+    pub fn get_chain_index_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: &RpcHash,
+    ) -> Result<Option<u64>> {
+        rtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| decode_index(&value))
+            .transpose()
+    }
+
+    pub fn get_chain_index_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: &RpcHash,
+    ) -> Result<Option<u64>> {
+        wtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| decode_index(&value))
+            .transpose()
+    }
+}
+
+fn decode((key, value): &(fjall::UserKey, fjall::UserValue)) -> Result<(u64, RpcHash)> {
+    Ok((decode_index(key)?, decode_hash(value)?))
+}
+
+fn decode_index(bytes: &[u8]) -> Result<u64> {
+    match bytes.try_into() {
+        Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+        Err(_) => bail!("Invalid chain index length"),
+    }
+}
+
+fn decode_hash(bytes: &[u8]) -> Result<RpcHash> {
+    if bytes.len() != 32 {
+        bail!("Invalid block hash length");
+    }
+    Ok(RpcHash::from_slice(bytes))
+}
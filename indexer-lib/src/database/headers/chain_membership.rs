@@ -0,0 +1,179 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::RpcHash;
+
+/// Where the chain membership of a block was learned from
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainMembershipSource {
+    /// Verbose data of the block when it was received, stale after a reorg. Only found in
+    /// entries written before the flag moved to [`VerboseChainMembershipPartition`]
+    VerboseData = 0,
+    /// Virtual chain changes, authoritative
+    VirtualChain = 1,
+}
+
+/// Partition flagging blocks on the selected chain, kept next to the block headers.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [is_chain_block (1 byte)] + [source (1 byte)]
+///
+/// Virtual chain changes may arrive before the block itself, so the flag is stored apart
+/// from the header. Only the virtual chain processor writes it, the flag reported in verbose
+/// data lives in [`VerboseChainMembershipPartition`] and only counts until a virtual chain
+/// change set the flag. Entries written by verbose data before the split still read as such.
+#[derive(Clone)]
+pub struct ChainMembershipPartition {
+    partition: fjall::TxPartition,
+    verbose: VerboseChainMembershipPartition,
+}
+
+impl DescribePartition for ChainMembershipPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "chain_membership",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[
+            field("is_chain_block", FieldType::U8),
+            field("source", FieldType::U8),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl ChainMembershipPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self {
+            partition: schema::open_partition::<Self>(keyspace, PartitionCreateOptions::default())?,
+            verbose: VerboseChainMembershipPartition::new(keyspace)?,
+        })
+    }
+
+    /// Records the flag reported in verbose data. Returns the flag in effect as of the last
+    /// commit, the one set by a virtual chain change if there is one.
+    ///
+    /// The flag set by virtual chain changes is read outside of the transaction, so the block
+    /// processor never conflicts with the virtual chain processor over it
+    pub fn set_from_verbose_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        is_chain_block: bool,
+    ) -> Result<bool> {
+        wtx.insert(
+            &self.verbose.0,
+            block_hash.as_bytes(),
+            [is_chain_block as u8],
+        );
+        match self.partition.get(block_hash.as_bytes())? {
+            Some(value) => match decode(&value)? {
+                (stored, ChainMembershipSource::VirtualChain) => Ok(stored),
+                (_, ChainMembershipSource::VerboseData) => Ok(is_chain_block),
+            },
+            None => Ok(is_chain_block),
+        }
+    }
+
+    /// Flips the flag for a block added to or removed from the selected chain
+    pub fn set_from_vcc_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        is_chain_block: bool,
+    ) {
+        wtx.insert(
+            &self.partition,
+            block_hash.as_bytes(),
+            [
+                is_chain_block as u8,
+                ChainMembershipSource::VirtualChain as u8,
+            ],
+        );
+    }
+
+    /// None if the block is unknown
+    pub fn is_on_selected_chain(&self, block_hash: RpcHash) -> Result<Option<bool>> {
+        let key = block_hash.as_bytes();
+        resolve(self.partition.get(key)?, || Ok(self.verbose.0.get(key)?))
+    }
+
+    pub fn is_on_selected_chain_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<bool>> {
+        let key = block_hash.as_bytes();
+        resolve(rtx.get(&self.partition, key)?, || {
+            Ok(rtx.get(&self.verbose.0, key)?)
+        })
+    }
+
+    pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
+        self.partition.remove(block_hash.as_bytes())?;
+        self.verbose.0.remove(block_hash.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Flag reported in the verbose data of the block, written by the block processor only.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [is_chain_block (1 byte)]
+///
+/// Stale after a reorg, read through [`ChainMembershipPartition`] which prefers the flag set
+/// by virtual chain changes
+#[derive(Clone)]
+pub struct VerboseChainMembershipPartition(fjall::TxPartition);
+
+impl DescribePartition for VerboseChainMembershipPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "chain_membership_verbose",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[field("is_chain_block", FieldType::U8)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl VerboseChainMembershipPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+}
+
+/// The flag set by virtual chain changes, else the one of verbose data
+fn resolve(
+    stored: Option<fjall::Slice>,
+    verbose: impl FnOnce() -> Result<Option<fjall::Slice>>,
+) -> Result<Option<bool>> {
+    let stored = stored.map(|value| decode(&value)).transpose()?;
+    if let Some((flag, ChainMembershipSource::VirtualChain)) = stored {
+        return Ok(Some(flag));
+    }
+    match verbose()?.as_deref() {
+        Some([flag]) => Ok(Some(*flag != 0)),
+        Some(_) => bail!("Invalid verbose chain membership length"),
+        // written by verbose data before the flags were split
+        None => Ok(stored.map(|(flag, _)| flag)),
+    }
+}
+
+fn decode(value: &[u8]) -> Result<(bool, ChainMembershipSource)> {
+    match value {
+        [flag, source] => Ok((
+            *flag != 0,
+            match *source {
+                x if x == ChainMembershipSource::VerboseData as u8 => {
+                    ChainMembershipSource::VerboseData
+                }
+                x if x == ChainMembershipSource::VirtualChain as u8 => {
+                    ChainMembershipSource::VirtualChain
+                }
+                x => bail!("Unknown chain membership source: {x}"),
+            },
+        )),
+        _ => bail!("Invalid chain membership length"),
+    }
+}
@@ -1,6 +1,8 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::{Result, bail};
-use fjall::{PartitionCreateOptions, ReadTransaction};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
 use kaspa_rpc_core::RpcHash;
+use std::ops::Range;
 
 /// Secondary index partition for compact headers, indexed by DAA score (u64 BE bytes) + block hash
 /// Used for efficient pruning of old blocks with DAA score < threshold
@@ -9,23 +11,32 @@ use kaspa_rpc_core::RpcHash;
 #[derive(Clone)]
 pub struct DaaIndexPartition(fjall::TxPartition);
 
+impl DescribePartition for DaaIndexPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "daa_index_compact_header",
+        key: &[
+            field("daa_score", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl DaaIndexPartition {
     const KEY_LEN: usize = 8 + 32;
 
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(
-            keyspace.open_partition(
-                "daa_index_compact_header",
-                PartitionCreateOptions::default()
-                    .block_size(64 * 1024)
-                    .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
-                        fjall::compaction::SizeTiered {
-                            base_size: 4 * 1024 * 1024,
-                            level_ratio: 6,
-                        },
-                    )),
-            )?,
-        ))
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default()
+                .block_size(64 * 1024)
+                .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
+                    fjall::compaction::SizeTiered {
+                        base_size: 4 * 1024 * 1024,
+                        level_ratio: 6,
+                    },
+                )),
+        )?))
     }
 
     fn make_key(daa_score: u64, block_hash: &RpcHash) -> [u8; Self::KEY_LEN] {
@@ -41,12 +52,25 @@ impl DaaIndexPartition {
         Ok(())
     }
 
+    pub fn insert_wtx(&self, wtx: &mut WriteTransaction, daa_score: u64, block_hash: &RpcHash) {
+        wtx.insert(&self.0, Self::make_key(daa_score, block_hash), []);
+    }
+
     pub fn delete(&self, daa_score: u64, block_hash: &RpcHash) -> Result<()> {
         let key = Self::make_key(daa_score, block_hash);
         self.0.remove(key)?;
         Ok(())
     }
 
+    pub fn contains_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        daa_score: u64,
+        block_hash: &RpcHash,
+    ) -> Result<bool> {
+        Ok(rtx.contains_key(&self.0, Self::make_key(daa_score, block_hash))?)
+    }
+
     /// Returns an iterator over (daa_score, block_hash) where daa_score < max_daa.
     /// Assumes the underlying store iterates in sorted key order.
     pub fn iter_lt<'a>(
@@ -70,6 +94,45 @@ impl DaaIndexPartition {
         })
     }
 
+    /// Highest (daa_score, block_hash) with daa_score < max_daa
+    pub fn last_before_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        max_daa: u64,
+    ) -> Result<Option<(u64, RpcHash)>> {
+        rtx.range(&self.0, ..max_daa.to_be_bytes())
+            .next_back()
+            .map(|res| {
+                let (key, _) = res?;
+                Self::decode_key(&key)
+            })
+            .transpose()
+    }
+
+    /// Returns an iterator over (daa_score, block_hash) within `daa_range`
+    pub fn iter_range_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        daa_range: Range<u64>,
+    ) -> impl Iterator<Item = Result<(u64, RpcHash)>> + 'a {
+        rtx.range(
+            &self.0,
+            daa_range.start.to_be_bytes()..daa_range.end.to_be_bytes(),
+        )
+        .map(|res| {
+            let (key, _) = res?;
+            Self::decode_key(&key)
+        })
+    }
+
+    fn decode_key(key: &[u8]) -> Result<(u64, RpcHash)> {
+        if key.len() != Self::KEY_LEN {
+            bail!("Invalid key length: {}", key.len());
+        }
+        let daa_score = u64::from_be_bytes(key[0..8].try_into()?);
+        Ok((daa_score, RpcHash::from_slice(&key[8..])))
+    }
+
     pub fn len(&self) -> Result<usize> {
         Ok(self.0.inner().len()?)
     }
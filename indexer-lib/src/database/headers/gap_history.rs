@@ -0,0 +1,289 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::PartitionCreateOptions;
+use kaspa_rpc_core::RpcHash;
+use std::fmt;
+
+/// Records kept in the partition, older ones are dropped on insert
+pub const MAX_GAP_HISTORY: usize = 1000;
+
+const KEY_LEN: usize = 8 + 32 + 32 + 1;
+const VALUE_LEN: usize = 8 + 8 + 8 + 8 + 32 + 8 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapSyncEvent {
+    Started,
+    /// Progress while syncing
+    Checkpoint,
+    /// Stopped by shutdown, the rest of the gap stays recorded
+    Interrupted,
+    Completed,
+    Failed,
+}
+
+impl GapSyncEvent {
+    fn tag(self) -> u8 {
+        match self {
+            GapSyncEvent::Started => 0,
+            GapSyncEvent::Checkpoint => 1,
+            GapSyncEvent::Interrupted => 2,
+            GapSyncEvent::Completed => 3,
+            GapSyncEvent::Failed => 4,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => GapSyncEvent::Started,
+            1 => GapSyncEvent::Checkpoint,
+            2 => GapSyncEvent::Interrupted,
+            3 => GapSyncEvent::Completed,
+            4 => GapSyncEvent::Failed,
+            tag => bail!("Unknown gap sync event: {tag}"),
+        })
+    }
+}
+
+impl fmt::Display for GapSyncEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GapSyncEvent::Started => "started",
+            GapSyncEvent::Checkpoint => "checkpoint",
+            GapSyncEvent::Interrupted => "interrupted",
+            GapSyncEvent::Completed => "completed",
+            GapSyncEvent::Failed => "failed",
+        })
+    }
+}
+
+/// State of a gap syncer when an event happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapHistoryRecord {
+    pub recorded_at_ms: u64,
+    pub from_block_hash: RpcHash,
+    pub to_block_hash: RpcHash,
+    pub event: GapSyncEvent,
+    pub from_daa_score: u64,
+    pub to_daa_score: u64,
+    /// When the syncer started
+    pub started_at_ms: u64,
+    pub current_daa_score: u64,
+    pub current_block_hash: RpcHash,
+    pub blocks_processed: u64,
+    pub batches_processed: u64,
+    /// Transient RPC failures retried so far
+    pub retries: u32,
+    /// Error of a failed syncer
+    pub error: Option<String>,
+}
+
+impl GapHistoryRecord {
+    /// `[recorded_at_ms (8 bytes BE)] + [from_block_hash (32 bytes)] + [to_block_hash (32 bytes)]
+    /// + [event (1 byte)]`
+    fn key(&self) -> [u8; KEY_LEN] {
+        let mut key = [0u8; KEY_LEN];
+        key[..8].copy_from_slice(&self.recorded_at_ms.to_be_bytes());
+        key[8..40].copy_from_slice(&self.from_block_hash.as_bytes());
+        key[40..72].copy_from_slice(&self.to_block_hash.as_bytes());
+        key[72] = self.event.tag();
+        key
+    }
+
+    fn value(&self) -> Vec<u8> {
+        let error = self.error.as_deref().unwrap_or_default();
+        let mut value = Vec::with_capacity(VALUE_LEN + error.len());
+        value.extend_from_slice(&self.from_daa_score.to_be_bytes());
+        value.extend_from_slice(&self.to_daa_score.to_be_bytes());
+        value.extend_from_slice(&self.started_at_ms.to_be_bytes());
+        value.extend_from_slice(&self.current_daa_score.to_be_bytes());
+        value.extend_from_slice(&self.current_block_hash.as_bytes());
+        value.extend_from_slice(&self.blocks_processed.to_be_bytes());
+        value.extend_from_slice(&self.batches_processed.to_be_bytes());
+        value.extend_from_slice(&self.retries.to_be_bytes());
+        value.extend_from_slice(error.as_bytes());
+        value
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<Self> {
+        if key.len() != KEY_LEN || value.len() < VALUE_LEN {
+            bail!("Invalid gap history record length");
+        }
+        let u64_at =
+            |at: usize| -> Result<u64> { Ok(u64::from_be_bytes(value[at..at + 8].try_into()?)) };
+        let error = &value[VALUE_LEN..];
+        Ok(Self {
+            recorded_at_ms: u64::from_be_bytes(key[..8].try_into()?),
+            from_block_hash: RpcHash::from_slice(&key[8..40]),
+            to_block_hash: RpcHash::from_slice(&key[40..72]),
+            event: GapSyncEvent::from_tag(key[72])?,
+            from_daa_score: u64_at(0)?,
+            to_daa_score: u64_at(8)?,
+            started_at_ms: u64_at(16)?,
+            current_daa_score: u64_at(24)?,
+            current_block_hash: RpcHash::from_slice(&value[32..64]),
+            blocks_processed: u64_at(64)?,
+            batches_processed: u64_at(72)?,
+            retries: u32::from_be_bytes(value[80..84].try_into()?),
+            error: (!error.is_empty()).then(|| String::from_utf8_lossy(error).into_owned()),
+        })
+    }
+}
+
+impl fmt::Display for GapHistoryRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} gap {}..{} at DAA {}: {} blocks in {} batches, {} retries, {}s running",
+            self.recorded_at_ms,
+            self.event,
+            self.from_daa_score,
+            self.to_daa_score,
+            self.current_daa_score,
+            self.blocks_processed,
+            self.batches_processed,
+            self.retries,
+            self.recorded_at_ms.saturating_sub(self.started_at_ms) / 1000
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Partition keeping the last [`MAX_GAP_HISTORY`] events of the gap syncers, for looking into
+/// slow or failing gaps afterwards.
+///
+/// **Key:** [recorded_at_ms (8 bytes BE)] + [from_block_hash (32 bytes)] + [to_block_hash (32
+/// bytes)] + [event (1 byte)]
+/// **Value:** [from_daa_score, to_daa_score, started_at_ms, current_daa_score (8 bytes BE each)]
+/// + [current_block_hash (32 bytes)] + [blocks_processed, batches_processed (8 bytes BE each)]
+/// + [retries (4 bytes BE)] + [error (utf-8)]
+///
+/// Written by the syncers outside of transactions, like the crash reports.
+#[derive(Clone)]
+pub struct GapHistoryPartition(fjall::TxPartition);
+
+impl DescribePartition for GapHistoryPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "gap_history",
+        key: &[
+            field("recorded_at_ms", FieldType::U64Be),
+            field("from_block_hash", FieldType::Hash),
+            field("to_block_hash", FieldType::Hash),
+            field("event", FieldType::U8),
+        ],
+        value: &[
+            field("from_daa_score", FieldType::U64Be),
+            field("to_daa_score", FieldType::U64Be),
+            field("started_at_ms", FieldType::U64Be),
+            field("current_daa_score", FieldType::U64Be),
+            field("current_block_hash", FieldType::Hash),
+            field("blocks_processed", FieldType::U64Be),
+            field("batches_processed", FieldType::U64Be),
+            field("retries", FieldType::Bytes(4)),
+            field("error", FieldType::Tail("utf8")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl GapHistoryPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert(&self, record: &GapHistoryRecord) -> Result<()> {
+        self.insert_capped(record, MAX_GAP_HISTORY)
+    }
+
+    fn insert_capped(&self, record: &GapHistoryRecord, capacity: usize) -> Result<()> {
+        let partition = self.0.inner();
+        partition.insert(record.key(), record.value())?;
+
+        let excess = partition.len()?.saturating_sub(capacity);
+        let oldest = partition
+            .keys()
+            .take(excess)
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in oldest {
+            partition.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` records, newest first
+    pub fn list_gap_history(&self, limit: usize) -> Result<Vec<GapHistoryRecord>> {
+        self.0
+            .inner()
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|kv| {
+                let (key, value) = kv?;
+                GapHistoryRecord::decode(&key, &value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(recorded_at_ms: u64, event: GapSyncEvent) -> GapHistoryRecord {
+        GapHistoryRecord {
+            recorded_at_ms,
+            from_block_hash: RpcHash::from_u64_word(1),
+            to_block_hash: RpcHash::from_u64_word(2),
+            event,
+            from_daa_score: 100,
+            to_daa_score: 5_000,
+            started_at_ms: 1_000,
+            current_daa_score: 2_500,
+            current_block_hash: RpcHash::from_u64_word(3),
+            blocks_processed: 2_400,
+            batches_processed: 24,
+            retries: 2,
+            error: (event == GapSyncEvent::Failed).then(|| "connection reset".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let keyspace = crate::database::test_keyspace("gap-history");
+        let history = GapHistoryPartition::new(&keyspace).unwrap();
+        for (at, event) in [
+            (1_000, GapSyncEvent::Started),
+            (2_000, GapSyncEvent::Checkpoint),
+            (3_000, GapSyncEvent::Checkpoint),
+            (61_000, GapSyncEvent::Failed),
+        ] {
+            history.insert_capped(&record(at, event), 3).unwrap();
+        }
+
+        let records = history.list_gap_history(10).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.recorded_at_ms, record.event))
+                .collect::<Vec<_>>(),
+            [
+                (61_000, GapSyncEvent::Failed),
+                (3_000, GapSyncEvent::Checkpoint),
+                (2_000, GapSyncEvent::Checkpoint)
+            ]
+        );
+        assert_eq!(records[0], record(61_000, GapSyncEvent::Failed));
+        assert_eq!(
+            records[0].to_string(),
+            "61000 failed gap 100..5000 at DAA 2500: 2400 blocks in 24 batches, 2 retries, \
+             60s running: connection reset"
+        );
+        assert_eq!(records[1].error, None);
+        assert_eq!(history.list_gap_history(1).unwrap().len(), 1);
+    }
+}
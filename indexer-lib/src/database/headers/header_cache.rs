@@ -0,0 +1,232 @@
+use crate::CompactHeader;
+use kaspa_rpc_core::RpcHash;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const DEFAULT_HEADER_CACHE_CAPACITY: usize = 300_000;
+const SHARDS: usize = 16;
+
+/// Sharded LRU cache of compact headers, shared by all clones of the headers partition.
+///
+/// Writes go through the cache, removals invalidate it. A lookup missing the cache reads
+/// the store without holding the shard lock and only fills the cache if no removal hit the
+/// shard meanwhile, so a concurrently pruned header can't be resurrected.
+pub struct HeaderCache {
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<RpcHash, (CompactHeader, u64)>,
+    /// Last use tick -> hash, the first entry is the least recently used one
+    recency: BTreeMap<u64, RpcHash>,
+    tick: u64,
+    /// Bumped by every removal
+    removals: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeaderCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl HeaderCache {
+    /// A zero capacity disables caching
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Default::default()).collect(),
+            shard_capacity: capacity.div_ceil(SHARDS),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    /// Returns the cached header or loads it with `load`
+    pub fn get_or_load<E>(
+        &self,
+        hash: &RpcHash,
+        load: impl FnOnce() -> Result<Option<CompactHeader>, E>,
+    ) -> Result<Option<CompactHeader>, E> {
+        if self.shard_capacity == 0 {
+            return load();
+        }
+        let removals = {
+            let mut shard = self.shard(hash).lock();
+            if let Some(header) = shard.touch(hash) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(header));
+            }
+            shard.removals
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let loaded = load()?;
+        if let Some(header) = loaded {
+            let mut shard = self.shard(hash).lock();
+            if shard.removals == removals {
+                shard.insert(*hash, header, self.shard_capacity);
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Called after the header is written to the store
+    pub fn insert(&self, hash: RpcHash, header: CompactHeader) {
+        if self.shard_capacity == 0 {
+            return;
+        }
+        self.shard(&hash)
+            .lock()
+            .insert(hash, header, self.shard_capacity);
+    }
+
+    /// Called after the header is removed from the store
+    pub fn remove(&self, hash: &RpcHash) {
+        let mut shard = self.shard(hash).lock();
+        shard.removals += 1;
+        if let Some((_, tick)) = shard.entries.remove(hash) {
+            shard.recency.remove(&tick);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().entries.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> HeaderCacheStats {
+        HeaderCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn shard(&self, hash: &RpcHash) -> &Mutex<Shard> {
+        // block hashes are uniformly distributed
+        &self.shards[hash.as_bytes()[0] as usize % SHARDS]
+    }
+}
+
+impl Shard {
+    fn touch(&mut self, hash: &RpcHash) -> Option<CompactHeader> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (header, last_used) = self.entries.get_mut(hash)?;
+        self.recency.remove(last_used);
+        *last_used = tick;
+        self.recency.insert(tick, *hash);
+        Some(*header)
+    }
+
+    fn insert(&mut self, hash: RpcHash, header: CompactHeader, capacity: usize) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((_, last_used)) = self.entries.insert(hash, (header, tick)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(tick, hash);
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    fn header(daa_score: u64) -> CompactHeader {
+        CompactHeader {
+            blue_work: Default::default(),
+            daa_score,
+        }
+    }
+
+    /// Hashes landing in the same shard
+    fn hash(i: u64) -> RpcHash {
+        let mut bytes = [0u8; 32];
+        bytes[24..].copy_from_slice(&i.to_be_bytes());
+        RpcHash::from_bytes(bytes)
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = HeaderCache::new(2 * SHARDS);
+        cache.insert(hash(1), header(1));
+        cache.insert(hash(2), header(2));
+        // 1 becomes the most recently used one
+        let hit = cache.get_or_load(&hash(1), || Ok::<_, Infallible>(None));
+        assert_eq!(hit.unwrap(), Some(header(1)));
+        cache.insert(hash(3), header(3));
+
+        let miss = cache.get_or_load(&hash(2), || Ok::<_, Infallible>(None));
+        assert_eq!(miss.unwrap(), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats(), HeaderCacheStats { hits: 1, misses: 1 });
+    }
+
+    #[test]
+    fn test_removal_during_load_is_not_resurrected() {
+        let cache = HeaderCache::new(SHARDS);
+        let loaded = cache.get_or_load(&hash(1), || {
+            // pruned while the store read is in flight
+            cache.remove(&hash(1));
+            Ok::<_, Infallible>(Some(header(1)))
+        });
+        assert_eq!(loaded.unwrap(), Some(header(1)));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_insert_get_prune() {
+        let cache = Arc::new(HeaderCache::new(64 * SHARDS));
+        // the store: headers of even numbered blocks are pruned
+        let stored = |i: u64| (i % 2 == 1).then(|| header(i));
+        let writers = (0..4)
+            .map(|t| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for i in (t..4000).step_by(4) {
+                        cache.insert(hash(i), header(i));
+                        if i % 2 == 0 {
+                            cache.remove(&hash(i));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let readers = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for i in 0..4000 {
+                        let found = cache
+                            .get_or_load(&hash(i), || Ok::<_, Infallible>(stored(i)))
+                            .unwrap();
+                        // a hit never disagrees with what the store has ever held
+                        assert!(found.is_none_or(|h| h == header(i)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in writers.into_iter().chain(readers) {
+            thread.join().unwrap();
+        }
+        for i in (0..4000).step_by(2) {
+            let cached = cache.get_or_load(&hash(i), || Ok::<_, Infallible>(None));
+            assert_eq!(cached.unwrap(), None, "pruned header {i} is still cached");
+        }
+    }
+}
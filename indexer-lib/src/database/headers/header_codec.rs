@@ -0,0 +1,212 @@
+//! Versioned value encoding of the block header partition.
+//!
+//! Compact mode (v3) values are a fixed size record, `[blue_work (23 BE)] [daa_score (8 BE)]
+//! [version (1)]`, the top byte of the blue work is dropped and has to be zero.
+//! Full mode values are `[version (1)] [mode (1)] [CompactHeader::encode (32)] [serialized
+//! RpcHeader]`, the compact part at a fixed offset so compact reads never decode a full header.
+//! Values written before versioning are a bare 32 byte `CompactHeaderDb`, whose last byte, the
+//! top byte of the DAA score, is always zero while v3 records end with their version. Both are
+//! decoded transparently.
+
+use crate::CompactHeader;
+use crate::database::headers::CompactHeaderDb;
+use anyhow::{Result, bail};
+use kaspa_rpc_core::RpcHeader;
+use serde::{Deserialize, Serialize};
+use workflow_serializer::prelude::{Deserializer, Serializer};
+
+pub const HEADER_CODEC_VERSION: u8 = 3;
+
+/// Compact mode values, v3 records as well as the unversioned ones
+pub const COMPACT_RECORD_LEN: usize = CompactHeader::ENCODED_LEN;
+const PREFIX_LEN: usize = 2;
+const COMPACT_LEN: usize = PREFIX_LEN + CompactHeader::ENCODED_LEN;
+
+/// What is stored per block header
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderStorageMode {
+    /// Fixed size record with blue work and daa score only
+    #[default]
+    Compact = 0,
+    /// Compact record followed by the complete header
+    Full = 1,
+}
+
+impl TryFrom<u8> for HeaderStorageMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            x if x == Self::Compact as u8 => Ok(Self::Compact),
+            x if x == Self::Full as u8 => Ok(Self::Full),
+            x => bail!("Unknown header storage mode: {x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StoredHeader {
+    Compact(CompactHeader),
+    Full(Box<RpcHeader>),
+}
+
+impl StoredHeader {
+    pub fn compact(&self) -> CompactHeader {
+        match self {
+            StoredHeader::Compact(compact) => *compact,
+            StoredHeader::Full(header) => CompactHeader {
+                blue_work: header.blue_work,
+                daa_score: header.daa_score,
+            },
+        }
+    }
+}
+
+pub fn encode_compact(header: &CompactHeader) -> Result<Vec<u8>> {
+    let encoded = header.encode();
+    if encoded[0] != 0 {
+        bail!(
+            "Blue work {} does not fit a compact header record",
+            header.blue_work
+        );
+    }
+    let mut value = Vec::with_capacity(COMPACT_RECORD_LEN);
+    value.extend_from_slice(&encoded[1..]);
+    value.push(HEADER_CODEC_VERSION);
+    Ok(value)
+}
+
+pub fn encode(mode: HeaderStorageMode, header: &RpcHeader) -> Result<Vec<u8>> {
+    let compact = CompactHeader {
+        blue_work: header.blue_work,
+        daa_score: header.daa_score,
+    };
+    match mode {
+        HeaderStorageMode::Compact => encode_compact(&compact),
+        HeaderStorageMode::Full => {
+            let mut value = Vec::with_capacity(COMPACT_LEN + 256);
+            value.push(HEADER_CODEC_VERSION);
+            value.push(HeaderStorageMode::Full as u8);
+            value.extend_from_slice(&compact.encode());
+            header.serialize(&mut value)?;
+            Ok(value)
+        }
+    }
+}
+
+/// Decodes only the compact part, whatever the version and mode of the value
+pub fn decode_compact(bytes: &[u8]) -> Result<CompactHeader> {
+    match bytes.len() {
+        COMPACT_RECORD_LEN => match bytes[COMPACT_RECORD_LEN - 1] {
+            0 => Ok((*bytemuck::from_bytes::<CompactHeaderDb>(bytes)).into()),
+            version => {
+                check_version(version)?;
+                let mut encoded = [0; CompactHeader::ENCODED_LEN];
+                encoded[1..].copy_from_slice(&bytes[..COMPACT_RECORD_LEN - 1]);
+                CompactHeader::decode(&encoded)
+            }
+        },
+        len if len >= COMPACT_LEN => {
+            check_version(bytes[0])?;
+            CompactHeader::decode(&bytes[PREFIX_LEN..COMPACT_LEN])
+        }
+        _ => bail!("Invalid CompactHeader length"),
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<StoredHeader> {
+    if bytes.len() == COMPACT_RECORD_LEN {
+        return Ok(StoredHeader::Compact(decode_compact(bytes)?));
+    }
+    // checks the length and the version
+    decode_compact(bytes)?;
+    match HeaderStorageMode::try_from(bytes[1])? {
+        // compact headers are always stored as records
+        HeaderStorageMode::Compact => bail!("Compact header with a full header prefix"),
+        HeaderStorageMode::Full => {
+            let mut reader = &bytes[COMPACT_LEN..];
+            Ok(StoredHeader::Full(Box::new(RpcHeader::deserialize(
+                &mut reader,
+            )?)))
+        }
+    }
+}
+
+fn check_version(version: u8) -> Result<()> {
+    if version != HEADER_CODEC_VERSION {
+        bail!("Unsupported header codec version: {version}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_consensus_core::BlueWorkType;
+
+    fn compact() -> CompactHeader {
+        CompactHeader {
+            blue_work: BlueWorkType::from_u64(777),
+            daa_score: 123,
+        }
+    }
+
+    fn compact_db() -> CompactHeaderDb {
+        CompactHeaderDb {
+            blue_work: BlueWorkType::from_u64(777).to_le_bytes(),
+            daa_score: 123u64.to_le_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_compact_roundtrip() {
+        let value = encode_compact(&compact()).unwrap();
+        assert_eq!(value.len(), COMPACT_RECORD_LEN);
+        assert_eq!(value[COMPACT_RECORD_LEN - 1], HEADER_CODEC_VERSION);
+        assert_eq!(decode_compact(&value).unwrap(), compact());
+        let StoredHeader::Compact(header) = decode(&value).unwrap() else {
+            panic!("compact value decoded as full header");
+        };
+        assert_eq!(header, compact());
+
+        let mut blue_work = [0xff; 24];
+        blue_work[0] = 0;
+        let largest = CompactHeader {
+            blue_work: BlueWorkType::from_be_bytes(blue_work),
+            daa_score: u64::MAX,
+        };
+        let value = encode_compact(&largest).unwrap();
+        assert_eq!(decode_compact(&value).unwrap(), largest);
+        let too_large = CompactHeader {
+            blue_work: BlueWorkType::from_be_bytes([1; 24]),
+            ..largest
+        };
+        assert!(encode_compact(&too_large).is_err());
+    }
+
+    #[test]
+    fn test_legacy_value_is_decoded() {
+        let legacy = bytemuck::bytes_of(&compact_db()).to_vec();
+        assert_eq!(decode_compact(&legacy).unwrap(), compact());
+        assert_eq!(decode(&legacy).unwrap().compact().daa_score, 123);
+    }
+
+    #[test]
+    fn test_unknown_version_is_rejected() {
+        let mut value = encode_compact(&compact()).unwrap();
+        value[COMPACT_RECORD_LEN - 1] = HEADER_CODEC_VERSION + 1;
+        assert!(decode_compact(&value).is_err());
+        // versions that never reached a database
+        value[COMPACT_RECORD_LEN - 1] = 2;
+        assert!(decode_compact(&value).is_err());
+        let mut value = vec![HEADER_CODEC_VERSION + 1, HeaderStorageMode::Full as u8];
+        value.extend_from_slice(&compact().encode());
+        assert!(decode_compact(&value).is_err());
+        value[0] = HEADER_CODEC_VERSION;
+        value[1] = 42;
+        assert!(decode(&value).is_err());
+        assert!(decode_compact(&value[..10]).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 //! Block header data and gap tracking.
 //!
-//! Contains partitions for storing block compact headers (DAA scores, blue work)
-//! and tracking missing blocks in the main chain.
+//! Contains partitions for storing block compact headers (DAA scores, blue work),
+//! the selected chain membership and order of blocks, the merge sets of blocks and tracking
+//! missing blocks in the main chain together with the history of their syncers.
 
 pub mod block_compact_headers;
 pub mod block_gaps;
@@ -11,3 +12,21 @@ pub use block_gaps::*;
 
 pub mod daa_index;
 pub use daa_index::*;
+
+pub mod chain_membership;
+pub use chain_membership::*;
+
+pub mod chain_index;
+pub use chain_index::*;
+
+pub mod block_relations;
+pub use block_relations::*;
+
+pub mod gap_history;
+pub use gap_history::*;
+
+pub mod header_cache;
+pub use header_cache::{DEFAULT_HEADER_CACHE_CAPACITY, HeaderCacheStats};
+
+pub mod header_codec;
+pub use header_codec::{HeaderStorageMode, StoredHeader};
@@ -0,0 +1,243 @@
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockGap, BlockGapsPartition, DaaIndexPartition,
+};
+use crate::database::processing::{AcceptingBlockToTxIDPartition, TxIDToAcceptancePartition};
+use anyhow::Result;
+use fjall::{ReadTransaction, TxKeyspace};
+use kaspa_rpc_core::{RpcHash, RpcTransactionId};
+use std::fmt;
+use tracing::{info, warn};
+
+/// Amount of offending keys kept per check as examples
+const MAX_EXAMPLES: usize = 5;
+
+/// Outcome of a single consistency check
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub name: &'static str,
+    pub checked: u64,
+    pub errors: u64,
+    pub repaired: u64,
+    pub examples: Vec<String>,
+}
+
+impl CheckReport {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    fn error(&mut self, example: impl FnOnce() -> String) {
+        self.errors += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(example());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub checks: Vec<CheckReport>,
+}
+
+impl IntegrityReport {
+    /// True when no check found an issue that is still unrepaired
+    pub fn is_consistent(&self) -> bool {
+        self.checks.iter().all(|c| c.errors == c.repaired)
+    }
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{}: checked {}, errors {}, repaired {}",
+                check.name, check.checked, check.errors, check.repaired
+            )?;
+            for example in &check.examples {
+                writeln!(f, "  {example}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Verifies cross-partition invariants of the database.
+///
+/// All checks stream through a single read snapshot, memory stays bounded regardless of database size.
+/// With `repair` dangling index entries are removed and missing index entries are re-created,
+/// everything else is only reported.
+pub fn check(keyspace: &TxKeyspace, repair: bool) -> Result<IntegrityReport> {
+    let rtx = keyspace.read_tx();
+    let headers = BlockCompactHeaderPartition::new(keyspace)?;
+    let daa_index = DaaIndexPartition::new(keyspace)?;
+
+    let report = IntegrityReport {
+        checks: vec![
+            check_daa_index_entries(&rtx, &headers, &daa_index, repair)?,
+            check_headers_indexed(&rtx, &headers, &daa_index, repair)?,
+            check_acceptance(
+                &rtx,
+                &TxIDToAcceptancePartition::new(keyspace)?,
+                &AcceptingBlockToTxIDPartition::new(keyspace)?,
+            )?,
+            check_gaps(BlockGapsPartition::new(keyspace)?.get_all_gaps_rtx(&rtx))?,
+        ],
+    };
+    if report.is_consistent() {
+        info!("Integrity check passed");
+    } else {
+        warn!("Integrity check found issues:\n{report}");
+    }
+    Ok(report)
+}
+
+/// Every daa index entry points at a stored header with the same daa score
+fn check_daa_index_entries(
+    rtx: &ReadTransaction,
+    headers: &BlockCompactHeaderPartition,
+    daa_index: &DaaIndexPartition,
+    repair: bool,
+) -> Result<CheckReport> {
+    let mut report = CheckReport::new("daa_index -> block_compact_header");
+    for entry in daa_index.iter_lt(rtx, u64::MAX) {
+        let (daa_score, hash) = entry?;
+        report.checked += 1;
+        let stored_daa = headers.get_daa_score_rtx(rtx, &hash)?;
+        if stored_daa != Some(daa_score) {
+            report.error(|| format!("daa {daa_score} block {hash}: header daa {stored_daa:?}"));
+            if repair {
+                daa_index.delete(daa_score, &hash)?;
+                report.repaired += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Every header is reachable through the daa index, otherwise it is never pruned
+fn check_headers_indexed(
+    rtx: &ReadTransaction,
+    headers: &BlockCompactHeaderPartition,
+    daa_index: &DaaIndexPartition,
+    repair: bool,
+) -> Result<CheckReport> {
+    let mut report = CheckReport::new("block_compact_header -> daa_index");
+    for entry in headers.iter_rtx(rtx) {
+        let (hash, header) = entry?;
+        report.checked += 1;
+        if !daa_index.contains_rtx(rtx, header.daa_score, &hash)? {
+            report.error(|| format!("block {hash} daa {} is not indexed", header.daa_score));
+            if repair {
+                daa_index.insert(header.daa_score, &hash)?;
+                report.repaired += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Every accepted transaction points at an accepting chain block listing it
+fn check_acceptance(
+    rtx: &ReadTransaction,
+    tx_id_to_acceptance: &TxIDToAcceptancePartition,
+    acceptance_to_tx_id: &AcceptingBlockToTxIDPartition,
+) -> Result<CheckReport> {
+    let mut report = CheckReport::new("tx_id_to_acceptance -> accepting_block_to_tx_id");
+    for key in tx_id_to_acceptance.iter_keys_rtx(rtx) {
+        let key = key?;
+        report.checked += 1;
+        if key.accepted_by_block_hash == [0u8; 32] {
+            // not accepted yet
+            continue;
+        }
+        let block = RpcHash::from_bytes(key.accepted_by_block_hash);
+        let listed = acceptance_to_tx_id
+            .get_rtx(rtx, &block)?
+            .is_some_and(|tx_ids| tx_ids.as_tx_ids().contains(&key.tx_id));
+        if !listed {
+            report.error(|| {
+                format!(
+                    "tx {} accepted by {block} which does not list it",
+                    RpcTransactionId::from_bytes(key.tx_id)
+                )
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Gaps are ordered by their start, none of them may start before the previous one ends
+fn check_gaps(gaps: impl Iterator<Item = Result<BlockGap>>) -> Result<CheckReport> {
+    let mut report = CheckReport::new("block_gaps overlap");
+    let mut previous: Option<BlockGap> = None;
+    for gap in gaps {
+        let gap = gap?;
+        report.checked += 1;
+        if let Some(prev) = &previous
+            && gap.from_daa_score < prev.to_daa_score
+        {
+            report.error(|| {
+                format!(
+                    "gap {}..{} overlaps gap {}..{}",
+                    gap.from_daa_score, gap.to_daa_score, prev.from_daa_score, prev.to_daa_score
+                )
+            });
+        }
+        if previous
+            .as_ref()
+            .is_none_or(|prev| gap.to_daa_score > prev.to_daa_score)
+        {
+            previous = Some(gap);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_math::Uint192;
+
+    fn gap(from: u64, to: u64) -> BlockGap {
+        BlockGap {
+            from_daa_score: from,
+            from_blue_work: Uint192::from_u64(from),
+            from_block_hash: RpcHash::from_u64_word(from),
+            to_blue_work: Uint192::from_u64(to),
+            to_block_hash: RpcHash::from_u64_word(to),
+            to_daa_score: to,
+        }
+    }
+
+    #[test]
+    fn test_check_gaps() {
+        let disjoint = vec![gap(0, 10), gap(10, 20), gap(30, 40)];
+        let report = check_gaps(disjoint.into_iter().map(Ok)).unwrap();
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.errors, 0);
+
+        // second gap is nested in the first, third one starts inside the first
+        let overlapping = vec![gap(0, 100), gap(10, 20), gap(50, 150)];
+        let report = check_gaps(overlapping.into_iter().map(Ok)).unwrap();
+        assert_eq!(report.errors, 2);
+        assert_eq!(report.examples.len(), 2);
+    }
+
+    #[test]
+    fn test_examples_are_capped() {
+        let mut report = CheckReport::new("test");
+        for i in 0..MAX_EXAMPLES * 2 {
+            report.error(|| i.to_string());
+        }
+        assert_eq!(report.errors, (MAX_EXAMPLES * 2) as u64);
+        assert_eq!(report.examples.len(), MAX_EXAMPLES);
+        let integrity = IntegrityReport {
+            checks: vec![report],
+        };
+        assert!(!integrity.is_consistent());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::database::messages::AddressPayload;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::{Result, bail};
 use bytemuck::{AnyBitPattern, NoUninit};
 use fjall::{PartitionCreateOptions, ReadTransaction, UserKey, WriteTransaction};
@@ -54,10 +55,26 @@ impl<T: AsRef<[u8]>> Deref for LikeContextualMessageBySenderKey<T> {
     }
 }
 
+impl DescribePartition for ContextualMessageBySenderPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "contextual_message_by_sender",
+        key: &[
+            field("sender", FieldType::AddressPayload),
+            field("alias", FieldType::Bytes(16)),
+            field("block_time", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+            field("version", FieldType::U8),
+            field("tx_id", FieldType::Hash),
+        ],
+        value: &[field("sealed_hex", FieldType::Tail("bytes"))],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl ContextualMessageBySenderPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "contextual_message_by_sender",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
@@ -150,6 +167,53 @@ impl ContextualMessageBySenderPartition {
             ))
         })
     }
+
+    /// Distinct aliases of the messages of `sender`, seeking from one alias to the next
+    pub fn get_aliases_by_sender_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        sender: &AddressPayload,
+    ) -> Result<Vec<[u8; 16]>> {
+        let sender = bytemuck::bytes_of(sender);
+        let mut aliases = Vec::new();
+        let mut from = [0u8; 16];
+        loop {
+            let start = [sender, &from].concat();
+            let Some(item) = rtx.range(&self.0, start..).next() else {
+                break;
+            };
+            let (key_bytes, _) = item?;
+            if !key_bytes.starts_with(sender) {
+                break;
+            }
+            let alias = LikeContextualMessageBySenderKey::new(key_bytes).alias;
+            aliases.push(alias);
+            match u128::from_be_bytes(alias).checked_add(1) {
+                Some(next) => from = next.to_be_bytes(),
+                None => break,
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// Messages of `sender` under `alias`, ordered by block time
+    pub fn get_by_sender_alias_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        sender: &AddressPayload,
+        alias: [u8; 16],
+    ) -> impl DoubleEndedIterator<Item = Result<(LikeContextualMessageBySenderKey<UserKey>, Vec<u8>)>> + 'a
+    {
+        let prefix = [bytemuck::bytes_of(sender), &alias].concat();
+        rtx.prefix(&self.0, prefix).map(|item| {
+            let (key_bytes, value_bytes) = item?;
+            Ok((
+                LikeContextualMessageBySenderKey::new(key_bytes),
+                value_bytes.to_vec(),
+            ))
+        })
+    }
+
     /// Get all contextual messages (for admin/debug purposes)
     pub fn get_all(
         &self,
@@ -1,7 +1,8 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::bail;
 use bytemuck::{AnyBitPattern, NoUninit};
-use fjall::{PartitionCreateOptions, WriteTransaction};
-use kaspa_addresses::Version;
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_addresses::{Prefix, Version};
 use kaspa_rpc_core::{RpcAddress, RpcScriptPublicKey};
 use kaspa_txscript::pay_to_address_script;
 use kaspa_txscript::script_class::ScriptClass;
@@ -12,7 +13,7 @@ use std::ops::Deref;
 const EMPTY_VERSION: u8 = 0; // used when we don't know address at all
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, AnyBitPattern, NoUninit)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, AnyBitPattern, NoUninit)]
 pub struct AddressPayload {
     pub inverse_version: u8,
     pub payload: [u8; 33], // last byte is unused in case of scripthash and XonlyPubkey
@@ -66,6 +67,19 @@ impl TryFrom<&RpcScriptPublicKey> for AddressPayload {
         }
     }
 }
+impl AddressPayload {
+    /// Address of the payload on the network of `prefix`, none while unknown
+    pub fn to_address(&self, prefix: Prefix) -> Option<RpcAddress> {
+        let (version, len) = match u8::MAX.wrapping_sub(self.inverse_version) {
+            v if v == Version::PubKey as u8 => (Version::PubKey, 32),
+            v if v == Version::PubKeyECDSA as u8 => (Version::PubKeyECDSA, 33),
+            v if v == Version::ScriptHash as u8 => (Version::ScriptHash, 32),
+            _ => return None,
+        };
+        Some(RpcAddress::new(prefix, version, &self.payload[..len]))
+    }
+}
+
 impl TryFrom<&RpcAddress> for AddressPayload {
     type Error = anyhow::Error;
 
@@ -77,10 +91,25 @@ impl TryFrom<&RpcAddress> for AddressPayload {
 #[derive(Clone)]
 pub struct HandshakeBySenderPartition(fjall::TxPartition);
 
+impl DescribePartition for HandshakeBySenderPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "handshake_by_sender",
+        key: &[
+            field("sender", FieldType::AddressPayload),
+            field("block_time", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+            field("receiver", FieldType::AddressPayload),
+            field("version", FieldType::U8),
+            field("tx_id", FieldType::Hash),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl HandshakeBySenderPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "handshake_by_sender",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
@@ -95,6 +124,28 @@ impl HandshakeBySenderPartition {
                 .map(|k| *bytemuck::from_bytes(k.as_ref()))
         })
     }
+
+    pub fn iter_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+    ) -> impl Iterator<Item = anyhow::Result<HandshakeKeyBySender>> + 'a {
+        rtx.keys(&self.0).map(|r| {
+            let key = r?;
+            Ok(*bytemuck::from_bytes(key.as_ref()))
+        })
+    }
+
+    /// Handshakes sent by `sender`, ordered by block time
+    pub fn get_by_sender_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        sender: &AddressPayload,
+    ) -> impl DoubleEndedIterator<Item = anyhow::Result<HandshakeKeyBySender>> + 'a {
+        rtx.prefix(&self.0, bytemuck::bytes_of(sender)).map(|r| {
+            let (key, _) = r?;
+            Ok(*bytemuck::from_bytes(key.as_ref()))
+        })
+    }
 }
 
 #[derive(Clone, Copy, Debug, AnyBitPattern, NoUninit, PartialEq, Eq)]
@@ -167,10 +218,25 @@ pub struct HandshakeKeyByReceiver {
 #[derive(Clone)]
 pub struct HandshakeByReceiverPartition(fjall::TxPartition);
 
+impl DescribePartition for HandshakeByReceiverPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "handshake_by_receiver",
+        key: &[
+            field("receiver", FieldType::AddressPayload),
+            field("block_time", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+            field("version", FieldType::U8),
+            field("tx_id", FieldType::Hash),
+        ],
+        value: &[field("sender", FieldType::AddressPayload)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl HandshakeByReceiverPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "handshake_by_receiver",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
@@ -199,6 +265,39 @@ impl HandshakeByReceiverPartition {
         );
     }
 
+    /// Handshakes received by `receiver` with their senders, ordered by block time
+    pub fn get_by_receiver_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        receiver: &AddressPayload,
+    ) -> impl DoubleEndedIterator<Item = anyhow::Result<(HandshakeKeyByReceiver, AddressPayload)>> + 'a
+    {
+        rtx.prefix(&self.0, bytemuck::bytes_of(receiver)).map(|r| {
+            let (key, value) = r?;
+            Ok((
+                *bytemuck::from_bytes(key.as_ref()),
+                *bytemuck::from_bytes(value.as_ref()),
+            ))
+        })
+    }
+
+    pub fn remove_wtx(&self, wtx: &mut WriteTransaction, key: &HandshakeKeyByReceiver) {
+        wtx.remove(&self.0, bytemuck::bytes_of(key));
+    }
+
+    pub fn iter_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+    ) -> impl Iterator<Item = anyhow::Result<(HandshakeKeyByReceiver, AddressPayload)>> + 'a {
+        rtx.iter(&self.0).map(|r| {
+            let (key, value) = r?;
+            Ok((
+                *bytemuck::from_bytes(key.as_ref()),
+                *bytemuck::from_bytes(value.as_ref()),
+            ))
+        })
+    }
+
     pub fn iter(
         &self,
     ) -> impl Iterator<Item = anyhow::Result<(HandshakeKeyByReceiver, AddressPayload)>> {
@@ -216,6 +315,15 @@ impl HandshakeByReceiverPartition {
 #[derive(Clone)]
 pub struct TxIdToHandshakePartition(fjall::TxPartition);
 
+impl DescribePartition for TxIdToHandshakePartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "tx-id-to-handshake",
+        key: &[field("tx_id", FieldType::Hash)],
+        value: &[field("sealed_hex", FieldType::Tail("bytes"))],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl TxIdToHandshakePartition {
     pub fn len(&self) -> anyhow::Result<usize> {
         Ok(self.0.inner().len()?)
@@ -228,8 +336,8 @@ impl TxIdToHandshakePartition {
 
 impl TxIdToHandshakePartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "tx-id-to-handshake",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
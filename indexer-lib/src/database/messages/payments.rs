@@ -1,7 +1,8 @@
 use crate::database::messages::AddressPayload;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::bail;
 use bytemuck::{AnyBitPattern, NoUninit};
-use fjall::{PartitionCreateOptions, WriteTransaction};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
 use kaspa_rpc_core::RpcTransactionId;
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -44,10 +45,25 @@ impl<T: AsRef<[u8]>> Deref for LikePaymentKeyBySender<T> {
 #[derive(Clone)]
 pub struct PaymentBySenderPartition(fjall::TxPartition);
 
+impl DescribePartition for PaymentBySenderPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "payment_by_sender",
+        key: &[
+            field("sender", FieldType::AddressPayload),
+            field("block_time", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+            field("receiver", FieldType::AddressPayload),
+            field("version", FieldType::U8),
+            field("tx_id", FieldType::Hash),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl PaymentBySenderPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "payment_by_sender",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
@@ -61,6 +77,28 @@ impl PaymentBySenderPartition {
         wtx.insert(&self.0, bytemuck::bytes_of(key), []);
     }
 
+    /// Payments sent by `sender`, ordered by block time
+    pub fn get_by_sender_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        sender: &AddressPayload,
+    ) -> impl DoubleEndedIterator<Item = anyhow::Result<PaymentKeyBySender>> + 'a {
+        rtx.prefix(&self.0, bytemuck::bytes_of(sender)).map(|r| {
+            let (key, _) = r?;
+            Ok(*bytemuck::from_bytes(key.as_ref()))
+        })
+    }
+
+    pub fn iter_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+    ) -> impl Iterator<Item = anyhow::Result<PaymentKeyBySender>> + 'a {
+        rtx.keys(&self.0).map(|r| {
+            let key = r?;
+            Ok(*bytemuck::from_bytes(key.as_ref()))
+        })
+    }
+
     pub fn approximate_len(&self) -> usize {
         self.0.approximate_len()
     }
@@ -103,10 +141,25 @@ impl<T: AsRef<[u8]>> Deref for LikePaymentKeyByReceiver<T> {
 #[derive(Clone)]
 pub struct PaymentByReceiverPartition(fjall::TxPartition);
 
+impl DescribePartition for PaymentByReceiverPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "payment_by_receiver",
+        key: &[
+            field("receiver", FieldType::AddressPayload),
+            field("block_time", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+            field("version", FieldType::U8),
+            field("tx_id", FieldType::Hash),
+        ],
+        value: &[field("sender", FieldType::AddressPayload)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl PaymentByReceiverPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "payment_by_receiver",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
@@ -135,11 +188,56 @@ impl PaymentByReceiverPartition {
             bytemuck::bytes_of(&sender),
         );
     }
+
+    pub fn remove_wtx(&self, wtx: &mut WriteTransaction, key: &PaymentKeyByReceiver) {
+        wtx.remove(&self.0, bytemuck::bytes_of(key));
+    }
+
+    pub fn iter_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+    ) -> impl Iterator<Item = anyhow::Result<(PaymentKeyByReceiver, AddressPayload)>> + 'a {
+        rtx.iter(&self.0).map(|r| {
+            let (key, value) = r?;
+            Ok((
+                *bytemuck::from_bytes(key.as_ref()),
+                *bytemuck::from_bytes(value.as_ref()),
+            ))
+        })
+    }
+
+    /// Payments received by `receiver` with their senders, ordered by block time
+    pub fn get_by_receiver_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        receiver: &AddressPayload,
+    ) -> impl DoubleEndedIterator<Item = anyhow::Result<(PaymentKeyByReceiver, AddressPayload)>> + 'a
+    {
+        rtx.prefix(&self.0, bytemuck::bytes_of(receiver)).map(|r| {
+            let (key, value) = r?;
+            Ok((
+                *bytemuck::from_bytes(key.as_ref()),
+                *bytemuck::from_bytes(value.as_ref()),
+            ))
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct TxIdToPaymentPartition(fjall::TxPartition);
 
+impl DescribePartition for TxIdToPaymentPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "tx_id_to_payment",
+        key: &[field("tx_id", FieldType::Hash)],
+        value: &[
+            field("amount", FieldType::U64Be),
+            field("sealed_hex", FieldType::Tail("bytes")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl TxIdToPaymentPartition {
     pub fn len(&self) -> anyhow::Result<usize> {
         Ok(self.0.inner().len()?)
@@ -152,8 +250,8 @@ impl TxIdToPaymentPartition {
 
 impl TxIdToPaymentPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "tx_id_to_payment",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default(),
         )?))
     }
@@ -220,6 +318,23 @@ impl TxIdToPaymentPartition {
         }
     }
 
+    pub fn get_amount_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tx_id: &[u8; 32],
+    ) -> anyhow::Result<Option<u64>> {
+        let Some(value_bytes) = rtx.get(&self.0, tx_id)? else {
+            return Ok(None);
+        };
+        let Some(amount_bytes) = value_bytes.first_chunk::<8>() else {
+            bail!(
+                "Invalid value length in tx_id_to_payment partition: expected at least 8 bytes, got {}",
+                value_bytes.len()
+            )
+        };
+        Ok(Some(u64::from_be_bytes(*amount_bytes)))
+    }
+
     pub fn get_tx_id(&self, tx_id: RpcTransactionId) -> anyhow::Result<Option<(u64, Vec<u8>)>> {
         self.get(&tx_id.as_bytes())
     }
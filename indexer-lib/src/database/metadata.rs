@@ -1,13 +1,37 @@
+use crate::database::headers::HeaderStorageMode;
+use crate::database::schema::{
+    self, Compression, DescribePartition, FieldType, PartitionDescription, field,
+};
 use crate::historical_syncer::Cursor;
+use crate::ingest_filter::{FilterChangePolicy, IngestFilter, IngestFilterState};
+use crate::node_capabilities::NodeVersion;
 use anyhow::{Result, bail};
 use bytemuck::{AnyBitPattern, NoUninit};
 use fjall::{CompressionType, PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::RpcHash;
 use std::cmp::Ordering;
 use tracing::warn;
 
+/// Block tips kept in [`MetadataKey::BlockTipHistory`]
+pub const BLOCK_TIP_HISTORY: usize = 16;
+
 /// Metadata partition for storing latest known cursors
 /// Key: enum of metadata types
-/// Value: cursor data (blue work + block hash + daa_score)
+/// Value: cursor data (blue work + block hash + daa_score),
+/// except for [`MetadataKey::HeaderValidation`] holding a [`HeaderValidationState`],
+/// [`MetadataKey::FinalizedChainIndex`] and [`MetadataKey::BalanceDeltasPrunedDaa`] holding
+/// 8 bytes BE, [`MetadataKey::NodeRequirements`] holding [`NodeRequirements`] and
+/// [`MetadataKey::NetworkId`] holding the network id (utf8),
+/// [`MetadataKey::AggregateBucketWidth`] and [`MetadataKey::LastWebhookSubscriptionId`]
+/// holding 8 bytes BE, [`MetadataKey::BlockTipHistory`] holding cursor values back to back,
+/// [`MetadataKey::IngestFilter`] holding an [`IngestFilterState`],
+/// [`MetadataKey::HeaderStorageMode`] holding the [`HeaderStorageMode`] byte,
+/// [`MetadataKey::BlocksPruned`] holding a [`BlocksPruned`]
+///
+/// Processor tips are written in the same write transaction as the data they cover, so a
+/// crash never leaves a tip ahead of its data. A processor committing its data in several
+/// transactions must write the tip with the last one: a tip behind the data is accepted,
+/// the data is reprocessed after restart and the writes are idempotent.
 #[derive(Clone)]
 pub struct MetadataPartition(pub fjall::TxPartition);
 
@@ -16,6 +40,68 @@ pub struct MetadataPartition(pub fjall::TxPartition);
 pub enum MetadataKey {
     LatestBlockCursor = 0,
     LatestAcceptingBlockCursor = 1,
+    /// Sink the selected chain syncer was syncing towards
+    Sink = 2,
+    /// Progress of the stored header re-validation
+    HeaderValidation = 3,
+    /// Pruning point of the node, last reported by the subscriber
+    NodePruningPoint = 4,
+    /// Chain index of the last chain block whose acceptance was finalized
+    FinalizedChainIndex = 5,
+    /// Last chain block the selected chain syncer forwarded, ahead of the VCP tip while the
+    /// forwarded pages are applied
+    ChainSyncPosition = 6,
+    /// Network and minimum version of the node the database was built from
+    NodeRequirements = 7,
+    /// DAA score of the last finalized chain block whose balance deltas were dropped
+    BalanceDeltasPrunedDaa = 8,
+    /// Network the database was created for
+    NetworkId = 9,
+    /// DAA scores per bucket of the aggregates
+    AggregateBucketWidth = 10,
+    /// Id of the last webhook subscription created
+    LastWebhookSubscriptionId = 11,
+    /// Sum of the coinbase outputs accepted since the supply is tracked
+    AcceptedRewards = 12,
+    /// Node circulating supply minus the accepted rewards at the first supply cross-check
+    SupplyBaseline = 13,
+    /// Last [`BLOCK_TIP_HISTORY`] block tips, oldest first, the current one included
+    BlockTipHistory = 14,
+    /// Ingest filter the blocks were indexed under
+    IngestFilter = 15,
+    /// Storage mode of the block headers, chosen when the database was created
+    HeaderStorageMode = 16,
+    /// DAA score below which the blocks are pruned, with the number of times it moved
+    BlocksPruned = 17,
+}
+
+/// Pruning state of the blocks, read by the query API to tell whether a page cursor
+/// survived the pruning since it was issued
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlocksPruned {
+    /// Incremented every time `daa_score` moves
+    pub sequence: u64,
+    /// Blocks below are pruned or being pruned
+    pub daa_score: u64,
+}
+
+impl BlocksPruned {
+    fn encode(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.daa_score.to_be_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let Ok(bytes) = <[u8; 16]>::try_from(bytes) else {
+            bail!("Invalid blocks pruned size");
+        };
+        Ok(Self {
+            sequence: u64::from_be_bytes(bytes[..8].try_into()?),
+            daa_score: u64::from_be_bytes(bytes[8..].try_into()?),
+        })
+    }
 }
 
 #[repr(C)]
@@ -26,40 +112,206 @@ pub struct CursorValue {
     pub daa_score: [u8; 8],
 }
 
+impl From<Cursor> for CursorValue {
+    fn from(cursor: Cursor) -> Self {
+        Self {
+            blue_work: cursor.blue_work.to_be_bytes(),
+            block_hash: *cursor.hash.as_ref(),
+            daa_score: cursor.daa_score.to_le_bytes(),
+        }
+    }
+}
+
+impl CursorValue {
+    fn decode(bytes: &[u8]) -> Result<Cursor> {
+        if bytes.len() != size_of::<Self>() {
+            bail!("Invalid cursor value size")
+        }
+        let value: Self = *bytemuck::from_bytes(bytes);
+        Ok(Cursor {
+            blue_work: kaspa_math::Uint192::from_be_bytes(value.blue_work),
+            hash: kaspa_rpc_core::RpcHash::from_slice(&value.block_hash),
+            daa_score: u64::from_le_bytes(value.daa_score),
+        })
+    }
+}
+
+/// Re-validation of stored headers against the consensus crate version it ran with.
+///
+/// Encoded as `[completed (1)] [watermark (32)] [checked (8 BE)] [mismatches (8 BE)] [version (utf8)]`,
+/// an all zero watermark means the scan has not started yet.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HeaderValidationState {
+    pub consensus_version: String,
+    /// Last header hash validated, the scan resumes after it
+    pub watermark: Option<RpcHash>,
+    pub checked: u64,
+    pub mismatches: u64,
+    pub completed: bool,
+}
+
+impl HeaderValidationState {
+    const FIXED_LEN: usize = 1 + 32 + 8 + 8;
+
+    pub fn new(consensus_version: &str) -> Self {
+        Self {
+            consensus_version: consensus_version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(Self::FIXED_LEN + self.consensus_version.len());
+        value.push(self.completed as u8);
+        value.extend_from_slice(&self.watermark.unwrap_or_default().as_bytes());
+        value.extend_from_slice(&self.checked.to_be_bytes());
+        value.extend_from_slice(&self.mismatches.to_be_bytes());
+        value.extend_from_slice(self.consensus_version.as_bytes());
+        value
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Self::FIXED_LEN {
+            bail!("Invalid header validation state size")
+        }
+        let watermark = RpcHash::from_slice(&bytes[1..33]);
+        Ok(Self {
+            completed: bytes[0] != 0,
+            watermark: (watermark != RpcHash::default()).then_some(watermark),
+            checked: u64::from_be_bytes(bytes[33..41].try_into()?),
+            mismatches: u64::from_be_bytes(bytes[41..49].try_into()?),
+            consensus_version: String::from_utf8(bytes[Self::FIXED_LEN..].to_vec())?,
+        })
+    }
+}
+
+/// Node a database may be served by, recorded with the first connect.
+///
+/// Encoded as `[major (2 BE)] [minor (2 BE)] [patch (2 BE)] [network_id (utf8)]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeRequirements {
+    pub network_id: String,
+    pub min_version: NodeVersion,
+}
+
+impl NodeRequirements {
+    const FIXED_LEN: usize = 6;
+
+    fn encode(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(Self::FIXED_LEN + self.network_id.len());
+        value.extend_from_slice(&self.min_version.major.to_be_bytes());
+        value.extend_from_slice(&self.min_version.minor.to_be_bytes());
+        value.extend_from_slice(&self.min_version.patch.to_be_bytes());
+        value.extend_from_slice(self.network_id.as_bytes());
+        value
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Self::FIXED_LEN {
+            bail!("Invalid node requirements size")
+        }
+        let component = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        Ok(Self {
+            min_version: NodeVersion::new(component(0), component(2), component(4)),
+            network_id: String::from_utf8(bytes[Self::FIXED_LEN..].to_vec())?,
+        })
+    }
+}
+
+impl DescribePartition for MetadataPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "metadata",
+        key: &[field("metadata_key", FieldType::U8)],
+        value: &[
+            field("blue_work", FieldType::Uint192Be),
+            field("block_hash", FieldType::Hash),
+            field("daa_score", FieldType::U64Le),
+        ],
+        compression: Compression::None,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl MetadataPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(
-            keyspace.open_partition(
-                "metadata",
-                PartitionCreateOptions::default()
-                    .block_size(1024)
-                    .compression(CompressionType::None),
-            )?,
-        ))
-    }
-
-    /// Store latest block cursor
-    pub fn set_latest_block_cursor(
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default()
+                .block_size(1024)
+                .compression(CompressionType::None),
+        )?))
+    }
+
+    /// Store the block processor tip, never moves backwards. A tip moving forward is added to
+    /// the tip history
+    pub fn set_block_tip(&self, wtx: &mut WriteTransaction, cursor: Cursor) -> Result<()> {
+        let key = [MetadataKey::LatestBlockCursor as u8];
+        let value = CursorValue::from(cursor);
+        let advances = match wtx.get(&self.0, key)? {
+            None => true,
+            Some(old_value) => {
+                let old = bytemuck::from_bytes::<CursorValue>(old_value.as_ref());
+                value.cmp(old) == Ordering::Greater
+            }
+        };
+        if !advances {
+            return Ok(());
+        }
+        wtx.insert(&self.0, key, bytemuck::bytes_of(&value));
+        let history_key = [MetadataKey::BlockTipHistory as u8];
+        let mut history = wtx
+            .get(&self.0, history_key)?
+            .map(|history| history.to_vec())
+            .unwrap_or_default();
+        let dropped =
+            (history.len() / size_of::<CursorValue>()).saturating_sub(BLOCK_TIP_HISTORY - 1);
+        history.drain(..dropped * size_of::<CursorValue>());
+        history.extend_from_slice(bytemuck::bytes_of(&value));
+        wtx.insert(&self.0, history_key, history);
+        Ok(())
+    }
+
+    /// Previous block tips, oldest first, the current tip last
+    pub fn get_block_tip_history_rtx(&self, rtx: &ReadTransaction) -> Result<Vec<Cursor>> {
+        let Some(bytes) = rtx.get(&self.0, [MetadataKey::BlockTipHistory as u8])? else {
+            return Ok(Vec::new());
+        };
+        if bytes.len() % size_of::<CursorValue>() != 0 {
+            bail!("Invalid block tip history size")
+        }
+        bytes
+            .chunks_exact(size_of::<CursorValue>())
+            .map(CursorValue::decode)
+            .collect()
+    }
+
+    /// Moves the block tip back to `cursor`, removes it for none. The history keeps the tips
+    /// up to the new one
+    pub fn roll_back_block_tip(
         &self,
         wtx: &mut WriteTransaction,
-        cursor: Cursor,
+        cursor: Option<Cursor>,
     ) -> Result<()> {
         let key = [MetadataKey::LatestBlockCursor as u8];
-        let value = CursorValue {
-            blue_work: cursor.blue_work.to_be_bytes(),
-            block_hash: *cursor.hash.as_ref(),
-            daa_score: cursor.daa_score.to_le_bytes(),
+        let history_key = [MetadataKey::BlockTipHistory as u8];
+        let Some(cursor) = cursor else {
+            wtx.remove(&self.0, key);
+            wtx.remove(&self.0, history_key);
+            return Ok(());
         };
-        wtx.fetch_update(&self.0, key, |old_value| match old_value {
-            None => Some(bytemuck::bytes_of(&value).into()),
-            Some(old_value) => {
-                let old = bytemuck::from_bytes::<CursorValue>(old_value.as_ref());
-                match value.cmp(old) {
-                    Ordering::Greater => Some(bytemuck::bytes_of(&value).into()),
-                    _ => Some(old_value.clone()),
+        let value = CursorValue::from(cursor);
+        wtx.insert(&self.0, key, bytemuck::bytes_of(&value));
+        let mut history = Vec::new();
+        if let Some(bytes) = wtx.get(&self.0, history_key)? {
+            for tip in bytes.chunks_exact(size_of::<CursorValue>()) {
+                if bytemuck::from_bytes::<CursorValue>(tip) >= &value {
+                    break;
                 }
+                history.extend_from_slice(tip);
             }
-        })?;
+        }
+        history.extend_from_slice(bytemuck::bytes_of(&value));
+        wtx.insert(&self.0, history_key, history);
         Ok(())
     }
 
@@ -103,22 +355,329 @@ impl MetadataPartition {
         }
     }
 
-    /// Store latest accepting block cursor
-    pub fn set_latest_accepting_block_cursor(
+    /// Store the virtual chain processor tip (latest accepting block).
+    /// Moves backwards on reorgs, so it's overwritten unconditionally
+    pub fn set_vcp_tip(&self, wtx: &mut WriteTransaction, cursor: Cursor) -> Result<()> {
+        let key = [MetadataKey::LatestAcceptingBlockCursor as u8];
+        wtx.insert(&self.0, key, bytemuck::bytes_of(&CursorValue::from(cursor)));
+        Ok(())
+    }
+
+    /// Store the sink the selected chain syncer is syncing towards.
+    /// Not tied to any data, hence written on its own
+    pub fn set_sink(&self, cursor: Cursor) -> Result<()> {
+        let key = [MetadataKey::Sink as u8];
+        self.0
+            .insert(key, bytemuck::bytes_of(&CursorValue::from(cursor)))?;
+        Ok(())
+    }
+
+    pub fn get_sink_rtx(&self, rtx: &ReadTransaction) -> Result<Option<Cursor>> {
+        let key = [MetadataKey::Sink as u8];
+        rtx.get(&self.0, key)?
+            .map(|bytes| CursorValue::decode(&bytes))
+            .transpose()
+    }
+
+    pub fn get_sink(&self) -> Result<Option<Cursor>> {
+        let key = [MetadataKey::Sink as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| CursorValue::decode(&bytes))
+            .transpose()
+    }
+
+    pub fn set_chain_sync_position(&self, cursor: Cursor) -> Result<()> {
+        let key = [MetadataKey::ChainSyncPosition as u8];
+        self.0
+            .insert(key, bytemuck::bytes_of(&CursorValue::from(cursor)))?;
+        Ok(())
+    }
+
+    pub fn get_chain_sync_position(&self) -> Result<Option<Cursor>> {
+        let key = [MetadataKey::ChainSyncPosition as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| CursorValue::decode(&bytes))
+            .transpose()
+    }
+
+    pub fn set_node_pruning_point_wtx(&self, wtx: &mut WriteTransaction, cursor: Cursor) {
+        let key = [MetadataKey::NodePruningPoint as u8];
+        wtx.insert(&self.0, key, bytemuck::bytes_of(&CursorValue::from(cursor)));
+    }
+
+    pub fn get_node_pruning_point_rtx(&self, rtx: &ReadTransaction) -> Result<Option<Cursor>> {
+        let key = [MetadataKey::NodePruningPoint as u8];
+        rtx.get(&self.0, key)?
+            .map(|bytes| CursorValue::decode(&bytes))
+            .transpose()
+    }
+
+    /// Written together with the finalized transactions
+    pub fn set_finalized_chain_index_wtx(&self, wtx: &mut WriteTransaction, index: Option<u64>) {
+        let key = [MetadataKey::FinalizedChainIndex as u8];
+        match index {
+            Some(index) => wtx.insert(&self.0, key, index.to_be_bytes()),
+            None => wtx.remove(&self.0, key),
+        }
+    }
+
+    pub fn get_finalized_chain_index_wtx(&self, wtx: &mut WriteTransaction) -> Result<Option<u64>> {
+        let key = [MetadataKey::FinalizedChainIndex as u8];
+        wtx.get(&self.0, key)?
+            .map(|bytes| match bytes.as_ref().try_into() {
+                Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+                Err(_) => bail!("Invalid finalized chain index size"),
+            })
+            .transpose()
+    }
+
+    /// Allocates the id of a new webhook subscription, ids are never reused
+    pub fn next_webhook_subscription_id_wtx(&self, wtx: &mut WriteTransaction) -> Result<u64> {
+        let key = [MetadataKey::LastWebhookSubscriptionId as u8];
+        let last = wtx
+            .get(&self.0, key)?
+            .map(|bytes| match bytes.as_ref().try_into() {
+                Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+                Err(_) => bail!("Invalid webhook subscription id size"),
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let next = last + 1;
+        wtx.insert(&self.0, key, next.to_be_bytes());
+        Ok(next)
+    }
+
+    /// Written together with the supply delta of each accepting block
+    pub fn set_accepted_rewards_wtx(&self, wtx: &mut WriteTransaction, rewards: u64) {
+        let key = [MetadataKey::AcceptedRewards as u8];
+        wtx.insert(&self.0, key, rewards.to_be_bytes());
+    }
+
+    pub fn get_accepted_rewards_wtx(&self, wtx: &mut WriteTransaction) -> Result<u64> {
+        let key = [MetadataKey::AcceptedRewards as u8];
+        decode_accepted_rewards(wtx.get(&self.0, key)?.as_deref())
+    }
+
+    pub fn get_accepted_rewards_rtx(&self, rtx: &ReadTransaction) -> Result<u64> {
+        let key = [MetadataKey::AcceptedRewards as u8];
+        decode_accepted_rewards(rtx.get(&self.0, key)?.as_deref())
+    }
+
+    pub fn set_supply_baseline(&self, baseline: u64) -> Result<()> {
+        let key = [MetadataKey::SupplyBaseline as u8];
+        self.0.insert(key, baseline.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// None until the first supply cross-check
+    pub fn get_supply_baseline_rtx(&self, rtx: &ReadTransaction) -> Result<Option<u64>> {
+        let key = [MetadataKey::SupplyBaseline as u8];
+        rtx.get(&self.0, key)?
+            .map(|bytes| match bytes.as_ref().try_into() {
+                Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+                Err(_) => bail!("Invalid supply baseline size"),
+            })
+            .transpose()
+    }
+
+    pub fn set_balance_deltas_pruned_daa_wtx(&self, wtx: &mut WriteTransaction, daa_score: u64) {
+        let key = [MetadataKey::BalanceDeltasPrunedDaa as u8];
+        wtx.insert(&self.0, key, daa_score.to_be_bytes());
+    }
+
+    pub fn get_balance_deltas_pruned_daa_rtx(&self, rtx: &ReadTransaction) -> Result<Option<u64>> {
+        let key = [MetadataKey::BalanceDeltasPrunedDaa as u8];
+        rtx.get(&self.0, key)?
+            .map(|bytes| match bytes.as_ref().try_into() {
+                Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+                Err(_) => bail!("Invalid balance deltas pruned DAA score size"),
+            })
+            .transpose()
+    }
+
+    /// Raises the pruned DAA score to `daa_score`, committed before the blocks are removed so
+    /// that a snapshot missing a block also sees the mark covering it
+    pub fn mark_blocks_pruned_wtx(
         &self,
         wtx: &mut WriteTransaction,
-        cursor: Cursor,
-    ) -> Result<()> {
-        let key = [MetadataKey::LatestAcceptingBlockCursor as u8];
-        let value = CursorValue {
-            blue_work: cursor.blue_work.to_be_bytes(),
-            block_hash: *cursor.hash.as_ref(),
-            daa_score: cursor.daa_score.to_le_bytes(),
+        daa_score: u64,
+    ) -> Result<BlocksPruned> {
+        let key = [MetadataKey::BlocksPruned as u8];
+        let current = wtx
+            .get(&self.0, key)?
+            .map(|bytes| BlocksPruned::decode(&bytes))
+            .transpose()?
+            .unwrap_or_default();
+        if daa_score <= current.daa_score {
+            return Ok(current);
+        }
+        let marked = BlocksPruned {
+            sequence: current.sequence + 1,
+            daa_score,
         };
-        wtx.insert(&self.0, key, bytemuck::bytes_of(&value));
+        wtx.insert(&self.0, key, marked.encode());
+        Ok(marked)
+    }
+
+    /// Default until blocks are first pruned
+    pub fn get_blocks_pruned_rtx(&self, rtx: &ReadTransaction) -> Result<BlocksPruned> {
+        let key = [MetadataKey::BlocksPruned as u8];
+        rtx.get(&self.0, key)?
+            .map(|bytes| BlocksPruned::decode(&bytes))
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Written on its own after every validated batch
+    pub fn set_header_validation(&self, state: &HeaderValidationState) -> Result<()> {
+        let key = [MetadataKey::HeaderValidation as u8];
+        self.0.insert(key, state.encode())?;
+        Ok(())
+    }
+
+    pub fn get_header_validation(&self) -> Result<Option<HeaderValidationState>> {
+        let key = [MetadataKey::HeaderValidation as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| HeaderValidationState::decode(&bytes))
+            .transpose()
+    }
+
+    /// Written once, by the first connect of the database
+    pub fn set_node_requirements(&self, requirements: &NodeRequirements) -> Result<()> {
+        let key = [MetadataKey::NodeRequirements as u8];
+        self.0.insert(key, requirements.encode())?;
+        Ok(())
+    }
+
+    pub fn get_node_requirements(&self) -> Result<Option<NodeRequirements>> {
+        let key = [MetadataKey::NodeRequirements as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| NodeRequirements::decode(&bytes))
+            .transpose()
+    }
+
+    pub fn get_network_id(&self) -> Result<Option<String>> {
+        let key = [MetadataKey::NetworkId as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| Ok(String::from_utf8(bytes.to_vec())?))
+            .transpose()
+    }
+
+    /// Records `network_id` for a new database, fails if the database belongs to another
+    /// network. Databases created before the network was recorded take it from the node
+    /// requirements, or are mainnet ones if they hold any block
+    pub fn check_network_id(&self, network_id: &str) -> Result<()> {
+        let stored = match self.get_network_id()? {
+            Some(stored) => Some(stored),
+            None => match self.get_node_requirements()? {
+                Some(requirements) => Some(requirements.network_id),
+                None => self
+                    .get_latest_block_cursor()?
+                    .map(|_| "mainnet".to_string()),
+            },
+        };
+        if let Some(stored) = &stored
+            && stored != network_id
+        {
+            bail!("Database belongs to network {stored}, configured network is {network_id}");
+        }
+        if self.get_network_id()?.is_none() {
+            let key = [MetadataKey::NetworkId as u8];
+            self.0.insert(key, network_id.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Records the bucket width of the aggregates when they are first built, fails if they
+    /// were built with another width
+    pub fn check_aggregate_bucket_width(&self, bucket_width: u64) -> Result<()> {
+        match self.get_aggregate_bucket_width()? {
+            Some(stored) if stored != bucket_width => bail!(
+                "Aggregates were built with buckets of {stored} DAA, configured width is {bucket_width}"
+            ),
+            Some(_) => {}
+            None => {
+                let key = [MetadataKey::AggregateBucketWidth as u8];
+                self.0.insert(key, bucket_width.to_be_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the header storage mode of a new database, fails if the database stores its
+    /// headers in another mode. Databases from before the mode was recorded take `mode`
+    pub fn check_header_storage_mode(&self, mode: HeaderStorageMode) -> Result<()> {
+        match self.get_header_storage_mode()? {
+            Some(stored) if stored != mode => {
+                bail!("Database stores headers in {stored:?} mode, configured mode is {mode:?}")
+            }
+            Some(_) => {}
+            None => {
+                let key = [MetadataKey::HeaderStorageMode as u8];
+                self.0.insert(key, [mode as u8])?;
+            }
+        }
         Ok(())
     }
 
+    pub fn get_header_storage_mode(&self) -> Result<Option<HeaderStorageMode>> {
+        let key = [MetadataKey::HeaderStorageMode as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| match bytes.as_ref() {
+                [mode] => HeaderStorageMode::try_from(*mode),
+                _ => bail!("Invalid header storage mode size"),
+            })
+            .transpose()
+    }
+
+    /// Records `filter` for the blocks from `next_daa` on, see [`IngestFilterState::apply`]
+    pub fn check_ingest_filter(
+        &self,
+        filter: IngestFilter,
+        policy: FilterChangePolicy,
+        next_daa: u64,
+    ) -> Result<IngestFilterState> {
+        let recorded = self.get_ingest_filter()?;
+        let state = IngestFilterState::apply(recorded.clone(), filter, policy, next_daa)?;
+        if recorded.as_ref() != Some(&state) {
+            self.set_ingest_filter(&state)?;
+        }
+        Ok(state)
+    }
+
+    pub fn set_ingest_filter(&self, state: &IngestFilterState) -> Result<()> {
+        let key = [MetadataKey::IngestFilter as u8];
+        self.0.insert(key, state.encode())?;
+        Ok(())
+    }
+
+    /// None for databases indexed before the filter was recorded, which were unfiltered
+    pub fn get_ingest_filter(&self) -> Result<Option<IngestFilterState>> {
+        let key = [MetadataKey::IngestFilter as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| IngestFilterState::decode(&bytes))
+            .transpose()
+    }
+
+    /// None until the aggregates are enabled
+    pub fn get_aggregate_bucket_width(&self) -> Result<Option<u64>> {
+        let key = [MetadataKey::AggregateBucketWidth as u8];
+        self.0
+            .get(key)?
+            .map(|bytes| match bytes.as_ref().try_into() {
+                Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+                Err(_) => bail!("Invalid aggregate bucket width size"),
+            })
+            .transpose()
+    }
+
     /// Get latest accepting block cursor
     pub fn get_latest_accepting_block_cursor_rtx(
         &self,
@@ -223,6 +782,17 @@ impl MetadataPartition {
     }
 }
 
+/// Zero until the first reward is accepted
+fn decode_accepted_rewards(bytes: Option<&[u8]>) -> Result<u64> {
+    bytes
+        .map(|bytes| match bytes.try_into() {
+            Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+            Err(_) => bail!("Invalid accepted rewards size"),
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -234,6 +804,183 @@ mod tests {
 
         let key = MetadataKey::LatestAcceptingBlockCursor;
         assert_eq!(key as u8, 1);
+
+        let key = MetadataKey::Sink;
+        assert_eq!(key as u8, 2);
+
+        let key = MetadataKey::HeaderValidation;
+        assert_eq!(key as u8, 3);
+
+        let key = MetadataKey::NodePruningPoint;
+        assert_eq!(key as u8, 4);
+
+        let key = MetadataKey::FinalizedChainIndex;
+        assert_eq!(key as u8, 5);
+
+        let key = MetadataKey::ChainSyncPosition;
+        assert_eq!(key as u8, 6);
+
+        let key = MetadataKey::NodeRequirements;
+        assert_eq!(key as u8, 7);
+
+        let key = MetadataKey::BalanceDeltasPrunedDaa;
+        assert_eq!(key as u8, 8);
+
+        let key = MetadataKey::NetworkId;
+        assert_eq!(key as u8, 9);
+
+        let key = MetadataKey::BlockTipHistory;
+        assert_eq!(key as u8, 14);
+
+        let key = MetadataKey::AggregateBucketWidth;
+        assert_eq!(key as u8, 10);
+
+        let key = MetadataKey::LastWebhookSubscriptionId;
+        assert_eq!(key as u8, 11);
+
+        let key = MetadataKey::IngestFilter;
+        assert_eq!(key as u8, 15);
+
+        let key = MetadataKey::HeaderStorageMode;
+        assert_eq!(key as u8, 16);
+
+        let key = MetadataKey::BlocksPruned;
+        assert_eq!(key as u8, 17);
+    }
+
+    #[test]
+    fn test_blocks_pruned_only_moves_forward() {
+        let keyspace = crate::database::test_keyspace("blocks-pruned");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        let pruned = || metadata.get_blocks_pruned_rtx(&keyspace.read_tx()).unwrap();
+        let mark = |daa_score| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            metadata
+                .mark_blocks_pruned_wtx(&mut wtx, daa_score)
+                .unwrap();
+            wtx.commit().unwrap().unwrap();
+        };
+        assert_eq!(pruned(), BlocksPruned::default());
+        mark(100);
+        mark(50);
+        mark(100);
+        assert_eq!(
+            pruned(),
+            BlocksPruned {
+                sequence: 1,
+                daa_score: 100
+            }
+        );
+        mark(120);
+        assert_eq!(pruned().sequence, 2);
+    }
+
+    #[test]
+    fn test_aggregate_bucket_width_is_kept() {
+        let keyspace = crate::database::test_keyspace("bucket-width");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        assert_eq!(metadata.get_aggregate_bucket_width().unwrap(), None);
+        metadata.check_aggregate_bucket_width(100).unwrap();
+        metadata.check_aggregate_bucket_width(100).unwrap();
+        assert!(metadata.check_aggregate_bucket_width(200).is_err());
+        assert_eq!(metadata.get_aggregate_bucket_width().unwrap(), Some(100));
+    }
+
+    #[test]
+    fn test_header_storage_mode_is_kept() {
+        let keyspace = crate::database::test_keyspace("header-storage");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        assert_eq!(metadata.get_header_storage_mode().unwrap(), None);
+        metadata
+            .check_header_storage_mode(HeaderStorageMode::Full)
+            .unwrap();
+        metadata
+            .check_header_storage_mode(HeaderStorageMode::Full)
+            .unwrap();
+        assert!(
+            metadata
+                .check_header_storage_mode(HeaderStorageMode::Compact)
+                .is_err()
+        );
+        assert_eq!(
+            metadata.get_header_storage_mode().unwrap(),
+            Some(HeaderStorageMode::Full)
+        );
+    }
+
+    #[test]
+    fn test_network_mismatch_is_rejected() {
+        let keyspace = crate::database::test_keyspace("network");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        metadata.check_network_id("testnet-10").unwrap();
+        assert_eq!(
+            metadata.get_network_id().unwrap().as_deref(),
+            Some("testnet-10")
+        );
+        metadata.check_network_id("testnet-10").unwrap();
+        let err = metadata
+            .check_network_id("mainnet")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("testnet-10"), "{err}");
+        assert_eq!(
+            metadata.get_network_id().unwrap().as_deref(),
+            Some("testnet-10")
+        );
+    }
+
+    #[test]
+    fn test_network_of_databases_created_before_recording() {
+        let keyspace = crate::database::test_keyspace("network-legacy");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        metadata
+            .set_node_requirements(&NodeRequirements {
+                network_id: "mainnet".to_string(),
+                min_version: NodeVersion::new(1, 0, 0),
+            })
+            .unwrap();
+        assert!(metadata.check_network_id("testnet-10").is_err());
+        assert_eq!(metadata.get_network_id().unwrap(), None);
+        metadata.check_network_id("mainnet").unwrap();
+        assert_eq!(
+            metadata.get_network_id().unwrap().as_deref(),
+            Some("mainnet")
+        );
+    }
+
+    #[test]
+    fn test_node_requirements_roundtrip() {
+        let requirements = NodeRequirements {
+            network_id: "testnet-10".to_string(),
+            min_version: NodeVersion::new(1, 2, 300),
+        };
+        assert_eq!(
+            NodeRequirements::decode(&requirements.encode()).unwrap(),
+            requirements
+        );
+        assert!(NodeRequirements::decode(&[0; 5]).is_err());
+    }
+
+    #[test]
+    fn test_header_validation_state_roundtrip() {
+        let state = HeaderValidationState::new("1.0.1");
+        assert_eq!(
+            HeaderValidationState::decode(&state.encode()).unwrap(),
+            state
+        );
+
+        let state = HeaderValidationState {
+            watermark: Some(RpcHash::from_u64_word(7)),
+            checked: 1000,
+            mismatches: 2,
+            completed: true,
+            ..state
+        };
+        assert_eq!(
+            HeaderValidationState::decode(&state.encode()).unwrap(),
+            state
+        );
+        assert!(HeaderValidationState::decode(&[1; 10]).is_err());
     }
 
     #[test]
@@ -281,4 +1028,85 @@ mod tests {
             MetadataKey::LatestAcceptingBlockCursor as u8
         );
     }
+
+    fn cursor(daa_score: u64) -> Cursor {
+        Cursor {
+            daa_score,
+            blue_work: kaspa_math::Uint192::from_u64(daa_score),
+            hash: kaspa_rpc_core::RpcHash::from_u64_word(daa_score),
+        }
+    }
+
+    /// Simulates a crash between the data and the tip write by dropping the second
+    /// transaction uncommitted, then inspects what a restarted indexer would see
+    #[test]
+    fn test_crash_between_data_and_tip() {
+        use crate::database::processing::AcceptingBlockToTxIDPartition;
+
+        let keyspace = crate::database::test_keyspace("metadata-crash");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        let data = AcceptingBlockToTxIDPartition::new(&keyspace).unwrap();
+        let tip_after_restart = || {
+            metadata
+                .get_latest_accepting_block_cursor_rtx(&keyspace.read_tx())
+                .unwrap()
+        };
+        let has_data = |block: &Cursor| {
+            data.get_rtx(&keyspace.read_tx(), &block.hash)
+                .unwrap()
+                .is_some()
+        };
+
+        // data first, crash before the tip: the tip lags behind, the block is processed
+        // again after restart and overwrites identical entries. Accepted
+        let block = cursor(1);
+        let mut wtx = keyspace.write_tx().unwrap();
+        data.insert_wtx(&mut wtx, &block.hash, &[[1; 32]]);
+        wtx.commit().unwrap().unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        metadata.set_vcp_tip(&mut wtx, block).unwrap();
+        drop(wtx);
+        assert!(has_data(&block));
+        assert_eq!(tip_after_restart(), None);
+
+        // tip first, crash before the data: the tip points past data which will never be
+        // written, resync starts after the hole. Not accepted, which is why processors
+        // never commit the tip ahead of the data
+        let block = cursor(2);
+        let mut wtx = keyspace.write_tx().unwrap();
+        metadata.set_vcp_tip(&mut wtx, block).unwrap();
+        wtx.commit().unwrap().unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        data.insert_wtx(&mut wtx, &block.hash, &[[2; 32]]);
+        drop(wtx);
+        assert!(!has_data(&block));
+        assert_eq!(tip_after_restart(), Some(block));
+
+        // both in one transaction: a crash before commit loses both, never just one
+        let block = cursor(3);
+        let mut wtx = keyspace.write_tx().unwrap();
+        data.insert_wtx(&mut wtx, &block.hash, &[[3; 32]]);
+        metadata.set_vcp_tip(&mut wtx, block).unwrap();
+        drop(wtx);
+        assert!(!has_data(&block));
+        assert_eq!(tip_after_restart(), Some(cursor(2)));
+
+        let mut wtx = keyspace.write_tx().unwrap();
+        data.insert_wtx(&mut wtx, &block.hash, &[[3; 32]]);
+        metadata.set_vcp_tip(&mut wtx, block).unwrap();
+        wtx.commit().unwrap().unwrap();
+        assert!(has_data(&block));
+        assert_eq!(tip_after_restart(), Some(block));
+    }
+
+    #[test]
+    fn test_cursor_value_decode() {
+        let cursor = cursor(42);
+        let value = CursorValue::from(cursor);
+        assert_eq!(
+            CursorValue::decode(bytemuck::bytes_of(&value)).unwrap(),
+            cursor
+        );
+        assert!(CursorValue::decode(&[0; 10]).is_err());
+    }
 }
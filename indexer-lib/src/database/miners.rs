@@ -0,0 +1,299 @@
+use crate::database::messages::AddressPayload;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, UserKey, UserValue, WriteTransaction};
+use kaspa_addresses::Address;
+use kaspa_rpc_core::RpcHash;
+use std::ops::Range;
+
+const PARSED: u8 = 0;
+const PARSE_FAILED: u8 = 1;
+
+/// Miner attribution of a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockMiner {
+    Parsed {
+        address: Address,
+        reward: u64,
+    },
+    /// The coinbase payload could not be decoded or pays to no address
+    ParseFailed,
+}
+
+/// Partition attributing blocks to their miner.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [status (1 byte)] + [daa_score (8 bytes BE)] + parsed only:
+/// [reward (8 bytes BE)] + [miner address (utf8)]
+#[derive(Clone)]
+pub struct BlockMinerPartition(fjall::TxPartition);
+
+/// Blocks of every miner, ordered by daa score.
+///
+/// **Key:** [miner (34 bytes AddressPayload)] + [daa_score (8 bytes BE)] + [block_hash (32 bytes)]
+/// **Value:** [reward (8 bytes BE)]
+#[derive(Clone)]
+pub struct MinerBlocksPartition(fjall::TxPartition);
+
+/// Blocks mined by an address within a daa range
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MinedBlocks {
+    pub blocks: u64,
+    pub reward: u64,
+}
+
+impl DescribePartition for BlockMinerPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_miner",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[
+            field("status", FieldType::U8),
+            field("daa_score", FieldType::U64Be),
+            field("reward", FieldType::U64Be),
+            field("miner_address", FieldType::Tail("utf8")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl DescribePartition for MinerBlocksPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "miner_blocks",
+        key: &[
+            field("miner", FieldType::AddressPayload),
+            field("daa_score", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+        ],
+        value: &[field("reward", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockMinerPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        daa_score: u64,
+        miner: &BlockMiner,
+    ) {
+        let mut value = Vec::with_capacity(1 + 8 + 8 + 80);
+        match miner {
+            BlockMiner::Parsed { address, reward } => {
+                value.push(PARSED);
+                value.extend_from_slice(&daa_score.to_be_bytes());
+                value.extend_from_slice(&reward.to_be_bytes());
+                value.extend_from_slice(address.to_string().as_bytes());
+            }
+            BlockMiner::ParseFailed => {
+                value.push(PARSE_FAILED);
+                value.extend_from_slice(&daa_score.to_be_bytes());
+            }
+        }
+        wtx.insert(&self.0, block_hash.as_bytes(), value);
+    }
+
+    pub fn get_block_miner(&self, block_hash: RpcHash) -> Result<Option<BlockMiner>> {
+        self.0
+            .get(block_hash.as_bytes())?
+            .map(|value| decode_miner(&value))
+            .transpose()
+    }
+
+    pub fn get_block_miner_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockMiner>> {
+        rtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| decode_miner(&value))
+            .transpose()
+    }
+
+    /// Removes the attribution, returns it
+    pub fn take_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockMiner>> {
+        let Some(value) = wtx.get(&self.0, block_hash.as_bytes())? else {
+            return Ok(None);
+        };
+        wtx.remove(&self.0, block_hash.as_bytes());
+        decode_miner(&value).map(Some)
+    }
+}
+
+fn decode_miner(value: &[u8]) -> Result<BlockMiner> {
+    match value {
+        [PARSE_FAILED, _daa_score @ ..] if value.len() == 9 => Ok(BlockMiner::ParseFailed),
+        [PARSED, rest @ ..] if rest.len() > 16 => Ok(BlockMiner::Parsed {
+            reward: u64::from_be_bytes(rest[8..16].try_into()?),
+            address: Address::try_from(std::str::from_utf8(&rest[16..])?)?,
+        }),
+        _ => bail!("Invalid block miner value"),
+    }
+}
+
+impl MinerBlocksPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        miner: &Address,
+        daa_score: u64,
+        block_hash: RpcHash,
+        reward: u64,
+    ) -> Result<()> {
+        wtx.insert(
+            &self.0,
+            Self::key(miner, daa_score, block_hash)?,
+            reward.to_be_bytes(),
+        );
+        Ok(())
+    }
+
+    pub fn remove_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        miner: &Address,
+        daa_score: u64,
+        block_hash: RpcHash,
+    ) -> Result<()> {
+        wtx.remove(&self.0, Self::key(miner, daa_score, block_hash)?);
+        Ok(())
+    }
+
+    /// Amount of blocks and summed reward of `miner` within `daa_range`
+    pub fn blocks_mined_by(&self, miner: &Address, daa_range: Range<u64>) -> Result<MinedBlocks> {
+        sum_mined(self.0.inner().range(Self::range(miner, daa_range)?))
+    }
+
+    pub fn blocks_mined_by_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        miner: &Address,
+        daa_range: Range<u64>,
+    ) -> Result<MinedBlocks> {
+        sum_mined(rtx.range(&self.0, Self::range(miner, daa_range)?))
+    }
+
+    fn key(miner: &Address, daa_score: u64, block_hash: RpcHash) -> Result<Vec<u8>> {
+        let mut key = Vec::with_capacity(34 + 8 + 32);
+        key.extend_from_slice(bytemuck::bytes_of(&AddressPayload::try_from(miner)?));
+        key.extend_from_slice(&daa_score.to_be_bytes());
+        key.extend_from_slice(&block_hash.as_bytes());
+        Ok(key)
+    }
+
+    fn range(miner: &Address, daa_range: Range<u64>) -> Result<Range<Vec<u8>>> {
+        let payload = AddressPayload::try_from(miner)?;
+        let bound = |daa_score: u64| {
+            let mut key = bytemuck::bytes_of(&payload).to_vec();
+            key.extend_from_slice(&daa_score.to_be_bytes());
+            key
+        };
+        Ok(bound(daa_range.start)..bound(daa_range.end))
+    }
+}
+
+fn sum_mined(
+    entries: impl Iterator<Item = fjall::Result<(UserKey, UserValue)>>,
+) -> Result<MinedBlocks> {
+    let mut mined = MinedBlocks::default();
+    for r in entries {
+        let (_, value) = r?;
+        mined.blocks += 1;
+        mined.reward += u64::from_be_bytes(value.as_ref().try_into()?);
+    }
+    Ok(mined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_addresses::{Prefix, Version};
+
+    #[test]
+    fn test_blocks_mined_by() {
+        let keyspace = crate::database::test_keyspace("miners");
+        let block_miners = BlockMinerPartition::new(&keyspace).unwrap();
+        let miner_blocks = MinerBlocksPartition::new(&keyspace).unwrap();
+        let alice = Address::new(Prefix::Mainnet, Version::PubKey, &[1; 32]);
+        let bob = Address::new(Prefix::Mainnet, Version::PubKey, &[2; 32]);
+
+        let mut wtx = keyspace.write_tx().unwrap();
+        for (i, miner) in [&alice, &bob, &alice, &alice].into_iter().enumerate() {
+            let hash = RpcHash::from_u64_word(i as u64);
+            let daa_score = 100 + i as u64;
+            let parsed = BlockMiner::Parsed {
+                address: miner.clone(),
+                reward: 10,
+            };
+            block_miners.insert_wtx(&mut wtx, hash, daa_score, &parsed);
+            miner_blocks
+                .insert_wtx(&mut wtx, miner, daa_score, hash, 10)
+                .unwrap();
+        }
+        let failed = RpcHash::from_u64_word(99);
+        block_miners.insert_wtx(&mut wtx, failed, 104, &BlockMiner::ParseFailed);
+        wtx.commit().unwrap().unwrap();
+
+        let rtx = keyspace.read_tx();
+        assert_eq!(
+            block_miners
+                .get_block_miner_rtx(&rtx, RpcHash::from_u64_word(1))
+                .unwrap(),
+            Some(BlockMiner::Parsed {
+                address: bob.clone(),
+                reward: 10
+            })
+        );
+        assert_eq!(
+            block_miners.get_block_miner_rtx(&rtx, failed).unwrap(),
+            Some(BlockMiner::ParseFailed)
+        );
+        assert_eq!(
+            block_miners
+                .get_block_miner_rtx(&rtx, RpcHash::from_u64_word(7))
+                .unwrap(),
+            None
+        );
+
+        let mined = |miner, range| {
+            miner_blocks
+                .blocks_mined_by_rtx(&rtx, miner, range)
+                .unwrap()
+        };
+        assert_eq!(
+            mined(&alice, 0..u64::MAX),
+            MinedBlocks {
+                blocks: 3,
+                reward: 30
+            }
+        );
+        // end exclusive
+        assert_eq!(mined(&alice, 100..103).blocks, 2);
+        assert_eq!(mined(&bob, 102..200).blocks, 0);
+        assert_eq!(
+            miner_blocks.blocks_mined_by(&bob, 0..u64::MAX).unwrap(),
+            MinedBlocks {
+                blocks: 1,
+                reward: 10
+            }
+        );
+    }
+}
@@ -1,8 +1,10 @@
+use crate::database::processing::{TxIdFilter, TxIdFilterStats};
 use crate::database::resolution_keys::{
     ContextualMessageKeyForResolution, HandshakeKeyForResolution,
     LikeContextualMessageKeyForResolution, LikeHandshakeKeyForResolution,
     LikePaymentKeyForResolution, PaymentKeyForResolution,
 };
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use crate::database::{LikeTxIds, PartitionId};
 use anyhow::Result;
 use bytemuck::{AnyBitPattern, NoUninit};
@@ -14,6 +16,7 @@ use kaspa_rpc_core::RpcHash;
 use std::fmt::{Debug, Formatter};
 use std::marker::PhantomData;
 use std::ops::Deref;
+use std::sync::Arc;
 
 /// Enum for accepting block resolution data types
 #[derive(Debug, Clone)]
@@ -27,10 +30,20 @@ pub enum AcceptingBlockResolutionData {
 #[derive(Clone)]
 pub struct AcceptingBlockToTxIDPartition(fjall::TxPartition);
 
+impl DescribePartition for AcceptingBlockToTxIDPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "accepting_block_to_tx_id",
+        key: &[field("accepting_block_hash", FieldType::Hash)],
+        value: &[field("tx_ids", FieldType::Tail("hash[]"))],
+        kv_separation: true,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl AcceptingBlockToTxIDPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "accepting_block_to_tx_id",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default().with_kv_separation(KvSeparationOptions::default()),
         )?))
     }
@@ -123,14 +136,60 @@ impl<T: AsRef<[u8]>> Deref for LikeAcceptanceTxKey<T> {
 }
 
 #[derive(Clone)]
-pub struct TxIDToAcceptancePartition(fjall::TxPartition);
+pub struct TxIDToAcceptancePartition(fjall::TxPartition, Option<Arc<TxIdFilter>>);
+
+impl DescribePartition for TxIDToAcceptancePartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "tx_id_to_acceptance",
+        key: &[
+            field("tx_id", FieldType::Hash),
+            field("accepted_at_daa", FieldType::U64Be),
+            field("accepted_by_block_hash", FieldType::Hash),
+            field("partition_id", FieldType::U8),
+        ],
+        value: &[field(
+            "resolution_key",
+            FieldType::Tail("resolution_key(partition_id)"),
+        )],
+        ..PartitionDescription::DEFAULT
+    };
+}
 
 impl TxIDToAcceptancePartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> anyhow::Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "tx_id_to_acceptance",
-            PartitionCreateOptions::default(),
-        )?))
+        Ok(Self(
+            schema::open_partition::<Self>(keyspace, PartitionCreateOptions::default())?,
+            None,
+        ))
+    }
+
+    /// Notes inserted transaction ids in `filter`, clones made afterwards share it
+    pub fn with_filter(mut self, filter: Arc<TxIdFilter>) -> Self {
+        self.1 = Some(filter);
+        self
+    }
+
+    /// False when the transaction is surely not stored, true without a filter
+    pub fn may_contain(&self, tx_id: &[u8; 32]) -> bool {
+        self.1.as_ref().is_none_or(|filter| filter.check(tx_id))
+    }
+
+    /// Called when a lookup `may_contain` passed found nothing
+    pub fn record_false_positive(&self) {
+        if let Some(filter) = &self.1 {
+            filter.record_false_positive();
+        }
+    }
+
+    pub fn filter_stats(&self) -> Option<TxIdFilterStats> {
+        self.1.as_ref().map(|filter| filter.stats())
+    }
+
+    /// Ids are noted before the commit, an aborted write only leaves a false positive
+    fn note_inserted(&self, tx_id: &[u8; 32]) {
+        if let Some(filter) = &self.1 {
+            filter.insert(tx_id);
+        }
     }
 
     /// Insert a handshake ForResolution key
@@ -148,6 +207,7 @@ impl TxIDToAcceptancePartition {
             accepted_by_block_hash: accepted_by_block_hash.unwrap_or_default(),
             partition_id: PartitionId::HandshakeBySender as u8,
         };
+        self.note_inserted(&tx_id);
         wtx.insert(
             &self.0,
             bytemuck::bytes_of(&key),
@@ -170,6 +230,7 @@ impl TxIDToAcceptancePartition {
             accepted_by_block_hash: accepted_by_block_hash.unwrap_or_default(),
             partition_id: PartitionId::ContextualMessageBySender as u8,
         };
+        self.note_inserted(&tx_id);
         wtx.insert(
             &self.0,
             bytemuck::bytes_of(&key),
@@ -192,6 +253,7 @@ impl TxIDToAcceptancePartition {
             accepted_by_block_hash: accepted_by_block_hash.unwrap_or_default(),
             partition_id: PartitionId::PaymentBySender as u8,
         };
+        self.note_inserted(&tx_id);
         wtx.insert(
             &self.0,
             bytemuck::bytes_of(&key),
@@ -205,6 +267,7 @@ impl TxIDToAcceptancePartition {
         key: &AcceptanceTxKey,
         value: AcceptingBlockResolutionData,
     ) {
+        self.note_inserted(&key.tx_id);
         wtx.insert(
             &self.0,
             bytemuck::bytes_of(key),
@@ -249,53 +312,109 @@ impl TxIDToAcceptancePartition {
     > + '_ {
         rtx.prefix(&self.0, tx_id).map(|r| {
             let (key_bytes, value_bytes) = r?;
-            if key_bytes.len() == 73 {
-                // 32 + 8 + 32 + 1
-                let key = LikeAcceptanceTxKey::new(key_bytes);
+            Self::decode_entry(key_bytes, value_bytes)
+        })
+    }
 
-                let partition_id = match key.partition_id {
-                    x if x == PartitionId::HandshakeBySender as u8 => {
-                        PartitionId::HandshakeBySender
-                    }
-                    x if x == PartitionId::ContextualMessageBySender as u8 => {
-                        PartitionId::ContextualMessageBySender
+    /// Batched lookup of the latest entry of each transaction within a single read snapshot,
+    /// in the order of `tx_ids`. Only protocol transactions have entries, the others yield
+    /// `None` like unknown ones
+    pub fn get_latest_many_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tx_ids: &[[u8; 32]],
+    ) -> Result<Vec<Option<AcceptanceTxKey>>> {
+        tx_ids
+            .iter()
+            .map(|tx_id| {
+                if !self.may_contain(tx_id) {
+                    return Ok(None);
+                }
+                self.get_by_tx_id(rtx, tx_id)
+                    .next_back()
+                    .map(|entry| entry.map(|(key, _)| *key))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    /// Same as `get_by_tx_id`, sees the writes of `wtx`
+    pub fn get_by_tx_id_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: &[u8; 32],
+    ) -> Result<Vec<(LikeAcceptanceTxKey<UserKey>, AcceptingBlockResolutionData)>> {
+        wtx.prefix(&self.0, tx_id)
+            .map(|r| {
+                let (key_bytes, value_bytes) = r?;
+                Self::decode_entry(key_bytes, value_bytes)
+            })
+            .collect()
+    }
+
+    fn decode_entry(
+        key_bytes: UserKey,
+        value_bytes: UserValue,
+    ) -> Result<(LikeAcceptanceTxKey<UserKey>, AcceptingBlockResolutionData)> {
+        if key_bytes.len() == 73 {
+            // 32 + 8 + 32 + 1
+            let key = LikeAcceptanceTxKey::new(key_bytes);
+
+            let partition_id = match key.partition_id {
+                x if x == PartitionId::HandshakeBySender as u8 => PartitionId::HandshakeBySender,
+                x if x == PartitionId::ContextualMessageBySender as u8 => {
+                    PartitionId::ContextualMessageBySender
+                }
+                x if x == PartitionId::PaymentBySender as u8 => PartitionId::PaymentBySender,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Invalid partition ID: {}",
+                        key.partition_id
+                    ));
+                }
+            };
+
+            let resolution_data = if value_bytes.is_empty() {
+                AcceptingBlockResolutionData::None
+            } else {
+                match partition_id {
+                    PartitionId::HandshakeBySender => AcceptingBlockResolutionData::HandshakeKey(
+                        LikeHandshakeKeyForResolution::new(value_bytes),
+                    ),
+                    PartitionId::ContextualMessageBySender => {
+                        AcceptingBlockResolutionData::ContextualMessageKey(
+                            LikeContextualMessageKeyForResolution::new(value_bytes),
+                        )
                     }
-                    x if x == PartitionId::PaymentBySender as u8 => PartitionId::PaymentBySender,
+                    PartitionId::PaymentBySender => AcceptingBlockResolutionData::PaymentKey(
+                        LikePaymentKeyForResolution::new(value_bytes),
+                    ),
                     _ => {
                         return Err(anyhow::anyhow!(
-                            "Invalid partition ID: {}",
-                            key.partition_id
+                            "Invalid partition ID for accepting block resolution: {:?}",
+                            partition_id
                         ));
                     }
-                };
-
-                let resolution_data = if value_bytes.is_empty() {
-                    AcceptingBlockResolutionData::None
-                } else {
-                    match partition_id {
-                        PartitionId::HandshakeBySender => {
-                            AcceptingBlockResolutionData::HandshakeKey(
-                                LikeHandshakeKeyForResolution::new(value_bytes),
-                            )
-                        }
-                        PartitionId::ContextualMessageBySender => {
-                            AcceptingBlockResolutionData::ContextualMessageKey(
-                                LikeContextualMessageKeyForResolution::new(value_bytes),
-                            )
-                        }
-                        PartitionId::PaymentBySender => AcceptingBlockResolutionData::PaymentKey(
-                            LikePaymentKeyForResolution::new(value_bytes),
-                        ),
-                        _ => {
-                            return Err(anyhow::anyhow!(
-                                "Invalid partition ID for accepting block resolution: {:?}",
-                                partition_id
-                            ));
-                        }
-                    }
-                };
+                }
+            };
+
+            Ok((key, resolution_data))
+        } else {
+            Err(anyhow::anyhow!(
+                "Invalid key length in tx_id_to_acceptance partition"
+            ))
+        }
+    }
 
-                Ok((key, resolution_data))
+    /// Iterates over all keys of the partition
+    pub fn iter_keys_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+    ) -> impl Iterator<Item = Result<LikeAcceptanceTxKey<UserKey>>> + 'a {
+        rtx.keys(&self.0).map(|r| {
+            let key_bytes = r?;
+            if key_bytes.len() == size_of::<AcceptanceTxKey>() {
+                Ok(LikeAcceptanceTxKey::new(key_bytes))
             } else {
                 Err(anyhow::anyhow!(
                     "Invalid key length in tx_id_to_acceptance partition"
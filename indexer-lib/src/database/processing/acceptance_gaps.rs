@@ -0,0 +1,101 @@
+use crate::database::headers::{BlockGap, BlockGapKey, BlockGapsPartition};
+use crate::database::schema::{self, DescribePartition, PartitionDescription};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction};
+
+/// Spans of the selected chain whose acceptance data is lost, keyed by [`BlockGapKey`].
+///
+/// Written when the node pruned the chain start of the selected chain syncer while the indexer
+/// was offline. Syncing resumes from the pruning point and transactions accepted in between
+/// stay unaccepted, the spans are kept for inspection only.
+#[derive(Clone)]
+pub struct AcceptanceGapsPartition(fjall::TxPartition);
+
+impl DescribePartition for AcceptanceGapsPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "acceptance_gaps",
+        key: BlockGapsPartition::DESCRIPTION.key,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl AcceptanceGapsPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn add_gap(&self, gap: &BlockGap) -> Result<()> {
+        self.0
+            .insert(bytemuck::bytes_of(&BlockGapKey::from(gap)), [])?;
+        Ok(())
+    }
+
+    /// Ordered by the DAA score of the span start
+    pub fn get_all_gaps_rtx(
+        &self,
+        rtx: &ReadTransaction,
+    ) -> impl DoubleEndedIterator<Item = Result<BlockGap>> + '_ {
+        rtx.iter(&self.0).map(|item| {
+            let (key, _) = item?;
+            if key.len() != size_of::<BlockGapKey>() {
+                bail!("Invalid key length in acceptance_gaps partition");
+            }
+            Ok(BlockGap::from(*bytemuck::from_bytes::<BlockGapKey>(&key)))
+        })
+    }
+
+    /// Whether a recorded span includes the DAA score, bounds included
+    pub fn covers_daa_rtx(&self, rtx: &ReadTransaction, daa_score: u64) -> Result<bool> {
+        // keys start with the big endian start score, later starts sort after the bound
+        for item in rtx.range(&self.0, ..daa_score.saturating_add(1).to_be_bytes()) {
+            let (key, _) = item?;
+            if key.len() != size_of::<BlockGapKey>() {
+                bail!("Invalid key length in acceptance_gaps partition");
+            }
+            if BlockGap::from(*bytemuck::from_bytes::<BlockGapKey>(&key)).to_daa_score >= daa_score
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_math::Uint192;
+    use kaspa_rpc_core::RpcHash;
+
+    #[test]
+    fn test_gaps_ordered_by_start() {
+        let keyspace = crate::database::test_keyspace("acceptance-gaps");
+        let gaps = AcceptanceGapsPartition::new(&keyspace).unwrap();
+        let gap = |from: u64, to: u64| BlockGap {
+            from_daa_score: from,
+            from_blue_work: Uint192::from_u64(from),
+            from_block_hash: RpcHash::from_u64_word(from),
+            to_blue_work: Uint192::from_u64(to),
+            to_block_hash: RpcHash::from_u64_word(to),
+            to_daa_score: to,
+        };
+        gaps.add_gap(&gap(300, 400)).unwrap();
+        gaps.add_gap(&gap(10, 20)).unwrap();
+        // recorded again after a crash before the syncer moved on
+        gaps.add_gap(&gap(10, 20)).unwrap();
+
+        let stored = gaps
+            .get_all_gaps_rtx(&keyspace.read_tx())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(stored, vec![gap(10, 20), gap(300, 400)]);
+
+        let rtx = keyspace.read_tx();
+        let covered = |daa| gaps.covers_daa_rtx(&rtx, daa).unwrap();
+        assert!(covered(10) && covered(20) && covered(350) && covered(400));
+        assert!(!covered(9) && !covered(21) && !covered(401));
+    }
+}
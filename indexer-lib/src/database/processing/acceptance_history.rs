@@ -0,0 +1,237 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use bytemuck::{AnyBitPattern, NoUninit};
+use fjall::{PartitionCreateOptions, ReadTransaction, UserKey, WriteTransaction};
+use kaspa_rpc_core::RpcHash;
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Records kept per transaction, the oldest ones are dropped first
+pub const MAX_RECORDS_PER_TX: usize = 32;
+
+/// Acceptance changes of transactions affected by reorgs.
+///
+/// **Key structure:** [tx_id (32 bytes)] + [seq (4 bytes BE)] = 36 bytes total
+/// **Value:** [`AcceptanceHistoryRecord`] = 49 bytes
+///
+/// Transactions accepted once are not recorded here, their acceptance lives in
+/// `tx_id_to_acceptance` only. History starts when a transaction is reorged out:
+/// the acceptance being reverted is recorded first, followed by the removal and
+/// every later re-acceptance.
+#[derive(Clone)]
+pub struct AcceptanceHistoryPartition(fjall::TxPartition);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, AnyBitPattern, NoUninit, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AcceptanceHistoryKey {
+    pub tx_id: [u8; 32],
+    pub seq: [u8; 4], // BE
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcceptanceChange {
+    Accepted = 0,
+    ReorgedOut = 1,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, AnyBitPattern, NoUninit, PartialEq, Eq)]
+pub struct AcceptanceHistoryRecord {
+    pub change: u8, // AcceptanceChange as u8
+    pub block_hash: [u8; 32],
+    pub daa_score: [u8; 8],   // BE, zero while the accepting daa is unresolved
+    pub recorded_at: [u8; 8], // BE, unix millis
+}
+
+impl AcceptanceHistoryRecord {
+    pub fn new(change: AcceptanceChange, block_hash: [u8; 32], daa_score: u64) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            change: change as u8,
+            block_hash,
+            daa_score: daa_score.to_be_bytes(),
+            recorded_at: now.to_be_bytes(),
+        }
+    }
+
+    pub fn change(&self) -> Result<AcceptanceChange> {
+        match self.change {
+            x if x == AcceptanceChange::Accepted as u8 => Ok(AcceptanceChange::Accepted),
+            x if x == AcceptanceChange::ReorgedOut as u8 => Ok(AcceptanceChange::ReorgedOut),
+            x => bail!("Invalid acceptance change: {x}"),
+        }
+    }
+
+    pub fn recorded_at_millis(&self) -> u64 {
+        u64::from_be_bytes(self.recorded_at)
+    }
+}
+
+impl Display for AcceptanceHistoryRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let change = match self.change() {
+            Ok(AcceptanceChange::Accepted) => "accepted by",
+            Ok(AcceptanceChange::ReorgedOut) => "reorged out with",
+            Err(_) => "unknown change of",
+        };
+        write!(
+            f,
+            "{change} {} at daa {}, recorded at {}",
+            RpcHash::from_bytes(self.block_hash),
+            u64::from_be_bytes(self.daa_score),
+            self.recorded_at_millis()
+        )
+    }
+}
+
+impl DescribePartition for AcceptanceHistoryPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "acceptance_history",
+        key: &[
+            field("tx_id", FieldType::Hash),
+            field("seq", FieldType::Bytes(4)),
+        ],
+        value: &[
+            field("change", FieldType::U8),
+            field("block_hash", FieldType::Hash),
+            field("daa_score", FieldType::U64Be),
+            field("recorded_at", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl AcceptanceHistoryPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    /// Checks within the write transaction, so records appended earlier in it are seen
+    pub fn has_history_wtx(&self, wtx: &mut WriteTransaction, tx_id: &[u8; 32]) -> Result<bool> {
+        Ok(wtx.prefix(&self.0, tx_id).next().transpose()?.is_some())
+    }
+
+    /// Appends a record, dropping the oldest ones beyond [`MAX_RECORDS_PER_TX`]
+    pub fn append_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: [u8; 32],
+        record: &AcceptanceHistoryRecord,
+    ) -> Result<()> {
+        let keys = wtx
+            .prefix(&self.0, tx_id)
+            .map(|r| r.map(|(key, _)| key))
+            .collect::<Result<Vec<UserKey>, _>>()?;
+        let seq = match keys.last() {
+            Some(key) => Self::seq(key)?.checked_add(1).unwrap_or(u32::MAX),
+            None => 0,
+        };
+        let excess = (keys.len() + 1).saturating_sub(MAX_RECORDS_PER_TX);
+        for key in keys.into_iter().take(excess) {
+            wtx.remove(&self.0, key);
+        }
+        let key = AcceptanceHistoryKey {
+            tx_id,
+            seq: seq.to_be_bytes(),
+        };
+        wtx.insert(
+            &self.0,
+            bytemuck::bytes_of(&key),
+            bytemuck::bytes_of(record),
+        );
+        Ok(())
+    }
+
+    /// Records of the transaction, oldest first
+    pub fn get_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tx_id: &[u8; 32],
+    ) -> Result<Vec<AcceptanceHistoryRecord>> {
+        rtx.prefix(&self.0, tx_id)
+            .map(|r| {
+                let (_, value) = r?;
+                Self::record(&value)
+            })
+            .collect()
+    }
+
+    /// Removes records older than `recorded_before` unix millis, returns the removed count.
+    /// Only reorged transactions are recorded, so a full scan stays small
+    pub fn prune_older_than_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        recorded_before: u64,
+    ) -> Result<usize> {
+        let mut keys = Vec::new();
+        for r in wtx.iter(&self.0) {
+            let (key, value) = r?;
+            if Self::record(&value)?.recorded_at_millis() < recorded_before {
+                keys.push(key);
+            }
+        }
+        let count = keys.len();
+        for key in keys {
+            wtx.remove(&self.0, key);
+        }
+        Ok(count)
+    }
+
+    fn seq(key: &[u8]) -> Result<u32> {
+        if key.len() != size_of::<AcceptanceHistoryKey>() {
+            bail!("Invalid acceptance history key length");
+        }
+        Ok(u32::from_be_bytes(
+            bytemuck::from_bytes::<AcceptanceHistoryKey>(key).seq,
+        ))
+    }
+
+    fn record(value: &[u8]) -> Result<AcceptanceHistoryRecord> {
+        if value.len() != size_of::<AcceptanceHistoryRecord>() {
+            bail!("Invalid acceptance history record length");
+        }
+        Ok(*bytemuck::from_bytes(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(size_of::<AcceptanceHistoryKey>(), 36);
+        assert_eq!(size_of::<AcceptanceHistoryRecord>(), 49);
+
+        let key = AcceptanceHistoryKey {
+            tx_id: [1; 32],
+            seq: 7u32.to_be_bytes(),
+        };
+        assert_eq!(
+            AcceptanceHistoryPartition::seq(bytemuck::bytes_of(&key)).unwrap(),
+            7
+        );
+        // sequence numbers order records of a transaction chronologically
+        let next = AcceptanceHistoryKey {
+            seq: 256u32.to_be_bytes(),
+            ..key
+        };
+        assert!(key < next);
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let record = AcceptanceHistoryRecord::new(AcceptanceChange::ReorgedOut, [2; 32], 100);
+        let decoded = AcceptanceHistoryPartition::record(bytemuck::bytes_of(&record)).unwrap();
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.change().unwrap(), AcceptanceChange::ReorgedOut);
+        assert!(AcceptanceHistoryPartition::record(&[0; 10]).is_err());
+    }
+}
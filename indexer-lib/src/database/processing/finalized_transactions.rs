@@ -0,0 +1,82 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::{RpcHash, RpcTransactionId};
+
+/// Partition flagging transactions whose accepting chain block is buried at finality depth.
+///
+/// **Key:** [tx_id (32 bytes)]
+/// **Value:** [accepting_block_hash (32 bytes)] + [chain_index (8 bytes BE)]
+///
+/// Written once and never touched by reorg handling, a reorg contradicting a record is
+/// reported instead.
+#[derive(Clone)]
+pub struct FinalizedTxPartition(fjall::TxPartition);
+
+/// Acceptance a transaction was finalized with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FinalizedAcceptance {
+    pub accepting_block_hash: RpcHash,
+    pub chain_index: u64,
+}
+
+impl DescribePartition for FinalizedTxPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "finalized_tx",
+        key: &[field("tx_id", FieldType::Hash)],
+        value: &[
+            field("accepting_block_hash", FieldType::Hash),
+            field("chain_index", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl FinalizedTxPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: &RpcTransactionId,
+        acceptance: FinalizedAcceptance,
+    ) {
+        let mut value = [0u8; 40];
+        value[..32].copy_from_slice(&acceptance.accepting_block_hash.as_bytes());
+        value[32..].copy_from_slice(&acceptance.chain_index.to_be_bytes());
+        wtx.insert(&self.0, tx_id.as_bytes(), value);
+    }
+
+    /// None until the transaction is final
+    pub fn get(&self, tx_id: &RpcTransactionId) -> Result<Option<FinalizedAcceptance>> {
+        self.0
+            .get(tx_id.as_bytes())?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+
+    pub fn get_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tx_id: &RpcTransactionId,
+    ) -> Result<Option<FinalizedAcceptance>> {
+        rtx.get(&self.0, tx_id.as_bytes())?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+}
+
+fn decode(value: &[u8]) -> Result<FinalizedAcceptance> {
+    if value.len() != 40 {
+        bail!("Invalid finalized transaction value length");
+    }
+    Ok(FinalizedAcceptance {
+        accepting_block_hash: RpcHash::from_slice(&value[..32]),
+        chain_index: u64::from_be_bytes(value[32..].try_into()?),
+    })
+}
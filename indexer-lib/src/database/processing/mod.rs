@@ -1,18 +1,41 @@
 //! Transaction processing state and resolution.
 //!
 //! Contains partitions for tracking transaction acceptance, unknown transaction
-//! resolution, DAA score resolution, and sender resolution workflows.
+//! resolution, DAA score resolution, and sender resolution workflows,
+//! as well as blocks parked until their parents are processed, the
+//! acceptance history of reorged transactions, the outpoint index
+//! linking inputs to the outputs they spend, the markers of processed blocks, the
+//! transactions accepted at finality depth, the chain spans whose acceptance was lost and
+//! the filter answering lookups of unknown transaction ids.
 
 pub mod acceptance;
+pub mod acceptance_gaps;
+pub mod acceptance_history;
+pub mod finalized_transactions;
+pub mod orphan_pool;
+pub mod outpoints;
 pub mod pending_sender_resolution;
+pub mod pending_spends;
+pub mod processed_blocks;
 pub mod skipped_transactions;
 pub mod skipped_tx_by_block;
+pub mod tx_id_filter;
+pub mod tx_inputs;
 pub mod unknown_daa_scores;
 pub mod unknown_transactions;
 
 pub use acceptance::*;
+pub use acceptance_gaps::*;
+pub use acceptance_history::*;
+pub use finalized_transactions::*;
+pub use orphan_pool::*;
+pub use outpoints::*;
 pub use pending_sender_resolution::*;
+pub use pending_spends::*;
+pub use processed_blocks::*;
 pub use skipped_transactions::*;
 pub use skipped_tx_by_block::*;
+pub use tx_id_filter::*;
+pub use tx_inputs::*;
 pub use unknown_daa_scores::*;
 pub use unknown_transactions::*;
@@ -0,0 +1,206 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::{RpcBlock, RpcHash};
+use std::collections::HashSet;
+use workflow_serializer::prelude::{Deserializer, Serializer};
+
+/// Partition parking blocks whose parents were not processed yet.
+///
+/// **Key structure:** [missing_parent_hash (32 bytes)] + [block_hash (32 bytes)] = 64 bytes total
+/// **Value:** [daa_score (8 bytes BE)] + [serialized RpcBlock]
+///
+/// A block missing several parents is parked once per missing parent, so processing
+/// any of them finds the block by prefix. The block is released once none are missing.
+#[derive(Clone)]
+pub struct OrphanPoolPartition(fjall::TxPartition);
+
+impl DescribePartition for OrphanPoolPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "orphan_pool",
+        key: &[
+            field("missing_parent_hash", FieldType::Hash),
+            field("block_hash", FieldType::Hash),
+        ],
+        value: &[
+            field("daa_score", FieldType::U64Be),
+            field("block", FieldType::Tail("workflow_serializer(RpcBlock)")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl OrphanPoolPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn park_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        missing_parent: &RpcHash,
+        block: &RpcBlock,
+    ) -> Result<()> {
+        wtx.insert(
+            &self.0,
+            Self::key(missing_parent, &block.header.hash),
+            encode_block(block)?,
+        );
+        Ok(())
+    }
+
+    /// Removes and returns all blocks waiting for `parent`
+    pub fn take_children_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        parent: &RpcHash,
+    ) -> Result<Vec<RpcBlock>> {
+        let mut keys = Vec::new();
+        let mut blocks = Vec::new();
+        for item in wtx.prefix(&self.0, parent.as_bytes()) {
+            let (key, value) = item?;
+            blocks.push(decode_block(&value)?);
+            keys.push(key);
+        }
+        for key in keys {
+            wtx.remove(&self.0, key);
+        }
+        Ok(blocks)
+    }
+
+    /// Removes and returns all blocks with daa score below `daa_score`.
+    /// A block parked under several parents is returned once
+    pub fn take_older_than_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        daa_score: u64,
+    ) -> Result<Vec<RpcBlock>> {
+        let mut keys = Vec::new();
+        let mut seen = HashSet::new();
+        let mut blocks = Vec::new();
+        for item in wtx.iter(&self.0) {
+            let (key, value) = item?;
+            if block_daa_score(&value)? >= daa_score {
+                continue;
+            }
+            if seen.insert(Self::block_hash(&key)?) {
+                blocks.push(decode_block(&value)?);
+            }
+            keys.push(key);
+        }
+        for key in keys {
+            wtx.remove(&self.0, key);
+        }
+        Ok(blocks)
+    }
+
+    /// Distinct parked blocks with daa score below `daa_score`, left parked
+    pub fn parked_older_than_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        daa_score: u64,
+    ) -> Result<Vec<RpcBlock>> {
+        let mut seen = HashSet::new();
+        let mut blocks = Vec::new();
+        for item in rtx.iter(&self.0) {
+            let (key, value) = item?;
+            if block_daa_score(&value)? < daa_score && seen.insert(Self::block_hash(&key)?) {
+                blocks.push(decode_block(&value)?);
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Amount of distinct parked blocks
+    pub fn count_blocks_rtx(&self, rtx: &ReadTransaction) -> Result<usize> {
+        let mut seen = HashSet::new();
+        for key in rtx.keys(&self.0) {
+            seen.insert(Self::block_hash(&key?)?);
+        }
+        Ok(seen.len())
+    }
+
+    /// Whether any block waits for `parent`
+    pub fn has_children(&self, parent: &RpcHash) -> Result<bool> {
+        Ok(self
+            .0
+            .inner()
+            .prefix(parent.as_bytes())
+            .next()
+            .transpose()?
+            .is_some())
+    }
+
+    /// Whether the block is parked under one of its direct parents
+    pub fn is_parked(&self, block: &RpcBlock) -> Result<bool> {
+        for parent in block.header.parents_by_level.first().into_iter().flatten() {
+            if self.0.get(Self::key(parent, &block.header.hash))?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn key(missing_parent: &RpcHash, block_hash: &RpcHash) -> [u8; 64] {
+        let mut key = [0u8; 64];
+        key[..32].copy_from_slice(&missing_parent.as_bytes());
+        key[32..].copy_from_slice(&block_hash.as_bytes());
+        key
+    }
+
+    fn block_hash(key: &[u8]) -> Result<[u8; 32]> {
+        if key.len() != 64 {
+            bail!("Invalid orphan pool key length");
+        }
+        Ok(key[32..].try_into()?)
+    }
+}
+
+fn encode_block(block: &RpcBlock) -> Result<Vec<u8>> {
+    let mut value = Vec::with_capacity(8 + 1024);
+    value.extend_from_slice(&block.header.daa_score.to_be_bytes());
+    block.serialize(&mut value)?;
+    Ok(value)
+}
+
+fn block_daa_score(value: &[u8]) -> Result<u64> {
+    if value.len() < 8 {
+        bail!("Invalid orphan pool value length");
+    }
+    Ok(u64::from_be_bytes(value[..8].try_into()?))
+}
+
+fn decode_block(value: &[u8]) -> Result<RpcBlock> {
+    block_daa_score(value)?;
+    let mut reader = &value[8..];
+    Ok(RpcBlock::deserialize(&mut reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_layout() {
+        let parent = RpcHash::from_u64_word(1);
+        let block = RpcHash::from_u64_word(2);
+        let key = OrphanPoolPartition::key(&parent, &block);
+        assert_eq!(&key[..32], &parent.as_bytes());
+        assert_eq!(
+            OrphanPoolPartition::block_hash(&key).unwrap(),
+            block.as_bytes()
+        );
+        assert!(OrphanPoolPartition::block_hash(&key[..40]).is_err());
+    }
+
+    #[test]
+    fn test_block_daa_score() {
+        let mut value = 42u64.to_be_bytes().to_vec();
+        value.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(block_daa_score(&value).unwrap(), 42);
+        assert!(block_daa_score(&value[..4]).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use crate::database::script_class::{OutputScriptClass, SCRIPT_CLASS_LEN, script_class};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_consensus_core::tx::ScriptPublicKey;
+use kaspa_rpc_core::{RpcTransactionId, RpcTransactionOutpoint};
+
+const UNSPENT: [u8; 32] = [0; 32];
+/// First byte of versioned values. Values written before versioning start with the top byte of
+/// the amount, which the max supply keeps below it
+const VERSIONED: u8 = 0xff;
+pub const OUTPUT_CODEC_VERSION: u8 = 1;
+const LEGACY_HEADER_LEN: usize = 8 + 32 + 2;
+const HEADER_LEN: usize = 2 + 8 + 32 + SCRIPT_CLASS_LEN + 2;
+
+/// Partition indexing transaction outputs by outpoint, linked to the transaction spending them.
+///
+/// **Key:** [tx_id (32 bytes)] + [output index (4 bytes BE)] = 36 bytes
+/// **Value:** [0xff] + [codec version (1 byte)] + [value (8 bytes BE)] +
+/// [spending tx_id (32 bytes, zeroes while unspent)] + [script class (3 bytes)] +
+/// [script version (2 bytes BE)] + [script]
+///
+/// Values written before the class was stored lack the first two bytes and the class, which is
+/// computed from the script when they are read.
+#[derive(Clone)]
+pub struct OutpointPartition(fjall::TxPartition);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedOutput {
+    pub value: u64,
+    pub script_public_key: ScriptPublicKey,
+    pub script_class: OutputScriptClass,
+    pub spent_by: Option<RpcTransactionId>,
+}
+
+impl IndexedOutput {
+    /// Classifies the script
+    pub fn new(
+        value: u64,
+        script_public_key: ScriptPublicKey,
+        spent_by: Option<RpcTransactionId>,
+    ) -> Self {
+        Self {
+            value,
+            script_class: script_class(&script_public_key),
+            script_public_key,
+            spent_by,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let script = self.script_public_key.script();
+        let mut value = Vec::with_capacity(HEADER_LEN + script.len());
+        value.push(VERSIONED);
+        value.push(OUTPUT_CODEC_VERSION);
+        value.extend_from_slice(&self.value.to_be_bytes());
+        value.extend_from_slice(&self.spent_by.map_or(UNSPENT, |tx_id| tx_id.as_bytes()));
+        value.extend_from_slice(&self.script_class.encode());
+        value.extend_from_slice(&self.script_public_key.version().to_be_bytes());
+        value.extend_from_slice(script);
+        value
+    }
+
+    fn decode(value: &[u8]) -> Result<Self> {
+        let (amount, spent_by, class, script) = match value.first() {
+            Some(&VERSIONED) => {
+                if value.len() < HEADER_LEN {
+                    bail!("Invalid outpoint value length");
+                }
+                if value[1] != OUTPUT_CODEC_VERSION {
+                    bail!("Unsupported outpoint codec version: {}", value[1]);
+                }
+                let class = &value[42..42 + SCRIPT_CLASS_LEN];
+                (
+                    &value[2..10],
+                    &value[10..42],
+                    Some(class),
+                    &value[42 + SCRIPT_CLASS_LEN..],
+                )
+            }
+            _ => {
+                if value.len() < LEGACY_HEADER_LEN {
+                    bail!("Invalid outpoint value length");
+                }
+                (&value[..8], &value[8..40], None, &value[40..])
+            }
+        };
+        let spent_by: [u8; 32] = spent_by.try_into()?;
+        let script_public_key = ScriptPublicKey::from_vec(
+            u16::from_be_bytes(script[..2].try_into()?),
+            script[2..].to_vec(),
+        );
+        Ok(Self {
+            value: u64::from_be_bytes(amount.try_into()?),
+            spent_by: (spent_by != UNSPENT).then(|| RpcTransactionId::from_bytes(spent_by)),
+            script_class: match class {
+                Some(class) => OutputScriptClass::decode(class)?,
+                None => script_class(&script_public_key),
+            },
+            script_public_key,
+        })
+    }
+}
+
+impl DescribePartition for OutpointPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "outpoints",
+        key: &[
+            field("tx_id", FieldType::Hash),
+            field("index", FieldType::Bytes(4)),
+        ],
+        value: &[
+            field("versioned", FieldType::U8),
+            field("codec_version", FieldType::U8),
+            field("value", FieldType::U64Be),
+            field("spending_tx_id", FieldType::Hash),
+            field("script_class", FieldType::Bytes(SCRIPT_CLASS_LEN)),
+            field("script_version", FieldType::Bytes(2)),
+            field("script", FieldType::Tail("bytes")),
+        ],
+        value_version: OUTPUT_CODEC_VERSION,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl OutpointPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    /// Inserts the output unless already indexed, so a reprocessed transaction keeps its
+    /// spend link
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: RpcTransactionId,
+        index: u32,
+        output: &IndexedOutput,
+    ) -> Result<bool> {
+        let key = outpoint_key(tx_id, index);
+        if wtx.contains_key(&self.0, key)? {
+            return Ok(false);
+        }
+        wtx.insert(&self.0, key, output.encode());
+        Ok(true)
+    }
+
+    pub fn get_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: RpcTransactionId,
+        index: u32,
+    ) -> Result<Option<IndexedOutput>> {
+        wtx.get(&self.0, outpoint_key(tx_id, index))?
+            .map(|value| IndexedOutput::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tx_id: RpcTransactionId,
+        index: u32,
+    ) -> Result<Option<IndexedOutput>> {
+        rtx.get(&self.0, outpoint_key(tx_id, index))?
+            .map(|value| IndexedOutput::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_output_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        outpoint: &RpcTransactionOutpoint,
+    ) -> Result<Option<IndexedOutput>> {
+        self.get_rtx(rtx, outpoint.transaction_id, outpoint.index)
+    }
+
+    /// Outputs of the transaction indexed so far, by output index
+    pub fn get_outputs_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: RpcTransactionId,
+    ) -> Result<Vec<(u32, IndexedOutput)>> {
+        wtx.prefix(&self.0, tx_id.as_bytes())
+            .map(|item| {
+                let (key, value) = item?;
+                if key.len() != 36 {
+                    bail!("Invalid outpoint key length");
+                }
+                Ok((
+                    u32::from_be_bytes(key[32..].try_into()?),
+                    IndexedOutput::decode(&value)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Links the output to the transaction spending it. Returns the output, none if it is
+    /// not indexed
+    pub fn mark_spent_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: RpcTransactionId,
+        index: u32,
+        spent_by: RpcTransactionId,
+    ) -> Result<Option<IndexedOutput>> {
+        let Some(mut output) = self.get_wtx(wtx, tx_id, index)? else {
+            return Ok(None);
+        };
+        if output.spent_by != Some(spent_by) {
+            output.spent_by = Some(spent_by);
+            wtx.insert(&self.0, outpoint_key(tx_id, index), output.encode());
+        }
+        Ok(Some(output))
+    }
+}
+
+pub fn outpoint_key(tx_id: RpcTransactionId, index: u32) -> [u8; 36] {
+    let mut key = [0u8; 36];
+    key[..32].copy_from_slice(&tx_id.as_bytes());
+    key[32..].copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_roundtrip() {
+        let mut output = IndexedOutput::new(
+            1_000,
+            ScriptPublicKey::from_vec(0, vec![0x20, 1, 2, 3, 0xac]),
+            None,
+        );
+        assert_eq!(IndexedOutput::decode(&output.encode()).unwrap(), output);
+        output.spent_by = Some(RpcTransactionId::from_u64_word(7));
+        assert_eq!(IndexedOutput::decode(&output.encode()).unwrap(), output);
+        output.script_public_key = ScriptPublicKey::from_vec(1, vec![]);
+        output.script_class = OutputScriptClass::Unknown(1);
+        assert_eq!(IndexedOutput::decode(&output.encode()).unwrap(), output);
+        assert!(IndexedOutput::decode(&[0; 12]).is_err());
+        assert!(IndexedOutput::decode(&[VERSIONED; 20]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_value_gets_classified() {
+        let mut script = vec![0x20];
+        script.extend_from_slice(&[5; 32]);
+        script.push(0xac);
+        let mut legacy = 2_000u64.to_be_bytes().to_vec();
+        legacy.extend_from_slice(&UNSPENT);
+        legacy.extend_from_slice(&0u16.to_be_bytes());
+        legacy.extend_from_slice(&script);
+        let output = IndexedOutput::decode(&legacy).unwrap();
+        assert_eq!(
+            (output.value, output.script_class, output.spent_by),
+            (2_000, OutputScriptClass::PubKey, None)
+        );
+        assert_eq!(output.script_public_key.script(), &script[..]);
+    }
+}
@@ -4,10 +4,13 @@ use crate::database::resolution_keys::{
     LikeContextualMessageKeyForResolution, LikeHandshakeKeyForResolution,
     LikePaymentKeyForResolution, PaymentKeyForResolution, SenderResolutionLikeKey,
 };
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use crate::database::util::delete_range_wtx;
 use anyhow::Result;
 use bytemuck::{AnyBitPattern, NoUninit};
 use fjall::{PartitionCreateOptions, ReadTransaction, UserKey, WriteTransaction};
 use kaspa_rpc_core::RpcTransactionId;
+use std::ops::Bound;
 
 #[derive(Clone)]
 pub struct PendingSenderResolutionPartition(fjall::TxPartition);
@@ -30,10 +33,26 @@ pub struct PendingSenderResolution<T: AsRef<[u8]> + Clone = UserKey> {
     pub like_key: SenderResolutionLikeKey<T>,
 }
 
+impl DescribePartition for PendingSenderResolutionPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "pending_sender_resolution",
+        key: &[
+            field("accepting_daa_score", FieldType::U64Be),
+            field("tx_id", FieldType::Hash),
+            field("partition_type", FieldType::U8),
+        ],
+        value: &[field(
+            "sender_like_key",
+            FieldType::Tail("key(partition_type)"),
+        )],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl PendingSenderResolutionPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(keyspace.open_partition(
-            "pending_sender_resolution",
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
             PartitionCreateOptions::default().block_size(64 * 1024),
         )?))
     }
@@ -184,6 +203,17 @@ impl PendingSenderResolutionPartition {
         Ok(results)
     }
 
+    /// Remove all pending entries accepted within `from_daa..=to_daa` (reorged chain span).
+    /// Returns the amount of removed entries
+    pub fn remove_daa_range_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        from_daa: u64,
+        to_daa: u64,
+    ) -> Result<usize> {
+        delete_range_wtx(wtx, &self.0, daa_range(from_daa, to_daa))
+    }
+
     /// Get all pending resolutions (for processing in DAA score order)
     pub fn get_all_pending(
         &self,
@@ -406,10 +436,46 @@ impl PendingSenderResolutionPartition {
     }
 }
 
+/// Key bounds covering every entry accepted within `from_daa..=to_daa`
+fn daa_range(from_daa: u64, to_daa: u64) -> (Bound<[u8; 8]>, Bound<[u8; 8]>) {
+    let end = match to_daa.checked_add(1) {
+        Some(end) => Bound::Excluded(end.to_be_bytes()),
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(from_daa.to_be_bytes()), end)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::database::messages::AddressPayload;
+    use std::ops::RangeBounds;
+
+    #[test]
+    fn test_daa_range_bounds() {
+        let key = |daa: u64| {
+            bytemuck::bytes_of(&PendingResolutionKey {
+                accepting_daa_score: daa.to_be_bytes(),
+                tx_id: [0xff; 32],
+                partition_type: u8::MAX,
+            })
+            .to_vec()
+        };
+        let contains = |range: &(Bound<[u8; 8]>, Bound<[u8; 8]>), daa| {
+            let (start, end) = range;
+            let as_slice = |b: &Bound<[u8; 8]>| b.as_ref().map(|b| b.to_vec());
+            (as_slice(start), as_slice(end)).contains(&key(daa))
+        };
+
+        let range = daa_range(100, 200);
+        assert!(!contains(&range, 99));
+        assert!(contains(&range, 100));
+        assert!(contains(&range, 200));
+        assert!(!contains(&range, 201));
+
+        let range = daa_range(u64::MAX - 1, u64::MAX);
+        assert!(contains(&range, u64::MAX));
+    }
 
     #[test]
     fn test_pending_resolution_key_serialization() {
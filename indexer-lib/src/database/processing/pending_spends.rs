@@ -0,0 +1,225 @@
+use crate::database::processing::outpoint_key;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::RpcTransactionId;
+
+/// Partition parking inputs whose previous outpoint is not indexed yet.
+///
+/// **Key:** [outpoint tx_id (32 bytes)] + [outpoint index (4 bytes BE)] + [spending tx_id (32 bytes)]
+/// **Value:** [daa_score (8 bytes BE)] of the spending block
+///
+/// The spend is linked once the output gets indexed. Outputs created before indexing started
+/// never are, their spends stay parked until evicted through [`PendingSpendByDaaPartition`].
+#[derive(Clone)]
+pub struct PendingSpendPartition {
+    partition: fjall::TxPartition,
+    by_daa: PendingSpendByDaaPartition,
+}
+
+impl DescribePartition for PendingSpendPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "pending_spends",
+        key: &[
+            field("outpoint_tx_id", FieldType::Hash),
+            field("outpoint_index", FieldType::Bytes(4)),
+            field("spending_tx_id", FieldType::Hash),
+        ],
+        value: &[field("daa_score", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl PendingSpendPartition {
+    /// Indexes spends parked before the daa index existed
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        let partition =
+            schema::open_partition::<Self>(keyspace, PartitionCreateOptions::default())?;
+        let by_daa = PendingSpendByDaaPartition::new(keyspace)?;
+        if by_daa.0.inner().is_empty()? && !partition.inner().is_empty()? {
+            let rtx = keyspace.read_tx();
+            let mut wtx = keyspace.write_tx()?;
+            for item in rtx.iter(&partition) {
+                let (key, value) = item?;
+                wtx.insert(&by_daa.0, by_daa_key(parked_daa_score(&value)?, &key)?, []);
+            }
+            wtx.commit()??;
+        }
+        Ok(Self { partition, by_daa })
+    }
+
+    /// False if the spend was parked already
+    pub fn park_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        outpoint_tx_id: RpcTransactionId,
+        outpoint_index: u32,
+        spent_by: RpcTransactionId,
+        daa_score: u64,
+    ) -> Result<bool> {
+        let mut key = [0u8; 68];
+        key[..36].copy_from_slice(&outpoint_key(outpoint_tx_id, outpoint_index));
+        key[36..].copy_from_slice(&spent_by.as_bytes());
+        let parked_at = wtx
+            .get(&self.partition, key)?
+            .map(|value| parked_daa_score(&value))
+            .transpose()?;
+        if let Some(parked_at) = parked_at {
+            wtx.remove(&self.by_daa.0, by_daa_key(parked_at, &key)?);
+        }
+        wtx.insert(&self.partition, key, daa_score.to_be_bytes());
+        wtx.insert(&self.by_daa.0, by_daa_key(daa_score, &key)?, []);
+        Ok(parked_at.is_none())
+    }
+
+    /// Removes and returns the transactions waiting for the outpoint
+    pub fn take_spenders_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        outpoint_tx_id: RpcTransactionId,
+        outpoint_index: u32,
+    ) -> Result<Vec<RpcTransactionId>> {
+        let prefix = outpoint_key(outpoint_tx_id, outpoint_index);
+        let mut spenders = Vec::new();
+        let mut taken = Vec::new();
+        for item in wtx.prefix(&self.partition, prefix) {
+            let (key, value) = item?;
+            spenders.push(Self::spender(&key)?);
+            taken.push((by_daa_key(parked_daa_score(&value)?, &key)?, key));
+        }
+        for (by_daa_key, key) in taken {
+            wtx.remove(&self.by_daa.0, by_daa_key);
+            wtx.remove(&self.partition, key);
+        }
+        Ok(spenders)
+    }
+
+    /// Drops parked spends from blocks below `daa_score`, returns the amount dropped. Only
+    /// the range of the daa index below `daa_score` is read
+    pub fn evict_older_than_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        daa_score: u64,
+    ) -> Result<usize> {
+        let mut evicted = Vec::new();
+        for key in wtx.range(&self.by_daa.0, ..daa_score.to_be_bytes().to_vec()) {
+            evicted.push(key?.0);
+        }
+        let count = evicted.len();
+        for key in evicted {
+            wtx.remove(&self.partition, &key[8..]);
+            wtx.remove(&self.by_daa.0, key);
+        }
+        Ok(count)
+    }
+
+    pub fn len_rtx(&self, rtx: &ReadTransaction) -> Result<usize> {
+        Ok(rtx.len(&self.partition)?)
+    }
+
+    fn spender(key: &[u8]) -> Result<RpcTransactionId> {
+        if key.len() != 68 {
+            bail!("Invalid pending spend key length");
+        }
+        Ok(RpcTransactionId::from_bytes(key[36..].try_into()?))
+    }
+}
+
+/// Daa index of [`PendingSpendPartition`], evictions only read the stale range.
+///
+/// **Key:** [daa_score (8 bytes BE)] + [pending spend key (68 bytes)]
+/// **Value:** empty
+#[derive(Clone)]
+pub struct PendingSpendByDaaPartition(fjall::TxPartition);
+
+impl DescribePartition for PendingSpendByDaaPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "pending_spends_by_daa",
+        key: &[
+            field("daa_score", FieldType::U64Be),
+            field("outpoint_tx_id", FieldType::Hash),
+            field("outpoint_index", FieldType::Bytes(4)),
+            field("spending_tx_id", FieldType::Hash),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl PendingSpendByDaaPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+}
+
+fn by_daa_key(daa_score: u64, key: &[u8]) -> Result<[u8; 76]> {
+    if key.len() != 68 {
+        bail!("Invalid pending spend key length");
+    }
+    let mut by_daa_key = [0u8; 76];
+    by_daa_key[..8].copy_from_slice(&daa_score.to_be_bytes());
+    by_daa_key[8..].copy_from_slice(key);
+    Ok(by_daa_key)
+}
+
+fn parked_daa_score(value: &[u8]) -> Result<u64> {
+    Ok(u64::from_be_bytes(value.try_into()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_spenders_and_evict() {
+        let keyspace = crate::database::test_keyspace("pending-spends");
+        let pending = PendingSpendPartition::new(&keyspace).unwrap();
+        let tx = RpcTransactionId::from_u64_word;
+
+        let mut wtx = keyspace.write_tx().unwrap();
+        assert!(pending.park_wtx(&mut wtx, tx(1), 0, tx(10), 100).unwrap());
+        assert!(pending.park_wtx(&mut wtx, tx(1), 1, tx(11), 100).unwrap());
+        assert!(pending.park_wtx(&mut wtx, tx(2), 0, tx(12), 200).unwrap());
+        // parked again by a reprocessed block
+        assert!(!pending.park_wtx(&mut wtx, tx(2), 0, tx(12), 200).unwrap());
+        assert_eq!(
+            pending.take_spenders_wtx(&mut wtx, tx(1), 0).unwrap(),
+            vec![tx(10)]
+        );
+        assert!(
+            pending
+                .take_spenders_wtx(&mut wtx, tx(1), 0)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(pending.evict_older_than_wtx(&mut wtx, 150).unwrap(), 1);
+        wtx.commit().unwrap().unwrap();
+
+        assert_eq!(pending.len_rtx(&keyspace.read_tx()).unwrap(), 1);
+        let rtx = keyspace.read_tx();
+        assert_eq!(rtx.len(&pending.by_daa.0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_spends_parked_before_the_daa_index_are_indexed() {
+        let keyspace = crate::database::test_keyspace("pending-spends-by-daa");
+        let pending = PendingSpendPartition::new(&keyspace).unwrap();
+        let tx = RpcTransactionId::from_u64_word;
+        let mut wtx = keyspace.write_tx().unwrap();
+        pending.park_wtx(&mut wtx, tx(1), 0, tx(10), 100).unwrap();
+        pending.park_wtx(&mut wtx, tx(2), 0, tx(11), 200).unwrap();
+        wtx.commit().unwrap().unwrap();
+        // as written before the index existed
+        for key in keyspace.read_tx().keys(&pending.by_daa.0) {
+            pending.by_daa.0.remove(key.unwrap()).unwrap();
+        }
+
+        let pending = PendingSpendPartition::new(&keyspace).unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        assert_eq!(pending.evict_older_than_wtx(&mut wtx, 150).unwrap(), 1);
+        wtx.commit().unwrap().unwrap();
+        assert_eq!(pending.len_rtx(&keyspace.read_tx()).unwrap(), 1);
+    }
+}
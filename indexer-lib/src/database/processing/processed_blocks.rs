@@ -0,0 +1,61 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::Result;
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_rpc_core::RpcHash;
+
+/// Partition marking blocks whose derived data is written.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [daa_score (8 bytes BE)]
+///
+/// The marker is written in the same transaction as the rest of the block, a block
+/// delivered again is skipped instead of counted twice.
+#[derive(Clone)]
+pub struct ProcessedBlockPartition(fjall::TxPartition);
+
+impl DescribePartition for ProcessedBlockPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "processed_blocks",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[field("daa_score", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl ProcessedBlockPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn mark_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash, daa_score: u64) {
+        wtx.insert(&self.0, block_hash.as_bytes(), daa_score.to_be_bytes());
+    }
+
+    pub fn unmark_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) {
+        wtx.remove(&self.0, block_hash.as_bytes());
+    }
+
+    pub fn is_processed(&self, block_hash: RpcHash) -> Result<bool> {
+        Ok(self.0.inner().contains_key(block_hash.as_bytes())?)
+    }
+
+    pub fn is_processed_rtx(&self, rtx: &ReadTransaction, block_hash: RpcHash) -> Result<bool> {
+        Ok(rtx.contains_key(&self.0, block_hash.as_bytes())?)
+    }
+
+    pub fn is_processed_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+    ) -> Result<bool> {
+        Ok(wtx.contains_key(&self.0, block_hash.as_bytes())?)
+    }
+
+    pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
+        self.0.remove(block_hash.as_bytes())?;
+        Ok(())
+    }
+}
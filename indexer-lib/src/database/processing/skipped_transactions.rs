@@ -1,3 +1,4 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::Result;
 use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
 use kaspa_rpc_core::RpcTransactionId;
@@ -5,22 +6,28 @@ use kaspa_rpc_core::RpcTransactionId;
 #[derive(Clone)]
 pub struct SkipTxPartition(fjall::TxPartition);
 
+impl DescribePartition for SkipTxPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "skip_tx",
+        key: &[field("tx_id", FieldType::Hash)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl SkipTxPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(
-            keyspace.open_partition(
-                "skip_tx",
-                PartitionCreateOptions::default()
-                    .max_memtable_size(32 * 1024 * 1024)
-                    .block_size(32 * 1024)
-                    .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
-                        fjall::compaction::SizeTiered {
-                            base_size: 6 * 1024 * 1024,
-                            level_ratio: 6,
-                        },
-                    )),
-            )?,
-        ))
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default()
+                .max_memtable_size(32 * 1024 * 1024)
+                .block_size(32 * 1024)
+                .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
+                    fjall::compaction::SizeTiered {
+                        base_size: 6 * 1024 * 1024,
+                        level_ratio: 6,
+                    },
+                )),
+        )?))
     }
 
     /// Mark a transaction as one to skip
@@ -1,4 +1,5 @@
 use crate::database::TxIdsSliceView;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::Result;
 use fjall::{
     KvSeparationOptions, PartitionCreateOptions, ReadTransaction, UserKey, WriteTransaction,
@@ -23,23 +24,34 @@ use fjall::{
 #[derive(Clone)]
 pub struct SkipTxByBlockPartition(fjall::TxPartition);
 
+impl DescribePartition for SkipTxByBlockPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "skip_tx_by_block",
+        key: &[
+            field("daa_score", FieldType::U64Be),
+            field("block_hash", FieldType::Hash),
+        ],
+        value: &[field("tx_ids", FieldType::Tail("hash[]"))],
+        kv_separation: true,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl SkipTxByBlockPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> fjall::Result<Self> {
-        Ok(Self(
-            keyspace.open_partition(
-                "skip_tx_by_block",
-                PartitionCreateOptions::default()
-                    .max_memtable_size(32 * 1024 * 1024)
-                    .block_size(64 * 1024)
-                    .with_kv_separation(KvSeparationOptions::default())
-                    .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
-                        fjall::compaction::SizeTiered {
-                            base_size: 8 * 1024 * 1024,
-                            level_ratio: 6,
-                        },
-                    )),
-            )?,
-        ))
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default()
+                .max_memtable_size(32 * 1024 * 1024)
+                .block_size(64 * 1024)
+                .with_kv_separation(KvSeparationOptions::default())
+                .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
+                    fjall::compaction::SizeTiered {
+                        base_size: 8 * 1024 * 1024,
+                        level_ratio: 6,
+                    },
+                )),
+        )?))
     }
 
     /// Add skipped transaction IDs for a specific block.
@@ -0,0 +1,262 @@
+use crate::database::processing::TxIDToAcceptancePartition;
+use anyhow::Result;
+use fjall::ReadTransaction;
+use std::f64::consts::LN_2;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// One absent transaction in a hundred passes the filter
+pub const DEFAULT_TX_FILTER_FP_RATE_PPM: u32 = 10_000;
+const COUNTERS_PER_WORD: usize = 16;
+const COUNTER_MAX: u64 = 0xF;
+const MAX_HASHES: u32 = 16;
+
+/// Counting bloom filter over the transaction ids of the [`TxIDToAcceptancePartition`],
+/// answering lookups of transactions which were never indexed without reading the store.
+///
+/// Ids are added when their acceptance entry is written, before the commit, so the filter
+/// never misses a stored transaction and an aborted write only leaves a false positive. The
+/// counters take 4 bits each, a saturated counter is never decremented again. The partition
+/// keeps transactions once indexed, [`TxIdFilter::remove`] is for removals made for good and
+/// is called after their commit.
+pub struct TxIdFilter {
+    counters: Box<[AtomicU64]>,
+    slots: u64,
+    hashes: u32,
+    negatives: AtomicU64,
+    positives: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TxIdFilterStats {
+    /// Lookups answered without reading the store
+    pub negatives: u64,
+    pub positives: u64,
+    /// Positive lookups the store did not have
+    pub false_positives: u64,
+    /// Share of absent ids passing the filter per million, from the occupied counters
+    pub fp_estimate_ppm: u64,
+}
+
+impl TxIdFilter {
+    /// Sized for `capacity` transactions with `fp_rate_ppm` false positives per million
+    /// lookups of absent ones
+    pub fn new(capacity: usize, fp_rate_ppm: u32) -> Self {
+        let fp_rate = fp_rate_ppm.clamp(1, 999_999) as f64 / 1e6;
+        let capacity = capacity.max(1) as f64;
+        let slots = (-capacity * fp_rate.ln() / (LN_2 * LN_2)).ceil() as usize;
+        let words = slots.div_ceil(COUNTERS_PER_WORD).max(1);
+        let slots = words * COUNTERS_PER_WORD;
+        let hashes = ((slots as f64 / capacity) * LN_2).round() as u32;
+        Self {
+            counters: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            slots: slots as u64,
+            hashes: hashes.clamp(1, MAX_HASHES),
+            negatives: Default::default(),
+            positives: Default::default(),
+            false_positives: Default::default(),
+        }
+    }
+
+    /// Filled with the transactions stored in the partition
+    pub fn build(
+        capacity: usize,
+        fp_rate_ppm: u32,
+        partition: &TxIDToAcceptancePartition,
+        rtx: &ReadTransaction,
+    ) -> Result<Self> {
+        let filter = Self::new(capacity, fp_rate_ppm);
+        let mut last = None;
+        for key in partition.iter_keys_rtx(rtx) {
+            let tx_id = key?.tx_id;
+            // entries of a transaction are adjacent
+            if last != Some(tx_id) {
+                filter.insert(&tx_id);
+                last = Some(tx_id);
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn insert(&self, tx_id: &[u8; 32]) {
+        for (word, shift) in self.slots_of(tx_id) {
+            _ = self.counters[word].fetch_update(Ordering::AcqRel, Ordering::Acquire, |counters| {
+                ((counters >> shift) & COUNTER_MAX < COUNTER_MAX).then(|| counters + (1 << shift))
+            });
+        }
+    }
+
+    pub fn remove(&self, tx_id: &[u8; 32]) {
+        for (word, shift) in self.slots_of(tx_id) {
+            _ = self.counters[word].fetch_update(Ordering::AcqRel, Ordering::Acquire, |counters| {
+                let counter = (counters >> shift) & COUNTER_MAX;
+                (1..COUNTER_MAX)
+                    .contains(&counter)
+                    .then(|| counters - (1 << shift))
+            });
+        }
+    }
+
+    /// False for transactions which are not stored
+    pub fn may_contain(&self, tx_id: &[u8; 32]) -> bool {
+        self.slots_of(tx_id).all(|(word, shift)| {
+            (self.counters[word].load(Ordering::Acquire) >> shift) & COUNTER_MAX > 0
+        })
+    }
+
+    /// Same as [`Self::may_contain`], counted in the stats
+    pub fn check(&self, tx_id: &[u8; 32]) -> bool {
+        let contained = self.may_contain(tx_id);
+        let counter = match contained {
+            true => &self.positives,
+            false => &self.negatives,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        contained
+    }
+
+    /// Called when the store did not have a transaction the filter passed
+    pub fn record_false_positive(&self) {
+        self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> TxIdFilterStats {
+        let occupied = self
+            .counters
+            .iter()
+            .map(|counters| {
+                let counters = counters.load(Ordering::Relaxed);
+                let occupied = counters | counters >> 1 | counters >> 2 | counters >> 3;
+                (occupied & 0x1111_1111_1111_1111).count_ones() as u64
+            })
+            .sum::<u64>();
+        let fill = occupied as f64 / self.slots as f64;
+        TxIdFilterStats {
+            negatives: self.negatives.load(Ordering::Relaxed),
+            positives: self.positives.load(Ordering::Relaxed),
+            false_positives: self.false_positives.load(Ordering::Relaxed),
+            fp_estimate_ppm: (fill.powi(self.hashes as i32) * 1e6).round() as u64,
+        }
+    }
+
+    /// Word and bit offset of the counters of the id, by double hashing. Transaction ids
+    /// are hashes already, their words are folded in
+    fn slots_of(&self, tx_id: &[u8; 32]) -> impl Iterator<Item = (usize, u32)> + '_ {
+        let word = |i: usize| u64::from_le_bytes(tx_id[i * 8..i * 8 + 8].try_into().unwrap());
+        let first = word(0) ^ word(2);
+        let step = (word(1) ^ word(3)) | 1;
+        (0..self.hashes as u64).map(move |i| {
+            let slot = first.wrapping_add(i.wrapping_mul(step)) % self.slots;
+            (
+                slot as usize / COUNTERS_PER_WORD,
+                (slot as usize % COUNTERS_PER_WORD) as u32 * 4,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::processing::{AcceptanceTxKey, AcceptingBlockResolutionData};
+    use std::sync::Arc;
+
+    fn tx_id(n: u64) -> [u8; 32] {
+        // spread like real ids
+        let mut state = n.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut id = [0u8; 32];
+        for chunk in id.chunks_mut(8) {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            chunk.copy_from_slice(&state.to_le_bytes());
+        }
+        id
+    }
+
+    #[test]
+    fn test_no_false_negatives_and_bounded_false_positives() {
+        let filter = TxIdFilter::new(10_000, DEFAULT_TX_FILTER_FP_RATE_PPM);
+        (0..10_000).for_each(|n| filter.insert(&tx_id(n)));
+        assert!((0..10_000).all(|n| filter.may_contain(&tx_id(n))));
+
+        let false_positives = (10_000..110_000)
+            .filter(|n| filter.may_contain(&tx_id(*n)))
+            .count();
+        // 1% expected
+        assert!(false_positives < 2_000, "{false_positives}");
+        let estimate = filter.stats().fp_estimate_ppm;
+        assert!((5_000..20_000).contains(&estimate), "{estimate}");
+    }
+
+    #[test]
+    fn test_remove_decrements() {
+        let filter = TxIdFilter::new(100, DEFAULT_TX_FILTER_FP_RATE_PPM);
+        filter.insert(&tx_id(1));
+        filter.insert(&tx_id(1));
+        filter.remove(&tx_id(1));
+        assert!(filter.may_contain(&tx_id(1)));
+        filter.remove(&tx_id(1));
+        assert!(!filter.may_contain(&tx_id(1)));
+        assert_eq!(filter.stats().fp_estimate_ppm, 0);
+
+        // saturated counters stay set
+        (0..20).for_each(|_| filter.insert(&tx_id(2)));
+        (0..20).for_each(|_| filter.remove(&tx_id(2)));
+        assert!(filter.may_contain(&tx_id(2)));
+    }
+
+    #[test]
+    fn test_check_counts_lookups() {
+        let filter = TxIdFilter::new(100, DEFAULT_TX_FILTER_FP_RATE_PPM);
+        filter.insert(&tx_id(1));
+        assert!(filter.check(&tx_id(1)));
+        assert!(!filter.check(&[0; 32]));
+        filter.record_false_positive();
+        let stats = filter.stats();
+        assert_eq!(
+            (stats.positives, stats.negatives, stats.false_positives),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_build_from_partition_and_note_inserts() {
+        let keyspace = crate::database::test_keyspace("tx-filter");
+        let partition = TxIDToAcceptancePartition::new(&keyspace).unwrap();
+        let insert = |partition: &TxIDToAcceptancePartition, n: u64, block: u64| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            let key = AcceptanceTxKey {
+                tx_id: tx_id(n),
+                accepted_at_daa: block.to_be_bytes(),
+                accepted_by_block_hash: tx_id(block),
+                partition_id: 0,
+            };
+            partition.insert_wtx(&mut wtx, &key, AcceptingBlockResolutionData::None);
+            wtx.commit().unwrap().unwrap();
+        };
+        // a transaction accepted by two blocks counts once
+        insert(&partition, 1, 10);
+        insert(&partition, 1, 11);
+        insert(&partition, 2, 10);
+
+        let filter = Arc::new(
+            TxIdFilter::build(
+                100,
+                DEFAULT_TX_FILTER_FP_RATE_PPM,
+                &partition,
+                &keyspace.read_tx(),
+            )
+            .unwrap(),
+        );
+        assert!(filter.may_contain(&tx_id(1)) && filter.may_contain(&tx_id(2)));
+        filter.remove(&tx_id(1));
+        assert!(!filter.may_contain(&tx_id(1)));
+
+        let partition = partition.with_filter(filter.clone());
+        assert!(!partition.may_contain(&tx_id(3)));
+        insert(&partition, 3, 12);
+        assert!(partition.may_contain(&tx_id(3)));
+        assert_eq!(partition.filter_stats().unwrap().negatives, 1);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::database::processing::outpoint_key;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, WriteTransaction};
+use kaspa_rpc_core::RpcTransactionId;
+
+/// Partition linking transaction inputs to the outpoints they spend, the reverse of the spend
+/// link of [`OutpointPartition`](crate::database::processing::OutpointPartition).
+///
+/// **Key:** [tx_id (32 bytes)] + [input index (4 bytes BE)]
+/// **Value:** [outpoint tx_id (32 bytes)] + [outpoint index (4 bytes BE)]
+///
+/// Written for every input, whether the spent output is indexed or not, so conflicting spends
+/// of the same outpoint each keep their own link.
+#[derive(Clone)]
+pub struct TxInputPartition(fjall::TxPartition);
+
+impl DescribePartition for TxInputPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "tx_inputs",
+        key: &[
+            field("tx_id", FieldType::Hash),
+            field("input_index", FieldType::Bytes(4)),
+        ],
+        value: &[
+            field("outpoint_tx_id", FieldType::Hash),
+            field("outpoint_index", FieldType::Bytes(4)),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl TxInputPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: RpcTransactionId,
+        input_index: u32,
+        outpoint_tx_id: RpcTransactionId,
+        outpoint_index: u32,
+    ) {
+        wtx.insert(
+            &self.0,
+            outpoint_key(tx_id, input_index),
+            outpoint_key(outpoint_tx_id, outpoint_index),
+        );
+    }
+
+    /// Outpoints spent by the transaction, in input order
+    pub fn get_spent_outpoints_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tx_id: RpcTransactionId,
+    ) -> Result<Vec<(RpcTransactionId, u32)>> {
+        wtx.prefix(&self.0, tx_id.as_bytes())
+            .map(|item| {
+                let (_, value) = item?;
+                if value.len() != 36 {
+                    bail!("Invalid tx input value length");
+                }
+                Ok((
+                    RpcTransactionId::from_bytes(value[..32].try_into()?),
+                    u32::from_be_bytes(value[32..].try_into()?),
+                ))
+            })
+            .collect()
+    }
+}
@@ -3,6 +3,7 @@ use crate::database::resolution_keys::{
     LikeContextualMessageKeyForResolution, LikeHandshakeKeyForResolution,
     LikePaymentKeyForResolution, PaymentKeyForResolution,
 };
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::Result;
 use bytemuck::{AnyBitPattern, NoUninit};
 use fjall::{
@@ -257,17 +258,28 @@ pub enum DaaResolutionPartitionType {
 
 // DaaResolutionLikeKey is now imported from resolution_keys module
 
+impl DescribePartition for UnknownAcceptingDaaPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "unknown_accepting_daa",
+        key: &[field("accepting_block_hash", FieldType::Hash)],
+        value: &[
+            field("attempt_count", FieldType::U8),
+            field("entries", FieldType::Tail("padded_resolution_entry[]")),
+        ],
+        kv_separation: true,
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl UnknownAcceptingDaaPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(
-            keyspace.open_partition(
-                "unknown_accepting_daa",
-                PartitionCreateOptions::default()
-                    .max_memtable_size(32 * 1024 * 1024)
-                    .block_size(64 * 1024)
-                    .with_kv_separation(KvSeparationOptions::default().separation_threshold(1024)),
-            )?,
-        ))
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default()
+                .max_memtable_size(32 * 1024 * 1024)
+                .block_size(64 * 1024)
+                .with_kv_separation(KvSeparationOptions::default().separation_threshold(1024)),
+        )?))
     }
 
     pub fn len(&self) -> Result<usize> {
@@ -1,4 +1,5 @@
 use crate::database::LikeTxIds;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
 use anyhow::Result;
 use fjall::{PartitionCreateOptions, ReadTransaction, UserValue, WriteTransaction};
 use kaspa_rpc_core::{RpcHash, RpcTransactionId};
@@ -23,22 +24,29 @@ pub struct UnknownTxInfo {
 #[derive(Clone)]
 pub struct UnknownTxPartition(fjall::TxPartition);
 
+impl DescribePartition for UnknownTxPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "unknown_tx",
+        key: &[field("accepting_block_hash", FieldType::Hash)],
+        value: &[field("tx_ids", FieldType::Tail("hash[]"))],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
 impl UnknownTxPartition {
     pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
-        Ok(Self(
-            keyspace.open_partition(
-                "unknown_tx",
-                PartitionCreateOptions::default()
-                    .max_memtable_size(32 * 1024 * 1024)
-                    .block_size(64 * 1024)
-                    .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
-                        fjall::compaction::SizeTiered {
-                            base_size: 6 * 1024 * 1024,
-                            level_ratio: 6,
-                        },
-                    )),
-            )?,
-        ))
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default()
+                .max_memtable_size(32 * 1024 * 1024)
+                .block_size(64 * 1024)
+                .compaction_strategy(fjall::compaction::Strategy::SizeTiered(
+                    fjall::compaction::SizeTiered {
+                        base_size: 6 * 1024 * 1024,
+                        level_ratio: 6,
+                    },
+                )),
+        )?))
     }
 
     /// Insert unknown transactions for an accepting block hash (batch operation)
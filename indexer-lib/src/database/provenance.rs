@@ -0,0 +1,329 @@
+use crate::database::difftest::{PartitionDigest, digest_partition_rtx};
+use crate::database::headers::{BlockGap, BlockGapsPartition};
+use crate::database::metadata::MetadataPartition;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use crate::historical_syncer::Cursor;
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction};
+use kaspa_consensus_core::config::params::Params;
+use kaspa_consensus_core::network::NetworkId;
+use kaspa_rpc_core::RpcHash;
+use std::fmt;
+use std::str::FromStr;
+
+/// Partition recording which node produced the indexed data.
+///
+/// Key: recorded_at unix millis (BE) so records are ordered by time.
+/// Value: virtual daa score (BE) followed by `url\nserver_version\nnetwork_id`.
+/// A record is appended every time the indexer starts talking to a different
+/// endpoint or node version, which together with the gaps partition describes
+/// the coverage window of the database.
+#[derive(Clone)]
+pub struct ProvenancePartition(fjall::TxPartition);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvenanceRecord {
+    pub recorded_at_ms: u64,
+    /// Virtual daa score of the node at the time of the switch
+    pub daa_score: u64,
+    pub node_url: String,
+    pub server_version: String,
+    pub network_id: String,
+}
+
+impl ProvenanceRecord {
+    /// Whether both records describe the same endpoint running the same node
+    pub fn same_source(&self, other: &Self) -> bool {
+        self.node_url == other.node_url
+            && self.server_version == other.server_version
+            && self.network_id == other.network_id
+    }
+
+    fn encode_value(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(
+            8 + self.node_url.len() + self.server_version.len() + self.network_id.len() + 2,
+        );
+        value.extend_from_slice(&self.daa_score.to_be_bytes());
+        value.extend_from_slice(self.node_url.as_bytes());
+        value.push(b'\n');
+        value.extend_from_slice(self.server_version.as_bytes());
+        value.push(b'\n');
+        value.extend_from_slice(self.network_id.as_bytes());
+        value
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<Self> {
+        if key.len() != 8 || value.len() < 8 {
+            bail!("Invalid provenance record length");
+        }
+        let mut fields = std::str::from_utf8(&value[8..])?.splitn(3, '\n');
+        let (Some(node_url), Some(server_version), Some(network_id)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("Invalid provenance record value");
+        };
+        Ok(Self {
+            recorded_at_ms: u64::from_be_bytes(key.try_into()?),
+            daa_score: u64::from_be_bytes(value[..8].try_into()?),
+            node_url: node_url.to_string(),
+            server_version: server_version.to_string(),
+            network_id: network_id.to_string(),
+        })
+    }
+}
+
+impl DescribePartition for ProvenancePartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "provenance",
+        key: &[field("recorded_at_ms", FieldType::U64Be)],
+        value: &[
+            field("daa_score", FieldType::U64Be),
+            field(
+                "source",
+                FieldType::Tail("utf8_lines(node_url,server_version,network_id)"),
+            ),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl ProvenancePartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert(&self, record: &ProvenanceRecord) -> Result<()> {
+        self.0
+            .insert(record.recorded_at_ms.to_be_bytes(), record.encode_value())?;
+        Ok(())
+    }
+
+    /// Appends `record` unless the latest record already describes the same source.
+    /// Returns whether the record was stored
+    pub fn record_if_changed(&self, record: &ProvenanceRecord) -> Result<bool> {
+        if let Some(latest) = self.latest()?
+            && latest.same_source(record)
+        {
+            return Ok(false);
+        }
+        self.insert(record)?;
+        Ok(true)
+    }
+
+    pub fn latest(&self) -> Result<Option<ProvenanceRecord>> {
+        self.0
+            .inner()
+            .last_key_value()?
+            .map(|(key, value)| ProvenanceRecord::decode(&key, &value))
+            .transpose()
+    }
+
+    pub fn get_all_rtx(
+        &self,
+        rtx: &ReadTransaction,
+    ) -> impl DoubleEndedIterator<Item = Result<ProvenanceRecord>> + '_ {
+        rtx.iter(&self.0).map(|item| {
+            let (key, value) = item?;
+            ProvenanceRecord::decode(&key, &value)
+        })
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.0.inner().len()?)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.0.inner().is_empty()?)
+    }
+}
+
+/// Provenance block describing where the data of a database comes from
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    pub indexer_version: String,
+    /// Network the database was created for, `None` for databases not recording it
+    pub network_id: Option<String>,
+    /// Genesis block of the network
+    pub genesis_hash: Option<RpcHash>,
+    pub sources: Vec<ProvenanceRecord>,
+    pub latest_block_cursor: Option<Cursor>,
+    pub latest_accepting_block_cursor: Option<Cursor>,
+    /// Ranges inside the covered window that are not synced yet
+    pub gaps: Vec<BlockGap>,
+    /// Content digest of every partition, by name
+    pub partitions: Vec<(String, PartitionDigest)>,
+}
+
+impl Provenance {
+    /// Collects the provenance of the database as seen by `rtx`, digesting the partitions reads
+    /// all of them
+    pub fn collect(keyspace: &fjall::TxKeyspace, rtx: &ReadTransaction) -> Result<Self> {
+        let provenance = ProvenancePartition::new(keyspace)?;
+        let metadata = MetadataPartition::new(keyspace)?;
+        let gaps = BlockGapsPartition::new(keyspace)?;
+        let network_id = metadata.get_network_id()?;
+        let genesis_hash = network_id
+            .as_deref()
+            .map(|network_id| {
+                anyhow::Ok(Params::from(NetworkId::from_str(network_id)?).genesis.hash)
+            })
+            .transpose()?;
+        let mut names = keyspace.list_partitions();
+        names.sort();
+        let partitions = names
+            .into_iter()
+            .map(|name| {
+                let digest = digest_partition_rtx(keyspace, rtx, &name)?;
+                Ok((name.to_string(), digest))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            indexer_version: env!("CARGO_PKG_VERSION").to_string(),
+            network_id,
+            genesis_hash,
+            partitions,
+            sources: provenance.get_all_rtx(rtx).collect::<Result<_>>()?,
+            latest_block_cursor: metadata.get_latest_block_cursor_rtx(rtx)?,
+            latest_accepting_block_cursor: metadata.get_latest_accepting_block_cursor_rtx(rtx)?,
+            gaps: gaps.get_all_gaps_rtx(rtx).collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "indexer version: {}", self.indexer_version)?;
+        writeln!(
+            f,
+            "network: {} (genesis {})",
+            self.network_id.as_deref().unwrap_or("unknown"),
+            self.genesis_hash
+                .map_or_else(|| "unknown".to_string(), |hash| hash.to_string())
+        )?;
+        writeln!(f, "sources:")?;
+        for source in &self.sources {
+            writeln!(
+                f,
+                "  since daa {} ({} ms): {} node {} on {}",
+                source.daa_score,
+                source.recorded_at_ms,
+                source.node_url,
+                source.server_version,
+                source.network_id
+            )?;
+        }
+        writeln!(f, "latest block: {:?}", self.latest_block_cursor)?;
+        writeln!(
+            f,
+            "latest accepting block: {:?}",
+            self.latest_accepting_block_cursor
+        )?;
+        writeln!(f, "gaps:")?;
+        for gap in &self.gaps {
+            writeln!(
+                f,
+                "  daa {} ({}) - daa {} ({})",
+                gap.from_daa_score, gap.from_block_hash, gap.to_daa_score, gap.to_block_hash
+            )?;
+        }
+        writeln!(f, "partitions:")?;
+        for (name, digest) in &self.partitions {
+            writeln!(
+                f,
+                "  {name}: {} entries, digest {:016x}",
+                digest.entries, digest.digest
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance_record_roundtrip() {
+        let record = ProvenanceRecord {
+            recorded_at_ms: 1_700_000_000_000,
+            daa_score: 42,
+            node_url: "ws://127.0.0.1:17110".to_string(),
+            server_version: "1.0.1".to_string(),
+            network_id: "mainnet".to_string(),
+        };
+        let decoded =
+            ProvenanceRecord::decode(&record.recorded_at_ms.to_be_bytes(), &record.encode_value())
+                .unwrap();
+        assert_eq!(decoded, record);
+
+        let other_node = ProvenanceRecord {
+            node_url: "ws://10.0.0.1:17110".to_string(),
+            ..record.clone()
+        };
+        assert!(!record.same_source(&other_node));
+        assert!(record.same_source(&ProvenanceRecord {
+            recorded_at_ms: 0,
+            daa_score: 0,
+            ..record.clone()
+        }));
+    }
+
+    #[test]
+    fn test_collect_genesis_and_digests() {
+        use kaspa_consensus_core::network::NetworkType;
+
+        let keyspace = crate::database::test_keyspace("provenance");
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        let provenance = Provenance::collect(&keyspace, &keyspace.read_tx()).unwrap();
+        assert_eq!(
+            (provenance.network_id, provenance.genesis_hash),
+            (None, None)
+        );
+
+        metadata.check_network_id("testnet-10").unwrap();
+        let blocks = keyspace
+            .open_partition("blocks", PartitionCreateOptions::default())
+            .unwrap();
+        blocks.insert(b"a", b"1").unwrap();
+        let provenance = Provenance::collect(&keyspace, &keyspace.read_tx()).unwrap();
+        let testnet = Params::from(NetworkId::with_suffix(NetworkType::Testnet, 10));
+        let mainnet = Params::from(NetworkId::new(NetworkType::Mainnet));
+        assert_eq!(provenance.genesis_hash, Some(testnet.genesis.hash));
+        assert_ne!(testnet.genesis.hash, mainnet.genesis.hash);
+        let digest = |provenance: &Provenance| {
+            provenance
+                .partitions
+                .iter()
+                .find(|(name, _)| name == "blocks")
+                .map(|(_, digest)| *digest)
+                .unwrap()
+        };
+        let before = digest(&provenance);
+        assert_eq!(before.entries, 1);
+        assert!(
+            provenance
+                .partitions
+                .iter()
+                .any(|(name, _)| name == "metadata")
+        );
+        assert!(
+            provenance
+                .to_string()
+                .contains(&testnet.genesis.hash.to_string())
+        );
+
+        // the digest is the one of the snapshot seen by the read transaction
+        let rtx = keyspace.read_tx();
+        blocks.insert(b"a", b"2").unwrap();
+        assert_eq!(
+            digest(&Provenance::collect(&keyspace, &rtx).unwrap()),
+            before
+        );
+        let after = digest(&Provenance::collect(&keyspace, &keyspace.read_tx()).unwrap());
+        assert_eq!(after.entries, 1);
+        assert_ne!(after.digest, before.digest);
+    }
+}
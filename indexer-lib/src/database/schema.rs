@@ -0,0 +1,491 @@
+//! Machine-readable description of the on-disk layout.
+//!
+//! Every partition implements [`DescribePartition`] next to its codec and is opened through
+//! [`open_partition`], which takes the partition name from the description. A partition without
+//! a description therefore can't be opened. [`describe_json`] renders all registered descriptions
+//! for external consumers reading the database files directly.
+
+use crate::database::aggregates::{
+    AcceptedAggregatesPartition, AggregatesPartition, BlockAcceptedCountPartition,
+};
+use crate::database::balances::{
+    AddressBalanceDeltaPartition, AddressBalancePartition, BlockBalanceDeltaPartition,
+};
+use crate::database::block_stats::BlockStatsPartition;
+use crate::database::block_transactions::BlockTransactionsPartition;
+use crate::database::crash_reports::CrashReportsPartition;
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockGapsPartition, BlockRelationsPartition,
+    ChainIndexByHashPartition, ChainIndexPartition, ChainMembershipPartition, DaaIndexPartition,
+    GapHistoryPartition, VerboseChainMembershipPartition,
+};
+use crate::database::messages::{
+    ContextualMessageBySenderPartition, HandshakeByReceiverPartition, HandshakeBySenderPartition,
+    PaymentByReceiverPartition, PaymentBySenderPartition, TxIdToHandshakePartition,
+    TxIdToPaymentPartition,
+};
+use crate::database::metadata::MetadataPartition;
+use crate::database::miners::{BlockMinerPartition, MinerBlocksPartition};
+use crate::database::processing::{
+    AcceptanceGapsPartition, AcceptanceHistoryPartition, AcceptingBlockToTxIDPartition,
+    FinalizedTxPartition, OrphanPoolPartition, OutpointPartition, PendingSenderResolutionPartition,
+    PendingSpendByDaaPartition, PendingSpendPartition, ProcessedBlockPartition,
+    SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition, TxInputPartition,
+    UnknownAcceptingDaaPartition, UnknownTxPartition,
+};
+use crate::database::provenance::ProvenancePartition;
+use crate::database::supply::{BlockRewardPartition, SupplyDeltaPartition, SupplyPendingPartition};
+use crate::database::token_operations::TokenOperationPartition;
+use crate::database::webhooks::{
+    WebhookAddressPartition, WebhookDeadLetterPartition, WebhookOutboxPartition,
+    WebhookPendingPartition, WebhookSubscriptionPartition,
+};
+use fjall::{PartitionCreateOptions, TxKeyspace};
+use std::fmt::Write;
+
+/// Bumped whenever a description changes in a way consumers have to handle
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    U8,
+    U64Be,
+    U64Le,
+    /// Two's complement
+    I64Be,
+    Uint192Be,
+    Uint192Le,
+    /// 32 byte block hash or transaction id
+    Hash,
+    /// Inverse address version (1 byte) followed by the 33 byte payload
+    AddressPayload,
+    /// Fixed length opaque bytes
+    Bytes(usize),
+    /// Variable length remainder of the key or value, named after the codec producing it
+    Tail(&'static str),
+}
+
+impl FieldType {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            FieldType::U8 => "u8",
+            FieldType::U64Be | FieldType::U64Le => "u64",
+            FieldType::I64Be => "i64",
+            FieldType::Uint192Be | FieldType::Uint192Le => "uint192",
+            FieldType::Hash => "hash",
+            FieldType::AddressPayload => "address_payload",
+            FieldType::Bytes(_) => "bytes",
+            FieldType::Tail(codec) => codec,
+        }
+    }
+
+    /// Encoded length, `None` for variable length fields
+    pub fn encoded_len(&self) -> Option<usize> {
+        match self {
+            FieldType::U8 => Some(1),
+            FieldType::U64Be | FieldType::U64Le | FieldType::I64Be => Some(8),
+            FieldType::Uint192Be | FieldType::Uint192Le => Some(24),
+            FieldType::Hash => Some(32),
+            FieldType::AddressPayload => Some(34),
+            FieldType::Bytes(len) => Some(*len),
+            FieldType::Tail(_) => None,
+        }
+    }
+
+    pub fn endianness(&self) -> Option<&'static str> {
+        match self {
+            FieldType::U64Be | FieldType::I64Be | FieldType::Uint192Be => Some("be"),
+            FieldType::U64Le | FieldType::Uint192Le => Some("le"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub ty: FieldType,
+}
+
+pub const fn field(name: &'static str, ty: FieldType) -> Field {
+    Field { name, ty }
+}
+
+/// Fixed length of the concatenated fields, `None` when any of them is variable
+fn fields_len(fields: &[Field]) -> Option<usize> {
+    fields.iter().map(|f| f.ty.encoded_len()).sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionDescription {
+    pub name: &'static str,
+    pub key: &'static [Field],
+    /// Empty for partitions storing everything in the key
+    pub value: &'static [Field],
+    /// Version of the value codec, 0 when values are not versioned
+    pub value_version: u8,
+    pub compression: Compression,
+    /// Large values live in blob files outside of the LSM tree
+    pub kv_separation: bool,
+}
+
+impl PartitionDescription {
+    /// Defaults matching `PartitionCreateOptions::default()`
+    pub const DEFAULT: Self = Self {
+        name: "",
+        key: &[],
+        value: &[],
+        value_version: 0,
+        compression: Compression::Lz4,
+        kv_separation: false,
+    };
+
+    pub fn key_len(&self) -> Option<usize> {
+        fields_len(self.key)
+    }
+
+    pub fn value_len(&self) -> Option<usize> {
+        fields_len(self.value)
+    }
+}
+
+pub trait DescribePartition {
+    const DESCRIPTION: PartitionDescription;
+}
+
+/// Opens the partition named by `P`'s description
+pub fn open_partition<P: DescribePartition>(
+    keyspace: &TxKeyspace,
+    options: PartitionCreateOptions,
+) -> fjall::Result<fjall::TxPartition> {
+    keyspace.open_partition(P::DESCRIPTION.name, options)
+}
+
+macro_rules! registered_partitions {
+    ($($partition:ty),* $(,)?) => {
+        /// Descriptions of every partition of the database
+        pub fn describe_all() -> Vec<PartitionDescription> {
+            vec![$(<$partition as DescribePartition>::DESCRIPTION),*]
+        }
+    };
+}
+
+registered_partitions![
+    MetadataPartition,
+    ProvenancePartition,
+    BlockCompactHeaderPartition,
+    DaaIndexPartition,
+    ChainMembershipPartition,
+    VerboseChainMembershipPartition,
+    ChainIndexPartition,
+    ChainIndexByHashPartition,
+    BlockRelationsPartition,
+    BlockGapsPartition,
+    GapHistoryPartition,
+    HandshakeBySenderPartition,
+    HandshakeByReceiverPartition,
+    TxIdToHandshakePartition,
+    PaymentBySenderPartition,
+    PaymentByReceiverPartition,
+    TxIdToPaymentPartition,
+    ContextualMessageBySenderPartition,
+    AcceptingBlockToTxIDPartition,
+    TxIDToAcceptancePartition,
+    AcceptanceHistoryPartition,
+    FinalizedTxPartition,
+    AcceptanceGapsPartition,
+    PendingSenderResolutionPartition,
+    UnknownTxPartition,
+    UnknownAcceptingDaaPartition,
+    SkipTxPartition,
+    SkipTxByBlockPartition,
+    OrphanPoolPartition,
+    CrashReportsPartition,
+    BlockMinerPartition,
+    MinerBlocksPartition,
+    OutpointPartition,
+    PendingSpendPartition,
+    PendingSpendByDaaPartition,
+    TxInputPartition,
+    BlockStatsPartition,
+    BlockTransactionsPartition,
+    ProcessedBlockPartition,
+    TokenOperationPartition,
+    AddressBalancePartition,
+    BlockBalanceDeltaPartition,
+    AddressBalanceDeltaPartition,
+    AggregatesPartition,
+    AcceptedAggregatesPartition,
+    BlockAcceptedCountPartition,
+    BlockRewardPartition,
+    SupplyDeltaPartition,
+    SupplyPendingPartition,
+    WebhookSubscriptionPartition,
+    WebhookAddressPartition,
+    WebhookPendingPartition,
+    WebhookOutboxPartition,
+    WebhookDeadLetterPartition,
+];
+
+/// Renders all descriptions as a JSON document
+pub fn describe_json() -> String {
+    let mut out = String::new();
+    _ = write!(
+        out,
+        "{{\"schema_version\":{SCHEMA_VERSION},\"indexer_version\":{},\"partitions\":[",
+        json_str(env!("CARGO_PKG_VERSION"))
+    );
+    for (i, description) in describe_all().iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_partition(&mut out, description);
+    }
+    out.push_str("]}");
+    out
+}
+
+fn write_partition(out: &mut String, d: &PartitionDescription) {
+    _ = write!(out, "{{\"name\":{},\"key\":", json_str(d.name));
+    write_layout(out, d.key);
+    out.push_str(",\"value\":");
+    write_layout(out, d.value);
+    _ = write!(
+        out,
+        ",\"value_version\":{},\"envelope\":{{\"compression\":\"{}\",\"checksum\":\"xxh3\",\"kv_separation\":{}}}}}",
+        d.value_version,
+        match d.compression {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+        },
+        d.kv_separation
+    );
+}
+
+fn write_layout(out: &mut String, fields: &[Field]) {
+    _ = write!(
+        out,
+        "{{\"fixed_len\":{},\"fields\":[",
+        json_opt(fields_len(fields))
+    );
+    let mut offset = Some(0);
+    for (i, f) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        _ = write!(
+            out,
+            "{{\"name\":{},\"type\":{},\"offset\":{},\"len\":{},\"endianness\":{}}}",
+            json_str(f.name),
+            json_str(f.ty.type_name()),
+            json_opt(offset),
+            json_opt(f.ty.encoded_len()),
+            f.ty.endianness()
+                .map_or_else(|| "null".to_string(), json_str)
+        );
+        offset = offset
+            .zip(f.ty.encoded_len())
+            .map(|(offset, len)| offset + len);
+    }
+    out.push_str("]}");
+}
+
+/// Renders `bytes` as `name=value` pairs following `fields`.
+/// Integers are printed as numbers, everything else as hex, bytes not covered by `fields` are appended
+pub fn render_fields(fields: &[Field], bytes: &[u8]) -> String {
+    let mut rendered = Vec::with_capacity(fields.len() + 1);
+    let mut rest = bytes;
+    for f in fields {
+        let len = f.ty.encoded_len().unwrap_or(rest.len());
+        if rest.len() < len {
+            break;
+        }
+        let (value, tail) = rest.split_at(len);
+        rest = tail;
+        let value = match f.ty {
+            FieldType::U8 => value[0].to_string(),
+            FieldType::U64Be => u64::from_be_bytes(value.try_into().unwrap()).to_string(),
+            FieldType::U64Le => u64::from_le_bytes(value.try_into().unwrap()).to_string(),
+            FieldType::I64Be => i64::from_be_bytes(value.try_into().unwrap()).to_string(),
+            _ => hex_preview(value),
+        };
+        rendered.push(format!("{}={value}", f.name));
+    }
+    if !rest.is_empty() {
+        rendered.push(format!("rest={}", hex_preview(rest)));
+    }
+    rendered.join(" ")
+}
+
+/// Hex of at most 64 bytes, longer input is truncated with its length noted
+fn hex_preview(bytes: &[u8]) -> String {
+    const MAX: usize = 64;
+    let mut out = String::with_capacity(bytes.len().min(MAX) * 2);
+    for b in bytes.iter().take(MAX) {
+        _ = write!(out, "{b:02x}");
+    }
+    if bytes.len() > MAX {
+        _ = write!(out, "..({} bytes)", bytes.len());
+    }
+    out
+}
+
+fn json_opt(value: Option<usize>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => _ = write!(out, "\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompactHeader;
+    use crate::database::headers::BlockGapKey;
+    use crate::database::messages::{
+        ContextualMessageBySenderKey, HandshakeKeyByReceiver, HandshakeKeyBySender,
+        PaymentKeyByReceiver, PaymentKeyBySender,
+    };
+    use crate::database::metadata::CursorValue;
+    use crate::database::processing::{AcceptanceTxKey, PendingResolutionKey};
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    #[test]
+    fn test_descriptions_match_codecs() {
+        let cases = [
+            (
+                MetadataPartition::DESCRIPTION.value_len(),
+                size_of::<CursorValue>(),
+            ),
+            (
+                BlockGapsPartition::DESCRIPTION.key_len(),
+                size_of::<BlockGapKey>(),
+            ),
+            (
+                AcceptanceGapsPartition::DESCRIPTION.key_len(),
+                size_of::<BlockGapKey>(),
+            ),
+            (
+                HandshakeBySenderPartition::DESCRIPTION.key_len(),
+                size_of::<HandshakeKeyBySender>(),
+            ),
+            (
+                HandshakeByReceiverPartition::DESCRIPTION.key_len(),
+                size_of::<HandshakeKeyByReceiver>(),
+            ),
+            (
+                PaymentBySenderPartition::DESCRIPTION.key_len(),
+                size_of::<PaymentKeyBySender>(),
+            ),
+            (
+                PaymentByReceiverPartition::DESCRIPTION.key_len(),
+                size_of::<PaymentKeyByReceiver>(),
+            ),
+            (
+                ContextualMessageBySenderPartition::DESCRIPTION.key_len(),
+                size_of::<ContextualMessageBySenderKey>(),
+            ),
+            (
+                TxIDToAcceptancePartition::DESCRIPTION.key_len(),
+                size_of::<AcceptanceTxKey>(),
+            ),
+            (
+                PendingSenderResolutionPartition::DESCRIPTION.key_len(),
+                size_of::<PendingResolutionKey>(),
+            ),
+        ];
+        for (described, actual) in cases {
+            assert_eq!(described, Some(actual));
+        }
+        // version + mode prefix in front of the compact record
+        assert_eq!(
+            BlockCompactHeaderPartition::DESCRIPTION.value[..4]
+                .iter()
+                .map(|f| f.ty.encoded_len().unwrap())
+                .sum::<usize>(),
+            2 + CompactHeader::ENCODED_LEN
+        );
+    }
+
+    #[test]
+    fn test_every_partition_is_registered() {
+        let all = describe_all();
+        let names = all.iter().map(|d| d.name).collect::<HashSet<_>>();
+        assert_eq!(names.len(), all.len(), "duplicate partition name");
+
+        // partitions may only be opened through `open_partition`, which requires a description,
+        // and every description has to be listed in `registered_partitions!`
+        let mut impls = 0;
+        visit_sources(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut |path, source| {
+                if path.ends_with("database/schema.rs") {
+                    return;
+                }
+                let compact = source.split_whitespace().collect::<String>();
+                assert!(
+                    !compact.contains(".open_partition(\""),
+                    "{} opens a partition by literal name, implement DescribePartition instead",
+                    path.display()
+                );
+                impls += source.matches("impl DescribePartition for").count();
+            },
+        );
+        assert_eq!(impls, all.len(), "a described partition is not registered");
+    }
+
+    fn visit_sources(dir: &Path, f: &mut impl FnMut(&Path, &str)) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                visit_sources(&path, f);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                f(&path, &std::fs::read_to_string(&path).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_fields() {
+        let fields = DaaIndexPartition::DESCRIPTION.key;
+        let mut key = 7u64.to_be_bytes().to_vec();
+        key.extend_from_slice(&[0xab; 32]);
+        assert_eq!(
+            render_fields(fields, &key),
+            format!("daa_score=7 block_hash={}", "ab".repeat(32))
+        );
+        // malformed entries keep the undecodable bytes visible
+        key.push(1);
+        assert!(render_fields(fields, &key).ends_with("rest=01"));
+        assert_eq!(render_fields(fields, &key[..4]), "rest=00000000");
+    }
+
+    #[test]
+    fn test_describe_json() {
+        let json = describe_json();
+        assert!(json.starts_with("{\"schema_version\":1,"));
+        assert!(json.contains("\"name\":\"block_compact_header\""));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json_str("a\"b\n"), "\"a\\\"b\\n\"");
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{Result, bail};
+use kaspa_consensus_core::tx::ScriptPublicKey;
+use kaspa_txscript::script_class::ScriptClass;
+
+/// Encoded length of an [`OutputScriptClass`]
+pub const SCRIPT_CLASS_LEN: usize = 3;
+
+/// Standard class of an output script, scripts of versions above the ones known to this build
+/// are kept apart instead of being taken for non-standard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputScriptClass {
+    NonStandard,
+    /// Pay to schnorr public key
+    PubKey,
+    PubKeyEcdsa,
+    ScriptHash,
+    /// Script version this build can't classify
+    Unknown(u16),
+}
+
+impl OutputScriptClass {
+    fn tag(&self) -> u8 {
+        match self {
+            OutputScriptClass::NonStandard => 0,
+            OutputScriptClass::PubKey => 1,
+            OutputScriptClass::PubKeyEcdsa => 2,
+            OutputScriptClass::ScriptHash => 3,
+            OutputScriptClass::Unknown(_) => 4,
+        }
+    }
+
+    /// `[tag (1 byte)] + [script version (2 bytes BE), unknown class only]`
+    pub fn encode(&self) -> [u8; SCRIPT_CLASS_LEN] {
+        let version = match self {
+            OutputScriptClass::Unknown(version) => *version,
+            _ => 0,
+        };
+        let mut bytes = [self.tag(), 0, 0];
+        bytes[1..].copy_from_slice(&version.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let Ok(bytes) = <[u8; SCRIPT_CLASS_LEN]>::try_from(bytes) else {
+            bail!("Invalid script class length");
+        };
+        Ok(match bytes[0] {
+            0 => OutputScriptClass::NonStandard,
+            1 => OutputScriptClass::PubKey,
+            2 => OutputScriptClass::PubKeyEcdsa,
+            3 => OutputScriptClass::ScriptHash,
+            4 => OutputScriptClass::Unknown(u16::from_be_bytes([bytes[1], bytes[2]])),
+            tag => bail!("Unknown script class tag: {tag}"),
+        })
+    }
+}
+
+impl std::fmt::Display for OutputScriptClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputScriptClass::NonStandard => f.write_str("nonstandard"),
+            OutputScriptClass::PubKey => f.write_str("pubkey"),
+            OutputScriptClass::PubKeyEcdsa => f.write_str("pubkeyecdsa"),
+            OutputScriptClass::ScriptHash => f.write_str("scripthash"),
+            OutputScriptClass::Unknown(version) => write!(f, "unknown(v{version})"),
+        }
+    }
+}
+
+/// Classifies the output script, never fails
+pub fn script_class(script_public_key: &ScriptPublicKey) -> OutputScriptClass {
+    let class = ScriptClass::from_script(script_public_key);
+    if script_public_key.version() > class.version() {
+        return OutputScriptClass::Unknown(script_public_key.version());
+    }
+    match class {
+        ScriptClass::NonStandard => OutputScriptClass::NonStandard,
+        ScriptClass::PubKey => OutputScriptClass::PubKey,
+        ScriptClass::PubKeyECDSA => OutputScriptClass::PubKeyEcdsa,
+        ScriptClass::ScriptHash => OutputScriptClass::ScriptHash,
+    }
+}
+
+/// Outputs counted per script class, unknown versions together
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScriptClassCounts {
+    pub non_standard: u64,
+    pub pubkey: u64,
+    pub pubkey_ecdsa: u64,
+    pub script_hash: u64,
+    pub unknown: u64,
+}
+
+impl ScriptClassCounts {
+    pub const ENCODED_LEN: usize = 5 * 8;
+
+    pub fn add(&mut self, class: OutputScriptClass) {
+        match class {
+            OutputScriptClass::NonStandard => self.non_standard += 1,
+            OutputScriptClass::PubKey => self.pubkey += 1,
+            OutputScriptClass::PubKeyEcdsa => self.pubkey_ecdsa += 1,
+            OutputScriptClass::ScriptHash => self.script_hash += 1,
+            OutputScriptClass::Unknown(_) => self.unknown += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.non_standard += other.non_standard;
+        self.pubkey += other.pubkey;
+        self.pubkey_ecdsa += other.pubkey_ecdsa;
+        self.script_hash += other.script_hash;
+        self.unknown += other.unknown;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.non_standard + self.pubkey + self.pubkey_ecdsa + self.script_hash + self.unknown
+    }
+
+    fn counters(&self) -> [u64; 5] {
+        [
+            self.non_standard,
+            self.pubkey,
+            self.pubkey_ecdsa,
+            self.script_hash,
+            self.unknown,
+        ]
+    }
+
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0u8; Self::ENCODED_LEN];
+        for (i, counter) in self.counters().into_iter().enumerate() {
+            bytes[i * 8..i * 8 + 8].copy_from_slice(&counter.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            bail!("Invalid script class counts length");
+        }
+        let counter = |i: usize| -> Result<u64> {
+            Ok(u64::from_be_bytes(bytes[i * 8..i * 8 + 8].try_into()?))
+        };
+        Ok(Self {
+            non_standard: counter(0)?,
+            pubkey: counter(1)?,
+            pubkey_ecdsa: counter(2)?,
+            script_hash: counter(3)?,
+            unknown: counter(4)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_addresses::{Address, Prefix, Version};
+    use kaspa_txscript::pay_to_address_script;
+
+    #[test]
+    fn test_classify_scripts() {
+        let script = |version, len| {
+            pay_to_address_script(&Address::new(Prefix::Mainnet, version, &vec![7; len]))
+        };
+        assert_eq!(
+            script_class(&script(Version::PubKey, 32)),
+            OutputScriptClass::PubKey
+        );
+        assert_eq!(
+            script_class(&script(Version::PubKeyECDSA, 33)),
+            OutputScriptClass::PubKeyEcdsa
+        );
+        assert_eq!(
+            script_class(&script(Version::ScriptHash, 32)),
+            OutputScriptClass::ScriptHash
+        );
+        assert_eq!(
+            script_class(&ScriptPublicKey::from_vec(0, vec![0x51])),
+            OutputScriptClass::NonStandard
+        );
+        // a standard looking script of a future version
+        let future = script(Version::PubKey, 32);
+        assert_eq!(
+            script_class(&ScriptPublicKey::from_vec(3, future.script().to_vec())),
+            OutputScriptClass::Unknown(3)
+        );
+    }
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        for class in [
+            OutputScriptClass::NonStandard,
+            OutputScriptClass::PubKey,
+            OutputScriptClass::PubKeyEcdsa,
+            OutputScriptClass::ScriptHash,
+            OutputScriptClass::Unknown(u16::MAX),
+        ] {
+            assert_eq!(OutputScriptClass::decode(&class.encode()).unwrap(), class);
+        }
+        assert!(OutputScriptClass::decode(&[9, 0, 0]).is_err());
+        assert!(OutputScriptClass::decode(&[1]).is_err());
+
+        let mut counts = ScriptClassCounts::default();
+        counts.add(OutputScriptClass::PubKey);
+        counts.add(OutputScriptClass::Unknown(1));
+        counts.add(OutputScriptClass::Unknown(2));
+        assert_eq!(ScriptClassCounts::decode(&counts.encode()).unwrap(), counts);
+        assert_eq!((counts.pubkey, counts.unknown, counts.total()), (1, 2, 3));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::database::metadata::MetadataPartition;
+use crate::database::provenance::Provenance;
+use crate::database::schema;
+use crate::historical_syncer::Cursor;
+use anyhow::{Result, bail};
+use fjall::{Config, PartitionCreateOptions, PersistMode, TxKeyspace};
+use std::path::Path;
+use tracing::info;
+
+/// Amount of entries written per batch while copying a partition into the snapshot
+const SNAPSHOT_BATCH_SIZE: usize = 16 * 1024;
+/// Schema description of the copied partitions, stored next to the partition files
+pub const SCHEMA_MANIFEST: &str = "schema.json";
+
+/// Summary of a point-in-time copy of the database
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotInfo {
+    pub partitions: usize,
+    pub entries: u64,
+    /// Block cursor at snapshot time, syncing of a restored copy resumes from it
+    pub latest_block_cursor: Option<Cursor>,
+    /// Accepting (sink) cursor at snapshot time
+    pub latest_accepting_block_cursor: Option<Cursor>,
+    /// Node sources and coverage of the copied data
+    pub provenance: Provenance,
+    /// JSON schema description, `None` for snapshots taken before it was embedded
+    pub schema: Option<String>,
+}
+
+/// Produces a consistent point-in-time copy of every partition into `path`.
+///
+/// All partitions are read through a single read transaction, so processors can keep
+/// writing while the copy is in progress. The metadata partition is copied as well,
+/// a restored database therefore resumes syncing from the cursors captured here.
+pub fn create_snapshot(keyspace: &TxKeyspace, path: impl AsRef<Path>) -> Result<SnapshotInfo> {
+    let path = path.as_ref();
+    if path.exists() && path.read_dir()?.next().is_some() {
+        bail!("Snapshot destination {} is not empty", path.display());
+    }
+
+    let rtx = keyspace.read_tx();
+    let metadata = MetadataPartition::new(keyspace)?;
+    let mut info = SnapshotInfo {
+        latest_block_cursor: metadata.get_latest_block_cursor_rtx(&rtx)?,
+        latest_accepting_block_cursor: metadata.get_latest_accepting_block_cursor_rtx(&rtx)?,
+        provenance: Provenance::collect(keyspace, &rtx)?,
+        ..Default::default()
+    };
+
+    let target = Config::new(path).open()?;
+    for name in keyspace.list_partitions() {
+        let source = keyspace.open_partition(&name, PartitionCreateOptions::default())?;
+        let destination = target.open_partition(&name, PartitionCreateOptions::default())?;
+
+        let mut batch = target.batch();
+        let mut batch_len = 0;
+        for kv in rtx.iter(&source) {
+            let (key, value) = kv?;
+            batch.insert(&destination, key, value);
+            batch_len += 1;
+            info.entries += 1;
+            if batch_len == SNAPSHOT_BATCH_SIZE {
+                std::mem::replace(&mut batch, target.batch()).commit()?;
+                batch_len = 0;
+            }
+        }
+        batch.commit()?;
+        info.partitions += 1;
+        info!(partition = %name, "Partition copied into snapshot");
+    }
+    target.persist(PersistMode::SyncAll)?;
+    let schema = schema::describe_json();
+    std::fs::write(path.join(SCHEMA_MANIFEST), &schema)?;
+    info.schema = Some(schema);
+
+    info!(
+        path = %path.display(),
+        partitions = info.partitions,
+        entries = info.entries,
+        "Snapshot created"
+    );
+    Ok(info)
+}
+
+/// Opens a snapshot previously produced by [`create_snapshot`] and reports its contents.
+/// No entry is written, but opening it as a keyspace writes the journal and version files of
+/// fjall into the snapshot directory, inspect a copy when the snapshot has to stay untouched.
+pub fn open_snapshot(path: impl AsRef<Path>) -> Result<(TxKeyspace, SnapshotInfo)> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        bail!("Snapshot {} does not exist", path.display());
+    }
+    let keyspace = Config::new(path).open_transactional()?;
+    let rtx = keyspace.read_tx();
+
+    let mut info = SnapshotInfo::default();
+    for name in keyspace.list_partitions() {
+        let partition = keyspace.open_partition(&name, PartitionCreateOptions::default())?;
+        info.entries += partition.inner().len()? as u64;
+        info.partitions += 1;
+    }
+    if keyspace.partition_exists("metadata") {
+        let metadata = MetadataPartition::new(&keyspace)?;
+        info.latest_block_cursor = metadata.get_latest_block_cursor_rtx(&rtx)?;
+        info.latest_accepting_block_cursor =
+            metadata.get_latest_accepting_block_cursor_rtx(&rtx)?;
+    }
+    if keyspace.partition_exists("provenance") {
+        info.provenance = Provenance::collect(&keyspace, &rtx)?;
+    }
+    let manifest = path.join(SCHEMA_MANIFEST);
+    if manifest.is_file() {
+        info.schema = Some(std::fs::read_to_string(manifest)?);
+    }
+    Ok((keyspace, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_math::Uint192;
+    use kaspa_rpc_core::RpcHash;
+
+    #[test]
+    fn test_create_and_reopen_snapshot() {
+        let dir =
+            std::env::temp_dir().join(format!("kasia-indexer-snapshot-{}", std::process::id()));
+        let keyspace = Config::new(dir.join("db"))
+            .temporary(true)
+            .open_transactional()
+            .unwrap();
+        let cursor = Cursor {
+            daa_score: 42,
+            blue_work: Uint192::from_u64(7),
+            hash: RpcHash::from_u64_word(1),
+        };
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        let blocks = keyspace
+            .open_partition("blocks", PartitionCreateOptions::default())
+            .unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        metadata.set_block_tip(&mut wtx, cursor).unwrap();
+        for i in 0..SNAPSHOT_BATCH_SIZE as u32 + 10 {
+            wtx.insert(&blocks, i.to_be_bytes(), b"block");
+        }
+        wtx.commit().unwrap().unwrap();
+
+        let path = dir.join("snapshot");
+        let created = create_snapshot(&keyspace, &path).unwrap();
+        assert_eq!(created.latest_block_cursor, Some(cursor));
+        assert!(created.entries > SNAPSHOT_BATCH_SIZE as u64);
+        // written after the snapshot, not part of it
+        blocks.insert(u32::MAX.to_be_bytes(), b"late").unwrap();
+        assert!(create_snapshot(&keyspace, &path).is_err());
+
+        let (snapshot, opened) = open_snapshot(&path).unwrap();
+        assert_eq!(
+            (opened.partitions, opened.entries),
+            (created.partitions, created.entries)
+        );
+        assert_eq!(opened.latest_block_cursor, Some(cursor));
+        assert_eq!(opened.schema, created.schema);
+        let copied = snapshot
+            .open_partition("blocks", PartitionCreateOptions::default())
+            .unwrap();
+        assert_eq!(copied.inner().len().unwrap(), SNAPSHOT_BATCH_SIZE + 10);
+        drop((snapshot, copied));
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert!(open_snapshot(&path).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use fjall::{PartitionCreateOptions, TxKeyspace};
+use std::fmt;
+
+/// Size statistics of a single partition.
+///
+/// Only counters kept by the storage engine are read, nothing is scanned,
+/// so collecting them is cheap enough to run continuously.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PartitionStats {
+    pub name: String,
+    /// Estimated from segment metadata, tombstones and overwritten keys are counted too
+    pub approximate_keys: u64,
+    /// Bytes occupied by flushed segments and blobs
+    pub disk_bytes: u64,
+    pub segments: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DatabaseStats {
+    pub partitions: Vec<PartitionStats>,
+    /// Bytes of all memtables not yet flushed to disk
+    pub write_buffer_bytes: u64,
+    /// Total bytes on disk, journals included
+    pub disk_bytes: u64,
+}
+
+impl DatabaseStats {
+    /// Partitions sorted by disk usage, largest first
+    pub fn largest(&self) -> Vec<&PartitionStats> {
+        let mut partitions = self.partitions.iter().collect::<Vec<_>>();
+        partitions.sort_by(|a, b| b.disk_bytes.cmp(&a.disk_bytes));
+        partitions
+    }
+}
+
+impl fmt::Display for DatabaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Database: {} bytes on disk, {} bytes in write buffers",
+            self.disk_bytes, self.write_buffer_bytes
+        )?;
+        for partition in self.largest() {
+            writeln!(
+                f,
+                "  {}: ~{} keys, {} bytes on disk, {} segments",
+                partition.name,
+                partition.approximate_keys,
+                partition.disk_bytes,
+                partition.segments
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects size statistics of every partition in the keyspace
+pub fn stats(keyspace: &TxKeyspace) -> Result<DatabaseStats> {
+    let mut partitions = Vec::new();
+    for name in keyspace.list_partitions() {
+        // returns the already opened handle, options are ignored for existing partitions
+        let partition = keyspace.open_partition(&name, PartitionCreateOptions::default())?;
+        let inner = partition.inner();
+        partitions.push(PartitionStats {
+            name: name.to_string(),
+            approximate_keys: inner.approximate_len() as u64,
+            disk_bytes: inner.disk_space(),
+            segments: inner.segment_count() as u64,
+        });
+    }
+    partitions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(DatabaseStats {
+        partitions,
+        write_buffer_bytes: keyspace.write_buffer_size(),
+        disk_bytes: keyspace.disk_space(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_largest_first() {
+        let partition = |name: &str, disk_bytes| PartitionStats {
+            name: name.to_string(),
+            disk_bytes,
+            ..Default::default()
+        };
+        let stats = DatabaseStats {
+            partitions: vec![partition("a", 10), partition("b", 30), partition("c", 20)],
+            ..Default::default()
+        };
+        let names = stats
+            .largest()
+            .into_iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b", "c", "a"]);
+    }
+}
@@ -0,0 +1,499 @@
+use crate::database::headers::BlockRelationsPartition;
+use crate::database::metadata::MetadataPartition;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, TxKeyspace, WriteTransaction};
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_COINBASE;
+use kaspa_consensus_core::tx::Transaction;
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransactionId};
+use tracing::debug;
+
+/// A thousand KAS, about what the node mints while a cross-check waits for acceptance
+pub const DEFAULT_SUPPLY_TOLERANCE_SOMPI: u64 = 1_000 * 100_000_000;
+
+/// Coinbase transaction of a block and the sum of its outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockReward {
+    pub coinbase_tx_id: RpcTransactionId,
+    pub reward: u64,
+}
+
+impl BlockReward {
+    /// None for blocks without a coinbase transaction
+    pub fn from_block(block: &RpcBlock) -> Result<Option<Self>> {
+        let Some(coinbase) = block
+            .transactions
+            .iter()
+            .find(|tx| tx.subnetwork_id == SUBNETWORK_ID_COINBASE)
+        else {
+            return Ok(None);
+        };
+        let coinbase_tx_id = match &coinbase.verbose_data {
+            Some(data) => data.transaction_id,
+            None => Transaction::try_from(coinbase.clone())?.id(),
+        };
+        Ok(Some(Self {
+            coinbase_tx_id,
+            reward: coinbase.outputs.iter().map(|output| output.value).sum(),
+        }))
+    }
+
+    /// `[coinbase_tx_id (32 bytes)] + [reward (8 bytes BE)]`
+    pub fn encode(&self) -> [u8; 40] {
+        let mut value = [0u8; 40];
+        value[..32].copy_from_slice(&self.coinbase_tx_id.as_bytes());
+        value[32..].copy_from_slice(&self.reward.to_be_bytes());
+        value
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self> {
+        if value.len() != 40 {
+            bail!("Invalid block reward length");
+        }
+        Ok(Self {
+            coinbase_tx_id: RpcTransactionId::from_slice(&value[..32]),
+            reward: u64::from_be_bytes(value[32..].try_into()?),
+        })
+    }
+}
+
+/// Partition holding the coinbase output sum of each block.
+///
+/// **Key:** [block_hash (32 bytes)]
+/// **Value:** [coinbase_tx_id (32 bytes)] + [reward (8 bytes BE)]
+///
+/// Pruned together with the headers.
+#[derive(Clone)]
+pub struct BlockRewardPartition(fjall::TxPartition);
+
+impl DescribePartition for BlockRewardPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "block_rewards",
+        key: &[field("block_hash", FieldType::Hash)],
+        value: &[
+            field("coinbase_tx_id", FieldType::Hash),
+            field("reward", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl BlockRewardPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        reward: &BlockReward,
+    ) {
+        wtx.insert(&self.0, block_hash.as_bytes(), reward.encode());
+    }
+
+    pub fn get_block_reward(&self, block_hash: RpcHash) -> Result<Option<BlockReward>> {
+        self.0
+            .get(block_hash.as_bytes())?
+            .map(|value| BlockReward::decode(&value))
+            .transpose()
+    }
+
+    pub fn get_block_reward_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        block_hash: RpcHash,
+    ) -> Result<Option<BlockReward>> {
+        rtx.get(&self.0, block_hash.as_bytes())?
+            .map(|value| BlockReward::decode(&value))
+            .transpose()
+    }
+
+    pub fn remove(&self, block_hash: &RpcHash) -> Result<()> {
+        self.0.remove(block_hash.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Partition keeping the reward each chain block added to the supply, until the block is
+/// finalized.
+///
+/// **Key:** [accepting block hash (32 bytes)]
+/// **Value:** [reward (8 bytes BE)]
+#[derive(Clone)]
+pub struct SupplyDeltaPartition(fjall::TxPartition);
+
+impl DescribePartition for SupplyDeltaPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "supply_deltas",
+        key: &[field("accepting_block_hash", FieldType::Hash)],
+        value: &[field("reward", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl SupplyDeltaPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn contains_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) -> Result<bool> {
+        Ok(wtx.get(&self.0, block_hash.as_bytes())?.is_some())
+    }
+
+    fn insert_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash, reward: u64) {
+        wtx.insert(&self.0, block_hash.as_bytes(), reward.to_be_bytes());
+    }
+
+    /// Removes and returns the reward of the block
+    fn take_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) -> Result<Option<u64>> {
+        let Some(value) = wtx.fetch_update(&self.0, block_hash.as_bytes(), |_| None)? else {
+            return Ok(None);
+        };
+        match value.as_ref().try_into() {
+            Ok(bytes) => Ok(Some(u64::from_be_bytes(bytes))),
+            Err(_) => bail!("Invalid supply delta length"),
+        }
+    }
+}
+
+/// Partition keeping the acceptance of chain blocks whose selected parent reward was not
+/// indexed yet, until the reward is read or the block is finalized.
+///
+/// **Key:** [accepting block hash (32 bytes)]
+/// **Value:** [accepted tx ids (32 bytes each)]
+#[derive(Clone)]
+pub struct SupplyPendingPartition(fjall::TxPartition);
+
+impl DescribePartition for SupplyPendingPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "supply_pending",
+        key: &[field("accepting_block_hash", FieldType::Hash)],
+        value: &[field("tx_ids", FieldType::Tail("hash[]"))],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl SupplyPendingPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn contains_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) -> Result<bool> {
+        Ok(wtx.get(&self.0, block_hash.as_bytes())?.is_some())
+    }
+
+    fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        block_hash: RpcHash,
+        accepted_tx_ids: &[RpcTransactionId],
+    ) {
+        let value = accepted_tx_ids
+            .iter()
+            .flat_map(|tx_id| tx_id.as_bytes())
+            .collect::<Vec<_>>();
+        wtx.insert(&self.0, block_hash.as_bytes(), value);
+    }
+
+    /// Removes the entry of the block, whether there was one
+    fn remove_wtx(&self, wtx: &mut WriteTransaction, block_hash: RpcHash) -> Result<bool> {
+        Ok(wtx
+            .fetch_update(&self.0, block_hash.as_bytes(), |_| None)?
+            .is_some())
+    }
+
+    /// Every pending block with the transactions it accepted
+    fn get_all_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+    ) -> Result<Vec<(RpcHash, Vec<RpcTransactionId>)>> {
+        wtx.iter(&self.0)
+            .map(|item| {
+                let (key, value) = item?;
+                if key.len() != 32 || value.len() % 32 != 0 {
+                    bail!("Invalid pending supply entry length");
+                }
+                let tx_ids = value
+                    .chunks_exact(32)
+                    .map(RpcTransactionId::from_slice)
+                    .collect();
+                Ok((RpcHash::from_slice(&key), tx_ids))
+            })
+            .collect()
+    }
+}
+
+/// Supply as tracked by the indexer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SupplyInfo {
+    /// Coinbase outputs accepted since the supply is tracked
+    pub accepted_rewards: u64,
+    /// Node circulating supply the tracking started from, none until the first cross-check
+    pub baseline: Option<u64>,
+}
+
+impl SupplyInfo {
+    pub fn circulating(&self) -> Option<u64> {
+        self.baseline
+            .map(|baseline| baseline.saturating_add(self.accepted_rewards))
+    }
+}
+
+/// Outcome of comparing the tracked supply with the one reported by the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupplyCheck {
+    pub indexed: u64,
+    pub node: u64,
+    /// Absolute difference of the two
+    pub divergence: u64,
+    /// The divergence exceeds the tolerance
+    pub diverged: bool,
+}
+
+/// Circulating supply counted from the accepted coinbase transactions.
+///
+/// The block processor records the coinbase output sum of every block it writes. A chain
+/// block accepts the coinbase of its selected parent, the virtual chain processor adds its sum
+/// to the accepted rewards and keeps it as the delta of the chain block until it is finalized,
+/// so a reorg removing the chain block takes it back. The reward is read outside the write
+/// transaction, so the block processor writing it doesn't conflict with the acceptance. A
+/// chain block accepted before its selected parent reward is indexed stays pending and is
+/// applied on a later notification, one finalized while pending is missed and the
+/// cross-check against the node reports the drift.
+#[derive(Clone)]
+pub struct Supply {
+    keyspace: TxKeyspace,
+    metadata_partition: MetadataPartition,
+    block_reward_partition: BlockRewardPartition,
+    block_relations_partition: BlockRelationsPartition,
+    supply_delta_partition: SupplyDeltaPartition,
+    supply_pending_partition: SupplyPendingPartition,
+}
+
+impl Supply {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self {
+            keyspace: keyspace.clone(),
+            metadata_partition: MetadataPartition::new(keyspace)?,
+            block_reward_partition: BlockRewardPartition::new(keyspace)?,
+            block_relations_partition: BlockRelationsPartition::new(keyspace)?,
+            supply_delta_partition: SupplyDeltaPartition::new(keyspace)?,
+            supply_pending_partition: SupplyPendingPartition::new(keyspace)?,
+        })
+    }
+
+    /// Records the coinbase of a written block, rewriting it is harmless
+    pub fn add_block_wtx(&self, wtx: &mut WriteTransaction, block: &RpcBlock) -> Result<()> {
+        if let Some(reward) = BlockReward::from_block(block)? {
+            self.block_reward_partition
+                .insert_wtx(wtx, block.header.hash, &reward);
+        }
+        Ok(())
+    }
+
+    /// Coinbase output sum of the block, none if it was not indexed or already pruned
+    pub fn get_block_reward(&self, block_hash: RpcHash) -> Result<Option<u64>> {
+        Ok(self
+            .block_reward_partition
+            .get_block_reward(block_hash)?
+            .map(|reward| reward.reward))
+    }
+
+    pub fn get_supply(&self) -> Result<SupplyInfo> {
+        let rtx = self.keyspace.read_tx();
+        Ok(SupplyInfo {
+            accepted_rewards: self.metadata_partition.get_accepted_rewards_rtx(&rtx)?,
+            baseline: self.metadata_partition.get_supply_baseline_rtx(&rtx)?,
+        })
+    }
+
+    /// Adds the reward of the coinbase the chain block accepted, once per block. A reward not
+    /// in `rtx` yet leaves the block pending, see [`Self::apply_pending_wtx`]
+    pub fn apply_accepted_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        rtx: &ReadTransaction,
+        accepting_block_hash: RpcHash,
+        accepted_tx_ids: &[RpcTransactionId],
+    ) -> Result<()> {
+        if self
+            .supply_delta_partition
+            .contains_wtx(wtx, accepting_block_hash)?
+            || self
+                .supply_pending_partition
+                .contains_wtx(wtx, accepting_block_hash)?
+        {
+            return Ok(());
+        }
+        if !self.accept_wtx(wtx, rtx, accepting_block_hash, accepted_tx_ids)? {
+            debug!(%accepting_block_hash, "Reward accepted by chain block is not indexed yet");
+            self.supply_pending_partition
+                .insert_wtx(wtx, accepting_block_hash, accepted_tx_ids);
+        }
+        Ok(())
+    }
+
+    /// Applies the pending chain blocks whose accepted reward is in `rtx` by now
+    pub fn apply_pending_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        rtx: &ReadTransaction,
+    ) -> Result<()> {
+        for (accepting_block_hash, accepted_tx_ids) in
+            self.supply_pending_partition.get_all_wtx(wtx)?
+        {
+            if self.accept_wtx(wtx, rtx, accepting_block_hash, &accepted_tx_ids)? {
+                debug!(%accepting_block_hash, "Applied pending accepted reward");
+                self.supply_pending_partition
+                    .remove_wtx(wtx, accepting_block_hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the accepted reward and keeps it as the delta of the chain block, false if the
+    /// block or the reward of its selected parent is not in `rtx`
+    fn accept_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        rtx: &ReadTransaction,
+        accepting_block_hash: RpcHash,
+        accepted_tx_ids: &[RpcTransactionId],
+    ) -> Result<bool> {
+        let reward = match self
+            .block_relations_partition
+            .get_block_relations_rtx(rtx, accepting_block_hash)?
+        {
+            Some(relations) => self
+                .block_reward_partition
+                .get_block_reward_rtx(rtx, relations.selected_parent)?,
+            None => None,
+        };
+        let Some(reward) = reward else {
+            return Ok(false);
+        };
+        let accepted = if accepted_tx_ids.contains(&reward.coinbase_tx_id) {
+            reward.reward
+        } else {
+            0
+        };
+        self.supply_delta_partition
+            .insert_wtx(wtx, accepting_block_hash, accepted);
+        let rewards = self.metadata_partition.get_accepted_rewards_wtx(wtx)?;
+        self.metadata_partition
+            .set_accepted_rewards_wtx(wtx, rewards.saturating_add(accepted));
+        Ok(true)
+    }
+
+    /// Takes back the reward the chain block removed by a reorg accepted
+    pub fn revert_accepted_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+    ) -> Result<()> {
+        let Some(reward) = self
+            .supply_delta_partition
+            .take_wtx(wtx, accepting_block_hash)?
+        else {
+            self.supply_pending_partition
+                .remove_wtx(wtx, accepting_block_hash)?;
+            return Ok(());
+        };
+        let rewards = self.metadata_partition.get_accepted_rewards_wtx(wtx)?;
+        self.metadata_partition
+            .set_accepted_rewards_wtx(wtx, rewards.saturating_sub(reward));
+        Ok(())
+    }
+
+    /// Drops the delta of the finalized chain block, its acceptance can't be reverted anymore.
+    /// A block still pending gives up on its reward
+    pub fn finalize_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+    ) -> Result<()> {
+        self.supply_delta_partition
+            .take_wtx(wtx, accepting_block_hash)?;
+        if self
+            .supply_pending_partition
+            .remove_wtx(wtx, accepting_block_hash)?
+        {
+            debug!(%accepting_block_hash, "Finalized chain block accepted a reward never indexed");
+        }
+        Ok(())
+    }
+
+    /// Compares the tracked supply with `node_circulating`. The first check takes the supply
+    /// the node minted before the tracking started as baseline
+    pub fn cross_check(&self, node_circulating: u64, tolerance: u64) -> Result<SupplyCheck> {
+        let supply = self.get_supply()?;
+        let baseline = match supply.baseline {
+            Some(baseline) => baseline,
+            None => {
+                let baseline = node_circulating.saturating_sub(supply.accepted_rewards);
+                self.metadata_partition.set_supply_baseline(baseline)?;
+                baseline
+            }
+        };
+        let indexed = baseline.saturating_add(supply.accepted_rewards);
+        let divergence = indexed.abs_diff(node_circulating);
+        Ok(SupplyCheck {
+            indexed,
+            node: node_circulating,
+            divergence,
+            diverged: divergence > tolerance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cross_check_raises_divergence() {
+        let keyspace = crate::database::test_keyspace("supply-check");
+        let supply = Supply::new(&keyspace).unwrap();
+        let accept = |rewards| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            supply
+                .metadata_partition
+                .set_accepted_rewards_wtx(&mut wtx, rewards);
+            wtx.commit().unwrap().unwrap();
+        };
+        accept(500);
+
+        // the first check starts from the node supply
+        let check = supply.cross_check(10_500, 100).unwrap();
+        assert_eq!(
+            (check.indexed, check.divergence, check.diverged),
+            (10_500, 0, false)
+        );
+        assert_eq!(supply.get_supply().unwrap().circulating(), Some(10_500));
+
+        // the node minted 80 more than accepted so far, within the tolerance
+        accept(600);
+        let check = supply.cross_check(10_680, 100).unwrap();
+        assert_eq!(
+            (check.indexed, check.divergence, check.diverged),
+            (10_600, 80, false)
+        );
+        // missed rewards
+        let check = supply.cross_check(10_800, 100).unwrap();
+        assert_eq!((check.divergence, check.diverged), (200, true));
+        // counted too much
+        accept(900);
+        let check = supply.cross_check(10_700, 100).unwrap();
+        assert_eq!((check.divergence, check.diverged), (200, true));
+    }
+}
@@ -0,0 +1,283 @@
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use crate::protocols::kasplex::{EnvelopeError, MAX_TICK_LEN, TokenOperation, TokenOperationKind};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, WriteTransaction};
+use kaspa_addresses::Address;
+use kaspa_rpc_core::{RpcHash, RpcTransactionId};
+use std::ops::Range;
+
+const VALID: u8 = 0;
+const KEY_LEN: usize = MAX_TICK_LEN + 8 + 32;
+
+/// Partition keeping the KRC-20 operations found in transactions, valid or not.
+///
+/// **Key:** [tick (6 bytes, upper case, zero padded)] + [daa_score (8 bytes BE)] + [tx_id (32 bytes)]
+/// **Value:** [status (1 byte)] + valid only: [kind (1 byte)] + [has_amount (1 byte)] +
+/// [amount (16 bytes BE)] + [to address (utf8)]
+///
+/// Status is zero for valid operations and the `EnvelopeError` otherwise. Envelopes broken
+/// before their tick was read are stored under the empty tick.
+#[derive(Clone)]
+pub struct TokenOperationPartition(fjall::TxPartition);
+
+/// Stored operation of a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedTokenOperation {
+    pub tick: String,
+    pub daa_score: u64,
+    pub tx_id: RpcTransactionId,
+    pub operation: Result<TokenOperation, EnvelopeError>,
+}
+
+impl DescribePartition for TokenOperationPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "token_operations",
+        key: &[
+            field("tick", FieldType::Bytes(MAX_TICK_LEN)),
+            field("daa_score", FieldType::U64Be),
+            field("tx_id", FieldType::Hash),
+        ],
+        value: &[
+            field("status", FieldType::U8),
+            field("kind", FieldType::U8),
+            field("has_amount", FieldType::U8),
+            field("amount", FieldType::Bytes(16)),
+            field("to_address", FieldType::Tail("utf8")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl TokenOperationPartition {
+    pub fn new(keyspace: &fjall::TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    pub fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        tick: &str,
+        daa_score: u64,
+        tx_id: RpcTransactionId,
+        operation: &Result<TokenOperation, EnvelopeError>,
+    ) -> Result<()> {
+        let mut value = Vec::with_capacity(1 + 1 + 1 + 16 + 80);
+        match operation {
+            Ok(operation) => {
+                value.push(VALID);
+                value.push(operation.kind as u8);
+                value.push(operation.amount.is_some() as u8);
+                value.extend_from_slice(&operation.amount.unwrap_or_default().to_be_bytes());
+                if let Some(to) = &operation.to {
+                    value.extend_from_slice(to.to_string().as_bytes());
+                }
+            }
+            Err(error) => value.push(*error as u8),
+        }
+        wtx.insert(&self.0, key(tick, daa_score, tx_id)?, value);
+        Ok(())
+    }
+
+    /// Operations of `tick` from `from_daa` on, oldest first
+    pub fn get_token_operations(
+        &self,
+        tick: &str,
+        from_daa: u64,
+        limit: usize,
+    ) -> Result<Vec<IndexedTokenOperation>> {
+        let start = key(tick, from_daa, RpcHash::default())?;
+        let prefix = start[..MAX_TICK_LEN].to_vec();
+        self.0
+            .inner()
+            .range(start..)
+            .take_while(|kv| {
+                kv.as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&prefix))
+            })
+            .take(limit)
+            .map(|kv| {
+                let (key, value) = kv?;
+                decode(&key, &value)
+            })
+            .collect()
+    }
+
+    pub fn get_token_operations_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        tick: &str,
+        from_daa: u64,
+        limit: usize,
+    ) -> Result<Vec<IndexedTokenOperation>> {
+        let start = key(tick, from_daa, RpcHash::default())?;
+        let prefix = start[..MAX_TICK_LEN].to_vec();
+        rtx.range(&self.0, start..)
+            .take_while(|kv| {
+                kv.as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&prefix))
+            })
+            .take(limit)
+            .map(|kv| {
+                let (key, value) = kv?;
+                decode(&key, &value)
+            })
+            .collect()
+    }
+
+    /// Removes the operations of every tick within `daa_range` by a full scan, returns the
+    /// amount removed
+    pub fn remove_daa_range_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        daa_range: Range<u64>,
+    ) -> Result<usize> {
+        let mut removed = Vec::new();
+        for item in wtx.iter(&self.0) {
+            let (key, _) = item?;
+            if daa_range.contains(&key_daa_score(&key)?) {
+                removed.push(key);
+            }
+        }
+        let count = removed.len();
+        for key in removed {
+            wtx.remove(&self.0, key);
+        }
+        Ok(count)
+    }
+
+    /// Operations of every tick within `daa_range`, by a full scan
+    pub fn count_daa_range_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        daa_range: Range<u64>,
+    ) -> Result<usize> {
+        rtx.iter(&self.0).try_fold(0, |count, item| {
+            let (key, _) = item?;
+            Ok(count + daa_range.contains(&key_daa_score(&key)?) as usize)
+        })
+    }
+}
+
+fn key_daa_score(key: &[u8]) -> Result<u64> {
+    if key.len() != KEY_LEN {
+        bail!("Invalid token operation key length");
+    }
+    Ok(u64::from_be_bytes(
+        key[MAX_TICK_LEN..MAX_TICK_LEN + 8].try_into()?,
+    ))
+}
+
+fn key(tick: &str, daa_score: u64, tx_id: RpcTransactionId) -> Result<[u8; KEY_LEN]> {
+    let tick = tick.to_ascii_uppercase();
+    if tick.len() > MAX_TICK_LEN {
+        bail!("Tick longer than {MAX_TICK_LEN} bytes: {tick}");
+    }
+    let mut key = [0u8; KEY_LEN];
+    key[..tick.len()].copy_from_slice(tick.as_bytes());
+    key[MAX_TICK_LEN..MAX_TICK_LEN + 8].copy_from_slice(&daa_score.to_be_bytes());
+    key[MAX_TICK_LEN + 8..].copy_from_slice(&tx_id.as_bytes());
+    Ok(key)
+}
+
+fn decode(key: &[u8], value: &[u8]) -> Result<IndexedTokenOperation> {
+    if key.len() != KEY_LEN {
+        bail!("Invalid token operation key length");
+    }
+    let tick = &key[..MAX_TICK_LEN];
+    let tick = &tick[..tick.iter().position(|b| *b == 0).unwrap_or(MAX_TICK_LEN)];
+    let operation = match value {
+        [VALID, kind, has_amount, rest @ ..] if rest.len() >= 16 => Ok(TokenOperation {
+            kind: TokenOperationKind::try_from(*kind)?,
+            amount: match *has_amount {
+                0 => None,
+                _ => Some(u128::from_be_bytes(rest[..16].try_into()?)),
+            },
+            to: match &rest[16..] {
+                [] => None,
+                to => Some(Address::try_from(std::str::from_utf8(to)?)?),
+            },
+        }),
+        [error] => Err(EnvelopeError::try_from(*error)?),
+        _ => bail!("Invalid token operation value"),
+    };
+    Ok(IndexedTokenOperation {
+        tick: String::from_utf8(tick.to_vec())?,
+        daa_score: u64::from_be_bytes(key[MAX_TICK_LEN..MAX_TICK_LEN + 8].try_into()?),
+        tx_id: RpcHash::from_slice(&key[MAX_TICK_LEN + 8..]),
+        operation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operations_by_tick() {
+        let keyspace = crate::database::test_keyspace("token-operations");
+        let partition = TokenOperationPartition::new(&keyspace).unwrap();
+        let to = Address::new(
+            kaspa_addresses::Prefix::Mainnet,
+            kaspa_addresses::Version::PubKey,
+            &[3; 32],
+        );
+        let transfer = Ok(TokenOperation {
+            kind: TokenOperationKind::Transfer,
+            amount: Some(u128::MAX),
+            to: Some(to),
+        });
+        let mint = Ok(TokenOperation {
+            kind: TokenOperationKind::Mint,
+            amount: None,
+            to: None,
+        });
+
+        let mut wtx = keyspace.write_tx().unwrap();
+        for (tick, daa_score, i, operation) in [
+            ("KASP", 10, 1, &mint),
+            ("KASP", 20, 2, &transfer),
+            ("kasp", 30, 3, &Err(EnvelopeError::InvalidAmount)),
+            ("KASPA", 15, 4, &mint),
+            ("", 15, 5, &Err(EnvelopeError::Truncated)),
+        ] {
+            partition
+                .insert_wtx(
+                    &mut wtx,
+                    tick,
+                    daa_score,
+                    RpcHash::from_u64_word(i),
+                    operation,
+                )
+                .unwrap();
+        }
+        wtx.commit().unwrap().unwrap();
+
+        let operations = partition.get_token_operations("kasp", 15, 10).unwrap();
+        assert_eq!(
+            operations,
+            vec![
+                IndexedTokenOperation {
+                    tick: "KASP".to_string(),
+                    daa_score: 20,
+                    tx_id: RpcHash::from_u64_word(2),
+                    operation: transfer,
+                },
+                IndexedTokenOperation {
+                    tick: "KASP".to_string(),
+                    daa_score: 30,
+                    tx_id: RpcHash::from_u64_word(3),
+                    operation: Err(EnvelopeError::InvalidAmount),
+                },
+            ]
+        );
+        let limited = partition.get_token_operations("KASP", 0, 1).unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].operation, mint);
+        let broken = partition.get_token_operations("", 0, 10).unwrap();
+        assert_eq!(broken.len(), 1);
+        assert_eq!(broken[0].operation, Err(EnvelopeError::Truncated));
+    }
+}
@@ -1,5 +1,27 @@
+use fjall::WriteTransaction;
 use std::borrow::Borrow;
 use std::cmp::Ordering;
+use std::ops::RangeBounds;
+
+/// Removes every key of `partition` within `range`, returns the amount of removed entries.
+///
+/// fjall has no range tombstones, so this scans the range and removes key by key
+/// inside the write transaction. Callers still benefit from a single range scan
+/// instead of one lookup per key.
+pub fn delete_range_wtx<K: AsRef<[u8]>, R: RangeBounds<K>>(
+    wtx: &mut WriteTransaction,
+    partition: &fjall::TxPartition,
+    range: R,
+) -> anyhow::Result<usize> {
+    let keys = wtx
+        .range(partition, range)
+        .map(|kv| kv.map(|(key, _)| key))
+        .collect::<Result<Vec<_>, _>>()?;
+    for key in &keys {
+        wtx.remove(partition, key.clone());
+    }
+    Ok(keys.len())
+}
 
 /// Two-pointer intersection iterator that finds elements from the first iterator
 /// that have keys present in the second iterator
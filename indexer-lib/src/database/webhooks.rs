@@ -0,0 +1,964 @@
+use crate::database::headers::{ChainIndexByHashPartition, ChainIndexPartition};
+use crate::database::messages::AddressPayload;
+use crate::database::metadata::MetadataPartition;
+use crate::database::processing::OutpointPartition;
+use crate::database::schema::{self, DescribePartition, FieldType, PartitionDescription, field};
+use anyhow::{Result, bail};
+use fjall::{PartitionCreateOptions, ReadTransaction, TxKeyspace, WriteTransaction};
+use kaspa_rpc_core::{RpcAddress, RpcHash, RpcTransactionId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 10;
+pub const DEFAULT_WEBHOOK_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+pub const DEFAULT_WEBHOOK_MAX_BACKOFF: Duration = Duration::from_secs(3600);
+pub const DEFAULT_WEBHOOK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type AddressKey = [u8; 34];
+
+fn address_key(address: &AddressPayload) -> AddressKey {
+    bytemuck::cast(*address)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookSubscription {
+    pub id: u64,
+    pub address: String,
+    pub url: String,
+    /// Confirmations an accepted output needs before it is delivered, the accepting chain
+    /// block counts as the first
+    pub min_confirmations: u64,
+    /// Key of the delivery signatures
+    pub secret: String,
+}
+
+impl WebhookSubscription {
+    fn encode_value(&self) -> Vec<u8> {
+        let mut value = self.min_confirmations.to_be_bytes().to_vec();
+        value.extend_from_slice(
+            format!("{}\n{}\n{}", self.address, self.url, self.secret).as_bytes(),
+        );
+        value
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<Self> {
+        if key.len() != 8 || value.len() < 8 {
+            bail!("Invalid webhook subscription length");
+        }
+        let mut lines = std::str::from_utf8(&value[8..])?.splitn(3, '\n');
+        let (Some(address), Some(url), Some(secret)) = (lines.next(), lines.next(), lines.next())
+        else {
+            bail!("Invalid webhook subscription value");
+        };
+        Ok(Self {
+            id: u64::from_be_bytes(key.try_into()?),
+            address: address.to_string(),
+            url: url.to_string(),
+            min_confirmations: u64::from_be_bytes(value[..8].try_into()?),
+            secret: secret.to_string(),
+        })
+    }
+}
+
+/// Partition keeping the webhook subscriptions.
+///
+/// **Key:** [subscription id (8 bytes BE)]
+/// **Value:** [min_confirmations (8 bytes BE)] + [address, url and secret (utf8 lines)]
+#[derive(Clone)]
+pub struct WebhookSubscriptionPartition(fjall::TxPartition);
+
+impl DescribePartition for WebhookSubscriptionPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "webhook_subscriptions",
+        key: &[field("subscription_id", FieldType::U64Be)],
+        value: &[
+            field("min_confirmations", FieldType::U64Be),
+            field(
+                "subscription",
+                FieldType::Tail("utf8_lines(address,url,secret)"),
+            ),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl WebhookSubscriptionPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn insert_wtx(&self, wtx: &mut WriteTransaction, subscription: &WebhookSubscription) {
+        wtx.insert(
+            &self.0,
+            subscription.id.to_be_bytes(),
+            subscription.encode_value(),
+        );
+    }
+
+    fn get_wtx(&self, wtx: &mut WriteTransaction, id: u64) -> Result<Option<WebhookSubscription>> {
+        wtx.get(&self.0, id.to_be_bytes())?
+            .map(|value| WebhookSubscription::decode(&id.to_be_bytes(), &value))
+            .transpose()
+    }
+
+    pub fn get_rtx(&self, rtx: &ReadTransaction, id: u64) -> Result<Option<WebhookSubscription>> {
+        rtx.get(&self.0, id.to_be_bytes())?
+            .map(|value| WebhookSubscription::decode(&id.to_be_bytes(), &value))
+            .transpose()
+    }
+
+    fn remove_wtx(&self, wtx: &mut WriteTransaction, id: u64) {
+        wtx.remove(&self.0, id.to_be_bytes());
+    }
+
+    pub fn iter_rtx(
+        &self,
+        rtx: &ReadTransaction,
+    ) -> impl Iterator<Item = Result<WebhookSubscription>> + '_ {
+        rtx.iter(&self.0).map(|item| {
+            let (key, value) = item?;
+            WebhookSubscription::decode(&key, &value)
+        })
+    }
+}
+
+/// Partition keeping the webhook subscriptions by watched address.
+///
+/// **Key:** [address payload (34 bytes)] + [subscription id (8 bytes BE)]
+/// **Value:** [min_confirmations (8 bytes BE)]
+#[derive(Clone)]
+pub struct WebhookAddressPartition(fjall::TxPartition);
+
+impl DescribePartition for WebhookAddressPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "webhook_subscriptions_by_address",
+        key: &[
+            field("address", FieldType::AddressPayload),
+            field("subscription_id", FieldType::U64Be),
+        ],
+        value: &[field("min_confirmations", FieldType::U64Be)],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl WebhookAddressPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn key(address: AddressKey, id: u64) -> [u8; 34 + 8] {
+        let mut key = [0u8; 34 + 8];
+        key[..34].copy_from_slice(&address);
+        key[34..].copy_from_slice(&id.to_be_bytes());
+        key
+    }
+
+    fn insert_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        address: AddressKey,
+        id: u64,
+        min_confirmations: u64,
+    ) {
+        wtx.insert(
+            &self.0,
+            Self::key(address, id),
+            min_confirmations.to_be_bytes(),
+        );
+    }
+
+    fn remove_wtx(&self, wtx: &mut WriteTransaction, address: AddressKey, id: u64) {
+        wtx.remove(&self.0, Self::key(address, id));
+    }
+
+    fn is_empty_wtx(&self, wtx: &mut WriteTransaction) -> Result<bool> {
+        Ok(wtx.iter(&self.0).next().transpose()?.is_none())
+    }
+
+    /// Subscriptions watching the address as (id, min_confirmations)
+    fn get_wtx(&self, wtx: &mut WriteTransaction, address: AddressKey) -> Result<Vec<(u64, u64)>> {
+        wtx.prefix(&self.0, address)
+            .map(|item| {
+                let (key, value) = item?;
+                if key.len() != 34 + 8 || value.len() != 8 {
+                    bail!("Invalid webhook address entry length");
+                }
+                Ok((
+                    u64::from_be_bytes(key[34..].try_into()?),
+                    u64::from_be_bytes(value[..].try_into()?),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Accepted output of a watched address, delivered once to its subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeliveryId {
+    pub subscription_id: u64,
+    pub tx_id: RpcTransactionId,
+    pub output_index: u32,
+}
+
+impl DeliveryId {
+    const ENCODED_LEN: usize = 8 + 32 + 4;
+
+    fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0u8; Self::ENCODED_LEN];
+        bytes[..8].copy_from_slice(&self.subscription_id.to_be_bytes());
+        bytes[8..40].copy_from_slice(&self.tx_id.as_bytes());
+        bytes[40..].copy_from_slice(&self.output_index.to_be_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::ENCODED_LEN {
+            bail!("Invalid webhook delivery id length");
+        }
+        Ok(Self {
+            subscription_id: u64::from_be_bytes(bytes[..8].try_into()?),
+            tx_id: RpcTransactionId::from_slice(&bytes[8..40]),
+            output_index: u32::from_be_bytes(bytes[40..].try_into()?),
+        })
+    }
+}
+
+/// `{subscription id}:{tx id}:{output index}`, the idempotency key of the deliveries
+impl fmt::Display for DeliveryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.subscription_id, self.tx_id, self.output_index
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PendingOutput {
+    /// Chain index at which the output has the confirmations of its subscription
+    due_index: u64,
+    accepting_block_hash: RpcHash,
+    id: DeliveryId,
+    accepting_index: u64,
+    amount: u64,
+}
+
+/// Partition keeping the accepted outputs of watched addresses until they are confirmed deep
+/// enough.
+///
+/// **Key:** [due chain index (8 bytes BE)] + [accepting block hash (32 bytes)] +
+/// [subscription id (8 bytes BE)] + [tx_id (32 bytes)] + [output index (4 bytes BE)]
+/// **Value:** [accepting chain index (8 bytes BE)] + [amount (8 bytes BE)]
+#[derive(Clone)]
+pub struct WebhookPendingPartition(fjall::TxPartition);
+
+impl DescribePartition for WebhookPendingPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "webhook_pending",
+        key: &[
+            field("due_chain_index", FieldType::U64Be),
+            field("accepting_block_hash", FieldType::Hash),
+            field("subscription_id", FieldType::U64Be),
+            field("tx_id", FieldType::Hash),
+            field("output_index", FieldType::Bytes(4)),
+        ],
+        value: &[
+            field("accepting_chain_index", FieldType::U64Be),
+            field("amount", FieldType::U64Be),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl WebhookPendingPartition {
+    const KEY_LEN: usize = 8 + 32 + DeliveryId::ENCODED_LEN;
+
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn insert_wtx(&self, wtx: &mut WriteTransaction, pending: &PendingOutput) {
+        let mut key = [0u8; Self::KEY_LEN];
+        key[..8].copy_from_slice(&pending.due_index.to_be_bytes());
+        key[8..40].copy_from_slice(&pending.accepting_block_hash.as_bytes());
+        key[40..].copy_from_slice(&pending.id.encode());
+        let mut value = [0u8; 16];
+        value[..8].copy_from_slice(&pending.accepting_index.to_be_bytes());
+        value[8..].copy_from_slice(&pending.amount.to_be_bytes());
+        wtx.insert(&self.0, key, value);
+    }
+
+    /// Removes and returns the outputs due at chain index `tip` or below
+    fn take_due_wtx(&self, wtx: &mut WriteTransaction, tip: u64) -> Result<Vec<PendingOutput>> {
+        let Some(end) = tip.checked_add(1) else {
+            bail!("Chain index overflow");
+        };
+        let mut due = Vec::new();
+        let mut taken = Vec::new();
+        for item in wtx.range(&self.0, ..end.to_be_bytes()) {
+            let (key, value) = item?;
+            if key.len() != Self::KEY_LEN || value.len() != 16 {
+                bail!("Invalid pending webhook length");
+            }
+            due.push(PendingOutput {
+                due_index: u64::from_be_bytes(key[..8].try_into()?),
+                accepting_block_hash: RpcHash::from_slice(&key[8..40]),
+                id: DeliveryId::decode(&key[40..])?,
+                accepting_index: u64::from_be_bytes(value[..8].try_into()?),
+                amount: u64::from_be_bytes(value[8..].try_into()?),
+            });
+            taken.push(key);
+        }
+        for key in taken {
+            wtx.remove(&self.0, key);
+        }
+        Ok(due)
+    }
+}
+
+/// Body of a webhook delivery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    /// Same for every delivery of the output, receivers dedupe on it
+    pub idempotency_key: String,
+    pub subscription_id: u64,
+    pub address: String,
+    pub tx_id: String,
+    pub output_index: u32,
+    /// Sompi
+    pub amount: u64,
+    pub accepting_block_hash: String,
+    /// When the delivery was queued
+    pub confirmations: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookDelivery {
+    pub id: DeliveryId,
+    pub next_attempt_ms: u64,
+    /// Failed attempts so far
+    pub attempts: u32,
+    /// [`WebhookPayload`] as JSON
+    pub payload: String,
+}
+
+/// Partition keeping the queued webhook deliveries by the time of their next attempt.
+///
+/// **Key:** [next attempt unix ms (8 bytes BE)] + [subscription id (8 bytes BE)] +
+/// [tx_id (32 bytes)] + [output index (4 bytes BE)]
+/// **Value:** [attempts (4 bytes BE)] + [payload (json)]
+#[derive(Clone)]
+pub struct WebhookOutboxPartition(fjall::TxPartition);
+
+impl DescribePartition for WebhookOutboxPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "webhook_outbox",
+        key: &[
+            field("next_attempt_ms", FieldType::U64Be),
+            field("subscription_id", FieldType::U64Be),
+            field("tx_id", FieldType::Hash),
+            field("output_index", FieldType::Bytes(4)),
+        ],
+        value: &[
+            field("attempts", FieldType::Bytes(4)),
+            field("payload", FieldType::Tail("json")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl WebhookOutboxPartition {
+    const KEY_LEN: usize = 8 + DeliveryId::ENCODED_LEN;
+
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn key(delivery: &WebhookDelivery) -> [u8; Self::KEY_LEN] {
+        let mut key = [0u8; Self::KEY_LEN];
+        key[..8].copy_from_slice(&delivery.next_attempt_ms.to_be_bytes());
+        key[8..].copy_from_slice(&delivery.id.encode());
+        key
+    }
+
+    fn insert_wtx(&self, wtx: &mut WriteTransaction, delivery: &WebhookDelivery) {
+        let mut value = delivery.attempts.to_be_bytes().to_vec();
+        value.extend_from_slice(delivery.payload.as_bytes());
+        wtx.insert(&self.0, Self::key(delivery), value);
+    }
+
+    fn remove_wtx(&self, wtx: &mut WriteTransaction, delivery: &WebhookDelivery) {
+        wtx.remove(&self.0, Self::key(delivery));
+    }
+
+    /// Deliveries whose next attempt is at `now_ms` or before, oldest first
+    pub fn get_due_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        now_ms: u64,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>> {
+        rtx.range(&self.0, ..now_ms.saturating_add(1).to_be_bytes())
+            .take(limit)
+            .map(|item| {
+                let (key, value) = item?;
+                if key.len() != Self::KEY_LEN || value.len() < 4 {
+                    bail!("Invalid webhook delivery length");
+                }
+                Ok(WebhookDelivery {
+                    id: DeliveryId::decode(&key[8..])?,
+                    next_attempt_ms: u64::from_be_bytes(key[..8].try_into()?),
+                    attempts: u32::from_be_bytes(value[..4].try_into()?),
+                    payload: String::from_utf8(value[4..].to_vec())?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn approximate_len(&self) -> usize {
+        self.0.inner().approximate_len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub id: DeliveryId,
+    pub failed_at_ms: u64,
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+    pub payload: String,
+}
+
+/// Partition keeping the webhook deliveries given up on.
+///
+/// **Key:** [subscription id (8 bytes BE)] + [tx_id (32 bytes)] + [output index (4 bytes BE)]
+/// **Value:** [failed at unix ms (8 bytes BE)] + [attempts (4 bytes BE)] +
+/// [error and payload (utf8 lines)]
+#[derive(Clone)]
+pub struct WebhookDeadLetterPartition(fjall::TxPartition);
+
+impl DescribePartition for WebhookDeadLetterPartition {
+    const DESCRIPTION: PartitionDescription = PartitionDescription {
+        name: "webhook_dead_letters",
+        key: &[
+            field("subscription_id", FieldType::U64Be),
+            field("tx_id", FieldType::Hash),
+            field("output_index", FieldType::Bytes(4)),
+        ],
+        value: &[
+            field("failed_at_ms", FieldType::U64Be),
+            field("attempts", FieldType::Bytes(4)),
+            field("delivery", FieldType::Tail("utf8_lines(error,payload)")),
+        ],
+        ..PartitionDescription::DEFAULT
+    };
+}
+
+impl WebhookDeadLetterPartition {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self(schema::open_partition::<Self>(
+            keyspace,
+            PartitionCreateOptions::default(),
+        )?))
+    }
+
+    fn insert_wtx(&self, wtx: &mut WriteTransaction, dead_letter: &DeadLetter) {
+        let mut value = dead_letter.failed_at_ms.to_be_bytes().to_vec();
+        value.extend_from_slice(&dead_letter.attempts.to_be_bytes());
+        // the payload is single line JSON
+        value.extend_from_slice(dead_letter.error.replace('\n', " ").as_bytes());
+        value.push(b'\n');
+        value.extend_from_slice(dead_letter.payload.as_bytes());
+        wtx.insert(&self.0, dead_letter.id.encode(), value);
+    }
+
+    pub fn iter_rtx(&self, rtx: &ReadTransaction) -> impl Iterator<Item = Result<DeadLetter>> + '_ {
+        rtx.iter(&self.0).map(|item| {
+            let (key, value) = item?;
+            if value.len() < 12 {
+                bail!("Invalid webhook dead letter length");
+            }
+            let Some((error, payload)) = std::str::from_utf8(&value[12..])?.split_once('\n') else {
+                bail!("Invalid webhook dead letter value");
+            };
+            Ok(DeadLetter {
+                id: DeliveryId::decode(&key)?,
+                failed_at_ms: u64::from_be_bytes(value[..8].try_into()?),
+                attempts: u32::from_be_bytes(value[8..12].try_into()?),
+                error: error.to_string(),
+                payload: payload.to_string(),
+            })
+        })
+    }
+}
+
+/// HTTP callbacks for outputs paying watched addresses.
+///
+/// The virtual chain processor records the outputs of the transactions a chain block accepts
+/// which pay a watched address, in the transaction applying the block. Each time the chain
+/// advances, the outputs reaching the confirmations of their subscription are queued for
+/// delivery, unless a reorg removed their accepting block meanwhile. Queued deliveries stay in
+/// the outbox until the receiver took them, see [`crate::webhooks`].
+///
+/// Only outputs in the outpoint index are seen.
+#[derive(Clone)]
+pub struct Webhooks {
+    keyspace: TxKeyspace,
+    metadata_partition: MetadataPartition,
+    outpoint_partition: OutpointPartition,
+    chain_index_partition: ChainIndexPartition,
+    chain_index_by_hash_partition: ChainIndexByHashPartition,
+    subscription_partition: WebhookSubscriptionPartition,
+    address_partition: WebhookAddressPartition,
+    pending_partition: WebhookPendingPartition,
+    outbox_partition: WebhookOutboxPartition,
+    dead_letter_partition: WebhookDeadLetterPartition,
+}
+
+impl Webhooks {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        Ok(Self {
+            keyspace: keyspace.clone(),
+            metadata_partition: MetadataPartition::new(keyspace)?,
+            outpoint_partition: OutpointPartition::new(keyspace)?,
+            chain_index_partition: ChainIndexPartition::new(keyspace)?,
+            chain_index_by_hash_partition: ChainIndexByHashPartition::new(keyspace)?,
+            subscription_partition: WebhookSubscriptionPartition::new(keyspace)?,
+            address_partition: WebhookAddressPartition::new(keyspace)?,
+            pending_partition: WebhookPendingPartition::new(keyspace)?,
+            outbox_partition: WebhookOutboxPartition::new(keyspace)?,
+            dead_letter_partition: WebhookDeadLetterPartition::new(keyspace)?,
+        })
+    }
+
+    /// Watches the address from the next accepted chain block on
+    pub fn subscribe(
+        &self,
+        address: &RpcAddress,
+        url: &str,
+        min_confirmations: u64,
+        secret: &str,
+    ) -> Result<WebhookSubscription> {
+        if url.contains('\n') || secret.contains('\n') {
+            bail!("Webhook url and secret must be single line");
+        }
+        let payload = AddressPayload::try_from(address)?;
+        let mut wtx = self.keyspace.write_tx()?;
+        let subscription = WebhookSubscription {
+            id: self
+                .metadata_partition
+                .next_webhook_subscription_id_wtx(&mut wtx)?,
+            address: address.to_string(),
+            url: url.to_string(),
+            min_confirmations,
+            secret: secret.to_string(),
+        };
+        self.subscription_partition
+            .insert_wtx(&mut wtx, &subscription);
+        self.address_partition.insert_wtx(
+            &mut wtx,
+            address_key(&payload),
+            subscription.id,
+            min_confirmations,
+        );
+        wtx.commit()??;
+        Ok(subscription)
+    }
+
+    /// Returns whether the subscription existed. Its queued deliveries are dropped by the
+    /// dispatcher
+    pub fn unsubscribe(&self, id: u64) -> Result<bool> {
+        let mut wtx = self.keyspace.write_tx()?;
+        let Some(subscription) = self.subscription_partition.get_wtx(&mut wtx, id)? else {
+            return Ok(false);
+        };
+        let address = RpcAddress::try_from(subscription.address.as_str())?;
+        self.address_partition.remove_wtx(
+            &mut wtx,
+            address_key(&AddressPayload::try_from(&address)?),
+            id,
+        );
+        self.subscription_partition.remove_wtx(&mut wtx, id);
+        wtx.commit()??;
+        Ok(true)
+    }
+
+    pub fn get_subscription(&self, id: u64) -> Result<Option<WebhookSubscription>> {
+        self.subscription_partition
+            .get_rtx(&self.keyspace.read_tx(), id)
+    }
+
+    pub fn subscriptions(&self) -> Result<Vec<WebhookSubscription>> {
+        self.subscription_partition
+            .iter_rtx(&self.keyspace.read_tx())
+            .collect()
+    }
+
+    /// Records the outputs paying watched addresses among the transactions the chain block
+    /// accepted, its chain index must be written already. Returns the number of outputs
+    /// recorded
+    pub fn apply_accepted_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        accepting_block_hash: RpcHash,
+        tx_ids: &[RpcTransactionId],
+    ) -> Result<usize> {
+        if self.address_partition.is_empty_wtx(wtx)? {
+            return Ok(0);
+        }
+        let Some(accepting_index) = self
+            .chain_index_by_hash_partition
+            .get_chain_index_wtx(wtx, &accepting_block_hash)?
+        else {
+            return Ok(0);
+        };
+        let mut recorded = 0;
+        for tx_id in tx_ids {
+            for (output_index, output) in self.outpoint_partition.get_outputs_wtx(wtx, *tx_id)? {
+                let Ok(address) = AddressPayload::try_from(&output.script_public_key) else {
+                    continue;
+                };
+                for (subscription_id, min_confirmations) in
+                    self.address_partition.get_wtx(wtx, address_key(&address))?
+                {
+                    self.pending_partition.insert_wtx(
+                        wtx,
+                        &PendingOutput {
+                            due_index: accepting_index + min_confirmations.max(1) - 1,
+                            accepting_block_hash,
+                            id: DeliveryId {
+                                subscription_id,
+                                tx_id: *tx_id,
+                                output_index,
+                            },
+                            accepting_index,
+                            amount: output.value,
+                        },
+                    );
+                    recorded += 1;
+                }
+            }
+        }
+        Ok(recorded)
+    }
+
+    /// Queues the recorded outputs confirmed deep enough at the current chain tip. Outputs
+    /// whose accepting block left the chain or whose subscription was removed are dropped.
+    /// Returns the number of deliveries queued
+    pub fn release_due_wtx(&self, wtx: &mut WriteTransaction) -> Result<usize> {
+        let Some(tip) = self.chain_index_partition.chain_tip_index_wtx(wtx)? else {
+            return Ok(0);
+        };
+        let mut queued = 0;
+        for pending in self.pending_partition.take_due_wtx(wtx, tip)? {
+            if self
+                .chain_index_by_hash_partition
+                .get_chain_index_wtx(wtx, &pending.accepting_block_hash)?
+                != Some(pending.accepting_index)
+            {
+                continue;
+            }
+            let Some(subscription) = self
+                .subscription_partition
+                .get_wtx(wtx, pending.id.subscription_id)?
+            else {
+                continue;
+            };
+            let payload = WebhookPayload {
+                idempotency_key: pending.id.to_string(),
+                subscription_id: subscription.id,
+                address: subscription.address,
+                tx_id: pending.id.tx_id.to_string(),
+                output_index: pending.id.output_index,
+                amount: pending.amount,
+                accepting_block_hash: pending.accepting_block_hash.to_string(),
+                confirmations: tip - pending.accepting_index + 1,
+            };
+            self.enqueue_wtx(wtx, pending.id, &payload)?;
+            queued += 1;
+        }
+        Ok(queued)
+    }
+
+    /// Queues a delivery due right away
+    pub fn enqueue_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        id: DeliveryId,
+        payload: &WebhookPayload,
+    ) -> Result<()> {
+        self.outbox_partition.insert_wtx(
+            wtx,
+            &WebhookDelivery {
+                id,
+                next_attempt_ms: unix_ms(),
+                attempts: 0,
+                payload: serde_json::to_string(payload)?,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn due_deliveries(&self, now_ms: u64, limit: usize) -> Result<Vec<WebhookDelivery>> {
+        self.outbox_partition
+            .get_due_rtx(&self.keyspace.read_tx(), now_ms, limit)
+    }
+
+    /// Removes a delivery the receiver took or whose subscription is gone
+    pub fn complete(&self, delivery: &WebhookDelivery) -> Result<()> {
+        let mut wtx = self.keyspace.write_tx()?;
+        self.outbox_partition.remove_wtx(&mut wtx, delivery);
+        wtx.commit()??;
+        Ok(())
+    }
+
+    /// Counts a failed attempt and moves the next one to `next_attempt_ms`
+    pub fn reschedule(&self, delivery: &WebhookDelivery, next_attempt_ms: u64) -> Result<()> {
+        let mut wtx = self.keyspace.write_tx()?;
+        self.outbox_partition.remove_wtx(&mut wtx, delivery);
+        self.outbox_partition.insert_wtx(
+            &mut wtx,
+            &WebhookDelivery {
+                next_attempt_ms,
+                attempts: delivery.attempts + 1,
+                ..delivery.clone()
+            },
+        );
+        wtx.commit()??;
+        Ok(())
+    }
+
+    /// Moves a delivery failing for the last time to the dead letters
+    pub fn dead_letter(&self, delivery: &WebhookDelivery, error: &str) -> Result<()> {
+        let mut wtx = self.keyspace.write_tx()?;
+        self.outbox_partition.remove_wtx(&mut wtx, delivery);
+        self.dead_letter_partition.insert_wtx(
+            &mut wtx,
+            &DeadLetter {
+                id: delivery.id,
+                failed_at_ms: unix_ms(),
+                attempts: delivery.attempts + 1,
+                error: error.to_string(),
+                payload: delivery.payload.clone(),
+            },
+        );
+        wtx.commit()??;
+        Ok(())
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        self.dead_letter_partition
+            .iter_rtx(&self.keyspace.read_tx())
+            .collect()
+    }
+
+    /// Approximate number of queued deliveries
+    pub fn outbox_len(&self) -> usize {
+        self.outbox_partition.approximate_len()
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::processing::IndexedOutput;
+    use kaspa_addresses::{Prefix, Version};
+    use kaspa_txscript::pay_to_address_script;
+
+    fn hash(i: u64) -> RpcHash {
+        RpcHash::from_u64_word(i)
+    }
+
+    fn address(byte: u8) -> RpcAddress {
+        RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[byte; 32])
+    }
+
+    /// Transaction 10 pays 100 to address 1 and 50 to address 2
+    fn setup(name: &str) -> (TxKeyspace, Webhooks) {
+        let keyspace = crate::database::test_keyspace(&format!("webhooks-{name}"));
+        let outpoints = OutpointPartition::new(&keyspace).unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        for (index, value, to) in [(0, 100, address(1)), (1, 50, address(2))] {
+            let output = IndexedOutput::new(value, pay_to_address_script(&to), None);
+            outpoints
+                .insert_wtx(&mut wtx, hash(10), index, &output)
+                .unwrap();
+        }
+        wtx.commit().unwrap().unwrap();
+        (keyspace.clone(), Webhooks::new(&keyspace).unwrap())
+    }
+
+    /// Extends the chain index by the block, accepting `tx_ids`, and releases the due outputs
+    fn add_chain_block(
+        keyspace: &TxKeyspace,
+        webhooks: &Webhooks,
+        index: u64,
+        block: u64,
+        tx_ids: &[RpcTransactionId],
+    ) -> usize {
+        let mut wtx = keyspace.write_tx().unwrap();
+        for hash in webhooks
+            .chain_index_partition
+            .truncate_wtx(&mut wtx, index)
+            .unwrap()
+        {
+            webhooks
+                .chain_index_by_hash_partition
+                .remove_wtx(&mut wtx, &hash);
+        }
+        webhooks
+            .chain_index_partition
+            .insert_wtx(&mut wtx, index, &hash(block));
+        webhooks
+            .chain_index_by_hash_partition
+            .insert_wtx(&mut wtx, &hash(block), index);
+        webhooks
+            .apply_accepted_wtx(&mut wtx, hash(block), tx_ids)
+            .unwrap();
+        let queued = webhooks.release_due_wtx(&mut wtx).unwrap();
+        wtx.commit().unwrap().unwrap();
+        queued
+    }
+
+    fn payloads(webhooks: &Webhooks) -> Vec<WebhookPayload> {
+        webhooks
+            .due_deliveries(u64::MAX, 100)
+            .unwrap()
+            .iter()
+            .map(|delivery| serde_json::from_str(&delivery.payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_output_delivered_at_confirmation_depth() {
+        let (keyspace, webhooks) = setup("depth");
+        let subscription = webhooks
+            .subscribe(&address(1), "http://localhost/hook", 3, "secret")
+            .unwrap();
+        assert_eq!(webhooks.subscriptions().unwrap(), [subscription.clone()]);
+
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 0, 1, &[hash(10)]), 0);
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 1, 2, &[]), 0);
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 2, 3, &[]), 1);
+        let id = DeliveryId {
+            subscription_id: subscription.id,
+            tx_id: hash(10),
+            output_index: 0,
+        };
+        assert_eq!(
+            payloads(&webhooks),
+            [WebhookPayload {
+                idempotency_key: id.to_string(),
+                subscription_id: subscription.id,
+                address: address(1).to_string(),
+                tx_id: hash(10).to_string(),
+                output_index: 0,
+                amount: 100,
+                accepting_block_hash: hash(1).to_string(),
+                confirmations: 3,
+            }]
+        );
+
+        // a failed attempt keeps the delivery, a taken one is removed
+        let delivery = webhooks.due_deliveries(u64::MAX, 100).unwrap().remove(0);
+        webhooks.reschedule(&delivery, u64::MAX - 1).unwrap();
+        let delivery = webhooks.due_deliveries(u64::MAX, 100).unwrap().remove(0);
+        assert_eq!((delivery.id, delivery.attempts), (id, 1));
+        assert!(
+            webhooks
+                .due_deliveries(u64::MAX - 2, 100)
+                .unwrap()
+                .is_empty()
+        );
+        webhooks.complete(&delivery).unwrap();
+        assert!(payloads(&webhooks).is_empty());
+    }
+
+    #[test]
+    fn test_reorged_acceptance_is_not_delivered() {
+        let (keyspace, webhooks) = setup("reorg");
+        webhooks
+            .subscribe(&address(2), "http://localhost/hook", 2, "secret")
+            .unwrap();
+        add_chain_block(&keyspace, &webhooks, 0, 1, &[hash(10)]);
+        // block 4 replaces block 1 without accepting the transaction
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 0, 4, &[]), 0);
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 1, 5, &[]), 0);
+        assert!(payloads(&webhooks).is_empty());
+
+        // accepted again by block 6
+        add_chain_block(&keyspace, &webhooks, 2, 6, &[hash(10)]);
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 3, 7, &[]), 1);
+        let payloads = payloads(&webhooks);
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(
+            (payloads[0].amount, &payloads[0].accepting_block_hash),
+            (50, &hash(6).to_string())
+        );
+    }
+
+    #[test]
+    fn test_unsubscribe_and_dead_letters() {
+        let (keyspace, webhooks) = setup("unsubscribe");
+        let first = webhooks
+            .subscribe(&address(1), "http://localhost/first", 1, "secret")
+            .unwrap();
+        let second = webhooks
+            .subscribe(&address(1), "http://localhost/second", 1, "secret")
+            .unwrap();
+        assert_eq!(second.id, first.id + 1);
+        assert!(webhooks.unsubscribe(first.id).unwrap());
+        assert!(!webhooks.unsubscribe(first.id).unwrap());
+        assert!(
+            webhooks
+                .subscribe(&address(1), "http://a\nb", 1, "")
+                .is_err()
+        );
+
+        assert_eq!(add_chain_block(&keyspace, &webhooks, 0, 1, &[hash(10)]), 1);
+        let delivery = webhooks.due_deliveries(u64::MAX, 100).unwrap().remove(0);
+        assert_eq!(delivery.id.subscription_id, second.id);
+        webhooks
+            .dead_letter(&delivery, "connection refused\nafter retries")
+            .unwrap();
+        assert!(payloads(&webhooks).is_empty());
+        let dead_letters = webhooks.dead_letters().unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(
+            (
+                dead_letters[0].id,
+                dead_letters[0].attempts,
+                dead_letters[0].error.as_str(),
+                &dead_letters[0].payload
+            ),
+            (
+                delivery.id,
+                1,
+                "connection refused after retries",
+                &delivery.payload
+            )
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Errors of the public entry points: the [`Indexer`](crate::indexer::Indexer) facade,
+//! [`HistoricalDataSyncer::sync`](crate::historical_syncer::HistoricalDataSyncer::sync),
+//! [`database::open`](crate::database::open) and the [`Queries`](crate::queries::Queries).
+//!
+//! The components fail with [`anyhow::Error`] internally. At the entry points an error is
+//! classified by the first error of its chain that tells its category and kept as the source,
+//! so logs printing the chain see every context of it.
+
+use crate::node_capabilities::NodeIncompatible;
+use crate::rpc_transport::TransportError;
+use kaspa_rpc_core::{RpcError, RpcHash};
+
+pub type IndexerResult<T> = Result<T, IndexerError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum IndexerError {
+    /// The node couldn't be reached, timed out or failed the call
+    #[error("node call failed")]
+    Rpc(#[source] anyhow::Error),
+    /// The node doesn't know the requested block
+    #[error("node doesn't know the requested block")]
+    NotFound(#[source] anyhow::Error),
+    /// The node no longer serves the blocks from `from`, its history was pruned past them
+    #[error("node no longer serves the blocks from {from}, they were likely pruned")]
+    Pruned {
+        from: RpcHash,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Reading or writing the store failed
+    #[error("database failed")]
+    Database(#[source] anyhow::Error),
+    /// The store contradicts itself, see `fsck`
+    #[error("database is inconsistent:\n{0}")]
+    Corrupt(String),
+    /// A component stopped while another one still sent to it
+    #[error("{0} channel closed")]
+    ChannelClosed(&'static str),
+    /// Stopped because shutdown was requested
+    #[error("shutdown requested")]
+    Shutdown,
+    /// Refused by the configuration or the stored state, such as a database of another network
+    #[error(transparent)]
+    Validation(anyhow::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl IndexerError {
+    /// Classifies `err`, falling back to [`IndexerError::Validation`]
+    pub fn validation(err: anyhow::Error) -> Self {
+        Self::classify(err, Self::Validation)
+    }
+
+    fn classify(err: anyhow::Error, fallback: fn(anyhow::Error) -> Self) -> Self {
+        let category = err.chain().find_map(|cause| {
+            if let Some(indexer) = cause.downcast_ref::<IndexerError>() {
+                indexer.category()
+            } else if let Some(transport) = cause.downcast_ref::<TransportError>() {
+                Some(Category::of_transport(transport))
+            } else if let Some(rpc) = cause.downcast_ref::<RpcError>() {
+                Some(Category::of_transport(&TransportError::Rpc(
+                    rpc.to_string(),
+                )))
+            } else if cause.is::<fjall::Error>() {
+                Some(Category::Database)
+            } else if cause.is::<NodeIncompatible>() {
+                Some(Category::Validation)
+            } else {
+                None
+            }
+        });
+        match category {
+            Some(category) => category.wrap(err),
+            None => fallback(err),
+        }
+    }
+
+    fn category(&self) -> Option<Category> {
+        Some(match self {
+            Self::Rpc(_) => Category::Rpc,
+            Self::NotFound(_) => Category::NotFound,
+            Self::Pruned { from, .. } => Category::Pruned(*from),
+            Self::Database(_) => Category::Database,
+            Self::Corrupt(report) => Category::Corrupt(report.clone()),
+            Self::ChannelClosed(channel) => Category::ChannelClosed(*channel),
+            Self::Shutdown => Category::Shutdown,
+            Self::Validation(_) => Category::Validation,
+            Self::Other(_) => return None,
+        })
+    }
+}
+
+/// Variant of an error found in a chain, applied to the whole chain
+enum Category {
+    Rpc,
+    NotFound,
+    Pruned(RpcHash),
+    Database,
+    Corrupt(String),
+    ChannelClosed(&'static str),
+    Shutdown,
+    Validation,
+}
+
+impl Category {
+    fn of_transport(err: &TransportError) -> Self {
+        match err.is_not_found() {
+            true => Self::NotFound,
+            false => Self::Rpc,
+        }
+    }
+
+    fn wrap(self, err: anyhow::Error) -> IndexerError {
+        match self {
+            Self::Rpc => IndexerError::Rpc(err),
+            Self::NotFound => IndexerError::NotFound(err),
+            Self::Pruned(from) => IndexerError::Pruned { from, source: err },
+            Self::Database => IndexerError::Database(err),
+            Self::Corrupt(report) => IndexerError::Corrupt(report),
+            Self::ChannelClosed(channel) => IndexerError::ChannelClosed(channel),
+            Self::Shutdown => IndexerError::Shutdown,
+            Self::Validation => IndexerError::Validation(err),
+        }
+    }
+}
+
+impl From<anyhow::Error> for IndexerError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::classify(err, Self::Other)
+    }
+}
+
+impl From<fjall::Error> for IndexerError {
+    fn from(err: fjall::Error) -> Self {
+        Self::Database(err.into())
+    }
+}
+
+/// Classified by its message like the calls of [`RpcNode`](crate::rpc_transport::RpcNode)
+impl From<RpcError> for IndexerError {
+    fn from(err: RpcError) -> Self {
+        Self::classify(err.into(), Self::Rpc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn test_classifies_by_chain() {
+        let not_found = Err::<(), _>(TransportError::Rpc("block not found".to_string()))
+            .context("Failed to fetch block")
+            .unwrap_err();
+        let err = IndexerError::from(not_found);
+        assert!(matches!(err, IndexerError::NotFound(_)), "{err:?}");
+        // the chain is kept below the variant
+        let chain = format!("{:#}", anyhow::Error::from(err));
+        assert!(chain.contains("Failed to fetch block"), "{chain}");
+        assert!(chain.contains("block not found"), "{chain}");
+
+        let err = IndexerError::from(anyhow::Error::from(TransportError::Timeout));
+        assert!(matches!(err, IndexerError::Rpc(_)));
+        let err = IndexerError::from(RpcError::General("cannot find header".to_string()));
+        assert!(matches!(err, IndexerError::NotFound(_)));
+        let err = IndexerError::from(anyhow::Error::from(NodeIncompatible("old".to_string())));
+        assert!(matches!(err, IndexerError::Validation(_)));
+
+        // an entry point error passing through anyhow code keeps its variant
+        let from = RpcHash::from_u64_word(7);
+        let pruned = IndexerError::Pruned {
+            from,
+            source: anyhow!("block not found"),
+        };
+        let err = IndexerError::from(anyhow::Error::from(pruned).context("Gap sync failed"));
+        assert!(matches!(err, IndexerError::Pruned { from: hash, .. } if hash == from));
+        let err = IndexerError::from(anyhow::Error::from(IndexerError::Shutdown).context("stop"));
+        assert!(matches!(err, IndexerError::Shutdown));
+
+        assert!(matches!(
+            IndexerError::from(anyhow!("something else")),
+            IndexerError::Other(_)
+        ));
+        let err = IndexerError::validation(anyhow!("Database belongs to network testnet-10"));
+        assert_eq!(err.to_string(), "Database belongs to network testnet-10");
+        assert!(matches!(err, IndexerError::Validation(_)));
+    }
+
+    #[test]
+    fn test_open_fails_with_database() {
+        let path = std::env::temp_dir().join(format!("kasia-indexer-error-{}", std::process::id()));
+        std::fs::write(&path, b"not a keyspace").unwrap();
+        let err = crate::database::open(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(err, IndexerError::Database(_)), "{err:?}");
+    }
+}
@@ -1,31 +1,331 @@
+use parking_lot::Mutex;
 use ringmap::set::RingSet;
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
 
-// will be used to sort already processed blocks and/or already processed transactions
-pub struct FifoSet<K> {
-    set: RingSet<K>,
+/// Hasher for keys which are hashes already, e.g. block hashes and transaction ids
+pub type FastHasher = rustc_hash::FxBuildHasher;
+
+/// Bounded set evicting the oldest key once full, used to skip already processed blocks and
+/// transactions. With a TTL keys also expire, whichever limit is hit first evicts
+pub struct FifoSet<K, S = RandomState> {
+    set: RingSet<K, S>,
+    /// The ring set may allocate more than asked for
+    capacity: usize,
+    ttl: Option<Ttl>,
+}
+
+struct Ttl {
+    ttl: Duration,
+    /// Insertion times in the order of the set
+    inserted_at: VecDeque<Instant>,
 }
 
-impl<K: std::hash::Hash + Eq> FifoSet<K> {
+impl<K: Hash + Eq> FifoSet<K> {
     pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> FifoSet<K, S> {
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
+        let capacity = capacity.max(1);
         Self {
-            set: RingSet::with_capacity(capacity),
+            set: RingSet::with_capacity_and_hasher(capacity, hasher),
+            capacity,
+            ttl: None,
         }
     }
 
+    /// Keys older than `ttl` count as absent, they are removed by later inserts or
+    /// [`Self::evict_expired`]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(Ttl {
+            ttl,
+            inserted_at: VecDeque::with_capacity(self.capacity),
+        });
+        self
+    }
+
+    /// Inserts the key unless present, evicting the oldest key when full. Returns whether the
+    /// key was new
     pub fn insert(&mut self, key: K) -> bool {
+        self.insert_at(key, Instant::now())
+    }
+
+    fn insert_at(&mut self, key: K, now: Instant) -> bool {
+        self.evict_expired_at(now);
         if self.set.contains(&key) {
             return false;
         }
 
-        if self.set.len() == self.set.capacity() {
-            self.set.pop_front();
+        if self.set.len() == self.capacity {
+            self.pop_front();
         }
 
         self.set.insert(key);
+        if let Some(ttl) = &mut self.ttl {
+            ttl.inserted_at.push_back(now);
+        }
         true
     }
 
     pub fn contains(&self, key: &K) -> bool {
-        self.set.contains(key)
+        self.contains_at(key, Instant::now())
+    }
+
+    fn contains_at(&self, key: &K, now: Instant) -> bool {
+        match (&self.ttl, self.set.get_index_of(key)) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(ttl), Some(index)) => !ttl.is_expired(ttl.inserted_at[index], now),
+        }
+    }
+
+    /// Removes the expired keys, inserts do so as well
+    pub fn evict_expired(&mut self) -> usize {
+        self.evict_expired_at(Instant::now())
+    }
+
+    fn evict_expired_at(&mut self, now: Instant) -> usize {
+        let mut evicted = 0;
+        while let Some(ttl) = &self.ttl
+            && ttl
+                .inserted_at
+                .front()
+                .is_some_and(|inserted_at| ttl.is_expired(*inserted_at, now))
+        {
+            self.pop_front();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    fn pop_front(&mut self) {
+        self.set.pop_front();
+        if let Some(ttl) = &mut self.ttl {
+            ttl.inserted_at.pop_front();
+        }
+    }
+
+    /// Expired keys count until evicted
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.set.clear();
+        if let Some(ttl) = &mut self.ttl {
+            ttl.inserted_at.clear();
+        }
+    }
+
+    /// Bytes allocated for the keys and the index, heap data owned by the keys excluded
+    pub fn approx_memory_usage(&self) -> usize {
+        // entries keep the key with its hash, the index holds a position and a control byte
+        let per_entry = size_of::<K>() + size_of::<u64>() + size_of::<usize>() + 1;
+        let timestamps = self
+            .ttl
+            .as_ref()
+            .map_or(0, |ttl| ttl.inserted_at.capacity() * size_of::<Instant>());
+        self.set.capacity() * per_entry + timestamps
+    }
+}
+
+impl Ttl {
+    fn is_expired(&self, inserted_at: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(inserted_at) >= self.ttl
+    }
+}
+
+/// [`FifoSet`] split into shards by key hash, each behind its own lock, for dedup from several
+/// threads. Every shard holds its share of the capacity and evicts its own oldest key
+pub struct ConcurrentFifoSet<K, S = RandomState> {
+    shards: Box<[Mutex<FifoSet<K, S>>]>,
+    hasher: S,
+}
+
+impl<K: Hash + Eq> ConcurrentFifoSet<K> {
+    pub fn new(capacity: usize, shards: usize) -> Self {
+        Self::with_hasher(capacity, shards, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher + Clone> ConcurrentFifoSet<K, S> {
+    /// The capacity is rounded up to a multiple of the shard count
+    pub fn with_hasher(capacity: usize, shards: usize, hasher: S) -> Self {
+        let shards = shards.max(1);
+        let per_shard = capacity.div_ceil(shards);
+        Self {
+            shards: (0..shards)
+                .map(|_| Mutex::new(FifoSet::with_hasher(per_shard, hasher.clone())))
+                .collect(),
+            hasher,
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<FifoSet<K, S>> {
+        // the low bits pick the bucket within the shard, the top ones tag it
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash >> 32) as usize % self.shards.len()]
+    }
+
+    /// Same as [`FifoSet::insert`], concurrent inserts of a key report it new exactly once
+    pub fn insert(&self, key: K) -> bool {
+        self.shard(&key).lock().insert(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.shard(key).lock().contains(key)
+    }
+
+    /// Sum over the shards, each locked in turn
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().capacity())
+            .sum()
+    }
+
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_fifo_eviction_matches_model() {
+        // xorshift, the same sequences every run
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for capacity in [1, 2, 7, 64] {
+            let mut set = FifoSet::with_hasher(capacity, FastHasher::default());
+            let mut model = VecDeque::new();
+            for _ in 0..2_000 {
+                let key = next() % (capacity as u64 * 3);
+                let is_new = !model.contains(&key);
+                if is_new {
+                    if model.len() == capacity {
+                        model.pop_front();
+                    }
+                    model.push_back(key);
+                }
+                assert_eq!(set.insert(key), is_new);
+                assert!(set.len() <= capacity);
+                assert_eq!(set.len(), model.len());
+                assert!(model.iter().all(|key| set.contains(key)));
+            }
+        }
+
+        let mut set = FifoSet::new(0);
+        assert!(set.insert(1));
+        assert!(set.insert(2));
+        assert_eq!(set.len(), 1);
+        assert!(!set.contains(&1));
+        assert!(set.approx_memory_usage() > 0);
+        set.clear();
+        assert!(set.is_empty());
+        assert!(set.insert(1));
+    }
+
+    #[test]
+    fn test_ttl_and_capacity_both_evict() {
+        let ttl = Duration::from_secs(10);
+        let mut set = FifoSet::new(3).with_ttl(ttl);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(set.insert_at(1, at(0)));
+        assert!(set.insert_at(2, at(4)));
+        assert!(!set.insert_at(1, at(9)));
+        assert!(set.contains_at(&1, at(9)));
+        // expired, though not evicted yet
+        assert!(!set.contains_at(&1, at(10)));
+        assert_eq!(set.len(), 2);
+        assert!(set.insert_at(1, at(10)));
+        assert_eq!(set.len(), 2);
+
+        // the count limit still applies within the ttl
+        assert!(set.insert_at(3, at(11)));
+        assert!(set.insert_at(4, at(11)));
+        assert!(!set.contains_at(&2, at(11)));
+        assert!(set.contains_at(&1, at(11)));
+
+        assert_eq!(set.evict_expired_at(at(20)), 1);
+        assert!(!set.contains_at(&1, at(20)));
+        assert!(set.contains_at(&3, at(20)));
+        assert_eq!(set.evict_expired_at(at(21)), 2);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_inserts_report_new_once() {
+        const THREADS: u64 = 8;
+        const KEYS: u64 = 10_000;
+        let set = ConcurrentFifoSet::with_hasher(KEYS as usize, 16, FastHasher::default());
+        assert_eq!(set.capacity(), 10_000);
+        // every thread inserts all keys, starting at a different offset
+        let new_keys = std::thread::scope(|scope| {
+            (0..THREADS)
+                .map(|thread| {
+                    let set = &set;
+                    scope.spawn(move || {
+                        (0..KEYS)
+                            .map(|i| (i + thread * KEYS / THREADS) % KEYS)
+                            .filter(|key| set.insert(*key))
+                            .count()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .sum::<usize>()
+        });
+        // shards may fill up unevenly and evict, a key is new once per stay in the set
+        assert!(new_keys >= KEYS as usize);
+        assert!(set.len() <= set.capacity());
+
+        let set = ConcurrentFifoSet::new(1_000_000, 8);
+        let new_keys = std::thread::scope(|scope| {
+            (0..THREADS)
+                .map(|_| scope.spawn(|| (0..KEYS).filter(|key| set.insert(*key)).count()))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .sum::<usize>()
+        });
+        assert_eq!(new_keys, KEYS as usize);
+        assert_eq!(set.len(), KEYS as usize);
+        set.clear();
+        assert!(set.is_empty());
     }
 }
@@ -0,0 +1,511 @@
+//! Recorded node traffic for deterministic regression tests.
+//!
+//! A [`FixtureRecorder`] given to the [`RpcNode`](crate::rpc_transport::RpcNode)s and the
+//! subscriber writes every call made through them with its response, and every notification,
+//! to a gzip compressed file of JSON lines. Calls made on the wRPC client directly aren't
+//! recorded. A [`FixtureRpcClient`] replays the file behind an `RpcNode`: each call gets the
+//! responses recorded for the same request in their recorded order, so concurrent callers
+//! replay the same way however they interleave. [`Fixture::trim`] cuts a recording down to a
+//! DAA score range, `cargo run --example trim_fixture` does it for a file.
+
+use crate::rpc_transport::TransportError;
+use anyhow::{Context, Result, bail};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use kaspa_rpc_core::{
+    GetBlockDagInfoResponse, GetServerInfoResponse, GetVirtualChainFromBlockResponse, Notification,
+    RpcAddress, RpcBlock, RpcHash,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Format of the first line, files of another version are refused
+pub const FIXTURE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FixtureHeader {
+    version: u32,
+}
+
+/// Request of a call, the key responses are replayed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureCall {
+    GetBlocks {
+        low_hash: RpcHash,
+        include_blocks: bool,
+        include_transactions: bool,
+    },
+    GetBlock {
+        hash: RpcHash,
+        include_transactions: bool,
+    },
+    GetBlockDagInfo,
+    GetServerInfo,
+    GetVirtualChainFromBlock {
+        start_hash: RpcHash,
+        include_accepted_transaction_ids: bool,
+    },
+    GetUtxoReturnAddress {
+        tx_id: RpcHash,
+        accepting_block_daa_score: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureResponse {
+    Blocks(Vec<RpcBlock>),
+    Block(RpcBlock),
+    BlockDagInfo(GetBlockDagInfoResponse),
+    ServerInfo(GetServerInfoResponse),
+    VirtualChain(GetVirtualChainFromBlockResponse),
+    UtxoReturnAddress(RpcAddress),
+}
+
+/// Value an [`RpcNode`](crate::rpc_transport::RpcNode) call returns
+pub trait FixtureValue: Clone + Sized {
+    fn into_response(self) -> FixtureResponse;
+    fn from_response(response: FixtureResponse) -> Option<Self>;
+}
+
+macro_rules! fixture_values {
+    ($($variant:ident($value:ty)),* $(,)?) => {
+        $(
+            impl FixtureValue for $value {
+                fn into_response(self) -> FixtureResponse {
+                    FixtureResponse::$variant(self)
+                }
+
+                fn from_response(response: FixtureResponse) -> Option<Self> {
+                    match response {
+                        FixtureResponse::$variant(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+fixture_values!(
+    Blocks(Vec<RpcBlock>),
+    Block(RpcBlock),
+    BlockDagInfo(GetBlockDagInfoResponse),
+    ServerInfo(GetServerInfoResponse),
+    VirtualChain(GetVirtualChainFromBlockResponse),
+    UtxoReturnAddress(RpcAddress),
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureEntry {
+    Call {
+        call: FixtureCall,
+        result: Result<FixtureResponse, TransportError>,
+    },
+    Notification(Notification),
+}
+
+impl FixtureEntry {
+    /// Keeps the blocks within `daa_range`, false if nothing of the entry is left
+    fn trim(&mut self, daa_range: &RangeInclusive<u64>) -> bool {
+        match self {
+            Self::Call {
+                call:
+                    FixtureCall::GetUtxoReturnAddress {
+                        accepting_block_daa_score,
+                        ..
+                    },
+                ..
+            } => daa_range.contains(accepting_block_daa_score),
+            Self::Call {
+                result: Ok(FixtureResponse::Blocks(blocks)),
+                ..
+            } => {
+                let recorded = blocks.len();
+                blocks.retain(|block| daa_range.contains(&block.header.daa_score));
+                recorded == 0 || !blocks.is_empty()
+            }
+            Self::Call {
+                result: Ok(FixtureResponse::Block(block)),
+                ..
+            } => daa_range.contains(&block.header.daa_score),
+            Self::Notification(Notification::BlockAdded(added)) => {
+                daa_range.contains(&added.block.header.daa_score)
+            }
+            Self::Notification(Notification::VirtualDaaScoreChanged(changed)) => {
+                daa_range.contains(&changed.virtual_daa_score)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Entries of a fixture file in recorded order
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    pub entries: Vec<FixtureEntry>,
+}
+
+impl Fixture {
+    /// A file cut short, like one of a recording killed before it finished, loads up to its
+    /// last complete entry
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open fixture {}", path.display()))?;
+        let mut lines = BufReader::new(GzDecoder::new(file)).lines();
+        let header: FixtureHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => bail!("fixture {} is empty", path.display()),
+        };
+        if header.version != FIXTURE_VERSION {
+            bail!(
+                "fixture {} has version {}, expected {FIXTURE_VERSION}",
+                path.display(),
+                header.version
+            );
+        }
+        let mut entries = Vec::new();
+        for (number, line) in lines.enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    warn!(
+                        "Fixture {} is truncated, loaded {number} entries",
+                        path.display()
+                    );
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let entry = serde_json::from_str(&line).with_context(|| {
+                format!("invalid entry {} of fixture {}", number + 1, path.display())
+            })?;
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut writer = FixtureWriter::create(path)?;
+        for entry in &self.entries {
+            writer.write(entry)?;
+        }
+        writer.finish()
+    }
+
+    /// Drops the blocks outside of `daa_range` and the entries left without any, everything
+    /// else is kept as recorded
+    pub fn trim(&mut self, daa_range: RangeInclusive<u64>) {
+        self.entries.retain_mut(|entry| entry.trim(&daa_range));
+    }
+}
+
+struct FixtureWriter(GzEncoder<BufWriter<File>>);
+
+impl FixtureWriter {
+    fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create fixture {}", path.display()))?;
+        let mut writer = Self(GzEncoder::new(BufWriter::new(file), Compression::default()));
+        writer.write_line(&FixtureHeader {
+            version: FIXTURE_VERSION,
+        })?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, entry: &FixtureEntry) -> Result<()> {
+        self.write_line(entry)
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<()> {
+        serde_json::to_writer(&mut self.0, value)?;
+        self.0.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        self.0.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Shared by all the nodes and the subscriber recording into the same file. The file is
+/// complete once [`finish`](Self::finish) is called or the last clone is dropped
+#[derive(Clone)]
+pub struct FixtureRecorder(Arc<Mutex<Option<FixtureWriter>>>);
+
+impl FixtureRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self(Arc::new(Mutex::new(Some(FixtureWriter::create(
+            path,
+        )?)))))
+    }
+
+    pub fn record_call<T: FixtureValue>(
+        &self,
+        call: FixtureCall,
+        result: &Result<T, TransportError>,
+    ) {
+        self.record(FixtureEntry::Call {
+            call,
+            result: result.clone().map(FixtureValue::into_response),
+        });
+    }
+
+    pub fn record_notification(&self, notification: &Notification) {
+        self.record(FixtureEntry::Notification(notification.clone()));
+    }
+
+    /// Failing to write stops the recording, the indexer goes on
+    fn record(&self, entry: FixtureEntry) {
+        let mut writer = self.0.lock();
+        if let Some(recording) = writer.as_mut()
+            && let Err(err) = recording.write(&entry)
+        {
+            warn!("Failed to record fixture entry, recording stopped: {err:#}");
+            *writer = None;
+        }
+    }
+
+    /// Writes the end of the file, later entries are dropped
+    pub fn finish(&self) -> Result<()> {
+        match self.0.lock().take() {
+            Some(writer) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Replays a [`Fixture`] behind an [`RpcNode`](crate::rpc_transport::RpcNode), see
+/// `RpcNode::from`
+pub struct FixtureRpcClient {
+    calls: Mutex<HashMap<FixtureCall, VecDeque<Result<FixtureResponse, TransportError>>>>,
+    notifications: Vec<Notification>,
+}
+
+impl From<Fixture> for FixtureRpcClient {
+    fn from(fixture: Fixture) -> Self {
+        let mut calls = HashMap::<_, VecDeque<_>>::new();
+        let mut notifications = Vec::new();
+        for entry in fixture.entries {
+            match entry {
+                FixtureEntry::Call { call, result } => {
+                    calls.entry(call).or_default().push_back(result)
+                }
+                FixtureEntry::Notification(notification) => notifications.push(notification),
+            }
+        }
+        Self {
+            calls: Mutex::new(calls),
+            notifications,
+        }
+    }
+}
+
+impl FixtureRpcClient {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Fixture::load(path)?.into())
+    }
+
+    /// Recorded notifications in their order, for feeding them to a subscriber
+    pub fn notifications(&self) -> &[Notification] {
+        &self.notifications
+    }
+
+    /// Responses not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.calls.lock().values().map(VecDeque::len).sum()
+    }
+
+    /// Next recorded response of `call`, a call the fixture has no response left for fails
+    pub(crate) fn replay<T: FixtureValue>(&self, call: FixtureCall) -> Result<T, TransportError> {
+        let response = self
+            .calls
+            .lock()
+            .get_mut(&call)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| TransportError::Rpc(format!("fixture has no response to {call:?}")))?;
+        T::from_response(response?).ok_or_else(|| {
+            TransportError::Rpc(format!(
+                "fixture has a response of another call to {call:?}"
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_consensus_core::header::Header;
+    use kaspa_rpc_core::{BlockAddedNotification, VirtualDaaScoreChangedNotification};
+
+    fn block(daa_score: u64) -> RpcBlock {
+        let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(daa_score), vec![]);
+        header.daa_score = daa_score;
+        RpcBlock {
+            header: (&header).into(),
+            transactions: vec![],
+            verbose_data: None,
+        }
+    }
+
+    fn get_blocks(low: u64) -> FixtureCall {
+        FixtureCall::GetBlocks {
+            low_hash: RpcHash::from_u64_word(low),
+            include_blocks: true,
+            include_transactions: true,
+        }
+    }
+
+    #[test]
+    fn test_replays_by_request_in_recorded_order() {
+        let client = FixtureRpcClient::from(Fixture {
+            entries: vec![
+                FixtureEntry::Call {
+                    call: get_blocks(1),
+                    result: Err(TransportError::Timeout),
+                },
+                FixtureEntry::Call {
+                    call: get_blocks(2),
+                    result: Ok(FixtureResponse::Blocks(vec![block(2), block(3)])),
+                },
+                FixtureEntry::Call {
+                    call: get_blocks(1),
+                    result: Ok(FixtureResponse::Blocks(vec![block(1)])),
+                },
+            ],
+        });
+        assert_eq!(client.remaining(), 3);
+
+        // the second request is answered although the first one was recorded before it
+        let blocks = client.replay::<Vec<RpcBlock>>(get_blocks(2)).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(
+            client.replay::<Vec<RpcBlock>>(get_blocks(1)).unwrap_err(),
+            TransportError::Timeout
+        );
+        assert_eq!(
+            client.replay::<Vec<RpcBlock>>(get_blocks(1)).unwrap().len(),
+            1
+        );
+        assert_eq!(client.remaining(), 0);
+
+        let err = client.replay::<Vec<RpcBlock>>(get_blocks(1)).unwrap_err();
+        assert!(!err.is_transient(), "{err}");
+    }
+
+    #[test]
+    fn test_trim_keeps_daa_range() {
+        let added = |daa_score| {
+            FixtureEntry::Notification(Notification::BlockAdded(BlockAddedNotification {
+                block: Arc::new(block(daa_score)),
+            }))
+        };
+        let mut fixture = Fixture {
+            entries: vec![
+                FixtureEntry::Call {
+                    call: get_blocks(1),
+                    result: Ok(FixtureResponse::Blocks((1..=6).map(block).collect())),
+                },
+                FixtureEntry::Call {
+                    call: get_blocks(7),
+                    result: Ok(FixtureResponse::Blocks((7..=9).map(block).collect())),
+                },
+                FixtureEntry::Call {
+                    call: FixtureCall::GetBlock {
+                        hash: RpcHash::from_u64_word(2),
+                        include_transactions: false,
+                    },
+                    result: Ok(FixtureResponse::Block(block(2))),
+                },
+                FixtureEntry::Call {
+                    call: get_blocks(9),
+                    result: Ok(FixtureResponse::Blocks(vec![])),
+                },
+                FixtureEntry::Call {
+                    call: FixtureCall::GetBlockDagInfo,
+                    result: Err(TransportError::Disconnected),
+                },
+                added(4),
+                added(8),
+                FixtureEntry::Notification(Notification::VirtualDaaScoreChanged(
+                    VirtualDaaScoreChangedNotification {
+                        virtual_daa_score: 1,
+                    },
+                )),
+            ],
+        };
+        fixture.trim(3..=5);
+
+        let kept = fixture
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                FixtureEntry::Call {
+                    result: Ok(FixtureResponse::Blocks(blocks)),
+                    ..
+                } => {
+                    let scores = blocks.iter().map(|block| block.header.daa_score);
+                    format!("blocks {:?}", scores.collect::<Vec<_>>())
+                }
+                FixtureEntry::Call { call, .. } => format!("{call:?}"),
+                FixtureEntry::Notification(Notification::BlockAdded(added)) => {
+                    format!("added {}", added.block.header.daa_score)
+                }
+                FixtureEntry::Notification(_) => "notification".to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            [
+                "blocks [3, 4, 5]",
+                "blocks []",
+                "GetBlockDagInfo",
+                "added 4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recording_loads_back() {
+        let path = std::env::temp_dir().join(format!(
+            "kasia-indexer-fixture-{}.jsonl.gz",
+            std::process::id()
+        ));
+        let recorder = FixtureRecorder::create(&path).unwrap();
+        recorder.record_call(get_blocks(1), &Ok(vec![block(1), block(2)]));
+        recorder.record_call::<RpcBlock>(
+            FixtureCall::GetBlock {
+                hash: RpcHash::from_u64_word(3),
+                include_transactions: false,
+            },
+            &Err(TransportError::Rpc("block not found".to_string())),
+        );
+        recorder.finish().unwrap();
+        // entries after the end are dropped
+        recorder.record_call(get_blocks(2), &Ok(vec![block(2)]));
+
+        let client = FixtureRpcClient::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(client.remaining(), 2);
+        let blocks = client.replay::<Vec<RpcBlock>>(get_blocks(1)).unwrap();
+        assert_eq!(blocks[1].header.hash, RpcHash::from_u64_word(2));
+        let err = client
+            .replay::<RpcBlock>(FixtureCall::GetBlock {
+                hash: RpcHash::from_u64_word(3),
+                include_transactions: false,
+            })
+            .unwrap_err();
+        assert!(err.is_not_found());
+    }
+}
@@ -0,0 +1,242 @@
+//! Periodic rescan of the pending gaps, so gaps left behind by failed or never started syncers
+//! are synced without anyone noticing them first.
+
+use crate::RK_PRUNING_DEPTH;
+use crate::database::headers::{BlockGap, BlockGapsPartition};
+use crate::historical_syncer::{ActiveSyncers, SyncerProgress};
+use crate::metrics::SharedMetrics;
+use crate::scheduler::{PeriodicTask, TaskContext};
+use kaspa_rpc_core::RpcHash;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{info, warn};
+
+pub const DEFAULT_MAX_GAP_SYNCERS: usize = 4;
+const GAP_RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+/// Delay before the first retry of a failed gap, doubled with every further failure
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Hands pending gaps nobody syncs to the subscriber, which spawns a syncer per gap. Gaps
+/// closest to the tip go first, up to `max_syncers` running syncers
+#[derive(bon::Builder)]
+pub struct GapRescan {
+    block_gaps_partition: BlockGapsPartition,
+    active_syncers: ActiveSyncers,
+    /// The backfill requests of the subscriber
+    backfill_requests: Sender<BlockGap>,
+    virtual_daa: Arc<AtomicU64>,
+    metrics: SharedMetrics,
+    #[builder(default = DEFAULT_MAX_GAP_SYNCERS)]
+    max_syncers: usize,
+    /// Gaps handed out before, by their start and end block
+    #[builder(skip)]
+    attempts: HashMap<(RpcHash, RpcHash), Attempt>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attempt {
+    /// Handed out since the last check
+    requested: bool,
+    failures: u32,
+    retry_at: Instant,
+}
+
+impl PeriodicTask for GapRescan {
+    fn name(&self) -> &'static str {
+        "gap_rescan"
+    }
+
+    fn interval(&self) -> Duration {
+        GAP_RESCAN_INTERVAL
+    }
+
+    fn run(&mut self, _ctx: &TaskContext) -> anyhow::Result<()> {
+        // zero until the subscriber connected, which syncs the recorded gaps itself then
+        let virtual_daa = self.virtual_daa.load(Ordering::Relaxed);
+        if virtual_daa == 0 {
+            return Ok(());
+        }
+        let gaps = self
+            .block_gaps_partition
+            .get_all_gaps_since_daa(virtual_daa.saturating_sub(RK_PRUNING_DEPTH * 2))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let active = self.active_syncers.snapshot();
+        let mut selected = self.select(gaps, &active, Instant::now()).into_iter();
+        while let Some(gap) = selected.next() {
+            match self.backfill_requests.try_send(gap.clone()) {
+                Ok(()) => info!(?gap, "Requesting a syncer for a pending gap"),
+                Err(TrySendError::Full(_)) => {
+                    // not requested after all, the next rescan picks them up again
+                    for gap in std::iter::once(gap).chain(selected) {
+                        if let Some(attempt) = self.attempts.get_mut(&key(&gap)) {
+                            attempt.requested = false;
+                        }
+                    }
+                    break;
+                }
+                Err(TrySendError::Closed(_)) => anyhow::bail!("backfill requests closed"),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GapRescan {
+    /// Gaps to request syncers for, closest to the tip first. A gap handed out before and
+    /// neither synced nor being synced by now failed and is retried after a growing delay
+    fn select(
+        &mut self,
+        gaps: Vec<BlockGap>,
+        active: &[SyncerProgress],
+        now: Instant,
+    ) -> Vec<BlockGap> {
+        // gaps gone were filled
+        self.attempts
+            .retain(|pending, _| gaps.iter().any(|gap| key(gap) == *pending));
+        let mut candidates = Vec::new();
+        for gap in gaps {
+            if active.iter().any(|progress| progress.fills(&gap)) {
+                continue;
+            }
+            if let Some(attempt) = self.attempts.get_mut(&key(&gap)) {
+                if attempt.requested {
+                    attempt.requested = false;
+                    attempt.failures += 1;
+                    attempt.retry_at = now + retry_delay(attempt.failures);
+                    warn!(
+                        ?gap,
+                        failures = attempt.failures,
+                        "Gap is still pending after its syncer stopped, backing off"
+                    );
+                    self.metrics.increment_gap_sync_failures();
+                }
+                if attempt.retry_at > now {
+                    continue;
+                }
+            }
+            candidates.push(gap);
+        }
+        self.metrics.set_gaps_backing_off(
+            self.attempts
+                .values()
+                .filter(|attempt| attempt.retry_at > now)
+                .count() as u64,
+        );
+
+        candidates.sort_by(|a, b| b.to_daa_score.cmp(&a.to_daa_score));
+        candidates.truncate(self.max_syncers.saturating_sub(active.len()));
+        for gap in &candidates {
+            self.attempts
+                .entry(key(gap))
+                .or_insert(Attempt {
+                    requested: false,
+                    failures: 0,
+                    retry_at: now,
+                })
+                .requested = true;
+        }
+        candidates
+    }
+}
+
+fn key(gap: &BlockGap) -> (RpcHash, RpcHash) {
+    (gap.from_block_hash, gap.to_block_hash)
+}
+
+fn retry_delay(failures: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::historical_syncer::{Cursor, SyncStats};
+    use crate::metrics::create_shared_metrics;
+    use kaspa_math::Uint192;
+
+    fn cursor(daa_score: u64) -> Cursor {
+        Cursor::new(
+            daa_score,
+            Uint192::from_u64(daa_score * 10),
+            RpcHash::from_u64_word(daa_score),
+        )
+    }
+
+    fn gap(from: u64, to: u64) -> BlockGap {
+        BlockGap::from_cursors(cursor(from), cursor(to))
+    }
+
+    fn syncing(gap: &BlockGap) -> SyncerProgress {
+        let from = cursor(gap.from_daa_score);
+        SyncerProgress {
+            from,
+            current: from,
+            target: cursor(gap.to_daa_score),
+            initial: false,
+            stats: SyncStats {
+                total_blocks_processed: 0,
+                batches_processed: 0,
+                current_blue_work: from.blue_work,
+                target_blue_work: gap.to_blue_work,
+                anticone_candidates_count: 0,
+                wait_for_slot: Duration::ZERO,
+                rpc_latency: Duration::ZERO,
+            },
+        }
+    }
+
+    #[test]
+    fn test_select_prioritizes_and_backs_off() {
+        let keyspace = crate::database::test_keyspace("gap-rescan");
+        let metrics = create_shared_metrics();
+        let (tx, _rx) = tokio::sync::mpsc::channel(8);
+        let mut rescan = GapRescan::builder()
+            .block_gaps_partition(BlockGapsPartition::new(&keyspace).unwrap())
+            .active_syncers(ActiveSyncers::default())
+            .backfill_requests(tx)
+            .virtual_daa(Default::default())
+            .metrics(metrics.clone())
+            .max_syncers(2)
+            .build();
+        let (old, middle, recent) = (gap(100, 200), gap(300, 400), gap(500, 600));
+        let gaps = || vec![old.clone(), middle.clone(), recent.clone()];
+        let start = Instant::now();
+
+        // one slot is taken by the syncer of the middle gap
+        let selected = rescan.select(gaps(), &[syncing(&middle)], start);
+        assert_eq!(selected, vec![recent.clone()]);
+
+        // the recent gap failed, the old one goes first while it backs off
+        let selected = rescan.select(gaps(), &[], start);
+        assert_eq!(selected, vec![middle.clone(), old.clone()]);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.gap_sync_failures, 1);
+        assert_eq!(snapshot.gaps_backing_off, 1);
+
+        // the middle gap was filled and is forgotten, the recent one is retried once its
+        // delay passed and waits twice as long after failing again
+        let gaps = || vec![old.clone(), recent.clone()];
+        let selected = rescan.select(gaps(), &[syncing(&old)], start + RETRY_DELAY);
+        assert_eq!(selected, vec![recent.clone()]);
+        assert_eq!(rescan.attempts.len(), 2);
+        let later = start + RETRY_DELAY * 2;
+        assert!(rescan.select(gaps(), &[syncing(&old)], later).is_empty());
+        assert_eq!(
+            rescan.attempts[&key(&recent)].retry_at,
+            later + RETRY_DELAY * 2
+        );
+        assert_eq!(metrics.snapshot().gap_sync_failures, 2);
+
+        let selected = rescan.select(vec![recent.clone()], &[], later + RETRY_DELAY * 2);
+        assert_eq!(selected, vec![recent.clone()]);
+        assert_eq!(rescan.attempts.len(), 1);
+        assert_eq!(retry_delay(40), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,204 @@
+//! Re-validation of stored headers after kaspa consensus crate upgrades.
+//!
+//! The consensus crate version headers were last validated with is kept in metadata. The
+//! first run with another version starts a new validation, the periodic processor then
+//! recomputes the hashes of a sample of stored full headers one batch per tick. The
+//! watermark is persisted after every batch, so the scan resumes across restarts.
+//!
+//! Received blocks can be verified the same way before they are indexed, see
+//! [`verify_header_hash`].
+
+use crate::database::headers::{BlockCompactHeaderPartition, StoredHeader, header_codec};
+use crate::database::metadata::{HeaderValidationState, MetadataPartition};
+use crate::metrics::SharedMetrics;
+use anyhow::Result;
+use fjall::TxKeyspace;
+use kaspa_consensus_core::hashing;
+use kaspa_consensus_core::header::Header;
+use kaspa_rpc_core::{RpcHash, RpcHeader};
+use std::fmt;
+use tracing::{error, info};
+
+/// Version and source of the kaspa-consensus-core crate this binary was built with
+pub const CONSENSUS_CORE_VERSION: &str = env!("KASPA_CONSENSUS_CORE_VERSION");
+pub const DEFAULT_VALIDATION_DENSITY_PERCENT: u8 = 10;
+const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+#[derive(bon::Builder)]
+pub struct HeaderValidator {
+    tx_keyspace: TxKeyspace,
+    block_compact_header_partition: BlockCompactHeaderPartition,
+    metadata_partition: MetadataPartition,
+    metrics: SharedMetrics,
+    /// Percentage of stored headers validated, 100 validates all of them
+    #[builder(default = DEFAULT_VALIDATION_DENSITY_PERCENT)]
+    density_percent: u8,
+    /// Headers scanned per tick
+    #[builder(default = DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+}
+
+impl HeaderValidator {
+    /// Starts a new validation if headers were last validated with another consensus crate.
+    /// Returns whether a validation is pending
+    pub fn prepare(&self) -> Result<bool> {
+        match self.metadata_partition.get_header_validation()? {
+            Some(state) if state.consensus_version == CONSENSUS_CORE_VERSION => {
+                Ok(!state.completed)
+            }
+            previous => {
+                info!(
+                    previous = previous.map(|state| state.consensus_version),
+                    current = CONSENSUS_CORE_VERSION,
+                    "Consensus crate changed, stored headers will be re-validated"
+                );
+                self.metadata_partition
+                    .set_header_validation(&HeaderValidationState::new(CONSENSUS_CORE_VERSION))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Validates the next batch, does nothing once the validation completed
+    pub fn step(&self) -> Result<()> {
+        let Some(mut state) = self.metadata_partition.get_header_validation()? else {
+            return Ok(());
+        };
+        if state.completed || state.consensus_version != CONSENSUS_CORE_VERSION {
+            return Ok(());
+        }
+        let rtx = self.tx_keyspace.read_tx();
+        let mut scanned = 0;
+        for r in self
+            .block_compact_header_partition
+            .scan_after_rtx(&rtx, state.watermark)
+            .take(self.batch_size)
+        {
+            let (hash, value) = r?;
+            scanned += 1;
+            state.watermark = Some(hash);
+            if !is_sampled(&hash, self.density_percent) {
+                continue;
+            }
+            let computed = match header_codec::decode(&value) {
+                Ok(StoredHeader::Full(header)) => hashing::header::hash(&Header::from(&*header)),
+                // compact headers carry nothing to hash
+                Ok(StoredHeader::Compact(_)) => continue,
+                Err(err) => {
+                    error!(%hash, "Stored header no longer decodes: {err}");
+                    state.checked += 1;
+                    state.mismatches += 1;
+                    self.metrics.increment_header_validation_mismatches();
+                    continue;
+                }
+            };
+            state.checked += 1;
+            if computed != hash {
+                error!(
+                    %hash,
+                    %computed,
+                    consensus_version = CONSENSUS_CORE_VERSION,
+                    "Stored header hashes differently with the current consensus crate"
+                );
+                state.mismatches += 1;
+                self.metrics.increment_header_validation_mismatches();
+            }
+        }
+        if scanned < self.batch_size {
+            state.completed = true;
+            if state.mismatches == 0 {
+                info!(checked = state.checked, "Header re-validation completed");
+            } else {
+                error!(
+                    checked = state.checked,
+                    mismatches = state.mismatches,
+                    "Header re-validation completed with mismatches"
+                );
+            }
+        }
+        self.metadata_partition.set_header_validation(&state)
+    }
+}
+
+/// Header whose fields hash differently than the hash the node reported for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderHashMismatch {
+    pub reported: RpcHash,
+    pub computed: RpcHash,
+}
+
+impl fmt::Display for HeaderHashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Header reported as {} hashes to {}",
+            self.reported, self.computed
+        )
+    }
+}
+
+impl std::error::Error for HeaderHashMismatch {}
+
+/// Recomputes the hash of a received header with the consensus hashing
+pub fn verify_header_hash(header: &RpcHeader) -> Result<(), HeaderHashMismatch> {
+    let computed = hashing::header::hash(&Header::from(header));
+    if computed != header.hash {
+        return Err(HeaderHashMismatch {
+            reported: header.hash,
+            computed,
+        });
+    }
+    Ok(())
+}
+
+/// Block hashes are uniformly distributed, their first bytes pick a sample that stays the
+/// same across restarts
+fn is_sampled(hash: &RpcHash, density_percent: u8) -> bool {
+    let bytes = hash.as_bytes();
+    let bucket = u16::from_le_bytes([bytes[0], bytes[1]]) as u32;
+    bucket * 100 < density_percent as u32 * 65536
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_density() {
+        let hashes = (0..10_000u64)
+            .map(|i| {
+                let mut bytes = [0u8; 32];
+                bytes[..2].copy_from_slice(&((i * 6553) as u16).to_le_bytes());
+                RpcHash::from_bytes(bytes)
+            })
+            .collect::<Vec<_>>();
+        let sampled = |density| hashes.iter().filter(|h| is_sampled(h, density)).count();
+        assert_eq!(sampled(0), 0);
+        assert_eq!(sampled(100), hashes.len());
+        let ten_percent = sampled(10);
+        assert!((900..1100).contains(&ten_percent), "{ten_percent}");
+    }
+
+    #[test]
+    fn test_verify_header_hash() {
+        let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(1), vec![]);
+        header.daa_score = 7;
+        header.finalize();
+        let mut received = RpcHeader::from(&header);
+        verify_header_hash(&received).unwrap();
+
+        // a field changed on the way, the reported hash no longer matches
+        received.daa_score = 8;
+        header.daa_score = 8;
+        let mismatch = verify_header_hash(&received).unwrap_err();
+        assert_eq!(mismatch.reported, received.hash);
+        assert_eq!(mismatch.computed, hashing::header::hash(&header));
+        assert_ne!(mismatch.reported, mismatch.computed);
+    }
+
+    #[test]
+    fn test_consensus_core_version_is_known() {
+        assert_ne!(CONSENSUS_CORE_VERSION, "unknown");
+        assert!(CONSENSUS_CORE_VERSION.starts_with(char::is_numeric));
+    }
+}
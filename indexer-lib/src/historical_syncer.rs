@@ -1,16 +1,31 @@
-use crate::database::headers::{BlockGap, BlockGapsPartition};
-use crate::{APP_IS_RUNNING, BlockOrMany};
-use anyhow::bail;
+use crate::BlockOrMany;
+use crate::database::headers::{
+    BlockGap, BlockGapsPartition, GapHistoryPartition, GapHistoryRecord, GapSyncEvent,
+};
+use crate::error::{IndexerError, IndexerResult};
+use crate::ingest_trace::{TRACE_TARGET, TraceContext};
+use crate::metrics::SharedMetrics;
+use crate::rpc_dispatcher::RpcDispatcher;
+use crate::rpc_transport::{RetryBackoff, RpcNode, TransportError};
+use crate::shutdown::Shutdown;
 use itertools::FoldWhile::{Continue, Done};
 use itertools::Itertools;
 use kaspa_math::Uint192;
-use kaspa_rpc_core::api::ops::RpcApiOps;
-use kaspa_rpc_core::{GetBlocksRequest, GetBlocksResponse, RpcBlock, RpcHash, RpcHeader};
-use kaspa_wrpc_client::KaspaRpcClient;
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcHeader};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::task;
-use tracing::{debug, error, info, trace, warn};
-use workflow_serializer::prelude::Serializable;
+use tracing::{Instrument, debug, debug_span, error, info, trace, warn};
+
+/// A historical syncer waiting this long to hand a batch to the block processor warns
+pub const DEFAULT_INTAKE_STALL_WARNING: Duration = Duration::from_secs(30);
+/// Stall warnings of all syncers together are logged at most this often
+const STALL_WARNING_INTERVAL: Duration = Duration::from_secs(300);
+/// Batches between progress logs and gap history checkpoints
+const CHECKPOINT_BATCHES: u64 = 100;
 
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Default)]
 pub struct Cursor {
@@ -80,28 +95,48 @@ pub struct HistoricalDataSyncer {
     /// Candidates for anticone resolution during sync
     anticone_candidates: Vec<Cursor>,
 
-    /// RPC client for communicating with Kaspa node
-    rpc_client: KaspaRpcClient,
+    /// Node connection, wRPC or gRPC
+    rpc_client: RpcNode,
+    /// Url of the node, sent along with its blocks
+    node: Arc<str>,
     /// Channel to send processed blocks to handler
     block_handler: flume::Sender<BlockOrMany>,
-    /// Shutdown signal receiver
-    shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+    /// Cancelled on shutdown or to stop this syncer alone
+    shutdown: Shutdown,
 
     /// Statistics for monitoring
     total_blocks_processed: u64,
     batches_processed: u64,
+    /// Time spent waiting for a dispatcher slot
+    wait_for_slot: Duration,
+    /// Time spent in RPC calls once a slot was granted
+    rpc_latency: Duration,
+    /// Transient RPC failures retried
+    retries: u32,
+    started_at_ms: u64,
 
     block_gaps_partition: BlockGapsPartition,
+    /// Receives the start, checkpoints and outcome of the sync
+    gap_history: Option<GapHistoryPartition>,
+
+    /// Shared dispatcher together with this syncer's queue id
+    dispatcher: Option<(RpcDispatcher, u64)>,
+    /// Counts the blocks sent into the intake
+    metrics: Option<SharedMetrics>,
+    /// Where the progress is published together with this syncer's id
+    active_syncers: Option<(ActiveSyncers, u64)>,
+    /// Warns when the intake stays full for too long
+    stall_warning: Option<IntakeStallWarning>,
 }
 
 impl HistoricalDataSyncer {
     /// Creates a new historical data syncer
     pub fn new(
-        rpc_client: KaspaRpcClient,
+        rpc_client: RpcNode,
         start_cursor: Cursor,
         target_cursor: Cursor,
         block_handler: flume::Sender<BlockOrMany>,
-        shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+        shutdown: Shutdown,
         block_gaps_partition: BlockGapsPartition,
     ) -> Self {
         info!(
@@ -119,34 +154,124 @@ impl HistoricalDataSyncer {
             current_cursor: start_cursor,
             target_cursor,
             anticone_candidates: Vec::new(),
+            node: Arc::from(rpc_client.url().unwrap_or_else(|| "primary".to_string())),
             rpc_client,
             block_handler,
-            shutdown_rx,
+            shutdown,
             total_blocks_processed: 0,
             batches_processed: 0,
+            wait_for_slot: Duration::ZERO,
+            rpc_latency: Duration::ZERO,
+            retries: 0,
+            started_at_ms: unix_ms(),
             block_gaps_partition,
+            gap_history: None,
+            dispatcher: None,
+            metrics: None,
+            active_syncers: None,
+            stall_warning: None,
         }
     }
 
-    /// Starts the synchronization process
-    pub async fn sync(&mut self) -> anyhow::Result<()> {
+    /// Issues RPC calls through a dispatcher shared with other syncers
+    pub fn with_dispatcher(mut self, dispatcher: RpcDispatcher) -> Self {
+        let worker_id = dispatcher.register_worker();
+        self.dispatcher = Some((dispatcher, worker_id));
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn with_gap_history(mut self, gap_history: GapHistoryPartition) -> Self {
+        self.gap_history = Some(gap_history);
+        self
+    }
+
+    /// Warns when a batch waits too long for room in the intake
+    pub fn with_stall_warning(mut self, stall_warning: IntakeStallWarning) -> Self {
+        self.stall_warning = Some(stall_warning);
+        self
+    }
+
+    /// Publishes the progress until the syncer is dropped, `initial` for the backfill from the
+    /// pruning point into an empty database
+    pub fn with_active_syncers(mut self, active_syncers: ActiveSyncers, initial: bool) -> Self {
+        let id = active_syncers.register(SyncerProgress {
+            from: self.from_cursor,
+            current: self.current_cursor,
+            target: self.target_cursor,
+            initial,
+            stats: self.get_sync_stats(),
+        });
+        self.active_syncers = Some((active_syncers, id));
+        self
+    }
+
+    /// Starts the synchronization process. Fails with [`IndexerError::Pruned`] once the node no
+    /// longer serves the blocks from the current cursor, an interruption by the shutdown is not
+    /// a failure
+    pub async fn sync(&mut self) -> IndexerResult<()> {
         info!("Starting historical data synchronization");
+        self.record_history(GapSyncEvent::Started, None);
+        let synced = self.sync_batches().await;
+        if let Err(err) = &synced {
+            self.record_history(GapSyncEvent::Failed, Some(format!("{err:#}")));
+        }
+        synced.map_err(|err| match TransportError::is_not_found_error(&err) {
+            true => IndexerError::Pruned {
+                from: self.current_cursor.hash,
+                source: err,
+            },
+            false => err.into(),
+        })
+    }
 
+    async fn sync_batches(&mut self) -> anyhow::Result<()> {
         loop {
+            let span = debug_span!(
+                target: TRACE_TARGET,
+                "ingest",
+                source = "historical",
+                blocks = tracing::field::Empty
+            );
+            let rpc_span = debug_span!(target: TRACE_TARGET, parent: &span, "rpc_get_blocks");
             let fetch_next_batch = async || {
-                get_blocks_with_retries(&self.rpc_client, self.current_cursor.hash, true, true)
-                    .await
-                    .inspect_err(|e| error!("RPC get_blocks failed: {}", e))
+                let wait_started = Instant::now();
+                let _slot = match &self.dispatcher {
+                    Some((dispatcher, worker_id)) => Some(dispatcher.acquire(*worker_id).await),
+                    None => None,
+                };
+                let waited = wait_started.elapsed();
+                let request_started = Instant::now();
+                get_blocks_with_retries(
+                    &self.rpc_client,
+                    &self.shutdown,
+                    self.current_cursor.hash,
+                    true,
+                    true,
+                )
+                .instrument(rpc_span.clone())
+                .await
+                .inspect_err(|e| match TransportError::is_not_found_error(e) {
+                    // the gap stays recorded, the next startup plan handles a pruned start
+                    true => warn!(
+                        from = %self.current_cursor.hash,
+                        "Node no longer serves the blocks of the gap, it was likely pruned: {e}"
+                    ),
+                    false => error!("RPC get_blocks failed: {}", e),
+                })
+                .map(|(blocks, retries)| (blocks, retries, waited, request_started.elapsed()))
             };
 
             // Check for shutdown signal and fetch next batch
-            let blocks = tokio::select! {
+            let (blocks, retries, waited, latency) = tokio::select! {
                 biased;
 
-                shutdown_result = &mut self.shutdown_rx => {
-                    shutdown_result
-                    .inspect(|_| info!("Shutdown signal received, stopping sync, overwriting current gap"))
-                    .inspect_err(|e|  warn!("Shutdown receiver error: {}", e))?;
+                _ = self.shutdown.cancelled() => {
+                    info!("Shutdown signal received, stopping sync, overwriting current gap");
 
                     // it prevents overlapping gaps in case of shutdown during initial sync
                     let new_gap = BlockGap::from_cursors(self.current_cursor, self.target_cursor);
@@ -156,33 +281,47 @@ impl HistoricalDataSyncer {
                         self.block_gaps_partition.add_gap(new_gap)?;
                         self.block_gaps_partition.remove_gap(old_gap)?;
                     }
+                    self.record_history(GapSyncEvent::Interrupted, None);
 
                     return Ok(())
                 }
                 response = fetch_next_batch() => response?,
             };
 
+            self.wait_for_slot += waited;
+            self.rpc_latency += latency;
+            self.retries += retries;
             let batch_size = blocks.len();
+            span.record("blocks", batch_size);
             debug!("Processing batch of {} blocks", batch_size);
 
             // Process the batch and check if target is reached
             let target_status = self.process_blocks_batch(&blocks)?;
 
             // Send blocks to handler
-            if let Err(e) = self
-                .block_handler
-                .send_async(BlockOrMany::Many(blocks))
-                .await
-            {
+            let blocks = BlockOrMany::Many(blocks, self.node.clone(), TraceContext::new(span));
+            if let Some(metrics) = &self.metrics {
+                metrics.add_intake_blocks(&blocks);
+            }
+            let send_started = Instant::now();
+            let sent = self.send_blocks(blocks).await;
+            if let Some(metrics) = &self.metrics {
+                metrics.add_historical_blocked(send_started.elapsed());
+            }
+            if let Err(e) = sent {
                 error!("Failed to send blocks to handler: {}", e);
-                return Err(anyhow::anyhow!("Block handler channel closed: {}", e));
+                return Err(IndexerError::ChannelClosed("block handler").into());
             }
 
             self.batches_processed += 1;
             self.total_blocks_processed += batch_size as u64;
+            if let Some((active_syncers, id)) = &self.active_syncers {
+                active_syncers.update(*id, self.current_cursor, self.get_sync_stats());
+            }
 
             // Log progress periodically
-            if self.batches_processed % 100 == 0 {
+            if self.batches_processed % CHECKPOINT_BATCHES == 0 {
+                self.record_history(GapSyncEvent::Checkpoint, None);
                 let initial_blue_work = self.from_cursor.blue_work;
                 let current_blue_work = self.current_cursor.blue_work;
                 let target_blue_work = self.target_cursor.blue_work;
@@ -201,6 +340,8 @@ impl HistoricalDataSyncer {
                     current_blue_work = %current_blue_work,
                     target_block = %self.target_cursor.hash,
                     target_blue_work = %target_blue_work,
+                    wait_for_slot = ?self.wait_for_slot,
+                    rpc_latency = ?self.rpc_latency,
                     "Sync progress: {}% ({} batches processed, {} blocks processed)",
                     percentage,
                     self.batches_processed,
@@ -225,11 +366,85 @@ impl HistoricalDataSyncer {
                     to_daa_score: self.target_cursor.daa_score,
                 };
                 task::spawn_blocking(move || gaps_partition.remove_gap(gap)).await??;
+                self.record_history(GapSyncEvent::Completed, None);
                 return Ok(());
             }
         }
     }
 
+    /// Failing to record is logged only, the sync goes on
+    fn record_history(&self, event: GapSyncEvent, error: Option<String>) {
+        let Some(gap_history) = &self.gap_history else {
+            return;
+        };
+        let record = GapHistoryRecord {
+            recorded_at_ms: unix_ms(),
+            from_block_hash: self.from_cursor.hash,
+            to_block_hash: self.target_cursor.hash,
+            event,
+            from_daa_score: self.from_cursor.daa_score,
+            to_daa_score: self.target_cursor.daa_score,
+            started_at_ms: self.started_at_ms,
+            current_daa_score: self.current_cursor.daa_score,
+            current_block_hash: self.current_cursor.hash,
+            blocks_processed: self.total_blocks_processed,
+            batches_processed: self.batches_processed,
+            retries: self.retries,
+            error,
+        };
+        if let Err(err) = gap_history.insert(&record) {
+            warn!(%event, "Failed to record gap history: {err:#}");
+        }
+    }
+
+    /// Waits for room in the intake, warning once the wait passes the stall threshold
+    async fn send_blocks(&self, blocks: BlockOrMany) -> Result<(), flume::SendError<BlockOrMany>> {
+        let Some((stall_warning, threshold)) = self
+            .stall_warning
+            .as_ref()
+            .and_then(|warning| Some((warning, warning.threshold()?)))
+        else {
+            return self.block_handler.send_async(blocks).await;
+        };
+        let started = Instant::now();
+        let send = self.block_handler.send_async(blocks);
+        tokio::pin!(send);
+        match tokio::time::timeout(threshold, &mut send).await {
+            Ok(sent) => sent,
+            Err(_) => {
+                if stall_warning.claim(Instant::now()) {
+                    self.warn_stalled(started.elapsed());
+                }
+                send.await
+            }
+        }
+    }
+
+    fn warn_stalled(&self, blocked: Duration) {
+        let depth = self.block_handler.len();
+        let capacity = self.block_handler.capacity().unwrap_or(usize::MAX);
+        let Some((stage, mean)) = self.metrics.as_ref().and_then(|m| m.slowest_block_stage())
+        else {
+            warn!(
+                ?blocked,
+                depth, capacity, "Block processor intake full, historical sync is stalled"
+            );
+            return;
+        };
+        let bound = match stage {
+            "decode" => "decode bound, consider more processing.block_workers",
+            _ => "database bound",
+        };
+        warn!(
+            ?blocked,
+            depth,
+            capacity,
+            slowest_stage = stage,
+            stage_mean_per_block = ?mean,
+            "Block processor intake full, historical sync is stalled, the processor is {bound}"
+        );
+    }
+
     /// Processes a batch of blocks and determines sync status
     fn process_blocks_batch(&mut self, blocks: &[RpcBlock]) -> anyhow::Result<SyncTargetStatus> {
         let block_count = blocks.len();
@@ -338,10 +553,103 @@ impl HistoricalDataSyncer {
             current_blue_work: self.current_cursor.blue_work,
             target_blue_work: self.target_cursor.blue_work,
             anticone_candidates_count: self.anticone_candidates.len(),
+            wait_for_slot: self.wait_for_slot,
+            rpc_latency: self.rpc_latency,
         }
     }
 }
 
+impl Drop for HistoricalDataSyncer {
+    fn drop(&mut self) {
+        if let Some((active_syncers, id)) = &self.active_syncers {
+            active_syncers.unregister(*id);
+        }
+    }
+}
+
+/// Progress of a running syncer
+#[derive(Debug, Clone)]
+pub struct SyncerProgress {
+    pub from: Cursor,
+    pub current: Cursor,
+    pub target: Cursor,
+    /// Backfill from the pruning point into an empty database
+    pub initial: bool,
+    pub stats: SyncStats,
+}
+
+impl SyncerProgress {
+    pub fn fills(&self, gap: &BlockGap) -> bool {
+        self.from.hash == gap.from_block_hash && self.target.hash == gap.to_block_hash
+    }
+}
+
+/// Running syncers by start order, clones share the set
+#[derive(Debug, Clone, Default)]
+pub struct ActiveSyncers(Arc<Mutex<(u64, BTreeMap<u64, SyncerProgress>)>>);
+
+impl ActiveSyncers {
+    fn register(&self, progress: SyncerProgress) -> u64 {
+        let mut guard = self.0.lock();
+        let (next_id, syncers) = &mut *guard;
+        *next_id += 1;
+        syncers.insert(*next_id, progress);
+        *next_id
+    }
+
+    fn update(&self, id: u64, current: Cursor, stats: SyncStats) {
+        if let Some(progress) = self.0.lock().1.get_mut(&id) {
+            progress.current = current;
+            progress.stats = stats;
+        }
+    }
+
+    fn unregister(&self, id: u64) {
+        self.0.lock().1.remove(&id);
+    }
+
+    pub fn snapshot(&self) -> Vec<SyncerProgress> {
+        self.0.lock().1.values().cloned().collect()
+    }
+
+    /// Whether a running syncer fills the gap
+    pub fn is_syncing(&self, gap: &BlockGap) -> bool {
+        self.0.lock().1.values().any(|progress| progress.fills(gap))
+    }
+}
+
+/// Stall warning shared by the historical syncers, so a slow processor is reported once
+/// rather than by every syncer waiting on it
+#[derive(Debug, Clone)]
+pub struct IntakeStallWarning {
+    threshold: Duration,
+    last_warned: Arc<Mutex<Option<Instant>>>,
+}
+
+impl IntakeStallWarning {
+    /// Warns about sends waiting longer than `threshold`, zero never warns
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            last_warned: Default::default(),
+        }
+    }
+
+    fn threshold(&self) -> Option<Duration> {
+        (!self.threshold.is_zero()).then_some(self.threshold)
+    }
+
+    /// Whether a warning may be logged at `now`, later claims are refused for a while
+    fn claim(&self, now: Instant) -> bool {
+        let mut last_warned = self.last_warned.lock();
+        if last_warned.is_some_and(|at| now.duration_since(at) < STALL_WARNING_INTERVAL) {
+            return false;
+        }
+        *last_warned = Some(now);
+        true
+    }
+}
+
 /// Statistics for monitoring sync progress
 #[derive(Debug, Clone)]
 pub struct SyncStats {
@@ -350,43 +658,114 @@ pub struct SyncStats {
     pub current_blue_work: Uint192,
     pub target_blue_work: Uint192,
     pub anticone_candidates_count: usize,
+    /// Time spent queued behind other syncers sharing the RPC connection
+    pub wait_for_slot: Duration,
+    /// Time spent in GetBlocks calls, excluding queueing
+    pub rpc_latency: Duration,
 }
 
+/// Transient failures are retried with a growing backoff, other errors are returned with
+/// their [`TransportError`]. Returns the blocks with the amount of retries
 async fn get_blocks_with_retries(
-    client: &KaspaRpcClient,
+    client: &RpcNode,
+    shutdown: &Shutdown,
     rpc_hash: RpcHash,
     include_blocks: bool,
     include_txs: bool,
-) -> anyhow::Result<Vec<RpcBlock>> {
+) -> anyhow::Result<(Vec<RpcBlock>, u32)> {
+    let mut backoff = RetryBackoff::default();
+    let mut retries = 0;
     loop {
-        if !APP_IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
-            bail!("App is stopped");
+        if shutdown.is_cancelled() {
+            return Err(IndexerError::Shutdown.into());
         }
         if !client.is_connected() {
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             continue;
         }
         match client
-            .rpc_client()
-            .call(
-                RpcApiOps::GetBlocks,
-                Serializable(GetBlocksRequest::new(
-                    Some(rpc_hash),
-                    include_blocks,
-                    include_txs,
-                )),
-            )
+            .get_blocks(rpc_hash, include_blocks, include_txs)
             .await
         {
-            Ok(Serializable(GetBlocksResponse { blocks, .. })) => return Ok(blocks),
-            Err(
-                workflow_rpc::client::error::Error::Disconnect
-                | workflow_rpc::client::error::Error::Timeout,
-            ) => {
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            Ok(blocks) => return Ok((blocks, retries)),
+            Err(err) if err.is_transient() => {
+                retries += 1;
+                let delay = backoff.next_delay();
+                warn!(%rpc_hash, ?delay, "Blocks request failed, retrying: {err}");
+                tokio::time::sleep(delay).await;
                 continue;
             }
             Err(e) => return Err(e.into()),
         }
     }
 }
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::FixtureRpcClient;
+    use std::path::Path;
+
+    #[test]
+    fn test_stall_warning_is_rate_limited() {
+        assert_eq!(IntakeStallWarning::new(Duration::ZERO).threshold(), None);
+
+        let warning = IntakeStallWarning::new(DEFAULT_INTAKE_STALL_WARNING);
+        let other_syncer = warning.clone();
+        let now = Instant::now();
+        assert!(warning.claim(now));
+        assert!(!other_syncer.claim(now + Duration::from_secs(1)));
+        assert!(other_syncer.claim(now + STALL_WARNING_INTERVAL));
+    }
+
+    /// The target is off the chain in the anticone of the start, so the node never returns it.
+    /// A block of its anticone with more blue work is merged by a later chain block instead
+    #[tokio::test]
+    async fn test_target_found_via_anticone_fixture() {
+        let fixture = FixtureRpcClient::load(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/anticone_target.jsonl.gz"
+        )))
+        .unwrap();
+        let keyspace = crate::database::test_keyspace("anticone");
+        let gaps = BlockGapsPartition::new(&keyspace).unwrap();
+        let hash = |byte| RpcHash::from_bytes([byte; 32]);
+        let start = Cursor::new(100, Uint192::from_u64(0x100), hash(1));
+        let target = Cursor::new(102, Uint192::from_u64(0x130), hash(9));
+        gaps.add_gap(BlockGap::from_cursors(start, target)).unwrap();
+
+        let (block_handler, intake) = flume::unbounded();
+        let mut syncer = HistoricalDataSyncer::new(
+            RpcNode::from(fixture),
+            start,
+            target,
+            block_handler,
+            Shutdown::new(),
+            gaps.clone(),
+        );
+        syncer.sync().await.unwrap();
+
+        let batches = intake
+            .drain()
+            .map(|batch| match batch {
+                BlockOrMany::Many(blocks, ..) => blocks
+                    .iter()
+                    .map(|block| block.header.daa_score)
+                    .collect::<Vec<_>>(),
+                BlockOrMany::Block(..) => panic!("historical syncers send batches"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(batches, [vec![100, 101, 102], vec![102, 103]]);
+        // the second batch starts from the candidate again
+        assert_eq!(syncer.get_sync_stats().anticone_candidates_count, 2);
+        assert_eq!(syncer.current_cursor.hash, hash(4));
+        assert_eq!(gaps.get_all_gaps_rtx(&keyspace.read_tx()).count(), 0);
+    }
+}
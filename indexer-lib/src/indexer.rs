@@ -0,0 +1,1417 @@
+//! The whole indexer behind one handle.
+//!
+//! [`Indexer::builder`] opens the database, creates the node clients and builds every component
+//! with its channels. [`Indexer::run`] spawns them and follows the node until
+//! [`Indexer::shutdown`] is called or the subscriber stops, then stops them stage by stage.
+
+use crate::acceptance_slo::AcceptanceSlo;
+use crate::block_events::IndexedBlocks;
+use crate::block_processor::BlockProcessor;
+use crate::call_limiter::CallLimiter;
+use crate::config::{IndexerConfig, NodeConfig, RpcConfig};
+use crate::database::aggregates::Aggregates;
+use crate::database::balances::Balances;
+use crate::database::block_stats::BlockStatsPartition;
+use crate::database::block_transactions::BlockTransactionsPartition;
+use crate::database::crash_reports::CrashReportsPartition;
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockGapsPartition, BlockRelationsPartition,
+    ChainIndexByHashPartition, ChainIndexPartition, ChainMembershipPartition, DaaIndexPartition,
+    GapHistoryPartition, GapHistoryRecord,
+};
+use crate::database::integrity;
+use crate::database::messages::{
+    ContextualMessageBySenderPartition, HandshakeByReceiverPartition, HandshakeBySenderPartition,
+    PaymentByReceiverPartition, PaymentBySenderPartition, TxIdToHandshakePartition,
+    TxIdToPaymentPartition,
+};
+use crate::database::metadata::MetadataPartition;
+use crate::database::miners::{BlockMinerPartition, MinerBlocksPartition};
+use crate::database::processing::{
+    AcceptanceGapsPartition, AcceptanceHistoryPartition, AcceptingBlockToTxIDPartition,
+    FinalizedTxPartition, IndexedOutput, OrphanPoolPartition, OutpointPartition,
+    PendingSenderResolutionPartition, PendingSpendPartition, ProcessedBlockPartition,
+    SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition, TxIdFilter,
+    TxInputPartition, UnknownAcceptingDaaPartition, UnknownTxPartition,
+};
+use crate::database::provenance::ProvenancePartition;
+use crate::database::supply::{BlockRewardPartition, Supply};
+use crate::database::token_operations::TokenOperationPartition;
+use crate::database::webhooks::Webhooks;
+use crate::error::{IndexerError, IndexerResult};
+use crate::fifo_set::FifoSet;
+use crate::fixture::FixtureRecorder;
+use crate::gap_rescan::GapRescan;
+use crate::header_validation::{CONSENSUS_CORE_VERSION, HeaderValidator};
+use crate::historical_syncer::{ActiveSyncers, IntakeStallWarning};
+use crate::ingest_filter::IngestFilter;
+use crate::mempool::{Mempool, MempoolProcessor};
+use crate::metrics::{IndexerMetricsSnapshot, SharedMetrics, create_shared_metrics_from_snapshot};
+use crate::metrics_exporter::{self, HealthCheck, MetricsRegistry, register_indexer_metrics};
+use crate::node_capabilities::SharedNodeCapabilities;
+use crate::node_pool::NodePool;
+use crate::periodic_processor::{Notification, PeriodicProcessor, run_ticker};
+use crate::queries::{FullBlock, Queries};
+use crate::reindex::{self, Reindex, ReindexSummary, ReindexTarget};
+use crate::reorder_buffer::DEFAULT_REORDER_CAPACITY;
+use crate::resolver::Resolver;
+use crate::rpc_dispatcher::RpcDispatcher;
+use crate::rpc_transport::{RpcNode, TransportError};
+use crate::selected_chain_syncer::{ChainRecovery, SelectedChainSyncer};
+use crate::shutdown::{Shutdown, ShutdownController, Stage};
+use crate::startup;
+use crate::status::{self, IndexerStatus};
+use crate::subscriber::Subscriber;
+use crate::supervisor::{RestartPolicy, Supervisor};
+use crate::supply_check::SupplyChecker;
+use crate::virtual_chain_processor::VirtualChainProcessor;
+use anyhow::{Context, Result};
+use fjall::{TxKeyspace, WriteTransaction};
+use kaspa_addresses::Prefix;
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_rpc_core::{RpcBlock, RpcHash, RpcTransactionOutpoint};
+use kaspa_wrpc_client::client::{ConnectOptions, ConnectStrategy};
+use kaspa_wrpc_client::{KaspaRpcClient, WrpcEncoding};
+use parking_lot::Mutex;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use workflow_core::channel::{Receiver, Sender};
+
+/// Channel capacities between the components
+const VCC_INTAKE_CAPACITY: usize = 4096;
+const CHAIN_INTAKE_CAPACITY: usize = 4096;
+const BACKFILL_REQUESTS_CAPACITY: usize = 64;
+const RESOLVER_REQUESTS_CAPACITY: usize = 16384;
+const RESOLVER_RESPONSES_CAPACITY: usize = 32768;
+
+pub struct Indexer {
+    config: IndexerConfig,
+    tx_keyspace: TxKeyspace,
+    metadata_partition: MetadataPartition,
+    outpoint_partition: OutpointPartition,
+    gap_history_partition: GapHistoryPartition,
+    /// Joined read paths
+    queries: Queries,
+    metrics: SharedMetrics,
+    indexed_blocks: IndexedBlocks,
+    /// Built when address balances are configured
+    balances: Option<Balances>,
+    /// Built when aggregates are configured
+    aggregates: Option<Aggregates>,
+    /// Built when webhooks are enabled
+    webhooks: Option<Webhooks>,
+    /// Built when the mempool is tracked
+    mempool: Option<Mempool>,
+    /// Built when the supply is tracked
+    supply: Option<Supply>,
+    rpc_client: KaspaRpcClient,
+    status: status::Indexer,
+    /// Restarts the processors after errors and panics
+    supervisor: Supervisor,
+    shutdown: ShutdownController,
+    /// Cancelled by [`Indexer::shutdown`]
+    stop: Shutdown,
+    /// Built but not running yet, taken by [`Indexer::run`]
+    components: Mutex<Option<Components>>,
+}
+
+struct Components {
+    block_worker: BlockProcessor,
+    acceptance_worker: VirtualChainProcessor,
+    scan_worker: PeriodicProcessor,
+    resolver: Resolver,
+    resolver_nodes: NodePool,
+    selected_chain_syncer: SelectedChainSyncer,
+    subscriber: Subscriber,
+    scan_worker_job_done_rx: Receiver<()>,
+    resolver_response_tx: Sender<Notification>,
+    /// Built when the query API is configured
+    #[cfg(feature = "api")]
+    query_api: Option<crate::api::QueryApi>,
+    /// Built when webhooks are enabled
+    #[cfg(feature = "webhooks")]
+    webhook_dispatcher: Option<crate::webhooks::WebhookDispatcher>,
+    /// Built when the mempool is tracked
+    mempool_processor: Option<MempoolProcessor>,
+    /// Built when the supply is tracked
+    supply_checker: Option<SupplyChecker>,
+}
+
+#[bon::bon]
+impl Indexer {
+    /// Opens the database at the configured path unless given one, and connects to the
+    /// configured node unless given a client. Nothing is spawned before [`Indexer::run`].
+    /// Fails with [`IndexerError::Validation`] for a database of another network or an ingest
+    /// filter change the configuration refuses
+    #[builder]
+    pub async fn new(
+        #[builder(default)] config: IndexerConfig,
+        database: Option<TxKeyspace>,
+        rpc_client: Option<KaspaRpcClient>,
+    ) -> IndexerResult<Self> {
+        Ok(Self::open(config, database, rpc_client).await?)
+    }
+
+    async fn open(
+        config: IndexerConfig,
+        database: Option<TxKeyspace>,
+        rpc_client: Option<KaspaRpcClient>,
+    ) -> Result<Self> {
+        let tx_keyspace = match database {
+            Some(tx_keyspace) => tx_keyspace,
+            None => crate::database::open(&config.db_path())?,
+        };
+        if config.startup_fsck {
+            info!("Running startup integrity check");
+            let report = integrity::check(&tx_keyspace, true)?;
+            if !report.is_consistent() {
+                return Err(IndexerError::Corrupt(report.to_string()).into());
+            }
+        }
+        let reorg_lock = Arc::new(Mutex::new(()));
+        // Partitions
+        let metadata_partition = MetadataPartition::new(&tx_keyspace)?;
+        {
+            metadata_partition.0.inner().major_compact()?;
+        }
+        let network_id = config.node.network_id().map_err(IndexerError::validation)?;
+        metadata_partition
+            .check_network_id(&network_id.to_string())
+            .map_err(IndexerError::validation)?;
+        let address_prefix = Prefix::from(network_id);
+
+        let handshake_by_receiver_partition = HandshakeByReceiverPartition::new(&tx_keyspace)?;
+        let tx_id_to_handshake_partition = TxIdToHandshakePartition::new(&tx_keyspace)?;
+        let contextual_message_partition = ContextualMessageBySenderPartition::new(&tx_keyspace)?;
+        let payment_by_receiver_partition = PaymentByReceiverPartition::new(&tx_keyspace)?;
+        let tx_id_to_payment_partition = TxIdToPaymentPartition::new(&tx_keyspace)?;
+        let tx_id_to_acceptance_partition = TxIDToAcceptancePartition::new(&tx_keyspace)?;
+        // only lookups of the API consult the filter
+        let tx_id_filter = (config.api.addr.is_some() && config.api.tx_filter_capacity > 0)
+            .then(|| {
+                TxIdFilter::build(
+                    config.api.tx_filter_capacity,
+                    config.api.tx_filter_fp_rate_ppm,
+                    &tx_id_to_acceptance_partition,
+                    &tx_keyspace.read_tx(),
+                )
+                .map(Arc::new)
+            })
+            .transpose()?;
+        let tx_id_to_acceptance_partition = match &tx_id_filter {
+            Some(filter) => {
+                info!(
+                    "Built the transaction id filter, estimated false positive rate {} ppm",
+                    filter.stats().fp_estimate_ppm
+                );
+                tx_id_to_acceptance_partition.with_filter(filter.clone())
+            }
+            None => tx_id_to_acceptance_partition,
+        };
+        let skip_tx_partition = SkipTxPartition::new(&tx_keyspace)?;
+        let skip_tx_by_block_partition = SkipTxByBlockPartition::new(&tx_keyspace)?;
+        metadata_partition
+            .check_header_storage_mode(config.storage.header_storage)
+            .map_err(IndexerError::validation)?;
+        let block_compact_header_partition = BlockCompactHeaderPartition::new_with_mode(
+            &tx_keyspace,
+            config.storage.header_storage,
+        )?
+        .with_cache_capacity(config.storage.header_cache_size);
+        let acceptance_to_tx_id_partition = AcceptingBlockToTxIDPartition::new(&tx_keyspace)?;
+        let unknown_tx_partition = UnknownTxPartition::new(&tx_keyspace)?;
+        let unknown_accepting_daa_partition = UnknownAcceptingDaaPartition::new(&tx_keyspace)?;
+        let pending_sender_resolution_partition =
+            PendingSenderResolutionPartition::new(&tx_keyspace)?;
+        let handshake_by_sender_partition = HandshakeBySenderPartition::new(&tx_keyspace)?;
+        let payment_by_sender_partition = PaymentBySenderPartition::new(&tx_keyspace)?;
+        let block_gaps_partition = BlockGapsPartition::new(&tx_keyspace)?;
+        let gap_history_partition = GapHistoryPartition::new(&tx_keyspace)?;
+        let block_daa_index_partition = DaaIndexPartition::new(&tx_keyspace)?;
+        let chain_membership_partition = ChainMembershipPartition::new(&tx_keyspace)?;
+        let block_relations_partition = BlockRelationsPartition::new(&tx_keyspace)?;
+        let chain_index_partition = ChainIndexPartition::new(&tx_keyspace)?;
+        let chain_index_by_hash_partition = ChainIndexByHashPartition::new(&tx_keyspace)?;
+        let orphan_pool_partition = OrphanPoolPartition::new(&tx_keyspace)?;
+        let block_miner_partition = BlockMinerPartition::new(&tx_keyspace)?;
+        let miner_blocks_partition = MinerBlocksPartition::new(&tx_keyspace)?;
+        let outpoint_partition = OutpointPartition::new(&tx_keyspace)?;
+        let pending_spend_partition = PendingSpendPartition::new(&tx_keyspace)?;
+        let block_stats_partition = BlockStatsPartition::new(&tx_keyspace)?;
+        let block_transactions_partition = BlockTransactionsPartition::new(&tx_keyspace)?;
+        let processed_block_partition = ProcessedBlockPartition::new(&tx_keyspace)?;
+        let acceptance_history_partition = AcceptanceHistoryPartition::new(&tx_keyspace)?;
+        let finalized_tx_partition = FinalizedTxPartition::new(&tx_keyspace)?;
+        let crash_reports_partition = CrashReportsPartition::new(&tx_keyspace)?;
+        let unviewed_crashes = crash_reports_partition
+            .list()?
+            .into_iter()
+            .filter(|report| !report.viewed)
+            .collect::<Vec<_>>();
+        if let Some(latest) = unviewed_crashes.last() {
+            warn!(
+                "{} unviewed crash reports, latest: {latest}. Inspect with `crash-reports list`",
+                unviewed_crashes.len()
+            );
+        }
+        let block_tip = startup::verify_block_tip(
+            &tx_keyspace,
+            &metadata_partition,
+            &block_compact_header_partition,
+            &processed_block_partition,
+        )?
+        .tip;
+        let ingest_filter = IngestFilter::from_config(&config.ingest_filter, address_prefix)
+            .map_err(IndexerError::validation)?;
+        let ingest_filter_state = metadata_partition
+            .check_ingest_filter(
+                ingest_filter.clone(),
+                config.ingest_filter.on_change,
+                block_tip.map_or(0, |tip| tip.daa_score + 1),
+            )
+            .map_err(IndexerError::validation)?;
+        if ingest_filter != IngestFilter::default() {
+            info!(
+                "Ingest filter {:016x} applies from DAA {}",
+                ingest_filter.filter_hash(),
+                ingest_filter_state.since_daa
+            );
+        }
+        if let Some(replay) = &ingest_filter_state.replay {
+            info!("Blocks of DAA {replay:?} are indexed again under the ingest filter on start");
+        }
+        info!(
+            "Gaps exist: {:?}",
+            block_gaps_partition
+                .get_all_gaps_since_daa(0)
+                .collect::<Result<Vec<_>, _>>()
+        );
+
+        let metrics = create_shared_metrics_from_snapshot(IndexerMetricsSnapshot {
+            handshakes_by_sender: handshake_by_sender_partition.approximate_len() as u64,
+            handshakes_by_receiver: tx_id_to_handshake_partition.approximate_len() as u64,
+            payments_by_sender: payment_by_sender_partition.approximate_len() as u64,
+            payments_by_receiver: tx_id_to_payment_partition.approximate_len() as u64,
+            contextual_messages: contextual_message_partition.len()? as u64,
+            blocks_processed: block_compact_header_partition.len()? as u64,
+            latest_block: block_tip.unwrap_or_default().hash,
+            latest_accepting_block: metadata_partition
+                .get_latest_accepting_block_cursor()?
+                .unwrap_or_default()
+                .hash,
+            unknown_daa_entries: unknown_accepting_daa_partition.len()? as u64,
+            unknown_sender_entries: pending_sender_resolution_partition.len()? as u64,
+            unknown_tx_entries: 0,
+            resolved_daa: 0,
+            resolved_senders: 0,
+            orphan_blocks: orphan_pool_partition.count_blocks_rtx(&tx_keyspace.read_tx())? as u64,
+            pending_spends: pending_spend_partition.len_rtx(&tx_keyspace.read_tx())? as u64,
+            orphans_reprocessed: 0,
+            orphans_over_capacity: 0,
+            orphan_backfills: 0,
+            unindexed_accepted_txs: 0,
+            auto_created_gaps: 0,
+            reorg_entries_removed: 0,
+            deep_reorgs: 0,
+            finality_violations: 0,
+            reconnects: 0,
+            reconnect_gap_daa: 0,
+            header_validation_mismatches: 0,
+            header_hash_mismatches: 0,
+            seconds_since_last_notification: 0,
+            resubscriptions: 0,
+            duplicate_block_notifications: 0,
+            mirror_blocks_first: 0,
+            mirror_reconnects: 0,
+            resolver_usable_nodes: 0,
+            resolver_nodes: 0,
+            resolver_failovers: 0,
+            rpc_permits_in_use: 0,
+            rpc_permit_wait_ms: 0,
+            rpc_open_breakers: 0,
+            node_connected: 0,
+            subscriber_blocks_processed: 0,
+            last_subscriber_block_unix_ms: 0,
+            block_intake_depth: 0,
+            blocks_dropped: 0,
+            overflow_gaps: 0,
+            gap_sync_failures: 0,
+            gaps_backing_off: 0,
+            component_restarts: 0,
+            indexed_block_events_dropped: 0,
+            subscriber_intake_depth: 0,
+            historical_intake_depth: 0,
+            subscriber_producer_blocked_ms: 0,
+            historical_producer_blocked_ms: 0,
+            block_e2e_latency: Default::default(),
+            block_decode_time: Default::default(),
+            header_hash_verification_time: Default::default(),
+            block_processing_time: Default::default(),
+            block_commit_time: Default::default(),
+            compactions: 0,
+            compactions_deferred: 0,
+            compaction_reclaimed_bytes: 0,
+            last_compaction_unix_ms: 0,
+            last_compaction_duration_ms: 0,
+            database: Default::default(),
+            periodic_tasks: Default::default(),
+            nodes: Default::default(),
+            header_cache_hits: 0,
+            header_cache_misses: 0,
+            tx_filter_negatives: 0,
+            tx_filter_positives: 0,
+            tx_filter_false_positives: 0,
+            tx_filter_fp_estimate_ppm: 0,
+            webhook_deliveries: 0,
+            webhook_delivery_failures: 0,
+            webhook_dead_letters: 0,
+            mempool_transactions: 0,
+            mempool_evictions: 0,
+            supply_divergence: 0,
+            supply_divergence_alarms: 0,
+            api_rate_limited: 0,
+            api_overloaded: 0,
+            api_limit_rejections: 0,
+            api_daa_range_rejections: 0,
+            api_entries_read: 0,
+            chain_sync_blocks: 0,
+            chain_sync_acceptance_records: 0,
+            chain_sync_remaining_daa: 0,
+            block_lag_daa: 0,
+            acceptance_lag_daa: 0,
+            lag_seconds: 0,
+        });
+        let shutdown = ShutdownController::new();
+        let processors = shutdown.stage(Stage::Processors);
+
+        let (block_intake_tx, block_intake_rx) =
+            flume::bounded(config.sync.realtime_intake_capacity);
+        let (historical_intake_tx, historical_intake_rx) =
+            flume::bounded(config.sync.historical_intake_capacity);
+
+        let (vcc_intake_tx, vcc_intake_rx) = flume::bounded(VCC_INTAKE_CAPACITY);
+        let virtual_daa = Arc::new(AtomicU64::new(0));
+        let node_capabilities = SharedNodeCapabilities::default();
+
+        let balances = config
+            .storage
+            .address_balances
+            .then(|| Balances::new(&tx_keyspace))
+            .transpose()?;
+        let aggregates = config
+            .storage
+            .aggregates
+            .then(|| {
+                let bucket_width = config.storage.aggregate_bucket_width;
+                metadata_partition.check_aggregate_bucket_width(bucket_width)?;
+                Aggregates::new(&tx_keyspace, bucket_width)
+            })
+            .transpose()?;
+        let webhooks = (cfg!(feature = "webhooks") && config.webhooks.enabled)
+            .then(|| Webhooks::new(&tx_keyspace))
+            .transpose()?;
+        let mempool = config.mempool.enabled.then(Mempool::new);
+        let supply = config
+            .supply
+            .enabled
+            .then(|| Supply::new(&tx_keyspace))
+            .transpose()?;
+
+        let (backfill_requests_tx, backfill_requests_rx) =
+            tokio::sync::mpsc::channel(BACKFILL_REQUESTS_CAPACITY);
+        let indexed_blocks = IndexedBlocks::new(config.storage.indexed_blocks_capacity)
+            .with_metrics(metrics.clone());
+
+        let rpc_client = match rpc_client {
+            Some(rpc_client) => rpc_client,
+            None => create_rpc_client(&config.node)?,
+        };
+        // shared by everything calling the primary node through the limiter
+        let primary_call_limiter = create_call_limiter(
+            &rpc_client.url().unwrap_or_else(|| "primary".to_string()),
+            &config.rpc,
+            &metrics,
+        );
+
+        let fixture_recorder = config
+            .node
+            .record_fixture
+            .as_deref()
+            .map(FixtureRecorder::create)
+            .transpose()?;
+        let recorded = |node: RpcNode| match &fixture_recorder {
+            Some(recorder) => node.with_recorder(recorder.clone()),
+            None => node,
+        };
+
+        let resolver_nodes = NodePool::new(
+            recorded(RpcNode::from(rpc_client.clone()).with_limiter(primary_call_limiter.clone())),
+            &network_id.to_string(),
+        )
+        .with_nodes(
+            create_resolver_rpc_clients(&config.node, &config.rpc, &metrics)
+                .await?
+                .into_iter()
+                .map(recorded)
+                .collect(),
+        )
+        .with_metrics(metrics.clone());
+        let mut block_worker = BlockProcessor::builder()
+            .processed_blocks(FifoSet::new(256))
+            .intake(block_intake_rx)
+            .historical_intake(historical_intake_rx)
+            .shutdown(processors.clone())
+            .tx_keyspace(tx_keyspace.clone())
+            .metadata_partition(metadata_partition.clone())
+            .handshake_by_receiver_partition(handshake_by_receiver_partition.clone())
+            .tx_id_to_handshake_partition(tx_id_to_handshake_partition.clone())
+            .contextual_message_partition(contextual_message_partition.clone())
+            .payment_by_receiver_partition(payment_by_receiver_partition.clone())
+            .tx_id_to_payment_partition(tx_id_to_payment_partition.clone())
+            .tx_id_to_acceptance_partition(tx_id_to_acceptance_partition.clone())
+            .skip_tx_partition(skip_tx_partition.clone())
+            .skip_tx_by_block_partition(skip_tx_by_block_partition.clone())
+            .block_compact_header_partition(block_compact_header_partition.clone())
+            .metrics(metrics.clone())
+            .processed_txs(FifoSet::new(
+                300/*txs per block*/ * 255, /*max mergeset size*/
+            ))
+            .block_daa_index(block_daa_index_partition.clone())
+            .chain_membership_partition(chain_membership_partition.clone())
+            .block_relations_partition(block_relations_partition.clone())
+            .orphan_pool_partition(orphan_pool_partition)
+            .block_miner_partition(block_miner_partition)
+            .miner_blocks_partition(miner_blocks_partition)
+            .outpoint_partition(outpoint_partition.clone())
+            .pending_spend_partition(pending_spend_partition)
+            .tx_input_partition(TxInputPartition::new(&tx_keyspace)?)
+            .block_stats_partition(block_stats_partition.clone())
+            .block_transactions_partition(block_transactions_partition.clone())
+            .maybe_aggregates(aggregates.clone())
+            .maybe_supply(supply.clone())
+            .maybe_mempool(mempool.clone())
+            .processed_block_partition(processed_block_partition.clone())
+            .index_outpoints(config.storage.outpoint_index)
+            .token_operation_partition(TokenOperationPartition::new(&tx_keyspace)?)
+            .index_token_operations(config.storage.token_operations)
+            .ingest_filter(Arc::new(ingest_filter))
+            .address_prefix(address_prefix)
+            .indexed_blocks(indexed_blocks.clone())
+            .virtual_daa(virtual_daa.clone())
+            .orphan_max_daa_distance(config.processing.orphan_max_daa_distance)
+            .max_orphan_blocks(config.processing.max_orphan_blocks)
+            .orphan_backfill_daa_distance(config.processing.orphan_backfill_daa_distance)
+            .pending_spend_max_daa_distance(config.processing.pending_spend_max_daa_distance)
+            .backfill_requests(backfill_requests_tx.clone())
+            .workers(config.processing.block_workers)
+            .flush_policy(config.processing.flush_policy())
+            .verify_historical_hashes(config.processing.verify_historical_hashes)
+            .verify_realtime_hashes(config.processing.verify_realtime_hashes)
+            .node_pool(resolver_nodes.clone())
+            .build();
+
+        let acceptance_slo = Arc::new(AcceptanceSlo::new(Duration::from_millis(
+            config.chain.acceptance_slo_ms,
+        )));
+        let mut acceptance_worker = VirtualChainProcessor::builder()
+            .daa_resolution_attempt_count(5)
+            .reorg_log(reorg_lock.clone())
+            .vcc_rx(vcc_intake_rx)
+            .shutdown(processors.clone())
+            .tx_keyspace(tx_keyspace.clone())
+            .metadata_partition(metadata_partition.clone())
+            .skip_tx_partition(skip_tx_partition.clone())
+            .tx_id_to_acceptance_partition(tx_id_to_acceptance_partition.clone())
+            .acceptance_to_tx_id_partition(acceptance_to_tx_id_partition.clone())
+            .unknown_tx_partition(unknown_tx_partition.clone())
+            .unknown_accepting_daa_partition(unknown_accepting_daa_partition.clone())
+            .block_compact_header_partition(block_compact_header_partition.clone())
+            .chain_membership_partition(chain_membership_partition.clone())
+            .chain_index_partition(chain_index_partition.clone())
+            .chain_index_by_hash_partition(chain_index_by_hash_partition.clone())
+            .pending_sender_resolution_partition(pending_sender_resolution_partition.clone())
+            .acceptance_history_partition(acceptance_history_partition.clone())
+            .finalized_tx_partition(finalized_tx_partition)
+            .block_gaps_partition(block_gaps_partition.clone())
+            .maybe_balances(balances.clone())
+            .maybe_aggregates(aggregates.clone())
+            .maybe_supply(supply.clone())
+            .maybe_webhooks(webhooks.clone())
+            .acceptance_slo(acceptance_slo.clone())
+            .metrics(metrics.clone())
+            .deep_reorg_depth(config.chain.deep_reorg_depth)
+            .maybe_finality_depth(config.chain.finality_depth)
+            .indexed_blocks(indexed_blocks.clone())
+            .backfill_requests(backfill_requests_tx.clone())
+            .unindexed_acceptance_threshold(config.chain.unindexed_acceptance_threshold)
+            .build();
+
+        let (resolver_block_request_tx, resolver_block_request_rx) =
+            workflow_core::channel::bounded(RESOLVER_REQUESTS_CAPACITY);
+        let (resolver_sender_request_tx, resolver_sender_request_rx) =
+            workflow_core::channel::bounded(RESOLVER_REQUESTS_CAPACITY);
+        let (resolver_response_tx, resolver_response_rx) =
+            workflow_core::channel::bounded(RESOLVER_RESPONSES_CAPACITY);
+
+        let requests_in_progress = Arc::new(AtomicU64::new(0));
+        let mut resolver = Resolver::new(
+            processors.clone(),
+            resolver_block_request_rx,
+            resolver_sender_request_rx,
+            resolver_response_tx.clone(),
+            resolver_nodes.clone(),
+            requests_in_progress.clone(),
+        );
+
+        let (scan_worker_job_done_tx, scan_worker_job_done_rx) = workflow_core::channel::bounded(1);
+
+        let header_validator = match config.storage.header_validation_density {
+            0 => None,
+            density => {
+                let validator = HeaderValidator::builder()
+                    .tx_keyspace(tx_keyspace.clone())
+                    .block_compact_header_partition(block_compact_header_partition.clone())
+                    .metadata_partition(metadata_partition.clone())
+                    .metrics(metrics.clone())
+                    .density_percent(density)
+                    .build();
+                if validator.prepare()? {
+                    info!(
+                        "Re-validating {density}% of stored headers against {CONSENSUS_CORE_VERSION}"
+                    );
+                }
+                Some(validator)
+            }
+        };
+
+        let active_syncers = ActiveSyncers::default();
+        let gap_rescan = GapRescan::builder()
+            .block_gaps_partition(block_gaps_partition.clone())
+            .active_syncers(active_syncers.clone())
+            .backfill_requests(backfill_requests_tx)
+            .virtual_daa(virtual_daa.clone())
+            .metrics(metrics.clone())
+            .max_syncers(config.sync.max_gap_syncers)
+            .build();
+
+        let mut scan_worker = PeriodicProcessor::builder()
+            .tick_and_resolution_rx(resolver_response_rx)
+            .resolver_request_block_tx(resolver_block_request_tx)
+            .resolver_request_sender_tx(resolver_sender_request_tx)
+            .job_done_tx(scan_worker_job_done_tx)
+            .reorg_lock(reorg_lock)
+            .tx_keyspace(tx_keyspace.clone())
+            .tx_id_to_acceptance_partition(tx_id_to_acceptance_partition.clone())
+            .unknown_tx_partition(unknown_tx_partition.clone())
+            .skip_tx_partition(skip_tx_partition.clone())
+            .skip_tx_by_block_partition(skip_tx_by_block_partition.clone())
+            .unknown_accepting_daa_partition(unknown_accepting_daa_partition.clone())
+            .block_compact_header_partition(block_compact_header_partition.clone())
+            .daa_resolution_attempt_count(5)
+            .pending_sender_resolution_partition(pending_sender_resolution_partition.clone())
+            .acceptance_history_partition(acceptance_history_partition)
+            .handshake_by_receiver_partition(handshake_by_receiver_partition.clone())
+            .handshake_by_sender_partition(handshake_by_sender_partition.clone())
+            .contextual_message_by_sender_partition(contextual_message_partition.clone())
+            .payment_by_receiver_partition(payment_by_receiver_partition.clone())
+            .payment_by_sender_partition(payment_by_sender_partition.clone())
+            .tx_id_to_payment_partition(tx_id_to_payment_partition.clone())
+            .tx_id_to_handshake_partition(tx_id_to_handshake_partition.clone())
+            .metrics(metrics.clone())
+            .metrics_snapshot_interval(Duration::from_secs(10))
+            .metadata_partition(metadata_partition.clone())
+            .resolver_requests_in_progress(requests_in_progress)
+            .block_daa_index(block_daa_index_partition)
+            .chain_membership_partition(chain_membership_partition)
+            .block_relations_partition(block_relations_partition)
+            .block_reward_partition(BlockRewardPartition::new(&tx_keyspace)?)
+            .block_stats_partition(block_stats_partition)
+            .block_transactions_partition(block_transactions_partition)
+            .processed_block_partition(processed_block_partition)
+            .block_gaps_partition(block_gaps_partition.clone())
+            .virtual_daa(virtual_daa.clone())
+            .node_capabilities(node_capabilities.clone())
+            .maybe_header_validator(header_validator)
+            .gap_rescan(gap_rescan)
+            .shutdown(processors.clone())
+            .compaction_max_lag_daa(config.maintenance.compaction_max_lag_daa)
+            .task_intervals(config.maintenance.task_intervals())
+            .pruning_depth(config.chain.pruning_depth)
+            .build();
+
+        let (selected_chain_intake_tx, selected_chain_intake_rx) =
+            tokio::sync::mpsc::channel(CHAIN_INTAKE_CAPACITY);
+        let (historical_sync_done_tx, historical_sync_done_rx) = tokio::sync::mpsc::channel(1);
+
+        let mut selected_chain_syncer = SelectedChainSyncer::new(
+            rpc_client.clone(),
+            metadata_partition.clone(),
+            block_compact_header_partition.clone(),
+            selected_chain_intake_rx,
+            historical_sync_done_rx,
+            historical_sync_done_tx,
+            vcc_intake_tx,
+            shutdown.stage(Stage::Syncers),
+        )
+        .with_metrics(metrics.clone())
+        .with_call_limiter(primary_call_limiter.clone())
+        .with_max_chain_blocks_per_step(config.sync.max_chain_blocks_per_step)
+        .with_recovery(ChainRecovery {
+            chain_index_partition,
+            chain_index_by_hash_partition,
+            acceptance_gaps_partition: AcceptanceGapsPartition::new(&tx_keyspace)?,
+        });
+
+        let staleness_threshold = Duration::from_secs(config.sync.staleness_threshold_secs);
+        let mut subscriber = Subscriber::new(
+            rpc_client.clone(),
+            block_intake_tx.clone(),
+            shutdown.stage(Stage::Intake),
+            block_gaps_partition.clone(),
+            ProvenancePartition::new(&tx_keyspace)?,
+            selected_chain_intake_tx,
+            block_tip,
+            virtual_daa.clone(),
+            node_capabilities,
+            RpcDispatcher::new(2).with_acceptance_slo(acceptance_slo), // in-flight GetBlocks calls shared by gap syncers
+        )
+        .with_metrics(metrics.clone())
+        .with_periodic_processor(resolver_response_tx.clone())
+        .with_reorder_window(
+            Duration::from_millis(config.sync.reorder_window_ms),
+            DEFAULT_REORDER_CAPACITY,
+        )
+        .with_staleness_threshold(staleness_threshold)
+        .with_mirror_nodes(create_mirror_rpc_clients(&config.node)?)
+        .with_backfill_requests(backfill_requests_rx)
+        .with_node_requirements(metadata_partition.clone())
+        .with_call_limiter(primary_call_limiter)
+        .with_active_syncers(active_syncers.clone())
+        .with_gap_history(gap_history_partition.clone())
+        .with_historical_intake(
+            historical_intake_tx,
+            IntakeStallWarning::new(Duration::from_secs(config.sync.intake_stall_warning_secs)),
+        )
+        .with_syncers_shutdown(shutdown.stage(Stage::Syncers));
+        if let Some(recorder) = fixture_recorder {
+            info!("Recording node calls and notifications into a fixture");
+            selected_chain_syncer = selected_chain_syncer.with_fixture_recorder(recorder.clone());
+            subscriber = subscriber.with_fixture_recorder(recorder);
+        }
+
+        let supervisor = Supervisor::new(RestartPolicy::default(), metrics.clone());
+        let queries = Queries::new(&tx_keyspace, block_compact_header_partition.clone())?;
+        let queries = match &tx_id_filter {
+            Some(filter) => queries.with_tx_id_filter(filter.clone()),
+            None => queries,
+        };
+        let status = status::Indexer::builder()
+            .tx_keyspace(tx_keyspace.clone())
+            .metadata_partition(metadata_partition.clone())
+            .block_gaps_partition(block_gaps_partition.clone())
+            .gap_history_partition(gap_history_partition.clone())
+            .metrics(metrics.clone())
+            .virtual_daa(virtual_daa.clone())
+            .active_syncers(active_syncers)
+            .node_pool(resolver_nodes.clone())
+            .supervisor(supervisor.clone())
+            .build();
+        #[cfg(feature = "api")]
+        let query_api = config
+            .api
+            .addr
+            .is_some()
+            .then(|| {
+                crate::api::QueryApi::new(
+                    &tx_keyspace,
+                    block_compact_header_partition.clone(),
+                    Some(status.clone()),
+                )
+                .map(|api| {
+                    let api = match tx_id_filter {
+                        Some(filter) => api.with_tx_id_filter(filter),
+                        None => api,
+                    };
+                    #[cfg(feature = "webhooks")]
+                    let api = match &webhooks {
+                        Some(webhooks) => api.with_webhooks(
+                            // validated as set, an empty one would authorize nothing
+                            crate::api::webhooks::WebhookApi::new(
+                                webhooks.clone(),
+                                config.webhooks.admin_token.clone().unwrap_or_default(),
+                            )
+                            .with_private_callbacks(config.webhooks.allow_private_callbacks)
+                            .with_address_prefix(address_prefix),
+                        ),
+                        None => api,
+                    };
+                    #[cfg(feature = "profiling")]
+                    let api = match config.profiling.enabled {
+                        // validated as set, an empty one would authorize nothing
+                        true => api.with_profiling(
+                            crate::api::profile::ProfileApi::new(
+                                config.profiling.admin_token.clone().unwrap_or_default(),
+                            )
+                            .with_max_cpu_profile(Duration::from_secs(
+                                config.profiling.max_cpu_secs,
+                            )),
+                        ),
+                        false => api,
+                    };
+                    let api = match &mempool {
+                        Some(mempool) => api.with_mempool(mempool.clone()),
+                        None => api,
+                    };
+                    let api = match config.api.rate_limit_per_second {
+                        0 => api,
+                        per_second => api.with_rate_limit(per_second, config.api.rate_limit_burst),
+                    };
+                    let api = match config.api.max_concurrent_requests {
+                        0 => api,
+                        max => api.with_max_concurrent_requests(max),
+                    };
+                    api.with_limits(crate::api::QueryLimits {
+                        max_limit: config.api.max_limit,
+                        max_daa_range: config.api.max_daa_range,
+                    })
+                    .with_metrics(metrics.clone())
+                    .with_address_prefix(address_prefix)
+                    .with_push_stream(
+                        crate::api::ws::PushStream::new(indexed_blocks.clone())
+                            .with_address_prefix(address_prefix),
+                    )
+                })
+            })
+            .transpose()?;
+        #[cfg(feature = "webhooks")]
+        let webhook_dispatcher = webhooks
+            .clone()
+            .map(|webhooks| {
+                crate::webhooks::WebhookDispatcher::new(
+                    webhooks,
+                    config.webhooks.retry_policy(),
+                    metrics.clone(),
+                    config.webhooks.allow_private_callbacks,
+                )
+            })
+            .transpose()?;
+
+        let mempool_processor = mempool.clone().map(|mempool| {
+            MempoolProcessor::new(rpc_client.clone(), mempool, metrics.clone())
+                .with_poll_interval(Duration::from_millis(config.mempool.poll_interval_ms))
+                .with_ttl(Duration::from_secs(config.mempool.ttl_secs))
+        });
+        let supply_checker = supply.clone().map(|supply| {
+            SupplyChecker::new(rpc_client.clone(), supply, metrics.clone())
+                .with_interval(Duration::from_secs(config.supply.check_interval_secs))
+                .with_tolerance(config.supply.tolerance_sompi)
+        });
+
+        Ok(Self {
+            config,
+            tx_keyspace,
+            metadata_partition,
+            outpoint_partition,
+            gap_history_partition,
+            queries,
+            metrics,
+            indexed_blocks,
+            balances,
+            aggregates,
+            webhooks,
+            mempool,
+            supply,
+            rpc_client,
+            status,
+            supervisor,
+            shutdown,
+            stop: Shutdown::new(),
+            components: Mutex::new(Some(Components {
+                block_worker,
+                acceptance_worker,
+                scan_worker,
+                resolver,
+                resolver_nodes,
+                selected_chain_syncer,
+                subscriber,
+                scan_worker_job_done_rx,
+                resolver_response_tx,
+                #[cfg(feature = "api")]
+                query_api,
+                #[cfg(feature = "webhooks")]
+                webhook_dispatcher,
+                mempool_processor,
+                supply_checker,
+            })),
+        })
+    }
+
+    /// Spawns the components and connects to the node. Returns once [`Indexer::shutdown`] was
+    /// called, the subscriber stopped or a processor failed beyond restarting, after every
+    /// stage stopped. Fails with the processor failure, else with the result of the subscriber
+    pub async fn run(&self) -> IndexerResult<()> {
+        Ok(self.run_components().await?)
+    }
+
+    async fn run_components(&self) -> Result<()> {
+        self.replay_ingest_filter().await?;
+        let Components {
+            block_worker,
+            acceptance_worker,
+            scan_worker,
+            mut resolver,
+            resolver_nodes,
+            mut selected_chain_syncer,
+            mut subscriber,
+            scan_worker_job_done_rx,
+            resolver_response_tx,
+            #[cfg(feature = "api")]
+            query_api,
+            #[cfg(feature = "webhooks")]
+            webhook_dispatcher,
+            mempool_processor,
+            supply_checker,
+        } = self
+            .components
+            .lock()
+            .take()
+            .context("Indexer is already running")?;
+        let shutdown = &self.shutdown;
+        let processors = shutdown.stage(Stage::Processors);
+        let metrics = &self.metrics;
+
+        processors.spawn(run_ticker(
+            processors.clone(),
+            scan_worker_job_done_rx,
+            resolver_response_tx,
+            Duration::from_secs(10),
+        ));
+
+        // a processor failing for good stops the indexer like Indexer::shutdown
+        let supervisor = &self.supervisor;
+        let block_worker_handle =
+            supervisor.spawn(&processors, &self.stop, "block worker", block_worker);
+        let acceptance_worker_handle = supervisor.spawn(
+            &processors,
+            &self.stop,
+            "acceptance worker",
+            acceptance_worker,
+        );
+        let scan_worker_handle =
+            supervisor.spawn(&processors, &self.stop, "scan worker", scan_worker);
+
+        let resolver_handle = processors.spawn(async move { resolver.process().await });
+        // reads the outbox until the database closes
+        #[cfg(feature = "webhooks")]
+        if let Some(webhook_dispatcher) = webhook_dispatcher {
+            processors.spawn(webhook_dispatcher.run(processors.clone()));
+        }
+        if let Some(mempool_processor) = mempool_processor {
+            processors.spawn(mempool_processor.run(processors.clone()));
+        }
+        if let Some(supply_checker) = supply_checker {
+            processors.spawn(supply_checker.run(processors.clone()));
+        }
+        let node_health_handle = processors.spawn(resolver_nodes.run_health_checks(
+            Duration::from_secs(self.config.node.health_interval_secs),
+            processors.clone(),
+        ));
+        let selected_chain_syncer_handle = shutdown
+            .stage(Stage::Syncers)
+            .spawn(async move { selected_chain_syncer.process().await });
+        let mut subscriber_handle = shutdown
+            .stage(Stage::Intake)
+            .spawn(async move { subscriber.task().await });
+
+        let metrics_handle = match &self.config.telemetry.metrics_addr {
+            Some(addr) => {
+                let registry = MetricsRegistry::new();
+                register_indexer_metrics(&registry, metrics);
+                let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+                    anyhow::anyhow!("Failed to bind metrics listener to {addr}: {e}")
+                })?;
+                Some(processors.spawn(metrics_exporter::serve(
+                    listener,
+                    registry,
+                    HealthCheck::new(
+                        metrics.clone(),
+                        Duration::from_secs(self.config.sync.staleness_threshold_secs),
+                    ),
+                    Some(self.status.clone()),
+                    processors.clone(),
+                )))
+            }
+            None => None,
+        };
+        // the API reads the database, it stops with the intake before the processors close it
+        #[cfg(feature = "api")]
+        let api_handle = match (&self.config.api.addr, query_api) {
+            (Some(addr), Some(query_api)) => {
+                let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+                    anyhow::anyhow!("Failed to bind query API listener to {addr}: {e}")
+                })?;
+                let intake = shutdown.stage(Stage::Intake);
+                Some(intake.spawn(crate::api::serve(listener, query_api, intake.clone())))
+            }
+            _ => None,
+        };
+
+        let options = ConnectOptions {
+            block_async_connect: false,
+            connect_timeout: Some(Duration::from_millis(10_000)),
+            strategy: ConnectStrategy::Retry,
+            ..Default::default()
+        };
+        let connect = async {
+            tokio::time::sleep(Duration::from_secs(5)).await; // let time to spawn everything
+            info!("Connecting to Kaspa node...");
+            self.rpc_client
+                .connect(Some(options))
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to connect to node: {}", e))
+        };
+        // a shutdown requested meanwhile skips connecting
+        let connected = tokio::select! {
+            _ = self.stop.cancelled() => false,
+            connected = connect => {
+                connected?;
+                true
+            }
+        };
+
+        // the subscriber stops on its own when the node is incompatible
+        let subscriber_result = tokio::select! {
+            _ = self.stop.cancelled() => {
+                info!("Shutdown requested. Shutting down...");
+                None
+            }
+            result = &mut subscriber_handle, if connected => {
+                error!("Subscriber stopped. Shutting down...");
+                Some(result?)
+            }
+        };
+
+        // intake, then syncers, then processors, each waiting for the previous stage
+        shutdown.shutdown().await;
+
+        #[cfg(feature = "api")]
+        if let Some(api_handle) = api_handle {
+            _ = api_handle
+                .await?
+                .inspect_err(|err| error!("query API listener stopped with error: {err}"));
+        }
+        if let Some(metrics_handle) = metrics_handle {
+            _ = metrics_handle
+                .await?
+                .inspect_err(|err| error!("metrics listener stopped with error: {err}"));
+        }
+
+        // Collect the results, the stages waited for the tasks already
+        info!("waiting for resolver finish");
+        _ = resolver_handle
+            .await?
+            .inspect(|_| info!("resolver has stopped")); // todo logs
+        _ = node_health_handle
+            .await?
+            .inspect_err(|err| error!("node health checks stopped with error: {err}"));
+        info!("waiting for syncer finish");
+        _ = selected_chain_syncer_handle
+            .await
+            .inspect(|_| info!("selected chain syncer has stopped"))?; // todo logs
+        info!("waiting for subscriber finish");
+        let subscriber_result = match subscriber_result {
+            Some(result) => result,
+            None => subscriber_handle.await?,
+        };
+        info!("subscriber has stopped");
+        info!("waiting for acceptance worker finish");
+        let acceptance_worker_result = acceptance_worker_handle
+            .await
+            .expect("failed to join acceptance worker");
+        info!("waiting for scan worker finish");
+        let scan_worker_result = scan_worker_handle
+            .await
+            .expect("failed to join scan_worker thread");
+        info!("waiting for block worker finish");
+        let block_worker_result = block_worker_handle
+            .await
+            .expect("failed to join block_worker thread");
+
+        info!("All tasks shut down.");
+        block_worker_result
+            .and(acceptance_worker_result)
+            .and(scan_worker_result)
+            .and(subscriber_result)
+    }
+
+    /// Asks [`Indexer::run`] to stop, which returns once every stage stopped
+    pub fn shutdown(&self) {
+        self.stop.cancel();
+    }
+
+    /// Cancelled once [`Indexer::shutdown`] was called or a processor failed for good
+    pub fn shutdown_signal(&self) -> Shutdown {
+        self.stop.child()
+    }
+
+    /// Reads cached values and the local database only, the node is not called
+    pub fn status(&self) -> IndexerResult<IndexerStatus> {
+        Ok(self.status.status()?)
+    }
+
+    /// Drops the data derived from the block and indexes it again, fetched from the node.
+    /// Only before [`Indexer::run`], fails with [`IndexerError::NotFound`] for blocks the node
+    /// doesn't know
+    pub async fn reprocess(&self, hash: RpcHash) -> IndexerResult<()> {
+        self.connect_for_maintenance().await?;
+        let block = self.rpc_client.get_block(hash, true).await?;
+        self.force_reprocess(&block)?;
+        info!("Reprocessed block {hash}");
+        self.rpc_client.disconnect().await?;
+        Ok(())
+    }
+
+    /// Drops the `targets` data of the blocks within `daa_range` and indexes the blocks again,
+    /// fetched from the node. Refuses ranges the live processors may still write unless
+    /// `force` is set. Every block is fetched before anything is dropped, fails with
+    /// [`IndexerError::Pruned`] leaving the range as it was when the node no longer serves one.
+    /// Only before [`Indexer::run`]
+    pub async fn reindex(
+        &self,
+        daa_range: Range<u64>,
+        targets: &[ReindexTarget],
+        force: bool,
+    ) -> IndexerResult<ReindexSummary> {
+        let reindex = Reindex::new(&self.tx_keyspace)?;
+        if !force {
+            reindex
+                .check_range(&daa_range, reindex::live_margin(&self.config.chain))
+                .map_err(IndexerError::validation)?;
+        }
+        let blocks = reindex.blocks(&daa_range)?;
+        self.connect_for_maintenance().await?;
+        let fetched = self.fetch_range(&blocks).await;
+        self.rpc_client.disconnect().await?;
+        let deleted = self.force_reprocess_all(
+            &fetched?,
+            |wtx| reindex.delete_wtx(wtx, &daa_range, &blocks, targets),
+            |wtx| reindex.restore_senders_wtx(wtx, &blocks, targets),
+        )?;
+        debug!("Reindexed {} blocks of DAA {daa_range:?}", blocks.len());
+        let summary = ReindexSummary {
+            daa_range: daa_range.clone(),
+            blocks: blocks.len(),
+            deleted,
+            rewritten: reindex.count(&daa_range, targets)?,
+        };
+        info!("{}", summary.to_string().trim_end());
+        Ok(summary)
+    }
+
+    /// Full blocks of `blocks`, fails with [`IndexerError::Pruned`] on the first one the node
+    /// no longer serves in full
+    async fn fetch_range(&self, blocks: &[(u64, RpcHash)]) -> IndexerResult<Vec<RpcBlock>> {
+        let mut fetched = Vec::with_capacity(blocks.len());
+        for (daa_score, hash) in blocks {
+            let block = self
+                .rpc_client
+                .get_block(*hash, true)
+                .await
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("Failed to fetch block {hash} to reindex"))
+                .map_err(|err| match TransportError::is_not_found_error(&err) {
+                    true => IndexerError::Pruned {
+                        from: *hash,
+                        source: err,
+                    },
+                    false => err.into(),
+                })?;
+            // a pruned block body is served as its header alone, every block has a coinbase
+            if block.transactions.is_empty() {
+                return Err(IndexerError::Pruned {
+                    from: *hash,
+                    source: anyhow::anyhow!("Node serves block {hash} without its transactions"),
+                });
+            }
+            if block.header.hash != *hash || block.header.daa_score != *daa_score {
+                return Err(IndexerError::Validation(anyhow::anyhow!(
+                    "Node served block {} at DAA {} for {hash} at DAA {daa_score}",
+                    block.header.hash,
+                    block.header.daa_score
+                )));
+            }
+            fetched.push(block);
+        }
+        Ok(fetched)
+    }
+
+    /// Indexes the blocks of a pending ingest filter replay again, fetched from the node. An
+    /// interrupted replay is run again on the next start
+    async fn replay_ingest_filter(&self) -> Result<()> {
+        let Some(mut state) = self.metadata_partition.get_ingest_filter()? else {
+            return Ok(());
+        };
+        let Some(replay) = state.replay.clone() else {
+            return Ok(());
+        };
+        let blocks = Reindex::new(&self.tx_keyspace)?.blocks(&replay)?;
+        info!(
+            "Replaying {} blocks of DAA {replay:?} under the ingest filter",
+            blocks.len()
+        );
+        self.connect_for_maintenance().await?;
+        for (daa_score, hash) in &blocks {
+            let block = self
+                .rpc_client
+                .get_block(*hash, true)
+                .await
+                .with_context(|| format!("Failed to fetch block {hash} to replay"))?;
+            self.force_reprocess(&block)?;
+            debug!("Replayed block {hash} at DAA {daa_score}");
+        }
+        self.rpc_client.disconnect().await?;
+        state.replay = None;
+        self.metadata_partition.set_ingest_filter(&state)?;
+        info!("Ingest filter replay of DAA {replay:?} done");
+        Ok(())
+    }
+
+    async fn connect_for_maintenance(&self) -> Result<()> {
+        self.rpc_client
+            .connect(Some(ConnectOptions {
+                block_async_connect: true,
+                connect_timeout: Some(Duration::from_millis(10_000)),
+                strategy: ConnectStrategy::Fallback,
+                ..Default::default()
+            }))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to node: {}", e))
+    }
+
+    fn force_reprocess(&self, block: &RpcBlock) -> Result<()> {
+        self.components
+            .lock()
+            .as_mut()
+            .context("Indexer is already running")?
+            .block_worker
+            .force_reprocess(block)
+    }
+
+    fn force_reprocess_all<T>(
+        &self,
+        blocks: &[RpcBlock],
+        before: impl FnOnce(&mut WriteTransaction) -> Result<T>,
+        after: impl FnOnce(&mut WriteTransaction) -> Result<()>,
+    ) -> Result<T> {
+        self.components
+            .lock()
+            .as_mut()
+            .context("Indexer is already running")?
+            .block_worker
+            .force_reprocess_all(blocks, before, after)
+    }
+
+    pub fn config(&self) -> &IndexerConfig {
+        &self.config
+    }
+
+    pub fn tx_keyspace(&self) -> &TxKeyspace {
+        &self.tx_keyspace
+    }
+
+    pub fn metadata_partition(&self) -> &MetadataPartition {
+        &self.metadata_partition
+    }
+
+    pub fn metrics(&self) -> &SharedMetrics {
+        &self.metrics
+    }
+
+    /// Subscribe for the blocks and chain changes committed from now on
+    pub fn indexed_blocks(&self) -> &IndexedBlocks {
+        &self.indexed_blocks
+    }
+
+    /// Confirmed address balances, none unless `storage.address_balances` is set
+    pub fn balances(&self) -> Option<&Balances> {
+        self.balances.as_ref()
+    }
+
+    /// Output with its script class, always none unless `storage.outpoint_index` is set
+    pub fn get_output(
+        &self,
+        outpoint: &RpcTransactionOutpoint,
+    ) -> IndexerResult<Option<IndexedOutput>> {
+        Ok(self
+            .outpoint_partition
+            .get_output_rtx(&self.tx_keyspace.read_tx(), outpoint)?)
+    }
+
+    /// Latest gap syncer events, newest first
+    pub fn list_gap_history(&self, limit: usize) -> IndexerResult<Vec<GapHistoryRecord>> {
+        Ok(self.gap_history_partition.list_gap_history(limit)?)
+    }
+
+    /// The block with everything stored about it, transactions none unless they were indexed
+    pub fn get_block_full(&self, hash: RpcHash) -> IndexerResult<Option<FullBlock>> {
+        self.queries.get_block_full(hash)
+    }
+
+    /// Totals per DAA bucket, none unless `storage.aggregates` is set
+    pub fn aggregates(&self) -> Option<&Aggregates> {
+        self.aggregates.as_ref()
+    }
+
+    /// Webhook subscriptions and deliveries, none unless `webhooks.enabled` is set
+    pub fn webhooks(&self) -> Option<&Webhooks> {
+        self.webhooks.as_ref()
+    }
+
+    /// Pending transactions of the node mempool, none unless `mempool.enabled` is set
+    pub fn mempool(&self) -> Option<&Mempool> {
+        self.mempool.as_ref()
+    }
+
+    /// Coinbase rewards and circulating supply, none unless `supply.enabled` is set
+    pub fn supply(&self) -> Option<&Supply> {
+        self.supply.as_ref()
+    }
+}
+
+/// The url is checked to be a wRPC one by [`IndexerConfig::validate`]
+pub fn create_rpc_client(node: &NodeConfig) -> IndexerResult<KaspaRpcClient> {
+    let encoding = WrpcEncoding::Borsh;
+
+    let url = node.url.as_deref();
+    let resolver = if url.is_some() {
+        None
+    } else {
+        Some(kaspa_wrpc_client::Resolver::default())
+    };
+
+    let network_id = node.network_id().map_err(IndexerError::validation)?;
+    let selected_network = Some(network_id);
+
+    let subscription_context = None;
+
+    info!("Creating RPC client for network: {network_id}");
+
+    let client = KaspaRpcClient::new(
+        encoding,
+        url,
+        resolver,
+        selected_network,
+        subscription_context,
+    )
+    .map_err(|e| IndexerError::Rpc(anyhow::anyhow!("Failed to create RPC client: {}", e)))?;
+    Ok(client)
+}
+
+fn create_mirror_rpc_clients(node: &NodeConfig) -> Result<Vec<KaspaRpcClient>> {
+    let network_id = node.network_id()?;
+    node.mirror_urls
+        .iter()
+        .map(|url| {
+            info!("Creating mirror RPC client for {url}");
+            KaspaRpcClient::new(WrpcEncoding::Borsh, Some(url), None, Some(network_id), None)
+                .map_err(|e| anyhow::anyhow!("Failed to create mirror RPC client: {}", e))
+        })
+        .collect()
+}
+
+/// Additional resolver nodes, over gRPC for `grpc://` urls, plus a node of the public resolver
+/// service if enabled
+async fn create_resolver_rpc_clients(
+    node: &NodeConfig,
+    rpc: &RpcConfig,
+    metrics: &SharedMetrics,
+) -> Result<Vec<RpcNode>> {
+    let network_id = node.network_id()?;
+    let create_wrpc = |url: Option<&str>| {
+        KaspaRpcClient::new(
+            WrpcEncoding::Borsh,
+            url,
+            url.is_none().then(kaspa_wrpc_client::Resolver::default),
+            Some(network_id),
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to create resolver RPC client: {}", e))
+    };
+    let mut nodes = Vec::new();
+    for url in &node.resolver_urls {
+        info!("Creating resolver RPC client for {url}");
+        let resolver_node = RpcNode::for_url(url, |url| create_wrpc(Some(url))).await?;
+        nodes.push(resolver_node.with_limiter(create_call_limiter(url, rpc, metrics)));
+    }
+    if node.resolver_public_node {
+        info!("Creating resolver RPC client for public resolver");
+        let resolver_node = RpcNode::from(create_wrpc(None)?);
+        nodes.push(resolver_node.with_limiter(create_call_limiter(
+            "public resolver",
+            rpc,
+            metrics,
+        )));
+    }
+    Ok(nodes)
+}
+
+/// The breaker opens after `breaker_failures` consecutive failures for its cooldown
+fn create_call_limiter(node: &str, rpc: &RpcConfig, metrics: &SharedMetrics) -> CallLimiter {
+    CallLimiter::new(node, rpc.permits_per_node)
+        .with_breaker(
+            rpc.breaker_failures,
+            Duration::from_secs(rpc.breaker_cooldown_secs),
+        )
+        .with_metrics(metrics.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_build_status_and_shutdown_before_connecting() {
+        let tx_keyspace = crate::database::test_keyspace("facade");
+        let mut config = IndexerConfig::default();
+        // nothing listens there, the shutdown comes first
+        config.node.url = Some("ws://127.0.0.1:1".to_string());
+        let indexer = Indexer::builder()
+            .config(config)
+            .database(tx_keyspace)
+            .build()
+            .await
+            .unwrap();
+
+        let status = indexer.status().unwrap();
+        assert!(!status.node_connected);
+        assert!(status.block_tip.is_none());
+        assert!(status.gaps.is_empty());
+
+        indexer.shutdown();
+        indexer.run().await.unwrap();
+        assert!(indexer.run().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_database_of_another_network_is_refused() {
+        let tx_keyspace = crate::database::test_keyspace("facade-network");
+        MetadataPartition::new(&tx_keyspace)
+            .unwrap()
+            .check_network_id("testnet-10")
+            .unwrap();
+        let mut config = IndexerConfig::default();
+        config.node.url = Some("ws://127.0.0.1:1".to_string());
+        let err = Indexer::builder()
+            .config(config)
+            .database(tx_keyspace)
+            .build()
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, IndexerError::Validation(_)), "{err:?}");
+        assert!(err.to_string().contains("testnet-10"), "{err}");
+    }
+}
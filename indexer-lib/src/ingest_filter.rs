@@ -0,0 +1,362 @@
+//! Ingest filter of the block processor, for deployments querying part of the data only.
+//!
+//! Headers and the block level data are indexed for every block. With `addresses`, only the
+//! transactions paying to a watched address or, with the outpoint index, spending from one are
+//! indexed. `chain_blocks_only` leaves out the transactions of blocks received off the selected
+//! chain, `skip_payloads` stores messages without their sealed payload. Transactions left out
+//! are recorded as skipped, like the ones without a protocol operation.
+//!
+//! The filter is recorded in the metadata as an [`IngestFilterState`], queries answer with
+//! "not indexed by configuration" for what it leaves out. On startup a filter admitting data
+//! the recorded one left out is refused, unless the [`FilterChangePolicy`] accepts the change
+//! or replays the blocks indexed under the recorded filter.
+
+use crate::config::IngestFilterConfig;
+use crate::database::messages::AddressPayload;
+use anyhow::{Result, bail};
+use kaspa_addresses::Prefix;
+use kaspa_rpc_core::{RpcAddress, RpcBlock, RpcTransaction, RpcTransactionOutpoint};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::Range;
+
+const CHAIN_BLOCKS_ONLY: u8 = 1;
+const SKIP_PAYLOADS: u8 = 2;
+const ADDRESS_LEN: usize = size_of::<AddressPayload>();
+
+/// What to do on startup when the configured filter admits data the recorded one left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterChangePolicy {
+    /// Refuses to start
+    #[default]
+    Fail,
+    /// Applies the filter to the blocks indexed from now on, earlier blocks stay as indexed
+    Accept,
+    /// Indexes the blocks indexed under the recorded filter again, fetched from the node
+    Replay,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestFilter {
+    /// Watched addresses, none indexes the transactions of every address
+    addresses: HashSet<AddressPayload>,
+    pub chain_blocks_only: bool,
+    pub skip_payloads: bool,
+}
+
+impl IngestFilter {
+    /// Fails on addresses of another network than `prefix`
+    pub fn from_config(config: &IngestFilterConfig, prefix: Prefix) -> Result<Self> {
+        let mut addresses = HashSet::with_capacity(config.addresses.len());
+        for address in &config.addresses {
+            let Ok(parsed) = RpcAddress::try_from(address.as_str()) else {
+                bail!("ingest_filter.addresses holds an invalid address {address}");
+            };
+            if parsed.prefix != prefix {
+                bail!("ingest_filter.addresses holds {address}, not a {prefix} address");
+            }
+            addresses.insert(AddressPayload::try_from(&parsed)?);
+        }
+        Ok(Self {
+            addresses,
+            chain_blocks_only: config.chain_blocks_only,
+            skip_payloads: config.skip_payloads,
+        })
+    }
+
+    pub fn with_addresses(mut self, addresses: impl IntoIterator<Item = AddressPayload>) -> Self {
+        self.addresses = addresses.into_iter().collect();
+        self
+    }
+
+    /// Whether transactions are left out by address
+    pub fn watches_addresses(&self) -> bool {
+        !self.addresses.is_empty()
+    }
+
+    /// Whether the transactions of `address` are indexed
+    pub fn admits_address(&self, address: &AddressPayload) -> bool {
+        self.addresses.is_empty() || self.addresses.contains(address)
+    }
+
+    /// Whether the transactions of `block` are indexed, those of blocks received without verbose
+    /// data are
+    pub fn admits_block_transactions(&self, block: &RpcBlock) -> bool {
+        !self.chain_blocks_only
+            || block
+                .verbose_data
+                .as_ref()
+                .is_none_or(|verbose_data| verbose_data.is_chain_block)
+    }
+
+    /// Whether `tx` pays to a watched address or spends from one, `spent_from` resolves the
+    /// address of a spent output if known
+    pub fn admits_transaction(
+        &self,
+        tx: &RpcTransaction,
+        mut spent_from: impl FnMut(&RpcTransactionOutpoint) -> Result<Option<AddressPayload>>,
+    ) -> Result<bool> {
+        if self.addresses.is_empty() {
+            return Ok(true);
+        }
+        let pays_to_watched = tx.outputs.iter().any(|output| {
+            AddressPayload::try_from(&output.script_public_key)
+                .is_ok_and(|address| self.addresses.contains(&address))
+        });
+        if pays_to_watched {
+            return Ok(true);
+        }
+        for input in &tx.inputs {
+            if let Some(address) = spent_from(&input.previous_outpoint)?
+                && self.addresses.contains(&address)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether everything this filter admits is admitted by `other` as well
+    pub fn is_within(&self, other: &Self) -> bool {
+        let addresses = other.addresses.is_empty()
+            || (!self.addresses.is_empty() && self.addresses.is_subset(&other.addresses));
+        addresses
+            && (self.chain_blocks_only || !other.chain_blocks_only)
+            && (self.skip_payloads || !other.skip_payloads)
+    }
+
+    /// FNV-1a of the encoded filter, independent of the order of the addresses
+    pub fn filter_hash(&self) -> u64 {
+        self.encode()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    /// `[flags (1)] [addresses (34 each, ascending)]`
+    fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.chain_blocks_only {
+            flags |= CHAIN_BLOCKS_ONLY;
+        }
+        if self.skip_payloads {
+            flags |= SKIP_PAYLOADS;
+        }
+        let mut addresses = self
+            .addresses
+            .iter()
+            .map(bytemuck::bytes_of)
+            .collect::<Vec<_>>();
+        addresses.sort_unstable();
+        let mut value = Vec::with_capacity(1 + addresses.len() * ADDRESS_LEN);
+        value.push(flags);
+        addresses
+            .into_iter()
+            .for_each(|address| value.extend_from_slice(address));
+        value
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let Some((flags, addresses)) = bytes.split_first() else {
+            bail!("Invalid ingest filter size");
+        };
+        if addresses.len() % ADDRESS_LEN != 0 {
+            bail!("Invalid ingest filter size");
+        }
+        Ok(Self {
+            addresses: addresses
+                .chunks_exact(ADDRESS_LEN)
+                .map(bytemuck::pod_read_unaligned)
+                .collect(),
+            chain_blocks_only: flags & CHAIN_BLOCKS_ONLY != 0,
+            skip_payloads: flags & SKIP_PAYLOADS != 0,
+        })
+    }
+}
+
+/// Filter the blocks were indexed under, recorded in the metadata.
+///
+/// Encoded as `[filter hash (8 BE)] [since_daa (8 BE)] [replaying (1)] [replay start (8 BE)]
+/// [replay end (8 BE)] [filter]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestFilterState {
+    pub filter: IngestFilter,
+    /// Blocks from this DAA score on are indexed under `filter`, once replayed. Earlier ones
+    /// were indexed under the filters recorded before
+    pub since_daa: u64,
+    /// Blocks indexed under the previous filter, to be indexed again under `filter`
+    pub replay: Option<Range<u64>>,
+}
+
+impl IngestFilterState {
+    const FIXED_LEN: usize = 8 + 8 + 1 + 8 + 8;
+
+    /// State of a database indexing under `filter` from now on, `next_daa` is the DAA score
+    /// after the block tip. A database recorded without a filter was indexed unfiltered. A
+    /// filter admitting data the `recorded` one left out fails per `policy`, unless no block
+    /// was indexed under the recorded one
+    pub fn apply(
+        recorded: Option<Self>,
+        filter: IngestFilter,
+        policy: FilterChangePolicy,
+        next_daa: u64,
+    ) -> Result<Self> {
+        let recorded = recorded.unwrap_or(Self {
+            filter: IngestFilter::default(),
+            since_daa: 0,
+            replay: None,
+        });
+        if recorded.filter == filter {
+            return Ok(recorded);
+        }
+        let affected = recorded.since_daa..next_daa;
+        if filter.is_within(&recorded.filter) || affected.is_empty() {
+            // a replay pending still covers data of an earlier filter
+            return Ok(Self { filter, ..recorded });
+        }
+        match policy {
+            FilterChangePolicy::Fail => bail!(
+                "Ingest filter {:016x} admits data the recorded filter {:016x} left out of the \
+                blocks of DAA {affected:?}, set ingest_filter.on_change to accept or replay",
+                filter.filter_hash(),
+                recorded.filter.filter_hash()
+            ),
+            FilterChangePolicy::Accept => Ok(Self {
+                filter,
+                since_daa: next_daa,
+                replay: None,
+            }),
+            FilterChangePolicy::Replay => Ok(Self {
+                filter,
+                replay: Some(match recorded.replay {
+                    Some(replay) => replay.start.min(affected.start)..next_daa,
+                    None => affected,
+                }),
+                ..recorded
+            }),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let filter = self.filter.encode();
+        let replay = self.replay.clone().unwrap_or_default();
+        let mut value = Vec::with_capacity(Self::FIXED_LEN + filter.len());
+        value.extend_from_slice(&self.filter.filter_hash().to_be_bytes());
+        value.extend_from_slice(&self.since_daa.to_be_bytes());
+        value.push(self.replay.is_some() as u8);
+        value.extend_from_slice(&replay.start.to_be_bytes());
+        value.extend_from_slice(&replay.end.to_be_bytes());
+        value.extend_from_slice(&filter);
+        value
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Self::FIXED_LEN {
+            bail!("Invalid ingest filter state size")
+        }
+        let u64_at =
+            |i: usize| -> Result<u64> { Ok(u64::from_be_bytes(bytes[i..i + 8].try_into()?)) };
+        let filter = IngestFilter::decode(&bytes[Self::FIXED_LEN..])?;
+        if filter.filter_hash() != u64_at(0)? {
+            bail!("Ingest filter does not match its recorded hash");
+        }
+        Ok(Self {
+            filter,
+            since_daa: u64_at(8)?,
+            replay: (bytes[16] != 0).then_some(u64_at(17)?..u64_at(25)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(byte: u8) -> AddressPayload {
+        AddressPayload {
+            inverse_version: 1,
+            payload: [byte; 33],
+        }
+    }
+
+    #[test]
+    fn test_state_roundtrip_and_hash() {
+        let filter = IngestFilter {
+            chain_blocks_only: true,
+            ..Default::default()
+        }
+        .with_addresses([address(1), address(2)]);
+        let reordered = IngestFilter {
+            chain_blocks_only: true,
+            ..Default::default()
+        }
+        .with_addresses([address(2), address(1)]);
+        assert_eq!(filter.filter_hash(), reordered.filter_hash());
+        assert_ne!(filter.filter_hash(), IngestFilter::default().filter_hash());
+        let state = IngestFilterState {
+            filter,
+            since_daa: 10,
+            replay: Some(3..7),
+        };
+        assert_eq!(IngestFilterState::decode(&state.encode()).unwrap(), state);
+        let mut corrupted = state.encode();
+        corrupted[IngestFilterState::FIXED_LEN + 1] ^= 1;
+        assert!(IngestFilterState::decode(&corrupted).is_err());
+    }
+
+    #[test]
+    fn test_filter_changes() {
+        let watched = IngestFilter::default().with_addresses([address(1)]);
+        let wider = IngestFilter::default().with_addresses([address(1), address(2)]);
+        let apply = |recorded, filter: &IngestFilter, policy| {
+            IngestFilterState::apply(recorded, filter.clone(), policy, 100)
+        };
+
+        // narrowing an unfiltered database keeps its data
+        let state = apply(None, &watched, FilterChangePolicy::Fail).unwrap();
+        assert_eq!((state.since_daa, state.replay.clone()), (0, None));
+        assert_eq!(
+            apply(Some(state.clone()), &watched, FilterChangePolicy::Fail).unwrap(),
+            state
+        );
+
+        // widening leaves the blocks indexed so far incomplete
+        let err = apply(Some(state.clone()), &wider, FilterChangePolicy::Fail)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("0..100"), "{err}");
+        let accepted = apply(Some(state.clone()), &wider, FilterChangePolicy::Accept).unwrap();
+        assert_eq!((accepted.since_daa, accepted.replay), (100, None));
+        let replayed = apply(Some(state.clone()), &wider, FilterChangePolicy::Replay).unwrap();
+        assert_eq!((replayed.since_daa, replayed.replay), (0, Some(0..100)));
+
+        // only the blocks since the accepted change were indexed under the recorded filter
+        let accepted = IngestFilterState {
+            since_daa: 60,
+            ..state
+        };
+        let replayed = apply(Some(accepted), &wider, FilterChangePolicy::Replay).unwrap();
+        assert_eq!(replayed.replay, Some(60..100));
+
+        // no block indexed under the recorded filter yet
+        let empty = IngestFilterState::apply(None, watched, FilterChangePolicy::Fail, 0).unwrap();
+        let widened = IngestFilterState::apply(
+            Some(empty),
+            IngestFilter::default(),
+            FilterChangePolicy::Fail,
+            0,
+        )
+        .unwrap();
+        assert_eq!(widened.filter, IngestFilter::default());
+
+        let payloads = IngestFilter {
+            skip_payloads: true,
+            ..Default::default()
+        };
+        assert!(payloads.is_within(&IngestFilter::default()));
+        assert!(!IngestFilter::default().is_within(&payloads));
+        assert!(!wider.is_within(&IngestFilter::default().with_addresses([address(1)])));
+    }
+}
@@ -0,0 +1,41 @@
+//! Spans following blocks from the subscriber or a historical syncer through the block processor
+//! to the commit.
+//!
+//! Every message on the block processor intake carries the root span of its blocks. The spans
+//! are disabled unless a subscriber enables [`TRACE_TARGET`], e.g. the OpenTelemetry layer the
+//! indexer installs when an OTLP endpoint is configured, a disabled span costs a branch.
+
+use tracing::{Span, debug_span};
+
+/// Target of the ingestion spans
+pub const TRACE_TARGET: &str = "ingestion";
+
+/// Root span of a message and its time spent queued on the intake
+#[derive(Debug)]
+pub struct TraceContext {
+    pub span: Span,
+    /// Open until the block processor takes the message
+    queued: Span,
+}
+
+impl TraceContext {
+    pub fn new(span: Span) -> Self {
+        let queued = debug_span!(target: TRACE_TARGET, parent: &span, "queued");
+        Self { span, queued }
+    }
+
+    /// Closes the queued span
+    pub fn dequeued(&mut self) {
+        self.queued = Span::none();
+    }
+}
+
+impl Default for TraceContext {
+    /// Context of a message nobody traces
+    fn default() -> Self {
+        Self {
+            span: Span::none(),
+            queued: Span::none(),
+        }
+    }
+}
@@ -1,49 +1,296 @@
+use crate::ingest_trace::TraceContext;
 use kaspa_consensus_core::BlueWorkType;
 use kaspa_rpc_core::RpcBlock;
-use std::ops::Deref;
 use std::slice;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
-pub static APP_IS_RUNNING: AtomicBool = AtomicBool::new(true);
 pub const RK_PRUNING_DEPTH: u64 = 1080000;
+/// DAA score the network advances by per second
+pub const TARGET_BLOCKS_PER_SECOND: u64 = 10;
 
+pub mod acceptance_slo;
+#[cfg(feature = "api")]
+pub mod api;
+pub mod block_events;
+pub mod call_limiter;
+pub mod coinbase;
+pub mod config;
+pub mod crash_handler;
+pub mod error;
 pub mod fifo_set;
+pub mod fixture;
+pub mod gap_rescan;
+pub mod header_validation;
 pub mod historical_syncer;
+pub mod indexer;
+pub mod ingest_filter;
+pub mod ingest_trace;
+pub mod mempool;
+pub mod mirror_feed;
+pub mod node_capabilities;
+pub mod node_pool;
+pub mod profiling;
+pub mod protocols;
+pub mod queries;
+pub mod reindex;
+pub mod reorder_buffer;
+pub mod shutdown;
+pub mod startup;
 pub mod subscriber;
+pub mod supervisor;
+pub mod supply_check;
 
 pub mod database;
 pub mod metrics;
+pub mod metrics_exporter;
 
 pub mod block_processor;
 pub mod periodic_processor;
+pub mod scheduler;
 pub mod virtual_chain_processor;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
 
 pub mod selected_chain_syncer;
+pub mod status;
 
 pub mod resolver;
+pub mod rpc_dispatcher;
+pub mod rpc_transport;
 
+/// Blocks on the block processor intake together with the url of the node they came from
 pub enum BlockOrMany {
-    Many(Vec<RpcBlock>),
-    Block(Arc<RpcBlock>),
+    Many(Vec<RpcBlock>, Arc<str>, TraceContext),
+    /// Block of a notification, with the time the notification was received if known
+    Block(Arc<RpcBlock>, Option<Instant>, Arc<str>, TraceContext),
 }
 
-impl Deref for BlockOrMany {
-    type Target = [RpcBlock];
+impl BlockOrMany {
+    pub fn received_at(&self) -> Option<Instant> {
+        match self {
+            BlockOrMany::Many(..) => None,
+            BlockOrMany::Block(_, received_at, ..) => *received_at,
+        }
+    }
+
+    pub fn node(&self) -> &Arc<str> {
+        match self {
+            BlockOrMany::Many(_, node, _) | BlockOrMany::Block(_, _, node, _) => node,
+        }
+    }
+
+    pub fn trace(&self) -> &TraceContext {
+        match self {
+            BlockOrMany::Many(_, _, trace) | BlockOrMany::Block(_, _, _, trace) => trace,
+        }
+    }
+
+    pub fn trace_mut(&mut self) -> &mut TraceContext {
+        match self {
+            BlockOrMany::Many(_, _, trace) | BlockOrMany::Block(_, _, _, trace) => trace,
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
+    pub fn as_slice(&self) -> &[RpcBlock] {
         match self {
-            BlockOrMany::Many(b) => b.as_slice(),
-            BlockOrMany::Block(b) => {
-                let ptr = Arc::as_ptr(b);
-                unsafe { slice::from_raw_parts(ptr, 1) }
+            BlockOrMany::Many(blocks, ..) => blocks,
+            BlockOrMany::Block(block, ..) => slice::from_ref(&**block),
+        }
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, RpcBlock> {
+        self.as_slice().iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    /// Clones a notified block unless this was its last reference
+    pub fn into_vec(self) -> Vec<RpcBlock> {
+        match self {
+            BlockOrMany::Many(blocks, ..) => blocks,
+            BlockOrMany::Block(block, ..) => {
+                vec![Arc::try_unwrap(block).unwrap_or_else(|block| (*block).clone())]
             }
         }
     }
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, PartialOrd)]
+impl<'a> IntoIterator for &'a BlockOrMany {
+    type Item = &'a RpcBlock;
+    type IntoIter = slice::Iter<'a, RpcBlock>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
 pub struct CompactHeader {
     pub blue_work: BlueWorkType,
     pub daa_score: u64,
 }
+
+impl CompactHeader {
+    pub const ENCODED_LEN: usize = 24 + 8;
+
+    /// `[blue_work (24 BE)] [daa_score (8 BE)]`, the byte order of encoded headers is their
+    /// [`Ord`] order
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0u8; Self::ENCODED_LEN];
+        bytes[..24].copy_from_slice(&self.blue_work.to_be_bytes());
+        bytes[24..].copy_from_slice(&self.daa_score.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Ok(bytes) = <&[u8; Self::ENCODED_LEN]>::try_from(bytes) else {
+            anyhow::bail!("Invalid compact header length: {}", bytes.len());
+        };
+        Ok(Self {
+            blue_work: BlueWorkType::from_be_bytes(bytes[..24].try_into()?),
+            daa_score: u64::from_be_bytes(bytes[24..].try_into()?),
+        })
+    }
+}
+
+/// Blue work first, the DAA score breaks ties
+impl Ord for CompactHeader {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.blue_work
+            .cmp(&other.blue_work)
+            .then(self.daa_score.cmp(&other.daa_score))
+    }
+}
+
+impl PartialOrd for CompactHeader {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaspa_consensus_core::header::Header;
+    use kaspa_rpc_core::RpcHash;
+
+    fn block(i: u64) -> RpcBlock {
+        let header = Header::from_precomputed_hash(RpcHash::from_u64_word(i), vec![]);
+        RpcBlock {
+            header: (&header).into(),
+            transactions: vec![],
+            verbose_data: None,
+        }
+    }
+
+    /// Run under Miri as well, see the CI workflow
+    #[test]
+    fn test_block_or_many_slices() {
+        let notified = Arc::new(block(1));
+        let single = BlockOrMany::Block(notified.clone(), None, "node".into(), Default::default());
+        assert_eq!(single.len(), 1);
+        assert!(!single.is_empty());
+        assert!(std::ptr::eq(&single.as_slice()[0], &*notified));
+        assert_eq!(
+            (&single)
+                .into_iter()
+                .map(|b| b.header.hash)
+                .collect::<Vec<_>>(),
+            vec![RpcHash::from_u64_word(1)]
+        );
+        // shared with the test, so the block is cloned
+        assert_eq!(single.into_vec()[0].header.hash, notified.header.hash);
+        let single = BlockOrMany::Block(notified, None, "node".into(), Default::default());
+        assert_eq!(single.into_vec().len(), 1);
+
+        let many = BlockOrMany::Many(
+            (2..5).map(block).collect(),
+            "node".into(),
+            Default::default(),
+        );
+        assert_eq!(many.len(), 3);
+        assert_eq!(
+            many.iter().map(|b| b.header.hash).collect::<Vec<_>>(),
+            (2..5).map(RpcHash::from_u64_word).collect::<Vec<_>>()
+        );
+        assert_eq!(many.into_vec().len(), 3);
+        assert!(BlockOrMany::Many(vec![], "node".into(), Default::default()).is_empty());
+    }
+
+    /// Headers with few distinct values, so blue work and DAA score ties are common
+    fn headers(count: usize) -> Vec<CompactHeader> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..count)
+            .map(|_| {
+                let mut blue_work = [0u8; 24];
+                // spread over the high and low limbs
+                blue_work[0] = (next() % 3) as u8;
+                blue_work[23] = (next() % 3) as u8;
+                CompactHeader {
+                    blue_work: BlueWorkType::from_be_bytes(blue_work),
+                    daa_score: match next() % 4 {
+                        0 => u64::MAX,
+                        score => (score << 56) | (next() % 2),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compact_header_encoding_roundtrip() {
+        for header in headers(200) {
+            let encoded = header.encode();
+            assert_eq!(CompactHeader::decode(&encoded).unwrap(), header);
+        }
+        assert!(CompactHeader::decode(&[0; CompactHeader::ENCODED_LEN - 1]).is_err());
+        assert!(CompactHeader::decode(&[0; CompactHeader::ENCODED_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_compact_header_order_matches_encoding() {
+        let headers = headers(64);
+        for a in &headers {
+            for b in &headers {
+                assert_eq!(a.cmp(b), a.encode().cmp(&b.encode()), "{a:?} {b:?}");
+                assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+                assert_eq!(a.cmp(b) == std::cmp::Ordering::Equal, a == b);
+            }
+        }
+
+        // same blue work, the DAA score decides
+        let blue_work = BlueWorkType::from_u64(1_000);
+        let low = CompactHeader {
+            blue_work,
+            daa_score: 5,
+        };
+        let high = CompactHeader {
+            blue_work,
+            daa_score: 6,
+        };
+        assert!(low < high);
+        // more blue work wins over a higher DAA score
+        let heavier = CompactHeader {
+            blue_work: BlueWorkType::from_u64(1_001),
+            daa_score: 0,
+        };
+        assert!(high < heavier);
+        let mut sorted = vec![heavier, high, low];
+        sorted.sort();
+        assert_eq!(sorted, [low, high, heavier]);
+        assert_eq!(std::collections::HashSet::from([low, high, low]).len(), 2);
+    }
+}
@@ -0,0 +1,264 @@
+//! Pending transactions of the node mempool, kept in memory.
+//!
+//! The [`MempoolProcessor`] polls `getMempoolEntries` and records the transactions it has not
+//! seen yet. The block processor removes them once their block is committed, the ones still
+//! pending after the TTL are dropped as evicted. Nothing is persisted, a restart starts over
+//! with the entries of the next poll.
+
+use crate::database::block_stats::nearest_rank;
+use crate::metrics::SharedMetrics;
+use crate::shutdown::Shutdown;
+use anyhow::Result;
+use kaspa_consensus_core::tx::Transaction;
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_rpc_core::{RpcMempoolEntry, RpcTransactionId};
+use kaspa_wrpc_client::KaspaRpcClient;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_MEMPOOL_POLL_INTERVAL: Duration = Duration::from_secs(2);
+pub const DEFAULT_MEMPOOL_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Percentiles of [`MempoolSummary::fee_rate_percentiles`]
+pub const FEE_RATE_PERCENTILES: [u8; 3] = [50, 90, 99];
+
+/// Pending transaction as first seen in the mempool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MempoolEntry {
+    pub first_seen_ms: u64,
+    pub fee: u64,
+    /// Mass committed to by the transaction, its compute mass if it committed to none
+    pub mass: u64,
+}
+
+impl MempoolEntry {
+    /// Sompi per gram, none without mass
+    pub fn fee_rate(&self) -> Option<f64> {
+        (self.mass > 0).then(|| self.fee as f64 / self.mass as f64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolSummary {
+    pub count: usize,
+    /// Fee rates at [`FEE_RATE_PERCENTILES`], none while no entry has mass
+    pub fee_rate_percentiles: Option<Vec<f64>>,
+}
+
+/// Pending transactions shared by the processor, the block processor and the readers
+#[derive(Clone, Default)]
+pub struct Mempool(Arc<RwLock<HashMap<RpcTransactionId, MempoolEntry>>>);
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_mempool_entry(&self, tx_id: &RpcTransactionId) -> Option<MempoolEntry> {
+        self.0.read().get(tx_id).copied()
+    }
+
+    pub fn mempool_summary(&self) -> MempoolSummary {
+        let entries = self.0.read();
+        let mut rates = entries
+            .values()
+            .filter_map(MempoolEntry::fee_rate)
+            .collect::<Vec<_>>();
+        MempoolSummary {
+            count: entries.len(),
+            fee_rate_percentiles: nearest_rank(&mut rates, &FEE_RATE_PERCENTILES),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().is_empty()
+    }
+
+    /// Records the transactions not tracked yet as first seen at `now_ms`, returns how many
+    pub fn observe(
+        &self,
+        entries: impl IntoIterator<Item = (RpcTransactionId, u64, u64)>,
+        now_ms: u64,
+    ) -> usize {
+        let mut tracked = self.0.write();
+        let before = tracked.len();
+        for (tx_id, fee, mass) in entries {
+            tracked.entry(tx_id).or_insert(MempoolEntry {
+                first_seen_ms: now_ms,
+                fee,
+                mass,
+            });
+        }
+        tracked.len() - before
+    }
+
+    /// Forgets the transactions of a committed block
+    pub fn remove_included<'a>(&self, tx_ids: impl IntoIterator<Item = &'a RpcTransactionId>) {
+        let mut tracked = self.0.write();
+        if tracked.is_empty() {
+            return;
+        }
+        for tx_id in tx_ids {
+            tracked.remove(tx_id);
+        }
+    }
+
+    /// Drops the entries first seen `ttl` or longer before `now_ms`, returns how many
+    pub fn evict_expired(&self, now_ms: u64, ttl: Duration) -> usize {
+        let seen_after = now_ms.saturating_sub(ttl.as_millis() as u64);
+        let mut tracked = self.0.write();
+        let before = tracked.len();
+        tracked.retain(|_, entry| entry.first_seen_ms > seen_after);
+        before - tracked.len()
+    }
+}
+
+/// Polls the node mempool into a [`Mempool`]
+pub struct MempoolProcessor {
+    rpc_client: KaspaRpcClient,
+    mempool: Mempool,
+    metrics: SharedMetrics,
+    poll_interval: Duration,
+    ttl: Duration,
+}
+
+impl MempoolProcessor {
+    pub fn new(rpc_client: KaspaRpcClient, mempool: Mempool, metrics: SharedMetrics) -> Self {
+        Self {
+            rpc_client,
+            mempool,
+            metrics,
+            poll_interval: DEFAULT_MEMPOOL_POLL_INTERVAL,
+            ttl: DEFAULT_MEMPOOL_TTL,
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Pending this long without being indexed, an entry is dropped as evicted
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub async fn run(self, shutdown: Shutdown) {
+        info!(
+            "Mempool processor started, polling every {:?}",
+            self.poll_interval
+        );
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(self.poll_interval) => {}
+            }
+            // entries stay tracked while disconnected, only the TTL drops them
+            if self.rpc_client.is_connected()
+                && let Err(err) = self.poll().await
+            {
+                warn!("Mempool poll failed: {err:#}");
+            }
+            let evicted = self.mempool.evict_expired(unix_ms(), self.ttl);
+            if evicted > 0 {
+                debug!(evicted, "Evicted mempool transactions past their TTL");
+                self.metrics.add_mempool_evictions(evicted as u64);
+            }
+            self.metrics
+                .set_mempool_transactions(self.mempool.len() as u64);
+        }
+        info!("Mempool processor stopped");
+    }
+
+    async fn poll(&self) -> Result<()> {
+        let entries = self.rpc_client.get_mempool_entries(false, false).await?;
+        let entries = entries
+            .iter()
+            .map(|entry| Ok((tx_id(entry)?, entry.fee, mass(entry))))
+            .collect::<Result<Vec<_>>>()?;
+        let added = self.mempool.observe(entries, unix_ms());
+        if added > 0 {
+            debug!(added, "Tracked new mempool transactions");
+        }
+        Ok(())
+    }
+}
+
+fn tx_id(entry: &RpcMempoolEntry) -> Result<RpcTransactionId> {
+    Ok(match &entry.transaction.verbose_data {
+        Some(data) => data.transaction_id,
+        None => Transaction::try_from(entry.transaction.clone())?.id(),
+    })
+}
+
+fn mass(entry: &RpcMempoolEntry) -> u64 {
+    match entry.transaction.mass {
+        0 => entry
+            .transaction
+            .verbose_data
+            .as_ref()
+            .map_or(0, |data| data.compute_mass),
+        mass => mass,
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx_id(byte: u8) -> RpcTransactionId {
+        RpcTransactionId::from_bytes([byte; 32])
+    }
+
+    #[test]
+    fn test_track_pending_transactions() {
+        let mempool = Mempool::new();
+        let entries = |fee_rates: &[(u8, u64)]| {
+            fee_rates
+                .iter()
+                .map(|&(byte, rate)| (tx_id(byte), rate * 1_000, 1_000))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(mempool.observe(entries(&[(1, 1), (2, 5)]), 1_000), 2);
+        // seen again later, the first sighting is kept
+        assert_eq!(mempool.observe(entries(&[(2, 5), (3, 10)]), 5_000), 1);
+        assert_eq!(
+            mempool.get_mempool_entry(&tx_id(2)).unwrap().first_seen_ms,
+            1_000
+        );
+        assert_eq!(
+            mempool.mempool_summary(),
+            MempoolSummary {
+                count: 3,
+                fee_rate_percentiles: Some(vec![5.0, 10.0, 10.0]),
+            }
+        );
+
+        mempool.remove_included(&[tx_id(3), tx_id(9)]);
+        assert_eq!(mempool.get_mempool_entry(&tx_id(3)), None);
+        assert_eq!(mempool.len(), 2);
+
+        // 1 and 2 were first seen an hour ago
+        let now_ms = 1_000 + DEFAULT_MEMPOOL_TTL.as_millis() as u64;
+        assert_eq!(mempool.observe(entries(&[(4, 2)]), now_ms), 1);
+        assert_eq!(mempool.evict_expired(now_ms, DEFAULT_MEMPOOL_TTL), 2);
+        assert_eq!(mempool.mempool_summary().count, 1);
+        mempool.remove_included(&[tx_id(4)]);
+        assert_eq!(mempool.mempool_summary().fee_rate_percentiles, None);
+    }
+}
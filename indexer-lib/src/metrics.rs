@@ -1,8 +1,133 @@
+use crate::BlockOrMany;
+use crate::database::compaction::CompactionRun;
+use crate::database::headers::HeaderCacheStats;
+use crate::database::processing::TxIdFilterStats;
+use crate::database::stats::DatabaseStats;
+use crate::mirror_feed::NodeHealth;
+use crate::scheduler::TaskStats;
+use crate::status::SyncStatus;
 use arc_swap::ArcSwap;
 use kaspa_rpc_core::RpcHash;
+use parking_lot::Mutex;
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets in microseconds, one more bucket counts
+/// everything above the last bound
+pub const LATENCY_BUCKETS_MICROS: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+/// Latency histogram with fixed buckets
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_MICROS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+/// Counts per bucket of [`LATENCY_BUCKETS_MICROS`], the last one holding the overflow
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LatencyHistogramSnapshot {
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_micros: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+            count: Default::default(),
+            sum_micros: Default::default(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &LatencyHistogramSnapshot) -> Self {
+        let histogram = Self::new();
+        for (bucket, count) in histogram.buckets.iter().zip(&snapshot.buckets) {
+            bucket.store(*count, Ordering::Relaxed);
+        }
+        histogram.count.store(snapshot.count, Ordering::Relaxed);
+        histogram
+            .sum_micros
+            .store(snapshot.sum_micros, Ordering::Relaxed);
+        histogram
+    }
+
+    pub fn observe(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKETS_MICROS.partition_point(|bound| *bound < micros);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LatencyHistogramSnapshot {
+        LatencyHistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogramSnapshot {
+    /// Upper bound of the bucket holding the quantile, none without observations or when it
+    /// falls into the overflow bucket
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS_MICROS
+                    .get(bucket)
+                    .map(|bound| Duration::from_micros(*bound));
+            }
+        }
+        None
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.sum_micros / self.count))
+    }
+}
+
+impl Display for LatencyHistogramSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let Some(mean) = self.mean() else {
+            return write!(f, "no samples");
+        };
+        let quantile = |q| match self.quantile(q) {
+            Some(bound) => format!("<= {bound:?}"),
+            None => "overflow".to_string(),
+        };
+        write!(
+            f,
+            "mean {mean:?}, p50 {}, p99 {} ({} samples)",
+            quantile(0.5),
+            quantile(0.99),
+            self.count
+        )
+    }
+}
 
 /// A snapshot of the indexer metrics.
 /// This structure contains a copy of all metric counters as simple u64 values.
@@ -32,6 +157,157 @@ pub struct IndexerMetricsSnapshot {
     pub unknown_tx_entries: u64,
     pub resolved_daa: u64,
     pub resolved_senders: u64,
+    /// Number of blocks parked until their parents are processed
+    pub orphan_blocks: u64,
+    /// Number of parked blocks processed after their parents arrived
+    pub orphans_reprocessed: u64,
+    /// Number of blocks processed without their parents because the orphan pool was full
+    pub orphans_over_capacity: u64,
+    /// Number of backfills requested for the missing parents of orphans
+    pub orphan_backfills: u64,
+    /// Accepted transactions the block processor never indexed
+    pub unindexed_accepted_txs: u64,
+    /// Number of backfills requested because accepted transactions were never indexed
+    pub auto_created_gaps: u64,
+    /// Inputs waiting for the output they spend to be indexed
+    pub pending_spends: u64,
+    /// Number of entries removed while reverting reorged chain blocks
+    pub reorg_entries_removed: u64,
+    /// Number of reorgs removing more chain blocks than the deep reorg threshold
+    pub deep_reorgs: u64,
+    /// Number of finalized chain blocks a reorg tried to remove
+    pub finality_violations: u64,
+    /// Number of times the node connection was re-established
+    pub reconnects: u64,
+    /// DAA span covered by gaps created after reconnects
+    pub reconnect_gap_daa: u64,
+    /// Stored headers hashing differently after a consensus crate upgrade
+    pub header_validation_mismatches: u64,
+    /// Received blocks rejected since their headers hash differently than reported
+    pub header_hash_mismatches: u64,
+    /// Seconds since the last block notification, as of the last subscription check
+    pub seconds_since_last_notification: u64,
+    /// Number of times a silent subscription was registered again
+    pub resubscriptions: u64,
+    /// Block notifications already received from another node
+    pub duplicate_block_notifications: u64,
+    /// Blocks announced by a mirror node before the primary one
+    pub mirror_blocks_first: u64,
+    /// Reconnects of mirror node connections
+    pub mirror_reconnects: u64,
+    /// Resolver nodes synced on the expected network as of the last health check
+    pub resolver_usable_nodes: u64,
+    /// Resolver nodes configured
+    pub resolver_nodes: u64,
+    /// Resolver requests retried on another node after a failure
+    pub resolver_failovers: u64,
+    /// Node call permits currently held
+    pub rpc_permits_in_use: u64,
+    /// Total time calls waited for a permit of their node
+    pub rpc_permit_wait_ms: u64,
+    /// Nodes whose circuit breaker is open or waiting for the outcome of a call after cooldown
+    pub rpc_open_breakers: u64,
+    /// 1 while the subscriber is connected to the node
+    pub node_connected: u64,
+    /// Blocks from notifications committed by the block processor
+    pub subscriber_blocks_processed: u64,
+    /// Unix time in milliseconds the last block from a notification was committed
+    pub last_subscriber_block_unix_ms: u64,
+    /// Blocks waiting in the block processor intake
+    pub block_intake_depth: u64,
+    /// Block notifications dropped while the intake was over its high-water mark
+    pub blocks_dropped: u64,
+    /// Gaps recorded for dropped block notifications
+    pub overflow_gaps: u64,
+    /// Syncer runs which ended without filling their gap, as seen by the gap rescan
+    pub gap_sync_failures: u64,
+    /// Failed gaps waiting for their retry, as of the last gap rescan
+    pub gaps_backing_off: u64,
+    /// Processor restarts after an error or a panic
+    pub component_restarts: u64,
+    /// Indexed block events lagging subscribers missed
+    pub indexed_block_events_dropped: u64,
+    /// Blocks from notifications waiting in the block processor intake
+    pub subscriber_intake_depth: u64,
+    /// Blocks from historical sync waiting in the block processor intake
+    pub historical_intake_depth: u64,
+    /// Total time the subscriber waited for room in the full intake
+    pub subscriber_producer_blocked_ms: u64,
+    /// Total time the historical syncers waited for room in the full intake
+    pub historical_producer_blocked_ms: u64,
+    /// Time from receiving a block notification until the block is committed
+    pub block_e2e_latency: LatencyHistogramSnapshot,
+    /// Decoding time per block of a message, spread over the decoding threads
+    pub block_decode_time: LatencyHistogramSnapshot,
+    /// Time recomputing the hash of a received header, part of decoding
+    pub header_hash_verification_time: LatencyHistogramSnapshot,
+    /// Time the block processor spends writing a block, decoding runs beforehand in parallel
+    pub block_processing_time: LatencyHistogramSnapshot,
+    /// Commit time per block of a committed batch
+    pub block_commit_time: LatencyHistogramSnapshot,
+    /// Compact header lookups served from the cache
+    pub header_cache_hits: u64,
+    /// Compact header lookups which went to the store
+    pub header_cache_misses: u64,
+    /// Transaction lookups the filter answered without reading the store
+    pub tx_filter_negatives: u64,
+    /// Transaction lookups the filter passed to the store
+    pub tx_filter_positives: u64,
+    /// Passed lookups the store did not have
+    pub tx_filter_false_positives: u64,
+    /// Estimated false positive rate of the filter per million
+    pub tx_filter_fp_estimate_ppm: u64,
+    /// Webhook deliveries the receivers took
+    pub webhook_deliveries: u64,
+    /// Failed webhook delivery attempts
+    pub webhook_delivery_failures: u64,
+    /// Webhook deliveries given up on
+    pub webhook_dead_letters: u64,
+    /// Pending transactions tracked from the node mempool
+    pub mempool_transactions: u64,
+    /// Tracked mempool transactions dropped without being indexed in time
+    pub mempool_evictions: u64,
+    /// Difference in sompi between the indexed circulating supply and the node's, at the last check
+    pub supply_divergence: u64,
+    /// Supply checks whose divergence exceeded the tolerance
+    pub supply_divergence_alarms: u64,
+    /// Query API requests refused by the per address rate limit
+    pub api_rate_limited: u64,
+    /// Query API requests refused while the concurrent requests were at the cap
+    pub api_overloaded: u64,
+    /// Query API requests refused for a `limit` over the maximum
+    pub api_limit_rejections: u64,
+    /// Query API requests refused for a DAA range wider than the maximum
+    pub api_daa_range_rejections: u64,
+    /// Entries the query API listings read from the store
+    pub api_entries_read: u64,
+    /// Chain blocks the selected chain syncer forwarded to the virtual chain processor
+    pub chain_sync_blocks: u64,
+    /// Accepted transaction ids within the forwarded chain blocks
+    pub chain_sync_acceptance_records: u64,
+    /// DAA distance between the selected chain syncer position and its target
+    pub chain_sync_remaining_daa: u64,
+    /// DAA score the block tip is behind the node
+    pub block_lag_daa: u64,
+    /// DAA score the virtual chain processor tip is behind the node
+    pub acceptance_lag_daa: u64,
+    /// The larger lag in seconds at the target block rate
+    pub lag_seconds: u64,
+    /// Major compactions of single partitions
+    pub compactions: u64,
+    /// Scheduled compactions skipped while the indexer was behind the node
+    pub compactions_deferred: u64,
+    /// Disk bytes reclaimed by compactions
+    pub compaction_reclaimed_bytes: u64,
+    /// Unix time in milliseconds the last compaction started
+    pub last_compaction_unix_ms: u64,
+    pub last_compaction_duration_ms: u64,
+    /// Per-partition size statistics, refreshed every minute
+    pub database: DatabaseStats,
+    /// Runs of the periodic maintenance tasks, by task name
+    pub periodic_tasks: Vec<TaskStats>,
+    /// Health of the primary and mirror nodes, by node url
+    pub nodes: Vec<NodeHealth>,
 }
 
 impl Display for IndexerMetricsSnapshot {
@@ -61,7 +337,162 @@ impl Display for IndexerMetricsSnapshot {
         )?;
         writeln!(f, "  Unknown tx entries: {}", self.unknown_tx_entries)?;
         writeln!(f, "  Resolved DAA entries: {}", self.resolved_daa)?;
-        writeln!(f, "  Resolved senders: {}", self.resolved_senders)
+        writeln!(f, "  Resolved senders: {}", self.resolved_senders)?;
+        writeln!(f, "  Orphan blocks: {}", self.orphan_blocks)?;
+        writeln!(f, "  Pending spends: {}", self.pending_spends)?;
+        writeln!(f, "  Orphans reprocessed: {}", self.orphans_reprocessed)?;
+        writeln!(f, "  Orphans over capacity: {}", self.orphans_over_capacity)?;
+        writeln!(f, "  Orphan backfills: {}", self.orphan_backfills)?;
+        writeln!(
+            f,
+            "  Unindexed accepted transactions: {}",
+            self.unindexed_accepted_txs
+        )?;
+        writeln!(f, "  Auto-created gaps: {}", self.auto_created_gaps)?;
+        writeln!(f, "  Reorg entries removed: {}", self.reorg_entries_removed)?;
+        writeln!(f, "  Deep reorgs: {}", self.deep_reorgs)?;
+        writeln!(f, "  Finality violations: {}", self.finality_violations)?;
+        writeln!(
+            f,
+            "  Reconnects: {} (gap DAA span: {})",
+            self.reconnects, self.reconnect_gap_daa
+        )?;
+        writeln!(
+            f,
+            "  Header validation mismatches: {}",
+            self.header_validation_mismatches
+        )?;
+        writeln!(
+            f,
+            "  Received header hash mismatches: {}",
+            self.header_hash_mismatches
+        )?;
+        writeln!(
+            f,
+            "  Seconds since last notification: {} (resubscriptions: {})",
+            self.seconds_since_last_notification, self.resubscriptions
+        )?;
+        writeln!(
+            f,
+            "  Mirror nodes: {} blocks first, {} duplicates, {} reconnects",
+            self.mirror_blocks_first, self.duplicate_block_notifications, self.mirror_reconnects
+        )?;
+        writeln!(
+            f,
+            "  Resolver nodes: {}/{} usable, {} failovers",
+            self.resolver_usable_nodes, self.resolver_nodes, self.resolver_failovers
+        )?;
+        writeln!(
+            f,
+            "  Node calls: {} permits in use, {}ms waited for permits, {} open breakers",
+            self.rpc_permits_in_use, self.rpc_permit_wait_ms, self.rpc_open_breakers
+        )?;
+        writeln!(
+            f,
+            "  Node connected: {} ({} blocks from notifications, last at {} ms)",
+            self.node_connected == 1,
+            self.subscriber_blocks_processed,
+            self.last_subscriber_block_unix_ms
+        )?;
+        writeln!(
+            f,
+            "  Block intake depth: {} (dropped: {}, overflow gaps: {})",
+            self.block_intake_depth, self.blocks_dropped, self.overflow_gaps
+        )?;
+        writeln!(
+            f,
+            "  Gap sync failures: {} ({} gaps backing off)",
+            self.gap_sync_failures, self.gaps_backing_off
+        )?;
+        writeln!(f, "  Component restarts: {}", self.component_restarts)?;
+        writeln!(
+            f,
+            "  Indexed block events dropped: {}",
+            self.indexed_block_events_dropped
+        )?;
+        writeln!(
+            f,
+            "  Intake depth by path: {} subscriber, {} historical",
+            self.subscriber_intake_depth, self.historical_intake_depth
+        )?;
+        writeln!(
+            f,
+            "  Intake producers blocked: {} ms subscriber, {} ms historical",
+            self.subscriber_producer_blocked_ms, self.historical_producer_blocked_ms
+        )?;
+        writeln!(f, "  Block end-to-end latency: {}", self.block_e2e_latency)?;
+        writeln!(f, "  Block decode time: {}", self.block_decode_time)?;
+        writeln!(
+            f,
+            "  Header hash verification time: {}",
+            self.header_hash_verification_time
+        )?;
+        writeln!(f, "  Block processing time: {}", self.block_processing_time)?;
+        writeln!(f, "  Block commit time: {}", self.block_commit_time)?;
+        writeln!(
+            f,
+            "  Header cache hits/misses: {}/{}",
+            self.header_cache_hits, self.header_cache_misses
+        )?;
+        writeln!(
+            f,
+            "  Tx filter negatives/positives/false positives: {}/{}/{} (estimated fp rate: {} ppm)",
+            self.tx_filter_negatives,
+            self.tx_filter_positives,
+            self.tx_filter_false_positives,
+            self.tx_filter_fp_estimate_ppm
+        )?;
+        writeln!(
+            f,
+            "  Webhooks delivered/failed attempts/dead letters: {}/{}/{}",
+            self.webhook_deliveries, self.webhook_delivery_failures, self.webhook_dead_letters
+        )?;
+        writeln!(
+            f,
+            "  Mempool transactions: {} ({} evicted)",
+            self.mempool_transactions, self.mempool_evictions
+        )?;
+        writeln!(
+            f,
+            "  Supply divergence: {} sompi ({} alarms)",
+            self.supply_divergence, self.supply_divergence_alarms
+        )?;
+        writeln!(
+            f,
+            "  API rejected rate/overload/limit/DAA range: {}/{}/{}/{} ({} entries read)",
+            self.api_rate_limited,
+            self.api_overloaded,
+            self.api_limit_rejections,
+            self.api_daa_range_rejections,
+            self.api_entries_read
+        )?;
+        writeln!(
+            f,
+            "  Chain sync: {} blocks, {} acceptance records (remaining DAA: {})",
+            self.chain_sync_blocks,
+            self.chain_sync_acceptance_records,
+            self.chain_sync_remaining_daa
+        )?;
+        writeln!(
+            f,
+            "  Lag behind node: {} DAA blocks, {} DAA acceptance (~{}s)",
+            self.block_lag_daa, self.acceptance_lag_daa, self.lag_seconds
+        )?;
+        writeln!(
+            f,
+            "  Compactions: {} ({} deferred, {} bytes reclaimed, last took {}ms)",
+            self.compactions,
+            self.compactions_deferred,
+            self.compaction_reclaimed_bytes,
+            self.last_compaction_duration_ms
+        )?;
+        for task in &self.periodic_tasks {
+            writeln!(f, "  {task}")?;
+        }
+        for node in &self.nodes {
+            writeln!(f, "  {node}")?;
+        }
+        write!(f, "{}", self.database)
     }
 }
 
@@ -92,6 +523,153 @@ pub struct IndexerMetrics {
     pub unknown_tx_entries: AtomicU64,
     pub resolved_daa: AtomicU64,
     pub resolved_sender: AtomicU64,
+    /// Number of blocks parked until their parents are processed
+    pub orphan_blocks: AtomicU64,
+    /// Number of parked blocks processed after their parents arrived
+    pub orphans_reprocessed: AtomicU64,
+    /// Number of blocks processed without their parents because the orphan pool was full
+    pub orphans_over_capacity: AtomicU64,
+    /// Number of backfills requested for the missing parents of orphans
+    pub orphan_backfills: AtomicU64,
+    /// Accepted transactions the block processor never indexed
+    pub unindexed_accepted_txs: AtomicU64,
+    /// Number of backfills requested because accepted transactions were never indexed
+    pub auto_created_gaps: AtomicU64,
+    /// Inputs waiting for the output they spend to be indexed
+    pub pending_spends: AtomicU64,
+    /// Number of entries removed while reverting reorged chain blocks
+    pub reorg_entries_removed: AtomicU64,
+    /// Number of reorgs removing more chain blocks than the deep reorg threshold
+    pub deep_reorgs: AtomicU64,
+    /// Number of finalized chain blocks a reorg tried to remove
+    pub finality_violations: AtomicU64,
+    /// Number of times the node connection was re-established
+    pub reconnects: AtomicU64,
+    /// DAA span covered by gaps created after reconnects
+    pub reconnect_gap_daa: AtomicU64,
+    /// Stored headers hashing differently after a consensus crate upgrade
+    pub header_validation_mismatches: AtomicU64,
+    /// Received blocks rejected since their headers hash differently than reported
+    pub header_hash_mismatches: AtomicU64,
+    /// Seconds since the last block notification, as of the last subscription check
+    pub seconds_since_last_notification: AtomicU64,
+    /// Number of times a silent subscription was registered again
+    pub resubscriptions: AtomicU64,
+    /// Block notifications already received from another node
+    pub duplicate_block_notifications: AtomicU64,
+    /// Blocks announced by a mirror node before the primary one
+    pub mirror_blocks_first: AtomicU64,
+    /// Reconnects of mirror node connections
+    pub mirror_reconnects: AtomicU64,
+    /// Resolver nodes synced on the expected network as of the last health check
+    pub resolver_usable_nodes: AtomicU64,
+    /// Resolver nodes configured
+    pub resolver_nodes: AtomicU64,
+    /// Resolver requests retried on another node after a failure
+    pub resolver_failovers: AtomicU64,
+    /// Node call permits currently held
+    pub rpc_permits_in_use: AtomicU64,
+    /// Total time calls waited for a permit of their node
+    pub rpc_permit_wait_ms: AtomicU64,
+    /// Nodes whose circuit breaker is open or waiting for the outcome of a call after cooldown
+    pub rpc_open_breakers: AtomicU64,
+    /// 1 while the subscriber is connected to the node
+    pub node_connected: AtomicU64,
+    /// Blocks from notifications committed by the block processor
+    pub subscriber_blocks_processed: AtomicU64,
+    /// Unix time in milliseconds the last block from a notification was committed
+    pub last_subscriber_block_unix_ms: AtomicU64,
+    /// Blocks waiting in the block processor intake
+    pub block_intake_depth: AtomicU64,
+    /// Block notifications dropped while the intake was over its high-water mark
+    pub blocks_dropped: AtomicU64,
+    /// Gaps recorded for dropped block notifications
+    pub overflow_gaps: AtomicU64,
+    /// Syncer runs which ended without filling their gap, as seen by the gap rescan
+    pub gap_sync_failures: AtomicU64,
+    /// Failed gaps waiting for their retry, as of the last gap rescan
+    pub gaps_backing_off: AtomicU64,
+    /// Processor restarts after an error or a panic
+    pub component_restarts: AtomicU64,
+    /// Indexed block events lagging subscribers missed
+    pub indexed_block_events_dropped: AtomicU64,
+    /// Blocks from notifications waiting in the block processor intake
+    pub subscriber_intake_depth: AtomicU64,
+    /// Blocks from historical sync waiting in the block processor intake
+    pub historical_intake_depth: AtomicU64,
+    /// Total time the subscriber waited for room in the full intake
+    pub subscriber_producer_blocked_ms: AtomicU64,
+    /// Total time the historical syncers waited for room in the full intake
+    pub historical_producer_blocked_ms: AtomicU64,
+    /// Time from receiving a block notification until the block is committed
+    pub block_e2e_latency: LatencyHistogram,
+    /// Decoding time per block of a message, spread over the decoding threads
+    pub block_decode_time: LatencyHistogram,
+    /// Time recomputing the hash of a received header, part of decoding
+    pub header_hash_verification_time: LatencyHistogram,
+    /// Time the block processor spends writing a block, decoding runs beforehand in parallel
+    pub block_processing_time: LatencyHistogram,
+    /// Commit time per block of a committed batch
+    pub block_commit_time: LatencyHistogram,
+    /// Compact header lookups served from the cache
+    pub header_cache_hits: AtomicU64,
+    /// Compact header lookups which went to the store
+    pub header_cache_misses: AtomicU64,
+    pub tx_filter_negatives: AtomicU64,
+    pub tx_filter_positives: AtomicU64,
+    pub tx_filter_false_positives: AtomicU64,
+    pub tx_filter_fp_estimate_ppm: AtomicU64,
+    /// Webhook deliveries the receivers took
+    pub webhook_deliveries: AtomicU64,
+    /// Failed webhook delivery attempts
+    pub webhook_delivery_failures: AtomicU64,
+    /// Webhook deliveries given up on
+    pub webhook_dead_letters: AtomicU64,
+    /// Pending transactions tracked from the node mempool
+    pub mempool_transactions: AtomicU64,
+    /// Tracked mempool transactions dropped without being indexed in time
+    pub mempool_evictions: AtomicU64,
+    /// Difference in sompi between the indexed circulating supply and the node's, at the last check
+    pub supply_divergence: AtomicU64,
+    /// Supply checks whose divergence exceeded the tolerance
+    pub supply_divergence_alarms: AtomicU64,
+    /// Query API requests refused by the per address rate limit
+    pub api_rate_limited: AtomicU64,
+    /// Query API requests refused while the concurrent requests were at the cap
+    pub api_overloaded: AtomicU64,
+    /// Query API requests refused for a `limit` over the maximum
+    pub api_limit_rejections: AtomicU64,
+    /// Query API requests refused for a DAA range wider than the maximum
+    pub api_daa_range_rejections: AtomicU64,
+    /// Entries the query API listings read from the store
+    pub api_entries_read: AtomicU64,
+    /// Chain blocks the selected chain syncer forwarded to the virtual chain processor
+    pub chain_sync_blocks: AtomicU64,
+    /// Accepted transaction ids within the forwarded chain blocks
+    pub chain_sync_acceptance_records: AtomicU64,
+    /// DAA distance between the selected chain syncer position and its target
+    pub chain_sync_remaining_daa: AtomicU64,
+    /// DAA score the block tip is behind the node
+    pub block_lag_daa: AtomicU64,
+    /// DAA score the virtual chain processor tip is behind the node
+    pub acceptance_lag_daa: AtomicU64,
+    /// The larger lag in seconds at the target block rate
+    pub lag_seconds: AtomicU64,
+    /// Major compactions of single partitions
+    pub compactions: AtomicU64,
+    /// Scheduled compactions skipped while the indexer was behind the node
+    pub compactions_deferred: AtomicU64,
+    /// Disk bytes reclaimed by compactions
+    pub compaction_reclaimed_bytes: AtomicU64,
+    /// Unix time in milliseconds the last compaction started
+    pub last_compaction_unix_ms: AtomicU64,
+    pub last_compaction_duration_ms: AtomicU64,
+    /// Per-partition size statistics, refreshed every minute
+    pub database: ArcSwap<DatabaseStats>,
+    /// Runs of the periodic maintenance tasks, sorted by task name
+    pub periodic_tasks: Mutex<Vec<TaskStats>>,
+    /// Health of the primary and mirror nodes, sorted by node url
+    pub nodes: Mutex<Vec<NodeHealth>>,
 }
 
 impl IndexerMetrics {
@@ -111,6 +689,82 @@ impl IndexerMetrics {
             unknown_tx_entries: AtomicU64::new(0),
             resolved_daa: Default::default(),
             resolved_sender: Default::default(),
+            orphan_blocks: Default::default(),
+            pending_spends: Default::default(),
+            orphans_reprocessed: Default::default(),
+            orphans_over_capacity: Default::default(),
+            orphan_backfills: Default::default(),
+            unindexed_accepted_txs: Default::default(),
+            auto_created_gaps: Default::default(),
+            reorg_entries_removed: Default::default(),
+            deep_reorgs: Default::default(),
+            finality_violations: Default::default(),
+            reconnects: Default::default(),
+            reconnect_gap_daa: Default::default(),
+            header_validation_mismatches: Default::default(),
+            header_hash_mismatches: Default::default(),
+            seconds_since_last_notification: Default::default(),
+            resubscriptions: Default::default(),
+            duplicate_block_notifications: Default::default(),
+            mirror_blocks_first: Default::default(),
+            mirror_reconnects: Default::default(),
+            resolver_usable_nodes: Default::default(),
+            resolver_nodes: Default::default(),
+            resolver_failovers: Default::default(),
+            rpc_permits_in_use: Default::default(),
+            rpc_permit_wait_ms: Default::default(),
+            rpc_open_breakers: Default::default(),
+            node_connected: Default::default(),
+            subscriber_blocks_processed: Default::default(),
+            last_subscriber_block_unix_ms: Default::default(),
+            block_intake_depth: Default::default(),
+            blocks_dropped: Default::default(),
+            overflow_gaps: Default::default(),
+            gap_sync_failures: Default::default(),
+            gaps_backing_off: Default::default(),
+            component_restarts: Default::default(),
+            indexed_block_events_dropped: Default::default(),
+            subscriber_intake_depth: Default::default(),
+            historical_intake_depth: Default::default(),
+            subscriber_producer_blocked_ms: Default::default(),
+            historical_producer_blocked_ms: Default::default(),
+            block_e2e_latency: Default::default(),
+            block_decode_time: Default::default(),
+            header_hash_verification_time: Default::default(),
+            block_processing_time: Default::default(),
+            block_commit_time: Default::default(),
+            header_cache_hits: Default::default(),
+            header_cache_misses: Default::default(),
+            tx_filter_negatives: Default::default(),
+            tx_filter_positives: Default::default(),
+            tx_filter_false_positives: Default::default(),
+            tx_filter_fp_estimate_ppm: Default::default(),
+            webhook_deliveries: Default::default(),
+            webhook_delivery_failures: Default::default(),
+            webhook_dead_letters: Default::default(),
+            mempool_transactions: Default::default(),
+            mempool_evictions: Default::default(),
+            supply_divergence: Default::default(),
+            supply_divergence_alarms: Default::default(),
+            api_rate_limited: Default::default(),
+            api_overloaded: Default::default(),
+            api_limit_rejections: Default::default(),
+            api_daa_range_rejections: Default::default(),
+            api_entries_read: Default::default(),
+            chain_sync_blocks: Default::default(),
+            chain_sync_acceptance_records: Default::default(),
+            chain_sync_remaining_daa: Default::default(),
+            block_lag_daa: Default::default(),
+            acceptance_lag_daa: Default::default(),
+            lag_seconds: Default::default(),
+            compactions: Default::default(),
+            compactions_deferred: Default::default(),
+            compaction_reclaimed_bytes: Default::default(),
+            last_compaction_unix_ms: Default::default(),
+            last_compaction_duration_ms: Default::default(),
+            database: Default::default(),
+            periodic_tasks: Default::default(),
+            nodes: Default::default(),
         }
     }
 
@@ -130,6 +784,86 @@ impl IndexerMetrics {
             unknown_tx_entries: AtomicU64::new(snapshot.unknown_tx_entries),
             resolved_daa: AtomicU64::new(snapshot.resolved_daa),
             resolved_sender: AtomicU64::new(snapshot.resolved_senders),
+            orphan_blocks: AtomicU64::new(snapshot.orphan_blocks),
+            pending_spends: AtomicU64::new(snapshot.pending_spends),
+            orphans_reprocessed: AtomicU64::new(snapshot.orphans_reprocessed),
+            orphans_over_capacity: AtomicU64::new(snapshot.orphans_over_capacity),
+            orphan_backfills: AtomicU64::new(snapshot.orphan_backfills),
+            unindexed_accepted_txs: AtomicU64::new(snapshot.unindexed_accepted_txs),
+            auto_created_gaps: AtomicU64::new(snapshot.auto_created_gaps),
+            reorg_entries_removed: AtomicU64::new(snapshot.reorg_entries_removed),
+            deep_reorgs: AtomicU64::new(snapshot.deep_reorgs),
+            finality_violations: AtomicU64::new(snapshot.finality_violations),
+            reconnects: AtomicU64::new(snapshot.reconnects),
+            reconnect_gap_daa: AtomicU64::new(snapshot.reconnect_gap_daa),
+            header_validation_mismatches: AtomicU64::new(snapshot.header_validation_mismatches),
+            header_hash_mismatches: AtomicU64::new(snapshot.header_hash_mismatches),
+            seconds_since_last_notification: AtomicU64::new(
+                snapshot.seconds_since_last_notification,
+            ),
+            resubscriptions: AtomicU64::new(snapshot.resubscriptions),
+            duplicate_block_notifications: AtomicU64::new(snapshot.duplicate_block_notifications),
+            mirror_blocks_first: AtomicU64::new(snapshot.mirror_blocks_first),
+            mirror_reconnects: AtomicU64::new(snapshot.mirror_reconnects),
+            resolver_usable_nodes: AtomicU64::new(snapshot.resolver_usable_nodes),
+            resolver_nodes: AtomicU64::new(snapshot.resolver_nodes),
+            resolver_failovers: AtomicU64::new(snapshot.resolver_failovers),
+            rpc_permits_in_use: AtomicU64::new(snapshot.rpc_permits_in_use),
+            rpc_permit_wait_ms: AtomicU64::new(snapshot.rpc_permit_wait_ms),
+            rpc_open_breakers: AtomicU64::new(snapshot.rpc_open_breakers),
+            node_connected: AtomicU64::new(snapshot.node_connected),
+            subscriber_blocks_processed: AtomicU64::new(snapshot.subscriber_blocks_processed),
+            last_subscriber_block_unix_ms: AtomicU64::new(snapshot.last_subscriber_block_unix_ms),
+            block_intake_depth: AtomicU64::new(snapshot.block_intake_depth),
+            blocks_dropped: AtomicU64::new(snapshot.blocks_dropped),
+            overflow_gaps: AtomicU64::new(snapshot.overflow_gaps),
+            gap_sync_failures: AtomicU64::new(snapshot.gap_sync_failures),
+            gaps_backing_off: AtomicU64::new(snapshot.gaps_backing_off),
+            component_restarts: AtomicU64::new(snapshot.component_restarts),
+            indexed_block_events_dropped: AtomicU64::new(snapshot.indexed_block_events_dropped),
+            subscriber_intake_depth: AtomicU64::new(snapshot.subscriber_intake_depth),
+            historical_intake_depth: AtomicU64::new(snapshot.historical_intake_depth),
+            subscriber_producer_blocked_ms: AtomicU64::new(snapshot.subscriber_producer_blocked_ms),
+            historical_producer_blocked_ms: AtomicU64::new(snapshot.historical_producer_blocked_ms),
+            block_e2e_latency: LatencyHistogram::from_snapshot(&snapshot.block_e2e_latency),
+            block_decode_time: LatencyHistogram::from_snapshot(&snapshot.block_decode_time),
+            header_hash_verification_time: LatencyHistogram::from_snapshot(
+                &snapshot.header_hash_verification_time,
+            ),
+            block_processing_time: LatencyHistogram::from_snapshot(&snapshot.block_processing_time),
+            block_commit_time: LatencyHistogram::from_snapshot(&snapshot.block_commit_time),
+            header_cache_hits: AtomicU64::new(snapshot.header_cache_hits),
+            header_cache_misses: AtomicU64::new(snapshot.header_cache_misses),
+            tx_filter_negatives: AtomicU64::new(snapshot.tx_filter_negatives),
+            tx_filter_positives: AtomicU64::new(snapshot.tx_filter_positives),
+            tx_filter_false_positives: AtomicU64::new(snapshot.tx_filter_false_positives),
+            tx_filter_fp_estimate_ppm: AtomicU64::new(snapshot.tx_filter_fp_estimate_ppm),
+            webhook_deliveries: AtomicU64::new(snapshot.webhook_deliveries),
+            webhook_delivery_failures: AtomicU64::new(snapshot.webhook_delivery_failures),
+            webhook_dead_letters: AtomicU64::new(snapshot.webhook_dead_letters),
+            mempool_transactions: AtomicU64::new(snapshot.mempool_transactions),
+            mempool_evictions: AtomicU64::new(snapshot.mempool_evictions),
+            supply_divergence: AtomicU64::new(snapshot.supply_divergence),
+            supply_divergence_alarms: AtomicU64::new(snapshot.supply_divergence_alarms),
+            api_rate_limited: AtomicU64::new(snapshot.api_rate_limited),
+            api_overloaded: AtomicU64::new(snapshot.api_overloaded),
+            api_limit_rejections: AtomicU64::new(snapshot.api_limit_rejections),
+            api_daa_range_rejections: AtomicU64::new(snapshot.api_daa_range_rejections),
+            api_entries_read: AtomicU64::new(snapshot.api_entries_read),
+            chain_sync_blocks: AtomicU64::new(snapshot.chain_sync_blocks),
+            chain_sync_acceptance_records: AtomicU64::new(snapshot.chain_sync_acceptance_records),
+            chain_sync_remaining_daa: AtomicU64::new(snapshot.chain_sync_remaining_daa),
+            block_lag_daa: AtomicU64::new(snapshot.block_lag_daa),
+            acceptance_lag_daa: AtomicU64::new(snapshot.acceptance_lag_daa),
+            lag_seconds: AtomicU64::new(snapshot.lag_seconds),
+            compactions: AtomicU64::new(snapshot.compactions),
+            compactions_deferred: AtomicU64::new(snapshot.compactions_deferred),
+            compaction_reclaimed_bytes: AtomicU64::new(snapshot.compaction_reclaimed_bytes),
+            last_compaction_unix_ms: AtomicU64::new(snapshot.last_compaction_unix_ms),
+            last_compaction_duration_ms: AtomicU64::new(snapshot.last_compaction_duration_ms),
+            database: ArcSwap::new(Arc::new(snapshot.database)),
+            periodic_tasks: Mutex::new(snapshot.periodic_tasks),
+            nodes: Mutex::new(snapshot.nodes),
         }
     }
 
@@ -149,6 +883,94 @@ impl IndexerMetrics {
             unknown_tx_entries: self.unknown_tx_entries.load(Ordering::Relaxed),
             resolved_daa: self.resolved_daa.load(Ordering::Relaxed),
             resolved_senders: self.resolved_sender.load(Ordering::Relaxed),
+            orphan_blocks: self.orphan_blocks.load(Ordering::Relaxed),
+            pending_spends: self.pending_spends.load(Ordering::Relaxed),
+            orphans_reprocessed: self.orphans_reprocessed.load(Ordering::Relaxed),
+            orphans_over_capacity: self.orphans_over_capacity.load(Ordering::Relaxed),
+            orphan_backfills: self.orphan_backfills.load(Ordering::Relaxed),
+            unindexed_accepted_txs: self.unindexed_accepted_txs.load(Ordering::Relaxed),
+            auto_created_gaps: self.auto_created_gaps.load(Ordering::Relaxed),
+            reorg_entries_removed: self.reorg_entries_removed.load(Ordering::Relaxed),
+            deep_reorgs: self.deep_reorgs.load(Ordering::Relaxed),
+            finality_violations: self.finality_violations.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            reconnect_gap_daa: self.reconnect_gap_daa.load(Ordering::Relaxed),
+            header_validation_mismatches: self.header_validation_mismatches.load(Ordering::Relaxed),
+            header_hash_mismatches: self.header_hash_mismatches.load(Ordering::Relaxed),
+            seconds_since_last_notification: self
+                .seconds_since_last_notification
+                .load(Ordering::Relaxed),
+            resubscriptions: self.resubscriptions.load(Ordering::Relaxed),
+            duplicate_block_notifications: self
+                .duplicate_block_notifications
+                .load(Ordering::Relaxed),
+            mirror_blocks_first: self.mirror_blocks_first.load(Ordering::Relaxed),
+            mirror_reconnects: self.mirror_reconnects.load(Ordering::Relaxed),
+            resolver_usable_nodes: self.resolver_usable_nodes.load(Ordering::Relaxed),
+            resolver_nodes: self.resolver_nodes.load(Ordering::Relaxed),
+            resolver_failovers: self.resolver_failovers.load(Ordering::Relaxed),
+            rpc_permits_in_use: self.rpc_permits_in_use.load(Ordering::Relaxed),
+            rpc_permit_wait_ms: self.rpc_permit_wait_ms.load(Ordering::Relaxed),
+            rpc_open_breakers: self.rpc_open_breakers.load(Ordering::Relaxed),
+            node_connected: self.node_connected.load(Ordering::Relaxed),
+            subscriber_blocks_processed: self.subscriber_blocks_processed.load(Ordering::Relaxed),
+            last_subscriber_block_unix_ms: self
+                .last_subscriber_block_unix_ms
+                .load(Ordering::Relaxed),
+            block_intake_depth: self.block_intake_depth.load(Ordering::Relaxed),
+            blocks_dropped: self.blocks_dropped.load(Ordering::Relaxed),
+            overflow_gaps: self.overflow_gaps.load(Ordering::Relaxed),
+            gap_sync_failures: self.gap_sync_failures.load(Ordering::Relaxed),
+            gaps_backing_off: self.gaps_backing_off.load(Ordering::Relaxed),
+            component_restarts: self.component_restarts.load(Ordering::Relaxed),
+            indexed_block_events_dropped: self.indexed_block_events_dropped.load(Ordering::Relaxed),
+            subscriber_intake_depth: self.subscriber_intake_depth.load(Ordering::Relaxed),
+            historical_intake_depth: self.historical_intake_depth.load(Ordering::Relaxed),
+            subscriber_producer_blocked_ms: self
+                .subscriber_producer_blocked_ms
+                .load(Ordering::Relaxed),
+            historical_producer_blocked_ms: self
+                .historical_producer_blocked_ms
+                .load(Ordering::Relaxed),
+            block_e2e_latency: self.block_e2e_latency.snapshot(),
+            block_decode_time: self.block_decode_time.snapshot(),
+            header_hash_verification_time: self.header_hash_verification_time.snapshot(),
+            block_processing_time: self.block_processing_time.snapshot(),
+            block_commit_time: self.block_commit_time.snapshot(),
+            header_cache_hits: self.header_cache_hits.load(Ordering::Relaxed),
+            header_cache_misses: self.header_cache_misses.load(Ordering::Relaxed),
+            tx_filter_negatives: self.tx_filter_negatives.load(Ordering::Relaxed),
+            tx_filter_positives: self.tx_filter_positives.load(Ordering::Relaxed),
+            tx_filter_false_positives: self.tx_filter_false_positives.load(Ordering::Relaxed),
+            tx_filter_fp_estimate_ppm: self.tx_filter_fp_estimate_ppm.load(Ordering::Relaxed),
+            webhook_deliveries: self.webhook_deliveries.load(Ordering::Relaxed),
+            webhook_delivery_failures: self.webhook_delivery_failures.load(Ordering::Relaxed),
+            webhook_dead_letters: self.webhook_dead_letters.load(Ordering::Relaxed),
+            mempool_transactions: self.mempool_transactions.load(Ordering::Relaxed),
+            mempool_evictions: self.mempool_evictions.load(Ordering::Relaxed),
+            supply_divergence: self.supply_divergence.load(Ordering::Relaxed),
+            supply_divergence_alarms: self.supply_divergence_alarms.load(Ordering::Relaxed),
+            api_rate_limited: self.api_rate_limited.load(Ordering::Relaxed),
+            api_overloaded: self.api_overloaded.load(Ordering::Relaxed),
+            api_limit_rejections: self.api_limit_rejections.load(Ordering::Relaxed),
+            api_daa_range_rejections: self.api_daa_range_rejections.load(Ordering::Relaxed),
+            api_entries_read: self.api_entries_read.load(Ordering::Relaxed),
+            chain_sync_blocks: self.chain_sync_blocks.load(Ordering::Relaxed),
+            chain_sync_acceptance_records: self
+                .chain_sync_acceptance_records
+                .load(Ordering::Relaxed),
+            chain_sync_remaining_daa: self.chain_sync_remaining_daa.load(Ordering::Relaxed),
+            block_lag_daa: self.block_lag_daa.load(Ordering::Relaxed),
+            acceptance_lag_daa: self.acceptance_lag_daa.load(Ordering::Relaxed),
+            lag_seconds: self.lag_seconds.load(Ordering::Relaxed),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            compactions_deferred: self.compactions_deferred.load(Ordering::Relaxed),
+            compaction_reclaimed_bytes: self.compaction_reclaimed_bytes.load(Ordering::Relaxed),
+            last_compaction_unix_ms: self.last_compaction_unix_ms.load(Ordering::Relaxed),
+            last_compaction_duration_ms: self.last_compaction_duration_ms.load(Ordering::Relaxed),
+            database: self.database.load().as_ref().clone(),
+            periodic_tasks: self.periodic_tasks.lock().clone(),
+            nodes: self.nodes.lock().clone(),
         }
     }
 
@@ -256,6 +1078,475 @@ impl IndexerMetrics {
     pub fn increment_senders_resolved(&self) {
         self.resolved_sender.fetch_add(1, Ordering::Relaxed);
     }
+
+    /// Set current orphan blocks count
+    pub fn set_orphan_blocks(&self, count: u64) {
+        self.orphan_blocks.store(count, Ordering::Relaxed);
+    }
+
+    /// Set current pending spends count
+    pub fn set_pending_spends(&self, count: u64) {
+        self.pending_spends.store(count, Ordering::Relaxed);
+    }
+
+    /// Increment reprocessed orphans count by 1
+    pub fn increment_orphans_reprocessed(&self) {
+        self.orphans_reprocessed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment orphans processed over capacity count by 1
+    pub fn increment_orphans_over_capacity(&self) {
+        self.orphans_over_capacity.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment orphan backfills count by 1
+    pub fn increment_orphan_backfills(&self) {
+        self.orphan_backfills.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add to the count of accepted transactions which were never indexed
+    pub fn add_unindexed_accepted_txs(&self, count: u64) {
+        self.unindexed_accepted_txs
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Increment backfills requested for unindexed accepted transactions by 1
+    pub fn increment_auto_created_gaps(&self) {
+        self.auto_created_gaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Update header cache counters
+    pub fn set_header_cache_stats(&self, stats: HeaderCacheStats) {
+        self.header_cache_hits.store(stats.hits, Ordering::Relaxed);
+        self.header_cache_misses
+            .store(stats.misses, Ordering::Relaxed);
+    }
+
+    /// Update transaction filter counters
+    pub fn set_tx_filter_stats(&self, stats: TxIdFilterStats) {
+        self.tx_filter_negatives
+            .store(stats.negatives, Ordering::Relaxed);
+        self.tx_filter_positives
+            .store(stats.positives, Ordering::Relaxed);
+        self.tx_filter_false_positives
+            .store(stats.false_positives, Ordering::Relaxed);
+        self.tx_filter_fp_estimate_ppm
+            .store(stats.fp_estimate_ppm, Ordering::Relaxed);
+    }
+
+    /// Increment webhook deliveries the receivers took by 1
+    pub fn increment_webhook_deliveries(&self) {
+        self.webhook_deliveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment failed webhook delivery attempts by 1
+    pub fn increment_webhook_delivery_failures(&self) {
+        self.webhook_delivery_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment webhook deliveries given up on by 1
+    pub fn increment_webhook_dead_letters(&self) {
+        self.webhook_dead_letters.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set pending transactions tracked from the node mempool
+    pub fn set_mempool_transactions(&self, count: u64) {
+        self.mempool_transactions.store(count, Ordering::Relaxed);
+    }
+
+    /// Add tracked mempool transactions dropped without being indexed
+    pub fn add_mempool_evictions(&self, count: u64) {
+        self.mempool_evictions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Set the divergence found by the last supply check
+    pub fn set_supply_divergence(&self, divergence: u64) {
+        self.supply_divergence.store(divergence, Ordering::Relaxed);
+    }
+
+    pub fn increment_supply_divergence_alarms(&self) {
+        self.supply_divergence_alarms
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_api_rate_limited(&self) {
+        self.api_rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_api_overloaded(&self) {
+        self.api_overloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_api_limit_rejections(&self) {
+        self.api_limit_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_api_daa_range_rejections(&self) {
+        self.api_daa_range_rejections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add entries a query API listing read from the store
+    pub fn add_api_entries_read(&self, count: u64) {
+        self.api_entries_read.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Counts one page applied by the selected chain syncer
+    pub fn add_chain_sync_page(&self, blocks: u64, acceptance_records: u64, remaining_daa: u64) {
+        self.chain_sync_blocks.fetch_add(blocks, Ordering::Relaxed);
+        self.chain_sync_acceptance_records
+            .fetch_add(acceptance_records, Ordering::Relaxed);
+        self.chain_sync_remaining_daa
+            .store(remaining_daa, Ordering::Relaxed);
+    }
+
+    /// Set the lag of the indexed tips behind the node
+    pub fn set_sync_lag(&self, status: &SyncStatus) {
+        self.block_lag_daa
+            .store(status.block_lag_daa, Ordering::Relaxed);
+        self.acceptance_lag_daa
+            .store(status.acceptance_lag_daa, Ordering::Relaxed);
+        self.lag_seconds
+            .store(status.lag.as_secs(), Ordering::Relaxed);
+    }
+
+    /// Replace per-partition size statistics
+    pub fn set_database_stats(&self, stats: DatabaseStats) {
+        self.database.store(Arc::new(stats));
+    }
+
+    /// Record a finished partition compaction
+    pub fn record_compaction(&self, run: &CompactionRun) {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        self.compaction_reclaimed_bytes
+            .fetch_add(run.reclaimed_bytes, Ordering::Relaxed);
+        self.last_compaction_unix_ms
+            .store(run.started_at_unix_ms, Ordering::Relaxed);
+        self.last_compaction_duration_ms
+            .store(run.duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Increment compactions deferred by 1
+    pub fn increment_compactions_deferred(&self) {
+        self.compactions_deferred.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn update_task(&self, name: &str, update: impl FnOnce(&mut TaskStats)) {
+        let mut tasks = self.periodic_tasks.lock();
+        let index = match tasks.binary_search_by(|task| task.name.as_str().cmp(name)) {
+            Ok(index) => index,
+            Err(index) => {
+                tasks.insert(
+                    index,
+                    TaskStats {
+                        name: name.to_string(),
+                        ..Default::default()
+                    },
+                );
+                index
+            }
+        };
+        update(&mut tasks[index]);
+    }
+
+    /// Record a finished run of a periodic task
+    pub fn record_task_run(&self, name: &str, duration: Duration, ok: bool) {
+        self.update_task(name, |task| {
+            task.runs += 1;
+            task.failures += u64::from(!ok);
+            task.last_duration = duration;
+            task.last_ok = ok;
+        });
+    }
+
+    /// Record a periodic task run skipped because the previous one was still in progress
+    pub fn record_task_skipped(&self, name: &str) {
+        self.update_task(name, |task| task.skipped += 1);
+    }
+
+    /// Record a periodic task run asked to stop after its timeout
+    pub fn record_task_timeout(&self, name: &str) {
+        self.update_task(name, |task| task.timeouts += 1);
+    }
+
+    fn update_node(&self, node: &str, update: impl FnOnce(&mut NodeHealth)) {
+        let mut nodes = self.nodes.lock();
+        let index = match nodes.binary_search_by(|health| health.node.as_str().cmp(node)) {
+            Ok(index) => index,
+            Err(index) => {
+                nodes.insert(
+                    index,
+                    NodeHealth {
+                        node: node.to_string(),
+                        ..Default::default()
+                    },
+                );
+                index
+            }
+        };
+        update(&mut nodes[index]);
+    }
+
+    /// Record a connect to a node, counted as reconnect unless it is the first one
+    pub fn record_node_connected(&self, node: &str, reconnect: bool) {
+        self.update_node(node, |health| {
+            health.connected = true;
+            health.reconnects += u64::from(reconnect);
+        });
+    }
+
+    /// Record a lost connection to a node
+    pub fn record_node_disconnected(&self, node: &str) {
+        self.update_node(node, |health| health.connected = false);
+    }
+
+    /// Record a block notification from a node
+    pub fn record_node_notification(&self, node: &str) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.update_node(node, |health| {
+            health.block_notifications += 1;
+            health.last_notification_unix_ms = now;
+        });
+    }
+
+    /// Add entries removed by a reorg
+    pub fn add_reorg_entries_removed(&self, count: u64) {
+        self.reorg_entries_removed
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Increment deep reorgs count by 1
+    pub fn increment_deep_reorgs(&self) {
+        self.deep_reorgs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment finality violations by 1
+    pub fn increment_finality_violations(&self) {
+        self.finality_violations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment header validation mismatches by 1
+    pub fn increment_header_validation_mismatches(&self) {
+        self.header_validation_mismatches
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment received header hash mismatches by 1
+    pub fn increment_header_hash_mismatches(&self) {
+        self.header_hash_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set seconds since the last block notification
+    pub fn set_seconds_since_last_notification(&self, seconds: u64) {
+        self.seconds_since_last_notification
+            .store(seconds, Ordering::Relaxed);
+    }
+
+    /// Increment resubscriptions by 1
+    pub fn increment_resubscriptions(&self) {
+        self.resubscriptions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment duplicate_block_notifications by 1
+    pub fn increment_duplicate_block_notifications(&self) {
+        self.duplicate_block_notifications
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment mirror_blocks_first by 1
+    pub fn increment_mirror_blocks_first(&self) {
+        self.mirror_blocks_first.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment mirror_reconnects by 1
+    pub fn increment_mirror_reconnects(&self) {
+        self.mirror_reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Update resolver node counts after a health check
+    pub fn set_resolver_nodes(&self, usable: u64, total: u64) {
+        self.resolver_usable_nodes.store(usable, Ordering::Relaxed);
+        self.resolver_nodes.store(total, Ordering::Relaxed);
+    }
+
+    /// Increment resolver_failovers by 1
+    pub fn increment_resolver_failovers(&self) {
+        self.resolver_failovers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment rpc_permits_in_use by 1
+    pub fn increment_rpc_permits_in_use(&self) {
+        self.rpc_permits_in_use.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrement rpc_permits_in_use by 1
+    pub fn decrement_rpc_permits_in_use(&self) {
+        _ = self
+            .rpc_permits_in_use
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(1))
+            });
+    }
+
+    /// Add to the total time calls waited for a permit
+    pub fn add_rpc_permit_wait_ms(&self, ms: u64) {
+        self.rpc_permit_wait_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    /// Increment rpc_open_breakers by 1
+    pub fn increment_rpc_open_breakers(&self) {
+        self.rpc_open_breakers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrement rpc_open_breakers by 1
+    pub fn decrement_rpc_open_breakers(&self) {
+        _ = self
+            .rpc_open_breakers
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(1))
+            });
+    }
+
+    /// Set whether the subscriber is connected to the node
+    pub fn set_node_connected(&self, connected: bool) {
+        self.node_connected
+            .store(connected as u64, Ordering::Relaxed);
+    }
+
+    /// Count a committed block from a notification and remember when it was committed
+    pub fn record_subscriber_block_processed(&self) {
+        self.subscriber_blocks_processed
+            .fetch_add(1, Ordering::Relaxed);
+        self.last_subscriber_block_unix_ms.store(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Set current block intake depth
+    pub fn set_block_intake_depth(&self, depth: u64) {
+        self.block_intake_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Increment dropped block notifications by 1
+    pub fn increment_blocks_dropped(&self) {
+        self.blocks_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment overflow gaps by 1
+    pub fn increment_overflow_gaps(&self) {
+        self.overflow_gaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment gap sync failures by 1
+    pub fn increment_gap_sync_failures(&self) {
+        self.gap_sync_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Set the failed gaps waiting for their retry
+    pub fn set_gaps_backing_off(&self, count: u64) {
+        self.gaps_backing_off.store(count, Ordering::Relaxed);
+    }
+
+    /// Increment component restarts by 1
+    pub fn increment_component_restarts(&self) {
+        self.component_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add indexed block events a lagging subscriber missed
+    pub fn add_indexed_block_events_dropped(&self, count: u64) {
+        self.indexed_block_events_dropped
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Count blocks sent into the block processor intake, call before sending
+    pub fn add_intake_blocks(&self, blocks: &BlockOrMany) {
+        self.intake_depth(blocks)
+            .fetch_add(blocks.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Count blocks taken out of the block processor intake
+    pub fn remove_intake_blocks(&self, blocks: &BlockOrMany) {
+        _ = self
+            .intake_depth(blocks)
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                Some(depth.saturating_sub(blocks.len() as u64))
+            });
+    }
+
+    fn intake_depth(&self, blocks: &BlockOrMany) -> &AtomicU64 {
+        match blocks {
+            BlockOrMany::Block(..) => &self.subscriber_intake_depth,
+            BlockOrMany::Many(..) => &self.historical_intake_depth,
+        }
+    }
+
+    /// Add the time the subscriber waited for room in the intake
+    pub fn add_subscriber_blocked(&self, blocked: Duration) {
+        self.subscriber_producer_blocked_ms
+            .fetch_add(blocked.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Add the time a historical syncer waited for room in the intake
+    pub fn add_historical_blocked(&self, blocked: Duration) {
+        self.historical_producer_blocked_ms
+            .fetch_add(blocked.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Record the time from notification to commit of a block
+    pub fn observe_block_e2e_latency(&self, latency: Duration) {
+        self.block_e2e_latency.observe(latency);
+    }
+
+    /// Record the time spent writing a block
+    pub fn observe_block_processing_time(&self, elapsed: Duration) {
+        self.block_processing_time.observe(elapsed);
+    }
+
+    /// Record the time spent decoding `blocks` blocks, per block
+    pub fn observe_block_decode_time(&self, elapsed: Duration, blocks: usize) {
+        if blocks > 0 {
+            self.block_decode_time.observe(elapsed / blocks as u32);
+        }
+    }
+
+    /// Record the time spent recomputing the hash of a received header
+    pub fn observe_header_hash_verification_time(&self, elapsed: Duration) {
+        self.header_hash_verification_time.observe(elapsed);
+    }
+
+    /// Record the time spent committing a batch of `blocks` blocks, per block
+    pub fn observe_block_commit_time(&self, elapsed: Duration, blocks: usize) {
+        if blocks > 0 {
+            self.block_commit_time.observe(elapsed / blocks as u32);
+        }
+    }
+
+    /// Block processor stage with the highest mean time per block so far, decoding is CPU
+    /// bound while writing and committing wait for the database
+    pub fn slowest_block_stage(&self) -> Option<(&'static str, Duration)> {
+        [
+            ("decode", &self.block_decode_time),
+            ("write", &self.block_processing_time),
+            ("commit", &self.block_commit_time),
+        ]
+        .into_iter()
+        .filter_map(|(stage, histogram)| Some((stage, histogram.snapshot().mean()?)))
+        .max_by_key(|(_, mean)| *mean)
+    }
+
+    /// Record a reconnect and the DAA span of the gap it left, zero if none
+    pub fn record_reconnect(&self, gap_daa: u64) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.reconnect_gap_daa.fetch_add(gap_daa, Ordering::Relaxed);
+    }
 }
 
 impl Default for IndexerMetrics {
@@ -276,3 +1567,49 @@ pub fn create_shared_metrics() -> SharedMetrics {
 pub fn create_shared_metrics_from_snapshot(snapshot: IndexerMetricsSnapshot) -> SharedMetrics {
     Arc::new(IndexerMetrics::from_snapshot(snapshot))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram_quantiles() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.snapshot().quantile(0.5), None);
+        for _ in 0..98 {
+            histogram.observe(Duration::from_micros(800));
+        }
+        histogram.observe(Duration::from_millis(40));
+        histogram.observe(Duration::from_secs(60));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 100);
+        assert_eq!(snapshot.quantile(0.5), Some(Duration::from_millis(1)));
+        assert_eq!(snapshot.quantile(0.99), Some(Duration::from_millis(50)));
+        // above the largest bound
+        assert_eq!(snapshot.quantile(1.0), None);
+        assert_eq!(
+            LatencyHistogram::from_snapshot(&snapshot).snapshot(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_slowest_block_stage() {
+        let metrics = IndexerMetrics::new();
+        assert_eq!(metrics.slowest_block_stage(), None);
+        metrics.observe_block_decode_time(Duration::from_millis(40), 10);
+        metrics.observe_block_processing_time(Duration::from_millis(2));
+        assert_eq!(
+            metrics.slowest_block_stage(),
+            Some(("decode", Duration::from_millis(4)))
+        );
+        // a slow commit of a large batch is spread over its blocks
+        metrics.observe_block_commit_time(Duration::from_millis(500), 50);
+        metrics.observe_block_commit_time(Duration::from_secs(1), 0);
+        assert_eq!(
+            metrics.slowest_block_stage(),
+            Some(("commit", Duration::from_millis(10)))
+        );
+    }
+}
@@ -0,0 +1,1284 @@
+//! Prometheus endpoint for the indexer metrics.
+//!
+//! Components register their metrics into a [`MetricsRegistry`] under stable names, values are
+//! read when scraped. [`serve`] answers `/metrics` in the Prometheus text format, `/healthz`
+//! with 200 only while the node is connected and blocks from notifications keep being committed,
+//! and `/status` with the [`Indexer`] status snapshot as JSON.
+
+use crate::database::stats::PartitionStats;
+use crate::metrics::{
+    IndexerMetrics, LATENCY_BUCKETS_MICROS, LatencyHistogramSnapshot, SharedMetrics,
+};
+use crate::mirror_feed::NodeHealth;
+use crate::scheduler::TaskStats;
+use crate::shutdown::Shutdown;
+use crate::status::Indexer;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, State};
+use axum::http::header::{CONTENT_TYPE, HOST};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Router};
+use http_body_util::{BodyExt, Empty};
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::service::TowerToHyperService;
+use parking_lot::RwLock;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Request heads not received completely within this are dropped
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest request head, the smallest read buffer hyper accepts
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+/// Single value of a family, `suffix` is appended to the family name, e.g. `_bucket`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub suffix: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+type Collect = Box<dyn Fn(&mut Vec<Sample>) + Send + Sync>;
+
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+    collectors: Vec<Collect>,
+}
+
+/// Metric families by name, registering a name again adds samples with other labels to it
+#[derive(Clone, Default)]
+pub struct MetricsRegistry {
+    families: Arc<RwLock<Vec<Family>>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        read: impl Fn() -> u64 + Send + Sync + 'static,
+    ) {
+        self.single(name, help, MetricKind::Counter, labels, read);
+    }
+
+    pub fn gauge(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        read: impl Fn() -> u64 + Send + Sync + 'static,
+    ) {
+        self.single(name, help, MetricKind::Gauge, labels, read);
+    }
+
+    /// Latency histogram exported in seconds
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        read: impl Fn() -> LatencyHistogramSnapshot + Send + Sync + 'static,
+    ) {
+        let labels = owned_labels(labels);
+        self.collector(name, help, MetricKind::Histogram, move |samples| {
+            let snapshot = read();
+            let mut cumulative = 0;
+            for (i, count) in snapshot.buckets.iter().enumerate() {
+                cumulative += count;
+                let le = LATENCY_BUCKETS_MICROS.get(i).map_or_else(
+                    || "+Inf".to_string(),
+                    |bound| (*bound as f64 / 1_000_000.0).to_string(),
+                );
+                let mut labels = labels.clone();
+                labels.push(("le", le));
+                samples.push(Sample {
+                    suffix: "_bucket",
+                    labels,
+                    value: cumulative as f64,
+                });
+            }
+            samples.push(Sample {
+                suffix: "_sum",
+                labels: labels.clone(),
+                value: snapshot.sum_micros as f64 / 1_000_000.0,
+            });
+            samples.push(Sample {
+                suffix: "_count",
+                labels: labels.clone(),
+                value: snapshot.count as f64,
+            });
+        });
+    }
+
+    /// Samples known only when scraped, e.g. one per database partition
+    pub fn collector(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: MetricKind,
+        collect: impl Fn(&mut Vec<Sample>) + Send + Sync + 'static,
+    ) {
+        let mut families = self.families.write();
+        match families.iter_mut().find(|family| family.name == name) {
+            Some(family) => {
+                debug_assert_eq!(family.kind, kind, "{name} registered as another kind");
+                family.collectors.push(Box::new(collect));
+            }
+            None => families.push(Family {
+                name,
+                help,
+                kind,
+                collectors: vec![Box::new(collect)],
+            }),
+        }
+    }
+
+    fn single(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: MetricKind,
+        labels: &[(&'static str, &str)],
+        read: impl Fn() -> u64 + Send + Sync + 'static,
+    ) {
+        let labels = owned_labels(labels);
+        self.collector(name, help, kind, move |samples| {
+            samples.push(Sample {
+                suffix: "",
+                labels: labels.clone(),
+                value: read() as f64,
+            })
+        });
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut samples = Vec::new();
+        for family in self.families.read().iter() {
+            samples.clear();
+            for collect in &family.collectors {
+                collect(&mut samples);
+            }
+            _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            _ = writeln!(out, "# TYPE {} {}", family.name, family.kind.as_str());
+            for sample in &samples {
+                out.push_str(family.name);
+                out.push_str(sample.suffix);
+                if !sample.labels.is_empty() {
+                    out.push('{');
+                    for (i, (name, value)) in sample.labels.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        _ = write!(out, "{name}=\"{}\"", escape_label(value));
+                    }
+                    out.push('}');
+                }
+                _ = writeln!(out, " {}", sample.value);
+            }
+        }
+        out
+    }
+}
+
+fn owned_labels(labels: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+    labels
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()))
+        .collect()
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn read(
+    metrics: &SharedMetrics,
+    field: fn(&IndexerMetrics) -> &AtomicU64,
+) -> impl Fn() -> u64 + Send + Sync + 'static {
+    let metrics = metrics.clone();
+    move || field(&metrics).load(Ordering::Relaxed)
+}
+
+/// Registers the metrics of every component
+pub fn register_indexer_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    register_subscriber_metrics(registry, metrics);
+    register_processor_metrics(registry, metrics);
+    register_syncer_metrics(registry, metrics);
+    register_resolver_metrics(registry, metrics);
+    register_database_metrics(registry, metrics);
+    register_task_metrics(registry, metrics);
+}
+
+pub fn register_subscriber_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    registry.gauge(
+        "indexer_node_connected",
+        "1 while the subscriber is connected to the node",
+        &[],
+        read(metrics, |m| &m.node_connected),
+    );
+    registry.counter(
+        "indexer_reconnects_total",
+        "Times the node connection was re-established",
+        &[],
+        read(metrics, |m| &m.reconnects),
+    );
+    registry.counter(
+        "indexer_reconnect_gap_daa_total",
+        "DAA span covered by gaps created after reconnects",
+        &[],
+        read(metrics, |m| &m.reconnect_gap_daa),
+    );
+    registry.gauge(
+        "indexer_seconds_since_last_notification",
+        "Seconds since the last block notification as of the last subscription check",
+        &[],
+        read(metrics, |m| &m.seconds_since_last_notification),
+    );
+    registry.counter(
+        "indexer_resubscriptions_total",
+        "Times a silent subscription was registered again",
+        &[],
+        read(metrics, |m| &m.resubscriptions),
+    );
+    registry.counter(
+        "indexer_duplicate_block_notifications_total",
+        "Block notifications already received from another node",
+        &[],
+        read(metrics, |m| &m.duplicate_block_notifications),
+    );
+    registry.counter(
+        "indexer_mirror_blocks_first_total",
+        "Blocks announced by a mirror node before the primary one",
+        &[],
+        read(metrics, |m| &m.mirror_blocks_first),
+    );
+    registry.counter(
+        "indexer_mirror_reconnects_total",
+        "Reconnects of mirror node connections",
+        &[],
+        read(metrics, |m| &m.mirror_reconnects),
+    );
+    registry.counter(
+        "indexer_blocks_dropped_total",
+        "Block notifications dropped while the intake was over its high-water mark",
+        &[],
+        read(metrics, |m| &m.blocks_dropped),
+    );
+    registry.counter(
+        "indexer_overflow_gaps_total",
+        "Gaps recorded for dropped block notifications",
+        &[],
+        read(metrics, |m| &m.overflow_gaps),
+    );
+    node_sample(
+        registry,
+        metrics,
+        "indexer_feed_node_connected",
+        "1 while the node feeding block notifications is connected",
+        MetricKind::Gauge,
+        |node| f64::from(u8::from(node.connected)),
+    );
+    node_sample(
+        registry,
+        metrics,
+        "indexer_feed_node_block_notifications_total",
+        "Block notifications received from a node",
+        MetricKind::Counter,
+        |node| node.block_notifications as f64,
+    );
+    node_sample(
+        registry,
+        metrics,
+        "indexer_feed_node_reconnects_total",
+        "Reconnects of a node feeding block notifications",
+        MetricKind::Counter,
+        |node| node.reconnects as f64,
+    );
+    node_sample(
+        registry,
+        metrics,
+        "indexer_feed_node_last_notification_timestamp_seconds",
+        "Unix time of the last block notification from a node",
+        MetricKind::Gauge,
+        |node| node.last_notification_unix_ms as f64 / 1000.0,
+    );
+}
+
+/// One sample per node which connected
+fn node_sample(
+    registry: &MetricsRegistry,
+    metrics: &SharedMetrics,
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+    value: fn(&NodeHealth) -> f64,
+) {
+    let metrics = metrics.clone();
+    registry.collector(name, help, kind, move |samples| {
+        for node in metrics.nodes.lock().iter() {
+            samples.push(Sample {
+                suffix: "",
+                labels: vec![("node", node.node.clone())],
+                value: value(node),
+            });
+        }
+    });
+}
+
+pub fn register_processor_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    registry.counter(
+        "indexer_blocks_processed_total",
+        "Blocks committed by the block processor",
+        &[("source", "subscriber")],
+        read(metrics, |m| &m.subscriber_blocks_processed),
+    );
+    registry.counter(
+        "indexer_blocks_processed_total",
+        "Blocks committed by the block processor",
+        &[("source", "historical")],
+        {
+            let metrics = metrics.clone();
+            move || {
+                metrics
+                    .blocks_processed
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(metrics.subscriber_blocks_processed.load(Ordering::Relaxed))
+            }
+        },
+    );
+    registry.gauge(
+        "indexer_intake_depth",
+        "Blocks waiting in the block processor intake",
+        &[("source", "subscriber")],
+        read(metrics, |m| &m.subscriber_intake_depth),
+    );
+    registry.gauge(
+        "indexer_intake_depth",
+        "Blocks waiting in the block processor intake",
+        &[("source", "historical")],
+        read(metrics, |m| &m.historical_intake_depth),
+    );
+    let subscriber_blocked_ms = read(metrics, |m| &m.subscriber_producer_blocked_ms);
+    let historical_blocked_ms = read(metrics, |m| &m.historical_producer_blocked_ms);
+    registry.collector(
+        "indexer_intake_producer_blocked_seconds_total",
+        "Total time producers waited for room in the full block processor intake",
+        MetricKind::Counter,
+        move |samples| {
+            for (source, blocked_ms) in [
+                ("subscriber", subscriber_blocked_ms()),
+                ("historical", historical_blocked_ms()),
+            ] {
+                samples.push(Sample {
+                    suffix: "",
+                    labels: vec![("source", source.to_string())],
+                    value: blocked_ms as f64 / 1000.0,
+                });
+            }
+        },
+    );
+    registry.counter(
+        "indexer_handshakes_indexed_total",
+        "Handshakes indexed",
+        &[("by", "sender")],
+        read(metrics, |m| &m.handshakes_by_sender),
+    );
+    registry.counter(
+        "indexer_handshakes_indexed_total",
+        "Handshakes indexed",
+        &[("by", "receiver")],
+        read(metrics, |m| &m.handshakes_by_receiver),
+    );
+    registry.counter(
+        "indexer_payments_indexed_total",
+        "Payments indexed",
+        &[("by", "sender")],
+        read(metrics, |m| &m.payments_by_sender),
+    );
+    registry.counter(
+        "indexer_payments_indexed_total",
+        "Payments indexed",
+        &[("by", "receiver")],
+        read(metrics, |m| &m.payments_by_receiver),
+    );
+    registry.counter(
+        "indexer_contextual_messages_indexed_total",
+        "Contextual messages indexed",
+        &[],
+        read(metrics, |m| &m.contextual_messages),
+    );
+    registry.gauge(
+        "indexer_orphan_blocks",
+        "Blocks parked until their parents are processed",
+        &[],
+        read(metrics, |m| &m.orphan_blocks),
+    );
+    registry.counter(
+        "indexer_orphans_reprocessed_total",
+        "Parked blocks processed after their parents arrived",
+        &[],
+        read(metrics, |m| &m.orphans_reprocessed),
+    );
+    registry.counter(
+        "indexer_orphans_over_capacity_total",
+        "Blocks processed without their parents because the orphan pool was full",
+        &[],
+        read(metrics, |m| &m.orphans_over_capacity),
+    );
+    registry.counter(
+        "indexer_orphan_backfills_total",
+        "Backfills requested for the missing parents of orphans",
+        &[],
+        read(metrics, |m| &m.orphan_backfills),
+    );
+    registry.gauge(
+        "indexer_pending_spends",
+        "Inputs waiting for the output they spend to be indexed",
+        &[],
+        read(metrics, |m| &m.pending_spends),
+    );
+    registry.counter(
+        "indexer_header_validation_mismatches_total",
+        "Stored headers hashing differently after a consensus crate upgrade",
+        &[],
+        read(metrics, |m| &m.header_validation_mismatches),
+    );
+    registry.counter(
+        "indexer_header_hash_mismatches_total",
+        "Received blocks rejected since their headers hash differently than reported",
+        &[],
+        read(metrics, |m| &m.header_hash_mismatches),
+    );
+    registry.counter(
+        "indexer_indexed_block_events_dropped_total",
+        "Indexed block events lagging subscribers missed",
+        &[],
+        read(metrics, |m| &m.indexed_block_events_dropped),
+    );
+    registry.counter(
+        "indexer_header_cache_lookups_total",
+        "Compact header lookups",
+        &[("result", "hit")],
+        read(metrics, |m| &m.header_cache_hits),
+    );
+    registry.counter(
+        "indexer_header_cache_lookups_total",
+        "Compact header lookups",
+        &[("result", "miss")],
+        read(metrics, |m| &m.header_cache_misses),
+    );
+    registry.counter(
+        "indexer_tx_filter_lookups_total",
+        "Transaction lookups checked against the filter of indexed transaction ids",
+        &[("result", "negative")],
+        read(metrics, |m| &m.tx_filter_negatives),
+    );
+    registry.counter(
+        "indexer_tx_filter_lookups_total",
+        "Transaction lookups checked against the filter of indexed transaction ids",
+        &[("result", "positive")],
+        read(metrics, |m| &m.tx_filter_positives),
+    );
+    registry.counter(
+        "indexer_tx_filter_false_positives_total",
+        "Transaction lookups the filter passed which the store did not have",
+        &[],
+        read(metrics, |m| &m.tx_filter_false_positives),
+    );
+    registry.gauge(
+        "indexer_tx_filter_false_positive_estimate_ppm",
+        "Estimated false positive rate of the transaction filter per million",
+        &[],
+        read(metrics, |m| &m.tx_filter_fp_estimate_ppm),
+    );
+    registry.counter(
+        "indexer_webhook_deliveries_total",
+        "Webhook deliveries the receivers took",
+        &[],
+        read(metrics, |m| &m.webhook_deliveries),
+    );
+    registry.counter(
+        "indexer_webhook_delivery_failures_total",
+        "Failed webhook delivery attempts",
+        &[],
+        read(metrics, |m| &m.webhook_delivery_failures),
+    );
+    registry.counter(
+        "indexer_webhook_dead_letters_total",
+        "Webhook deliveries given up on after their last attempt",
+        &[],
+        read(metrics, |m| &m.webhook_dead_letters),
+    );
+    registry.gauge(
+        "indexer_mempool_transactions",
+        "Pending transactions tracked from the node mempool",
+        &[],
+        read(metrics, |m| &m.mempool_transactions),
+    );
+    registry.counter(
+        "indexer_mempool_evictions_total",
+        "Tracked mempool transactions dropped without being indexed within the TTL",
+        &[],
+        read(metrics, |m| &m.mempool_evictions),
+    );
+    registry.gauge(
+        "indexer_supply_divergence_sompi",
+        "Difference between the indexed circulating supply and the node's at the last check",
+        &[],
+        read(metrics, |m| &m.supply_divergence),
+    );
+    registry.counter(
+        "indexer_supply_divergence_alarms_total",
+        "Supply checks whose divergence from the node exceeded the tolerance",
+        &[],
+        read(metrics, |m| &m.supply_divergence_alarms),
+    );
+    for (reason, rejections) in [
+        ("rate_limited", read(metrics, |m| &m.api_rate_limited)),
+        ("overloaded", read(metrics, |m| &m.api_overloaded)),
+        ("limit", read(metrics, |m| &m.api_limit_rejections)),
+        ("daa_range", read(metrics, |m| &m.api_daa_range_rejections)),
+    ] {
+        registry.counter(
+            "indexer_api_rejections_total",
+            "Query API requests refused by the rate limit, the concurrency cap or the size caps",
+            &[("reason", reason)],
+            rejections,
+        );
+    }
+    registry.counter(
+        "indexer_api_entries_read_total",
+        "Entries the query API listings read from the store",
+        &[],
+        read(metrics, |m| &m.api_entries_read),
+    );
+    registry.histogram(
+        "indexer_block_e2e_latency_seconds",
+        "Time from receiving a block notification until the block is committed",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.block_e2e_latency.snapshot()
+        },
+    );
+    registry.histogram(
+        "indexer_block_processing_seconds",
+        "Time the block processor spends writing a block",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.block_processing_time.snapshot()
+        },
+    );
+    registry.histogram(
+        "indexer_block_decode_seconds",
+        "Time the block processor spends decoding a block",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.block_decode_time.snapshot()
+        },
+    );
+    registry.histogram(
+        "indexer_header_hash_verification_seconds",
+        "Time the block processor spends recomputing the hash of a received header",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.header_hash_verification_time.snapshot()
+        },
+    );
+    registry.histogram(
+        "indexer_block_commit_seconds",
+        "Time the block processor spends committing a block, batches spread over their blocks",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.block_commit_time.snapshot()
+        },
+    );
+    registry.counter(
+        "indexer_unindexed_accepted_txs_total",
+        "Accepted transactions the block processor never indexed",
+        &[],
+        read(metrics, |m| &m.unindexed_accepted_txs),
+    );
+    registry.counter(
+        "indexer_auto_created_gaps_total",
+        "Backfills requested because accepted transactions were never indexed",
+        &[],
+        read(metrics, |m| &m.auto_created_gaps),
+    );
+    registry.counter(
+        "indexer_reorg_entries_removed_total",
+        "Entries removed while reverting reorged chain blocks",
+        &[],
+        read(metrics, |m| &m.reorg_entries_removed),
+    );
+    registry.counter(
+        "indexer_deep_reorgs_total",
+        "Reorgs removing more chain blocks than the deep reorg threshold",
+        &[],
+        read(metrics, |m| &m.deep_reorgs),
+    );
+    registry.counter(
+        "indexer_finality_violations_total",
+        "Finalized chain blocks a reorg tried to remove",
+        &[],
+        read(metrics, |m| &m.finality_violations),
+    );
+}
+
+pub fn register_syncer_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    registry.counter(
+        "indexer_gap_sync_failures_total",
+        "Syncer runs which ended without filling their gap",
+        &[],
+        read(metrics, |m| &m.gap_sync_failures),
+    );
+    registry.gauge(
+        "indexer_gaps_backing_off",
+        "Failed gaps waiting for their retry",
+        &[],
+        read(metrics, |m| &m.gaps_backing_off),
+    );
+    registry.counter(
+        "indexer_chain_sync_blocks_total",
+        "Chain blocks the selected chain syncer forwarded to the virtual chain processor",
+        &[],
+        read(metrics, |m| &m.chain_sync_blocks),
+    );
+    registry.counter(
+        "indexer_chain_sync_acceptance_records_total",
+        "Accepted transaction ids within the forwarded chain blocks",
+        &[],
+        read(metrics, |m| &m.chain_sync_acceptance_records),
+    );
+    registry.gauge(
+        "indexer_chain_sync_remaining_daa",
+        "DAA distance between the selected chain syncer position and its target",
+        &[],
+        read(metrics, |m| &m.chain_sync_remaining_daa),
+    );
+    registry.gauge(
+        "indexer_block_lag_daa",
+        "DAA score the block tip is behind the node",
+        &[],
+        read(metrics, |m| &m.block_lag_daa),
+    );
+    registry.gauge(
+        "indexer_acceptance_lag_daa",
+        "DAA score the virtual chain processor tip is behind the node",
+        &[],
+        read(metrics, |m| &m.acceptance_lag_daa),
+    );
+    registry.gauge(
+        "indexer_lag_seconds",
+        "Estimated seconds the index is behind the node at the target block rate",
+        &[],
+        read(metrics, |m| &m.lag_seconds),
+    );
+}
+
+pub fn register_resolver_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    registry.gauge(
+        "indexer_unresolved_entries",
+        "Entries waiting for resolution",
+        &[("kind", "daa")],
+        read(metrics, |m| &m.unknown_daa_entries),
+    );
+    registry.gauge(
+        "indexer_unresolved_entries",
+        "Entries waiting for resolution",
+        &[("kind", "sender")],
+        read(metrics, |m| &m.unknown_sender_entries),
+    );
+    registry.gauge(
+        "indexer_unresolved_entries",
+        "Entries waiting for resolution",
+        &[("kind", "tx")],
+        read(metrics, |m| &m.unknown_tx_entries),
+    );
+    registry.counter(
+        "indexer_resolved_total",
+        "Entries resolved",
+        &[("kind", "daa")],
+        read(metrics, |m| &m.resolved_daa),
+    );
+    registry.counter(
+        "indexer_resolved_total",
+        "Entries resolved",
+        &[("kind", "sender")],
+        read(metrics, |m| &m.resolved_sender),
+    );
+    registry.gauge(
+        "indexer_resolver_nodes",
+        "Resolver nodes configured",
+        &[],
+        read(metrics, |m| &m.resolver_nodes),
+    );
+    registry.gauge(
+        "indexer_resolver_usable_nodes",
+        "Resolver nodes synced on the expected network as of the last health check",
+        &[],
+        read(metrics, |m| &m.resolver_usable_nodes),
+    );
+    registry.counter(
+        "indexer_resolver_failovers_total",
+        "Resolver requests retried on another node after a failure",
+        &[],
+        read(metrics, |m| &m.resolver_failovers),
+    );
+    registry.counter(
+        "indexer_component_restarts_total",
+        "Processor restarts after an error or a panic",
+        &[],
+        read(metrics, |m| &m.component_restarts),
+    );
+    registry.gauge(
+        "indexer_rpc_permits_in_use",
+        "Node call permits currently held",
+        &[],
+        read(metrics, |m| &m.rpc_permits_in_use),
+    );
+    let wait_ms = read(metrics, |m| &m.rpc_permit_wait_ms);
+    registry.collector(
+        "indexer_rpc_permit_wait_seconds_total",
+        "Total time calls waited for a permit of their node",
+        MetricKind::Counter,
+        move |samples| {
+            samples.push(Sample {
+                suffix: "",
+                labels: Vec::new(),
+                value: wait_ms() as f64 / 1000.0,
+            })
+        },
+    );
+    registry.gauge(
+        "indexer_rpc_open_breakers",
+        "Nodes whose circuit breaker is not closed",
+        &[],
+        read(metrics, |m| &m.rpc_open_breakers),
+    );
+}
+
+pub fn register_database_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    registry.gauge(
+        "indexer_database_disk_bytes",
+        "Total bytes on disk, journals included",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.database.load().disk_bytes
+        },
+    );
+    registry.gauge(
+        "indexer_database_write_buffer_bytes",
+        "Bytes of all memtables not yet flushed to disk",
+        &[],
+        {
+            let metrics = metrics.clone();
+            move || metrics.database.load().write_buffer_bytes
+        },
+    );
+    registry.counter(
+        "indexer_compactions_total",
+        "Major compactions of single partitions",
+        &[],
+        read(metrics, |m| &m.compactions),
+    );
+    registry.counter(
+        "indexer_compactions_deferred_total",
+        "Scheduled compactions skipped while the indexer was behind the node",
+        &[],
+        read(metrics, |m| &m.compactions_deferred),
+    );
+    registry.counter(
+        "indexer_compaction_reclaimed_bytes_total",
+        "Disk bytes reclaimed by compactions",
+        &[],
+        read(metrics, |m| &m.compaction_reclaimed_bytes),
+    );
+    let started_ms = read(metrics, |m| &m.last_compaction_unix_ms);
+    registry.collector(
+        "indexer_last_compaction_timestamp_seconds",
+        "Unix time the last compaction started",
+        MetricKind::Gauge,
+        move |samples| {
+            samples.push(Sample {
+                suffix: "",
+                labels: Vec::new(),
+                value: started_ms() as f64 / 1000.0,
+            })
+        },
+    );
+    let duration_ms = read(metrics, |m| &m.last_compaction_duration_ms);
+    registry.collector(
+        "indexer_last_compaction_duration_seconds",
+        "Duration of the last compaction",
+        MetricKind::Gauge,
+        move |samples| {
+            samples.push(Sample {
+                suffix: "",
+                labels: Vec::new(),
+                value: duration_ms() as f64 / 1000.0,
+            })
+        },
+    );
+    partition_gauge(
+        registry,
+        metrics,
+        "indexer_partition_approximate_keys",
+        "Estimated keys of a partition, tombstones and overwritten keys included",
+        |partition| partition.approximate_keys,
+    );
+    partition_gauge(
+        registry,
+        metrics,
+        "indexer_partition_disk_bytes",
+        "Bytes occupied by flushed segments and blobs of a partition",
+        |partition| partition.disk_bytes,
+    );
+    partition_gauge(
+        registry,
+        metrics,
+        "indexer_partition_segments",
+        "Segments of a partition",
+        |partition| partition.segments,
+    );
+}
+
+/// One sample per partition of the last database stats
+fn partition_gauge(
+    registry: &MetricsRegistry,
+    metrics: &SharedMetrics,
+    name: &'static str,
+    help: &'static str,
+    value: fn(&PartitionStats) -> u64,
+) {
+    let metrics = metrics.clone();
+    registry.collector(name, help, MetricKind::Gauge, move |samples| {
+        for partition in &metrics.database.load().partitions {
+            samples.push(Sample {
+                suffix: "",
+                labels: vec![("partition", partition.name.clone())],
+                value: value(partition) as f64,
+            });
+        }
+    });
+}
+
+pub fn register_task_metrics(registry: &MetricsRegistry, metrics: &SharedMetrics) {
+    task_sample(
+        registry,
+        metrics,
+        "indexer_task_runs_total",
+        "Finished runs of a periodic task, failed ones included",
+        MetricKind::Counter,
+        |task| task.runs as f64,
+    );
+    task_sample(
+        registry,
+        metrics,
+        "indexer_task_failures_total",
+        "Runs of a periodic task which returned an error",
+        MetricKind::Counter,
+        |task| task.failures as f64,
+    );
+    task_sample(
+        registry,
+        metrics,
+        "indexer_task_skipped_total",
+        "Runs of a periodic task skipped because the previous one was still in progress",
+        MetricKind::Counter,
+        |task| task.skipped as f64,
+    );
+    task_sample(
+        registry,
+        metrics,
+        "indexer_task_timeouts_total",
+        "Runs of a periodic task asked to stop after their timeout",
+        MetricKind::Counter,
+        |task| task.timeouts as f64,
+    );
+    task_sample(
+        registry,
+        metrics,
+        "indexer_task_last_duration_seconds",
+        "Duration of the last run of a periodic task",
+        MetricKind::Gauge,
+        |task| task.last_duration.as_secs_f64(),
+    );
+    task_sample(
+        registry,
+        metrics,
+        "indexer_task_last_success",
+        "1 if the last run of a periodic task succeeded",
+        MetricKind::Gauge,
+        |task| f64::from(u8::from(task.last_ok)),
+    );
+}
+
+/// One sample per periodic task which ran or was skipped
+fn task_sample(
+    registry: &MetricsRegistry,
+    metrics: &SharedMetrics,
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+    value: fn(&TaskStats) -> f64,
+) {
+    let metrics = metrics.clone();
+    registry.collector(name, help, kind, move |samples| {
+        for task in metrics.periodic_tasks.lock().iter() {
+            samples.push(Sample {
+                suffix: "",
+                labels: vec![("task", task.name.clone())],
+                value: value(task),
+            });
+        }
+    });
+}
+
+/// Healthy while the node is connected and a block from a notification was committed within
+/// the staleness window
+#[derive(Clone)]
+pub struct HealthCheck {
+    metrics: SharedMetrics,
+    staleness_window: Duration,
+}
+
+impl HealthCheck {
+    pub fn new(metrics: SharedMetrics, staleness_window: Duration) -> Self {
+        Self {
+            metrics,
+            staleness_window,
+        }
+    }
+
+    /// The reason when unhealthy
+    pub fn check(&self) -> Result<(), String> {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.check_at(now_ms)
+    }
+
+    fn check_at(&self, now_ms: u64) -> Result<(), String> {
+        if self.metrics.node_connected.load(Ordering::Relaxed) == 0 {
+            return Err("node disconnected".to_string());
+        }
+        let last = self
+            .metrics
+            .last_subscriber_block_unix_ms
+            .load(Ordering::Relaxed);
+        if last == 0 {
+            return Err("no block processed yet".to_string());
+        }
+        let since = Duration::from_millis(now_ms.saturating_sub(last));
+        if since > self.staleness_window {
+            return Err(format!("no block processed for {}s", since.as_secs()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Endpoints {
+    registry: MetricsRegistry,
+    health: HealthCheck,
+    status: Option<Indexer>,
+}
+
+/// Answers scrapes until shutdown, then stops accepting connections
+pub async fn serve(
+    listener: TcpListener,
+    registry: MetricsRegistry,
+    health: HealthCheck,
+    status: Option<Indexer>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    info!("Metrics listening on {}", listener.local_addr()?);
+    let router = Router::new()
+        .route("/metrics", get(render_metrics))
+        .route("/healthz", get(check_health))
+        .route("/status", get(report_status))
+        .fallback(|| async { (StatusCode::NOT_FOUND, "not found\n") })
+        .with_state(Endpoints {
+            registry,
+            health,
+            status,
+        });
+    serve_router(listener, router, shutdown).await;
+    info!("Metrics listener stopped");
+    Ok(())
+}
+
+async fn render_metrics(State(endpoints): State<Endpoints>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        endpoints.registry.render(),
+    )
+}
+
+async fn check_health(State(endpoints): State<Endpoints>) -> impl IntoResponse {
+    match endpoints.health.check() {
+        Ok(()) => (StatusCode::OK, "ok\n".to_string()),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, format!("{reason}\n")),
+    }
+}
+
+async fn report_status(State(endpoints): State<Endpoints>) -> Response {
+    let Some(indexer) = endpoints.status else {
+        return (StatusCode::NOT_FOUND, "not found\n").into_response();
+    };
+    let status = tokio::task::spawn_blocking(move || indexer.status())
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|status| Ok(serde_json::to_string_pretty(&status?)? + "\n"));
+    match status {
+        Ok(body) => ([(CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}\n")).into_response(),
+    }
+}
+
+/// Serves `router` over HTTP/1.1 until shutdown, then stops accepting connections and closes
+/// the open ones once their request is answered. A connection is closed when a request head
+/// exceeds [`MAX_REQUEST_BYTES`] or is not received within [`REQUEST_TIMEOUT`]. Handlers see
+/// the client address as [`ConnectInfo`]
+pub(crate) async fn serve_router(listener: TcpListener, router: Router, shutdown: Shutdown) {
+    let connections = CancellationToken::new();
+    let _connections = connections.clone().drop_guard();
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!("Failed to accept HTTP connection: {err}");
+                        continue;
+                    }
+                };
+                let service =
+                    TowerToHyperService::new(router.clone().layer(Extension(ConnectInfo(peer))));
+                let connections = connections.clone();
+                tokio::spawn(async move {
+                    let connection = http1::Builder::new()
+                        .timer(TokioTimer::new())
+                        .header_read_timeout(REQUEST_TIMEOUT)
+                        .max_buf_size(MAX_REQUEST_BYTES)
+                        .serve_connection(TokioIo::new(stream), service)
+                        .with_upgrades();
+                    let mut connection = std::pin::pin!(connection);
+                    let result = tokio::select! {
+                        result = connection.as_mut() => result,
+                        _ = connections.cancelled() => {
+                            connection.as_mut().graceful_shutdown();
+                            connection.as_mut().await
+                        }
+                    };
+                    if let Err(err) = result {
+                        debug!(%peer, "HTTP connection failed: {err}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Body of a GET of `path` from a running indexer listening on `addr`, e.g. its status
+pub async fn fetch(addr: &str, path: &str) -> anyhow::Result<String> {
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let stream = TcpStream::connect(addr).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+        let request = Request::get(path)
+            .header(HOST, addr)
+            .body(Empty::<Bytes>::new())?;
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+        anyhow::ensure!(status == StatusCode::OK, "{status}: {}", body.trim_end());
+        Ok(body)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::create_shared_metrics;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let metrics = create_shared_metrics();
+        let registry = MetricsRegistry::new();
+        register_processor_metrics(&registry, &metrics);
+        metrics.increment_blocks_processed();
+        metrics.increment_blocks_processed();
+        metrics.record_subscriber_block_processed();
+        metrics.observe_block_processing_time(Duration::from_micros(800));
+
+        let text = registry.render();
+        assert!(text.contains("# TYPE indexer_blocks_processed_total counter\n"));
+        assert_eq!(
+            text.matches("# HELP indexer_blocks_processed_total ")
+                .count(),
+            1
+        );
+        assert!(text.contains("indexer_blocks_processed_total{source=\"subscriber\"} 1\n"));
+        assert!(text.contains("indexer_blocks_processed_total{source=\"historical\"} 1\n"));
+        assert!(text.contains("indexer_block_processing_seconds_bucket{le=\"0.0005\"} 0\n"));
+        assert!(text.contains("indexer_block_processing_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(text.contains("indexer_block_processing_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("indexer_block_processing_seconds_count 1\n"));
+
+        registry.gauge("escaped", "", &[("label", "a\"b\\c")], || 0);
+        assert!(
+            registry
+                .render()
+                .contains("escaped{label=\"a\\\"b\\\\c\"} 0\n")
+        );
+    }
+
+    #[test]
+    fn test_health_check() {
+        let metrics = create_shared_metrics();
+        let health = HealthCheck::new(metrics.clone(), Duration::from_secs(60));
+        assert!(health.check_at(1_000_000).is_err());
+
+        metrics.set_node_connected(true);
+        assert!(health.check_at(1_000_000).is_err());
+
+        metrics
+            .last_subscriber_block_unix_ms
+            .store(1_000_000, Ordering::Relaxed);
+        assert!(health.check_at(1_030_000).is_ok());
+        assert!(health.check_at(1_061_000).is_err());
+
+        metrics.set_node_connected(false);
+        assert!(health.check_at(1_030_000).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_until_shutdown() {
+        let metrics = create_shared_metrics();
+        let registry = MetricsRegistry::new();
+        register_indexer_metrics(&registry, &metrics);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(
+            listener,
+            registry,
+            HealthCheck::new(metrics, Duration::from_secs(60)),
+            None,
+            shutdown.clone(),
+        ));
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(
+                    format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("indexer_node_connected 0\n"));
+        assert!(get("/healthz").await.starts_with("HTTP/1.1 503"));
+        assert!(get("/other").await.starts_with("HTTP/1.1 404"));
+        // no indexer to report on
+        assert!(get("/status").await.starts_with("HTTP/1.1 404"));
+        let listening = addr.to_string();
+        assert!(
+            fetch(&listening, "/metrics")
+                .await
+                .unwrap()
+                .contains("indexer_node_connected 0\n")
+        );
+        assert!(fetch(&listening, "/status").await.is_err());
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_head_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(
+            listener,
+            MetricsRegistry::new(),
+            HealthCheck::new(create_shared_metrics(), Duration::from_secs(60)),
+            None,
+            shutdown.clone(),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let padding = "x".repeat(MAX_REQUEST_BYTES * 2);
+        // the server may close before everything is written
+        _ = stream
+            .write_all(format!("GET /metrics HTTP/1.1\r\nX-Padding: {padding}\r\n\r\n").as_bytes())
+            .await;
+        let mut response = String::new();
+        _ = stream.read_to_string(&mut response).await;
+        assert!(!response.starts_with("HTTP/1.1 200"), "{response}");
+
+        // keeps answering other connections
+        assert!(fetch(&addr.to_string(), "/metrics").await.is_ok());
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! Block notifications from additional nodes.
+//!
+//! The subscriber takes everything from its primary node. Mirror nodes only subscribe to added
+//! blocks, so a block the primary misses while it hiccups still reaches the block processor.
+//! The subscriber deduplicates the merged stream by hash. Every node's health is tracked in
+//! the metrics, the gap syncers run against the healthiest one.
+
+use crate::metrics::SharedMetrics;
+use crate::shutdown::Shutdown;
+use futures_util::future::FutureExt;
+use kaspa_rpc_core::api::ctl::RpcState;
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_rpc_core::notify::connection::{ChannelConnection, ChannelType};
+use kaspa_rpc_core::{BlockAddedNotification, Notification, RpcBlock};
+use kaspa_wrpc_client::KaspaRpcClient;
+use kaspa_wrpc_client::client::{ConnectOptions, ConnectStrategy};
+use kaspa_wrpc_client::prelude::{BlockAddedScope, ListenerId, Scope};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use workflow_core::channel::Channel;
+
+/// Notifications lagging the freshest node by less than this still count as current
+const HEALTHY_LAG_MS: u64 = 5_000;
+
+/// Health of a node delivering block notifications
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NodeHealth {
+    pub node: String,
+    pub connected: bool,
+    pub block_notifications: u64,
+    pub reconnects: u64,
+    /// Unix time in milliseconds of the last block notification, 0 before the first one
+    pub last_notification_unix_ms: u64,
+}
+
+impl fmt::Display for NodeHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Node {}: {}, {} block notifications, {} reconnects, last at {}ms",
+            self.node,
+            if self.connected {
+                "connected"
+            } else {
+                "disconnected"
+            },
+            self.block_notifications,
+            self.reconnects,
+            self.last_notification_unix_ms
+        )
+    }
+}
+
+/// Index of the healthiest of `candidates`, listed by preference. A node has to be connected
+/// with current notifications, the one with the fewest reconnects wins. None when no
+/// candidate qualifies
+pub fn healthiest_node(health: &[NodeHealth], candidates: &[&str]) -> Option<usize> {
+    let connected = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| {
+            health
+                .iter()
+                .find(|health| health.node == *candidate && health.connected)
+                .map(|health| (index, health))
+        })
+        .collect::<Vec<_>>();
+    let freshest = connected
+        .iter()
+        .map(|(_, health)| health.last_notification_unix_ms)
+        .max()?;
+    connected
+        .into_iter()
+        .filter(|(_, health)| health.last_notification_unix_ms + HEALTHY_LAG_MS >= freshest)
+        .min_by_key(|(_, health)| health.reconnects)
+        .map(|(index, _)| index)
+}
+
+/// Block added notification received from a mirror node
+pub struct MirrorBlock {
+    /// Url of the node the notification came from
+    pub node: Arc<str>,
+    pub block: Arc<RpcBlock>,
+}
+
+pub struct MirrorFeed {
+    rpc_client: KaspaRpcClient,
+    node: Arc<str>,
+    blocks: tokio::sync::mpsc::Sender<MirrorBlock>,
+    shutdown: Shutdown,
+    notification_channel: Channel<Notification>,
+    listener_id: Option<ListenerId>,
+    had_first_connect: bool,
+    metrics: SharedMetrics,
+}
+
+impl MirrorFeed {
+    pub fn new(
+        rpc_client: KaspaRpcClient,
+        blocks: tokio::sync::mpsc::Sender<MirrorBlock>,
+        shutdown: Shutdown,
+        metrics: SharedMetrics,
+    ) -> Self {
+        let node = Arc::from(rpc_client.url().unwrap_or_default());
+        Self {
+            rpc_client,
+            node,
+            blocks,
+            shutdown,
+            notification_channel: Channel::bounded(256),
+            listener_id: None,
+            had_first_connect: false,
+            metrics,
+        }
+    }
+
+    /// Connects to the node and forwards its added blocks until shutdown, then unsubscribes
+    /// and disconnects
+    pub async fn task(mut self) -> anyhow::Result<()> {
+        let rpc_ctl_channel = self.rpc_client.rpc_ctl().multiplexer().channel();
+        self.rpc_client
+            .connect(Some(ConnectOptions {
+                block_async_connect: false,
+                connect_timeout: Some(Duration::from_millis(10_000)),
+                strategy: ConnectStrategy::Retry,
+                ..Default::default()
+            }))
+            .await?;
+        loop {
+            tokio::select! {
+            biased;
+                _ = self.shutdown.cancelled() => {
+                    if let Err(err) = self.unregister_listener().await {
+                        warn!(node = %self.node, "Failed to unsubscribe mirror node: {err}");
+                    }
+                    self.rpc_client.disconnect().await?;
+                    self.metrics.record_node_disconnected(&self.node);
+                    info!(node = %self.node, "Mirror node disconnected");
+                    return Ok(());
+                }
+                msg = rpc_ctl_channel.receiver.recv().fuse() => {
+                    match msg? {
+                        RpcState::Connected => {
+                            if let Err(err) = self.handle_connect().await {
+                                error!(node = %self.node, "Error in mirror connect handler: {err}");
+                            }
+                        }
+                        RpcState::Disconnected => {
+                            info!(node = %self.node, "Mirror node disconnected, reconnecting");
+                            self.metrics.record_node_disconnected(&self.node);
+                            if let Err(err) = self.unregister_listener().await {
+                                warn!(node = %self.node, "Failed to unregister mirror listener: {err}");
+                            }
+                        }
+                    }
+                }
+                notification = self.notification_channel.receiver.recv().fuse() => {
+                    match notification? {
+                        Notification::BlockAdded(BlockAddedNotification { block }) => {
+                            self.metrics.record_node_notification(&self.node);
+                            self.blocks
+                                .send(MirrorBlock { node: self.node.clone(), block })
+                                .await?;
+                        }
+                        notification => warn!("unknown notification: {:?}", notification),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_connect(&mut self) -> anyhow::Result<()> {
+        info!(node = %self.node, "Connected to mirror node");
+        if self.had_first_connect {
+            self.metrics.increment_mirror_reconnects();
+        }
+        self.metrics
+            .record_node_connected(&self.node, self.had_first_connect);
+        self.had_first_connect = true;
+        let listener_id = self
+            .rpc_client
+            .register_new_listener(ChannelConnection::new(
+                "kasia-mirror",
+                self.notification_channel.sender.clone(),
+                ChannelType::Persistent,
+            ));
+        self.listener_id = Some(listener_id);
+        self.rpc_client
+            .start_notify(listener_id, Scope::BlockAdded(BlockAddedScope {}))
+            .await?;
+        Ok(())
+    }
+
+    async fn unregister_listener(&mut self) -> anyhow::Result<()> {
+        if let Some(listener_id) = self.listener_id.take() {
+            self.rpc_client.unregister_listener(listener_id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(node: &str, connected: bool, reconnects: u64, last: u64) -> NodeHealth {
+        NodeHealth {
+            node: node.to_string(),
+            connected,
+            block_notifications: 1,
+            reconnects,
+            last_notification_unix_ms: last,
+        }
+    }
+
+    #[test]
+    fn test_healthiest_node() {
+        let candidates = ["primary", "mirror-a", "mirror-b"];
+        assert_eq!(healthiest_node(&[], &candidates), None);
+
+        // current nodes tie on reconnects, the preferred one wins
+        let nodes = [
+            health("mirror-a", true, 0, 100_000),
+            health("primary", true, 0, 99_000),
+        ];
+        assert_eq!(healthiest_node(&nodes, &candidates), Some(0));
+
+        // a disconnected or lagging node is skipped
+        let nodes = [
+            health("mirror-a", true, 3, 100_000),
+            health("mirror-b", true, 0, 90_000),
+            health("primary", false, 0, 100_000),
+        ];
+        assert_eq!(healthiest_node(&nodes, &candidates), Some(1));
+
+        // among current nodes the one with the fewest reconnects wins
+        let nodes = [
+            health("mirror-a", true, 1, 100_000),
+            health("mirror-b", true, 0, 98_000),
+            health("primary", true, 2, 100_000),
+        ];
+        assert_eq!(healthiest_node(&nodes, &candidates), Some(2));
+    }
+}
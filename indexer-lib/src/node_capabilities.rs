@@ -0,0 +1,364 @@
+use crate::database::metadata::NodeRequirements;
+use crate::rpc_transport::RpcNode;
+use arc_swap::ArcSwap;
+use std::fmt;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// First node release serving `GetUtxoReturnAddress`, required for sender resolution
+pub const MIN_UTXO_RETURN_ADDRESS_VERSION: NodeVersion = NodeVersion::new(1, 0, 1);
+/// Oldest node release the indexer can be served by
+pub const MIN_NODE_VERSION: NodeVersion = NodeVersion::new(1, 0, 0);
+
+pub type SharedNodeCapabilities = Arc<ArcSwap<NodeCapabilities>>;
+
+/// Optional node functionality the indexer can make use of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Resolving the sender of accepted transactions via `GetUtxoReturnAddress`
+    SenderResolution,
+    /// Queries served by the node utxo index
+    UtxoIndex,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 2] = [Feature::SenderResolution, Feature::UtxoIndex];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NodeVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl NodeVersion {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses versions like `1.0.1`, `v1.0.1` or `1.0.1-dev`, missing components are zero
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches('v');
+        let s = s.split(['-', '+', ' ']).next()?;
+        let mut parts = s.split('.').map(str::parse::<u16>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().transpose().ok()?.unwrap_or_default();
+        let patch = parts.next().transpose().ok()?.unwrap_or_default();
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for NodeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The connected node can't serve the database, the indexer must not go on with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeIncompatible(pub String);
+
+impl fmt::Display for NodeIncompatible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Incompatible node: {}", self.0)
+    }
+}
+
+impl std::error::Error for NodeIncompatible {}
+
+/// What the connected node is able to serve.
+///
+/// Probed on every connect, features consult it instead of assuming availability.
+/// Before the first probe every feature is considered available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeCapabilities {
+    pub probed: bool,
+    /// Version string as reported by the node
+    pub server_version: String,
+    pub version: Option<NodeVersion>,
+    pub rpc_api_version: u16,
+    pub network_id: Option<String>,
+    pub has_utxo_index: bool,
+    pub is_synced: bool,
+}
+
+impl Default for NodeCapabilities {
+    fn default() -> Self {
+        Self {
+            probed: false,
+            server_version: String::new(),
+            version: None,
+            rpc_api_version: 0,
+            network_id: None,
+            has_utxo_index: true,
+            is_synced: true,
+        }
+    }
+}
+
+impl NodeCapabilities {
+    pub fn new(
+        server_version: &str,
+        rpc_api_version: u16,
+        network_id: Option<String>,
+        has_utxo_index: bool,
+        is_synced: bool,
+    ) -> Self {
+        Self {
+            probed: true,
+            server_version: server_version.to_string(),
+            version: NodeVersion::parse(server_version),
+            rpc_api_version,
+            network_id,
+            has_utxo_index,
+            is_synced,
+        }
+    }
+
+    /// Probes every RPC on its own. A failed probe leaves what it would have reported unknown,
+    /// the features depending on it disabled, instead of failing the connect
+    pub async fn probe(node: &RpcNode) -> Self {
+        let mut capabilities = match node.get_server_info().await {
+            Ok(info) => Self::new(
+                &info.server_version,
+                info.rpc_api_version,
+                Some(info.network_id.to_string()),
+                info.has_utxo_index,
+                info.is_synced,
+            ),
+            Err(err) => {
+                warn!("Node server info probe failed, its version is unknown: {err}");
+                Self::new("", 0, None, false, false)
+            }
+        };
+        // nodes not serving their server info still report their network
+        if capabilities.network_id.is_none() {
+            match node.get_block_dag_info().await {
+                Ok(info) => capabilities.network_id = Some(info.network.to_string()),
+                Err(err) => warn!("Node DAG info probe failed, its network is unknown: {err}"),
+            }
+        }
+        info!(
+            server_version = %capabilities.server_version,
+            network = capabilities.network_id.as_deref().unwrap_or("unknown"),
+            has_utxo_index = capabilities.has_utxo_index,
+            is_synced = capabilities.is_synced,
+            "Node capabilities probed"
+        );
+        for feature in Feature::ALL {
+            if let Some(note) = capabilities.unavailable_reason(feature) {
+                warn!(?feature, "Feature disabled: {note}");
+            }
+        }
+        capabilities
+    }
+
+    /// Requirements to record for a database built from this node
+    pub fn requirements(&self) -> Result<NodeRequirements, NodeIncompatible> {
+        let network_id = self
+            .network_id
+            .clone()
+            .ok_or_else(|| NodeIncompatible("node did not report its network".to_string()))?;
+        Ok(NodeRequirements {
+            network_id,
+            min_version: MIN_NODE_VERSION,
+        })
+    }
+
+    /// Checks the node against the requirements recorded for the database and the indexer's own.
+    /// Nodes with unparsable versions pass the version check
+    pub fn check_requirements(&self, required: &NodeRequirements) -> Result<(), NodeIncompatible> {
+        if self.network_id.as_deref() != Some(required.network_id.as_str()) {
+            return Err(NodeIncompatible(format!(
+                "node is on network {}, the database was built from {}",
+                self.network_id.as_deref().unwrap_or("unknown"),
+                required.network_id
+            )));
+        }
+        let min_version = required.min_version.max(MIN_NODE_VERSION);
+        if let Some(version) = self.version
+            && version < min_version
+        {
+            return Err(NodeIncompatible(format!(
+                "node version {version} is older than the required {min_version}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.unavailable_reason(feature).is_none()
+    }
+
+    /// Status note explaining why `feature` is disabled, `None` when it is available
+    pub fn unavailable_reason(&self, feature: Feature) -> Option<&'static str> {
+        if !self.probed {
+            return None;
+        }
+        if self.server_version.is_empty() {
+            return Some("node did not report its server info");
+        }
+        match feature {
+            Feature::SenderResolution => match self.version {
+                Some(version) if version >= MIN_UTXO_RETURN_ADDRESS_VERSION => None,
+                Some(_) => Some("node version does not serve GetUtxoReturnAddress"),
+                None => Some("unknown node version"),
+            },
+            Feature::UtxoIndex => (!self.has_utxo_index).then_some("node runs without --utxoindex"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, FixtureCall, FixtureEntry, FixtureResponse, FixtureRpcClient};
+    use crate::rpc_transport::TransportError;
+    use kaspa_consensus_core::network::{NetworkId, NetworkType};
+    use kaspa_rpc_core::{GetBlockDagInfoResponse, GetServerInfoResponse, RpcHash};
+
+    /// Node answering the server info with `server_info`, and the DAG info once
+    fn node(server_info: Result<(&str, bool), TransportError>) -> RpcNode {
+        let network_id = NetworkId::with_suffix(NetworkType::Testnet, 10);
+        let server_info = server_info.map(|(server_version, has_utxo_index)| {
+            FixtureResponse::ServerInfo(GetServerInfoResponse {
+                rpc_api_version: 1,
+                rpc_api_revision: 0,
+                server_version: server_version.to_string(),
+                network_id,
+                has_utxo_index,
+                is_synced: true,
+                virtual_daa_score: 1_000,
+            })
+        });
+        let sink = RpcHash::from_u64_word(1);
+        let dag_info = GetBlockDagInfoResponse::new(
+            network_id,
+            0,
+            0,
+            vec![sink],
+            1.0,
+            0,
+            vec![sink],
+            RpcHash::from_u64_word(0),
+            1_000,
+            sink,
+        );
+        RpcNode::from(FixtureRpcClient::from(Fixture {
+            entries: vec![
+                FixtureEntry::Call {
+                    call: FixtureCall::GetServerInfo,
+                    result: server_info,
+                },
+                FixtureEntry::Call {
+                    call: FixtureCall::GetBlockDagInfo,
+                    result: Ok(FixtureResponse::BlockDagInfo(dag_info)),
+                },
+            ],
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_probe_mock_nodes() {
+        let full = NodeCapabilities::probe(&node(Ok(("1.0.1", true)))).await;
+        assert_eq!(full.network_id.as_deref(), Some("testnet-10"));
+        assert!(Feature::ALL.iter().all(|f| full.supports(*f)));
+
+        let without_index = NodeCapabilities::probe(&node(Ok(("1.0.1", false)))).await;
+        assert!(without_index.supports(Feature::SenderResolution));
+        assert!(!without_index.supports(Feature::UtxoIndex));
+
+        let old = NodeCapabilities::probe(&node(Ok(("0.17.0", true)))).await;
+        assert!(!old.supports(Feature::SenderResolution));
+        assert!(old.supports(Feature::UtxoIndex));
+
+        // the connect goes on, with the network from the DAG info and no optional feature
+        let unknown = node(Err(TransportError::Rpc("method not found".to_string())));
+        let unknown = NodeCapabilities::probe(&unknown).await;
+        assert!(unknown.probed);
+        assert_eq!(
+            (unknown.version, unknown.network_id.as_deref()),
+            (None, Some("testnet-10"))
+        );
+        assert!(Feature::ALL.iter().all(|f| !unknown.supports(*f)));
+        let requirements = unknown.requirements().unwrap();
+        assert!(unknown.check_requirements(&requirements).is_ok());
+    }
+
+    #[test]
+    fn test_version_parse() {
+        assert_eq!(NodeVersion::parse("1.0.1"), Some(NodeVersion::new(1, 0, 1)));
+        assert_eq!(NodeVersion::parse("v1.1"), Some(NodeVersion::new(1, 1, 0)));
+        assert_eq!(
+            NodeVersion::parse("1.0.2-dev"),
+            Some(NodeVersion::new(1, 0, 2))
+        );
+        assert_eq!(NodeVersion::parse("garbage"), None);
+    }
+
+    #[test]
+    fn test_feature_matrix() {
+        let unprobed = NodeCapabilities::default();
+        assert!(Feature::ALL.iter().all(|f| unprobed.supports(*f)));
+
+        let full = NodeCapabilities::new("1.0.1", 1, None, true, true);
+        assert!(Feature::ALL.iter().all(|f| full.supports(*f)));
+
+        let old_without_index = NodeCapabilities::new("0.17.0", 1, None, false, true);
+        assert!(!old_without_index.supports(Feature::SenderResolution));
+        assert!(!old_without_index.supports(Feature::UtxoIndex));
+
+        let unknown_version = NodeCapabilities::new("custom-build", 1, None, true, true);
+        assert!(!unknown_version.supports(Feature::SenderResolution));
+        assert!(unknown_version.supports(Feature::UtxoIndex));
+    }
+
+    #[test]
+    fn test_check_requirements() {
+        let node = |version, network: &str| {
+            NodeCapabilities::new(version, 1, Some(network.to_string()), true, true)
+        };
+        let mainnet = node("1.0.1", "mainnet").requirements().unwrap();
+        assert_eq!(mainnet.min_version, MIN_NODE_VERSION);
+
+        assert!(
+            node("1.0.1", "mainnet")
+                .check_requirements(&mainnet)
+                .is_ok()
+        );
+        assert!(
+            node("custom-build", "mainnet")
+                .check_requirements(&mainnet)
+                .is_ok()
+        );
+        assert!(
+            node("1.0.1", "testnet-10")
+                .check_requirements(&mainnet)
+                .is_err()
+        );
+        assert!(
+            node("0.17.0", "mainnet")
+                .check_requirements(&mainnet)
+                .is_err()
+        );
+        let raised = NodeRequirements {
+            min_version: NodeVersion::new(1, 1, 0),
+            ..mainnet
+        };
+        assert!(
+            node("1.0.1", "mainnet")
+                .check_requirements(&raised)
+                .is_err()
+        );
+        assert!(
+            NodeCapabilities::new("1.0.1", 1, None, true, true)
+                .requirements()
+                .is_err()
+        );
+    }
+}
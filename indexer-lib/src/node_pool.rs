@@ -0,0 +1,319 @@
+//! Nodes the resolver fetches blocks and senders from.
+//!
+//! Every node is checked periodically with `getServerInfo`, which reports sync state and network
+//! in one round trip whose latency ranks the node. Unreachable, unsynced nodes and nodes on
+//! another network are left out of the ranking, the resolver is handed the fastest remaining one.
+//! Nodes caught serving blocks whose headers hash differently are demoted, they rank behind all
+//! other usable nodes until restart.
+
+use crate::metrics::SharedMetrics;
+use crate::rpc_transport::RpcNode;
+use crate::shutdown::Shutdown;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// A node answering slower than this counts as unreachable
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Check interval while no node is usable
+const UNUSABLE_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Client handed out by the pool together with the url it is known by
+#[derive(Clone)]
+pub struct PooledClient {
+    pub url: Arc<str>,
+    pub client: RpcNode,
+}
+
+/// Outcome of the last health check of a node
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeHealth {
+    /// None while the node is unreachable or not checked yet
+    pub latency: Option<Duration>,
+    pub is_synced: bool,
+    pub network_id: Option<String>,
+}
+
+impl NodeHealth {
+    pub fn is_usable(&self, network_id: &str) -> bool {
+        self.latency.is_some() && self.is_synced && self.network_id.as_deref() == Some(network_id)
+    }
+}
+
+struct Node {
+    client: PooledClient,
+    /// Connected by the pool, the primary client is connected by the subscriber
+    owned: bool,
+    health: NodeHealth,
+    /// Served a block whose header hashes differently than reported
+    demoted: bool,
+}
+
+struct PoolState {
+    nodes: Vec<Node>,
+    /// Indexes of the usable nodes, fastest first
+    ranking: Vec<usize>,
+}
+
+#[derive(Clone)]
+pub struct NodePool {
+    state: Arc<RwLock<PoolState>>,
+    network_id: Arc<str>,
+    metrics: SharedMetrics,
+}
+
+impl NodePool {
+    /// Pool of the primary client only, nodes on networks other than `network_id` are excluded
+    pub fn new(primary: RpcNode, network_id: &str) -> Self {
+        let url = primary.url().unwrap_or_else(|| "primary".to_string());
+        Self {
+            state: Arc::new(RwLock::new(PoolState {
+                nodes: vec![Node {
+                    client: PooledClient {
+                        url: Arc::from(url),
+                        client: primary,
+                    },
+                    owned: false,
+                    health: NodeHealth::default(),
+                    demoted: false,
+                }],
+                ranking: Vec::new(),
+            })),
+            network_id: Arc::from(network_id),
+            metrics: Default::default(),
+        }
+    }
+
+    /// Additional nodes over either transport, connected once health checks start. Clients
+    /// without an url pick their node through the public resolver service
+    pub fn with_nodes(self, clients: Vec<RpcNode>) -> Self {
+        {
+            let mut state = self.state.write();
+            let offset = state.nodes.len();
+            state
+                .nodes
+                .extend(clients.into_iter().enumerate().map(|(i, client)| {
+                    let url = client
+                        .url()
+                        .unwrap_or_else(|| format!("public resolver #{}", offset + i));
+                    Node {
+                        client: PooledClient {
+                            url: Arc::from(url),
+                            client,
+                        },
+                        owned: true,
+                        health: NodeHealth::default(),
+                        demoted: false,
+                    }
+                }));
+        }
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Healthiest node, none while no node is usable
+    pub fn get_client(&self) -> Option<PooledClient> {
+        self.get_client_excluding(&[])
+    }
+
+    /// Healthiest node apart from `urls`, to retry a failed request on another node. Nodes whose
+    /// circuit breaker opened since the last health check are skipped too
+    pub fn get_client_excluding(&self, urls: &[Arc<str>]) -> Option<PooledClient> {
+        let state = self.state.read();
+        state
+            .ranking
+            .iter()
+            .map(|i| &state.nodes[*i].client)
+            .find(|node| !urls.contains(&node.url) && node.client.is_available())
+            .cloned()
+    }
+
+    /// Counts a request moved to another node after `url` failed it
+    pub fn record_failover(&self, url: &str) {
+        debug!(node = url, "Request failed, retrying on another node");
+        self.metrics.increment_resolver_failovers();
+    }
+
+    /// Ranks the node behind all other usable nodes from now on
+    pub fn demote(&self, url: &str) {
+        let mut state = self.state.write();
+        let Some(index) = state.nodes.iter().position(|node| &*node.client.url == url) else {
+            debug!(node = url, "Node to demote is not a resolver node");
+            return;
+        };
+        if state.nodes[index].demoted {
+            return;
+        }
+        warn!(
+            node = url,
+            "Demoting resolver node to the bottom of the ranking"
+        );
+        state.nodes[index].demoted = true;
+        let PoolState { nodes, ranking } = &mut *state;
+        demote_last(ranking, |i| nodes[i].demoted);
+    }
+
+    pub fn health(&self) -> Vec<(Arc<str>, NodeHealth)> {
+        self.state
+            .read()
+            .nodes
+            .iter()
+            .map(|node| (node.client.url.clone(), node.health.clone()))
+            .collect()
+    }
+
+    /// Connects the additional nodes and checks all nodes every `interval` until shutdown, then
+    /// disconnects the additional nodes
+    pub async fn run_health_checks(
+        self,
+        interval: Duration,
+        shutdown: Shutdown,
+    ) -> anyhow::Result<()> {
+        let owned = self.owned_clients();
+        for node in &owned {
+            info!(node = %node.url, "Connecting to resolver node");
+            node.client.connect().await?;
+        }
+        loop {
+            self.check_all().await;
+            // nodes come up at startup and after outages, don't leave the resolver waiting
+            let delay = if self.state.read().ranking.is_empty() {
+                interval.min(UNUSABLE_RECHECK_INTERVAL)
+            } else {
+                interval
+            };
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+        for node in &owned {
+            if let Err(err) = node.client.disconnect().await {
+                warn!(node = %node.url, "Failed to disconnect resolver node: {err}");
+            }
+        }
+        info!("Resolver node health checks stopped");
+        Ok(())
+    }
+
+    fn owned_clients(&self) -> Vec<PooledClient> {
+        self.state
+            .read()
+            .nodes
+            .iter()
+            .filter(|node| node.owned)
+            .map(|node| node.client.clone())
+            .collect()
+    }
+
+    async fn check_all(&self) {
+        let clients = self
+            .state
+            .read()
+            .nodes
+            .iter()
+            .map(|node| node.client.clone())
+            .collect::<Vec<_>>();
+        let health = futures_util::future::join_all(clients.iter().map(check)).await;
+        let mut state = self.state.write();
+        for (node, health) in state.nodes.iter_mut().zip(health) {
+            node.health = health;
+        }
+        let mut ranking = rank(
+            &state
+                .nodes
+                .iter()
+                .map(|node| node.health.clone())
+                .collect::<Vec<_>>(),
+            &self.network_id,
+        );
+        demote_last(&mut ranking, |i| state.nodes[i].demoted);
+        if ranking != state.ranking {
+            debug!(
+                ranking = ?ranking.iter().map(|i| &*state.nodes[*i].client.url).collect::<Vec<_>>(),
+                "Resolver node ranking changed"
+            );
+            if ranking.is_empty() {
+                warn!("No usable resolver node");
+            }
+        }
+        self.metrics
+            .set_resolver_nodes(ranking.len() as u64, state.nodes.len() as u64);
+        state.ranking = ranking;
+    }
+}
+
+async fn check(node: &PooledClient) -> NodeHealth {
+    if !node.client.is_connected() {
+        return NodeHealth::default();
+    }
+    let started = Instant::now();
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, node.client.get_server_info()).await {
+        Ok(Ok(info)) => NodeHealth {
+            latency: Some(started.elapsed()),
+            is_synced: info.is_synced,
+            network_id: Some(info.network_id.to_string()),
+        },
+        Ok(Err(err)) => {
+            debug!(node = %node.url, "Health check failed: {err}");
+            NodeHealth::default()
+        }
+        Err(_) => {
+            debug!(node = %node.url, "Health check timed out");
+            NodeHealth::default()
+        }
+    }
+}
+
+/// Indexes of the usable nodes, fastest first
+fn rank(health: &[NodeHealth], network_id: &str) -> Vec<usize> {
+    let mut ranking = (0..health.len())
+        .filter(|i| health[*i].is_usable(network_id))
+        .collect::<Vec<_>>();
+    ranking.sort_by_key(|i| health[*i].latency);
+    ranking
+}
+
+/// Moves the demoted nodes behind the others, keeping the order within both
+fn demote_last(ranking: &mut [usize], is_demoted: impl Fn(usize) -> bool) {
+    ranking.sort_by_key(|i| is_demoted(*i));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_excludes_unusable_nodes() {
+        let node = |latency_ms, is_synced, network_id: &str| NodeHealth {
+            latency: latency_ms.map(Duration::from_millis),
+            is_synced,
+            network_id: Some(network_id.to_string()),
+        };
+        let health = [
+            node(Some(40), true, "mainnet"),
+            node(Some(5), false, "mainnet"),
+            node(Some(10), true, "testnet-10"),
+            node(None, true, "mainnet"),
+            node(Some(20), true, "mainnet"),
+            NodeHealth::default(),
+        ];
+        assert_eq!(rank(&health, "mainnet"), vec![4, 0]);
+        assert_eq!(rank(&health, "testnet-10"), vec![2]);
+        assert!(rank(&[], "mainnet").is_empty());
+    }
+
+    #[test]
+    fn test_demoted_nodes_rank_last() {
+        let mut ranking = vec![4, 0, 2, 1];
+        demote_last(&mut ranking, |i| i == 4 || i == 2);
+        assert_eq!(ranking, vec![0, 1, 4, 2]);
+    }
+}
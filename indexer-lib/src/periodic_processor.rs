@@ -1,7 +1,12 @@
-use crate::APP_IS_RUNNING;
 use crate::RK_PRUNING_DEPTH;
 use crate::database::PartitionId;
-use crate::database::headers::{BlockCompactHeaderPartition, DaaIndexPartition};
+use crate::database::block_stats::BlockStatsPartition;
+use crate::database::block_transactions::BlockTransactionsPartition;
+use crate::database::compaction::{DEFAULT_COMPACTION_MAX_LAG_DAA, PartitionCompaction};
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockGapsPartition, BlockRelationsPartition,
+    ChainMembershipPartition, DaaIndexPartition,
+};
 use crate::database::messages::{
     ContextualMessageBySenderPartition, HandshakeByReceiverPartition, HandshakeBySenderPartition,
     HandshakeKeyByReceiver, HandshakeKeyBySender, PaymentByReceiverPartition,
@@ -10,33 +15,52 @@ use crate::database::messages::{
 };
 use crate::database::metadata::MetadataPartition;
 use crate::database::processing::{
-    AcceptingBlockResolutionData, PendingResolutionKey, PendingSenderResolutionPartition,
-    ResolutionEntries, SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition,
+    AcceptanceHistoryPartition, AcceptingBlockResolutionData, PendingResolutionKey,
+    PendingSenderResolutionPartition, ProcessedBlockPartition, ResolutionEntries,
+    SkipTxByBlockPartition, SkipTxPartition, TxIDToAcceptancePartition,
     UnknownAcceptingDaaPartition, UnknownTxPartition, UnknownTxUpdateAction,
 };
 use crate::database::resolution_keys::{DaaResolutionLikeKey, SenderResolutionLikeKey};
+use crate::database::stats;
+use crate::database::supply::BlockRewardPartition;
+use crate::gap_rescan::GapRescan;
+use crate::header_validation::HeaderValidator;
+use crate::historical_syncer::Cursor;
 use crate::metrics::SharedMetrics;
+use crate::node_capabilities::{Feature, SharedNodeCapabilities};
 use crate::resolver::{ResolverResponse, SenderByTxIdAndDaa};
+use crate::scheduler::{PeriodicTask, Scheduler, TaskContext};
+use crate::shutdown::Shutdown;
+use crate::status;
 use anyhow::Context;
 use fjall::TxKeyspace;
 use kaspa_rpc_core::{RpcAddress, RpcHash, RpcHeader, RpcTransactionId};
 use parking_lot::Mutex;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Duration;
-use std::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
 use tracing::{debug, error, info, trace, warn};
 use workflow_core::channel::{Receiver, Sender};
 
+/// Acceptance history records are kept this long
+const ACCEPTANCE_HISTORY_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+const ACCEPTANCE_HISTORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Interval of the maintenance tasks which used to run on every tick
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10);
+/// Entries deeper than this below the virtual DAA score are pruned
+pub const DEFAULT_PRUNING_DEPTH: u64 = RK_PRUNING_DEPTH * 3;
+
 #[derive(Debug, Clone)]
 pub enum Notification {
     Tick,
-    Shutdown,
     ResolverResponse(ResolverResponse),
+    /// Pruning point reported by the node
+    PruningPoint(Cursor),
 }
 
 pub async fn run_ticker(
-    mut shutdown: tokio::sync::oneshot::Receiver<()>,
+    shutdown: Shutdown,
     job_done_rx: Receiver<()>,
     tick_tx: Sender<Notification>,
     interval: Duration,
@@ -55,9 +79,8 @@ pub async fn run_ticker(
                 r?;
                 need_to_send = true;
             }
-            r = &mut shutdown => {
+            _ = shutdown.cancelled() => {
                 info!("Shutting down scan ticker");
-                r?;
                 return Ok(())
             }
         }
@@ -80,8 +103,16 @@ pub struct PeriodicProcessor {
     unknown_accepting_daa_partition: UnknownAcceptingDaaPartition,
     block_compact_header_partition: BlockCompactHeaderPartition,
     block_daa_index: DaaIndexPartition,
+    chain_membership_partition: ChainMembershipPartition,
+    block_relations_partition: BlockRelationsPartition,
+    block_reward_partition: BlockRewardPartition,
+    block_stats_partition: BlockStatsPartition,
+    block_transactions_partition: BlockTransactionsPartition,
+    processed_block_partition: ProcessedBlockPartition,
+    block_gaps_partition: BlockGapsPartition,
     daa_resolution_attempt_count: u8,
     pending_sender_resolution_partition: PendingSenderResolutionPartition,
+    acceptance_history_partition: AcceptanceHistoryPartition,
 
     handshake_by_receiver_partition: HandshakeByReceiverPartition,
     handshake_by_sender_partition: HandshakeBySenderPartition,
@@ -93,54 +124,182 @@ pub struct PeriodicProcessor {
     metadata_partition: MetadataPartition,
     metrics: SharedMetrics,
     metrics_snapshot_interval: Duration,
-    #[builder(default = Instant::now())]
-    last_metrics_snapshot_time: Instant,
+    #[builder(default = Duration::from_secs(60))]
+    database_stats_interval: Duration,
+    /// Partitions are compacted only while the block tip is at most this far behind the node
+    #[builder(default = DEFAULT_COMPACTION_MAX_LAG_DAA)]
+    compaction_max_lag_daa: u64,
+    /// Replace the default interval of a periodic task, by task name
+    #[builder(default)]
+    task_intervals: Vec<(String, Duration)>,
+    /// Entries deeper than this below the virtual DAA score are pruned
+    #[builder(default = DEFAULT_PRUNING_DEPTH)]
+    pruning_depth: u64,
     resolver_requests_in_progress: Arc<AtomicU64>,
 
     virtual_daa: Arc<AtomicU64>,
+
+    #[builder(default)]
+    node_capabilities: SharedNodeCapabilities,
+    #[builder(default)]
+    sender_resolution_disabled: bool,
+    /// Re-validates stored headers after consensus crate upgrades, a batch per run
+    header_validator: Option<HeaderValidator>,
+    /// Requests syncers for pending gaps nobody syncs
+    gap_rescan: Option<GapRescan>,
+    /// Of the processors stage, the worker stops once cancelled
+    #[builder(default)]
+    shutdown: Shutdown,
+    /// Notification a failed run was handling, the next run handles it first
+    #[builder(skip)]
+    in_flight: Option<Notification>,
 }
 
 impl PeriodicProcessor {
     pub fn worker(&mut self) -> anyhow::Result<()> {
-        while APP_IS_RUNNING.load(Ordering::Relaxed) {
-            match self.tick_and_resolution_rx.recv_blocking()? {
+        let mut scheduler = self.scheduler();
+        loop {
+            let notification = match self.in_flight.take() {
+                Some(notification) => {
+                    info!("Handling the notification the last run failed on");
+                    notification
+                }
+                None => match self.next_notification()? {
+                    Some(notification) => notification,
+                    None => break,
+                },
+            };
+            self.in_flight = Some(notification.clone());
+            match notification {
                 Notification::ResolverResponse(ResolverResponse::Block(r)) => {
                     self.handle_daa_resolution(r)?;
                 }
                 Notification::ResolverResponse(ResolverResponse::Sender(r)) => {
                     self.handle_sender_resolution(r)?;
                 }
+                Notification::PruningPoint(pruning_point) => {
+                    self.handle_pruning_point(pruning_point)?;
+                }
                 Notification::Tick => {
+                    scheduler.tick();
                     self.tick_work()?;
                     self.job_done_tx.send_blocking(())?;
                 }
-                Notification::Shutdown => {
-                    info!("Shutting down scan worker");
-                    return Ok(());
-                }
             }
+            self.in_flight = None;
         }
-        info!("Scan worker shut down");
+        info!("Shutting down scan worker");
+        scheduler.shutdown();
         Ok(())
     }
 
+    /// None once the stage is cancelled
+    fn next_notification(&self) -> anyhow::Result<Option<Notification>> {
+        // runs on a blocking thread of the runtime, see `Shutdown::spawn_blocking`
+        Handle::current().block_on(async {
+            tokio::select! {
+                biased;
+                _ = self.shutdown.cancelled() => Ok(None),
+                notification = self.tick_and_resolution_rx.recv() => Ok(Some(notification?)),
+            }
+        })
+    }
+
+    /// Maintenance runs on its own intervals, apart from the resolution work of every tick
+    fn scheduler(&mut self) -> Scheduler {
+        let mut scheduler = Scheduler::default()
+            .with_intervals(self.task_intervals.clone())
+            .with_metrics(self.metrics.clone());
+        scheduler.register(SkipTxPruning {
+            tx_keyspace: self.tx_keyspace.clone(),
+            skip_tx_partition: self.skip_tx_partition.clone(),
+            skip_tx_by_block_partition: self.skip_tx_by_block_partition.clone(),
+            virtual_daa: self.virtual_daa.clone(),
+            pruning_depth: self.pruning_depth,
+        });
+        scheduler.register(self.block_header_pruning());
+        scheduler.register(AcceptanceHistoryPruning {
+            tx_keyspace: self.tx_keyspace.clone(),
+            acceptance_history_partition: self.acceptance_history_partition.clone(),
+        });
+        if let Some(validator) = self.header_validator.take() {
+            scheduler.register(validator);
+        }
+        if let Some(gap_rescan) = self.gap_rescan.take() {
+            scheduler.register(gap_rescan);
+        }
+        scheduler.register(MetadataCompaction(self.metadata_partition.clone()));
+        scheduler.register(PartitionCompaction::new(
+            self.tx_keyspace.clone(),
+            self.metrics.clone(),
+            self.compaction_max_lag_daa,
+        ));
+        scheduler.register(DatabaseStatsRefresh {
+            tx_keyspace: self.tx_keyspace.clone(),
+            metrics: self.metrics.clone(),
+            interval: self.database_stats_interval,
+        });
+        scheduler.register(MetricsSnapshotLog {
+            metrics: self.metrics.clone(),
+            resolver_requests_in_progress: self.resolver_requests_in_progress.clone(),
+            interval: self.metrics_snapshot_interval,
+        });
+        scheduler
+    }
+
+    fn block_header_pruning(&self) -> BlockHeaderPruning {
+        BlockHeaderPruning {
+            tx_keyspace: self.tx_keyspace.clone(),
+            block_daa_index: self.block_daa_index.clone(),
+            block_compact_header_partition: self.block_compact_header_partition.clone(),
+            block_stats_partition: self.block_stats_partition.clone(),
+            block_transactions_partition: self.block_transactions_partition.clone(),
+            processed_block_partition: self.processed_block_partition.clone(),
+            chain_membership_partition: self.chain_membership_partition.clone(),
+            block_relations_partition: self.block_relations_partition.clone(),
+            block_reward_partition: self.block_reward_partition.clone(),
+            metadata_partition: self.metadata_partition.clone(),
+            virtual_daa: self.virtual_daa.clone(),
+            pruning_depth: self.pruning_depth,
+        }
+    }
+
     pub fn tick_work(&mut self) -> anyhow::Result<()> {
         self.resolve_unknown_tx()?;
         self.unknown_daa()?;
         self.unknown_sender()?;
-        self.prune_skip_transactions()?;
-        self.prune_block_headers()?;
-        self.compact_metadata()?;
         self.update_metrics()?;
         Ok(())
     }
 
-    fn compact_metadata(&self) -> anyhow::Result<()> {
-        if self.metadata_partition.0.inner().disk_space() > 1024 * 1024 {
-            self.metadata_partition.0.inner().major_compact()?;
+    /// Stores a moved node pruning point and prunes right away. Pending gaps starting below
+    /// it can't be synced from the node anymore and are marked unrecoverable
+    fn handle_pruning_point(&self, pruning_point: Cursor) -> anyhow::Result<()> {
+        let known = self
+            .metadata_partition
+            .get_node_pruning_point_rtx(&self.tx_keyspace.read_tx())?;
+        if known.is_some_and(|known| known.hash == pruning_point.hash) {
+            return Ok(());
         }
-        Ok(())
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        self.metadata_partition
+            .set_node_pruning_point_wtx(&mut wtx, pruning_point);
+        let unrecoverable = self
+            .block_gaps_partition
+            .mark_unrecoverable_before_wtx(&mut wtx, pruning_point.daa_score)?;
+        wtx.commit()?
+            .context("failed to commit, conflict pruning_point")?;
+        info!(hash = %pruning_point.hash, daa_score = pruning_point.daa_score, "Node pruning point moved");
+        for gap in unrecoverable {
+            warn!(
+                from_daa_score = gap.from_daa_score,
+                to_daa_score = gap.to_daa_score,
+                "Gap starts below the node pruning point, marked unrecoverable"
+            );
+        }
+        self.block_header_pruning().run(&TaskContext::default())
     }
+
     fn update_metrics(&mut self) -> anyhow::Result<()> {
         self.metrics
             .set_handshakes_by_receiver(self.tx_id_to_handshake_partition.approximate_len() as u64); // todo use len at startup and atomic for update
@@ -150,6 +309,11 @@ impl PeriodicProcessor {
             .set_payments_by_receiver(self.tx_id_to_payment_partition.approximate_len() as u64); // todo use len at startup and atomic for update
         self.metrics
             .set_payments_by_sender(self.payment_by_sender_partition.approximate_len() as u64);
+        self.metrics
+            .set_header_cache_stats(self.block_compact_header_partition.cache_stats());
+        if let Some(stats) = self.tx_id_to_acceptance_partition.filter_stats() {
+            self.metrics.set_tx_filter_stats(stats);
+        }
         self.metrics.set_latest_block(
             self.metadata_partition
                 .get_latest_block_cursor()?
@@ -162,16 +326,16 @@ impl PeriodicProcessor {
                 .unwrap_or_default()
                 .hash,
         );
-
-        if self.last_metrics_snapshot_time.elapsed() > self.metrics_snapshot_interval {
-            info!("{}", self.metrics.snapshot());
-            info!(
-                "requests in progress: {}",
-                self.resolver_requests_in_progress.load(Ordering::Relaxed)
-            );
-            self.last_metrics_snapshot_time = Instant::now();
+        // kept current by the subscriber from virtual DAA score notifications, zero until the
+        // first one arrived
+        let node_daa_score = self.virtual_daa.load(Ordering::Relaxed);
+        if node_daa_score > 0 {
+            self.metrics.set_sync_lag(&status::get_status(
+                &self.metadata_partition,
+                &self.tx_keyspace.read_tx(),
+                node_daa_score,
+            )?);
         }
-
         Ok(())
     }
 
@@ -197,11 +361,7 @@ impl PeriodicProcessor {
                     return Ok(());
                 };
                 debug!(block_hash = %header.hash, daa_score = %header.daa_score, "Successfully resolved DAA score for block");
-                self.block_compact_header_partition.insert_compact_header(
-                    &header.hash,
-                    header.blue_work,
-                    header.daa_score,
-                )?;
+                self.block_compact_header_partition.insert_header(&header)?;
                 self.block_daa_index
                     .insert(header.daa_score, &header.hash)?;
 
@@ -556,6 +716,17 @@ impl PeriodicProcessor {
     }
 
     fn unknown_sender(&mut self) -> anyhow::Result<()> {
+        let capabilities = self.node_capabilities.load();
+        if let Some(note) = capabilities.unavailable_reason(Feature::SenderResolution) {
+            if !self.sender_resolution_disabled {
+                warn!("Sender resolution is paused: {note}");
+                self.sender_resolution_disabled = true;
+            }
+            return Ok(());
+        } else if self.sender_resolution_disabled {
+            info!("Sender resolution is resumed");
+            self.sender_resolution_disabled = false;
+        }
         let rtx = self.tx_keyspace.read_tx();
         let mut count = 0;
         for pending in self
@@ -582,12 +753,29 @@ impl PeriodicProcessor {
         self.metrics.set_unknown_sender_entries(count);
         Ok(())
     }
+}
 
-    /// Prune skipped transactions based on DAA score threshold.
-    fn prune_skip_transactions(&mut self) -> anyhow::Result<()> {
+/// Removes skipped transactions of blocks below the indexer pruning depth
+struct SkipTxPruning {
+    tx_keyspace: TxKeyspace,
+    skip_tx_partition: SkipTxPartition,
+    skip_tx_by_block_partition: SkipTxByBlockPartition,
+    virtual_daa: Arc<AtomicU64>,
+    pruning_depth: u64,
+}
+
+impl PeriodicTask for SkipTxPruning {
+    fn name(&self) -> &'static str {
+        "prune_skip_transactions"
+    }
+
+    fn interval(&self) -> Duration {
+        MAINTENANCE_INTERVAL
+    }
+
+    fn run(&mut self, ctx: &TaskContext) -> anyhow::Result<()> {
         let current_daa = self.virtual_daa.load(Ordering::Relaxed);
-        const INDEXER_PRUNING_DEPTH: u64 = RK_PRUNING_DEPTH * 3;
-        let prune_before_daa = current_daa.saturating_sub(INDEXER_PRUNING_DEPTH);
+        let prune_before_daa = current_daa.saturating_sub(self.pruning_depth);
         let prune_before_daa_bytes = prune_before_daa.to_be_bytes();
 
         debug!(current_daa = %current_daa, prune_before_daa = %prune_before_daa, "Starting skip transaction pruning");
@@ -603,6 +791,10 @@ impl PeriodicProcessor {
             .skip_tx_by_block_partition
             .get_entries_to_prune(&rtx, prune_before_daa_bytes)
         {
+            // blocks pruned so far are committed, the rest is left for the next run
+            if ctx.is_cancelled() {
+                break;
+            }
             let (raw_key, tx_ids_view) = entry_result.context("Failed to get entry to prune")?;
             error!("prune_block: {}", RpcHash::from_slice(&raw_key[8..]));
             // Remove individual skip transactions from the main skip partition
@@ -633,20 +825,176 @@ impl PeriodicProcessor {
 
         Ok(())
     }
+}
+
+/// Removes headers and per-block data below the indexer pruning depth, also run right away
+/// when the node pruning point moves. The pruned DAA score is marked before any block is
+/// removed, see [`MetadataPartition::mark_blocks_pruned_wtx`]
+struct BlockHeaderPruning {
+    tx_keyspace: TxKeyspace,
+    block_daa_index: DaaIndexPartition,
+    block_compact_header_partition: BlockCompactHeaderPartition,
+    block_stats_partition: BlockStatsPartition,
+    block_transactions_partition: BlockTransactionsPartition,
+    processed_block_partition: ProcessedBlockPartition,
+    chain_membership_partition: ChainMembershipPartition,
+    block_relations_partition: BlockRelationsPartition,
+    block_reward_partition: BlockRewardPartition,
+    metadata_partition: MetadataPartition,
+    virtual_daa: Arc<AtomicU64>,
+    pruning_depth: u64,
+}
+
+impl PeriodicTask for BlockHeaderPruning {
+    fn name(&self) -> &'static str {
+        "prune_block_headers"
+    }
 
-    fn prune_block_headers(&self) -> anyhow::Result<()> {
+    fn interval(&self) -> Duration {
+        MAINTENANCE_INTERVAL
+    }
+
+    fn run(&mut self, ctx: &TaskContext) -> anyhow::Result<()> {
         let read_tx = self.tx_keyspace.read_tx();
-        const INDEXER_PRUNING_DEPTH: u64 = RK_PRUNING_DEPTH * 3;
-        for r in self.block_daa_index.iter_lt(
-            &read_tx,
-            self.virtual_daa
-                .load(Ordering::Relaxed)
-                .saturating_sub(INDEXER_PRUNING_DEPTH),
-        ) {
+        let prune_before_daa = self
+            .virtual_daa
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.pruning_depth);
+        let mut entries = self
+            .block_daa_index
+            .iter_lt(&read_tx, prune_before_daa)
+            .peekable();
+        if entries.peek().is_none() {
+            return Ok(());
+        }
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        self.metadata_partition
+            .mark_blocks_pruned_wtx(&mut wtx, prune_before_daa)?;
+        wtx.commit()?
+            .context("failed to commit, conflict prune_block_headers")?;
+        for r in entries {
+            if ctx.is_cancelled() {
+                break;
+            }
             let (daa, hash) = r?;
             self.block_compact_header_partition.remove(&hash)?;
+            self.block_stats_partition.remove(&hash)?;
+            self.block_transactions_partition.remove(&hash)?;
+            self.processed_block_partition.remove(&hash)?;
+            self.chain_membership_partition.remove(&hash)?;
+            self.block_relations_partition.remove(&hash)?;
+            self.block_reward_partition.remove(&hash)?;
             self.block_daa_index.delete(daa, &hash)?
         }
         Ok(())
     }
 }
+
+struct AcceptanceHistoryPruning {
+    tx_keyspace: TxKeyspace,
+    acceptance_history_partition: AcceptanceHistoryPartition,
+}
+
+impl PeriodicTask for AcceptanceHistoryPruning {
+    fn name(&self) -> &'static str {
+        "prune_acceptance_history"
+    }
+
+    fn interval(&self) -> Duration {
+        ACCEPTANCE_HISTORY_PRUNE_INTERVAL
+    }
+
+    fn run(&mut self, _ctx: &TaskContext) -> anyhow::Result<()> {
+        let recorded_before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .saturating_sub(ACCEPTANCE_HISTORY_RETENTION)
+            .as_millis() as u64;
+        let mut wtx = self.tx_keyspace.write_tx()?;
+        let pruned = self
+            .acceptance_history_partition
+            .prune_older_than_wtx(&mut wtx, recorded_before)?;
+        wtx.commit()??;
+        debug!(pruned, "Pruned acceptance history");
+        Ok(())
+    }
+}
+
+impl PeriodicTask for HeaderValidator {
+    fn name(&self) -> &'static str {
+        "validate_headers"
+    }
+
+    fn interval(&self) -> Duration {
+        MAINTENANCE_INTERVAL
+    }
+
+    fn run(&mut self, _ctx: &TaskContext) -> anyhow::Result<()> {
+        self.step()
+    }
+}
+
+struct MetadataCompaction(MetadataPartition);
+
+impl PeriodicTask for MetadataCompaction {
+    fn name(&self) -> &'static str {
+        "compact_metadata"
+    }
+
+    fn interval(&self) -> Duration {
+        MAINTENANCE_INTERVAL
+    }
+
+    fn run(&mut self, _ctx: &TaskContext) -> anyhow::Result<()> {
+        if self.0.0.inner().disk_space() > 1024 * 1024 {
+            self.0.0.inner().major_compact()?;
+        }
+        Ok(())
+    }
+}
+
+struct DatabaseStatsRefresh {
+    tx_keyspace: TxKeyspace,
+    metrics: SharedMetrics,
+    interval: Duration,
+}
+
+impl PeriodicTask for DatabaseStatsRefresh {
+    fn name(&self) -> &'static str {
+        "database_stats"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn run(&mut self, _ctx: &TaskContext) -> anyhow::Result<()> {
+        self.metrics
+            .set_database_stats(stats::stats(&self.tx_keyspace)?);
+        Ok(())
+    }
+}
+
+struct MetricsSnapshotLog {
+    metrics: SharedMetrics,
+    resolver_requests_in_progress: Arc<AtomicU64>,
+    interval: Duration,
+}
+
+impl PeriodicTask for MetricsSnapshotLog {
+    fn name(&self) -> &'static str {
+        "metrics_snapshot"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn run(&mut self, _ctx: &TaskContext) -> anyhow::Result<()> {
+        info!("{}", self.metrics.snapshot());
+        info!(
+            "requests in progress: {}",
+            self.resolver_requests_in_progress.load(Ordering::Relaxed)
+        );
+        Ok(())
+    }
+}
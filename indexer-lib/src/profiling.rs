@@ -0,0 +1,241 @@
+//! Tagging of the pipeline stages for the profiles served under `/admin/profile` by the query
+//! API, see `api::profile`.
+//!
+//! The supervised components run inside a [`component_span`] for their whole run. With the
+//! `profiling` feature, `ComponentLayer` follows these spans so the stages are told apart:
+//!
+//! - CPU samples of a thread inside a component span are rooted at the component name instead
+//!   of the thread name, see `cpu_profile`
+//! - allocations through [`ComponentAllocator`] are counted per component, see [`heap_stats`]
+//!
+//! Async tasks are not tagged, their samples stay under the runtime worker threads and their
+//! allocations count as [`UNTAGGED`].
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{Span, debug_span};
+
+#[cfg(feature = "profiling")]
+pub use tagging::{ComponentLayer, CpuProfileRunning, cpu_profile};
+
+#[cfg(feature = "profiling")]
+pub mod tagging;
+
+/// Longest CPU profile a request may ask for
+pub const DEFAULT_MAX_CPU_PROFILE: Duration = Duration::from_secs(60);
+/// Samples per second of a CPU profile, off the round frequencies of periodic work
+pub const CPU_SAMPLE_FREQUENCY: i32 = 99;
+/// Target of the component spans
+pub const COMPONENT_TARGET: &str = "component";
+/// Component of the work outside every component span
+pub const UNTAGGED: &str = "other";
+/// Components told apart, later ones count as untagged
+const MAX_COMPONENTS: usize = 16;
+
+/// Span tagging the work done inside it with the component `name`
+pub fn component_span(name: &'static str) -> Span {
+    debug_span!(target: COMPONENT_TARGET, "component", name)
+}
+
+/// Component names by index, the untagged one first
+static COMPONENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Index of the component the thread works for
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Index of the component, registered on first use
+fn component_index(name: &str) -> usize {
+    let mut components = COMPONENTS.lock();
+    if components.is_empty() {
+        components.push(UNTAGGED.to_string());
+    }
+    match components.iter().position(|known| known == name) {
+        Some(index) => index,
+        None if components.len() < MAX_COMPONENTS => {
+            components.push(name.to_string());
+            components.len() - 1
+        }
+        None => 0,
+    }
+}
+
+fn current_component() -> usize {
+    CURRENT.try_with(Cell::get).unwrap_or(0)
+}
+
+struct HeapCounters {
+    allocations: AtomicU64,
+    allocated_bytes: AtomicU64,
+    freed_bytes: AtomicU64,
+}
+
+impl HeapCounters {
+    const fn new() -> Self {
+        Self {
+            allocations: AtomicU64::new(0),
+            allocated_bytes: AtomicU64::new(0),
+            freed_bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+static HEAP: [HeapCounters; MAX_COMPONENTS] = [const { HeapCounters::new() }; MAX_COMPONENTS];
+static ALLOCATOR_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+fn record_allocation(size: usize) {
+    let counters = &HEAP[current_component()];
+    counters.allocations.fetch_add(1, Ordering::Relaxed);
+    counters
+        .allocated_bytes
+        .fetch_add(size as u64, Ordering::Relaxed);
+}
+
+fn record_free(size: usize) {
+    HEAP[current_component()]
+        .freed_bytes
+        .fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// Allocator counting the allocations of `A` per component, installed by the binary:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: ComponentAllocator<System> = ComponentAllocator(System);
+/// ```
+///
+/// Two relaxed atomic additions per call, the component is a thread local
+pub struct ComponentAllocator<A>(pub A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ComponentAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.0.alloc(layout) };
+        if !ptr.is_null() {
+            ALLOCATOR_INSTALLED.store(true, Ordering::Relaxed);
+            record_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.0.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            ALLOCATOR_INSTALLED.store(true, Ordering::Relaxed);
+            record_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.dealloc(ptr, layout) };
+        record_free(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.0.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            record_free(layout.size());
+            record_allocation(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Allocations of a component since startup. Frees count for the component of the freeing
+/// thread, so memory handed between stages shows up as allocated by one and freed by another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentHeap {
+    pub component: String,
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    pub freed_bytes: u64,
+}
+
+/// Allocations per component, none unless [`ComponentAllocator`] is the global allocator
+pub fn heap_stats() -> Option<Vec<ComponentHeap>> {
+    if !ALLOCATOR_INSTALLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    Some(heap_by_component())
+}
+
+fn heap_by_component() -> Vec<ComponentHeap> {
+    let mut components = COMPONENTS.lock().clone();
+    if components.is_empty() {
+        components.push(UNTAGGED.to_string());
+    }
+    components
+        .into_iter()
+        .zip(&HEAP)
+        .map(|(component, counters)| ComponentHeap {
+            component,
+            allocations: counters.allocations.load(Ordering::Relaxed),
+            allocated_bytes: counters.allocated_bytes.load(Ordering::Relaxed),
+            freed_bytes: counters.freed_bytes.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::System;
+
+    #[test]
+    fn test_components_are_registered_once() {
+        let index = component_index("test registered component");
+        assert!(index > 0);
+        assert_eq!(component_index("test registered component"), index);
+        assert_eq!(component_index(UNTAGGED), 0);
+    }
+
+    #[test]
+    fn test_allocations_count_for_the_current_component() {
+        let index = component_index("test allocating component");
+        let allocator = ComponentAllocator(System);
+        let layout = Layout::from_size_align(1_000, 8).unwrap();
+        let counted = || {
+            heap_by_component()
+                .into_iter()
+                .find(|heap| heap.component == "test allocating component")
+                .unwrap()
+        };
+        let before = counted();
+
+        CURRENT.with(|current| current.set(index));
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let ptr = allocator.realloc(ptr, layout, 3_000);
+            allocator.dealloc(ptr, Layout::from_size_align(3_000, 8).unwrap());
+        }
+        CURRENT.with(|current| current.set(0));
+
+        let after = counted();
+        assert_eq!(after.allocations - before.allocations, 2);
+        assert_eq!(after.allocated_bytes - before.allocated_bytes, 4_000);
+        assert_eq!(after.freed_bytes - before.freed_bytes, 4_000);
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_layer_tags_the_component_span() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(ComponentLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let index = component_index("test traced component");
+            assert_eq!(current_component(), 0);
+            let span = component_span("test traced component");
+            {
+                let _entered = span.enter();
+                assert_eq!(current_component(), index);
+            }
+            assert_eq!(current_component(), 0);
+        });
+    }
+}
@@ -0,0 +1,148 @@
+//! Following of the component spans and the CPU sampling, built with the `profiling` feature.
+
+use super::{COMPONENT_TARGET, COMPONENTS, CPU_SAMPLE_FREQUENCY, CURRENT, component_index};
+use itertools::Itertools;
+use parking_lot::Mutex;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::Subscriber;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Threads inside a component span by pthread id, with the component index
+static THREADS: Mutex<Vec<(u64, usize)>> = Mutex::new(Vec::new());
+static CPU_PROFILE_RUNNING: AtomicBool = AtomicBool::new(false);
+
+struct ComponentIndex(usize);
+
+#[derive(Default)]
+struct ComponentName(Option<String>);
+
+impl Visit for ComponentName {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "name" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "name" && self.0.is_none() {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+fn pthread_id() -> u64 {
+    (unsafe { libc::pthread_self() }) as u64
+}
+
+/// Follows the component spans, installed next to the logging layers
+pub struct ComponentLayer;
+
+impl<S> Layer<S> for ComponentLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().target() != COMPONENT_TARGET {
+            return;
+        }
+        let mut name = ComponentName::default();
+        attrs.record(&mut name);
+        if let (Some(name), Some(span)) = (name.0, ctx.span(id)) {
+            span.extensions_mut()
+                .insert(ComponentIndex(component_index(&name)));
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        if let Some(ComponentIndex(index)) = span.extensions().get::<ComponentIndex>() {
+            _ = CURRENT.try_with(|current| current.set(*index));
+            let thread = pthread_id();
+            let mut threads = THREADS.lock();
+            threads.retain(|(id, _)| *id != thread);
+            threads.push((thread, *index));
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        if span.extensions().get::<ComponentIndex>().is_some() {
+            _ = CURRENT.try_with(|current| current.set(0));
+            let thread = pthread_id();
+            THREADS.lock().retain(|(id, _)| *id != thread);
+        }
+    }
+}
+
+/// A CPU profile was asked for while another one was running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuProfileRunning;
+
+impl fmt::Display for CpuProfileRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a CPU profile is running already")
+    }
+}
+
+impl std::error::Error for CpuProfileRunning {}
+
+struct Running;
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        CPU_PROFILE_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// Samples every thread for `duration` and answers the stacks in the folded format of
+/// flamegraph.pl, which speedscope and inferno open. Blocks for `duration`, only one
+/// profile runs at a time
+pub fn cpu_profile(duration: Duration) -> anyhow::Result<String> {
+    if CPU_PROFILE_RUNNING.swap(true, Ordering::AcqRel) {
+        return Err(CpuProfileRunning.into());
+    }
+    let _running = Running;
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(CPU_SAMPLE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    std::thread::sleep(duration);
+    // components run on their thread for the whole profile, so the current mapping holds
+    let threads = THREADS.lock().clone();
+    let components = COMPONENTS.lock().clone();
+    let report = guard
+        .report()
+        .frames_post_processor(move |frames| {
+            if let Some(component) = threads
+                .iter()
+                .find(|(thread, _)| *thread == frames.thread_id)
+                .and_then(|(_, index)| components.get(*index))
+            {
+                frames.thread_name = component.clone();
+            }
+        })
+        .build()?;
+    Ok(report
+        .data
+        .iter()
+        .map(|(frames, samples)| {
+            let stack = std::iter::once(frames.thread_name_or_id())
+                .chain(
+                    frames
+                        .frames
+                        .iter()
+                        .rev()
+                        .flat_map(|frame| frame.iter().rev().map(|symbol| symbol.to_string())),
+                )
+                .join(";");
+            format!("{stack} {samples}\n")
+        })
+        .sorted()
+        .collect())
+}
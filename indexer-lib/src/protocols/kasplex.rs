@@ -0,0 +1,328 @@
+//! Kasplex KRC-20 envelope detection.
+//!
+//! Operations are revealed by spending a P2SH commit output. The redeem script, the last
+//! push of the input's signature script, carries the envelope:
+//! `OP_FALSE OP_IF "kasplex" ([tag] [data])* OP_ENDIF`. The data of tag `OP_0` is the JSON
+//! operation, e.g. `{"p":"krc-20","op":"transfer","tick":"KASP","amt":"100","to":"kaspa:..."}`.
+
+use kaspa_addresses::Address;
+use kaspa_rpc_core::RpcTransaction;
+use std::fmt::{Display, Formatter};
+
+pub const ENVELOPE_MARKER: &[u8] = b"kasplex";
+pub const MAX_TICK_LEN: usize = 6;
+const MIN_TICK_LEN: usize = 4;
+
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_IF: u8 = 0x63;
+const OP_ENDIF: u8 = 0x68;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenOperationKind {
+    Deploy = 0,
+    Mint = 1,
+    Transfer = 2,
+}
+
+impl TryFrom<u8> for TokenOperationKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        match value {
+            x if x == Self::Deploy as u8 => Ok(Self::Deploy),
+            x if x == Self::Mint as u8 => Ok(Self::Mint),
+            x if x == Self::Transfer as u8 => Ok(Self::Transfer),
+            x => anyhow::bail!("Unknown token operation kind: {x}"),
+        }
+    }
+}
+
+/// Why an envelope was not a valid operation. Zero is reserved for valid operations in the
+/// stored value
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// The script ends within a push or before `OP_ENDIF`, or the operation is missing
+    Truncated = 1,
+    InvalidJson = 2,
+    /// Valid JSON of a protocol other than krc-20
+    UnsupportedProtocol = 3,
+    UnknownOperation = 4,
+    InvalidTick = 5,
+    InvalidAmount = 6,
+    InvalidAddress = 7,
+}
+
+impl TryFrom<u8> for EnvelopeError {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        [
+            Self::Truncated,
+            Self::InvalidJson,
+            Self::UnsupportedProtocol,
+            Self::UnknownOperation,
+            Self::InvalidTick,
+            Self::InvalidAmount,
+            Self::InvalidAddress,
+        ]
+        .into_iter()
+        .find(|error| *error as u8 == value)
+        .ok_or_else(|| anyhow::anyhow!("Unknown envelope error: {value}"))
+    }
+}
+
+impl Display for EnvelopeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Truncated => "truncated envelope",
+            Self::InvalidJson => "invalid json",
+            Self::UnsupportedProtocol => "unsupported protocol",
+            Self::UnknownOperation => "unknown operation",
+            Self::InvalidTick => "invalid tick",
+            Self::InvalidAmount => "invalid amount",
+            Self::InvalidAddress => "invalid address",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenOperation {
+    pub kind: TokenOperationKind,
+    /// `max` of a deploy, `amt` of a transfer, none for a mint
+    pub amount: Option<u128>,
+    pub to: Option<Address>,
+}
+
+/// Envelope found in a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// Upper case, empty if the envelope broke before a valid tick was read
+    pub tick: String,
+    pub operation: Result<TokenOperation, EnvelopeError>,
+}
+
+/// First envelope revealed by the inputs of the transaction
+pub fn find_envelope(tx: &RpcTransaction) -> Option<Envelope> {
+    tx.inputs.iter().find_map(|input| {
+        let (pushes, _) = parse_ops(&input.signature_script);
+        match pushes.last()? {
+            Op::Push(redeem_script) => envelope(redeem_script),
+            Op::Code(_) => None,
+        }
+    })
+}
+
+/// Envelope of the redeem script, none if it carries no kasplex marker
+pub fn envelope(redeem_script: &[u8]) -> Option<Envelope> {
+    let (ops, _) = parse_ops(redeem_script);
+    let start = ops.windows(3).position(|w| {
+        matches!(w, [Op::Push([]), Op::Code(OP_IF), Op::Push(marker)] if *marker == ENVELOPE_MARKER)
+    })?;
+    let truncated = || Envelope {
+        tick: String::new(),
+        operation: Err(EnvelopeError::Truncated),
+    };
+    let mut content = None;
+    let mut fields = ops[start + 3..].iter();
+    loop {
+        match fields.next() {
+            Some(Op::Code(OP_ENDIF)) => break,
+            Some(tag) => match (tag, fields.next()) {
+                (Op::Push([]), Some(Op::Push(data))) => content = Some(*data),
+                // metadata tags are not used by krc-20
+                (_, Some(Op::Push(_))) => {}
+                _ => return Some(truncated()),
+            },
+            None => return Some(truncated()),
+        }
+    }
+    let Some(content) = content else {
+        return Some(truncated());
+    };
+    Some(parse_operation(content))
+}
+
+fn parse_operation(content: &[u8]) -> Envelope {
+    let mut tick = String::new();
+    let operation = (|| {
+        let json = serde_json::from_slice::<serde_json::Value>(content)
+            .map_err(|_| EnvelopeError::InvalidJson)?;
+        let object = json.as_object().ok_or(EnvelopeError::InvalidJson)?;
+        let field = |name: &str| object.get(name).and_then(serde_json::Value::as_str);
+        if !field("p").is_some_and(|p| p.eq_ignore_ascii_case("krc-20")) {
+            return Err(EnvelopeError::UnsupportedProtocol);
+        }
+        let kind = match field("op").map(str::to_ascii_lowercase).as_deref() {
+            Some("deploy") => TokenOperationKind::Deploy,
+            Some("mint") => TokenOperationKind::Mint,
+            Some("transfer") => TokenOperationKind::Transfer,
+            _ => return Err(EnvelopeError::UnknownOperation),
+        };
+        tick = field("tick")
+            .filter(|tick| {
+                (MIN_TICK_LEN..=MAX_TICK_LEN).contains(&tick.len())
+                    && tick.bytes().all(|b| b.is_ascii_alphabetic())
+            })
+            .ok_or(EnvelopeError::InvalidTick)?
+            .to_ascii_uppercase();
+        let amount = |name| {
+            field(name)
+                .and_then(|amount| amount.parse::<u128>().ok())
+                .filter(|amount| *amount > 0)
+                .ok_or(EnvelopeError::InvalidAmount)
+        };
+        let amount = match kind {
+            TokenOperationKind::Deploy => Some(amount("max")?),
+            TokenOperationKind::Mint => None,
+            TokenOperationKind::Transfer => Some(amount("amt")?),
+        };
+        let to = field("to")
+            .map(|to| Address::try_from(to).map_err(|_| EnvelopeError::InvalidAddress))
+            .transpose()?;
+        if kind == TokenOperationKind::Transfer && to.is_none() {
+            return Err(EnvelopeError::InvalidAddress);
+        }
+        Ok(TokenOperation { kind, amount, to })
+    })();
+    Envelope { tick, operation }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op<'a> {
+    Push(&'a [u8]),
+    Code(u8),
+}
+
+/// Operations of the script up to the first truncated push, false if there was one
+fn parse_ops(mut script: &[u8]) -> (Vec<Op<'_>>, bool) {
+    let mut ops = Vec::new();
+    while let Some((&opcode, rest)) = script.split_first() {
+        let (len, rest) = match opcode {
+            0x00..OP_PUSHDATA1 => (opcode as usize, rest),
+            OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => {
+                let width = match opcode {
+                    OP_PUSHDATA1 => 1,
+                    OP_PUSHDATA2 => 2,
+                    _ => 4,
+                };
+                if rest.len() < width {
+                    return (ops, false);
+                }
+                let mut len = [0u8; 8];
+                len[..width].copy_from_slice(&rest[..width]);
+                (u64::from_le_bytes(len) as usize, &rest[width..])
+            }
+            _ => {
+                ops.push(Op::Code(opcode));
+                script = rest;
+                continue;
+            }
+        };
+        if rest.len() < len {
+            return (ops, false);
+        }
+        ops.push(Op::Push(&rest[..len]));
+        script = &rest[len..];
+    }
+    (ops, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(script: &mut Vec<u8>, data: &[u8]) {
+        match data.len() {
+            len if len < OP_PUSHDATA1 as usize => script.push(len as u8),
+            len if len <= u8::MAX as usize => script.extend([OP_PUSHDATA1, len as u8]),
+            len => {
+                script.push(OP_PUSHDATA2);
+                script.extend_from_slice(&(len as u16).to_le_bytes());
+            }
+        }
+        script.extend_from_slice(data);
+    }
+
+    fn redeem_script(json: &str) -> Vec<u8> {
+        let mut script = Vec::new();
+        push(&mut script, &[7; 32]);
+        script.push(0xac); // OP_CHECKSIG
+        script.extend([0x00, OP_IF]);
+        push(&mut script, ENVELOPE_MARKER);
+        script.push(0x00);
+        push(&mut script, json.as_bytes());
+        script.push(OP_ENDIF);
+        script
+    }
+
+    #[test]
+    fn test_operations() {
+        let to = Address::new(
+            kaspa_addresses::Prefix::Mainnet,
+            kaspa_addresses::Version::PubKey,
+            &[3; 32],
+        );
+        let transfer = format!(
+            r#"{{"p":"krc-20","op":"transfer","tick":"kasp","amt":"100000000","to":"{to}"}}"#
+        );
+        assert_eq!(
+            envelope(&redeem_script(&transfer)),
+            Some(Envelope {
+                tick: "KASP".to_string(),
+                operation: Ok(TokenOperation {
+                    kind: TokenOperationKind::Transfer,
+                    amount: Some(100_000_000),
+                    to: Some(to),
+                }),
+            })
+        );
+        let mint = envelope(&redeem_script(
+            r#"{"p":"KRC-20","op":"mint","tick":"NACHO"}"#,
+        ))
+        .unwrap();
+        assert_eq!(mint.tick, "NACHO");
+        assert_eq!(mint.operation.unwrap().kind, TokenOperationKind::Mint);
+        // no envelope at all
+        assert_eq!(envelope(&[0x20; 33]), None);
+    }
+
+    #[test]
+    fn test_invalid_envelopes_are_reported() {
+        let error = |script: &[u8]| envelope(script).unwrap().operation.unwrap_err();
+        let valid = redeem_script(r#"{"p":"krc-20","op":"mint","tick":"KASP"}"#);
+        assert_eq!(error(&valid[..valid.len() - 1]), EnvelopeError::Truncated);
+        assert_eq!(error(&valid[..valid.len() - 10]), EnvelopeError::Truncated);
+        assert_eq!(
+            error(&redeem_script("{not json")),
+            EnvelopeError::InvalidJson
+        );
+        assert_eq!(
+            error(&redeem_script(
+                r#"{"p":"brc-20","op":"mint","tick":"KASP"}"#
+            )),
+            EnvelopeError::UnsupportedProtocol
+        );
+        assert_eq!(
+            error(&redeem_script(r#"{"p":"krc-20","op":"mint","tick":"K4"}"#)),
+            EnvelopeError::InvalidTick
+        );
+        let invalid_amount = envelope(&redeem_script(
+            r#"{"p":"krc-20","op":"transfer","tick":"KASP","amt":"-1","to":"x"}"#,
+        ))
+        .unwrap();
+        // the tick was read before the amount
+        assert_eq!(invalid_amount.tick, "KASP");
+        assert_eq!(invalid_amount.operation, Err(EnvelopeError::InvalidAmount));
+        assert_eq!(
+            error(&redeem_script(
+                r#"{"p":"krc-20","op":"transfer","tick":"KASP","amt":"1","to":"x"}"#
+            )),
+            EnvelopeError::InvalidAddress
+        );
+    }
+}
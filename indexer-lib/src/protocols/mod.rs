@@ -0,0 +1,6 @@
+//! Opt-in detection of third party protocols carried in transactions.
+//!
+//! Nothing in here runs unless enabled in the block processor, the base indexer only
+//! needs the messaging protocol.
+
+pub mod kasplex;
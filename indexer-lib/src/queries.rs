@@ -0,0 +1,561 @@
+//! Read paths joining several partitions into one view, each within a single read snapshot.
+//!
+//! [`Queries::get_block_full`] assembles a block from its header, chain index and membership,
+//! merge set, stats, miner and transactions with their acceptance. Data that isn't stored is
+//! `None` rather than empty, a block synced header only has no transactions listed while a
+//! block without transactions lists none.
+//!
+//! [`Queries::stream_address_history`] streams every message of an address, for exports too
+//! large to collect. Records are read on a blocking thread that stays at most
+//! [`EXPORT_BUFFER`] records ahead of the consumer, so a slow consumer holds the store
+//! iterator instead of growing a buffer.
+
+use crate::block_events::MessageKind;
+use crate::database::block_stats::{BlockStats, BlockStatsPartition};
+use crate::database::block_transactions::BlockTransactionsPartition;
+use crate::database::confirmations::Confirmations;
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockRelations, BlockRelationsPartition,
+    ChainIndexByHashPartition, ChainMembershipPartition,
+};
+use crate::database::messages::{
+    AddressPayload, ContextualMessageBySenderPartition, HandshakeByReceiverPartition,
+    HandshakeBySenderPartition, PaymentByReceiverPartition, PaymentBySenderPartition,
+    TxIdToPaymentPartition,
+};
+use crate::database::miners::{BlockMiner, BlockMinerPartition};
+use crate::database::processing::{AcceptanceTxKey, TxIDToAcceptancePartition, TxIdFilter};
+use crate::error::{IndexerError, IndexerResult};
+use anyhow::Result;
+use fjall::{ReadTransaction, TxKeyspace};
+use futures_util::Stream;
+use kaspa_addresses::Prefix;
+use kaspa_consensus_core::BlueWorkType;
+use kaspa_rpc_core::{RpcAddress, RpcHash, RpcTransactionId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// Records an address history stream reads ahead of its consumer
+pub const EXPORT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullBlock {
+    pub hash: RpcHash,
+    pub daa_score: u64,
+    pub blue_work: BlueWorkType,
+    /// Position in the selected chain, none for blocks off the chain
+    pub chain_index: Option<u64>,
+    /// None while the chain membership is not recorded
+    pub is_chain_block: Option<bool>,
+    /// None for blocks indexed without verbose data
+    pub relations: Option<BlockRelations>,
+    /// None for blocks indexed before their stats were recorded
+    pub stats: Option<BlockStats>,
+    pub miner: Option<BlockMiner>,
+    /// In block order, none when the transactions of the block aren't stored
+    pub transactions: Option<Vec<BlockTransaction>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTransaction {
+    pub tx_id: RpcTransactionId,
+    pub acceptance: TxAcceptance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxAcceptance {
+    Accepted {
+        accepting_block_hash: RpcHash,
+        accepting_daa_score: u64,
+    },
+    /// A protocol transaction not accepted yet
+    NotAccepted,
+    /// Acceptance is only recorded for protocol transactions
+    Untracked,
+}
+
+impl From<AcceptanceTxKey> for TxAcceptance {
+    fn from(key: AcceptanceTxKey) -> Self {
+        let accepting_block_hash = RpcHash::from_slice(&key.accepted_by_block_hash);
+        if accepting_block_hash == RpcHash::default() {
+            return TxAcceptance::NotAccepted;
+        }
+        TxAcceptance::Accepted {
+            accepting_block_hash,
+            accepting_daa_score: u64::from_be_bytes(key.accepted_at_daa),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        })
+    }
+}
+
+/// A message sent or received by an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressHistoryRecord {
+    /// Time of the block, stored with the message, so no estimate from the DAA score is needed
+    pub timestamp_ms: u64,
+    pub daa_score: u64,
+    pub block_hash: RpcHash,
+    pub tx_id: RpcTransactionId,
+    pub kind: MessageKind,
+    pub direction: Direction,
+    /// Sompi paid, payments only
+    pub amount: Option<u64>,
+    /// Receiver of what was sent, sender of what was received, empty while unknown
+    pub counterparts: Vec<RpcAddress>,
+    /// See [`Confirmations::get_confirmations`], zero while not accepted, none when unknown
+    pub confirmations: Option<u64>,
+}
+
+/// Records of [`Queries::stream_address_history`], dropping the stream stops the reader
+pub struct AddressHistoryStream {
+    rx: mpsc::Receiver<IndexerResult<AddressHistoryRecord>>,
+    records_read: Arc<AtomicU64>,
+}
+
+impl AddressHistoryStream {
+    /// Records read from the store so far, the ones not consumed yet included
+    pub fn records_read(&self) -> u64 {
+        self.records_read.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for AddressHistoryStream {
+    type Item = IndexerResult<AddressHistoryRecord>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Message of an address as stored in the message partitions
+pub(crate) struct AddressEntry {
+    pub kind: MessageKind,
+    pub direction: Direction,
+    pub tx_id: [u8; 32],
+    pub block_hash: [u8; 32],
+    pub block_time_ms: u64,
+    /// Receiver of what was sent, sender of what was received
+    pub counterpart: Option<AddressPayload>,
+}
+
+impl AddressEntry {
+    fn new(
+        kind: MessageKind,
+        direction: Direction,
+        tx_id: [u8; 32],
+        block_hash: [u8; 32],
+        block_time: [u8; 8],
+        counterpart: AddressPayload,
+    ) -> Self {
+        Self {
+            kind,
+            direction,
+            tx_id,
+            block_hash,
+            block_time_ms: u64::from_be_bytes(block_time),
+            counterpart: (counterpart != AddressPayload::default()).then_some(counterpart),
+        }
+    }
+}
+
+/// Partitions the joined read paths read from
+#[derive(Clone)]
+pub struct Queries {
+    tx_keyspace: TxKeyspace,
+    block_compact_header_partition: BlockCompactHeaderPartition,
+    chain_index_by_hash_partition: ChainIndexByHashPartition,
+    chain_membership_partition: ChainMembershipPartition,
+    block_relations_partition: BlockRelationsPartition,
+    block_stats_partition: BlockStatsPartition,
+    block_miner_partition: BlockMinerPartition,
+    block_transactions_partition: BlockTransactionsPartition,
+    tx_id_to_acceptance_partition: TxIDToAcceptancePartition,
+    confirmations: Confirmations,
+    handshake_by_sender_partition: HandshakeBySenderPartition,
+    handshake_by_receiver_partition: HandshakeByReceiverPartition,
+    payment_by_sender_partition: PaymentBySenderPartition,
+    payment_by_receiver_partition: PaymentByReceiverPartition,
+    contextual_message_partition: ContextualMessageBySenderPartition,
+    tx_id_to_payment_partition: TxIdToPaymentPartition,
+}
+
+impl Queries {
+    /// `block_compact_header_partition` is the one of the processors, sharing its header cache
+    pub fn new(
+        tx_keyspace: &TxKeyspace,
+        block_compact_header_partition: BlockCompactHeaderPartition,
+    ) -> IndexerResult<Self> {
+        Ok(Self {
+            tx_keyspace: tx_keyspace.clone(),
+            block_compact_header_partition,
+            chain_index_by_hash_partition: ChainIndexByHashPartition::new(tx_keyspace)?,
+            chain_membership_partition: ChainMembershipPartition::new(tx_keyspace)?,
+            block_relations_partition: BlockRelationsPartition::new(tx_keyspace)?,
+            block_stats_partition: BlockStatsPartition::new(tx_keyspace)?,
+            block_miner_partition: BlockMinerPartition::new(tx_keyspace)?,
+            block_transactions_partition: BlockTransactionsPartition::new(tx_keyspace)?,
+            tx_id_to_acceptance_partition: TxIDToAcceptancePartition::new(tx_keyspace)?,
+            confirmations: Confirmations::new(tx_keyspace)?,
+            handshake_by_sender_partition: HandshakeBySenderPartition::new(tx_keyspace)?,
+            handshake_by_receiver_partition: HandshakeByReceiverPartition::new(tx_keyspace)?,
+            payment_by_sender_partition: PaymentBySenderPartition::new(tx_keyspace)?,
+            payment_by_receiver_partition: PaymentByReceiverPartition::new(tx_keyspace)?,
+            contextual_message_partition: ContextualMessageBySenderPartition::new(tx_keyspace)?,
+            tx_id_to_payment_partition: TxIdToPaymentPartition::new(tx_keyspace)?,
+        })
+    }
+
+    /// Acceptance lookups of transactions missing the filter skip the store
+    pub fn with_tx_id_filter(mut self, filter: Arc<TxIdFilter>) -> Self {
+        self.tx_id_to_acceptance_partition = self.tx_id_to_acceptance_partition.with_filter(filter);
+        self
+    }
+
+    /// None unless the header of the block is stored
+    pub fn get_block_full(&self, hash: RpcHash) -> IndexerResult<Option<FullBlock>> {
+        self.get_block_full_rtx(&self.tx_keyspace.read_tx(), hash)
+    }
+
+    pub fn get_block_full_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        hash: RpcHash,
+    ) -> IndexerResult<Option<FullBlock>> {
+        let Some(header) = self
+            .block_compact_header_partition
+            .get_compact_header_rtx(rtx, &hash)?
+        else {
+            return Ok(None);
+        };
+        let transactions = self
+            .block_transactions_partition
+            .get_block_transactions_rtx(rtx, hash)?
+            .map(|tx_ids| self.with_acceptance(rtx, tx_ids))
+            .transpose()?;
+        Ok(Some(FullBlock {
+            hash,
+            daa_score: header.daa_score,
+            blue_work: header.blue_work,
+            chain_index: self
+                .chain_index_by_hash_partition
+                .get_chain_index_rtx(rtx, &hash)?,
+            is_chain_block: self
+                .chain_membership_partition
+                .is_on_selected_chain_rtx(rtx, hash)?,
+            relations: self
+                .block_relations_partition
+                .get_block_relations_rtx(rtx, hash)?,
+            stats: self.block_stats_partition.get_block_stats_rtx(rtx, hash)?,
+            miner: self.block_miner_partition.get_block_miner_rtx(rtx, hash)?,
+            transactions,
+        }))
+    }
+
+    fn with_acceptance(
+        &self,
+        rtx: &ReadTransaction,
+        tx_ids: Vec<RpcTransactionId>,
+    ) -> Result<Vec<BlockTransaction>> {
+        let keys = tx_ids
+            .iter()
+            .map(|tx_id| tx_id.as_bytes())
+            .collect::<Vec<_>>();
+        let acceptances = self
+            .tx_id_to_acceptance_partition
+            .get_latest_many_rtx(rtx, &keys)?;
+        Ok(tx_ids
+            .into_iter()
+            .zip(acceptances)
+            .map(|(tx_id, key)| BlockTransaction {
+                tx_id,
+                acceptance: key.map_or(TxAcceptance::Untracked, TxAcceptance::from),
+            })
+            .collect())
+    }
+
+    /// Messages of `address` in blocks of `daa_range`, ordered by block time and read from a
+    /// single snapshot. Messages of blocks whose header was pruned are left out.
+    ///
+    /// Must be called within a Tokio runtime, the store is read on a blocking thread.
+    pub fn stream_address_history(
+        &self,
+        address: &RpcAddress,
+        daa_range: Range<u64>,
+    ) -> IndexerResult<AddressHistoryStream> {
+        let payload = AddressPayload::try_from(address).map_err(IndexerError::validation)?;
+        let prefix = address.prefix;
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER);
+        let records_read = Arc::new(AtomicU64::new(0));
+        let queries = self.clone();
+        let read = records_read.clone();
+        tokio::task::spawn_blocking(move || {
+            let rtx = queries.tx_keyspace.read_tx();
+            for record in queries.address_history_rtx(&rtx, &payload, prefix, daa_range) {
+                read.fetch_add(1, Ordering::Relaxed);
+                let failed = record.is_err();
+                // blocks while the consumer is behind, fails once the stream is dropped
+                if tx
+                    .blocking_send(record.map_err(IndexerError::from))
+                    .is_err()
+                    || failed
+                {
+                    break;
+                }
+            }
+        });
+        Ok(AddressHistoryStream { rx, records_read })
+    }
+
+    fn address_history_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        payload: &'a AddressPayload,
+        prefix: Prefix,
+        daa_range: Range<u64>,
+    ) -> impl Iterator<Item = Result<AddressHistoryRecord>> + 'a {
+        self.address_entries_rtx(rtx, payload)
+            .filter_map(move |entry| {
+                self.history_record_rtx(rtx, entry, prefix, &daa_range)
+                    .transpose()
+            })
+    }
+
+    fn history_record_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        entry: Result<AddressEntry>,
+        prefix: Prefix,
+        daa_range: &Range<u64>,
+    ) -> Result<Option<AddressHistoryRecord>> {
+        let entry = entry?;
+        let block_hash = RpcHash::from_bytes(entry.block_hash);
+        let Some(header) = self
+            .block_compact_header_partition
+            .get_compact_header_rtx(rtx, &block_hash)?
+        else {
+            return Ok(None);
+        };
+        if !daa_range.contains(&header.daa_score) {
+            return Ok(None);
+        }
+        let tx_id = RpcTransactionId::from_bytes(entry.tx_id);
+        let amount = match entry.kind {
+            MessageKind::Payment => self
+                .tx_id_to_payment_partition
+                .get_amount_rtx(rtx, &entry.tx_id)?,
+            MessageKind::Handshake | MessageKind::ContextualMessage => None,
+        };
+        Ok(Some(AddressHistoryRecord {
+            timestamp_ms: entry.block_time_ms,
+            daa_score: header.daa_score,
+            block_hash,
+            tx_id,
+            kind: entry.kind,
+            direction: entry.direction,
+            amount,
+            counterparts: entry
+                .counterpart
+                .and_then(|counterpart| counterpart.to_address(prefix))
+                .into_iter()
+                .collect(),
+            confirmations: self.confirmations.get_confirmations_rtx(rtx, &tx_id)?,
+        }))
+    }
+
+    /// Messages sent or received by `payload`, ordered by block time across the message
+    /// partitions
+    pub(crate) fn address_entries_rtx<'a>(
+        &'a self,
+        rtx: &'a ReadTransaction,
+        payload: &'a AddressPayload,
+    ) -> impl Iterator<Item = Result<AddressEntry>> + 'a {
+        let handshakes_sent = self
+            .handshake_by_sender_partition
+            .get_by_sender_rtx(rtx, payload)
+            .map(|key| {
+                let key = key?;
+                Ok(AddressEntry::new(
+                    MessageKind::Handshake,
+                    Direction::Sent,
+                    key.tx_id,
+                    key.block_hash,
+                    key.block_time,
+                    key.receiver,
+                ))
+            });
+        let handshakes_received = self
+            .handshake_by_receiver_partition
+            .get_by_receiver_rtx(rtx, payload)
+            .map(|entry| {
+                let (key, sender) = entry?;
+                Ok(AddressEntry::new(
+                    MessageKind::Handshake,
+                    Direction::Received,
+                    key.tx_id,
+                    key.block_hash,
+                    key.block_time,
+                    sender,
+                ))
+            });
+        let payments_sent = self
+            .payment_by_sender_partition
+            .get_by_sender_rtx(rtx, payload)
+            .map(|key| {
+                let key = key?;
+                Ok(AddressEntry::new(
+                    MessageKind::Payment,
+                    Direction::Sent,
+                    key.tx_id,
+                    key.block_hash,
+                    key.block_time,
+                    key.receiver,
+                ))
+            });
+        let payments_received = self
+            .payment_by_receiver_partition
+            .get_by_receiver_rtx(rtx, payload)
+            .map(|entry| {
+                let (key, sender) = entry?;
+                Ok(AddressEntry::new(
+                    MessageKind::Payment,
+                    Direction::Received,
+                    key.tx_id,
+                    key.block_hash,
+                    key.block_time,
+                    sender,
+                ))
+            });
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<AddressEntry>> + 'a>> = vec![
+            Box::new(handshakes_sent),
+            Box::new(handshakes_received),
+            Box::new(payments_sent),
+            Box::new(payments_received),
+        ];
+        // contextual messages are ordered by block time within each alias only
+        match self
+            .contextual_message_partition
+            .get_aliases_by_sender_rtx(rtx, payload)
+        {
+            Ok(aliases) => sources.extend(aliases.into_iter().map(|alias| {
+                let messages_sent = self
+                    .contextual_message_partition
+                    .get_by_sender_alias_rtx(rtx, payload, alias)
+                    .map(|entry| {
+                        let (key, _sealed_hex) = entry?;
+                        Ok(AddressEntry::new(
+                            MessageKind::ContextualMessage,
+                            Direction::Sent,
+                            key.tx_id,
+                            key.block_hash,
+                            key.block_time,
+                            AddressPayload::default(),
+                        ))
+                    });
+                Box::new(messages_sent) as Box<dyn Iterator<Item = Result<AddressEntry>> + 'a>
+            })),
+            Err(err) => sources.push(Box::new(std::iter::once(Err(err)))),
+        }
+        // errors come first, ending the merge early
+        itertools::kmerge_by(
+            sources,
+            |a: &Result<AddressEntry>, b: &Result<AddressEntry>| match (a, b) {
+                (Ok(a), Ok(b)) => a.block_time_ms < b.block_time_ms,
+                (Err(_), _) => true,
+                (Ok(_), Err(_)) => false,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::resolution_keys::HandshakeKeyForResolution;
+
+    fn hash(byte: u8) -> RpcHash {
+        RpcHash::from_bytes([byte; 32])
+    }
+
+    #[test]
+    fn test_full_block_tells_missing_from_empty() {
+        let keyspace = crate::database::test_keyspace("queries");
+        let headers = BlockCompactHeaderPartition::new(&keyspace).unwrap();
+        for (byte, daa_score) in [(1, 10), (2, 11)] {
+            headers
+                .insert_compact_header(&hash(byte), BlueWorkType::from_u64(daa_score), daa_score)
+                .unwrap();
+        }
+        let queries = Queries::new(&keyspace, headers).unwrap();
+        // block 1 holds a coinbase, an accepted and a pending handshake, block 2 is header only
+        let mut wtx = keyspace.write_tx().unwrap();
+        BlockTransactionsPartition::new(&keyspace)
+            .unwrap()
+            .insert_wtx(&mut wtx, hash(1), &[hash(0xc0), hash(0xa1), hash(0xa2)]);
+        let acceptance = TxIDToAcceptancePartition::new(&keyspace).unwrap();
+        for (tx_id, accepted) in [([0xa1; 32], true), ([0xa2; 32], false)] {
+            acceptance.insert_handshake_wtx(
+                &mut wtx,
+                tx_id,
+                &HandshakeKeyForResolution {
+                    block_time: 1_000u64.to_be_bytes(),
+                    block_hash: [1; 32],
+                    receiver: Default::default(),
+                    version: 1,
+                    tx_id,
+                    attempt_count: 0,
+                },
+                accepted.then_some(12),
+                accepted.then_some([3; 32]),
+            );
+        }
+        wtx.commit().unwrap().unwrap();
+
+        let block = queries.get_block_full(hash(1)).unwrap().unwrap();
+        assert_eq!(block.daa_score, 10);
+        assert_eq!(block.chain_index, None);
+        assert_eq!(
+            block.transactions.unwrap(),
+            [
+                BlockTransaction {
+                    tx_id: hash(0xc0),
+                    acceptance: TxAcceptance::Untracked,
+                },
+                BlockTransaction {
+                    tx_id: hash(0xa1),
+                    acceptance: TxAcceptance::Accepted {
+                        accepting_block_hash: hash(3),
+                        accepting_daa_score: 12,
+                    },
+                },
+                BlockTransaction {
+                    tx_id: hash(0xa2),
+                    acceptance: TxAcceptance::NotAccepted,
+                },
+            ]
+        );
+        let block = queries.get_block_full(hash(2)).unwrap().unwrap();
+        assert_eq!(block.transactions, None);
+        assert_eq!(queries.get_block_full(hash(9)).unwrap(), None);
+    }
+}
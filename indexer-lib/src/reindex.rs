@@ -0,0 +1,401 @@
+//! Targeted reindex of the data derived from the blocks of a DAA score range.
+//!
+//! Every block the DAA index holds within the range is fetched from the node first. Only then
+//! [`Reindex::delete_wtx`] drops the records of the selected targets and the blocks are written
+//! again by [`force_reprocess_all`](crate::block_processor::BlockProcessor::force_reprocess_all)
+//! all in one transaction, so a range the node can't serve anymore is left as it was.
+
+use crate::config::ChainConfig;
+use crate::database::aggregates::Aggregates;
+use crate::database::block_stats::BlockStatsPartition;
+use crate::database::headers::{BlockGapsPartition, DaaIndexPartition};
+use crate::database::messages::{
+    HandshakeByReceiverPartition, HandshakeBySenderPartition, HandshakeKeyByReceiver,
+    PaymentByReceiverPartition, PaymentBySenderPartition, PaymentKeyByReceiver,
+};
+use crate::database::metadata::MetadataPartition;
+use crate::database::miners::{BlockMiner, BlockMinerPartition, MinerBlocksPartition};
+use crate::database::token_operations::TokenOperationPartition;
+use anyhow::{Result, bail};
+use fjall::{ReadTransaction, TxKeyspace, WriteTransaction};
+use kaspa_rpc_core::RpcHash;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Live margin while finality is off, blocks this close to the block tip may still be
+/// delivered to the live processors
+pub const DEFAULT_LIVE_DAA_MARGIN: u64 = 1_000;
+
+/// DAA scores below the block tip the live processors may still write, the finality depth
+/// when it is configured
+pub fn live_margin(chain: &ChainConfig) -> u64 {
+    chain.finality_depth.unwrap_or(DEFAULT_LIVE_DAA_MARGIN)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReindexTarget {
+    /// Block stats: transaction count, mass and fees
+    Fees,
+    /// Miner attribution and the blocks by miner address
+    Miners,
+    TokenOperations,
+    /// Handshakes and payments by receiving address, their resolved senders are kept
+    AddressIndex,
+}
+
+impl ReindexTarget {
+    pub const ALL: [ReindexTarget; 4] = [
+        Self::Fees,
+        Self::Miners,
+        Self::TokenOperations,
+        Self::AddressIndex,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fees => "fees",
+            Self::Miners => "miners",
+            Self::TokenOperations => "token_operations",
+            Self::AddressIndex => "address_index",
+        }
+    }
+
+    /// Comma separated target names
+    pub fn parse_list(targets: &str) -> Result<Vec<Self>> {
+        let mut parsed = targets
+            .split(',')
+            .map(str::trim)
+            .filter(|target| !target.is_empty())
+            .map(Self::from_str)
+            .collect::<Result<Vec<_>>>()?;
+        parsed.sort();
+        parsed.dedup();
+        if parsed.is_empty() {
+            bail!("No reindex target given");
+        }
+        Ok(parsed)
+    }
+}
+
+impl FromStr for ReindexTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(target: &str) -> Result<Self> {
+        match Self::ALL.into_iter().find(|known| known.name() == target) {
+            Some(target) => Ok(target),
+            None => bail!(
+                "Unknown reindex target {target}, expected one of {}",
+                Self::ALL.map(|target| target.name()).join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ReindexTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Records per target
+pub type RecordCounts = BTreeMap<ReindexTarget, usize>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReindexSummary {
+    pub daa_range: Range<u64>,
+    pub blocks: usize,
+    pub deleted: RecordCounts,
+    pub rewritten: RecordCounts,
+}
+
+impl fmt::Display for ReindexSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Reindexed {} blocks of DAA {}..{}",
+            self.blocks, self.daa_range.start, self.daa_range.end
+        )?;
+        for (target, deleted) in &self.deleted {
+            writeln!(
+                f,
+                "  {target}: {deleted} deleted, {} rewritten",
+                self.rewritten.get(target).copied().unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Deletes and counts the derived records of a DAA score range
+#[derive(Clone)]
+pub struct Reindex {
+    keyspace: TxKeyspace,
+    metadata_partition: MetadataPartition,
+    block_gaps_partition: BlockGapsPartition,
+    daa_index_partition: DaaIndexPartition,
+    block_stats_partition: BlockStatsPartition,
+    block_miner_partition: BlockMinerPartition,
+    miner_blocks_partition: MinerBlocksPartition,
+    token_operation_partition: TokenOperationPartition,
+    handshake_by_sender_partition: HandshakeBySenderPartition,
+    handshake_by_receiver_partition: HandshakeByReceiverPartition,
+    payment_by_sender_partition: PaymentBySenderPartition,
+    payment_by_receiver_partition: PaymentByReceiverPartition,
+    /// Set when the database keeps aggregates, deleted fees are taken out of them
+    aggregates: Option<Aggregates>,
+}
+
+impl Reindex {
+    pub fn new(keyspace: &TxKeyspace) -> Result<Self> {
+        let metadata_partition = MetadataPartition::new(keyspace)?;
+        let aggregates = metadata_partition
+            .get_aggregate_bucket_width()?
+            .map(|bucket_width| Aggregates::new(keyspace, bucket_width))
+            .transpose()?;
+        Ok(Self {
+            keyspace: keyspace.clone(),
+            metadata_partition,
+            block_gaps_partition: BlockGapsPartition::new(keyspace)?,
+            daa_index_partition: DaaIndexPartition::new(keyspace)?,
+            block_stats_partition: BlockStatsPartition::new(keyspace)?,
+            block_miner_partition: BlockMinerPartition::new(keyspace)?,
+            miner_blocks_partition: MinerBlocksPartition::new(keyspace)?,
+            token_operation_partition: TokenOperationPartition::new(keyspace)?,
+            handshake_by_sender_partition: HandshakeBySenderPartition::new(keyspace)?,
+            handshake_by_receiver_partition: HandshakeByReceiverPartition::new(keyspace)?,
+            payment_by_sender_partition: PaymentBySenderPartition::new(keyspace)?,
+            payment_by_receiver_partition: PaymentByReceiverPartition::new(keyspace)?,
+            aggregates,
+        })
+    }
+
+    /// Fails for a range the live processors may still write, within `live_margin` of the
+    /// block tip or overlapping a pending block gap
+    pub fn check_range(&self, daa_range: &Range<u64>, live_margin: u64) -> Result<()> {
+        if daa_range.is_empty() {
+            bail!("Empty DAA range {daa_range:?}");
+        }
+        let rtx = self.keyspace.read_tx();
+        let Some(tip) = self.metadata_partition.get_latest_block_cursor_rtx(&rtx)? else {
+            bail!("No block is indexed yet");
+        };
+        let live_from = tip.daa_score.saturating_sub(live_margin);
+        if daa_range.end > live_from {
+            bail!(
+                "DAA range {daa_range:?} refused: it ends past DAA {live_from}, the blocks within {live_margin} (chain.finality_depth, {DEFAULT_LIVE_DAA_MARGIN} when unset) of the block tip {} may still be written by the live block processor. End the range at {live_from} or pass --force",
+                tip.daa_score
+            );
+        }
+        for gap in self.block_gaps_partition.get_all_gaps_rtx(&rtx) {
+            let gap = gap?;
+            if gap.from_daa_score < daa_range.end && daa_range.start <= gap.to_daa_score {
+                bail!(
+                    "DAA range {daa_range:?} overlaps the pending block gap {}..={}",
+                    gap.from_daa_score,
+                    gap.to_daa_score
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Indexed blocks of the range, by DAA score
+    pub fn blocks(&self, daa_range: &Range<u64>) -> Result<Vec<(u64, RpcHash)>> {
+        let rtx = self.keyspace.read_tx();
+        self.daa_index_partition
+            .iter_range_rtx(&rtx, daa_range.clone())
+            .collect()
+    }
+
+    /// Deletes the records of `targets` for `blocks`, the blocks of the range
+    pub fn delete_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        daa_range: &Range<u64>,
+        blocks: &[(u64, RpcHash)],
+        targets: &[ReindexTarget],
+    ) -> Result<RecordCounts> {
+        let rtx = self.keyspace.read_tx();
+        let mut deleted = RecordCounts::new();
+        for target in targets {
+            let count = match target {
+                ReindexTarget::Fees => {
+                    let mut count = 0;
+                    for (daa_score, hash) in blocks {
+                        if let Some(stats) = self
+                            .block_stats_partition
+                            .get_block_stats_rtx(&rtx, *hash)?
+                        {
+                            self.block_stats_partition.remove_wtx(wtx, *hash);
+                            // added back when the block is reprocessed
+                            if let Some(aggregates) = &self.aggregates {
+                                aggregates.replace_fees_wtx(
+                                    wtx,
+                                    *daa_score,
+                                    stats.total_fees,
+                                    0,
+                                )?;
+                            }
+                            count += 1;
+                        }
+                    }
+                    count
+                }
+                ReindexTarget::Miners => {
+                    let mut count = 0;
+                    for (daa_score, hash) in blocks {
+                        match self.block_miner_partition.take_wtx(wtx, *hash)? {
+                            Some(BlockMiner::Parsed { address, .. }) => {
+                                self.miner_blocks_partition
+                                    .remove_wtx(wtx, &address, *daa_score, *hash)?;
+                                count += 2;
+                            }
+                            Some(BlockMiner::ParseFailed) => count += 1,
+                            None => {}
+                        }
+                    }
+                    count
+                }
+                ReindexTarget::TokenOperations => self
+                    .token_operation_partition
+                    .remove_daa_range_wtx(wtx, daa_range.clone())?,
+                ReindexTarget::AddressIndex => {
+                    let (handshakes, payments) = self.address_entries_rtx(&rtx, blocks)?;
+                    for key in &handshakes {
+                        self.handshake_by_receiver_partition.remove_wtx(wtx, key);
+                    }
+                    for key in &payments {
+                        self.payment_by_receiver_partition.remove_wtx(wtx, key);
+                    }
+                    handshakes.len() + payments.len()
+                }
+            };
+            deleted.insert(*target, count);
+        }
+        Ok(deleted)
+    }
+
+    /// Gives the address entries the blocks were written again with back their resolved
+    /// senders, reprocessing writes them unresolved
+    pub fn restore_senders_wtx(
+        &self,
+        wtx: &mut WriteTransaction,
+        blocks: &[(u64, RpcHash)],
+        targets: &[ReindexTarget],
+    ) -> Result<()> {
+        if !targets.contains(&ReindexTarget::AddressIndex) {
+            return Ok(());
+        }
+        let rtx = self.keyspace.read_tx();
+        let hashes = block_hashes(blocks);
+        for key in self.handshake_by_sender_partition.iter_rtx(&rtx) {
+            let key = key?;
+            if hashes.contains(&key.block_hash) {
+                let entry = HandshakeKeyByReceiver {
+                    receiver: key.receiver,
+                    block_time: key.block_time,
+                    block_hash: key.block_hash,
+                    version: key.version,
+                    tx_id: key.tx_id,
+                };
+                self.handshake_by_receiver_partition
+                    .insert_wtx(wtx, &entry, Some(key.sender));
+            }
+        }
+        for key in self.payment_by_sender_partition.iter_rtx(&rtx) {
+            let key = key?;
+            if hashes.contains(&key.block_hash) {
+                let entry = PaymentKeyByReceiver {
+                    receiver: key.receiver,
+                    block_time: key.block_time,
+                    block_hash: key.block_hash,
+                    version: key.version,
+                    tx_id: key.tx_id,
+                };
+                self.payment_by_receiver_partition
+                    .insert_wtx(wtx, &entry, Some(key.sender));
+            }
+        }
+        Ok(())
+    }
+
+    /// Handshakes and payments by receiver of the blocks. The partitions are keyed by address,
+    /// so they are walked whole
+    fn address_entries_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        blocks: &[(u64, RpcHash)],
+    ) -> Result<(Vec<HandshakeKeyByReceiver>, Vec<PaymentKeyByReceiver>)> {
+        let hashes = block_hashes(blocks);
+        let mut handshakes = Vec::new();
+        for entry in self.handshake_by_receiver_partition.iter_rtx(rtx) {
+            let (key, _) = entry?;
+            if hashes.contains(&key.block_hash) {
+                handshakes.push(key);
+            }
+        }
+        let mut payments = Vec::new();
+        for entry in self.payment_by_receiver_partition.iter_rtx(rtx) {
+            let (key, _) = entry?;
+            if hashes.contains(&key.block_hash) {
+                payments.push(key);
+            }
+        }
+        Ok((handshakes, payments))
+    }
+
+    /// Records of `targets` the blocks of the range have
+    pub fn count(&self, daa_range: &Range<u64>, targets: &[ReindexTarget]) -> Result<RecordCounts> {
+        let rtx = self.keyspace.read_tx();
+        let blocks = self.blocks(daa_range)?;
+        targets
+            .iter()
+            .map(|target| Ok((*target, self.count_rtx(&rtx, daa_range, &blocks, *target)?)))
+            .collect()
+    }
+
+    fn count_rtx(
+        &self,
+        rtx: &ReadTransaction,
+        daa_range: &Range<u64>,
+        blocks: &[(u64, RpcHash)],
+        target: ReindexTarget,
+    ) -> Result<usize> {
+        let mut count = 0;
+        match target {
+            ReindexTarget::Fees => {
+                for (_, hash) in blocks {
+                    count += self
+                        .block_stats_partition
+                        .get_block_stats_rtx(rtx, *hash)?
+                        .is_some() as usize;
+                }
+            }
+            ReindexTarget::Miners => {
+                for (_, hash) in blocks {
+                    count += match self.block_miner_partition.get_block_miner_rtx(rtx, *hash)? {
+                        Some(BlockMiner::Parsed { .. }) => 2,
+                        Some(BlockMiner::ParseFailed) => 1,
+                        None => 0,
+                    };
+                }
+            }
+            ReindexTarget::TokenOperations => {
+                count = self
+                    .token_operation_partition
+                    .count_daa_range_rtx(rtx, daa_range.clone())?;
+            }
+            ReindexTarget::AddressIndex => {
+                let (handshakes, payments) = self.address_entries_rtx(rtx, blocks)?;
+                count = handshakes.len() + payments.len();
+            }
+        }
+        Ok(count)
+    }
+}
+
+fn block_hashes(blocks: &[(u64, RpcHash)]) -> HashSet<[u8; 32]> {
+    blocks.iter().map(|(_, hash)| hash.as_bytes()).collect()
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_REORDER_WINDOW: Duration = Duration::from_millis(200);
+pub const DEFAULT_REORDER_CAPACITY: usize = 256;
+
+/// Holds items for a short window and releases them sorted by key.
+///
+/// An item is released once it is the smallest one held and either its window passed or
+/// the buffer is over capacity. Items sorting before one already released can't be put in
+/// order anymore, they are returned right away and flagged late.
+pub struct ReorderBuffer<K, T> {
+    window: Duration,
+    capacity: usize,
+    pending: BTreeMap<K, (Instant, T)>,
+    /// Highest key released so far
+    released: Option<K>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Released<T> {
+    pub item: T,
+    /// Sorts before an item released earlier
+    pub late: bool,
+}
+
+impl<K: Ord + Copy, T> ReorderBuffer<K, T> {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            pending: BTreeMap::new(),
+            released: None,
+        }
+    }
+
+    /// Buffers the item, a late one is returned instead
+    pub fn push(&mut self, key: K, item: T, now: Instant) -> Option<Released<T>> {
+        if self.released.is_some_and(|released| key < released) {
+            return Some(Released { item, late: true });
+        }
+        self.pending.insert(key, (now, item));
+        None
+    }
+
+    /// Items due at `now`, in key order
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<Released<T>> {
+        let mut ready = Vec::new();
+        while let Some(entry) = self.pending.first_entry() {
+            let (arrived, _) = entry.get();
+            if self.pending.len() <= self.capacity && now.duration_since(*arrived) < self.window {
+                break;
+            }
+            let (key, (_, item)) = entry.remove_entry();
+            self.released = Some(key);
+            ready.push(Released { item, late: false });
+        }
+        ready
+    }
+
+    /// When the next item becomes due
+    pub fn next_release(&self) -> Option<Instant> {
+        self.pending
+            .first_key_value()
+            .map(|(_, (arrived, _))| *arrived + self.window)
+    }
+
+    /// Releases everything held, in key order
+    pub fn flush(&mut self) -> Vec<Released<T>> {
+        let pending = std::mem::take(&mut self.pending);
+        if let Some(last) = pending.last_key_value().map(|(key, _)| *key) {
+            self.released = Some(last);
+        }
+        pending
+            .into_values()
+            .map(|(_, item)| Released { item, late: false })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(released: Vec<Released<u64>>) -> Vec<u64> {
+        released.into_iter().map(|r| r.item).collect()
+    }
+
+    #[test]
+    fn test_shuffled_input_is_released_in_order() {
+        let start = Instant::now();
+        let mut buffer = ReorderBuffer::new(Duration::from_millis(200), 1000);
+        // deterministic shuffle of 0..500
+        for (i, key) in (0..500u64).map(|i| (i, i * 7919 % 500)).collect::<Vec<_>>() {
+            let arrived = start + Duration::from_micros(i);
+            assert_eq!(buffer.push(key, key, arrived), None);
+        }
+        assert!(buffer.pop_ready(start).is_empty());
+        assert_eq!(
+            buffer.next_release(),
+            Some(start + Duration::from_micros(0) + Duration::from_millis(200))
+        );
+
+        let released = buffer.pop_ready(start + Duration::from_secs(1));
+        assert!(released.iter().all(|r| !r.late));
+        assert_eq!(items(released), (0..500).collect::<Vec<_>>());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_capacity_forces_release() {
+        let now = Instant::now();
+        let mut buffer = ReorderBuffer::new(Duration::from_secs(60), 2);
+        for key in [3, 1, 2] {
+            buffer.push(key, key, now);
+        }
+        assert_eq!(items(buffer.pop_ready(now)), vec![1]);
+        assert_eq!(buffer.len(), 2);
+    }
+
+    #[test]
+    fn test_late_item_is_forwarded_flagged() {
+        let now = Instant::now();
+        let mut buffer = ReorderBuffer::new(Duration::ZERO, 10);
+        buffer.push(10, 10, now);
+        buffer.push(20, 20, now);
+        assert_eq!(items(buffer.pop_ready(now)), vec![10, 20]);
+
+        // behind the released tip: not dropped, flagged late
+        assert_eq!(
+            buffer.push(15, 15, now),
+            Some(Released {
+                item: 15,
+                late: true
+            })
+        );
+        assert_eq!(buffer.push(25, 25, now), None);
+        assert_eq!(items(buffer.flush()), vec![25]);
+    }
+}
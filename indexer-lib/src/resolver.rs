@@ -1,17 +1,12 @@
-use crate::APP_IS_RUNNING;
 use crate::fifo_set::FifoSet;
+use crate::node_pool::{NodePool, PooledClient};
+use crate::shutdown::Shutdown;
 use anyhow::bail;
-use kaspa_rpc_core::api::ops::RpcApiOps;
-use kaspa_rpc_core::prelude::*;
-use kaspa_rpc_core::{
-    GetBlockRequest, GetBlockResponse, RpcAddress, RpcBlock, RpcHash, RpcHeader, RpcTransactionId,
-};
-use kaspa_wrpc_client::KaspaRpcClient;
+use kaspa_rpc_core::{RpcAddress, RpcBlock, RpcHash, RpcHeader, RpcTransactionId};
 use std::sync::Arc;
 use std::sync::atomic::AtomicU64;
 use tracing::{debug, error, info};
 use workflow_core::channel::{Receiver, Sender};
-use workflow_serializer::serializer::Serializable;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SenderByTxIdAndDaa {
@@ -26,10 +21,10 @@ pub enum ResolverResponse {
 }
 
 pub struct Resolver {
-    shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+    shutdown: Shutdown,
     block_request_rx: Receiver<RpcHash>,
     sender_request_rx: Receiver<SenderByTxIdAndDaa>,
-    kaspa_rpc_client: KaspaRpcClient,
+    nodes: NodePool,
     response_tx: Sender<crate::periodic_processor::Notification>,
     block_requests_cache: FifoSet<RpcHash>,
     sender_request_cache: FifoSet<SenderByTxIdAndDaa>,
@@ -38,11 +33,11 @@ pub struct Resolver {
 
 impl Resolver {
     pub fn new(
-        shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+        shutdown: Shutdown,
         block_request_rx: Receiver<RpcHash>,
         sender_request_rx: Receiver<SenderByTxIdAndDaa>,
         response_tx: Sender<crate::periodic_processor::Notification>,
-        kaspa_rpc_client: KaspaRpcClient,
+        nodes: NodePool,
         requests_in_progress: Arc<AtomicU64>,
     ) -> Self {
         Self {
@@ -64,15 +59,25 @@ impl Resolver {
                     256 * 300
                 },
             ),
-            shutdown_rx,
+            shutdown,
             block_request_rx,
             sender_request_rx,
-            kaspa_rpc_client,
+            nodes,
             response_tx,
             requests_in_progress,
         }
     }
 
+    /// Healthiest node, none while no node is usable
+    pub fn get_client(&self) -> Option<PooledClient> {
+        self.nodes.get_client()
+    }
+
+    /// Healthiest node apart from `urls`
+    pub fn get_client_excluding(&self, urls: &[Arc<str>]) -> Option<PooledClient> {
+        self.nodes.get_client_excluding(urls)
+    }
+
     pub async fn process(&mut self) -> anyhow::Result<()> {
         info!("Resolver started");
         loop {
@@ -86,7 +91,7 @@ impl Resolver {
                     } else {
                         debug!(%hash, "Received block resolution request");
                     }
-                    match get_block_with_retries(&self.kaspa_rpc_client, hash).await {
+                    match get_block_with_retries(&self.nodes, &self.shutdown, hash).await {
                         Ok(block) => {
                             self.response_tx
                                 .send(crate::periodic_processor::Notification::ResolverResponse(
@@ -117,7 +122,8 @@ impl Resolver {
                         debug!(%tx_id, %daa_score, "Received sender resolution request");
                     }
                     match get_utxo_return_address_with_retries(
-                        &self.kaspa_rpc_client,
+                        &self.nodes,
+                        &self.shutdown,
                         tx_id,
                         daa_score,
                     )
@@ -168,7 +174,7 @@ impl Resolver {
     async fn select_input(&mut self) -> anyhow::Result<Input> {
         tokio::select! {
             biased;
-            _ = &mut self.shutdown_rx => {
+            _ = self.shutdown.cancelled() => {
                 Ok(Input::Shutdown)
             }
             req = self.sender_request_rx.recv() => {
@@ -188,31 +194,45 @@ enum Input {
     SenderRequest(SenderByTxIdAndDaa),
 }
 
+/// Next node to send a request to, nodes which failed it are tried again once every usable
+/// node failed
+async fn next_node(nodes: &NodePool, failed: &mut Vec<Arc<str>>) -> Option<PooledClient> {
+    let node = nodes.get_client_excluding(failed).or_else(|| {
+        failed.clear();
+        nodes.get_client()
+    });
+    match node {
+        Some(node) if node.client.is_connected() => Some(node),
+        Some(node) => {
+            failed.push(node.url);
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            None
+        }
+        None => {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            None
+        }
+    }
+}
+
 async fn get_block_with_retries(
-    client: &KaspaRpcClient,
+    nodes: &NodePool,
+    shutdown: &Shutdown,
     rpc_hash: RpcHash,
 ) -> anyhow::Result<RpcBlock> {
+    let mut failed = Vec::new();
     loop {
-        if !APP_IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
-            bail!("App is stopped");
+        if shutdown.is_cancelled() {
+            bail!("Resolver is stopped");
         }
-        if !client.is_connected() {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let Some(PooledClient { url, client }) = next_node(nodes, &mut failed).await else {
             continue;
-        }
-        match client
-            .rpc_client()
-            .call(
-                RpcApiOps::GetBlock,
-                Serializable(GetBlockRequest::new(rpc_hash, false)),
-            )
-            .await
-        {
-            Ok(Serializable(GetBlockResponse { block })) => return Ok(block),
-            Err(
-                workflow_rpc::client::error::Error::Disconnect
-                | workflow_rpc::client::error::Error::Timeout,
-            ) => {
+        };
+        match client.get_block(rpc_hash, false).await {
+            Ok(block) => return Ok(block),
+            Err(err) if err.is_transient() => {
+                nodes.record_failover(&url);
+                failed.push(url);
                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 continue;
             }
@@ -222,36 +242,27 @@ async fn get_block_with_retries(
 }
 
 async fn get_utxo_return_address_with_retries(
-    client: &KaspaRpcClient,
+    nodes: &NodePool,
+    shutdown: &Shutdown,
     txid: RpcHash,
     accepting_block_daa_score: u64,
 ) -> anyhow::Result<RpcAddress> {
+    let mut failed = Vec::new();
     loop {
-        if !APP_IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
-            bail!("App is stopped");
+        if shutdown.is_cancelled() {
+            bail!("Resolver is stopped");
         }
-        if !client.is_connected() {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let Some(PooledClient { url, client }) = next_node(nodes, &mut failed).await else {
             continue;
-        }
+        };
         match client
-            .rpc_client()
-            .call(
-                RpcApiOps::GetUtxoReturnAddress,
-                Serializable(GetUtxoReturnAddressRequest::new(
-                    txid,
-                    accepting_block_daa_score,
-                )),
-            )
+            .get_utxo_return_address(txid, accepting_block_daa_score)
             .await
         {
-            Ok(Serializable(GetUtxoReturnAddressResponse { return_address })) => {
-                return Ok(return_address);
-            }
-            Err(
-                workflow_rpc::client::error::Error::Disconnect
-                | workflow_rpc::client::error::Error::Timeout,
-            ) => {
+            Ok(return_address) => return Ok(return_address),
+            Err(err) if err.is_transient() => {
+                nodes.record_failover(&url);
+                failed.push(url);
                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 continue;
             }
@@ -0,0 +1,211 @@
+use crate::acceptance_slo::SharedAcceptanceSlo;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::oneshot;
+
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 2;
+
+/// Fair slot dispatcher for workers sharing one RPC connection.
+///
+/// Every worker waits in its own queue, free slots are handed out round-robin
+/// across workers so a streaming worker can't starve the others.
+/// The amount of simultaneously issued calls is capped by `max_in_flight`.
+/// With an acceptance SLO attached, no slot is handed out while the SLO is breached.
+#[derive(Clone)]
+pub struct RpcDispatcher {
+    state: Arc<Mutex<DispatcherState>>,
+    next_worker_id: Arc<AtomicU64>,
+    acceptance_slo: Option<SharedAcceptanceSlo>,
+}
+
+struct DispatcherState {
+    max_in_flight: usize,
+    in_flight: usize,
+    last_served: u64,
+    queues: BTreeMap<u64, VecDeque<oneshot::Sender<()>>>,
+}
+
+impl Default for RpcDispatcher {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IN_FLIGHT)
+    }
+}
+
+impl RpcDispatcher {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(DispatcherState {
+                max_in_flight: max_in_flight.max(1),
+                in_flight: 0,
+                last_served: 0,
+                queues: BTreeMap::new(),
+            })),
+            next_worker_id: Arc::new(AtomicU64::new(0)),
+            acceptance_slo: None,
+        }
+    }
+
+    /// Pauses dispatching while acceptance commits are slower than the SLO allows
+    pub fn with_acceptance_slo(mut self, acceptance_slo: SharedAcceptanceSlo) -> Self {
+        self.acceptance_slo = Some(acceptance_slo);
+        self
+    }
+
+    /// Returns an id identifying the caller's queue
+    pub fn register_worker(&self) -> u64 {
+        self.next_worker_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().in_flight
+    }
+
+    /// Waits for a free slot, the slot is given back once the guard is dropped.
+    /// Cancel safe: a slot handed to a dropped waiter is passed on to the next one
+    pub async fn acquire(&self, worker_id: u64) -> SlotGuard {
+        if let Some(slo) = &self.acceptance_slo {
+            slo.wait_until_clear().await;
+        }
+        let rx = {
+            let mut state = self.state.lock();
+            if state.in_flight < state.max_in_flight && state.queues.is_empty() {
+                state.in_flight += 1;
+                state.last_served = worker_id;
+                return SlotGuard {
+                    state: self.state.clone(),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            state.queues.entry(worker_id).or_default().push_back(tx);
+            rx
+        };
+        let mut waiter = Waiter {
+            rx: Some(rx),
+            state: self.state.clone(),
+        };
+        // sender is never dropped without sending while the dispatcher is alive
+        _ = waiter.rx.as_mut().unwrap().await;
+        waiter.rx = None;
+        SlotGuard {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl DispatcherState {
+    /// Hands the released slot to the next waiting worker after the last served one
+    fn release(&mut self) {
+        loop {
+            let next = self
+                .queues
+                .range((Bound::Excluded(self.last_served), Bound::Unbounded))
+                .chain(self.queues.range(..=self.last_served))
+                .map(|(id, _)| *id)
+                .next();
+            let Some(worker_id) = next else {
+                self.in_flight -= 1;
+                return;
+            };
+            let queue = self.queues.get_mut(&worker_id).unwrap();
+            let tx = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                self.queues.remove(&worker_id);
+            }
+            self.last_served = worker_id;
+            if tx.send(()).is_ok() {
+                return;
+            }
+        }
+    }
+}
+
+pub struct SlotGuard {
+    state: Arc<Mutex<DispatcherState>>,
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        self.state.lock().release();
+    }
+}
+
+struct Waiter {
+    rx: Option<oneshot::Receiver<()>>,
+    state: Arc<Mutex<DispatcherState>>,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.state.lock().release();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_round_robin_between_workers() {
+        let dispatcher = RpcDispatcher::new(1);
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let workers = (0..3)
+            .map(|_| {
+                let dispatcher = dispatcher.clone();
+                let served = served.clone();
+                let worker_id = dispatcher.register_worker();
+                tokio::spawn(async move {
+                    for _ in 0..10 {
+                        let _slot = dispatcher.acquire(worker_id).await;
+                        served.lock().push(worker_id);
+                        // slow endpoint
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.await.unwrap();
+        }
+
+        let served = served.lock();
+        assert_eq!(served.len(), 30);
+        // every prefix is balanced, no worker runs ahead while others wait
+        let mut counts = [0i32; 3];
+        for (i, worker_id) in served.iter().enumerate() {
+            counts[*worker_id as usize] += 1;
+            if i >= 3 {
+                let max = counts.iter().max().unwrap();
+                let min = counts.iter().min().unwrap();
+                assert!(max - min <= 1, "unfair order: {served:?}");
+            }
+        }
+        assert_eq!(dispatcher.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_passes_slot_on() {
+        let dispatcher = RpcDispatcher::new(1);
+        let slot = dispatcher.acquire(0).await;
+        let waiting = tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move { dispatcher.acquire(1).await }
+        });
+        tokio::task::yield_now().await;
+        waiting.abort();
+        _ = waiting.await;
+        drop(slot);
+
+        assert_eq!(dispatcher.in_flight(), 0);
+        let _slot = dispatcher.acquire(2).await;
+        assert_eq!(dispatcher.in_flight(), 1);
+    }
+}
@@ -0,0 +1,600 @@
+//! Node connections over wRPC or gRPC.
+//!
+//! The resolver nodes, the historical syncers and the selected chain syncer fetch through an
+//! [`RpcNode`] and don't depend on the transport. Call failures are normalized into
+//! [`TransportError`], so retries treat a dropped gRPC stream like a wRPC disconnect.
+//! Failures are classified by [`TransportError::class`] from a table of messages observed
+//! from nodes, the retry loops only retry [`ErrorClass::Transient`] ones.
+//! Notifications go through the [`RpcApi`] listener interface both clients implement, the
+//! subscriber still needs wRPC since its reconnect handling follows the wRPC connection state.
+//! A node given a [`CallLimiter`] holds one of its permits per call and refuses calls while its
+//! circuit breaker is open. A node given a [`FixtureRecorder`] records its calls, one created
+//! from a [`FixtureRpcClient`] replays them, see [`crate::fixture`].
+
+use crate::call_limiter::{BreakerState, CallLimiter};
+use crate::fixture::{FixtureCall, FixtureRecorder, FixtureRpcClient, FixtureValue};
+use kaspa_grpc_client::GrpcClient;
+use kaspa_rpc_core::api::ops::RpcApiOps;
+use kaspa_rpc_core::api::rpc::{DynRpcApi, RpcApi};
+use kaspa_rpc_core::{
+    GetBlockDagInfoRequest, GetBlockDagInfoResponse, GetBlockRequest, GetBlockResponse,
+    GetBlocksRequest, GetBlocksResponse, GetServerInfoRequest, GetServerInfoResponse,
+    GetUtxoReturnAddressRequest, GetUtxoReturnAddressResponse, GetVirtualChainFromBlockRequest,
+    GetVirtualChainFromBlockResponse, RpcAddress, RpcBlock, RpcError, RpcHash,
+};
+use kaspa_wrpc_client::KaspaRpcClient;
+use kaspa_wrpc_client::client::{ConnectOptions, ConnectStrategy};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use workflow_serializer::prelude::Serializable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Wrpc,
+    Grpc,
+    /// Replayed from a recorded fixture
+    Fixture,
+}
+
+impl Transport {
+    /// gRPC for `grpc://` urls, wRPC otherwise
+    pub fn from_url(url: &str) -> Self {
+        if url.starts_with("grpc://") {
+            Self::Grpc
+        } else {
+            Self::Wrpc
+        }
+    }
+}
+
+/// Retry backoff of transient failures, doubling up to [`MAX_RETRY_BACKOFF`]
+pub const INITIAL_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How a failed call is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Retried with backoff, the same call is expected to succeed later
+    Transient,
+    /// The node doesn't know the requested block, it may have been pruned
+    NotFound,
+    /// Retrying won't help
+    Fatal,
+}
+
+/// Messages of failed calls by class, matched case-insensitively as substrings in order.
+/// Messages matching none are [`ErrorClass::Fatal`]
+const ERROR_CLASSES: &[(&str, ErrorClass)] = &[
+    ("not found", ErrorClass::NotFound),
+    ("cannot find", ErrorClass::NotFound),
+    ("is not in the dag", ErrorClass::NotFound),
+    ("unknown block", ErrorClass::NotFound),
+    ("timeout", ErrorClass::Transient),
+    ("timed out", ErrorClass::Transient),
+    // the node limits concurrent calls per route
+    ("route capacity", ErrorClass::Transient),
+    ("server is busy", ErrorClass::Transient),
+    // partial responses while the node restarts
+    ("deserializ", ErrorClass::Transient),
+    ("unexpected end of", ErrorClass::Transient),
+    ("channel closed", ErrorClass::Transient),
+    ("connection reset", ErrorClass::Transient),
+    ("connection refused", ErrorClass::Transient),
+    ("broken pipe", ErrorClass::Transient),
+    ("is not synced", ErrorClass::Transient),
+];
+
+fn classify_message(message: &str) -> ErrorClass {
+    let message = message.to_lowercase();
+    ERROR_CLASSES
+        .iter()
+        .find(|(pattern, _)| message.contains(pattern))
+        .map_or(ErrorClass::Fatal, |(_, class)| *class)
+}
+
+/// Backoff between retries of transient failures
+#[derive(Debug, Clone)]
+pub struct RetryBackoff(Duration);
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        Self(INITIAL_RETRY_BACKOFF)
+    }
+}
+
+impl RetryBackoff {
+    /// Delay before the next retry, doubled for the one after
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.0;
+        self.0 = (self.0 * 2).min(MAX_RETRY_BACKOFF);
+        delay
+    }
+}
+
+/// Failure of a call, the same for both transports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransportError {
+    Disconnected,
+    Timeout,
+    /// Refused without calling the node while its circuit breaker is open
+    CircuitOpen,
+    /// Rejected by the node or failed otherwise, classified by its message
+    Rpc(String),
+}
+
+impl TransportError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Disconnected | Self::Timeout | Self::CircuitOpen => ErrorClass::Transient,
+            Self::Rpc(message) => classify_message(message),
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        self.class() == ErrorClass::Transient
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.class() == ErrorClass::NotFound
+    }
+
+    /// Whether the error, as returned by the retry loops, is a [`ErrorClass::NotFound`] one,
+    /// also when wrapped by an [`IndexerError`](crate::error::IndexerError)
+    pub fn is_not_found_error(err: &anyhow::Error) -> bool {
+        err.chain()
+            .any(|cause| cause.downcast_ref::<Self>().is_some_and(Self::is_not_found))
+    }
+
+    /// The gRPC client reports everything as [`RpcError`], a call failing while the client is
+    /// disconnected failed because of it
+    fn from_grpc(err: RpcError, is_connected: bool) -> Self {
+        let message = err.to_string();
+        if !is_connected {
+            Self::Disconnected
+        } else if message.to_lowercase().contains("timeout")
+            || message.to_lowercase().contains("timed out")
+        {
+            Self::Timeout
+        } else {
+            Self::Rpc(message)
+        }
+    }
+}
+
+impl From<workflow_rpc::client::error::Error> for TransportError {
+    fn from(err: workflow_rpc::client::error::Error) -> Self {
+        match err {
+            workflow_rpc::client::error::Error::Disconnect => Self::Disconnected,
+            workflow_rpc::client::error::Error::Timeout => Self::Timeout,
+            err => Self::Rpc(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disconnected => write!(f, "node disconnected"),
+            Self::Timeout => write!(f, "node request timed out"),
+            Self::CircuitOpen => write!(f, "node circuit breaker open"),
+            Self::Rpc(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+#[derive(Clone)]
+enum Client {
+    Wrpc(KaspaRpcClient),
+    Grpc {
+        client: Arc<GrpcClient>,
+        url: Arc<str>,
+    },
+    Fixture(Arc<FixtureRpcClient>),
+}
+
+#[derive(Clone)]
+pub struct RpcNode {
+    client: Client,
+    limiter: Option<CallLimiter>,
+    recorder: Option<FixtureRecorder>,
+}
+
+impl From<KaspaRpcClient> for RpcNode {
+    fn from(client: KaspaRpcClient) -> Self {
+        Self {
+            client: Client::Wrpc(client),
+            limiter: None,
+            recorder: None,
+        }
+    }
+}
+
+/// Always connected, calls are answered from the fixture
+impl From<FixtureRpcClient> for RpcNode {
+    fn from(client: FixtureRpcClient) -> Self {
+        Self {
+            client: Client::Fixture(Arc::new(client)),
+            limiter: None,
+            recorder: None,
+        }
+    }
+}
+
+impl RpcNode {
+    /// gRPC clients are connected right away and reconnect on their own, wRPC clients are
+    /// connected with [`connect`](Self::connect)
+    pub async fn for_url(
+        url: &str,
+        create_wrpc: impl FnOnce(&str) -> anyhow::Result<KaspaRpcClient>,
+    ) -> anyhow::Result<Self> {
+        match Transport::from_url(url) {
+            Transport::Wrpc => Ok(Self::from(create_wrpc(url)?)),
+            Transport::Grpc => Ok(Self {
+                client: Client::Grpc {
+                    client: Arc::new(GrpcClient::connect(url.to_string()).await?),
+                    url: Arc::from(url),
+                },
+                limiter: None,
+                recorder: None,
+            }),
+        }
+    }
+
+    /// Limits calls of this node and all its clones, a limiter shared with another handle of
+    /// the same node makes both count against it
+    pub fn with_limiter(mut self, limiter: CallLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Records the calls of this node and all its clones together with their responses
+    pub fn with_recorder(mut self, recorder: FixtureRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// False while the circuit breaker refuses calls
+    pub fn is_available(&self) -> bool {
+        self.limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.state() != BreakerState::Open)
+    }
+
+    /// Holds a permit for the duration of `call` only, retries acquire again
+    async fn limited<T>(
+        &self,
+        call: impl Future<Output = Result<T, TransportError>>,
+    ) -> Result<T, TransportError> {
+        let Some(limiter) = &self.limiter else {
+            return call.await;
+        };
+        let _permit = limiter.acquire().await?;
+        let result = call.await;
+        limiter.record(&result);
+        result
+    }
+
+    /// Makes `call` limited and records it with its result as `request`
+    async fn call<T: FixtureValue>(
+        &self,
+        request: FixtureCall,
+        call: impl Future<Output = Result<T, TransportError>>,
+    ) -> Result<T, TransportError> {
+        let result = self.limited(call).await;
+        if let Some(recorder) = &self.recorder {
+            recorder.record_call(request, &result);
+        }
+        result
+    }
+
+    pub fn transport(&self) -> Transport {
+        match self.client {
+            Client::Wrpc(_) => Transport::Wrpc,
+            Client::Grpc { .. } => Transport::Grpc,
+            Client::Fixture(_) => Transport::Fixture,
+        }
+    }
+
+    pub fn url(&self) -> Option<String> {
+        match &self.client {
+            Client::Wrpc(client) => client.url(),
+            Client::Grpc { url, .. } => Some(url.to_string()),
+            Client::Fixture(_) => None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        match &self.client {
+            Client::Wrpc(client) => client.is_connected(),
+            Client::Grpc { client, .. } => client.is_connected(),
+            Client::Fixture(_) => true,
+        }
+    }
+
+    /// Listener registration and everything not wrapped here, none for a fixture
+    pub fn api(&self) -> Option<Arc<DynRpcApi>> {
+        match &self.client {
+            Client::Wrpc(client) => Some(Arc::new(client.clone()) as Arc<DynRpcApi>),
+            Client::Grpc { client, .. } => Some(client.clone() as Arc<DynRpcApi>),
+            Client::Fixture(_) => None,
+        }
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<()> {
+        if let Client::Wrpc(client) = &self.client {
+            client
+                .connect(Some(ConnectOptions {
+                    block_async_connect: false,
+                    connect_timeout: Some(Duration::from_millis(10_000)),
+                    strategy: ConnectStrategy::Retry,
+                    ..Default::default()
+                }))
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> anyhow::Result<()> {
+        match &self.client {
+            Client::Wrpc(client) => client.disconnect().await?,
+            Client::Grpc { client, .. } => client.disconnect().await?,
+            Client::Fixture(_) => {}
+        }
+        Ok(())
+    }
+
+    pub async fn get_blocks(
+        &self,
+        low_hash: RpcHash,
+        include_blocks: bool,
+        include_transactions: bool,
+    ) -> Result<Vec<RpcBlock>, TransportError> {
+        let request = GetBlocksRequest::new(Some(low_hash), include_blocks, include_transactions);
+        let recorded = FixtureCall::GetBlocks {
+            low_hash,
+            include_blocks,
+            include_transactions,
+        };
+        self.call(recorded, async {
+            let GetBlocksResponse { blocks, .. } = match &self.client {
+                Client::Wrpc(client) => {
+                    let Serializable(response) = client
+                        .rpc_client()
+                        .call(RpcApiOps::GetBlocks, Serializable(request))
+                        .await?;
+                    response
+                }
+                Client::Grpc { client, .. } => client
+                    .get_blocks_call(None, request)
+                    .await
+                    .map_err(|err| TransportError::from_grpc(err, client.is_connected()))?,
+                Client::Fixture(fixture) => return fixture.replay(recorded),
+            };
+            Ok(blocks)
+        })
+        .await
+    }
+
+    pub async fn get_block(
+        &self,
+        hash: RpcHash,
+        include_transactions: bool,
+    ) -> Result<RpcBlock, TransportError> {
+        let request = GetBlockRequest::new(hash, include_transactions);
+        let recorded = FixtureCall::GetBlock {
+            hash,
+            include_transactions,
+        };
+        self.call(recorded, async {
+            let GetBlockResponse { block } = match &self.client {
+                Client::Wrpc(client) => {
+                    let Serializable(response) = client
+                        .rpc_client()
+                        .call(RpcApiOps::GetBlock, Serializable(request))
+                        .await?;
+                    response
+                }
+                Client::Grpc { client, .. } => client
+                    .get_block_call(None, request)
+                    .await
+                    .map_err(|err| TransportError::from_grpc(err, client.is_connected()))?,
+                Client::Fixture(fixture) => return fixture.replay(recorded),
+            };
+            Ok(block)
+        })
+        .await
+    }
+
+    pub async fn get_block_dag_info(&self) -> Result<GetBlockDagInfoResponse, TransportError> {
+        let recorded = FixtureCall::GetBlockDagInfo;
+        self.call(recorded, async {
+            match &self.client {
+                Client::Wrpc(client) => {
+                    let Serializable(response) = client
+                        .rpc_client()
+                        .call(
+                            RpcApiOps::GetBlockDagInfo,
+                            Serializable(GetBlockDagInfoRequest {}),
+                        )
+                        .await?;
+                    Ok(response)
+                }
+                Client::Grpc { client, .. } => client
+                    .get_block_dag_info_call(None, GetBlockDagInfoRequest {})
+                    .await
+                    .map_err(|err| TransportError::from_grpc(err, client.is_connected())),
+                Client::Fixture(fixture) => fixture.replay(recorded),
+            }
+        })
+        .await
+    }
+
+    pub async fn get_server_info(&self) -> Result<GetServerInfoResponse, TransportError> {
+        let recorded = FixtureCall::GetServerInfo;
+        self.call(recorded, async {
+            match &self.client {
+                Client::Wrpc(client) => {
+                    let Serializable(response) = client
+                        .rpc_client()
+                        .call(
+                            RpcApiOps::GetServerInfo,
+                            Serializable(GetServerInfoRequest {}),
+                        )
+                        .await?;
+                    Ok(response)
+                }
+                Client::Grpc { client, .. } => client
+                    .get_server_info_call(None, GetServerInfoRequest {})
+                    .await
+                    .map_err(|err| TransportError::from_grpc(err, client.is_connected())),
+                Client::Fixture(fixture) => fixture.replay(recorded),
+            }
+        })
+        .await
+    }
+
+    pub async fn get_virtual_chain_from_block(
+        &self,
+        start_hash: RpcHash,
+        include_accepted_transaction_ids: bool,
+    ) -> Result<GetVirtualChainFromBlockResponse, TransportError> {
+        let request =
+            GetVirtualChainFromBlockRequest::new(start_hash, include_accepted_transaction_ids);
+        let recorded = FixtureCall::GetVirtualChainFromBlock {
+            start_hash,
+            include_accepted_transaction_ids,
+        };
+        self.call(recorded, async {
+            match &self.client {
+                Client::Wrpc(client) => {
+                    let Serializable(response) = client
+                        .rpc_client()
+                        .call(RpcApiOps::GetVirtualChainFromBlock, Serializable(request))
+                        .await?;
+                    Ok(response)
+                }
+                Client::Grpc { client, .. } => client
+                    .get_virtual_chain_from_block_call(None, request)
+                    .await
+                    .map_err(|err| TransportError::from_grpc(err, client.is_connected())),
+                Client::Fixture(fixture) => fixture.replay(recorded),
+            }
+        })
+        .await
+    }
+
+    pub async fn get_utxo_return_address(
+        &self,
+        tx_id: RpcHash,
+        accepting_block_daa_score: u64,
+    ) -> Result<RpcAddress, TransportError> {
+        let request = GetUtxoReturnAddressRequest::new(tx_id, accepting_block_daa_score);
+        let recorded = FixtureCall::GetUtxoReturnAddress {
+            tx_id,
+            accepting_block_daa_score,
+        };
+        self.call(recorded, async {
+            let GetUtxoReturnAddressResponse { return_address } = match &self.client {
+                Client::Wrpc(client) => {
+                    let Serializable(response) = client
+                        .rpc_client()
+                        .call(RpcApiOps::GetUtxoReturnAddress, Serializable(request))
+                        .await?;
+                    response
+                }
+                Client::Grpc { client, .. } => client
+                    .get_utxo_return_address_call(None, request)
+                    .await
+                    .map_err(|err| TransportError::from_grpc(err, client.is_connected()))?,
+                Client::Fixture(fixture) => return fixture.replay(recorded),
+            };
+            Ok(return_address)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transport_from_url() {
+        assert_eq!(
+            Transport::from_url("grpc://127.0.0.1:16110"),
+            Transport::Grpc
+        );
+        assert_eq!(Transport::from_url("ws://127.0.0.1:17110"), Transport::Wrpc);
+        assert_eq!(
+            Transport::from_url("wrpc://127.0.0.1:17110"),
+            Transport::Wrpc
+        );
+    }
+
+    #[test]
+    fn test_errors_normalized_across_transports() {
+        let grpc = |message: &str, is_connected| {
+            TransportError::from_grpc(RpcError::General(message.to_string()), is_connected)
+        };
+        assert_eq!(
+            grpc("channel closed", false),
+            TransportError::from(workflow_rpc::client::error::Error::Disconnect)
+        );
+        assert_eq!(
+            grpc("request timeout", true),
+            TransportError::from(workflow_rpc::client::error::Error::Timeout)
+        );
+        assert!(grpc("request timed out", true).is_transient());
+        assert_eq!(
+            grpc("block not found", true),
+            TransportError::Rpc("block not found".to_string())
+        );
+        assert!(!grpc("block not found", true).is_transient());
+        // the caller backs off or fails over like after a dropped connection
+        assert!(TransportError::CircuitOpen.is_transient());
+    }
+
+    #[test]
+    fn test_error_classes() {
+        let class = |message: &str| TransportError::Rpc(message.to_string()).class();
+        for message in [
+            "route capacity reached",
+            "Borsh deserialize error: Unexpected length of input",
+            "failed to fill whole buffer: unexpected end of file",
+            "WebSocket error: Connection reset without closing handshake",
+            "receive channel closed",
+            "Server is busy",
+            "Node is not synced",
+        ] {
+            assert_eq!(class(message), ErrorClass::Transient, "{message}");
+        }
+        for message in [
+            "Block 4b2a9c5f8e0d3b71a6c4e2f9d8b7a6c5e4d3c2b1a09f8e7d6c5b4a3928170605 not found",
+            "cannot find header 4b2a9c5f8e0d3b71a6c4e2f9d8b7a6c5e4d3c2b1a09f8e7d6c5b4a3928170605",
+            "Rpc error: block is not in the DAG",
+        ] {
+            assert_eq!(class(message), ErrorClass::NotFound, "{message}");
+        }
+        for message in ["Feature not supported", "invalid argument", ""] {
+            assert_eq!(class(message), ErrorClass::Fatal, "{message}");
+        }
+        assert!(TransportError::is_not_found_error(&anyhow::Error::from(
+            TransportError::Rpc("block not found".to_string())
+        )));
+        assert!(!TransportError::is_not_found_error(&anyhow::anyhow!(
+            "block not found"
+        )));
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_up_to_max() {
+        let mut backoff = RetryBackoff::default();
+        let delays = (0..7).map(|_| backoff.next_delay().as_secs());
+        assert_eq!(delays.collect::<Vec<_>>(), [1, 2, 4, 8, 16, 30, 30]);
+    }
+}
@@ -0,0 +1,382 @@
+//! Periodic maintenance tasks, each on its own interval.
+//!
+//! The owner polls [`Scheduler::tick`], a due task then runs on a thread of its own. A task
+//! still running when due again is skipped with a warning. Runs past their timeout and all
+//! runs on shutdown are asked to stop through their [`TaskContext`], which tasks check between
+//! steps, so nothing is interrupted halfway through a write.
+
+use crate::metrics::SharedMetrics;
+use anyhow::Context as _;
+use parking_lot::Mutex;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+pub const DEFAULT_TASK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+pub trait PeriodicTask: Send + 'static {
+    /// Stable name, used for configuration, logs and metrics
+    fn name(&self) -> &'static str;
+    fn interval(&self) -> Duration;
+    /// Runs exceeding this are asked to stop
+    fn timeout(&self) -> Duration {
+        DEFAULT_TASK_TIMEOUT
+    }
+    fn run(&mut self, ctx: &TaskContext) -> anyhow::Result<()>;
+}
+
+/// Runs of a task since startup
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TaskStats {
+    pub name: String,
+    /// Finished runs, failed ones included
+    pub runs: u64,
+    pub failures: u64,
+    /// Runs skipped because the previous one was still in progress
+    pub skipped: u64,
+    pub timeouts: u64,
+    pub last_duration: Duration,
+    pub last_ok: bool,
+}
+
+impl fmt::Display for TaskStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Task {}: {} runs, {} failed, {} skipped, {} timed out, last {} in {:?}",
+            self.name,
+            self.runs,
+            self.failures,
+            self.skipped,
+            self.timeouts,
+            if self.last_ok { "ok" } else { "failed" },
+            self.last_duration
+        )
+    }
+}
+
+/// Source of the scheduler time, replaced in tests
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Handed to every run, a default one is never cancelled
+#[derive(Default)]
+pub struct TaskContext {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TaskContext {
+    /// Set on timeout and shutdown, the task should return as soon as its state allows
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+struct Run {
+    started: Instant,
+    cancelled: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+struct ScheduledTask {
+    name: &'static str,
+    interval: Duration,
+    timeout: Duration,
+    task: Arc<Mutex<Box<dyn PeriodicTask>>>,
+    next_run: Instant,
+    running: Option<Run>,
+}
+
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+    clock: Arc<dyn Clock>,
+    /// Replace the interval a task asks for, by task name
+    intervals: Vec<(String, Duration)>,
+    metrics: SharedMetrics,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl Scheduler {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            tasks: Vec::new(),
+            clock,
+            intervals: Vec::new(),
+            metrics: Default::default(),
+        }
+    }
+
+    /// Tasks registered afterwards run on these intervals instead of their own
+    pub fn with_intervals(mut self, intervals: Vec<(String, Duration)>) -> Self {
+        self.intervals = intervals;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The task first runs on the next tick
+    pub fn register(&mut self, task: impl PeriodicTask) {
+        let name = task.name();
+        let interval = self
+            .intervals
+            .iter()
+            .find(|(configured, _)| configured == name)
+            .map_or(task.interval(), |(_, interval)| *interval);
+        self.tasks.push(ScheduledTask {
+            name,
+            interval,
+            timeout: task.timeout(),
+            task: Arc::new(Mutex::new(Box::new(task))),
+            next_run: self.clock.now(),
+            running: None,
+        });
+    }
+
+    /// Starts the due tasks, finished runs are collected and overdue ones asked to stop
+    pub fn tick(&mut self) {
+        let now = self.clock.now();
+        for scheduled in &mut self.tasks {
+            if let Some(run) = &scheduled.running {
+                if run.handle.is_finished() {
+                    scheduled.running = None;
+                } else if now.saturating_duration_since(run.started) >= scheduled.timeout
+                    && !run.cancelled.swap(true, Ordering::Relaxed)
+                {
+                    warn!(task = scheduled.name, timeout = ?scheduled.timeout, "Task timed out, asking it to stop");
+                    self.metrics.record_task_timeout(scheduled.name);
+                }
+            }
+            if now < scheduled.next_run {
+                continue;
+            }
+            scheduled.next_run = now + scheduled.interval;
+            if scheduled.running.is_some() {
+                warn!(
+                    task = scheduled.name,
+                    "Previous run still in progress, skipping"
+                );
+                self.metrics.record_task_skipped(scheduled.name);
+                continue;
+            }
+            match spawn_run(scheduled, now, self.clock.clone(), self.metrics.clone()) {
+                Ok(run) => scheduled.running = Some(run),
+                Err(err) => error!(task = scheduled.name, "Failed to start task: {err}"),
+            }
+        }
+    }
+
+    /// Asks the running tasks to stop and waits for them
+    pub fn shutdown(&mut self) {
+        let runs = self
+            .tasks
+            .iter_mut()
+            .filter_map(|scheduled| scheduled.running.take())
+            .collect::<Vec<_>>();
+        for run in &runs {
+            run.cancelled.store(true, Ordering::Relaxed);
+        }
+        for run in runs {
+            _ = run.handle.join();
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn spawn_run(
+    scheduled: &ScheduledTask,
+    now: Instant,
+    clock: Arc<dyn Clock>,
+    metrics: SharedMetrics,
+) -> anyhow::Result<Run> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let ctx = TaskContext {
+        cancelled: cancelled.clone(),
+    };
+    let task = scheduled.task.clone();
+    let name = scheduled.name;
+    let handle = std::thread::Builder::new()
+        .name(format!("task-{name}"))
+        .spawn(move || {
+            let started = clock.now();
+            let result = task.lock().run(&ctx);
+            let duration = clock.now().saturating_duration_since(started);
+            if let Err(err) = &result {
+                error!(task = name, "Task failed: {err:#}");
+            }
+            metrics.record_task_run(name, duration, result.is_ok());
+        })
+        .context("failed to spawn task thread")?;
+    Ok(Run {
+        started: now,
+        cancelled,
+        handle,
+    })
+}
+
+/// Parses `name=seconds` pairs separated by commas, e.g. `database_stats=300,compact_metadata=60`
+pub fn parse_intervals(value: &str) -> anyhow::Result<Vec<(String, Duration)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, secs) = pair
+                .split_once('=')
+                .with_context(|| format!("expected name=seconds, got {pair}"))?;
+            let secs = secs
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid interval of {name}"))?;
+            Ok((name.trim().to_string(), Duration::from_secs(secs)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::create_shared_metrics;
+    use std::sync::mpsc;
+
+    struct MockClock(Mutex<Instant>);
+
+    impl MockClock {
+        fn advance(&self, by: Duration) {
+            *self.0.lock() += by;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+    }
+
+    /// Reports every run and blocks until released or cancelled
+    struct Blocking {
+        started: mpsc::Sender<()>,
+        release: Arc<AtomicBool>,
+    }
+
+    impl PeriodicTask for Blocking {
+        fn name(&self) -> &'static str {
+            "blocking"
+        }
+
+        fn interval(&self) -> Duration {
+            Duration::from_secs(10)
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(30)
+        }
+
+        fn run(&mut self, ctx: &TaskContext) -> anyhow::Result<()> {
+            self.started.send(())?;
+            while !self.release.load(Ordering::Relaxed) {
+                if ctx.is_cancelled() {
+                    anyhow::bail!("cancelled");
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Ok(())
+        }
+    }
+
+    fn wait_idle(scheduler: &mut Scheduler) {
+        for scheduled in &mut scheduler.tasks {
+            if let Some(run) = scheduled.running.take() {
+                run.handle.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_scheduling_skips_and_timeouts() {
+        let clock = Arc::new(MockClock(Mutex::new(Instant::now())));
+        let metrics = create_shared_metrics();
+        let mut scheduler = Scheduler::new(clock.clone())
+            .with_intervals(vec![("blocking".to_string(), Duration::from_secs(20))])
+            .with_metrics(metrics.clone());
+        let (started_tx, started_rx) = mpsc::channel();
+        let release = Arc::new(AtomicBool::new(true));
+        scheduler.register(Blocking {
+            started: started_tx,
+            release: release.clone(),
+        });
+
+        scheduler.tick();
+        started_rx.recv().unwrap();
+        wait_idle(&mut scheduler);
+        // not due before the configured interval
+        clock.advance(Duration::from_secs(10));
+        scheduler.tick();
+        assert!(started_rx.try_recv().is_err());
+
+        release.store(false, Ordering::Relaxed);
+        clock.advance(Duration::from_secs(10));
+        scheduler.tick();
+        started_rx.recv().unwrap();
+        clock.advance(Duration::from_secs(20));
+        scheduler.tick();
+        assert!(started_rx.try_recv().is_err());
+        // past the timeout the run is asked to stop and fails
+        clock.advance(Duration::from_secs(10));
+        scheduler.tick();
+        wait_idle(&mut scheduler);
+
+        let tasks = metrics.snapshot().periodic_tasks;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].name, "blocking");
+        assert_eq!(tasks[0].runs, 2);
+        assert_eq!(tasks[0].failures, 1);
+        assert_eq!(tasks[0].skipped, 1);
+        assert_eq!(tasks[0].timeouts, 1);
+        assert!(!tasks[0].last_ok);
+
+        // shutdown cancels the run in progress
+        clock.advance(Duration::from_secs(10));
+        scheduler.tick();
+        started_rx.recv().unwrap();
+        scheduler.shutdown();
+        assert_eq!(metrics.snapshot().periodic_tasks[0].failures, 2);
+    }
+
+    #[test]
+    fn test_parse_intervals() {
+        assert_eq!(
+            parse_intervals(" database_stats=300, compact_metadata = 60,").unwrap(),
+            vec![
+                ("database_stats".to_string(), Duration::from_secs(300)),
+                ("compact_metadata".to_string(), Duration::from_secs(60)),
+            ]
+        );
+        assert!(parse_intervals("database_stats").is_err());
+        assert!(parse_intervals("database_stats=soon").is_err());
+    }
+}
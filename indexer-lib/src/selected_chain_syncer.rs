@@ -1,17 +1,35 @@
-use crate::database::headers::BlockCompactHeaderPartition;
+use crate::CompactHeader;
+use crate::call_limiter::CallLimiter;
+use crate::database::headers::{
+    BlockCompactHeaderPartition, BlockGap, ChainIndexByHashPartition, ChainIndexPartition,
+};
 use crate::database::metadata::MetadataPartition;
+use crate::database::processing::AcceptanceGapsPartition;
+use crate::fixture::FixtureRecorder;
 use crate::historical_syncer::Cursor;
+use crate::metrics::SharedMetrics;
+use crate::rpc_transport::{RetryBackoff, RpcNode, TransportError};
+use crate::shutdown::Shutdown;
 use crate::virtual_chain_processor::VirtualChainChangedNotificationAndBlueWork;
-use crate::{APP_IS_RUNNING, CompactHeader};
 use anyhow::{Context, bail};
-use kaspa_rpc_core::VirtualChainChangedNotification;
 use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_rpc_core::{GetVirtualChainFromBlockResponse, RpcHash, VirtualChainChangedNotification};
 use kaspa_wrpc_client::KaspaRpcClient;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::task;
 use tracing::{debug, error, info, warn};
 
+/// Chain blocks forwarded to the virtual chain processor per notification, a response of
+/// `getVirtualChainFromBlock` is split into notifications of at most this many blocks
+pub const VCC_CHUNK_SIZE: usize = 512;
+/// Chain blocks applied per step of a `getVirtualChainFromBlock` response, see
+/// [`SelectedChainSyncer::with_max_chain_blocks_per_step`]
+pub const DEFAULT_MAX_CHAIN_BLOCKS_PER_STEP: usize = 4096;
+/// Consecutive failed chain requests after which the chain start is considered unusable
+const MAX_FAILED_CHAIN_REQUESTS: u32 = 3;
+
 pub enum Intake {
     VirtualChainChangedNotification(VirtualChainChangedNotification),
     Disconnect,
@@ -35,6 +53,15 @@ pub enum TargetReachedVia {
     BlueWorkExceeded { final_block: Cursor },
 }
 
+/// Lets the historical syncer resume from a chain start the node no longer serves: a pruned
+/// start is replaced by the pruning point, a reorged out one is rewound along the chain index
+#[derive(Clone)]
+pub struct ChainRecovery {
+    pub chain_index_partition: ChainIndexPartition,
+    pub chain_index_by_hash_partition: ChainIndexByHashPartition,
+    pub acceptance_gaps_partition: AcceptanceGapsPartition,
+}
+
 #[derive(Default)]
 struct SyncState {
     is_synced: bool,
@@ -64,13 +91,18 @@ impl SyncState {
 
 pub struct SelectedChainSyncer {
     rpc_client: KaspaRpcClient,
+    /// Same node as `rpc_client`, for the historical syncer
+    rpc_node: RpcNode,
     metadata_partition: MetadataPartition,
     block_compact_header_partition: BlockCompactHeaderPartition,
     intake_rx: tokio::sync::mpsc::Receiver<Intake>,
     historical_sync_done_rx: tokio::sync::mpsc::Receiver<HistoricalSyncResult>,
     historical_sync_done_tx: tokio::sync::mpsc::Sender<HistoricalSyncResult>,
     worker_sender: flume::Sender<VirtualChainChangedNotificationAndBlueWork>,
-    shutdown: tokio::sync::oneshot::Receiver<()>,
+    shutdown: Shutdown,
+    recovery: Option<ChainRecovery>,
+    metrics: SharedMetrics,
+    max_chain_blocks_per_step: usize,
 
     queue_pow: u32,
 }
@@ -84,9 +116,10 @@ impl SelectedChainSyncer {
         historical_sync_done_rx: tokio::sync::mpsc::Receiver<HistoricalSyncResult>,
         historical_sync_done_tx: tokio::sync::mpsc::Sender<HistoricalSyncResult>,
         worker_sender: flume::Sender<VirtualChainChangedNotificationAndBlueWork>,
-        shutdown: tokio::sync::oneshot::Receiver<()>,
+        shutdown: Shutdown,
     ) -> Self {
         Self {
+            rpc_node: RpcNode::from(rpc_client.clone()),
             rpc_client,
             metadata_partition,
             block_compact_header_partition,
@@ -95,10 +128,41 @@ impl SelectedChainSyncer {
             historical_sync_done_tx,
             worker_sender,
             shutdown,
+            recovery: None,
+            metrics: Default::default(),
+            max_chain_blocks_per_step: DEFAULT_MAX_CHAIN_BLOCKS_PER_STEP,
             queue_pow: 10,
         }
     }
 
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Limits the calls of the historical syncer together with everything else sharing `limiter`
+    pub fn with_call_limiter(mut self, limiter: CallLimiter) -> Self {
+        self.rpc_node = self.rpc_node.with_limiter(limiter);
+        self
+    }
+
+    /// Records the calls of the historical syncer into a fixture
+    pub fn with_fixture_recorder(mut self, recorder: FixtureRecorder) -> Self {
+        self.rpc_node = self.rpc_node.with_recorder(recorder);
+        self
+    }
+
+    pub fn with_max_chain_blocks_per_step(mut self, max_chain_blocks_per_step: usize) -> Self {
+        self.max_chain_blocks_per_step = max_chain_blocks_per_step.max(1);
+        self
+    }
+
+    /// Without it requests from an unusable chain start are retried forever
+    pub fn with_recovery(mut self, recovery: ChainRecovery) -> Self {
+        self.recovery = Some(recovery);
+        self
+    }
+
     pub async fn process(&mut self) -> anyhow::Result<()> {
         info!("Starting selected chain syncer");
 
@@ -118,7 +182,7 @@ impl SelectedChainSyncer {
                     };
                     self.handle_intake(&mut state, intake).await?;
                 }
-                _ = &mut self.shutdown => {
+                _ = self.shutdown.cancelled() => {
                     return self.handle_shutdown(&mut state).await;
                 }
             }
@@ -170,6 +234,7 @@ impl SelectedChainSyncer {
                 };
 
             if let Some(cursor) = from_cursor {
+                let cursor = self.skip_pruned_span(cursor, &dag_info).await?;
                 self.sync_or_mark_complete(state, cursor, dag_info.sink)
                     .await?;
             } else {
@@ -183,6 +248,28 @@ impl SelectedChainSyncer {
         Ok(())
     }
 
+    /// The node can't serve the chain from a start below its pruning point, syncing resumes
+    /// from the pruning point and the skipped span is recorded as an acceptance gap
+    async fn skip_pruned_span(
+        &self,
+        cursor: Cursor,
+        dag_info: &kaspa_rpc_core::GetBlockDagInfoResponse,
+    ) -> anyhow::Result<Cursor> {
+        let pruning_point = self.get_pruning_point_cursor(dag_info).await?;
+        if cursor.daa_score >= pruning_point.daa_score {
+            return Ok(cursor);
+        }
+        record_pruned_span(
+            self.recovery
+                .as_ref()
+                .map(|recovery| recovery.acceptance_gaps_partition.clone()),
+            cursor,
+            pruning_point,
+        )
+        .await?;
+        Ok(pruning_point)
+    }
+
     async fn handle_shutdown(&self, state: &mut SyncState) -> anyhow::Result<()> {
         info!("Selected chain syncer shutting down");
 
@@ -257,8 +344,8 @@ impl SelectedChainSyncer {
 
         // Keep trying to initialize sync until it succeeds
         loop {
-            if !APP_IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
-                bail!("App is stopped");
+            if self.shutdown.is_cancelled() {
+                bail!("Chain syncer is stopped");
             }
             if let Err(e) = self.try_initialize_sync(state).await {
                 error!(
@@ -374,7 +461,34 @@ impl SelectedChainSyncer {
 
     async fn get_last_cursor(&self) -> anyhow::Result<Option<Cursor>> {
         let metadata_partition = self.metadata_partition.clone();
-        task::spawn_blocking(move || metadata_partition.get_latest_accepting_block_cursor()).await?
+        let (tip, sink, position) = task::spawn_blocking(move || -> anyhow::Result<_> {
+            Ok((
+                metadata_partition.get_latest_accepting_block_cursor()?,
+                metadata_partition.get_sink()?,
+                metadata_partition.get_chain_sync_position()?,
+            ))
+        })
+        .await??;
+        // pages still queued for the virtual chain processor are lost, so the tip is the start
+        if let (Some(tip), Some(position)) = (tip, position)
+            && tip.blue_work < position.blue_work
+        {
+            info!(
+                ?tip,
+                ?position,
+                "Forwarded chain pages were not applied before the restart, resyncing from the tip"
+            );
+        }
+        if let (Some(tip), Some(sink)) = (tip, sink)
+            && tip.blue_work < sink.blue_work
+        {
+            info!(
+                ?tip,
+                ?sink,
+                "Previous sync stopped before reaching its sink, resyncing from the tip"
+            );
+        }
+        Ok(tip)
     }
 
     async fn get_pruning_point_cursor(
@@ -408,17 +522,24 @@ impl SelectedChainSyncer {
         };
 
         info!(from = ?from, to = ?target, "Selected chain syncer spawned");
+        let metadata_partition = self.metadata_partition.clone();
+        task::spawn_blocking(move || metadata_partition.set_sink(target)).await??;
 
         let (interrupt_tx, interrupt_rx) = tokio::sync::oneshot::channel();
-        let mut syncer = HistoricalSyncer::new(
-            self.rpc_client.clone(),
-            self.block_compact_header_partition.clone(),
-            self.historical_sync_done_tx.clone(),
-            interrupt_rx,
-            self.worker_sender.clone(),
-            from,
-            target,
-        );
+        let mut syncer = HistoricalSyncer::builder()
+            .rpc_client(self.rpc_node.clone())
+            .block_compact_header_partition(self.block_compact_header_partition.clone())
+            .metadata_partition(self.metadata_partition.clone())
+            .historical_sync_done_tx(self.historical_sync_done_tx.clone())
+            .interrupt(interrupt_rx)
+            .shutdown(self.shutdown.clone())
+            .worker_sender(self.worker_sender.clone())
+            .from(from)
+            .to(target)
+            .maybe_recovery(self.recovery.clone())
+            .metrics(self.metrics.clone())
+            .max_chain_blocks_per_step(self.max_chain_blocks_per_step)
+            .build();
 
         let task = tokio::spawn(async move {
             if let Err(e) = syncer.sync().await {
@@ -449,43 +570,44 @@ impl SelectedChainSyncer {
     }
 }
 
+#[derive(bon::Builder)]
 pub struct HistoricalSyncer {
-    rpc_client: KaspaRpcClient,
+    rpc_client: RpcNode,
     block_compact_header_partition: BlockCompactHeaderPartition,
+    metadata_partition: MetadataPartition,
     historical_sync_done_tx: tokio::sync::mpsc::Sender<HistoricalSyncResult>,
-    shutdown: tokio::sync::oneshot::Receiver<()>,
+    /// Stops the sync with an [`HistoricalSyncResult::Interrupted`], e.g. on disconnects
+    interrupt: tokio::sync::oneshot::Receiver<()>,
+    shutdown: Shutdown,
     worker_sender: flume::Sender<VirtualChainChangedNotificationAndBlueWork>,
     from: Cursor,
     to: Cursor,
+    recovery: Option<ChainRecovery>,
+    #[builder(default)]
+    metrics: SharedMetrics,
+    /// Chain blocks applied per step, the position is persisted after every step of a response
+    #[builder(default = DEFAULT_MAX_CHAIN_BLOCKS_PER_STEP)]
+    max_chain_blocks_per_step: usize,
 
     // Initialize progress tracking
+    #[builder(skip)]
     total_blocks_processed: u64,
+    #[builder(skip)]
+    total_acceptance_records: u64,
+    #[builder(skip)]
     batches_processed: u64,
+
+    #[builder(skip)]
+    failed_requests: u32,
+    /// Chain blocks to step back by on the next rewind, doubled every time
+    #[builder(skip = 1)]
+    rewind_distance: u64,
+    /// Chain blocks after a rewound start, removed with the next response
+    #[builder(skip)]
+    pending_removals: Vec<RpcHash>,
 }
 
 impl HistoricalSyncer {
-    pub fn new(
-        rpc_client: KaspaRpcClient,
-        block_compact_header_partition: BlockCompactHeaderPartition,
-        historical_sync_done_tx: tokio::sync::mpsc::Sender<HistoricalSyncResult>,
-        shutdown: tokio::sync::oneshot::Receiver<()>,
-        worker_sender: flume::Sender<VirtualChainChangedNotificationAndBlueWork>,
-        from: Cursor,
-        to: Cursor,
-    ) -> Self {
-        Self {
-            rpc_client,
-            block_compact_header_partition,
-            historical_sync_done_tx,
-            shutdown,
-            worker_sender,
-            from,
-            to,
-            total_blocks_processed: 0,
-            batches_processed: 0,
-        }
-    }
-
     async fn sync(&mut self) -> anyhow::Result<()> {
         let mut current = self.from;
 
@@ -493,12 +615,12 @@ impl HistoricalSyncer {
             tokio::select! {
                 biased;
 
-                _ = &mut self.shutdown => {
+                _ = &mut self.interrupt => {
                     info!("Historical syncer shutting down");
                     return self.handle_interruption(current).await;
                 }
                 // todo handle error
-                vcc_result = self.rpc_client.get_virtual_chain_from_block(current.hash, true) => {
+                vcc_result = get_virtual_chain_with_retries(&self.rpc_client, &self.shutdown, current.hash) => {
                     match self.process_vcc_result(vcc_result, &mut current).await? {
                         Some(result) => {
                             info!(
@@ -526,42 +648,113 @@ impl HistoricalSyncer {
 
     async fn process_vcc_result(
         &mut self,
-        vcc_result: Result<
-            kaspa_rpc_core::GetVirtualChainFromBlockResponse,
-            kaspa_rpc_core::RpcError,
-        >,
+        vcc_result: anyhow::Result<GetVirtualChainFromBlockResponse>,
         current: &mut Cursor,
     ) -> anyhow::Result<Option<HistoricalSyncResult>> {
-        if !APP_IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
-            bail!("App is stopped");
+        if self.shutdown.is_cancelled() {
+            bail!("Chain syncer is stopped");
         }
         let vcc_response = match vcc_result {
             Ok(vcc) => vcc,
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            Err(e) => {
+                self.failed_requests += 1;
+                warn!(
+                    from = %current.hash,
+                    attempt = self.failed_requests,
+                    "Virtual chain request failed: {}",
+                    e
+                );
+                // a start the node doesn't know is recovered from right away
+                if self.failed_requests >= MAX_FAILED_CHAIN_REQUESTS
+                    || TransportError::is_not_found_error(&e)
+                {
+                    self.failed_requests = 0;
+                    self.recover(current).await?;
+                } else {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
                 return Ok(None);
             }
         };
+        self.failed_requests = 0;
+        self.rewind_distance = 1;
+        if vcc_response.added_chain_block_hashes.is_empty() {
+            // nothing past the start yet
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            return Ok(None);
+        }
 
+        let mut removed = std::mem::take(&mut self.pending_removals);
+        removed.extend(vcc_response.removed_chain_block_hashes);
         let vcc = VirtualChainChangedNotification {
-            removed_chain_block_hashes: Arc::new(vcc_response.removed_chain_block_hashes),
+            removed_chain_block_hashes: Arc::new(removed),
             added_chain_block_hashes: Arc::new(vcc_response.added_chain_block_hashes),
             accepted_transaction_ids: Arc::new(vcc_response.accepted_transaction_ids),
         };
+        // the whole response is applied, a step at a time so a restart resumes within it
+        for step in split_into_chunks(&vcc, self.max_chain_blocks_per_step) {
+            if let Some(result) = self.apply_step(step, current).await? {
+                return Ok(Some(result));
+            }
+        }
+        Ok(None)
+    }
 
+    /// Forwards the chain blocks of one step to the virtual chain processor and persists the
+    /// position after them
+    async fn apply_step(
+        &mut self,
+        vcc: VirtualChainChangedNotification,
+        current: &mut Cursor,
+    ) -> anyhow::Result<Option<HistoricalSyncResult>> {
         // Update progress tracking
         let batch_size = vcc.added_chain_block_hashes.len();
         self.total_blocks_processed += batch_size as u64;
         self.batches_processed += 1;
 
+        // applied in bounded chunks, each one exactly like a live notification
+        let mut last_compact_header = None;
+        let mut acceptance_records = 0;
+        for chunk in split_into_chunks(&vcc, VCC_CHUNK_SIZE) {
+            let last_block = *chunk.added_chain_block_hashes.last().unwrap();
+            let compact_header = self.get_block_compact_header(last_block).await?;
+            acceptance_records += chunk
+                .accepted_transaction_ids
+                .iter()
+                .map(|ids| ids.accepted_transaction_ids.len() as u64)
+                .sum::<u64>();
+            self.worker_sender
+                .send_async(VirtualChainChangedNotificationAndBlueWork {
+                    vcc: chunk,
+                    last_block_blue_work: compact_header.blue_work,
+                    last_daa_score: compact_header.daa_score,
+                })
+                .await?;
+            last_compact_header = Some(compact_header);
+        }
+        let last_compact_header = last_compact_header.unwrap();
         let last_block = *vcc.added_chain_block_hashes.last().unwrap();
-        let last_compact_header = self.get_block_compact_header(last_block).await?;
 
         *current = Cursor {
             daa_score: last_compact_header.daa_score,
             hash: last_block,
             blue_work: last_compact_header.blue_work,
         };
+        let metadata_partition = self.metadata_partition.clone();
+        let position = *current;
+        task::spawn_blocking(move || metadata_partition.set_chain_sync_position(position))
+            .await??;
+        self.total_acceptance_records += acceptance_records;
+        let remaining_daa = self.to.daa_score.saturating_sub(current.daa_score);
+        self.metrics
+            .add_chain_sync_page(batch_size as u64, acceptance_records, remaining_daa);
+        debug!(
+            blocks = batch_size,
+            acceptance_records,
+            remaining_daa,
+            position = %current.hash,
+            "Selected chain page applied"
+        );
 
         // Log progress periodically (every 100 batches like in HistoricalDataSyncer)
         if self.batches_processed % 100 == 0 {
@@ -582,21 +775,15 @@ impl HistoricalSyncer {
                 current_blue_work = %current_blue_work,
                 target_block = %self.to.hash,
                 target_blue_work = %target_blue_work,
-                "Selected chain sync progress: {}% ({} batches processed, {} blocks processed)",
+                remaining_daa,
+                "Selected chain sync progress: {}% ({} batches processed, {} blocks processed, {} acceptance records)",
                 percentage,
                 self.batches_processed,
                 self.total_blocks_processed,
+                self.total_acceptance_records,
             );
         }
 
-        self.worker_sender
-            .send_async(VirtualChainChangedNotificationAndBlueWork {
-                vcc: vcc.clone(),
-                last_block_blue_work: last_compact_header.blue_work,
-                last_daa_score: last_compact_header.daa_score,
-            })
-            .await?;
-
         if self.target_reached_exactly(&vcc) {
             return Ok(Some(HistoricalSyncResult::TargetReached {
                 target: self.to,
@@ -616,6 +803,89 @@ impl HistoricalSyncer {
         Ok(None)
     }
 
+    /// Called once requests from `current` keep failing. A start below the pruning point of
+    /// the node is replaced by the pruning point and the skipped span is recorded, any other
+    /// start is taken as reorged out and rewound along the chain index
+    async fn recover(&mut self, current: &mut Cursor) -> anyhow::Result<()> {
+        let Some(recovery) = self.recovery.clone() else {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            return Ok(());
+        };
+        if self
+            .rpc_client
+            .get_block(current.hash, false)
+            .await
+            .is_err()
+        {
+            let dag_info = self.rpc_client.get_block_dag_info().await?;
+            let pruning_point = self
+                .get_block_compact_header(dag_info.pruning_point_hash)
+                .await?;
+            if pruning_point.daa_score > current.daa_score {
+                let resumed = Cursor {
+                    daa_score: pruning_point.daa_score,
+                    blue_work: pruning_point.blue_work,
+                    hash: dag_info.pruning_point_hash,
+                };
+                record_pruned_span(
+                    Some(recovery.acceptance_gaps_partition.clone()),
+                    *current,
+                    resumed,
+                )
+                .await?;
+                self.pending_removals.clear();
+                *current = resumed;
+                return Ok(());
+            }
+        }
+
+        let (hash, distance) = (current.hash, self.rewind_distance);
+        let rewound = task::spawn_blocking(move || -> anyhow::Result<_> {
+            let ChainRecovery {
+                chain_index_partition,
+                chain_index_by_hash_partition,
+                ..
+            } = recovery;
+            let Some(index) = chain_index_by_hash_partition.get_chain_index(&hash)? else {
+                return Ok(None);
+            };
+            let start_index = index.saturating_sub(distance);
+            let Some(start) = chain_index_partition.get_chain_block_by_index(start_index)? else {
+                return Ok(None);
+            };
+            let removed = chain_index_partition
+                .iter_chain_blocks(start_index + 1..u64::MAX)
+                .rev()
+                .map(|item| item.map(|(_, hash)| hash))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(Some((start, removed)))
+        })
+        .await??;
+        let Some((start, removed)) = rewound else {
+            warn!(start = %current.hash, "Chain start is not indexed, retrying it");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            return Ok(());
+        };
+        let compact_header = self.get_block_compact_header(start).await?;
+        warn!(
+            from = %current.hash,
+            to = %start,
+            removed = removed.len(),
+            "Chain start looks reorged out, rewinding along the chain index"
+        );
+        self.rewind_distance = self.rewind_distance.saturating_mul(2);
+        // everything indexed after the new start, a block still on the chain is added back
+        self.pending_removals = removed;
+        *current = Cursor {
+            daa_score: compact_header.daa_score,
+            blue_work: compact_header.blue_work,
+            hash: start,
+        };
+        // progress is measured from the rewound start
+        self.from = *current;
+        Ok(())
+    }
+
     fn target_reached_exactly(&self, vcc: &VirtualChainChangedNotification) -> bool {
         vcc.added_chain_block_hashes
             .iter()
@@ -633,11 +903,12 @@ impl HistoricalSyncer {
         })
         .await??;
 
+        let mut backoff = RetryBackoff::default();
         match local_blue_work {
             Some(blue_work) => Ok(blue_work),
             None => loop {
-                if !APP_IS_RUNNING.load(std::sync::atomic::Ordering::Relaxed) {
-                    bail!("App is stopped");
+                if self.shutdown.is_cancelled() {
+                    bail!("Chain syncer is stopped");
                 }
                 // TODO: Add proper error handling with HistoricalSyncResult::Failed variant
                 // to avoid infinite retry loops and allow graceful failure handling
@@ -648,12 +919,249 @@ impl HistoricalSyncer {
                             daa_score: block.header.daa_score,
                         });
                     }
-                    Err(_) => {
+                    Err(err) if err.is_transient() => {
+                        tokio::time::sleep(backoff.next_delay()).await;
+                    }
+                    Err(err) => {
+                        warn!(%block_hash, class = ?err.class(), "Block request failed, retrying: {err}");
                         tokio::time::sleep(Duration::from_secs(1)).await;
-                        continue;
                     }
                 }
             },
         }
     }
 }
+
+/// Warns once per lost span with its size and records it, transactions within it report
+/// unknown acceptance from then on
+async fn record_pruned_span(
+    acceptance_gaps_partition: Option<AcceptanceGapsPartition>,
+    from: Cursor,
+    to: Cursor,
+) -> anyhow::Result<()> {
+    warn!(
+        from = ?from,
+        to = ?to,
+        lost_daa = to.daa_score.saturating_sub(from.daa_score),
+        "Selected chain position was pruned by the node, acceptance data of the span up to the \
+         pruning point is lost and syncing resumes from the pruning point"
+    );
+    if let Some(partition) = acceptance_gaps_partition {
+        let gap = BlockGap::from_cursors(from, to);
+        task::spawn_blocking(move || partition.add_gap(&gap)).await??;
+    }
+    Ok(())
+}
+
+/// Transient failures are retried with a growing backoff like in `get_blocks_with_retries`,
+/// other errors are returned with their [`TransportError`]
+async fn get_virtual_chain_with_retries(
+    client: &RpcNode,
+    shutdown: &Shutdown,
+    start_hash: RpcHash,
+) -> anyhow::Result<GetVirtualChainFromBlockResponse> {
+    let mut backoff = RetryBackoff::default();
+    loop {
+        if shutdown.is_cancelled() {
+            bail!("Chain syncer is stopped");
+        }
+        if !client.is_connected() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        match client.get_virtual_chain_from_block(start_hash, true).await {
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_transient() => {
+                let delay = backoff.next_delay();
+                warn!(%start_hash, ?delay, "Virtual chain request failed, retrying: {err}");
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Splits the notification into notifications of at most `chunk_size` added chain blocks
+/// each, the removals go with the first one
+fn split_into_chunks(
+    vcc: &VirtualChainChangedNotification,
+    chunk_size: usize,
+) -> Vec<VirtualChainChangedNotification> {
+    let chunk_size = chunk_size.max(1);
+    let chunk_of = vcc
+        .added_chain_block_hashes
+        .iter()
+        .enumerate()
+        .map(|(i, hash)| (*hash, i / chunk_size))
+        .collect::<HashMap<_, _>>();
+    let mut accepted = vec![Vec::new(); vcc.added_chain_block_hashes.len().div_ceil(chunk_size)];
+    for ids in vcc.accepted_transaction_ids.iter() {
+        if let Some(chunk) = chunk_of.get(&ids.accepting_block_hash) {
+            accepted[*chunk].push(ids.clone());
+        }
+    }
+    vcc.added_chain_block_hashes
+        .chunks(chunk_size)
+        .zip(accepted)
+        .enumerate()
+        .map(
+            |(i, (added, accepted_transaction_ids))| VirtualChainChangedNotification {
+                removed_chain_block_hashes: Arc::new(if i == 0 {
+                    vcc.removed_chain_block_hashes.to_vec()
+                } else {
+                    Vec::new()
+                }),
+                added_chain_block_hashes: Arc::new(added.to_vec()),
+                accepted_transaction_ids: Arc::new(accepted_transaction_ids),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, FixtureRpcClient};
+    use kaspa_math::Uint192;
+    use kaspa_rpc_core::RpcAcceptedTransactionIds;
+    use std::ops::RangeInclusive;
+
+    #[test]
+    fn test_split_into_chunks() {
+        let hash = RpcHash::from_u64_word;
+        let accepted = |block| RpcAcceptedTransactionIds {
+            accepting_block_hash: hash(block),
+            accepted_transaction_ids: vec![hash(100 + block)],
+        };
+        let vcc = VirtualChainChangedNotification {
+            removed_chain_block_hashes: Arc::new(vec![hash(9), hash(8)]),
+            added_chain_block_hashes: Arc::new((1..=5).map(hash).collect()),
+            // block 3 accepts nothing
+            accepted_transaction_ids: Arc::new([1, 2, 4, 5].map(accepted).to_vec()),
+        };
+
+        let chunks = split_into_chunks(&vcc, 2);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| (
+                    chunk.removed_chain_block_hashes.to_vec(),
+                    chunk.added_chain_block_hashes.to_vec(),
+                    chunk
+                        .accepted_transaction_ids
+                        .iter()
+                        .map(|ids| ids.accepting_block_hash)
+                        .collect::<Vec<_>>()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    vec![hash(9), hash(8)],
+                    vec![hash(1), hash(2)],
+                    vec![hash(1), hash(2)]
+                ),
+                (vec![], vec![hash(3), hash(4)], vec![hash(4)]),
+                (vec![], vec![hash(5)], vec![hash(5)]),
+            ]
+        );
+        assert_eq!(split_into_chunks(&vcc, VCC_CHUNK_SIZE).len(), 1);
+    }
+
+    /// Syncer from block 0 to `target` applying `step` chain blocks at a time, the headers of
+    /// blocks 1 to 9 are stored
+    fn syncer(
+        name: &str,
+        step: usize,
+        target: u64,
+    ) -> (
+        HistoricalSyncer,
+        MetadataPartition,
+        flume::Receiver<VirtualChainChangedNotificationAndBlueWork>,
+    ) {
+        let keyspace = crate::database::test_keyspace(&format!("chain-steps-{name}"));
+        let headers = BlockCompactHeaderPartition::new(&keyspace).unwrap();
+        for i in 1..=9 {
+            headers
+                .insert_compact_header(&RpcHash::from_u64_word(i), Uint192::from_u64(i), i)
+                .unwrap();
+        }
+        let metadata = MetadataPartition::new(&keyspace).unwrap();
+        let cursor = |i| Cursor::new(i, Uint192::from_u64(i), RpcHash::from_u64_word(i));
+        let (worker_sender, worker_rx) = flume::unbounded();
+        let syncer = HistoricalSyncer::builder()
+            .rpc_client(RpcNode::from(FixtureRpcClient::from(Fixture::default())))
+            .block_compact_header_partition(headers)
+            .metadata_partition(metadata.clone())
+            .historical_sync_done_tx(tokio::sync::mpsc::channel(1).0)
+            .interrupt(tokio::sync::oneshot::channel().1)
+            .shutdown(Shutdown::new())
+            .worker_sender(worker_sender)
+            .from(cursor(0))
+            .to(cursor(target))
+            .max_chain_blocks_per_step(step)
+            .build();
+        (syncer, metadata, worker_rx)
+    }
+
+    fn response(added: RangeInclusive<u64>) -> GetVirtualChainFromBlockResponse {
+        GetVirtualChainFromBlockResponse::new(
+            vec![RpcHash::from_u64_word(20)],
+            added.map(RpcHash::from_u64_word).collect(),
+            vec![],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_response_is_applied_in_steps() {
+        let (mut syncer, metadata, worker_rx) = syncer("whole", 2, 9);
+        let mut current = syncer.from;
+
+        let result = syncer
+            .process_vcc_result(Ok(response(1..=5)), &mut current)
+            .await
+            .unwrap();
+
+        assert!(result.is_none());
+        assert_eq!(current.hash, RpcHash::from_u64_word(5));
+        assert_eq!(metadata.get_chain_sync_position().unwrap(), Some(current));
+        let steps = worker_rx
+            .drain()
+            .map(|step| {
+                (
+                    step.vcc.removed_chain_block_hashes.len(),
+                    step.vcc.added_chain_block_hashes.len(),
+                    step.last_daa_score,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(steps, [(1, 2, 2), (0, 2, 4), (0, 1, 5)]);
+        assert_eq!(
+            (syncer.total_blocks_processed, syncer.batches_processed),
+            (5, 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_steps_stop_at_the_target() {
+        let (mut syncer, metadata, worker_rx) = syncer("target", 2, 3);
+        let mut current = syncer.from;
+
+        let result = syncer
+            .process_vcc_result(Ok(response(1..=5)), &mut current)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            result,
+            Some(HistoricalSyncResult::TargetReached {
+                reached_via: TargetReachedVia::DirectMatch,
+                ..
+            })
+        ));
+        // the step holding the target is applied, the blocks after it are not
+        assert_eq!(current.hash, RpcHash::from_u64_word(4));
+        assert_eq!(metadata.get_chain_sync_position().unwrap(), Some(current));
+        assert_eq!(worker_rx.drain().count(), 2);
+    }
+}
@@ -0,0 +1,185 @@
+//! Staged shutdown of the indexer tasks.
+//!
+//! Every task gets the [`Shutdown`] of its [`Stage`] and spawns onto it what has to finish
+//! before the next stage stops. [`ShutdownController::shutdown`] then stops the stages in order:
+//! the subscriber stops taking in blocks, the syncers persist their remaining gaps, the
+//! processors drain their channels and flush their batches, and the database closes once the
+//! controller returns and the last partition handles are dropped.
+
+use std::future::Future;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::info;
+
+/// Cancellation of a task and of everything it spawned. Clones share the token, children are
+/// cancelled with their parent or on their own
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Completes once cancelled, immediately if already
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel()
+    }
+
+    /// Cancelled with this one, cancelling it leaves this one running. Tasks spawned on it are
+    /// waited for together with the ones of this stage
+    pub fn child(&self) -> Self {
+        Self {
+            token: self.token.child_token(),
+            tracker: self.tracker.clone(),
+        }
+    }
+
+    /// Spawns a task the stage waits for on shutdown
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tracker.spawn(task)
+    }
+
+    /// Same as [`Self::spawn`] for blocking work, e.g. the processor loops
+    pub fn spawn_blocking<F, T>(&self, task: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.tracker.spawn_blocking(task)
+    }
+}
+
+/// Shutdown stages, stopped in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Subscriber and mirror feeds, nothing new enters the intake afterwards
+    Intake,
+    /// Gap syncers and the selected chain syncer, persisting where they stopped
+    Syncers,
+    /// Block, acceptance and periodic processors with their helpers, draining their channels
+    Processors,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 3] = [Stage::Intake, Stage::Syncers, Stage::Processors];
+}
+
+#[derive(Debug, Default)]
+pub struct ShutdownController {
+    stages: [Shutdown; 3],
+}
+
+impl ShutdownController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stage(&self, stage: Stage) -> Shutdown {
+        self.stages[stage as usize].clone()
+    }
+
+    /// Stops the stages in order, each once all tasks of the previous one finished
+    pub async fn shutdown(&self) {
+        for stage in Stage::ALL {
+            self.stop(stage).await;
+        }
+    }
+
+    /// Cancels the stage and waits for its tasks, tasks spawned afterwards are not waited for
+    pub async fn stop(&self, stage: Stage) {
+        info!(?stage, "Stopping shutdown stage");
+        let shutdown = &self.stages[stage as usize];
+        shutdown.cancel();
+        shutdown.tracker.close();
+        shutdown.tracker.wait().await;
+        info!(?stage, "Shutdown stage stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_stages_stop_in_order() {
+        let controller = ShutdownController::new();
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        // later stages take less time to stop, so only the order of the stages orders them
+        for (delay, stage) in [
+            (30, Stage::Intake),
+            (20, Stage::Syncers),
+            (10, Stage::Processors),
+        ] {
+            let shutdown = controller.stage(stage);
+            let stopped = stopped.clone();
+            shutdown.clone().spawn(async move {
+                shutdown.cancelled().await;
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                stopped.lock().push(stage);
+            });
+        }
+        let processors = controller.stage(Stage::Processors);
+        let stopped_blocking = stopped.clone();
+        let drained = processors.clone();
+        processors.spawn_blocking(move || {
+            while !drained.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            stopped_blocking.lock().push(Stage::Processors);
+        });
+
+        tokio::task::yield_now().await;
+        assert!(stopped.lock().is_empty());
+        controller.shutdown().await;
+        assert_eq!(
+            *stopped.lock(),
+            vec![
+                Stage::Intake,
+                Stage::Syncers,
+                Stage::Processors,
+                Stage::Processors
+            ]
+        );
+        assert!(
+            Stage::ALL
+                .iter()
+                .all(|stage| controller.stage(*stage).is_cancelled())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_child_cancellation_is_scoped() {
+        let controller = ShutdownController::new();
+        let syncers = controller.stage(Stage::Syncers);
+        let first = syncers.child();
+        let second = syncers.child();
+        first.cancel();
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+        assert!(!syncers.is_cancelled());
+
+        controller.stop(Stage::Syncers).await;
+        second.cancelled().await;
+        assert!(!controller.stage(Stage::Processors).is_cancelled());
+    }
+}
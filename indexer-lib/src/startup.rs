@@ -0,0 +1,506 @@
+//! Sync plan derived on startup from the stored block tip and gaps and the node.
+//!
+//! [`compute_sync_plan`] covers exactly the blocks missing from the index: the pending gaps and
+//! the span from the block tip, or from the pruning point into an empty database, to the node
+//! sink. What lies below the node pruning point can't be fetched anymore, spans reaching below
+//! it are clamped to it and the part below is flagged unrecoverable.
+//!
+//! Before that, [`verify_block_tip`] rolls a block tip without its stored block back to the
+//! latest verified previous tip, the span to the sink then starts from there.
+
+use crate::database::headers::{BlockCompactHeaderPartition, BlockGap, BlockGapsPartition};
+use crate::database::metadata::MetadataPartition;
+use crate::database::processing::ProcessedBlockPartition;
+use crate::historical_syncer::Cursor;
+use anyhow::Result;
+use fjall::TxKeyspace;
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_wrpc_client::KaspaRpcClient;
+use tokio::task;
+use tracing::warn;
+
+/// Range of blocks one historical syncer fills
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncConfig {
+    pub from: Cursor,
+    pub to: Cursor,
+    /// Filling an empty database from the pruning point
+    pub initial: bool,
+}
+
+impl SyncConfig {
+    pub fn gap(&self) -> BlockGap {
+        BlockGap::from_cursors(self.from, self.to)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// Pending gaps by DAA score, then the span to the sink
+    pub syncs: Vec<SyncConfig>,
+    /// Stored gaps replaced by their part above the pruning point
+    pub clamped: Vec<BlockGap>,
+    /// Missing spans below the pruning point
+    pub unrecoverable: Vec<BlockGap>,
+}
+
+impl SyncPlan {
+    /// Records the syncs as gaps, replaces the clamped gaps and marks the unrecoverable spans
+    pub fn record(&self, block_gaps_partition: &BlockGapsPartition) -> Result<()> {
+        for sync in &self.syncs {
+            block_gaps_partition.add_gap(sync.gap())?;
+        }
+        for gap in &self.unrecoverable {
+            block_gaps_partition.add_unrecoverable_gap(gap)?;
+        }
+        for gap in &self.clamped {
+            block_gaps_partition.remove_gap(gap.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of [`verify_block_tip`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TipVerification {
+    /// Block tip to sync from, none when no recorded tip was verified
+    pub tip: Option<Cursor>,
+    /// Tips without their stored block, newest first
+    pub rolled_back: Vec<Cursor>,
+}
+
+/// Checks that the block of the stored tip has its header and processed marker. Otherwise
+/// walks back through the tip history to the latest tip that has both and stores it as the
+/// block tip. The blocks after it are synced again, the ones processed are skipped
+pub fn verify_block_tip(
+    keyspace: &TxKeyspace,
+    metadata_partition: &MetadataPartition,
+    headers: &BlockCompactHeaderPartition,
+    processed_block_partition: &ProcessedBlockPartition,
+) -> Result<TipVerification> {
+    let rtx = keyspace.read_tx();
+    let Some(tip) = metadata_partition.get_latest_block_cursor_rtx(&rtx)? else {
+        return Ok(TipVerification::default());
+    };
+    let is_stored = |cursor: &Cursor| -> Result<bool> {
+        Ok(headers
+            .get_compact_header_rtx(&rtx, &cursor.hash)?
+            .is_some()
+            && processed_block_partition.is_processed_rtx(&rtx, cursor.hash)?)
+    };
+    if is_stored(&tip)? {
+        return Ok(TipVerification {
+            tip: Some(tip),
+            rolled_back: Vec::new(),
+        });
+    }
+
+    let mut verification = TipVerification {
+        tip: None,
+        rolled_back: vec![tip],
+    };
+    for previous in metadata_partition
+        .get_block_tip_history_rtx(&rtx)?
+        .into_iter()
+        .rev()
+        .filter(|previous| previous.blue_work < tip.blue_work)
+    {
+        if is_stored(&previous)? {
+            verification.tip = Some(previous);
+            break;
+        }
+        verification.rolled_back.push(previous);
+    }
+    drop(rtx);
+
+    for cursor in &verification.rolled_back {
+        warn!(
+            "Block tip {} (DAA {}) has no stored block, rolled back",
+            cursor.hash, cursor.daa_score
+        );
+    }
+    match verification.tip {
+        Some(verified) => warn!(
+            "Block tip rolled back to {} (DAA {}), syncing again from there",
+            verified.hash, verified.daa_score
+        ),
+        None => warn!("No recorded block tip has a stored block, the block tip is removed"),
+    }
+    let mut wtx = keyspace.write_tx()?;
+    metadata_partition.roll_back_block_tip(&mut wtx, verification.tip)?;
+    wtx.commit()??;
+    Ok(verification)
+}
+
+/// Reads the pending gaps and fetches the node sink and pruning point, `block_tip` is the
+/// latest processed block
+pub async fn compute_sync_plan(
+    block_tip: Option<Cursor>,
+    block_gaps_partition: &BlockGapsPartition,
+    rpc_client: &KaspaRpcClient,
+) -> Result<SyncPlan> {
+    let gaps = {
+        let block_gaps_partition = block_gaps_partition.clone();
+        task::spawn_blocking(move || {
+            block_gaps_partition
+                .get_all_gaps_since_daa(0)
+                .collect::<Result<Vec<_>>>()
+        })
+        .await??
+    };
+    let info = rpc_client.get_block_dag_info().await?;
+    let sink = rpc_client.get_block(info.sink, false).await?.header;
+    let pruning_point = rpc_client
+        .get_block(info.pruning_point_hash, false)
+        .await?
+        .header;
+    Ok(plan_sync(
+        block_tip,
+        gaps,
+        Cursor::from(&sink),
+        Cursor::from(&pruning_point),
+    ))
+}
+
+/// Spans to sync for the stored `block_tip` and pending `gaps`, up to the node `sink`
+pub fn plan_sync(
+    block_tip: Option<Cursor>,
+    mut gaps: Vec<BlockGap>,
+    sink: Cursor,
+    pruning_point: Cursor,
+) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+    gaps.sort_by_key(|gap| (gap.from_daa_score, gap.from_blue_work));
+    // the span to the sink starts after the gaps, their ends were received already
+    let mut synced_to = block_tip;
+    for gap in gaps {
+        let to = Cursor::new(gap.to_daa_score, gap.to_blue_work, gap.to_block_hash);
+        if synced_to.is_none_or(|synced_to| synced_to.blue_work < to.blue_work) {
+            synced_to = Some(to);
+        }
+        if to.daa_score <= pruning_point.daa_score {
+            plan.unrecoverable.push(gap);
+            continue;
+        }
+        let from = Cursor::new(gap.from_daa_score, gap.from_blue_work, gap.from_block_hash);
+        if from.daa_score < pruning_point.daa_score {
+            plan.unrecoverable
+                .push(BlockGap::from_cursors(from, pruning_point));
+            plan.clamped.push(gap);
+            plan.syncs.push(SyncConfig {
+                from: pruning_point,
+                to,
+                initial: false,
+            });
+            continue;
+        }
+        plan.syncs.push(SyncConfig {
+            from,
+            to,
+            initial: false,
+        });
+    }
+    match synced_to {
+        None if pruning_point.hash != sink.hash => plan.syncs.push(SyncConfig {
+            from: pruning_point,
+            to: sink,
+            initial: true,
+        }),
+        Some(synced_to) if synced_to.blue_work < sink.blue_work => {
+            let from = if synced_to.daa_score < pruning_point.daa_score {
+                plan.unrecoverable
+                    .push(BlockGap::from_cursors(synced_to, pruning_point));
+                pruning_point
+            } else {
+                synced_to
+            };
+            plan.syncs.push(SyncConfig {
+                from,
+                to: sink,
+                initial: false,
+            });
+        }
+        _ => {}
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::metadata::BLOCK_TIP_HISTORY;
+    use kaspa_math::Uint192;
+    use kaspa_rpc_core::RpcHash;
+
+    fn cursor(daa_score: u64) -> Cursor {
+        Cursor::new(
+            daa_score,
+            Uint192::from_u64(daa_score),
+            RpcHash::from_u64_word(daa_score),
+        )
+    }
+
+    fn gap(from: u64, to: u64) -> BlockGap {
+        BlockGap::from_cursors(cursor(from), cursor(to))
+    }
+
+    fn sync(from: u64, to: u64) -> SyncConfig {
+        SyncConfig {
+            from: cursor(from),
+            to: cursor(to),
+            initial: false,
+        }
+    }
+
+    #[test]
+    fn test_fresh_database_syncs_from_pruning_point() {
+        let plan = plan_sync(None, vec![], cursor(5_000), cursor(1_000));
+        assert_eq!(
+            plan.syncs,
+            [SyncConfig {
+                initial: true,
+                ..sync(1_000, 5_000)
+            }]
+        );
+        assert!(plan.clamped.is_empty() && plan.unrecoverable.is_empty());
+
+        // nothing to sync on a node at its pruning point
+        let plan = plan_sync(None, vec![], cursor(1_000), cursor(1_000));
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_clean_restart_syncs_from_tip() {
+        let plan = plan_sync(Some(cursor(4_000)), vec![], cursor(5_000), cursor(1_000));
+        assert_eq!(plan.syncs, [sync(4_000, 5_000)]);
+
+        // the sink did not move
+        let plan = plan_sync(Some(cursor(5_000)), vec![], cursor(5_000), cursor(1_000));
+        assert_eq!(plan, SyncPlan::default());
+    }
+
+    #[test]
+    fn test_crash_restart_resumes_partial_gap() {
+        // a gap recorded on reconnect was partly synced, live blocks went on past its end
+        let plan = plan_sync(
+            Some(cursor(4_500)),
+            vec![gap(3_000, 4_000), gap(2_000, 2_500)],
+            cursor(5_000),
+            cursor(1_000),
+        );
+        assert_eq!(
+            plan.syncs,
+            [sync(2_000, 2_500), sync(3_000, 4_000), sync(4_500, 5_000)]
+        );
+
+        // crashed before any live block, the tip is behind the end of the gap
+        let plan = plan_sync(
+            Some(cursor(3_000)),
+            vec![gap(3_000, 4_000)],
+            cursor(5_000),
+            cursor(1_000),
+        );
+        assert_eq!(plan.syncs, [sync(3_000, 4_000), sync(4_000, 5_000)]);
+
+        // crashed during the initial backfill
+        let plan = plan_sync(None, vec![gap(1_200, 4_000)], cursor(5_000), cursor(1_000));
+        assert_eq!(plan.syncs, [sync(1_200, 4_000), sync(4_000, 5_000)]);
+    }
+
+    #[test]
+    fn test_offline_past_pruning_point() {
+        let plan = plan_sync(
+            Some(cursor(1_500)),
+            vec![gap(500, 800), gap(900, 1_400)],
+            cursor(9_000),
+            cursor(1_000),
+        );
+        assert_eq!(plan.syncs, [sync(1_000, 1_400), sync(1_500, 9_000)]);
+        assert_eq!(plan.clamped, [gap(900, 1_400)]);
+        assert_eq!(plan.unrecoverable, [gap(500, 800), gap(900, 1_000)]);
+
+        // the tip itself is below the pruning point
+        let plan = plan_sync(Some(cursor(700)), vec![], cursor(9_000), cursor(1_000));
+        assert_eq!(plan.syncs, [sync(1_000, 9_000)]);
+        assert_eq!(plan.unrecoverable, [gap(700, 1_000)]);
+        assert!(plan.clamped.is_empty());
+    }
+
+    struct Database {
+        keyspace: TxKeyspace,
+        metadata: MetadataPartition,
+        processed: ProcessedBlockPartition,
+    }
+
+    impl Database {
+        fn open(name: &str) -> Self {
+            let keyspace = crate::database::test_keyspace(&format!("startup-{name}"));
+            Self {
+                metadata: MetadataPartition::new(&keyspace).unwrap(),
+                processed: ProcessedBlockPartition::new(&keyspace).unwrap(),
+                keyspace,
+            }
+        }
+
+        /// Header, marker and tip, the tip write is lost unless `commit_tip`
+        fn index(&self, block: Cursor, commit_tip: bool) {
+            self.headers()
+                .insert_compact_header(&block.hash, block.blue_work, block.daa_score)
+                .unwrap();
+            let mut wtx = self.keyspace.write_tx().unwrap();
+            self.processed
+                .mark_wtx(&mut wtx, block.hash, block.daa_score);
+            wtx.commit().unwrap().unwrap();
+            let mut wtx = self.keyspace.write_tx().unwrap();
+            self.metadata.set_block_tip(&mut wtx, block).unwrap();
+            if commit_tip {
+                wtx.commit().unwrap().unwrap();
+            }
+        }
+
+        /// Tip committed, the data of the block lost
+        fn tip_only(&self, block: Cursor) {
+            let mut wtx = self.keyspace.write_tx().unwrap();
+            self.metadata.set_block_tip(&mut wtx, block).unwrap();
+            wtx.commit().unwrap().unwrap();
+        }
+
+        /// Opened again like after a restart, with an empty header cache
+        fn headers(&self) -> BlockCompactHeaderPartition {
+            BlockCompactHeaderPartition::new(&self.keyspace).unwrap()
+        }
+
+        fn verify(&self) -> TipVerification {
+            verify_block_tip(
+                &self.keyspace,
+                &self.metadata,
+                &self.headers(),
+                &self.processed,
+            )
+            .unwrap()
+        }
+
+        fn stored_tip(&self) -> Option<Cursor> {
+            self.metadata.get_latest_block_cursor().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_crash_with_tip_ahead_of_data() {
+        let db = Database::open("tip-ahead");
+        for daa_score in 1..=3 {
+            db.index(cursor(daa_score), true);
+        }
+        // 4 got its header but no processed marker, 5 nothing but the tip
+        db.headers()
+            .insert_compact_header(&cursor(4).hash, cursor(4).blue_work, 4)
+            .unwrap();
+        db.tip_only(cursor(4));
+        db.tip_only(cursor(5));
+        assert_eq!(db.stored_tip(), Some(cursor(5)));
+
+        let verification = db.verify();
+        assert_eq!(
+            verification,
+            TipVerification {
+                tip: Some(cursor(3)),
+                rolled_back: vec![cursor(5), cursor(4)],
+            }
+        );
+        assert_eq!(db.stored_tip(), Some(cursor(3)));
+        assert_eq!(
+            db.metadata
+                .get_block_tip_history_rtx(&db.keyspace.read_tx())
+                .unwrap(),
+            [cursor(1), cursor(2), cursor(3)]
+        );
+        // the span to the sink covers the rolled back blocks again
+        let plan = plan_sync(verification.tip, vec![], cursor(10), cursor(0));
+        assert_eq!(plan.syncs, [sync(3, 10)]);
+
+        // verified now, the tip moves forward again from there
+        assert!(db.verify().rolled_back.is_empty());
+        db.index(cursor(4), true);
+        assert_eq!(db.stored_tip(), Some(cursor(4)));
+
+        // no recorded tip has its block, everything is synced again
+        let db = Database::open("tip-ahead-empty");
+        db.tip_only(cursor(7));
+        let verification = db.verify();
+        assert_eq!(verification.tip, None);
+        assert_eq!(verification.rolled_back, [cursor(7)]);
+        assert_eq!(db.stored_tip(), None);
+    }
+
+    #[test]
+    fn test_crash_with_data_ahead_of_tip() {
+        let db = Database::open("data-ahead");
+        db.index(cursor(1), true);
+        // 2 and 3 were committed, the tip writes after them were lost
+        db.index(cursor(2), false);
+        db.index(cursor(3), false);
+        assert_eq!(db.stored_tip(), Some(cursor(1)));
+
+        // the tip stays, 2 and 3 are received again and skipped as processed
+        assert_eq!(
+            db.verify(),
+            TipVerification {
+                tip: Some(cursor(1)),
+                rolled_back: Vec::new(),
+            }
+        );
+        assert_eq!(db.stored_tip(), Some(cursor(1)));
+        assert!(db.processed.is_processed(cursor(3).hash).unwrap());
+    }
+
+    #[test]
+    fn test_tip_history_is_capped() {
+        let db = Database::open("tip-history");
+        for daa_score in 1..=BLOCK_TIP_HISTORY as u64 + 5 {
+            db.tip_only(cursor(daa_score));
+        }
+        // a tip behind the current one is not recorded
+        db.tip_only(cursor(2));
+        let history = db
+            .metadata
+            .get_block_tip_history_rtx(&db.keyspace.read_tx())
+            .unwrap();
+        assert_eq!(history.len(), BLOCK_TIP_HISTORY);
+        assert_eq!(history.first(), Some(&cursor(6)));
+        assert_eq!(history.last(), Some(&cursor(BLOCK_TIP_HISTORY as u64 + 5)));
+    }
+
+    #[test]
+    fn test_record_plan() {
+        let keyspace = crate::database::test_keyspace("startup");
+        let gaps = BlockGapsPartition::new(&keyspace).unwrap();
+        gaps.add_gap(gap(500, 800)).unwrap();
+        gaps.add_gap(gap(900, 1_400)).unwrap();
+        let stored = gaps
+            .get_all_gaps_since_daa(0)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        let plan = plan_sync(Some(cursor(1_500)), stored, cursor(9_000), cursor(1_000));
+        plan.record(&gaps).unwrap();
+
+        let pending = gaps
+            .get_all_gaps_since_daa(0)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(pending, [gap(1_000, 1_400), gap(1_500, 9_000)]);
+        let all = gaps
+            .get_all_gaps_rtx(&keyspace.read_tx())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            all,
+            [
+                gap(500, 800),
+                gap(900, 1_000),
+                gap(1_000, 1_400),
+                gap(1_500, 9_000)
+            ]
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! How far the index is behind the node, and a snapshot of what the running indexer is doing.
+
+use crate::TARGET_BLOCKS_PER_SECOND;
+use crate::database::headers::{BlockGapsPartition, GapHistoryPartition, GapHistoryRecord};
+use crate::database::metadata::MetadataPartition;
+use crate::historical_syncer::{ActiveSyncers, Cursor, SyncerProgress};
+use crate::metrics::SharedMetrics;
+use crate::node_pool::NodePool;
+use crate::supervisor::Supervisor;
+use anyhow::Result;
+use fjall::{ReadTransaction, TxKeyspace};
+use kaspa_math::Uint192;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Gap history records included in a status snapshot
+pub const STATUS_GAP_HISTORY: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// DAA score of the node tip
+    pub node_daa_score: u64,
+    /// Latest processed block, none before the first one
+    pub block_tip: Option<Cursor>,
+    /// Latest accepting block of the virtual chain processor, none before the first one
+    pub vcp_tip: Option<Cursor>,
+    /// DAA score the block tip is behind the node, all of it before the first block
+    pub block_lag_daa: u64,
+    /// DAA score the acceptance is behind the node, all of it before the first chain block
+    pub acceptance_lag_daa: u64,
+    /// The larger lag at the target block rate
+    pub lag: Duration,
+}
+
+impl SyncStatus {
+    pub fn new(node_daa_score: u64, block_tip: Option<Cursor>, vcp_tip: Option<Cursor>) -> Self {
+        let lag_daa =
+            |tip: Option<Cursor>| node_daa_score.saturating_sub(tip.map_or(0, |tip| tip.daa_score));
+        let block_lag_daa = lag_daa(block_tip);
+        let acceptance_lag_daa = lag_daa(vcp_tip);
+        Self {
+            node_daa_score,
+            block_tip,
+            vcp_tip,
+            block_lag_daa,
+            acceptance_lag_daa,
+            lag: Duration::from_secs(
+                block_lag_daa.max(acceptance_lag_daa) / TARGET_BLOCKS_PER_SECOND,
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SyncStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tip = |tip: &Option<Cursor>| match tip {
+            Some(tip) => format!("{} (DAA {})", tip.hash, tip.daa_score),
+            None => "none".to_string(),
+        };
+        writeln!(f, "Node DAA score: {}", self.node_daa_score)?;
+        writeln!(
+            f,
+            "Block tip: {}, {} DAA behind",
+            tip(&self.block_tip),
+            self.block_lag_daa
+        )?;
+        writeln!(
+            f,
+            "Acceptance tip: {}, {} DAA behind",
+            tip(&self.vcp_tip),
+            self.acceptance_lag_daa
+        )?;
+        write!(f, "Estimated lag: {}s", self.lag.as_secs())
+    }
+}
+
+/// Lag of the indexed tips behind `node_daa_score`, e.g. of the node sink
+pub fn get_status(
+    metadata_partition: &MetadataPartition,
+    rtx: &ReadTransaction,
+    node_daa_score: u64,
+) -> Result<SyncStatus> {
+    Ok(SyncStatus::new(
+        node_daa_score,
+        metadata_partition.get_latest_block_cursor_rtx(rtx)?,
+        metadata_partition.get_latest_accepting_block_cursor_rtx(rtx)?,
+    ))
+}
+
+/// Handles of the running indexer a status snapshot is read from
+#[derive(Clone, bon::Builder)]
+pub struct Indexer {
+    tx_keyspace: TxKeyspace,
+    metadata_partition: MetadataPartition,
+    block_gaps_partition: BlockGapsPartition,
+    gap_history_partition: Option<GapHistoryPartition>,
+    metrics: SharedMetrics,
+    /// Virtual DAA score last reported by the node
+    virtual_daa: Arc<AtomicU64>,
+    #[builder(default)]
+    active_syncers: ActiveSyncers,
+    /// Resolver nodes, the primary node included
+    node_pool: Option<NodePool>,
+    #[builder(default)]
+    supervisor: Supervisor,
+}
+
+impl Indexer {
+    /// Reads cached values and the local database only, the node is not called
+    pub fn status(&self) -> Result<IndexerStatus> {
+        let metrics = self.metrics.snapshot();
+        let rtx = self.tx_keyspace.read_tx();
+        let sync = get_status(
+            &self.metadata_partition,
+            &rtx,
+            self.virtual_daa.load(Ordering::Relaxed),
+        )?;
+        let gaps = self
+            .block_gaps_partition
+            .get_all_gaps_rtx(&rtx)
+            .map(|gap| {
+                gap.map(|gap| GapStatus {
+                    from_daa_score: gap.from_daa_score,
+                    to_daa_score: gap.to_daa_score,
+                    daa_span: gap.to_daa_score.saturating_sub(gap.from_daa_score),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let gap_history = match &self.gap_history_partition {
+            Some(partition) => partition
+                .list_gap_history(STATUS_GAP_HISTORY)?
+                .iter()
+                .map(GapHistoryStatus::from)
+                .collect(),
+            None => Vec::new(),
+        };
+        let syncers = self.active_syncers.snapshot();
+        Ok(IndexerStatus {
+            node_connected: metrics.node_connected == 1,
+            nodes: self
+                .node_pool
+                .iter()
+                .flat_map(NodePool::health)
+                .map(|(url, health)| NodeStatus {
+                    url: url.to_string(),
+                    reachable: health.latency.is_some(),
+                    latency_ms: health.latency.map(|latency| latency.as_millis() as u64),
+                    is_synced: health.is_synced,
+                    network_id: health.network_id,
+                })
+                .collect(),
+            components: self
+                .supervisor
+                .health()
+                .into_iter()
+                .map(|(name, health)| ComponentStatus {
+                    name: name.to_string(),
+                    restarts: health.restarts,
+                    last_error: health.last_error,
+                })
+                .collect(),
+            phase: SyncPhase::of(&syncers),
+            syncers: syncers.iter().map(SyncerStatus::from).collect(),
+            node_daa_score: sync.node_daa_score,
+            block_tip: sync.block_tip.map(TipStatus::from),
+            vcp_tip: sync.vcp_tip.map(TipStatus::from),
+            block_lag_daa: sync.block_lag_daa,
+            acceptance_lag_daa: sync.acceptance_lag_daa,
+            lag_seconds: sync.lag.as_secs(),
+            gaps,
+            gap_history,
+            channels: ChannelDepths {
+                block_intake: metrics.block_intake_depth,
+                subscriber_intake: metrics.subscriber_intake_depth,
+                historical_intake: metrics.historical_intake_depth,
+            },
+            partitions: metrics
+                .database
+                .partitions
+                .iter()
+                .map(|partition| PartitionStatus {
+                    name: partition.name.clone(),
+                    approximate_keys: partition.approximate_keys,
+                    disk_bytes: partition.disk_bytes,
+                })
+                .collect(),
+            disk_bytes: metrics.database.disk_bytes,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexerStatus {
+    /// Whether the subscriber is connected to the primary node
+    pub node_connected: bool,
+    /// As of the last health check
+    pub nodes: Vec<NodeStatus>,
+    /// Supervised processors
+    pub components: Vec<ComponentStatus>,
+    pub phase: SyncPhase,
+    pub syncers: Vec<SyncerStatus>,
+    pub node_daa_score: u64,
+    pub block_tip: Option<TipStatus>,
+    pub vcp_tip: Option<TipStatus>,
+    pub block_lag_daa: u64,
+    pub acceptance_lag_daa: u64,
+    pub lag_seconds: u64,
+    /// Recorded gaps, the ones being filled included
+    pub gaps: Vec<GapStatus>,
+    /// Latest gap syncer events, newest first
+    pub gap_history: Vec<GapHistoryStatus>,
+    pub channels: ChannelDepths,
+    /// As of the last database stats refresh
+    pub partitions: Vec<PartitionStatus>,
+    pub disk_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPhase {
+    /// Filling the range from the pruning point into an empty database
+    InitialBackfill,
+    GapFilling,
+    /// Following notifications only
+    Realtime,
+}
+
+impl SyncPhase {
+    fn of(syncers: &[SyncerProgress]) -> Self {
+        if syncers.iter().any(|syncer| syncer.initial) {
+            SyncPhase::InitialBackfill
+        } else if !syncers.is_empty() {
+            SyncPhase::GapFilling
+        } else {
+            SyncPhase::Realtime
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    pub url: String,
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub is_synced: bool,
+    pub network_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub restarts: u32,
+    /// Error or panic message of the last failed run
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncerStatus {
+    pub initial: bool,
+    pub from_daa_score: u64,
+    pub current_daa_score: u64,
+    pub target_daa_score: u64,
+    /// Share of the blue work between start and target synced
+    pub progress_percent: u64,
+    pub blocks_processed: u64,
+    pub batches_processed: u64,
+    pub anticone_candidates: usize,
+    pub wait_for_slot_ms: u64,
+    pub rpc_latency_ms: u64,
+}
+
+impl From<&SyncerProgress> for SyncerStatus {
+    fn from(progress: &SyncerProgress) -> Self {
+        let zero = Uint192::from_u64(0);
+        let since_start = |work: Uint192| {
+            if work > progress.from.blue_work {
+                work - progress.from.blue_work
+            } else {
+                zero
+            }
+        };
+        let (total, synced) = (
+            since_start(progress.target.blue_work),
+            since_start(progress.current.blue_work),
+        );
+        let progress_percent = if total > zero {
+            (synced.as_u128() * 100 / total.as_u128()).min(100) as u64
+        } else {
+            100
+        };
+        Self {
+            initial: progress.initial,
+            from_daa_score: progress.from.daa_score,
+            current_daa_score: progress.current.daa_score,
+            target_daa_score: progress.target.daa_score,
+            progress_percent,
+            blocks_processed: progress.stats.total_blocks_processed,
+            batches_processed: progress.stats.batches_processed,
+            anticone_candidates: progress.stats.anticone_candidates_count,
+            wait_for_slot_ms: progress.stats.wait_for_slot.as_millis() as u64,
+            rpc_latency_ms: progress.stats.rpc_latency.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TipStatus {
+    pub hash: String,
+    pub daa_score: u64,
+}
+
+impl From<Cursor> for TipStatus {
+    fn from(cursor: Cursor) -> Self {
+        Self {
+            hash: cursor.hash.to_string(),
+            daa_score: cursor.daa_score,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GapStatus {
+    pub from_daa_score: u64,
+    pub to_daa_score: u64,
+    pub daa_span: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GapHistoryStatus {
+    pub recorded_at_ms: u64,
+    pub event: String,
+    pub from_daa_score: u64,
+    pub to_daa_score: u64,
+    pub started_at_ms: u64,
+    pub current_daa_score: u64,
+    pub blocks_processed: u64,
+    pub batches_processed: u64,
+    pub retries: u32,
+    pub error: Option<String>,
+}
+
+impl From<&GapHistoryRecord> for GapHistoryStatus {
+    fn from(record: &GapHistoryRecord) -> Self {
+        Self {
+            recorded_at_ms: record.recorded_at_ms,
+            event: record.event.to_string(),
+            from_daa_score: record.from_daa_score,
+            to_daa_score: record.to_daa_score,
+            started_at_ms: record.started_at_ms,
+            current_daa_score: record.current_daa_score,
+            blocks_processed: record.blocks_processed,
+            batches_processed: record.batches_processed,
+            retries: record.retries,
+            error: record.error.clone(),
+        }
+    }
+}
+
+/// Blocks waiting in the block processor intake
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelDepths {
+    pub block_intake: u64,
+    pub subscriber_intake: u64,
+    pub historical_intake: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PartitionStatus {
+    pub name: String,
+    pub approximate_keys: u64,
+    pub disk_bytes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::historical_syncer::SyncStats;
+    use kaspa_rpc_core::RpcHash;
+
+    #[test]
+    fn test_sync_status_lag() {
+        let cursor = |daa_score| Cursor::new(daa_score, Default::default(), RpcHash::default());
+
+        let status = SyncStatus::new(10_000, Some(cursor(9_950)), Some(cursor(9_400)));
+        assert_eq!(status.block_lag_daa, 50);
+        assert_eq!(status.acceptance_lag_daa, 600);
+        assert_eq!(status.lag, Duration::from_secs(60));
+
+        // tips ahead of a stale node score don't underflow
+        let status = SyncStatus::new(100, Some(cursor(120)), None);
+        assert_eq!(status.block_lag_daa, 0);
+        assert_eq!(status.acceptance_lag_daa, 100);
+    }
+
+    #[test]
+    fn test_syncer_status() {
+        let cursor = |daa_score| {
+            Cursor::new(
+                daa_score,
+                Uint192::from_u64(daa_score * 10),
+                RpcHash::default(),
+            )
+        };
+        let progress = |initial| SyncerProgress {
+            from: cursor(100),
+            current: cursor(130),
+            target: cursor(200),
+            initial,
+            stats: SyncStats {
+                total_blocks_processed: 30,
+                batches_processed: 1,
+                current_blue_work: Uint192::from_u64(1300),
+                target_blue_work: Uint192::from_u64(2000),
+                anticone_candidates_count: 0,
+                wait_for_slot: Duration::from_millis(5),
+                rpc_latency: Duration::from_millis(40),
+            },
+        };
+        assert_eq!(SyncPhase::of(&[]), SyncPhase::Realtime);
+        assert_eq!(SyncPhase::of(&[progress(false)]), SyncPhase::GapFilling);
+        assert_eq!(
+            SyncPhase::of(&[progress(false), progress(true)]),
+            SyncPhase::InitialBackfill
+        );
+
+        let status = SyncerStatus::from(&progress(false));
+        assert_eq!(status.progress_percent, 30);
+        assert_eq!(status.rpc_latency_ms, 40);
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["current_daa_score"], 130);
+        assert_eq!(
+            serde_json::to_value(SyncPhase::GapFilling).unwrap(),
+            "gap_filling"
+        );
+    }
+}
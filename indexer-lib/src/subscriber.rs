@@ -1,33 +1,73 @@
 use crate::BlockOrMany;
 use crate::RK_PRUNING_DEPTH;
-use crate::database::headers::{BlockGap, BlockGapsPartition};
-use crate::historical_syncer::{Cursor, HistoricalDataSyncer};
+use crate::call_limiter::CallLimiter;
+use crate::database::headers::{BlockGap, BlockGapsPartition, GapHistoryPartition};
+use crate::database::metadata::MetadataPartition;
+use crate::database::provenance::{ProvenancePartition, ProvenanceRecord};
+use crate::fifo_set::{FastHasher, FifoSet};
+use crate::fixture::FixtureRecorder;
+use crate::historical_syncer::{ActiveSyncers, Cursor, HistoricalDataSyncer, IntakeStallWarning};
+use crate::ingest_trace::{TRACE_TARGET, TraceContext};
+use crate::metrics::{SharedMetrics, create_shared_metrics};
+use crate::mirror_feed::{MirrorBlock, MirrorFeed, healthiest_node};
+use crate::node_capabilities::{NodeCapabilities, NodeIncompatible, SharedNodeCapabilities};
+use crate::reorder_buffer::{
+    DEFAULT_REORDER_CAPACITY, DEFAULT_REORDER_WINDOW, Released, ReorderBuffer,
+};
+use crate::rpc_dispatcher::RpcDispatcher;
+use crate::rpc_transport::RpcNode;
 use crate::selected_chain_syncer::Intake;
+use crate::shutdown::Shutdown;
+use crate::startup;
 use anyhow::Context;
 use futures_util::future::FutureExt;
+use kaspa_math::Uint192;
 use kaspa_rpc_core::api::ctl::RpcState;
 use kaspa_rpc_core::api::rpc::RpcApi;
 use kaspa_rpc_core::notify::connection::{ChannelConnection, ChannelType};
-use kaspa_rpc_core::{BlockAddedNotification, Notification};
+use kaspa_rpc_core::{BlockAddedNotification, Notification, RpcBlock, RpcHash};
 use kaspa_wrpc_client::KaspaRpcClient;
 use kaspa_wrpc_client::client::ConnectOptions;
 use kaspa_wrpc_client::prelude::{
-    BlockAddedScope, ListenerId, Scope, VirtualChainChangedScope, VirtualDaaScoreChangedScope,
+    BlockAddedScope, ListenerId, PruningPointUtxoSetOverrideScope, Scope, VirtualChainChangedScope,
+    VirtualDaaScoreChangedScope,
 };
 use std::sync::Arc;
 use std::sync::atomic::AtomicU64;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::task;
-use tracing::{error, info, warn};
-use workflow_core::channel::Channel;
+use tracing::{debug, debug_span, error, info, warn};
+use workflow_core::channel::{Channel, Sender};
+
+pub const DEFAULT_STALENESS_THRESHOLD: Duration = Duration::from_secs(30);
+/// The watchdog checks three times per threshold, shorter thresholds would resubscribe on
+/// every gap between blocks
+pub const MIN_STALENESS_THRESHOLD: Duration = Duration::from_secs(3);
+/// Notified blocks the block processor intake holds
+pub const DEFAULT_REALTIME_INTAKE_CAPACITY: usize = 4096;
+/// Gap syncer batches the block processor intake holds
+pub const DEFAULT_HISTORICAL_INTAKE_CAPACITY: usize = 64;
+/// Added block hashes are remembered this long to drop notifications already received from
+/// another node
+const SEEN_BLOCKS_TTL: Duration = Duration::from_secs(300);
+/// Bounds the memory during bursts, far above the blocks within the TTL at the target rate
+const SEEN_BLOCKS_CAPACITY: usize = 65_536;
+
+/// The pruning point is polled this often besides the override notification,
+/// which only fires when the node replaces its UTXO set
+const PRUNING_POINT_CHECK_INTERVAL_DAA: u64 = 600;
 
 pub struct Subscriber {
     /// RPC client for communicating with Kaspa node
     rpc_client: KaspaRpcClient,
+    /// Same node as `rpc_client`, for the gap syncers
+    rpc_node: RpcNode,
+    /// Url of the node, sent along with its blocks
+    node: Arc<str>,
     /// Channel to send processed blocks to handler
     block_handler: flume::Sender<BlockOrMany>,
-    /// Shutdown signal receiver
-    shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+    /// Cancelled when the intake stops
+    shutdown: Shutdown,
     // channel supplied to the notification subsystem
     // to receive the node notifications we subscribe to
     notification_channel: Channel<Notification>,
@@ -37,58 +77,241 @@ pub struct Subscriber {
     listener_id: Option<ListenerId>,
 
     last_block_cursor: Option<Cursor>,
+    /// Delivers added blocks by (blue work, hash) instead of arrival order
+    /// Blocks with the time their notification was received
+    reorder_buffer: ReorderBuffer<(Uint192, RpcHash), (Arc<RpcBlock>, Instant, Arc<str>)>,
+    /// Block notifications are dropped from this block handler depth on
+    intake_high_water: usize,
+    /// and forwarded again once the depth fell to this one
+    intake_low_water: usize,
+    overflow: Option<Overflow>,
+    seen_blocks: FifoSet<RpcHash, FastHasher>,
+
+    /// Additional nodes only feeding added blocks, connected once the task starts
+    mirror_clients: Vec<KaspaRpcClient>,
+    /// Mirror nodes by url, gap syncers prefer the healthiest of them and the primary one
+    mirror_nodes: Vec<(Arc<str>, RpcNode)>,
+    mirror_blocks: Option<tokio::sync::mpsc::Receiver<MirrorBlock>>,
+    /// Children of the subscriber's shutdown, stopped with it or on their own
+    mirror_feeds: Vec<(Shutdown, task::JoinHandle<anyhow::Result<()>>)>,
 
-    historical_data_syncer_shutdown_tx: Vec<tokio::sync::oneshot::Sender<()>>,
+    /// Parent of the gap syncer tokens, cancelled alone when the node turns out incompatible
+    syncers_shutdown: Shutdown,
+    active_syncers: ActiveSyncers,
+    /// Start of the backfill into an empty database, the pruning point at the first connect
+    initial_backfill_from: Option<RpcHash>,
 
     block_gaps_partition: BlockGapsPartition,
+    provenance_partition: ProvenancePartition,
 
     selected_chain_syncer: tokio::sync::mpsc::Sender<Intake>,
 
     virtual_daa: Arc<AtomicU64>,
 
+    node_capabilities: SharedNodeCapabilities,
+
+    /// Shares the RPC connection fairly between historical syncers
+    rpc_dispatcher: RpcDispatcher,
+
     had_first_connect: bool,
+    connected: bool,
+
+    /// Without block notifications for this long the subscription is checked against the node
+    staleness_threshold: Duration,
+    last_block_notification_at: Instant,
+
+    metrics: SharedMetrics,
+
+    /// Receives node pruning point moves
+    periodic_processor: Option<Sender<crate::periodic_processor::Notification>>,
+    last_pruning_point: Option<RpcHash>,
+    next_pruning_point_check_daa: u64,
+
+    /// Gaps the block processor wants filled, e.g. the missing parents of orphans
+    backfill_requests: Option<tokio::sync::mpsc::Receiver<BlockGap>>,
+
+    /// Holds the node requirements of the database, none skips the compatibility check
+    metadata_partition: Option<MetadataPartition>,
+    /// Intake of the gap syncer batches, the block handler when not set
+    historical_intake: Option<flume::Sender<BlockOrMany>>,
+    stall_warning: Option<IntakeStallWarning>,
+    /// Where the gap syncers record their start, checkpoints and outcome
+    gap_history: Option<GapHistoryPartition>,
+    /// Records the notifications, the calls are recorded by `rpc_node`
+    fixture_recorder: Option<FixtureRecorder>,
 }
 
 impl Subscriber {
     pub fn new(
         rpc_client: KaspaRpcClient,
         block_handler: flume::Sender<BlockOrMany>,
-        shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+        shutdown: Shutdown,
         block_gaps_partition: BlockGapsPartition,
+        provenance_partition: ProvenancePartition,
         selected_chain_syncer: tokio::sync::mpsc::Sender<Intake>,
         last_block_cursor: Option<Cursor>,
         virtual_daa: Arc<AtomicU64>,
+        node_capabilities: SharedNodeCapabilities,
+        rpc_dispatcher: RpcDispatcher,
     ) -> Self {
         let notification_channel = Channel::bounded(256);
+        let intake_capacity = block_handler.capacity().unwrap_or(usize::MAX);
 
         Self {
+            rpc_node: RpcNode::from(rpc_client.clone()),
+            node: Arc::from(rpc_client.url().unwrap_or_else(|| "primary".to_string())),
             rpc_client,
             block_handler,
-            shutdown_rx,
+            syncers_shutdown: shutdown.child(),
+            shutdown,
             notification_channel,
             listener_id: None,
             last_block_cursor,
-            historical_data_syncer_shutdown_tx: Vec::new(),
+            reorder_buffer: ReorderBuffer::new(DEFAULT_REORDER_WINDOW, DEFAULT_REORDER_CAPACITY),
+            intake_high_water: intake_capacity - intake_capacity / 4,
+            intake_low_water: intake_capacity / 4,
+            overflow: None,
+            seen_blocks: FifoSet::with_hasher(SEEN_BLOCKS_CAPACITY, FastHasher::default())
+                .with_ttl(SEEN_BLOCKS_TTL),
+            mirror_clients: Vec::new(),
+            mirror_nodes: Vec::new(),
+            mirror_blocks: None,
+            mirror_feeds: Vec::new(),
+            active_syncers: Default::default(),
+            initial_backfill_from: None,
             block_gaps_partition,
+            provenance_partition,
             selected_chain_syncer,
             virtual_daa,
+            node_capabilities,
+            rpc_dispatcher,
             had_first_connect: false,
+            connected: false,
+            staleness_threshold: DEFAULT_STALENESS_THRESHOLD,
+            last_block_notification_at: Instant::now(),
+            metrics: create_shared_metrics(),
+            periodic_processor: None,
+            last_pruning_point: None,
+            next_pruning_point_check_daa: 0,
+            backfill_requests: None,
+            metadata_partition: None,
+            historical_intake: None,
+            stall_warning: None,
+            gap_history: None,
+            fixture_recorder: None,
         }
     }
 
+    /// Holds added blocks up to `window` or `capacity` blocks to deliver them by blue work
+    pub fn with_reorder_window(mut self, window: Duration, capacity: usize) -> Self {
+        self.reorder_buffer = ReorderBuffer::new(window, capacity);
+        self
+    }
+
+    /// Resubscribes when no block notification arrived for `threshold` while the sink moved
+    pub fn with_staleness_threshold(mut self, threshold: Duration) -> Self {
+        self.staleness_threshold = threshold;
+        self
+    }
+
+    /// Forwards node pruning point moves to the periodic processor
+    pub fn with_periodic_processor(
+        mut self,
+        periodic_processor: Sender<crate::periodic_processor::Notification>,
+    ) -> Self {
+        self.periodic_processor = Some(periodic_processor);
+        self
+    }
+
+    /// Also subscribes to added blocks of these nodes, a block is forwarded from whichever
+    /// node announces it first
+    pub fn with_mirror_nodes(mut self, mirror_clients: Vec<KaspaRpcClient>) -> Self {
+        self.mirror_clients = mirror_clients;
+        self
+    }
+
+    /// Hands the gap syncer batches to their own intake so they can't take the room of notified
+    /// blocks, a syncer waiting on it past the warning threshold logs why
+    pub fn with_historical_intake(
+        mut self,
+        historical_intake: flume::Sender<BlockOrMany>,
+        stall_warning: IntakeStallWarning,
+    ) -> Self {
+        self.historical_intake = Some(historical_intake);
+        self.stall_warning = Some(stall_warning);
+        self
+    }
+
+    /// Spawns a historical syncer for every gap received
+    pub fn with_backfill_requests(
+        mut self,
+        backfill_requests: tokio::sync::mpsc::Receiver<BlockGap>,
+    ) -> Self {
+        self.backfill_requests = Some(backfill_requests);
+        self
+    }
+
+    /// Records the network and version requirements with the first connect and stops the task
+    /// on every connect to a node not meeting them or not on the network of the database
+    pub fn with_node_requirements(mut self, metadata_partition: MetadataPartition) -> Self {
+        self.metadata_partition = Some(metadata_partition);
+        self
+    }
+
+    /// Limits the calls of the gap syncers together with everything else sharing `limiter`
+    pub fn with_call_limiter(mut self, limiter: CallLimiter) -> Self {
+        self.rpc_node = self.rpc_node.with_limiter(limiter);
+        self
+    }
+
+    /// Records the notifications and the calls of the gap syncers into a fixture
+    pub fn with_fixture_recorder(mut self, recorder: FixtureRecorder) -> Self {
+        self.rpc_node = self.rpc_node.with_recorder(recorder.clone());
+        self.fixture_recorder = Some(recorder);
+        self
+    }
+
+    /// Records reconnects and the gaps they leave into shared metrics
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Gap syncers stop with `shutdown` instead of the subscriber, e.g. with a later shutdown
+    /// stage
+    pub fn with_syncers_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.syncers_shutdown = shutdown.child();
+        self
+    }
+
+    /// Publishes the progress of the gap syncers
+    pub fn with_active_syncers(mut self, active_syncers: ActiveSyncers) -> Self {
+        self.active_syncers = active_syncers;
+        self
+    }
+
+    /// Records the history of every gap syncer
+    pub fn with_gap_history(mut self, gap_history: GapHistoryPartition) -> Self {
+        self.gap_history = Some(gap_history);
+        self
+    }
+
     pub async fn task(&mut self) -> anyhow::Result<()> {
         let rpc_ctl_channel = self.rpc_client.rpc_ctl().multiplexer().channel();
+        let mut watchdog =
+            tokio::time::interval((self.staleness_threshold / 3).max(Duration::from_secs(1)));
+        self.spawn_mirror_feeds();
         loop {
             tokio::select! {
             biased;
-                shutdown_result = &mut self.shutdown_rx => {
-                    shutdown_result
-                    .inspect(|_| info!("Shutdown signal received, stopping subscriber task"))
-                    .inspect_err(|e|  warn!("Shutdown receiver error: {}", e))?;
-                    for shutdown in std::mem::take(&mut self.historical_data_syncer_shutdown_tx) {
-                        _ = shutdown.send(()).inspect_err(|_err| error!("Error sending shutdown signal"));
-                        // todo wait for their responses
+                _ = self.shutdown.cancelled() => {
+                    info!("Shutdown signal received, stopping subscriber task");
+                    let buffered = self.reorder_buffer.flush();
+                    if let Err(err) = self.forward_blocks(buffered).await {
+                        error!("Error while flushing buffered blocks: {err}");
                     }
+                    // the gap syncers stop with their own stage
+                    self.stop_mirror_feeds().await;
                     return Ok(())
                 }
                 msg = rpc_ctl_channel.receiver.recv().fuse() => {
@@ -99,6 +322,12 @@ impl Subscriber {
                             match msg {
                                 RpcState::Connected => {
                                     if let Err(err) = self.handle_connect().await {
+                                        if err.is::<NodeIncompatible>() {
+                                            error!("Refusing to index from this node: {err}");
+                                            self.syncers_shutdown.cancel();
+                                            self.stop_mirror_feeds().await;
+                                            return Err(err);
+                                        }
                                         error!("Error in connect handler: {err}");
                                     }
                                 },
@@ -130,125 +359,260 @@ impl Subscriber {
                         }
                     }
                 },
+                Some(mirrored) = recv_mirror_block(&mut self.mirror_blocks) => {
+                    if let Err(err) = self.handle_mirror_block(mirrored).await {
+                        error!("Error while handling mirror block: {err}");
+                    }
+                },
+                Some(gap) = recv_backfill_request(&mut self.backfill_requests) => {
+                    info!("Backfill requested: {gap:?}");
+                    if let Err(err) = self.backfill(gap).await {
+                        error!("Error while spawning requested backfill: {err}");
+                    }
+                },
+                _ = watchdog.tick() => {
+                    self.seen_blocks.evict_expired();
+                    if let Err(err) = self.check_staleness().await {
+                        error!("Error while checking subscription staleness: {err}");
+                    }
+                },
+                _ = sleep_until(self.reorder_buffer.next_release()) => {
+                    let ready = self.reorder_buffer.pop_ready(Instant::now());
+                    if let Err(err) = self.forward_blocks(ready).await {
+                        error!("Error while forwarding buffered blocks: {err}");
+                    }
+                },
             }
         }
     }
 
     async fn handle_connect_impl(&mut self) -> anyhow::Result<()> {
         info!("Connected to {:?}", self.rpc_client.url());
+        self.connected = true;
+        self.metrics.set_node_connected(true);
+        self.metrics
+            .record_node_connected(&self.node, self.had_first_connect);
+        self.last_block_notification_at = Instant::now();
+        let capabilities = NodeCapabilities::probe(&self.rpc_node).await;
+        self.check_node_compatibility(&capabilities).await?;
         self.selected_chain_syncer.send(Intake::Connected).await?;
         // now that we have successfully connected we
         // can register for notifications
         self.register_notification_listeners().await?;
         let info = self.rpc_client.get_block_dag_info().await?;
-        let sink_header = self.rpc_client.get_block(info.sink, false).await?.header;
+        let record = ProvenanceRecord {
+            recorded_at_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_millis() as u64,
+            daa_score: info.virtual_daa_score,
+            node_url: self.rpc_client.url().unwrap_or_default(),
+            server_version: capabilities.server_version.clone(),
+            network_id: info.network.to_string(),
+        };
+        let provenance_partition = self.provenance_partition.clone();
+        if task::spawn_blocking(move || provenance_partition.record_if_changed(&record)).await?? {
+            info!("Node endpoint or version changed, provenance recorded");
+        }
+        self.node_capabilities.store(Arc::new(capabilities));
         if !self.had_first_connect {
-            let no_block_processed_before = self.last_block_cursor.is_none();
+            // planned before the pruning point is forwarded, which marks whole gaps below it
+            self.start_planned_syncs().await?;
+            self.had_first_connect = true;
+            self.forward_pruning_point(info.pruning_point_hash).await?;
+            return Ok(());
+        }
+        self.forward_pruning_point(info.pruning_point_hash).await?;
+        let sink_header = self.rpc_client.get_block(info.sink, false).await?.header;
+        let sink = Cursor::new(sink_header.daa_score, sink_header.blue_work, info.sink);
+        let gap = self
+            .last_block_cursor
+            .and_then(|last| missed_range_gap(last, sink, info.virtual_daa_score));
+        if gap.is_some() {
+            self.last_block_cursor = None;
+        }
+        let gap_daa = gap
+            .as_ref()
+            .map_or(0, |gap| gap.to_daa_score.saturating_sub(gap.from_daa_score));
+        self.metrics.record_reconnect(gap_daa);
+        info!("Reconnected, gap of {gap_daa} DAA missed while disconnected");
+        if let Some(gap) = gap {
+            self.backfill(gap).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Spawns historical syncers for the blocks missing up to the sink, live blocks continue
+    /// from there
+    async fn start_planned_syncs(&mut self) -> anyhow::Result<()> {
+        let plan = startup::compute_sync_plan(
+            self.last_block_cursor,
+            &self.block_gaps_partition,
+            &self.rpc_client,
+        )
+        .await?;
+        if !plan.unrecoverable.is_empty() {
+            warn!(
+                "{} missing spans are below the node pruning point and can't be synced anymore: {:?}",
+                plan.unrecoverable.len(),
+                plan.unrecoverable
+            );
+        }
+        {
+            let plan = plan.clone();
             let gaps_partition = self.block_gaps_partition.clone();
-            if no_block_processed_before {
-                let pp_header = self
-                    .rpc_client
-                    .get_block(info.pruning_point_hash, false)
-                    .await?
-                    .header;
-                info!("No block processed before, adding gap from pruning point to sink");
-                self.last_block_cursor = Some(Cursor::new(
-                    pp_header.daa_score,
-                    pp_header.blue_work,
-                    info.pruning_point_hash,
-                ));
+            task::spawn_blocking(move || plan.record(&gaps_partition)).await??;
+        }
+        info!(
+            "Spawning historical syncers at startup for {} spans: {:?}",
+            plan.syncs.len(),
+            plan.syncs
+        );
+        for sync in &plan.syncs {
+            if sync.initial {
+                info!("No block processed before, syncing from the pruning point to the sink");
+                self.initial_backfill_from = Some(sync.from.hash);
+            }
+            self.spawn_gap_syncer(&sync.gap());
+        }
+        self.last_block_cursor = plan
+            .syncs
+            .iter()
+            .map(|sync| sync.to)
+            .chain(self.last_block_cursor)
+            .max_by_key(|cursor| cursor.blue_work);
+        Ok(())
+    }
+
+    /// Records the gap and spawns a historical syncer filling it
+    async fn backfill(&mut self, gap: BlockGap) -> anyhow::Result<()> {
+        let gaps_partition = self.block_gaps_partition.clone();
+        let recorded = gap.clone();
+        task::spawn_blocking(move || gaps_partition.add_gap(recorded)).await??;
+        self.spawn_gap_syncer(&gap);
+        Ok(())
+    }
+
+    /// Spawns a historical syncer filling a recorded gap, unless one already does. The syncer
+    /// is registered before this returns and unregisters once it stops, failed or not
+    /// The primary node unless a mirror node is healthier
+    fn healthiest_node(&self) -> RpcNode {
+        let candidates = std::iter::once(self.node.as_ref())
+            .chain(self.mirror_nodes.iter().map(|(node, _)| node.as_ref()))
+            .collect::<Vec<_>>();
+        match healthiest_node(&self.metrics.nodes.lock(), &candidates) {
+            Some(index) if index > 0 => {
+                let (node, rpc_node) = &self.mirror_nodes[index - 1];
+                debug!(%node, "Gap syncer uses the healthier mirror node");
+                rpc_node.clone()
             }
-            let gaps = task::spawn_blocking(move || -> anyhow::Result<_> {
-                gaps_partition
-                    .get_all_gaps_since_daa(info.virtual_daa_score - RK_PRUNING_DEPTH * 2)
-                    .collect::<Result<Vec<_>, _>>()
+            _ => self.rpc_node.clone(),
+        }
+    }
+
+    fn spawn_gap_syncer(&mut self, gap: &BlockGap) {
+        if self.active_syncers.is_syncing(gap) {
+            debug!(?gap, "Gap is being synced already");
+            return;
+        }
+        let from = Cursor::new(gap.from_daa_score, gap.from_blue_work, gap.from_block_hash);
+        let to = Cursor::new(gap.to_daa_score, gap.to_blue_work, gap.to_block_hash);
+        let initial = self.initial_backfill_from == Some(gap.from_block_hash);
+        let intake = self
+            .historical_intake
+            .clone()
+            .unwrap_or_else(|| self.block_handler.clone());
+        let mut syncer = HistoricalDataSyncer::new(
+            self.healthiest_node(),
+            from,
+            to,
+            intake,
+            self.syncers_shutdown.child(),
+            self.block_gaps_partition.clone(),
+        )
+        .with_dispatcher(self.rpc_dispatcher.clone())
+        .with_metrics(self.metrics.clone())
+        .with_active_syncers(self.active_syncers.clone(), initial);
+        if let Some(stall_warning) = &self.stall_warning {
+            syncer = syncer.with_stall_warning(stall_warning.clone());
+        }
+        if let Some(gap_history) = &self.gap_history {
+            syncer = syncer.with_gap_history(gap_history.clone());
+        }
+        self.syncers_shutdown.spawn(async move {
+            _ = syncer
+                .sync()
+                .await
+                .map_err(anyhow::Error::from)
+                .inspect_err(|err| error!("Error in historical syncer: {err:#}"));
+        });
+    }
+
+    /// Fails with [`NodeIncompatible`] for nodes on another network or older than required, and
+    /// for nodes serving blocks without verbose data
+    async fn check_node_compatibility(
+        &self,
+        capabilities: &NodeCapabilities,
+    ) -> anyhow::Result<()> {
+        let Some(metadata_partition) = self.metadata_partition.clone() else {
+            return Ok(());
+        };
+        let (network_id, stored) = {
+            let metadata_partition = metadata_partition.clone();
+            task::spawn_blocking(move || {
+                anyhow::Ok((
+                    metadata_partition.get_network_id()?,
+                    metadata_partition.get_node_requirements()?,
+                ))
             })
-            .await??;
-            if !gaps.is_empty() {
+            .await??
+        };
+        if let Some(network_id) = network_id
+            && capabilities.network_id.as_deref() != Some(network_id.as_str())
+        {
+            return Err(NodeIncompatible(format!(
+                "node is on network {}, the database belongs to {network_id}",
+                capabilities.network_id.as_deref().unwrap_or("unknown")
+            ))
+            .into());
+        }
+        match stored {
+            Some(required) => capabilities.check_requirements(&required)?,
+            None => {
+                let requirements = capabilities.requirements()?;
+                capabilities.check_requirements(&requirements)?;
                 info!(
-                    "Found {} gaps at startup, spawning historical syncers for gaps: {:?}",
-                    gaps.len(),
-                    gaps
+                    network = %requirements.network_id,
+                    min_version = %requirements.min_version,
+                    "Recording node requirements of the database"
                 );
+                task::spawn_blocking(move || {
+                    metadata_partition.set_node_requirements(&requirements)
+                })
+                .await??;
             }
-            gaps.into_iter().try_for_each(
-                |BlockGap {
-                     from_daa_score,
-                     from_blue_work,
-                     from_block_hash,
-                     to_blue_work,
-                     to_block_hash,
-                     to_daa_score,
-                 }|
-                 -> anyhow::Result<()> {
-                    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
-                    self.historical_data_syncer_shutdown_tx.push(shutdown_tx);
-                    tokio::spawn({
-                        let rpc_client = self.rpc_client.clone();
-                        let block_handler = self.block_handler.clone();
-                        let gaps_partition = self.block_gaps_partition.clone();
-                        async move {
-                            _ = HistoricalDataSyncer::new(
-                                rpc_client,
-                                Cursor::new(from_daa_score, from_blue_work, from_block_hash),
-                                Cursor::new(to_daa_score, to_blue_work, to_block_hash),
-                                block_handler,
-                                shutdown_rx,
-                                gaps_partition,
-                            )
-                            .sync()
-                            .await
-                            .inspect_err(|err| error!("Error in historical syncer: {err}"));
-                        }
-                    });
-                    Ok(())
-                },
-            )?;
-
-            self.had_first_connect = true;
         }
-        if let Some(last) = self.last_block_cursor.take()
-            && last.daa_score + RK_PRUNING_DEPTH * 2 > info.virtual_daa_score
+        let sink = self.rpc_client.get_sink().await?.sink;
+        if self
+            .rpc_client
+            .get_block(sink, false)
+            .await?
+            .verbose_data
+            .is_none()
         {
-            let gaps_partition = self.block_gaps_partition.clone();
-            task::spawn_blocking(move || {
-                gaps_partition.add_gap(BlockGap {
-                    from_daa_score: last.daa_score,
-                    from_blue_work: last.blue_work,
-                    from_block_hash: last.hash,
-                    to_blue_work: sink_header.blue_work,
-                    to_block_hash: info.sink,
-                    to_daa_score: sink_header.daa_score,
-                })
-            })
-            .await??;
-            let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
-            self.historical_data_syncer_shutdown_tx.push(shutdown_tx);
-            tokio::spawn({
-                let rpc_client = self.rpc_client.clone();
-                let block_handler = self.block_handler.clone();
-                let gaps_partition = self.block_gaps_partition.clone();
-                async move {
-                    _ = HistoricalDataSyncer::new(
-                        rpc_client,
-                        last,
-                        Cursor::new(sink_header.daa_score, sink_header.blue_work, info.sink),
-                        block_handler,
-                        shutdown_rx,
-                        gaps_partition,
-                    )
-                    .sync()
-                    .await
-                    .inspect_err(|err| error!("Error in historical syncer: {err}"));
-                }
-            });
+            return Err(
+                NodeIncompatible("node serves blocks without verbose data".to_string()).into(),
+            );
         }
-
         Ok(())
     }
 
     async fn handle_connect(&mut self) -> anyhow::Result<()> {
         match self.handle_connect_impl().await {
+            Err(err) if err.is::<NodeIncompatible>() => {
+                self.rpc_client.disconnect().await?;
+                Err(err)
+            }
             Err(err) => {
                 error!("Error while connecting to node: {err}");
                 // force disconnect the client if we have failed
@@ -298,12 +662,25 @@ impl Subscriber {
                 }),
             )
             .await?;
+        self.rpc_client
+            .start_notify(
+                listener_id,
+                Scope::PruningPointUtxoSetOverride(PruningPointUtxoSetOverrideScope {}),
+            )
+            .await?;
 
         Ok(())
     }
 
     async fn handle_disconnect(&mut self) -> anyhow::Result<()> {
         info!("Disconnected from {:?}", self.rpc_client.url());
+        self.connected = false;
+        self.metrics.set_node_connected(false);
+        self.metrics.record_node_disconnected(&self.node);
+        let buffered = self.reorder_buffer.flush();
+        self.forward_blocks(buffered).await?;
+        // the last forwarded block stays the cursor, the reconnect gap covers dropped blocks
+        self.overflow = None;
         // Unregister notifications
         self.unregister_notification_listener().await?;
         self.selected_chain_syncer.send(Intake::Disconnect).await?;
@@ -319,14 +696,18 @@ impl Subscriber {
     }
 
     async fn handle_notification(&mut self, notification: Notification) -> anyhow::Result<()> {
+        if let Some(recorder) = &self.fixture_recorder {
+            recorder.record_notification(&notification);
+        }
         match notification {
             Notification::BlockAdded(BlockAddedNotification { block }) => {
-                let cursor = block.header.as_ref().into();
-                self.block_handler
-                    .send_async(BlockOrMany::Block(block))
-                    .await
-                    .context("block handler send failed")?;
-                self.last_block_cursor = Some(cursor);
+                self.last_block_notification_at = Instant::now();
+                self.metrics.record_node_notification(&self.node);
+                if !self.seen_blocks.insert(block.header.hash) {
+                    self.metrics.increment_duplicate_block_notifications();
+                    return Ok(());
+                }
+                self.buffer_block(block, self.node.clone()).await?;
             }
             Notification::VirtualChainChanged(vcc) => {
                 self.selected_chain_syncer
@@ -337,6 +718,16 @@ impl Subscriber {
             Notification::VirtualDaaScoreChanged(daa) => {
                 self.virtual_daa
                     .store(daa.virtual_daa_score, std::sync::atomic::Ordering::Relaxed);
+                if daa.virtual_daa_score >= self.next_pruning_point_check_daa {
+                    self.next_pruning_point_check_daa =
+                        daa.virtual_daa_score + PRUNING_POINT_CHECK_INTERVAL_DAA;
+                    let info = self.rpc_client.get_block_dag_info().await?;
+                    self.forward_pruning_point(info.pruning_point_hash).await?;
+                }
+            }
+            Notification::PruningPointUtxoSetOverride(_) => {
+                let info = self.rpc_client.get_block_dag_info().await?;
+                self.forward_pruning_point(info.pruning_point_hash).await?;
             }
             _ => {
                 warn!("unknown notification: {:?}", notification)
@@ -344,4 +735,449 @@ impl Subscriber {
         }
         Ok(())
     }
+
+    async fn handle_mirror_block(&mut self, mirrored: MirrorBlock) -> anyhow::Result<()> {
+        let MirrorBlock { node, block } = mirrored;
+        if !self.seen_blocks.insert(block.header.hash) {
+            self.metrics.increment_duplicate_block_notifications();
+            return Ok(());
+        }
+        debug!(%node, hash = %block.header.hash, "Block announced by a mirror node first");
+        self.metrics.increment_mirror_blocks_first();
+        self.buffer_block(block, node).await
+    }
+
+    async fn buffer_block(&mut self, block: Arc<RpcBlock>, node: Arc<str>) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let key = (block.header.blue_work, block.header.hash);
+        let mut released = Vec::from_iter(self.reorder_buffer.push(key, (block, now, node), now));
+        released.extend(self.reorder_buffer.pop_ready(now));
+        self.forward_blocks(released).await
+    }
+
+    fn spawn_mirror_feeds(&mut self) {
+        if self.mirror_clients.is_empty() {
+            return;
+        }
+        let (blocks_tx, blocks_rx) = tokio::sync::mpsc::channel(256);
+        self.mirror_blocks = Some(blocks_rx);
+        for rpc_client in std::mem::take(&mut self.mirror_clients) {
+            self.mirror_nodes.push((
+                Arc::from(rpc_client.url().unwrap_or_default()),
+                RpcNode::from(rpc_client.clone()),
+            ));
+            let shutdown = self.shutdown.child();
+            let feed = MirrorFeed::new(
+                rpc_client,
+                blocks_tx.clone(),
+                shutdown.clone(),
+                self.metrics.clone(),
+            );
+            let handle = shutdown.spawn(feed.task());
+            self.mirror_feeds.push((shutdown, handle));
+        }
+    }
+
+    /// Waits for every mirror feed to unsubscribe and disconnect
+    async fn stop_mirror_feeds(&mut self) {
+        for (shutdown, handle) in std::mem::take(&mut self.mirror_feeds) {
+            shutdown.cancel();
+            match handle.await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!("Mirror feed stopped with error: {err}"),
+                Err(err) => error!("Mirror feed panicked: {err}"),
+            }
+        }
+        self.mirror_blocks = None;
+        self.mirror_nodes.clear();
+    }
+
+    /// A connection can stay up while the node stops sending notifications. When none arrived
+    /// for the staleness threshold but the sink moved past the last forwarded block, the
+    /// listener is registered again and the missed span is backfilled
+    async fn check_staleness(&mut self) -> anyhow::Result<()> {
+        let silence = self.last_block_notification_at.elapsed();
+        self.metrics
+            .set_seconds_since_last_notification(silence.as_secs());
+        if !self.connected || silence < self.staleness_threshold {
+            return Ok(());
+        }
+        let info = self.rpc_node.get_block_dag_info().await?;
+        let Some(last) = self.last_block_cursor else {
+            return Ok(());
+        };
+        if last.hash == info.sink {
+            debug!(
+                ?silence,
+                "No block notifications, the node sink did not move either"
+            );
+            return Ok(());
+        }
+        warn!(
+            ?silence,
+            "No block notifications while the node sink moved, resubscribing"
+        );
+        self.unregister_notification_listener().await?;
+        self.register_notification_listeners().await?;
+        self.last_block_notification_at = Instant::now();
+        self.metrics.increment_resubscriptions();
+
+        let sink_header = self.rpc_node.get_block(info.sink, false).await?.header;
+        let sink = Cursor::new(sink_header.daa_score, sink_header.blue_work, info.sink);
+        if let Some(gap) = missed_range_gap(last, sink, info.virtual_daa_score) {
+            self.last_block_cursor = None;
+            self.backfill(gap).await?;
+        }
+        Ok(())
+    }
+
+    async fn forward_blocks(
+        &mut self,
+        released: Vec<Released<(Arc<RpcBlock>, Instant, Arc<str>)>>,
+    ) -> anyhow::Result<()> {
+        for Released {
+            item: (block, received_at, node),
+            late,
+        } in released
+        {
+            let cursor = block.header.as_ref().into();
+            if self.shed_block(cursor).await? {
+                continue;
+            }
+            let trace = TraceContext::new(debug_span!(
+                target: TRACE_TARGET,
+                "ingest",
+                source = "subscriber",
+                hash = %block.header.hash
+            ));
+            let block = BlockOrMany::Block(block, Some(received_at), node, trace);
+            self.metrics.add_intake_blocks(&block);
+            let send_started = Instant::now();
+            self.block_handler
+                .send_async(block)
+                .await
+                .context("block handler send failed")?;
+            self.metrics.add_subscriber_blocked(send_started.elapsed());
+            // a late anticone block must not move the cursor backwards
+            if !late {
+                self.last_block_cursor = Some(cursor);
+            }
+        }
+        self.metrics
+            .set_block_intake_depth(self.block_handler.len() as u64);
+        Ok(())
+    }
+
+    /// Drops the block while the block handler is over its high-water mark. Once it drained
+    /// to the low-water mark the dropped span is recorded as a gap and backfilled
+    async fn shed_block(&mut self, cursor: Cursor) -> anyhow::Result<bool> {
+        let depth = self.block_handler.len();
+        if let Some(overflow) = &mut self.overflow {
+            if depth > self.intake_low_water {
+                overflow.extend(cursor);
+                self.metrics.increment_blocks_dropped();
+                return Ok(true);
+            }
+            info!(
+                dropped = overflow.dropped,
+                "Block intake drained, backfilling dropped blocks"
+            );
+            let gap = overflow.gap();
+            self.overflow = None;
+            self.backfill(gap).await?;
+            self.metrics.increment_overflow_gaps();
+            return Ok(false);
+        }
+        if depth >= self.intake_high_water {
+            warn!(
+                depth,
+                "Block intake over its high-water mark, dropping blocks until it drains"
+            );
+            self.overflow = Some(Overflow::new(
+                self.last_block_cursor.unwrap_or(cursor),
+                cursor,
+            ));
+            self.metrics.increment_blocks_dropped();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn forward_pruning_point(&mut self, pruning_point: RpcHash) -> anyhow::Result<()> {
+        let Some(periodic_processor) = &self.periodic_processor else {
+            return Ok(());
+        };
+        if self.last_pruning_point == Some(pruning_point) {
+            return Ok(());
+        }
+        let header = self
+            .rpc_client
+            .get_block(pruning_point, false)
+            .await?
+            .header;
+        periodic_processor
+            .send(crate::periodic_processor::Notification::PruningPoint(
+                Cursor::from(&header),
+            ))
+            .await?;
+        self.last_pruning_point = Some(pruning_point);
+        Ok(())
+    }
+}
+
+/// Span of block notifications dropped while the block handler lagged behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Overflow {
+    /// Last block forwarded before dropping started
+    from: Cursor,
+    /// Dropped block with the highest blue work
+    to: Cursor,
+    dropped: u64,
+}
+
+impl Overflow {
+    fn new(from: Cursor, first_dropped: Cursor) -> Self {
+        Self {
+            from,
+            to: first_dropped,
+            dropped: 1,
+        }
+    }
+
+    fn extend(&mut self, dropped: Cursor) {
+        if dropped.blue_work > self.to.blue_work {
+            self.to = dropped;
+        }
+        self.dropped += 1;
+    }
+
+    fn gap(&self) -> BlockGap {
+        BlockGap::from_cursors(self.from, self.to)
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_mirror_block(
+    mirror_blocks: &mut Option<tokio::sync::mpsc::Receiver<MirrorBlock>>,
+) -> Option<MirrorBlock> {
+    match mirror_blocks {
+        Some(mirror_blocks) => mirror_blocks.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_backfill_request(
+    backfill_requests: &mut Option<tokio::sync::mpsc::Receiver<BlockGap>>,
+) -> Option<BlockGap> {
+    match backfill_requests {
+        Some(backfill_requests) => backfill_requests.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Gap between the last forwarded block and the current sink, none if nothing was missed
+/// or the last block is too deep to be synced from the node anymore
+fn missed_range_gap(last: Cursor, sink: Cursor, virtual_daa_score: u64) -> Option<BlockGap> {
+    (last.hash != sink.hash && last.daa_score + RK_PRUNING_DEPTH * 2 > virtual_daa_score)
+        .then(|| BlockGap::from_cursors(last, sink))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, FixtureCall, FixtureEntry, FixtureResponse, FixtureRpcClient};
+    use kaspa_consensus_core::header::Header;
+    use kaspa_consensus_core::network::{NetworkId, NetworkType};
+    use kaspa_math::Uint192;
+    use kaspa_rpc_core::{GetBlockDagInfoResponse, RpcHash};
+    use kaspa_wrpc_client::WrpcEncoding;
+
+    fn cursor(daa_score: u64, hash: u64) -> Cursor {
+        Cursor::new(
+            daa_score,
+            Uint192::from_u64(daa_score),
+            RpcHash::from_u64_word(hash),
+        )
+    }
+
+    /// Subscriber answering its calls from `fixture`, its wRPC client never connects
+    fn subscriber(name: &str, fixture: Fixture) -> (fjall::TxKeyspace, Subscriber) {
+        let keyspace = crate::database::test_keyspace(&format!("subscriber-{name}"));
+        let rpc_client = KaspaRpcClient::new(
+            WrpcEncoding::Borsh,
+            Some("ws://127.0.0.1:1"),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let (block_handler, _) = flume::bounded(16);
+        let (selected_chain_syncer, _) = tokio::sync::mpsc::channel(16);
+        let mut subscriber = Subscriber::new(
+            rpc_client,
+            block_handler,
+            Shutdown::new(),
+            BlockGapsPartition::new(&keyspace).unwrap(),
+            ProvenancePartition::new(&keyspace).unwrap(),
+            selected_chain_syncer,
+            None,
+            Default::default(),
+            Default::default(),
+            RpcDispatcher::new(1),
+        );
+        subscriber.rpc_node = RpcNode::from(FixtureRpcClient::from(fixture));
+        (keyspace, subscriber)
+    }
+
+    fn dag_info(sink: RpcHash) -> FixtureEntry {
+        FixtureEntry::Call {
+            call: FixtureCall::GetBlockDagInfo,
+            result: Ok(FixtureResponse::BlockDagInfo(GetBlockDagInfoResponse::new(
+                NetworkId::new(NetworkType::Mainnet),
+                0,
+                0,
+                vec![sink],
+                1.0,
+                0,
+                vec![sink],
+                RpcHash::from_u64_word(0),
+                1_000,
+                sink,
+            ))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_staleness_is_checked_after_the_threshold_only() {
+        // the fixture fails every call, any node call fails the check
+        let (_keyspace, mut subscriber) = subscriber("threshold", Fixture::default());
+        subscriber.last_block_cursor = Some(cursor(1_000, 1));
+        subscriber.last_block_notification_at = Instant::now() - Duration::from_secs(60);
+        // disconnected
+        subscriber.check_staleness().await.unwrap();
+        assert!(
+            subscriber
+                .metrics
+                .snapshot()
+                .seconds_since_last_notification
+                >= 60
+        );
+
+        subscriber.connected = true;
+        subscriber.last_block_notification_at = Instant::now();
+        subscriber.check_staleness().await.unwrap();
+
+        subscriber.last_block_notification_at = Instant::now() - Duration::from_secs(60);
+        assert!(subscriber.check_staleness().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unmoved_sink_is_not_resubscribed() {
+        let last = cursor(1_000, 1);
+        let fixture = Fixture {
+            entries: vec![dag_info(last.hash)],
+        };
+        let (_keyspace, mut subscriber) = subscriber("unmoved", fixture);
+        subscriber.connected = true;
+        subscriber.last_block_cursor = Some(last);
+        let silent_since = Instant::now() - Duration::from_secs(60);
+        subscriber.last_block_notification_at = silent_since;
+        subscriber.check_staleness().await.unwrap();
+
+        assert_eq!(subscriber.last_block_notification_at, silent_since);
+        assert_eq!(subscriber.last_block_cursor, Some(last));
+        assert_eq!(subscriber.metrics.snapshot().resubscriptions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_staleness_without_forwarded_blocks_is_ignored() {
+        let fixture = Fixture {
+            entries: vec![dag_info(RpcHash::from_u64_word(2))],
+        };
+        let (_keyspace, mut subscriber) = subscriber("no-cursor", fixture);
+        subscriber.connected = true;
+        subscriber.last_block_notification_at = Instant::now() - Duration::from_secs(60);
+        subscriber.check_staleness().await.unwrap();
+        assert_eq!(subscriber.metrics.snapshot().resubscriptions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_block_from_two_feeds_is_forwarded_once() {
+        let (_keyspace, subscriber) = subscriber("dedup", Fixture::default());
+        let mut subscriber = subscriber.with_reorder_window(Duration::ZERO, 16);
+        let (block_handler, forwarded) = flume::bounded(16);
+        subscriber.block_handler = block_handler;
+        let mut header = Header::from_precomputed_hash(RpcHash::from_u64_word(1), vec![]);
+        header.daa_score = 1;
+        let block = Arc::new(RpcBlock {
+            header: (&header).into(),
+            transactions: vec![],
+            verbose_data: None,
+        });
+
+        for node in ["ws://mirror-a", "ws://mirror-b"] {
+            let mirrored = MirrorBlock {
+                node: Arc::from(node),
+                block: block.clone(),
+            };
+            subscriber.handle_mirror_block(mirrored).await.unwrap();
+        }
+        let notification = Notification::BlockAdded(BlockAddedNotification {
+            block: block.clone(),
+        });
+        subscriber.handle_notification(notification).await.unwrap();
+
+        let forwarded = forwarded.drain().collect::<Vec<_>>();
+        assert_eq!(forwarded.len(), 1);
+        let BlockOrMany::Block(forwarded, _, node, _) = &forwarded[0] else {
+            panic!("expected a single block");
+        };
+        assert_eq!(forwarded.header.hash, header.hash);
+        assert_eq!(node.as_ref(), "ws://mirror-a");
+        let metrics = subscriber.metrics.snapshot();
+        assert_eq!(metrics.duplicate_block_notifications, 2);
+        assert_eq!(metrics.mirror_blocks_first, 1);
+    }
+
+    #[test]
+    fn test_missed_range_gap() {
+        let last = cursor(1_000, 1);
+        let sink = cursor(1_500, 2);
+        let gap = missed_range_gap(last, sink, 1_510).unwrap();
+        assert_eq!(gap, BlockGap::from_cursors(last, sink));
+        assert_eq!((gap.from_daa_score, gap.to_daa_score), (1_000, 1_500));
+        assert_eq!(
+            (gap.from_block_hash, gap.to_block_hash),
+            (last.hash, sink.hash)
+        );
+
+        // the sink did not move while disconnected
+        assert_eq!(missed_range_gap(sink, sink, 1_510), None);
+        // the last block is pruned on the node
+        assert_eq!(
+            missed_range_gap(last, sink, 1_000 + RK_PRUNING_DEPTH * 2),
+            None
+        );
+    }
+
+    #[test]
+    fn test_overflow_gap_spans_dropped_blocks() {
+        let forwarded = cursor(100, 1);
+        let mut overflow = Overflow::new(forwarded, cursor(102, 3));
+        // dropped out of blue work order
+        overflow.extend(cursor(101, 2));
+        overflow.extend(cursor(104, 5));
+        overflow.extend(cursor(103, 4));
+        assert_eq!(overflow.dropped, 4);
+        assert_eq!(
+            overflow.gap(),
+            BlockGap::from_cursors(forwarded, cursor(104, 5))
+        );
+    }
 }
@@ -0,0 +1,405 @@
+//! Supervision and restart of the processor loops.
+//!
+//! [`Supervisor::spawn`] runs a component on the blocking pool and classifies how it exits. A
+//! clean return is the component stopping on shutdown. Errors and panics restart it after an
+//! exponential backoff on the same instance, which keeps its channels and hands the next run the
+//! input the failed one was handling. A component that ran for the healthy interval before
+//! failing gets its restart budget back. Database errors, commit conflicts left after the
+//! components retried them and a spent restart budget stop the whole indexer instead. Each run
+//! is inside the [`profiling::component_span`] of the component.
+
+use crate::block_processor::BlockProcessor;
+use crate::crash_handler;
+use crate::metrics::SharedMetrics;
+use crate::periodic_processor::PeriodicProcessor;
+use crate::profiling;
+use crate::shutdown::Shutdown;
+use crate::virtual_chain_processor::VirtualChainProcessor;
+use anyhow::{Result, anyhow};
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+pub const DEFAULT_HEALTHY_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Blocking loop running until shutdown
+pub trait Supervised: Send + 'static {
+    fn run(&mut self) -> Result<()>;
+}
+
+impl Supervised for BlockProcessor {
+    fn run(&mut self) -> Result<()> {
+        self.process()
+    }
+}
+
+impl Supervised for VirtualChainProcessor {
+    fn run(&mut self) -> Result<()> {
+        self.process()
+    }
+}
+
+impl Supervised for PeriodicProcessor {
+    fn run(&mut self) -> Result<()> {
+        self.worker()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Restarts of a component before the indexer shuts down
+    pub max_restarts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Run time after which a failure no longer counts against the earlier restarts
+    pub healthy_interval: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            healthy_interval: DEFAULT_HEALTHY_INTERVAL,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Wait before the `restart`th restart, counted from 1, doubling up to the max backoff
+    pub fn backoff(&self, restart: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << restart.saturating_sub(1).min(16))
+            .min(self.max_backoff)
+    }
+}
+
+/// How a component run ended
+#[derive(Debug)]
+pub enum Exit {
+    /// Stopped on shutdown
+    Clean,
+    Error(anyhow::Error),
+    Panic(String),
+}
+
+impl Exit {
+    fn of(result: std::thread::Result<Result<()>>) -> Self {
+        match result {
+            Ok(Ok(())) => Self::Clean,
+            Ok(Err(err)) => Self::Error(err),
+            Err(payload) => Self::Panic(panic_message(payload.as_ref())),
+        }
+    }
+
+    /// Whether running the component again may succeed. A failing database won't recover and
+    /// a conflict the component gave up retrying means two writers keep overwriting each other
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            Self::Clean => false,
+            Self::Error(err) => !err
+                .chain()
+                .any(|cause| cause.is::<fjall::Error>() || cause.is::<fjall::Conflict>()),
+            Self::Panic(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clean => f.write_str("clean shutdown"),
+            Self::Error(err) => write!(f, "error: {err}"),
+            Self::Panic(message) => write!(f, "panic: {message}"),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "<non-string panic payload>".to_string())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentHealth {
+    pub restarts: u32,
+    /// Error or panic message of the last failed run
+    pub last_error: Option<String>,
+}
+
+/// Restarts failed components, clones share the health of the components
+#[derive(Debug, Clone, Default)]
+pub struct Supervisor {
+    policy: RestartPolicy,
+    metrics: SharedMetrics,
+    components: Arc<Mutex<BTreeMap<&'static str, ComponentHealth>>>,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicy, metrics: SharedMetrics) -> Self {
+        Self {
+            policy,
+            metrics,
+            components: Default::default(),
+        }
+    }
+
+    /// Supervised components by name
+    pub fn health(&self) -> BTreeMap<&'static str, ComponentHealth> {
+        self.components.lock().clone()
+    }
+
+    /// Runs the component on `stage` until it stops cleanly. Returns the failure which made
+    /// it give up, `stop` is cancelled then to shut the indexer down
+    pub fn spawn<C: Supervised>(
+        &self,
+        stage: &Shutdown,
+        stop: &Shutdown,
+        name: &'static str,
+        component: C,
+    ) -> JoinHandle<Result<()>> {
+        stage.spawn(
+            self.clone()
+                .supervise(stage.clone(), stop.clone(), name, component),
+        )
+    }
+
+    async fn supervise<C: Supervised>(
+        self,
+        stage: Shutdown,
+        stop: Shutdown,
+        name: &'static str,
+        mut component: C,
+    ) -> Result<()> {
+        self.components.lock().entry(name).or_default();
+        // restarts counted against the budget, the health keeps counting all of them
+        let mut budget_used = 0;
+        loop {
+            let started = Instant::now();
+            let (returned, result) = tokio::task::spawn_blocking(move || {
+                let _component = profiling::component_span(name).entered();
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| component.run()));
+                (component, result)
+            })
+            .await?;
+            component = returned;
+            let exit = Exit::of(result);
+            if let Exit::Clean = exit {
+                info!("{name} has stopped");
+                return Ok(());
+            }
+            error!("{name} stopped with {exit}");
+            // panics are reported by the panic hook
+            if let Exit::Error(err) = &exit {
+                crash_handler::report_error(name, err);
+            }
+            // isolated failures of a long running component don't add up to a shutdown
+            if started.elapsed() >= self.policy.healthy_interval {
+                budget_used = 0;
+            }
+            let restart = exit.is_recoverable() && budget_used < self.policy.max_restarts;
+            {
+                let mut components = self.components.lock();
+                let health = components.entry(name).or_default();
+                health.last_error = Some(exit.to_string());
+                health.restarts += restart as u32;
+            }
+            if !restart {
+                error!("{name} is not restarted, shutting down");
+                stop.cancel();
+                return Err(match exit {
+                    Exit::Error(err) => err.context(format!("{name} failed")),
+                    exit => anyhow!("{name} failed with {exit}"),
+                });
+            }
+            budget_used += 1;
+            self.metrics.increment_component_restarts();
+            let backoff = self.policy.backoff(budget_used);
+            warn!(
+                "Restarting {name} in {backoff:?}, restart {budget_used} of {}",
+                self.policy.max_restarts
+            );
+            // a stopping stage runs it again right away so it drains its channels
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = stage.cancelled() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fjall::PartitionCreateOptions;
+
+    /// Commits numbers up to `target` one by one, failing and panicking once before a commit.
+    /// Every run starts after the number it committed last
+    struct MockComponent {
+        keyspace: fjall::TxKeyspace,
+        cursor: fjall::TxPartition,
+        target: u64,
+        fail_at: Option<u64>,
+        panic_at: Option<u64>,
+        committed: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl MockComponent {
+        fn persisted_cursor(&self) -> u64 {
+            self.keyspace
+                .read_tx()
+                .get(&self.cursor, "cursor")
+                .unwrap()
+                .map_or(0, |value| u64::from_be_bytes(value[..].try_into().unwrap()))
+        }
+    }
+
+    impl Supervised for MockComponent {
+        fn run(&mut self) -> Result<()> {
+            for next in self.persisted_cursor() + 1..=self.target {
+                if self.fail_at == Some(next) {
+                    self.fail_at = None;
+                    anyhow::bail!("failed at {next}");
+                }
+                if self.panic_at == Some(next) {
+                    self.panic_at = None;
+                    panic!("panicked at {next}");
+                }
+                let mut wtx = self.keyspace.write_tx()?;
+                wtx.insert(&self.cursor, "cursor", next.to_be_bytes());
+                wtx.commit()??;
+                self.committed.lock().push(next);
+            }
+            Ok(())
+        }
+    }
+
+    fn policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            healthy_interval: Duration::MAX,
+        }
+    }
+
+    fn mock(name: &str, fail_at: Option<u64>, panic_at: Option<u64>) -> MockComponent {
+        let keyspace = crate::database::test_keyspace(&format!("supervisor-{name}"));
+        let cursor = keyspace
+            .open_partition("cursor", PartitionCreateOptions::default())
+            .unwrap();
+        MockComponent {
+            keyspace,
+            cursor,
+            target: 10,
+            fail_at,
+            panic_at,
+            committed: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RestartPolicy {
+            max_restarts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            healthy_interval: Duration::MAX,
+        };
+        let backoffs = (1..=6).map(|restart| policy.backoff(restart).as_secs());
+        assert_eq!(backoffs.collect::<Vec<_>>(), [1, 2, 4, 8, 10, 10]);
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_exit_classification() {
+        assert!(!Exit::of(Ok(Ok(()))).is_recoverable());
+        assert!(Exit::of(Ok(Err(anyhow!("channel closed")))).is_recoverable());
+        let database = anyhow::Error::from(fjall::Error::Poisoned).context("commit failed");
+        assert!(!Exit::of(Ok(Err(database))).is_recoverable());
+        let conflict = anyhow::Error::new(fjall::Conflict).context("failed to commit, conflict");
+        assert!(!Exit::of(Ok(Err(conflict))).is_recoverable());
+        let panic = Exit::of(Err(Box::new("boom") as Box<dyn Any + Send>));
+        assert!(panic.is_recoverable());
+        assert_eq!(panic.to_string(), "panic: boom");
+    }
+
+    #[tokio::test]
+    async fn test_failed_component_resumes_from_persisted_cursor() {
+        let metrics = SharedMetrics::default();
+        let supervisor = Supervisor::new(policy(3), metrics.clone());
+        let (stage, stop) = (Shutdown::new(), Shutdown::new());
+        let component = mock("resume", Some(4), Some(7));
+        let (keyspace, cursor) = (component.keyspace.clone(), component.cursor.clone());
+        let committed = component.committed.clone();
+
+        supervisor
+            .spawn(&stage, &stop, "mock", component)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(*committed.lock(), (1..=10).collect::<Vec<_>>());
+        let persisted = keyspace.read_tx().get(&cursor, "cursor").unwrap().unwrap();
+        assert_eq!(persisted[..], 10u64.to_be_bytes());
+        let health = &supervisor.health()["mock"];
+        assert_eq!(health.restarts, 2);
+        assert_eq!(health.last_error.as_deref(), Some("panic: panicked at 7"));
+        assert_eq!(metrics.snapshot().component_restarts, 2);
+        assert!(!stop.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_spent_restart_budget_stops_indexer() {
+        let supervisor = Supervisor::new(policy(1), SharedMetrics::default());
+        let (stage, stop) = (Shutdown::new(), Shutdown::new());
+        let component = mock("budget", Some(2), Some(3));
+        let committed = component.committed.clone();
+
+        let result = supervisor.spawn(&stage, &stop, "mock", component).await;
+
+        assert!(result.unwrap().is_err());
+        assert!(stop.is_cancelled());
+        assert_eq!(*committed.lock(), [1, 2]);
+        let health = &supervisor.health()["mock"];
+        assert_eq!(health.restarts, 1);
+        assert_eq!(health.last_error.as_deref(), Some("panic: panicked at 3"));
+    }
+
+    #[tokio::test]
+    async fn test_healthy_run_gets_its_budget_back() {
+        let supervisor = Supervisor::new(
+            RestartPolicy {
+                healthy_interval: Duration::ZERO,
+                ..policy(1)
+            },
+            SharedMetrics::default(),
+        );
+        let (stage, stop) = (Shutdown::new(), Shutdown::new());
+        let component = mock("healthy", Some(2), Some(3));
+        let committed = component.committed.clone();
+
+        supervisor
+            .spawn(&stage, &stop, "mock", component)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(*committed.lock(), (1..=10).collect::<Vec<_>>());
+        assert_eq!(supervisor.health()["mock"].restarts, 2);
+        assert!(!stop.is_cancelled());
+    }
+}
@@ -0,0 +1,133 @@
+//! Periodic comparison of the tracked circulating supply with `getCoinSupply` of the node.
+//!
+//! The supply is tracked from the accepted coinbase rewards, see
+//! [`Supply`](crate::database::supply::Supply). Every check reports the divergence and counts an
+//! alarm whenever it exceeds the tolerance.
+
+use crate::database::supply::{DEFAULT_SUPPLY_TOLERANCE_SOMPI, Supply, SupplyCheck};
+use crate::metrics::SharedMetrics;
+use crate::shutdown::Shutdown;
+use anyhow::Result;
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_wrpc_client::KaspaRpcClient;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_SUPPLY_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Cross-checks a [`Supply`] against the node
+pub struct SupplyChecker {
+    rpc_client: KaspaRpcClient,
+    supply: Supply,
+    metrics: SharedMetrics,
+    interval: Duration,
+    tolerance: u64,
+}
+
+impl SupplyChecker {
+    pub fn new(rpc_client: KaspaRpcClient, supply: Supply, metrics: SharedMetrics) -> Self {
+        Self {
+            rpc_client,
+            supply,
+            metrics,
+            interval: DEFAULT_SUPPLY_CHECK_INTERVAL,
+            tolerance: DEFAULT_SUPPLY_TOLERANCE_SOMPI,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Divergence in sompi tolerated before an alarm is raised
+    pub fn with_tolerance(mut self, tolerance: u64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub async fn run(self, shutdown: Shutdown) {
+        info!("Supply checker started, checking every {:?}", self.interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+            if !self.rpc_client.is_connected() {
+                continue;
+            }
+            if let Err(err) = self.check().await {
+                warn!("Supply check failed: {err:#}");
+            }
+        }
+        info!("Supply checker stopped");
+    }
+
+    async fn check(&self) -> Result<()> {
+        let node_supply = self.rpc_client.get_coin_supply().await?;
+        let check = self
+            .supply
+            .cross_check(node_supply.circulating_sompi, self.tolerance)?;
+        record(&self.metrics, &check);
+        Ok(())
+    }
+}
+
+fn record(metrics: &SharedMetrics, check: &SupplyCheck) {
+    metrics.set_supply_divergence(check.divergence);
+    if check.diverged {
+        warn!(
+            indexed = check.indexed,
+            node = check.node,
+            divergence = check.divergence,
+            "Indexed circulating supply diverged from the node"
+        );
+        metrics.increment_supply_divergence_alarms();
+    } else {
+        debug!(
+            indexed = check.indexed,
+            divergence = check.divergence,
+            "Supply check passed"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::create_shared_metrics;
+
+    #[test]
+    fn test_divergence_raises_alarm() {
+        let metrics = create_shared_metrics();
+        let check = |divergence, diverged| SupplyCheck {
+            indexed: 1_000,
+            node: 1_000 + divergence,
+            divergence,
+            diverged,
+        };
+        record(&metrics, &check(10, false));
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            (
+                snapshot.supply_divergence,
+                snapshot.supply_divergence_alarms
+            ),
+            (10, 0)
+        );
+        record(&metrics, &check(500, true));
+        record(&metrics, &check(600, true));
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            (
+                snapshot.supply_divergence,
+                snapshot.supply_divergence_alarms
+            ),
+            (600, 2)
+        );
+        // back within the tolerance, the alarms stay counted
+        record(&metrics, &check(0, false));
+        assert_eq!(metrics.snapshot().supply_divergence, 0);
+        assert_eq!(metrics.snapshot().supply_divergence_alarms, 2);
+    }
+}
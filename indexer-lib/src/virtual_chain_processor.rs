@@ -1,17 +1,35 @@
+use crate::CompactHeader;
+use crate::acceptance_slo::SharedAcceptanceSlo;
+use crate::block_events::{ChainBlockChanged, IndexedBlocks, TransactionFinalized};
 use crate::database::PartitionId;
+use crate::database::aggregates::Aggregates;
+use crate::database::balances::Balances;
 use crate::database::headers::block_compact_headers::BlockCompactHeaderPartition;
+use crate::database::headers::block_gaps::{BlockGap, BlockGapsPartition};
+use crate::database::headers::chain_index::{ChainIndexByHashPartition, ChainIndexPartition};
+use crate::database::headers::chain_membership::ChainMembershipPartition;
 use crate::database::metadata::MetadataPartition;
 use crate::database::processing::acceptance::{
     AcceptanceTxKey, AcceptingBlockResolutionData, AcceptingBlockToTxIDPartition,
     TxIDToAcceptancePartition,
 };
+use crate::database::processing::acceptance_history::{
+    AcceptanceChange, AcceptanceHistoryPartition, AcceptanceHistoryRecord,
+};
+use crate::database::processing::finalized_transactions::{
+    FinalizedAcceptance, FinalizedTxPartition,
+};
 use crate::database::processing::pending_sender_resolution::PendingSenderResolutionPartition;
 use crate::database::processing::skipped_transactions::SkipTxPartition;
 use crate::database::processing::unknown_daa_scores::{
     ResolutionEntries, UnknownAcceptingDaaPartition,
 };
 use crate::database::processing::unknown_transactions::UnknownTxPartition;
+use crate::database::supply::Supply;
+use crate::database::webhooks::Webhooks;
 use crate::historical_syncer::Cursor;
+use crate::metrics::SharedMetrics;
+use crate::shutdown::Shutdown;
 use fjall::{ReadTransaction, TxKeyspace, WriteTransaction};
 use itertools::process_results;
 use kaspa_consensus_core::BlueWorkType;
@@ -20,7 +38,18 @@ use kaspa_rpc_core::{
 };
 use parking_lot::Mutex;
 use std::sync::Arc;
-use tracing::{debug, info, trace, warn};
+use std::time::Instant;
+use tokio::runtime::Handle;
+use tracing::{debug, error, info, trace, warn};
+
+/// Reorgs removing more chain blocks than this are reported as deep
+pub const DEFAULT_DEEP_REORG_DEPTH: usize = 10;
+/// Bounds the work of a single notification while finalization catches up
+const MAX_FINALIZED_BLOCKS_PER_NOTIFICATION: u64 = 1000;
+/// A notification conflicting with the block processor is handled again this many times
+const MAX_VCC_COMMIT_ATTEMPTS: usize = 5;
+/// Accepted transactions missing from the index a chain span may collect before it is backfilled
+pub const DEFAULT_UNINDEXED_ACCEPTANCE_THRESHOLD: u64 = 50;
 
 pub struct VirtualChainChangedNotificationAndBlueWork {
     pub vcc: VirtualChainChangedNotification,
@@ -33,7 +62,9 @@ pub struct VirtualChainProcessor {
     daa_resolution_attempt_count: u8,
     reorg_log: Arc<Mutex<()>>, // during reorg we should not merge unknown tx into other partitions
     vcc_rx: flume::Receiver<VirtualChainChangedNotificationAndBlueWork>,
-    shutdown: flume::Receiver<()>,
+    /// Of the processors stage, the notifications left are drained once cancelled
+    #[builder(default)]
+    shutdown: Shutdown,
     tx_keyspace: TxKeyspace,
 
     metadata_partition: MetadataPartition,
@@ -44,40 +75,122 @@ pub struct VirtualChainProcessor {
     unknown_accepting_daa_partition: UnknownAcceptingDaaPartition,
 
     block_compact_header_partition: BlockCompactHeaderPartition,
+    chain_membership_partition: ChainMembershipPartition,
+    chain_index_partition: ChainIndexPartition,
+    chain_index_by_hash_partition: ChainIndexByHashPartition,
 
     pending_sender_resolution_partition: PendingSenderResolutionPartition,
+    acceptance_history_partition: AcceptanceHistoryPartition,
+    finalized_tx_partition: FinalizedTxPartition,
+    block_gaps_partition: BlockGapsPartition,
+    /// Confirmed address balances, applied at acceptance. None disables them
+    balances: Option<Balances>,
+    /// Per DAA bucket totals, accepted transactions added at acceptance
+    aggregates: Option<Aggregates>,
+    /// Circulating supply, accepted coinbase rewards added at acceptance
+    supply: Option<Supply>,
+    /// Records accepted outputs of watched addresses and queues their webhooks once confirmed
+    webhooks: Option<Webhooks>,
+
+    /// Receives the latency of every acceptance commit
+    acceptance_slo: Option<SharedAcceptanceSlo>,
+    #[builder(default)]
+    metrics: SharedMetrics,
+    #[builder(default = DEFAULT_DEEP_REORG_DEPTH)]
+    deep_reorg_depth: usize,
+    /// Confirmations, counted like [`Confirmations`](crate::database::confirmations::Confirmations),
+    /// after which acceptance is final. Finality is disabled without it
+    finality_depth: Option<u64>,
+    /// Notified of finalized transactions after each commit
+    #[builder(default)]
+    indexed_blocks: IndexedBlocks,
+    /// Receives gaps of blocks the acceptance stream shows were never indexed, none disables them
+    backfill_requests: Option<tokio::sync::mpsc::Sender<BlockGap>>,
+    #[builder(default = DEFAULT_UNINDEXED_ACCEPTANCE_THRESHOLD)]
+    unindexed_acceptance_threshold: u64,
+    #[builder(skip)]
+    unindexed_span: Mutex<UnindexedSpan>,
+    /// Notification a failed run was handling, the next run handles it first
+    #[builder(skip)]
+    in_flight: Option<VirtualChainChangedNotificationAndBlueWork>,
+    /// Shutdown was received, later runs only drain the notifications
+    #[builder(skip)]
+    draining: bool,
+}
+
+/// What a notification wrote, published and counted once committed so a notification handled
+/// again after a conflict is only counted once
+#[derive(Default)]
+struct VccWrite {
+    chain_changes: Vec<ChainBlockChanged>,
+    finalized: Vec<TransactionFinalized>,
+    /// Accepting blocks with their headers if indexed and their unindexed accepted transactions
+    unindexed: Vec<(RpcHash, Option<CompactHeader>, usize)>,
+    deep_reorg: bool,
+    finality_violations: u64,
+    reorg_entries_removed: u64,
+}
+
+/// Run of accepting chain blocks whose accepted transactions are missing from the index
+#[derive(Debug, Default)]
+struct UnindexedSpan {
+    /// Last accepting block whose transactions were all indexed
+    anchor: Option<Cursor>,
+    first: Option<Cursor>,
+    unindexed_txs: u64,
 }
 
 impl VirtualChainProcessor {
     pub fn process(&mut self) -> anyhow::Result<()> {
         info!("Acceptance worker started");
-        loop {
+        if let Some(vcc) = self.in_flight.take() {
+            info!("Handling the notification the last run failed on");
+            self.handle_next(vcc)?;
+        }
+        while !self.draining {
             match self.select_input()? {
-                VccOrShutdown::Shutdown(_) => {
+                VccOrShutdown::Shutdown => {
                     info!(
                         "Acceptance worker received shutdown signal, draining notifications first"
                     );
-                    let rx = std::mem::replace(&mut self.vcc_rx, flume::bounded(0).1);
-                    rx.drain().try_for_each(|vcc| -> anyhow::Result<()> {
-                        self.handle_vcc(&vcc)?;
-                        Ok(())
-                    })?;
-                    info!("Draining is done, stopping acceptance worker");
-                    return Ok(());
+                    self.draining = true;
                 }
                 VccOrShutdown::Vcc(vcc) => {
-                    self.handle_vcc(&vcc)?;
+                    self.handle_next(vcc)?;
                 }
             }
         }
+        while let Ok(vcc) = self.vcc_rx.try_recv() {
+            self.handle_next(vcc)?;
+        }
+        self.vcc_rx = flume::bounded(0).1;
+        info!("Draining is done, stopping acceptance worker");
+        Ok(())
+    }
+
+    /// Keeps the notification until it is handled, a run failing on it leaves it to the next
+    fn handle_next(
+        &mut self,
+        vcc: VirtualChainChangedNotificationAndBlueWork,
+    ) -> anyhow::Result<()> {
+        self.in_flight = Some(vcc);
+        if let Some(vcc) = &self.in_flight {
+            self.handle_vcc(vcc)?;
+        }
+        self.in_flight = None;
+        Ok(())
     }
 
     fn select_input(&self) -> anyhow::Result<VccOrShutdown> {
         trace!("Waiting for new vcc or shutdown signal");
-        Ok(flume::Selector::new()
-            .recv(&self.vcc_rx, |r| r.map(VccOrShutdown::from))
-            .recv(&self.shutdown, |r| r.map(VccOrShutdown::from))
-            .wait()?)
+        // runs on a blocking thread of the runtime, see `Shutdown::spawn_blocking`
+        Handle::current().block_on(async {
+            tokio::select! {
+                biased;
+                _ = self.shutdown.cancelled() => Ok(VccOrShutdown::Shutdown),
+                vcc = self.vcc_rx.recv_async() => Ok(vcc?.into()),
+            }
+        })
     }
 
     fn handle_vcc(
@@ -93,46 +206,479 @@ impl VirtualChainProcessor {
             // todo is it possible that vcc only has removals??
             return Ok(());
         }
-        let rtx = self.tx_keyspace.read_tx();
-        let mut wtx = self.tx_keyspace.write_tx()?;
-        vcc.removed_chain_block_hashes
-            .iter()
-            .try_for_each(|hash| -> anyhow::Result<()> {
-                debug!(%hash, "Handling chain block removal");
-                self.handle_chain_block_removal(&mut wtx, &rtx, hash)?;
-                Ok(())
-            })?;
-        vcc.accepted_transaction_ids.iter().try_for_each(
-            |RpcAcceptedTransactionIds {
-                 accepting_block_hash,
-                 accepted_transaction_ids,
-             }|
-             -> anyhow::Result<()> {
-                debug!(%accepting_block_hash, tx_count = %accepted_transaction_ids.len(), "Handling accepted block");
-                self.handle_accepted_block(
-                    &mut wtx,
-                    &rtx,
-                    accepting_block_hash,
-                    accepted_transaction_ids,
-                )?;
-                Ok(())
-            },
-        )?;
+        let started = Instant::now();
+        let mut attempt = 1;
+        let (rtx, written) = loop {
+            let rtx = self.tx_keyspace.read_tx();
+            let mut wtx = self.tx_keyspace.write_tx()?;
+            let written =
+                self.write_vcc(&mut wtx, &rtx, vcc, last_block_blue_work, *last_daa_score)?;
+            match wtx.commit()? {
+                Ok(()) => break (rtx, written),
+                Err(conflict) if attempt == MAX_VCC_COMMIT_ATTEMPTS => {
+                    return Err(anyhow::Error::new(conflict).context(format!(
+                        "failed to commit, conflict virtual chain change after {attempt} attempts"
+                    )));
+                }
+                // reads of the notification were overwritten by the block processor
+                Err(_) => {
+                    warn!(
+                        attempt,
+                        "Virtual chain change conflicted, handling it again"
+                    );
+                    attempt += 1;
+                }
+            }
+        };
+        if let Some(slo) = &self.acceptance_slo {
+            slo.record(started.elapsed());
+        }
+        if written.deep_reorg {
+            self.metrics.increment_deep_reorgs();
+        }
+        for _ in 0..written.finality_violations {
+            self.metrics.increment_finality_violations();
+        }
+        self.metrics
+            .add_reorg_entries_removed(written.reorg_entries_removed);
+        for event in written.chain_changes {
+            self.indexed_blocks.publish(event);
+        }
+        for event in written.finalized {
+            self.indexed_blocks.publish(event);
+        }
+        let backfills = self.track_unindexed_acceptance(&rtx, &written.unindexed)?;
+        self.request_backfills(backfills);
+
+        Ok(())
+    }
+
+    /// Writes the notification into `wtx`, the returned outcome applies once committed
+    fn write_vcc(
+        &self,
+        wtx: &mut WriteTransaction,
+        rtx: &ReadTransaction,
+        vcc: &VirtualChainChangedNotification,
+        last_block_blue_work: &BlueWorkType,
+        last_daa_score: u64,
+    ) -> anyhow::Result<VccWrite> {
+        let mut written = VccWrite::default();
         let last_block = vcc.added_chain_block_hashes.last().unwrap();
+        self.handle_removed_chain_blocks(
+            wtx,
+            rtx,
+            &vcc.removed_chain_block_hashes,
+            last_block,
+            &mut written,
+        )?;
+        self.update_chain_membership(
+            wtx,
+            &vcc.removed_chain_block_hashes,
+            &vcc.added_chain_block_hashes,
+        );
+        self.update_chain_index(
+            wtx,
+            &vcc.removed_chain_block_hashes,
+            &vcc.added_chain_block_hashes,
+        )?;
+        let accepting_hashes = vcc
+            .accepted_transaction_ids
+            .iter()
+            .map(|accepted| accepted.accepting_block_hash)
+            .collect::<Vec<_>>();
+        let accepting_headers = self
+            .block_compact_header_partition
+            .get_many_rtx(rtx, &accepting_hashes)?;
+        written.unindexed.reserve(accepting_headers.len());
+        written.chain_changes = vcc
+            .removed_chain_block_hashes
+            .iter()
+            .map(|hash| ChainBlockChanged {
+                hash: *hash,
+                added: false,
+                daa_score: None,
+                accepted_tx_count: 0,
+            })
+            .collect::<Vec<_>>();
+        vcc.accepted_transaction_ids
+            .iter()
+            .zip(accepting_headers)
+            .try_for_each(
+                |(
+                    RpcAcceptedTransactionIds {
+                        accepting_block_hash,
+                        accepted_transaction_ids,
+                    },
+                    accepting_header,
+                )|
+                 -> anyhow::Result<()> {
+                    debug!(%accepting_block_hash, tx_count = %accepted_transaction_ids.len(), "Handling accepted block");
+                    written.chain_changes.push(ChainBlockChanged {
+                        hash: *accepting_block_hash,
+                        added: true,
+                        daa_score: accepting_header.map(|header| header.daa_score),
+                        accepted_tx_count: accepted_transaction_ids.len(),
+                    });
+                    let unknown_count = self.handle_accepted_block(
+                        wtx,
+                        rtx,
+                        accepting_block_hash,
+                        accepting_header.map(|header| header.daa_score),
+                        accepted_transaction_ids,
+                    )?;
+                    if let Some(balances) = &self.balances {
+                        // a header not indexed yet is at most as deep as the notification
+                        balances.apply_accepted_wtx(
+                            wtx,
+                            *accepting_block_hash,
+                            accepting_header.map_or(last_daa_score, |header| header.daa_score),
+                            accepted_transaction_ids,
+                        )?;
+                    }
+                    if let Some(aggregates) = &self.aggregates {
+                        aggregates.apply_accepted_wtx(
+                            wtx,
+                            *accepting_block_hash,
+                            accepting_header.map_or(last_daa_score, |header| header.daa_score),
+                            accepted_transaction_ids.len() as u64,
+                        )?;
+                    }
+                    if let Some(supply) = &self.supply {
+                        supply.apply_accepted_wtx(
+                            wtx,
+                            rtx,
+                            *accepting_block_hash,
+                            accepted_transaction_ids,
+                        )?;
+                    }
+                    if let Some(webhooks) = &self.webhooks {
+                        webhooks.apply_accepted_wtx(
+                            wtx,
+                            *accepting_block_hash,
+                            accepted_transaction_ids,
+                        )?;
+                    }
+                    written.unindexed.push((
+                        *accepting_block_hash,
+                        accepting_header,
+                        unknown_count,
+                    ));
+                    Ok(())
+                },
+            )?;
+        if let Some(supply) = &self.supply {
+            supply.apply_pending_wtx(wtx, rtx)?;
+        }
+        written.finalized = self.finalize_accepted(wtx)?;
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.release_due_wtx(wtx)?;
+        }
         debug!(hash = %last_block, "Updating latest accepting block cursor");
-        self.metadata_partition.set_latest_accepting_block_cursor(
-            &mut wtx,
+        self.metadata_partition.set_vcp_tip(
+            wtx,
             Cursor {
-                daa_score: *last_daa_score,
+                daa_score: last_daa_score,
                 blue_work: *last_block_blue_work,
                 hash: *last_block,
             },
         )?;
-        wtx.commit()??;
+        Ok(written)
+    }
+
+    /// Accepted transactions which were never indexed point at blocks the gap tracking missed.
+    /// Returns a gap once a run of accepting chain blocks collects `unindexed_acceptance_threshold`
+    /// of them, spanning from the last accepting block without any. Blocks above the block tip or
+    /// within a pending block gap are still to be synced and don't count
+    fn track_unindexed_acceptance(
+        &self,
+        rtx: &ReadTransaction,
+        accepting_blocks: &[(RpcHash, Option<CompactHeader>, usize)],
+    ) -> anyhow::Result<Vec<BlockGap>> {
+        if self.backfill_requests.is_none() {
+            return Ok(Vec::new());
+        }
+        let block_tip_daa = self
+            .metadata_partition
+            .get_latest_block_cursor_rtx(rtx)?
+            .map_or(0, |cursor| cursor.daa_score);
+        let pending_gaps = self
+            .block_gaps_partition
+            .get_all_gaps_rtx(rtx)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut span = self.unindexed_span.lock();
+        let mut gaps = Vec::new();
+        for (hash, header, unknown_count) in accepting_blocks {
+            let Some(header) = header else {
+                continue;
+            };
+            let cursor = Cursor {
+                daa_score: header.daa_score,
+                blue_work: header.blue_work,
+                hash: *hash,
+            };
+            if *unknown_count == 0 {
+                *span = UnindexedSpan {
+                    anchor: Some(cursor),
+                    ..Default::default()
+                };
+                continue;
+            }
+            if header.daa_score > block_tip_daa
+                || pending_gaps
+                    .iter()
+                    .any(|gap| (gap.from_daa_score..=gap.to_daa_score).contains(&header.daa_score))
+            {
+                continue;
+            }
+            self.metrics
+                .add_unindexed_accepted_txs(*unknown_count as u64);
+            span.unindexed_txs += *unknown_count as u64;
+            let first = *span.first.get_or_insert(cursor);
+            if span.unindexed_txs >= self.unindexed_acceptance_threshold {
+                let from = span.anchor.unwrap_or(first);
+                gaps.push(BlockGap::from_cursors(from, cursor));
+                *span = UnindexedSpan {
+                    anchor: Some(cursor),
+                    ..Default::default()
+                };
+            }
+        }
+        Ok(gaps)
+    }
+
+    fn request_backfills(&self, gaps: Vec<BlockGap>) {
+        let Some(backfill_requests) = &self.backfill_requests else {
+            return;
+        };
+        for gap in gaps {
+            let (from_daa, to_daa) = (gap.from_daa_score, gap.to_daa_score);
+            if let Err(err) = backfill_requests.try_send(gap) {
+                debug!(from_daa, to_daa, "Backfill request not sent: {err}");
+                continue;
+            }
+            warn!(
+                from_daa,
+                to_daa, "Accepted transactions were never indexed, requesting a backfill"
+            );
+            self.metrics.increment_auto_created_gaps();
+        }
+    }
 
+    /// Rolls back everything derived from the acceptance of the removed chain blocks. Written
+    /// into the same transaction as the added blocks, which see the rollback. Finalized blocks
+    /// are reported and kept as they are
+    fn handle_removed_chain_blocks(
+        &self,
+        wtx: &mut WriteTransaction,
+        rtx: &ReadTransaction,
+        removed_block_hashes: &[RpcHash],
+        new_tip: &RpcHash,
+        written: &mut VccWrite,
+    ) -> anyhow::Result<()> {
+        if removed_block_hashes.is_empty() {
+            return Ok(());
+        }
+        if removed_block_hashes.len() > self.deep_reorg_depth {
+            let old_tip = self
+                .metadata_partition
+                .get_latest_accepting_block_cursor_rtx(rtx)?;
+            warn!(
+                depth = removed_block_hashes.len(),
+                old_tip = ?old_tip.map(|cursor| cursor.hash),
+                %new_tip,
+                "Deep reorg"
+            );
+            written.deep_reorg = true;
+        }
+        let finalized_index = self.metadata_partition.get_finalized_chain_index_wtx(wtx)?;
+        let mut rolled_back = Vec::with_capacity(removed_block_hashes.len());
+        for hash in removed_block_hashes {
+            let chain_index = self
+                .chain_index_by_hash_partition
+                .get_chain_index_wtx(wtx, hash)?;
+            match (chain_index, finalized_index) {
+                (Some(chain_index), Some(finalized_index)) if chain_index <= finalized_index => {
+                    error!(%hash, chain_index, finalized_index, %new_tip, "Reorg removes a finalized chain block, keeping its acceptance");
+                    written.finality_violations += 1;
+                }
+                _ => rolled_back.push(*hash),
+            }
+        }
+        written.reorg_entries_removed =
+            self.remove_reorged_pending_resolutions(wtx, rtx, &rolled_back)?;
+        for hash in &rolled_back {
+            debug!(%hash, "Handling chain block removal");
+            self.handle_chain_block_removal(wtx, rtx, hash)?;
+        }
         Ok(())
     }
 
+    /// Marks the transactions accepted by chain blocks which reached the finality depth, drops
+    /// their balance deltas and moves the finalized watermark past them. Chain indexes at or below the watermark are
+    /// never finalized again, not even after a violating reorg
+    fn finalize_accepted(
+        &self,
+        wtx: &mut WriteTransaction,
+    ) -> anyhow::Result<Vec<TransactionFinalized>> {
+        let Some(depth) = self.finality_depth else {
+            return Ok(Vec::new());
+        };
+        let Some(tip) = self.chain_index_partition.chain_tip_index_wtx(wtx)? else {
+            return Ok(Vec::new());
+        };
+        let Some(final_index) = (tip + 1).checked_sub(depth.max(1)) else {
+            return Ok(Vec::new());
+        };
+        let from = self
+            .metadata_partition
+            .get_finalized_chain_index_wtx(wtx)?
+            .map_or(0, |index| index + 1);
+        if from > final_index {
+            return Ok(Vec::new());
+        }
+        let to = final_index.min(from + MAX_FINALIZED_BLOCKS_PER_NOTIFICATION - 1);
+        let mut finalized = Vec::new();
+        for (chain_index, accepting_block_hash) in self
+            .chain_index_partition
+            .chain_blocks_wtx(wtx, from..to + 1)?
+        {
+            if let Some(balances) = &self.balances {
+                balances.finalize_wtx(wtx, accepting_block_hash)?;
+            }
+            if let Some(aggregates) = &self.aggregates {
+                aggregates.finalize_wtx(wtx, accepting_block_hash)?;
+            }
+            if let Some(supply) = &self.supply {
+                supply.finalize_wtx(wtx, accepting_block_hash)?;
+            }
+            let Some(tx_ids) = self
+                .acceptance_to_tx_id_partition
+                .get_wtx(wtx, &accepting_block_hash)?
+            else {
+                continue;
+            };
+            for tx_id in tx_ids.as_tx_ids() {
+                let tx_id = RpcTransactionId::from_bytes(*tx_id);
+                self.finalized_tx_partition.insert_wtx(
+                    wtx,
+                    &tx_id,
+                    FinalizedAcceptance {
+                        accepting_block_hash,
+                        chain_index,
+                    },
+                );
+                finalized.push(TransactionFinalized {
+                    tx_id,
+                    accepting_block_hash,
+                    chain_index,
+                });
+            }
+        }
+        debug!(
+            from,
+            to,
+            tx_count = finalized.len(),
+            "Finalized chain blocks"
+        );
+        self.metadata_partition
+            .set_finalized_chain_index_wtx(wtx, Some(to));
+        Ok(finalized)
+    }
+
+    /// Flags follow the selected chain, committed together with the acceptance changes so
+    /// readers never see one without the other
+    fn update_chain_membership(
+        &self,
+        wtx: &mut WriteTransaction,
+        removed_block_hashes: &[RpcHash],
+        added_block_hashes: &[RpcHash],
+    ) {
+        for hash in removed_block_hashes {
+            self.chain_membership_partition
+                .set_from_vcc_wtx(wtx, *hash, false);
+        }
+        // a block removed and added back within one notification ends up on the chain
+        for hash in added_block_hashes {
+            self.chain_membership_partition
+                .set_from_vcc_wtx(wtx, *hash, true);
+        }
+    }
+
+    /// Truncates the index at the first removed block and appends the added blocks after the
+    /// remaining tip, so indexes stay dense. An added block indexed already was delivered
+    /// again and is truncated as well
+    fn update_chain_index(
+        &self,
+        wtx: &mut WriteTransaction,
+        removed_block_hashes: &[RpcHash],
+        added_block_hashes: &[RpcHash],
+    ) -> anyhow::Result<()> {
+        let mut truncate_from: Option<u64> = None;
+        for hash in removed_block_hashes.iter().chain(added_block_hashes) {
+            if let Some(index) = self
+                .chain_index_by_hash_partition
+                .get_chain_index_wtx(wtx, hash)?
+            {
+                truncate_from = Some(truncate_from.map_or(index, |from| from.min(index)));
+            }
+        }
+        if let Some(from) = truncate_from {
+            for hash in self.chain_index_partition.truncate_wtx(wtx, from)? {
+                self.chain_index_by_hash_partition.remove_wtx(wtx, &hash);
+            }
+        }
+        let mut next = self
+            .chain_index_partition
+            .chain_tip_index_wtx(wtx)?
+            .map_or(0, |tip| tip + 1);
+        for hash in added_block_hashes {
+            self.chain_index_partition.insert_wtx(wtx, next, hash);
+            self.chain_index_by_hash_partition
+                .insert_wtx(wtx, hash, next);
+            next += 1;
+        }
+        Ok(())
+    }
+
+    /// Removed chain blocks form a contiguous span of the former selected chain,
+    /// so their pending sender resolutions are dropped with a single range delete. Returns the
+    /// amount removed
+    fn remove_reorged_pending_resolutions(
+        &self,
+        wtx: &mut WriteTransaction,
+        rtx: &ReadTransaction,
+        removed_block_hashes: &[RpcHash],
+    ) -> anyhow::Result<u64> {
+        if removed_block_hashes.is_empty() {
+            return Ok(0);
+        }
+        let (from_daa, to_daa) = self
+            .block_compact_header_partition
+            .get_many_rtx(rtx, removed_block_hashes)?
+            .into_iter()
+            .flatten()
+            .map(|header| header.daa_score)
+            .fold((u64::MAX, u64::MIN), |(min, max), daa| {
+                (min.min(daa), max.max(daa))
+            });
+        if from_daa > to_daa {
+            warn!(
+                removed = removed_block_hashes.len(),
+                "Headers of removed chain blocks are unknown, skipping pending resolution cleanup"
+            );
+            return Ok(0);
+        }
+        let _lock = self.reorg_log.lock();
+        let removed = self
+            .pending_sender_resolution_partition
+            .remove_daa_range_wtx(wtx, from_daa, to_daa)?;
+        debug!(
+            from_daa,
+            to_daa, removed, "Removed pending sender resolutions of reorged span"
+        );
+        Ok(removed as u64)
+    }
+
     fn handle_chain_block_removal(
         &self,
         wtx: &mut WriteTransaction,
@@ -140,19 +686,63 @@ impl VirtualChainProcessor {
         removed_block_hash: &RpcHash,
     ) -> anyhow::Result<()> {
         let _lock = self.reorg_log.lock();
+        self.unknown_accepting_daa_partition
+            .remove_by_accepting_block_hash(wtx, *removed_block_hash)?;
+        self.unknown_tx_partition
+            .remove_by_accepting_block_hash(wtx, removed_block_hash)?;
+        if let Some(balances) = &self.balances {
+            balances.revert_wtx(wtx, *removed_block_hash)?;
+        }
+        if let Some(aggregates) = &self.aggregates {
+            aggregates.revert_accepted_wtx(wtx, *removed_block_hash)?;
+        }
+        if let Some(supply) = &self.supply {
+            supply.revert_accepted_wtx(wtx, *removed_block_hash)?;
+        }
         let Some(tx_id_s) = self
             .acceptance_to_tx_id_partition
             .remove_wtx(wtx, removed_block_hash)?
         else {
             return Ok(());
         };
-        let daa = self
+        let removed_daa = self
             .block_compact_header_partition
-            .get_daa_score_rtx(rtx, removed_block_hash)?;
+            .get_daa_score_rtx(rtx, removed_block_hash)?
+            .unwrap_or_default();
         debug!(block_hash = %removed_block_hash, tx_count = %tx_id_s.as_tx_ids().len(), "Processing block removal");
         for tx_id in tx_id_s.as_tx_ids() {
-            for r in self.tx_id_to_acceptance_partition.get_by_tx_id(rtx, tx_id) {
-                let (key, value) = r?;
+            let mut history_recorded = false;
+            for (key, value) in self
+                .tx_id_to_acceptance_partition
+                .get_by_tx_id_wtx(wtx, tx_id)?
+            {
+                if !history_recorded {
+                    // the first reorg of a transaction carries its original acceptance into history
+                    if !self
+                        .acceptance_history_partition
+                        .has_history_wtx(wtx, tx_id)?
+                    {
+                        self.acceptance_history_partition.append_wtx(
+                            wtx,
+                            *tx_id,
+                            &AcceptanceHistoryRecord::new(
+                                AcceptanceChange::Accepted,
+                                key.accepted_by_block_hash,
+                                u64::from_be_bytes(key.accepted_at_daa),
+                            ),
+                        )?;
+                    }
+                    self.acceptance_history_partition.append_wtx(
+                        wtx,
+                        *tx_id,
+                        &AcceptanceHistoryRecord::new(
+                            AcceptanceChange::ReorgedOut,
+                            removed_block_hash.as_bytes(),
+                            removed_daa,
+                        ),
+                    )?;
+                    history_recorded = true;
+                }
                 let partition_id = key.partition_id;
                 self.tx_id_to_acceptance_partition.remove(wtx, key.clone());
                 self.tx_id_to_acceptance_partition.insert_wtx(
@@ -165,15 +755,6 @@ impl VirtualChainProcessor {
                     },
                     value,
                 );
-
-                self.unknown_accepting_daa_partition
-                    .remove_by_accepting_block_hash(wtx, *removed_block_hash)?;
-                self.unknown_tx_partition
-                    .remove_by_accepting_block_hash(wtx, removed_block_hash)?;
-                if let Some(daa) = daa {
-                    self.pending_sender_resolution_partition
-                        .remove_pending(wtx, daa, tx_id)?;
-                }
             }
         }
         // todo consider update of metadata partition
@@ -185,13 +766,11 @@ impl VirtualChainProcessor {
         wtx: &mut WriteTransaction,
         rtx: &ReadTransaction,
         accepting_block_hash: &RpcHash,
+        accepting_daa: Option<u64>,
         tx_id_s: &[RpcTransactionId],
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<usize> {
         let _lock = self.reorg_log.lock(); // rename lock
 
-        let accepting_daa = self
-            .block_compact_header_partition
-            .get_daa_score_rtx(rtx, accepting_block_hash)?;
         let filtered = process_results(
             tx_id_s.iter().map(|tx_id| {
                 self.skip_tx_partition
@@ -209,8 +788,11 @@ impl VirtualChainProcessor {
         let mut unknown_tx_ids = Vec::with_capacity(filtered.len());
         for tx_id in &filtered {
             let mut is_required = false;
-            for r in self.tx_id_to_acceptance_partition.get_by_tx_id(rtx, tx_id) {
-                let (key, resolution) = r?;
+            // earlier removals of this notification are visible to the write transaction only
+            for (key, resolution) in self
+                .tx_id_to_acceptance_partition
+                .get_by_tx_id_wtx(wtx, tx_id)?
+            {
                 assert_eq!(*tx_id, key.tx_id);
                 is_required = true;
                 match resolution {
@@ -274,6 +856,21 @@ impl VirtualChainProcessor {
                     }
                 }
             }
+            if is_required
+                && self
+                    .acceptance_history_partition
+                    .has_history_wtx(wtx, tx_id)?
+            {
+                self.acceptance_history_partition.append_wtx(
+                    wtx,
+                    *tx_id,
+                    &AcceptanceHistoryRecord::new(
+                        AcceptanceChange::Accepted,
+                        accepting_block_hash.as_bytes(),
+                        accepting_daa.unwrap_or_default(),
+                    ),
+                )?;
+            }
             if !is_required {
                 debug!(tx_id = %RpcTransactionId::from_bytes(*tx_id), %accepting_block_hash, "Marking transaction as unknown");
                 unknown_tx_ids.push(*tx_id);
@@ -293,13 +890,13 @@ impl VirtualChainProcessor {
             .insert_wtx(wtx, accepting_block_hash, &filtered);
         self.unknown_tx_partition
             .insert_wtx(wtx, accepting_block_hash, unknown_tx_ids.as_slice());
-        Ok(())
+        Ok(unknown_tx_ids.len())
     }
 }
 
 enum VccOrShutdown {
     Vcc(VirtualChainChangedNotificationAndBlueWork),
-    Shutdown(()),
+    Shutdown,
 }
 
 impl From<VirtualChainChangedNotificationAndBlueWork> for VccOrShutdown {
@@ -308,8 +905,929 @@ impl From<VirtualChainChangedNotificationAndBlueWork> for VccOrShutdown {
     }
 }
 
-impl From<()> for VccOrShutdown {
-    fn from(value: ()) -> Self {
-        Self::Shutdown(value)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_events::IndexEvent;
+    use crate::database::confirmations::Confirmations;
+    use crate::database::headers::{BlockGap, BlockRelations, BlockRelationsPartition};
+    use crate::database::messages::AddressPayload;
+    use crate::database::processing::{
+        AcceptanceGapsPartition, IndexedOutput, OutpointPartition, TxInputPartition,
+    };
+    use crate::database::resolution_keys::PaymentKeyForResolution;
+    use crate::database::schema::DescribePartition;
+    use crate::database::supply::{BlockReward, BlockRewardPartition};
+    use crate::metrics::create_shared_metrics;
+    use crate::supervisor::{RestartPolicy, Supervised, Supervisor};
+    use kaspa_addresses::{Prefix, Version};
+    use kaspa_rpc_core::RpcAddress;
+    use kaspa_txscript::pay_to_address_script;
+    use std::time::Duration;
+
+    fn vcc(added: &[RpcHash], removed: &[RpcHash]) -> VirtualChainChangedNotificationAndBlueWork {
+        VirtualChainChangedNotificationAndBlueWork {
+            vcc: VirtualChainChangedNotification {
+                removed_chain_block_hashes: Arc::new(removed.to_vec()),
+                added_chain_block_hashes: Arc::new(added.to_vec()),
+                accepted_transaction_ids: Arc::new(vec![]),
+            },
+            last_block_blue_work: BlueWorkType::from_u64(1),
+            last_daa_score: 1,
+        }
+    }
+
+    fn processor(keyspace: &TxKeyspace, metrics: SharedMetrics) -> VirtualChainProcessor {
+        VirtualChainProcessor::builder()
+            .daa_resolution_attempt_count(5)
+            .reorg_log(Default::default())
+            .vcc_rx(flume::unbounded().1)
+            .tx_keyspace(keyspace.clone())
+            .metadata_partition(MetadataPartition::new(keyspace).unwrap())
+            .skip_tx_partition(SkipTxPartition::new(keyspace).unwrap())
+            .tx_id_to_acceptance_partition(TxIDToAcceptancePartition::new(keyspace).unwrap())
+            .acceptance_to_tx_id_partition(AcceptingBlockToTxIDPartition::new(keyspace).unwrap())
+            .unknown_tx_partition(UnknownTxPartition::new(keyspace).unwrap())
+            .unknown_accepting_daa_partition(UnknownAcceptingDaaPartition::new(keyspace).unwrap())
+            .block_compact_header_partition(BlockCompactHeaderPartition::new(keyspace).unwrap())
+            .chain_membership_partition(ChainMembershipPartition::new(keyspace).unwrap())
+            .chain_index_partition(ChainIndexPartition::new(keyspace).unwrap())
+            .chain_index_by_hash_partition(ChainIndexByHashPartition::new(keyspace).unwrap())
+            .pending_sender_resolution_partition(
+                PendingSenderResolutionPartition::new(keyspace).unwrap(),
+            )
+            .acceptance_history_partition(AcceptanceHistoryPartition::new(keyspace).unwrap())
+            .finalized_tx_partition(FinalizedTxPartition::new(keyspace).unwrap())
+            .block_gaps_partition(BlockGapsPartition::new(keyspace).unwrap())
+            .metrics(metrics)
+            .build()
+    }
+
+    #[test]
+    fn test_chain_membership_follows_reorgs() {
+        let keyspace = crate::database::test_keyspace("chain-membership");
+        let processor = processor(&keyspace, create_shared_metrics());
+        let chain_membership = processor.chain_membership_partition.clone();
+        let (a, b) = (RpcHash::from_u64_word(1), RpcHash::from_u64_word(2));
+        let flag = |hash| chain_membership.is_on_selected_chain(hash).unwrap();
+        let ingest = |hash, is_chain_block| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            let flag = chain_membership
+                .set_from_verbose_wtx(&mut wtx, hash, is_chain_block)
+                .unwrap();
+            wtx.commit().unwrap().unwrap();
+            flag
+        };
+
+        // received as a red block, then selected
+        assert!(!ingest(a, false));
+        processor.handle_vcc(&vcc(&[a], &[])).unwrap();
+        assert_eq!(flag(a), Some(true));
+
+        processor.handle_vcc(&vcc(&[b], &[a])).unwrap();
+        assert_eq!((flag(a), flag(b)), (Some(false), Some(true)));
+        // b is delivered late with stale verbose data, the reorg wins
+        assert!(ingest(b, false));
+        assert_eq!(flag(b), Some(true));
+
+        processor.handle_vcc(&vcc(&[a], &[b])).unwrap();
+        assert_eq!((flag(a), flag(b)), (Some(true), Some(false)));
+        assert_eq!(flag(RpcHash::from_u64_word(3)), None);
+    }
+
+    #[test]
+    fn test_verbose_flag_does_not_conflict_with_vcc() {
+        let keyspace = crate::database::test_keyspace("chain-membership-conflict");
+        let processor = processor(&keyspace, create_shared_metrics());
+        let chain_membership = processor.chain_membership_partition.clone();
+        let a = RpcHash::from_u64_word(1);
+
+        // the block is written while the virtual chain processor selects it
+        let mut wtx = keyspace.write_tx().unwrap();
+        assert!(
+            !chain_membership
+                .set_from_verbose_wtx(&mut wtx, a, false)
+                .unwrap()
+        );
+        processor.handle_vcc(&vcc(&[a], &[])).unwrap();
+        wtx.commit().unwrap().unwrap();
+        assert_eq!(
+            chain_membership.is_on_selected_chain(a).unwrap(),
+            Some(true)
+        );
+    }
+
+    /// Chain block accepting the payments with the given numbers, 0 is a transaction the
+    /// block processor never stored
+    type ChainBlock = (u64, &'static [u64]);
+
+    fn tx_id(i: u64) -> RpcTransactionId {
+        RpcHash::from_u64_word(100 + i)
+    }
+
+    fn accepting_vcc(
+        added: &[ChainBlock],
+        removed: &[u64],
+    ) -> VirtualChainChangedNotificationAndBlueWork {
+        let added_hashes = added
+            .iter()
+            .map(|(block, _)| RpcHash::from_u64_word(*block))
+            .collect::<Vec<_>>();
+        let removed_hashes = removed
+            .iter()
+            .map(|block| RpcHash::from_u64_word(*block))
+            .collect::<Vec<_>>();
+        let mut vcc = vcc(&added_hashes, &removed_hashes);
+        vcc.vcc.accepted_transaction_ids = Arc::new(
+            added
+                .iter()
+                .map(|(block, txs)| RpcAcceptedTransactionIds {
+                    accepting_block_hash: RpcHash::from_u64_word(*block),
+                    accepted_transaction_ids: txs.iter().map(|i| tx_id(*i)).collect(),
+                })
+                .collect(),
+        );
+        vcc
+    }
+
+    /// Replays the notifications over the data the block processor would have stored for
+    /// payments 1 to 4, the header of block 4 is unknown
+    fn index(
+        name: &str,
+        notifications: &[VirtualChainChangedNotificationAndBlueWork],
+        deep_reorg_depth: usize,
+    ) -> (TxKeyspace, VirtualChainProcessor) {
+        let keyspace = crate::database::test_keyspace(&format!("reorg-{name}"));
+        let mut processor = processor(&keyspace, create_shared_metrics());
+        processor.deep_reorg_depth = deep_reorg_depth;
+        let mut wtx = keyspace.write_tx().unwrap();
+        for i in 1..=4 {
+            let payment = PaymentKeyForResolution {
+                block_time: i.to_be_bytes(),
+                block_hash: RpcHash::from_u64_word(i).as_bytes(),
+                receiver: AddressPayload::default(),
+                version: 1,
+                tx_id: tx_id(i).as_bytes(),
+                attempt_count: 5,
+            };
+            processor.tx_id_to_acceptance_partition.insert_payment_wtx(
+                &mut wtx,
+                tx_id(i).as_bytes(),
+                &payment,
+                None,
+                None,
+            );
+        }
+        wtx.commit().unwrap().unwrap();
+        for (block, daa_score) in [(1, 10), (2, 20), (3, 21)] {
+            processor
+                .block_compact_header_partition
+                .insert_compact_header(
+                    &RpcHash::from_u64_word(block),
+                    BlueWorkType::from_u64(daa_score),
+                    daa_score,
+                )
+                .unwrap();
+        }
+        for notification in notifications {
+            processor.handle_vcc(notification).unwrap();
+        }
+        (keyspace, processor)
+    }
+
+    /// Contents of the partitions derived from acceptance
+    fn dump(keyspace: &TxKeyspace) -> Vec<(&'static str, Vec<(fjall::Slice, fjall::Slice)>)> {
+        [
+            TxIDToAcceptancePartition::DESCRIPTION.name,
+            AcceptingBlockToTxIDPartition::DESCRIPTION.name,
+            UnknownTxPartition::DESCRIPTION.name,
+            UnknownAcceptingDaaPartition::DESCRIPTION.name,
+            PendingSenderResolutionPartition::DESCRIPTION.name,
+            MetadataPartition::DESCRIPTION.name,
+        ]
+        .into_iter()
+        .map(|name| {
+            let partition = keyspace.open_partition(name, Default::default()).unwrap();
+            let entries = keyspace
+                .read_tx()
+                .iter(&partition)
+                .map(Result::unwrap)
+                .collect();
+            (name, entries)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_reorg_matches_index_of_final_chain() {
+        let (reorged, processor) = index(
+            "replayed",
+            &[
+                accepting_vcc(&[(1, &[1])], &[]),
+                accepting_vcc(&[(2, &[2, 3, 0])], &[]),
+                accepting_vcc(&[(3, &[2]), (4, &[3, 4, 0])], &[2]),
+            ],
+            0,
+        );
+        let (from_scratch, _) = index(
+            "from-scratch",
+            &[
+                accepting_vcc(&[(1, &[1])], &[]),
+                accepting_vcc(&[(3, &[2]), (4, &[3, 4, 0])], &[]),
+            ],
+            DEFAULT_DEEP_REORG_DEPTH,
+        );
+        let reorged_dump = dump(&reorged);
+        assert!(reorged_dump.iter().all(|(_, entries)| !entries.is_empty()));
+        assert_eq!(reorged_dump, dump(&from_scratch));
+
+        let flag = |block| {
+            processor
+                .chain_membership_partition
+                .is_on_selected_chain(RpcHash::from_u64_word(block))
+                .unwrap()
+        };
+        assert_eq!(
+            (flag(2), flag(3), flag(4)),
+            (Some(false), Some(true), Some(true))
+        );
+        assert_eq!(processor.metrics.snapshot().deep_reorgs, 1);
+    }
+
+    #[cfg(feature = "api")]
+    #[test]
+    fn test_acceptance_history_is_served_after_reorg() {
+        use crate::api::{AcceptanceChangeKind::*, ApiError, QueryApi};
+        let (keyspace, processor) = index(
+            "history",
+            &[
+                accepting_vcc(&[(1, &[1])], &[]),
+                accepting_vcc(&[(2, &[2, 3, 0])], &[]),
+                accepting_vcc(&[(3, &[2]), (4, &[3, 4, 0])], &[2]),
+            ],
+            DEFAULT_DEEP_REORG_DEPTH,
+        );
+        let api = QueryApi::new(
+            &keyspace,
+            processor.block_compact_header_partition.clone(),
+            None,
+        )
+        .unwrap();
+        let block = |i| RpcHash::from_u64_word(i).to_string();
+        let changes = |i| {
+            let history = api.acceptance_history(tx_id(i)).unwrap();
+            assert_eq!(history.tx_id, tx_id(i).to_string());
+            history
+                .changes
+                .into_iter()
+                .map(|change| (change.change, change.block_hash, change.daa_score))
+                .collect::<Vec<_>>()
+        };
+
+        let reorged_out = [
+            (Accepted, block(2), Some(20)),
+            (ReorgedOut, block(2), Some(20)),
+        ];
+        assert_eq!(
+            changes(2),
+            [reorged_out.to_vec(), vec![(Accepted, block(3), Some(21))]].concat()
+        );
+        // the header of block 4 is unknown
+        assert_eq!(
+            changes(3),
+            [reorged_out.to_vec(), vec![(Accepted, block(4), None)]].concat()
+        );
+        // never reorged, nothing was recorded
+        let history = api.acceptance_history(tx_id(1)).unwrap();
+        assert_eq!(history.changes.len(), 1);
+        assert_eq!(history.changes[0].block_hash, block(1));
+        assert_eq!(history.changes[0].recorded_at_ms, None);
+        assert!(matches!(
+            api.acceptance_history(tx_id(9)),
+            Err(ApiError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_conflicting_vcc_is_handled_again() {
+        let (keyspace, processor) = index(
+            "conflict",
+            &[
+                accepting_vcc(&[(1, &[1])], &[]),
+                accepting_vcc(&[(2, &[2, 3, 0])], &[]),
+            ],
+            0,
+        );
+        let reorg = accepting_vcc(&[(3, &[2]), (4, &[3, 4, 0])], &[2]);
+
+        // the block processor stores a payment the notification accepts before it commits
+        let rtx = keyspace.read_tx();
+        let mut wtx = keyspace.write_tx().unwrap();
+        processor
+            .write_vcc(
+                &mut wtx,
+                &rtx,
+                &reorg.vcc,
+                &reorg.last_block_blue_work,
+                reorg.last_daa_score,
+            )
+            .unwrap();
+        let mut conflicting = keyspace.write_tx().unwrap();
+        let payment = PaymentKeyForResolution {
+            block_time: 3u64.to_be_bytes(),
+            block_hash: RpcHash::from_u64_word(3).as_bytes(),
+            receiver: AddressPayload::default(),
+            version: 1,
+            tx_id: tx_id(3).as_bytes(),
+            attempt_count: 5,
+        };
+        processor.tx_id_to_acceptance_partition.insert_payment_wtx(
+            &mut conflicting,
+            tx_id(3).as_bytes(),
+            &payment,
+            None,
+            None,
+        );
+        conflicting.commit().unwrap().unwrap();
+        assert!(wtx.commit().unwrap().is_err());
+        assert_eq!(processor.metrics.snapshot().deep_reorgs, 0);
+
+        processor.handle_vcc(&reorg).unwrap();
+        let (replayed, _) = index(
+            "conflict-replayed",
+            &[
+                accepting_vcc(&[(1, &[1])], &[]),
+                accepting_vcc(&[(2, &[2, 3, 0])], &[]),
+                reorg,
+            ],
+            0,
+        );
+        assert_eq!(dump(&keyspace), dump(&replayed));
+        assert_eq!(processor.metrics.snapshot().deep_reorgs, 1);
+    }
+
+    /// Fails its first run on an unreadable header of block 5, which it repairs afterwards
+    struct FailingOnce {
+        processor: VirtualChainProcessor,
+        repaired: bool,
+    }
+
+    impl Supervised for FailingOnce {
+        fn run(&mut self) -> anyhow::Result<()> {
+            let result = self.processor.run();
+            if !self.repaired {
+                self.repaired = true;
+                self.processor
+                    .block_compact_header_partition
+                    .insert_compact_header(
+                        &RpcHash::from_u64_word(5),
+                        BlueWorkType::from_u64(30),
+                        30,
+                    )?;
+            }
+            result
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_notification_is_handled_after_restart() {
+        let notification = || accepting_vcc(&[(1, &[1]), (5, &[2])], &[]);
+        let (keyspace, mut processor) = index("restart", &[], DEFAULT_DEEP_REORG_DEPTH);
+        keyspace
+            .open_partition(
+                BlockCompactHeaderPartition::DESCRIPTION.name,
+                Default::default(),
+            )
+            .unwrap()
+            .insert(RpcHash::from_u64_word(5).as_bytes(), [0; 3])
+            .unwrap();
+        let (vcc_tx, vcc_rx) = flume::unbounded();
+        vcc_tx.send(notification()).unwrap();
+        processor.shutdown.cancel();
+        processor.vcc_rx = vcc_rx;
+        let supervisor = Supervisor::new(
+            RestartPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            create_shared_metrics(),
+        );
+        let (stage, stop) = (Shutdown::new(), Shutdown::new());
+        let component = FailingOnce {
+            processor,
+            repaired: false,
+        };
+
+        supervisor
+            .spawn(&stage, &stop, "acceptance worker", component)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let health = &supervisor.health()["acceptance worker"];
+        assert_eq!(health.restarts, 1);
+        assert!(!stop.is_cancelled());
+        let (expected, expected_processor) =
+            index("restart-expected", &[], DEFAULT_DEEP_REORG_DEPTH);
+        expected_processor
+            .block_compact_header_partition
+            .insert_compact_header(&RpcHash::from_u64_word(5), BlueWorkType::from_u64(30), 30)
+            .unwrap();
+        expected_processor.handle_vcc(&notification()).unwrap();
+        assert_eq!(dump(&keyspace), dump(&expected));
+    }
+
+    #[test]
+    fn test_chain_index_stays_dense() {
+        let keyspace = crate::database::test_keyspace("chain-index");
+        let processor = processor(&keyspace, create_shared_metrics());
+        let hashes = |blocks: &[u64]| {
+            blocks
+                .iter()
+                .map(|block| RpcHash::from_u64_word(*block))
+                .collect::<Vec<_>>()
+        };
+        let handle = |added: &[u64], removed: &[u64]| {
+            processor
+                .handle_vcc(&vcc(&hashes(added), &hashes(removed)))
+                .unwrap()
+        };
+        let chain = || {
+            processor
+                .chain_index_partition
+                .iter_chain_blocks(0..u64::MAX)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+        let indexed = |blocks: &[u64]| {
+            hashes(blocks)
+                .into_iter()
+                .enumerate()
+                .map(|(index, hash)| (index as u64, hash))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            processor.chain_index_partition.chain_tip_index().unwrap(),
+            None
+        );
+
+        handle(&[1, 2], &[]);
+        handle(&[3, 4], &[]);
+        assert_eq!(chain(), indexed(&[1, 2, 3, 4]));
+        // reorg of the two last blocks onto a longer branch
+        handle(&[5, 6, 7], &[4, 3]);
+        assert_eq!(chain(), indexed(&[1, 2, 5, 6, 7]));
+        assert_eq!(
+            processor
+                .chain_index_by_hash_partition
+                .get_chain_index(&RpcHash::from_u64_word(3))
+                .unwrap(),
+            None
+        );
+        // back to the former branch, block 3 gets its index again
+        handle(&[3, 8], &[7, 6, 5]);
+        assert_eq!(chain(), indexed(&[1, 2, 3, 8]));
+        // delivered again after a restart
+        handle(&[8, 9], &[]);
+        assert_eq!(chain(), indexed(&[1, 2, 3, 8, 9]));
+
+        assert_eq!(
+            processor.chain_index_partition.chain_tip_index().unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            processor
+                .chain_index_partition
+                .get_chain_block_by_index(3)
+                .unwrap(),
+            Some(RpcHash::from_u64_word(8))
+        );
+        assert_eq!(
+            processor
+                .chain_index_by_hash_partition
+                .get_chain_index(&RpcHash::from_u64_word(9))
+                .unwrap(),
+            Some(4)
+        );
+        assert_eq!(
+            processor
+                .chain_index_partition
+                .iter_chain_blocks(1..3)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+            indexed(&[1, 2, 3])[1..].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_confirmations_drop_on_reorg() {
+        let (keyspace, processor) = index("confirmations", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let confirmations = Confirmations::new(&keyspace).unwrap();
+        let confirmed = |i| confirmations.get_confirmations(&tx_id(i)).unwrap();
+        // seen in a block, not accepted yet
+        assert_eq!(confirmed(1), Some(0));
+        assert_eq!(confirmed(9), None);
+
+        processor
+            .handle_vcc(&accepting_vcc(&[(1, &[]), (2, &[1])], &[]))
+            .unwrap();
+        assert_eq!(confirmed(1), Some(1));
+        processor
+            .handle_vcc(&accepting_vcc(&[(3, &[2])], &[]))
+            .unwrap();
+        assert_eq!((confirmed(1), confirmed(2)), (Some(2), Some(1)));
+        // both accepting blocks are reorged out, only 1 is accepted again on the new branch
+        processor
+            .handle_vcc(&accepting_vcc(&[(5, &[1])], &[3, 2]))
+            .unwrap();
+        assert_eq!((confirmed(1), confirmed(2)), (Some(1), Some(0)));
+        processor
+            .handle_vcc(&accepting_vcc(&[(6, &[2]), (7, &[])], &[]))
+            .unwrap();
+        assert_eq!((confirmed(1), confirmed(2)), (Some(3), Some(2)));
+    }
+
+    #[test]
+    fn test_confirmations_unknown_within_acceptance_gap() {
+        let (keyspace, _) = index("acceptance-gap", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let confirmations = Confirmations::new(&keyspace).unwrap();
+        let confirmed = |i| confirmations.get_confirmations(&tx_id(i)).unwrap();
+        // the syncer skipped the pruned span of DAA 15 to 20
+        AcceptanceGapsPartition::new(&keyspace)
+            .unwrap()
+            .add_gap(&BlockGap {
+                from_daa_score: 15,
+                from_blue_work: BlueWorkType::from_u64(15),
+                from_block_hash: RpcHash::from_u64_word(15),
+                to_blue_work: BlueWorkType::from_u64(20),
+                to_block_hash: RpcHash::from_u64_word(2),
+                to_daa_score: 20,
+            })
+            .unwrap();
+        // blocks 1 and 3 are outside of it, block 2 is its end, block 4 has no header
+        assert_eq!(
+            (1..=4).map(confirmed).collect::<Vec<_>>(),
+            vec![Some(0), None, Some(0), Some(0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_finalized_acceptance_survives_reorg() {
+        let (_keyspace, mut processor) = index("finality", &[], DEFAULT_DEEP_REORG_DEPTH);
+        processor.finality_depth = Some(2);
+        let finalized_tx = processor.finalized_tx_partition.clone();
+        let mut events = processor.indexed_blocks.subscribe();
+        let finalized = |i| finalized_tx.get(&tx_id(i)).unwrap();
+        let acceptance = |block, chain_index| {
+            Some(FinalizedAcceptance {
+                accepting_block_hash: RpcHash::from_u64_word(block),
+                chain_index,
+            })
+        };
+
+        processor
+            .handle_vcc(&accepting_vcc(&[(1, &[1])], &[]))
+            .unwrap();
+        assert_eq!(finalized(1), None);
+        processor
+            .handle_vcc(&accepting_vcc(&[(2, &[2])], &[]))
+            .unwrap();
+        assert_eq!(finalized(1), acceptance(1, 0));
+        // reorg above the finalized block
+        processor
+            .handle_vcc(&accepting_vcc(&[(3, &[3])], &[2]))
+            .unwrap();
+        processor
+            .handle_vcc(&accepting_vcc(&[(4, &[2])], &[]))
+            .unwrap();
+        assert_eq!((finalized(2), finalized(3)), (None, acceptance(3, 1)));
+        assert_eq!(processor.metrics.snapshot().finality_violations, 0);
+
+        // contradicts both finalized blocks
+        processor
+            .handle_vcc(&accepting_vcc(&[(5, &[])], &[4, 3, 1]))
+            .unwrap();
+        assert_eq!(processor.metrics.snapshot().finality_violations, 2);
+        assert_eq!(
+            (finalized(1), finalized(3)),
+            (acceptance(1, 0), acceptance(3, 1))
+        );
+        let accepted_by = |i| {
+            let rtx = processor.tx_keyspace.read_tx();
+            processor
+                .tx_id_to_acceptance_partition
+                .get_by_tx_id(&rtx, &tx_id(i).as_bytes())
+                .next()
+                .unwrap()
+                .unwrap()
+                .0
+                .accepted_by_block_hash
+        };
+        assert_eq!(accepted_by(1), RpcHash::from_u64_word(1).as_bytes());
+        assert_eq!(accepted_by(2), [0; 32]);
+
+        drop(processor);
+        let mut published = Vec::new();
+        let mut chain_changes = Vec::new();
+        while let Some(event) = events.recv().await {
+            match event {
+                IndexEvent::ChainBlockChanged(change) => chain_changes.push(change),
+                event => published.push(event),
+            }
+        }
+        // removed blocks come before the added ones of the same change
+        assert_eq!(
+            chain_changes
+                .iter()
+                .map(|change| (change.hash, change.added))
+                .collect::<Vec<_>>(),
+            [
+                (1, true),
+                (2, true),
+                (2, false),
+                (3, true),
+                (4, true),
+                (4, false),
+                (3, false),
+                (1, false),
+                (5, true),
+            ]
+            .map(|(block, added)| (RpcHash::from_u64_word(block), added))
+        );
+        assert_eq!(
+            chain_changes
+                .iter()
+                .find(|change| change.added)
+                .map(|change| change.accepted_tx_count),
+            Some(1)
+        );
+        assert_eq!(
+            published,
+            [(1, 1, 0), (3, 3, 1)]
+                .map(|(i, block, chain_index)| {
+                    IndexEvent::from(TransactionFinalized {
+                        tx_id: tx_id(i),
+                        accepting_block_hash: RpcHash::from_u64_word(block),
+                        chain_index,
+                    })
+                })
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_reorg_swaps_conflicting_spend_balances() {
+        let (keyspace, mut processor) = index("balances", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let balances = Balances::new(&keyspace).unwrap();
+        processor.balances = Some(balances.clone());
+        processor.finality_depth = Some(3);
+        let address = |byte| RpcAddress::new(Prefix::Mainnet, Version::PubKey, &[byte; 32]);
+        let (x, y, z) = (address(1), address(2), address(3));
+
+        // 10 pays 100 to x, 11 and 12 both spend it: 11 pays y, 12 pays 60 to z and 40 back
+        let outpoints = OutpointPartition::new(&keyspace).unwrap();
+        let inputs = TxInputPartition::new(&keyspace).unwrap();
+        let mut wtx = keyspace.write_tx().unwrap();
+        for (tx, index, value, to) in [
+            (10, 0, 100, &x),
+            (11, 0, 100, &y),
+            (12, 0, 60, &z),
+            (12, 1, 40, &x),
+        ] {
+            let output = IndexedOutput::new(value, pay_to_address_script(to), None);
+            outpoints
+                .insert_wtx(&mut wtx, tx_id(tx), index, &output)
+                .unwrap();
+        }
+        inputs.insert_wtx(&mut wtx, tx_id(11), 0, tx_id(10), 0);
+        inputs.insert_wtx(&mut wtx, tx_id(12), 0, tx_id(10), 0);
+        wtx.commit().unwrap().unwrap();
+        let balance = |address: &RpcAddress| balances.get_balance(address).unwrap();
+        let balance_at = |address: &RpcAddress, daa_score| {
+            balances.get_balance_at_daa(address, daa_score).unwrap()
+        };
+
+        processor
+            .handle_vcc(&accepting_vcc(&[(1, &[10])], &[]))
+            .unwrap();
+        processor
+            .handle_vcc(&accepting_vcc(&[(2, &[11])], &[]))
+            .unwrap();
+        assert_eq!((balance(&x), balance(&y), balance(&z)), (0, 100, 0));
+        assert_eq!(balance_at(&x, 10), Some(100));
+        assert_eq!(balance_at(&y, 19), Some(0));
+
+        // 12 wins the conflict
+        processor
+            .handle_vcc(&accepting_vcc(&[(3, &[12])], &[2]))
+            .unwrap();
+        assert_eq!((balance(&x), balance(&y), balance(&z)), (40, 0, 60));
+        assert_eq!(balance_at(&x, 20), Some(100));
+        // and loses it again
+        processor
+            .handle_vcc(&accepting_vcc(&[(2, &[11])], &[3]))
+            .unwrap();
+        assert_eq!((balance(&x), balance(&y), balance(&z)), (0, 100, 0));
+
+        // block 1 is finalized, its deltas are dropped
+        assert_eq!(balance_at(&x, 9), Some(0));
+        processor
+            .handle_vcc(&accepting_vcc(&[(4, &[])], &[]))
+            .unwrap();
+        assert_eq!(balance_at(&x, 9), None);
+        assert_eq!(balance_at(&x, 10), Some(100));
+        assert_eq!(balance(&x), 0);
+    }
+
+    #[test]
+    fn test_reorg_reverts_accepted_aggregates() {
+        let (keyspace, mut processor) = index("aggregates", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let aggregates = Aggregates::new(&keyspace, 10).unwrap();
+        processor.aggregates = Some(aggregates.clone());
+        let accepted = || {
+            aggregates
+                .get_aggregates(0..10)
+                .unwrap()
+                .iter()
+                .map(|bucket| (bucket.bucket, bucket.accepted_tx_count))
+                .collect::<Vec<_>>()
+        };
+
+        processor
+            .handle_vcc(&accepting_vcc(&[(1, &[1])], &[]))
+            .unwrap();
+        processor
+            .handle_vcc(&accepting_vcc(&[(2, &[2, 3, 0])], &[]))
+            .unwrap();
+        assert_eq!(accepted(), [(1, 1), (2, 3)]);
+
+        // block 3 at DAA score 21 replaces block 2 in bucket 2
+        processor
+            .handle_vcc(&accepting_vcc(&[(3, &[2])], &[2]))
+            .unwrap();
+        assert_eq!(accepted(), [(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn test_reorg_reverts_accepted_rewards() {
+        let (keyspace, mut processor) = index("supply", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let supply = Supply::new(&keyspace).unwrap();
+        processor.supply = Some(supply.clone());
+        // blocks 2 and 3 sit on 1, 4 on 2, the coinbase of block n is payment 10 + n
+        let relations = BlockRelationsPartition::new(&keyspace).unwrap();
+        let rewards = BlockRewardPartition::new(&keyspace).unwrap();
+        let hash = RpcHash::from_u64_word;
+        let mut wtx = keyspace.write_tx().unwrap();
+        for (block, selected_parent) in [(2, 1), (3, 1), (4, 2)] {
+            relations
+                .insert_wtx(
+                    &mut wtx,
+                    hash(block),
+                    &BlockRelations {
+                        selected_parent: hash(selected_parent),
+                        merge_set_blues: vec![hash(selected_parent)],
+                        merge_set_reds: vec![],
+                    },
+                )
+                .unwrap();
+        }
+        for block in [1, 2] {
+            rewards.insert_wtx(
+                &mut wtx,
+                hash(block),
+                &BlockReward {
+                    coinbase_tx_id: tx_id(10 + block),
+                    reward: 100 * block,
+                },
+            );
+        }
+        wtx.commit().unwrap().unwrap();
+        let accepted_rewards = || supply.get_supply().unwrap().accepted_rewards;
+
+        // block 1 accepts a coinbase that was never indexed
+        processor
+            .handle_vcc(&accepting_vcc(&[(1, &[1])], &[]))
+            .unwrap();
+        processor
+            .handle_vcc(&accepting_vcc(&[(2, &[11, 2]), (4, &[12])], &[]))
+            .unwrap();
+        assert_eq!(accepted_rewards(), 100 + 200);
+        // delivered again, counted once
+        processor
+            .handle_vcc(&accepting_vcc(&[(4, &[12])], &[]))
+            .unwrap();
+        assert_eq!(accepted_rewards(), 300);
+
+        // block 3 replaces 2 and 4, accepting the coinbase of 1 again
+        processor
+            .handle_vcc(&accepting_vcc(&[(3, &[11])], &[4, 2]))
+            .unwrap();
+        assert_eq!(accepted_rewards(), 100);
+    }
+
+    #[test]
+    fn test_reward_indexed_after_acceptance_is_applied() {
+        let (keyspace, mut processor) = index("supply-pending", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let supply = Supply::new(&keyspace).unwrap();
+        processor.supply = Some(supply.clone());
+        // block n sits on n - 1, its coinbase is payment 10 + n
+        let relations = BlockRelationsPartition::new(&keyspace).unwrap();
+        let rewards = BlockRewardPartition::new(&keyspace).unwrap();
+        let hash = RpcHash::from_u64_word;
+        let mut wtx = keyspace.write_tx().unwrap();
+        for block in [2, 3] {
+            relations
+                .insert_wtx(
+                    &mut wtx,
+                    hash(block),
+                    &BlockRelations {
+                        selected_parent: hash(block - 1),
+                        merge_set_blues: vec![hash(block - 1)],
+                        merge_set_reds: vec![],
+                    },
+                )
+                .unwrap();
+        }
+        wtx.commit().unwrap().unwrap();
+        let index_reward = |block| {
+            let mut wtx = keyspace.write_tx().unwrap();
+            rewards.insert_wtx(
+                &mut wtx,
+                hash(block),
+                &BlockReward {
+                    coinbase_tx_id: tx_id(10 + block),
+                    reward: 100 * block,
+                },
+            );
+            wtx.commit().unwrap().unwrap();
+        };
+        let accepted_rewards = || supply.get_supply().unwrap().accepted_rewards;
+
+        // the chain is accepted before the block processor indexed the rewards
+        processor
+            .handle_vcc(&accepting_vcc(&[(2, &[11]), (3, &[12])], &[]))
+            .unwrap();
+        assert_eq!(accepted_rewards(), 0);
+        index_reward(1);
+        processor
+            .handle_vcc(&accepting_vcc(&[(4, &[])], &[]))
+            .unwrap();
+        assert_eq!(accepted_rewards(), 100);
+
+        // block 3 is removed while pending, its reward is not applied anymore
+        processor
+            .handle_vcc(&accepting_vcc(&[(5, &[])], &[4, 3]))
+            .unwrap();
+        index_reward(2);
+        processor
+            .handle_vcc(&accepting_vcc(&[(6, &[])], &[]))
+            .unwrap();
+        assert_eq!(accepted_rewards(), 100);
+    }
+
+    #[test]
+    fn test_unindexed_acceptance_requests_backfill() {
+        let (keyspace, mut processor) = index("unindexed", &[], DEFAULT_DEEP_REORG_DEPTH);
+        let (backfill_tx, mut backfill_rx) = tokio::sync::mpsc::channel(4);
+        processor.backfill_requests = Some(backfill_tx);
+        processor.unindexed_acceptance_threshold = 3;
+        let mut wtx = keyspace.write_tx().unwrap();
+        processor
+            .metadata_partition
+            .set_block_tip(
+                &mut wtx,
+                Cursor {
+                    daa_score: 100,
+                    blue_work: BlueWorkType::from_u64(100),
+                    hash: RpcHash::from_u64_word(100),
+                },
+            )
+            .unwrap();
+        wtx.commit().unwrap().unwrap();
+
+        // blocks holding the transactions 10 to 13 were dropped by the block processor
+        processor
+            .handle_vcc(&accepting_vcc(&[(1, &[1]), (2, &[2, 10, 11])], &[]))
+            .unwrap();
+        assert!(backfill_rx.try_recv().is_err());
+        processor
+            .handle_vcc(&accepting_vcc(&[(3, &[12, 13])], &[]))
+            .unwrap();
+        assert_eq!(
+            backfill_rx.try_recv().unwrap(),
+            BlockGap {
+                from_daa_score: 10,
+                from_blue_work: BlueWorkType::from_u64(10),
+                from_block_hash: RpcHash::from_u64_word(1),
+                to_blue_work: BlueWorkType::from_u64(21),
+                to_block_hash: RpcHash::from_u64_word(3),
+                to_daa_score: 21,
+            }
+        );
+        let snapshot = processor.metrics.snapshot();
+        assert_eq!(
+            (snapshot.auto_created_gaps, snapshot.unindexed_accepted_txs),
+            (1, 4)
+        );
     }
 }
@@ -0,0 +1,530 @@
+//! Simnet nodes, a wallet paying protocol transactions and the checks of the indexed data
+//! against the node.
+//!
+//! Nodes are launched from the kaspad binary of `KASIA_IT_KASPAD`, each with its own data
+//! directory and ports. `KASIA_IT_NODE_URL` points the tests at a running simnet node instead,
+//! which must run with `--utxoindex --enable-unsynced-mining`. Simnet skips proof of work, so
+//! block templates are submitted as they are.
+
+use fjall::TxKeyspace;
+use indexer_lib::config::{IndexerConfig, NodeConfig};
+use indexer_lib::error::IndexerResult;
+use indexer_lib::indexer::{Indexer, create_rpc_client};
+use indexer_lib::queries::TxAcceptance;
+use kaspa_addresses::{Address, Prefix, Version};
+use kaspa_consensus_core::sign::sign;
+use kaspa_consensus_core::subnets::SUBNETWORK_ID_NATIVE;
+use kaspa_consensus_core::tx::{
+    MutableTransaction, Transaction, TransactionInput, TransactionOutpoint, TransactionOutput,
+    UtxoEntry,
+};
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_rpc_core::{
+    GetVirtualChainFromBlockRequest, GetVirtualChainFromBlockResponse, RpcBlock,
+    RpcContextualPeerAddress, RpcHash, RpcTransactionId, SubmitBlockReport,
+};
+use kaspa_txscript::pay_to_address_script;
+use kaspa_wrpc_client::KaspaRpcClient;
+use kaspa_wrpc_client::client::{ConnectOptions, ConnectStrategy};
+use secp256k1::{Keypair, SECP256K1};
+use std::collections::{HashMap, HashSet};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// Path of the kaspad binary the nodes are launched from
+pub const KASPAD_VAR: &str = "KASIA_IT_KASPAD";
+/// wRPC borsh url of a running simnet node used instead of launching one
+pub const NODE_URL_VAR: &str = "KASIA_IT_NODE_URL";
+/// Blocks mined with transactions once the miner can spend, 20 by default
+pub const BLOCKS_VAR: &str = "KASIA_IT_BLOCKS";
+/// Transactions submitted before each of those blocks, 4 by default
+pub const TXS_VAR: &str = "KASIA_IT_TXS";
+
+/// Sent to the receiver by each payment, the rest goes back to the miner
+const PAYMENT: u64 = 100_000_000;
+const FEE: u64 = 100_000;
+/// Blocks mined between attempts to spend the first coinbase
+const MATURITY_STEP: usize = 100;
+const MAX_MATURITY_STEPS: usize = 40;
+const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(300);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn env_or(var: &str, default: usize) -> usize {
+    std::env::var(var)
+        .ok()
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("{var} is not a number"))
+        })
+        .unwrap_or(default)
+}
+
+pub fn kaspad() -> Option<PathBuf> {
+    std::env::var_os(KASPAD_VAR).map(PathBuf::from)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port()
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+pub struct SimnetNode {
+    pub url: String,
+    /// Address other nodes connect to, none for the node of [`NODE_URL_VAR`]
+    pub p2p: Option<String>,
+    pub rpc: KaspaRpcClient,
+    /// The launched process with its data directory
+    process: Option<(Child, PathBuf)>,
+    templates: u64,
+}
+
+impl SimnetNode {
+    /// The node of [`NODE_URL_VAR`], else one launched from [`KASPAD_VAR`]
+    pub async fn start(name: &str) -> Self {
+        match std::env::var(NODE_URL_VAR) {
+            Ok(url) => Self::connect(url, None, None).await,
+            Err(_) => Self::launch(name).await,
+        }
+    }
+
+    pub async fn launch(name: &str) -> Self {
+        let kaspad = kaspad()
+            .unwrap_or_else(|| panic!("set {KASPAD_VAR} to a kaspad binary or {NODE_URL_VAR}"));
+        let appdir = std::env::temp_dir().join(format!(
+            "kasia-it-{name}-{}-{}",
+            std::process::id(),
+            unix_nanos()
+        ));
+        let (wrpc, grpc, p2p) = (free_port(), free_port(), free_port());
+        let child = Command::new(&kaspad)
+            .args([
+                "--simnet",
+                "--utxoindex",
+                "--unsaferpc",
+                "--enable-unsynced-mining",
+                "--nodnsseed",
+                "--disable-upnp",
+                "--nologfiles",
+                "--yes",
+            ])
+            .arg(format!("--appdir={}", appdir.display()))
+            .arg(format!("--rpclisten-borsh=127.0.0.1:{wrpc}"))
+            .arg(format!("--rpclisten=127.0.0.1:{grpc}"))
+            .arg(format!("--listen=127.0.0.1:{p2p}"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|err| panic!("failed to launch {}: {err}", kaspad.display()));
+        Self::connect(
+            format!("ws://127.0.0.1:{wrpc}"),
+            Some(format!("127.0.0.1:{p2p}")),
+            Some((child, appdir)),
+        )
+        .await
+    }
+
+    async fn connect(url: String, p2p: Option<String>, process: Option<(Child, PathBuf)>) -> Self {
+        let rpc = create_rpc_client(&node_config(&url)).unwrap();
+        // retried while the node starts
+        rpc.connect(Some(ConnectOptions {
+            block_async_connect: true,
+            connect_timeout: Some(Duration::from_secs(60)),
+            strategy: ConnectStrategy::Retry,
+            ..Default::default()
+        }))
+        .await
+        .unwrap_or_else(|err| panic!("failed to connect to {url}: {err}"));
+        Self {
+            url,
+            p2p,
+            rpc,
+            process,
+            templates: 0,
+        }
+    }
+
+    /// Mines `count` blocks paying `pay_address`, each holding the mempool transactions
+    pub async fn mine(&mut self, pay_address: &Address, count: usize) {
+        for _ in 0..count {
+            // a distinct coinbase, so a cached template is never submitted twice
+            self.templates += 1;
+            let template = self
+                .rpc
+                .get_block_template(pay_address.clone(), self.templates.to_le_bytes().to_vec())
+                .await
+                .unwrap();
+            let response = self.rpc.submit_block(template.block, false).await.unwrap();
+            assert!(
+                matches!(response.report, SubmitBlockReport::Success),
+                "block rejected: {:?}",
+                response.report
+            );
+        }
+    }
+
+    pub async fn sink(&self) -> RpcHash {
+        self.rpc.get_block_dag_info().await.unwrap().sink
+    }
+
+    /// Chain changes from the pruning point to the sink, with the accepted transactions
+    pub async fn virtual_chain(&self) -> GetVirtualChainFromBlockResponse {
+        let pruning_point = self
+            .rpc
+            .get_block_dag_info()
+            .await
+            .unwrap()
+            .pruning_point_hash;
+        self.rpc
+            .get_virtual_chain_from_block_call(
+                None,
+                GetVirtualChainFromBlockRequest::new(pruning_point, true),
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Every block past the pruning point with its transactions
+    pub async fn blocks(&self) -> Vec<RpcBlock> {
+        let mut low = self
+            .rpc
+            .get_block_dag_info()
+            .await
+            .unwrap()
+            .pruning_point_hash;
+        let mut seen = HashSet::from([low]);
+        let mut blocks = Vec::new();
+        loop {
+            let page = self
+                .rpc
+                .get_blocks(Some(low), true, true)
+                .await
+                .unwrap()
+                .blocks
+                .into_iter()
+                .filter(|block| seen.insert(block.header.hash))
+                .collect::<Vec<_>>();
+            let Some(last) = page.last() else {
+                return blocks;
+            };
+            low = last.header.hash;
+            blocks.extend(page);
+        }
+    }
+
+    /// Connects to `peer` and waits until both have the same sink
+    pub async fn sync_with(&self, peer: &SimnetNode) {
+        let address = peer
+            .p2p
+            .as_deref()
+            .expect("only launched nodes can be connected")
+            .parse::<RpcContextualPeerAddress>()
+            .unwrap();
+        self.rpc.add_peer(address, true).await.unwrap();
+        let started = Instant::now();
+        while self.sink().await != peer.sink().await {
+            assert!(
+                started.elapsed() < CATCH_UP_TIMEOUT,
+                "{} never synced",
+                self.url
+            );
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Drop for SimnetNode {
+    fn drop(&mut self) {
+        if let Some((child, appdir)) = &mut self.process {
+            _ = child.kill();
+            _ = child.wait();
+            _ = std::fs::remove_dir_all(appdir);
+        }
+    }
+}
+
+fn node_config(url: &str) -> NodeConfig {
+    NodeConfig {
+        network: "simnet".to_string(),
+        url: Some(url.to_string()),
+        ..Default::default()
+    }
+}
+
+fn indexer_config(node: &SimnetNode) -> IndexerConfig {
+    let mut config = IndexerConfig::default();
+    config.node = node_config(&node.url);
+    config.storage.outpoint_index = true;
+    config.storage.address_balances = true;
+    config
+}
+
+/// An [`Indexer`] following `node` on `tx_keyspace` that is not run, for the maintenance
+/// entries
+pub async fn maintenance_indexer(node: &SimnetNode, tx_keyspace: TxKeyspace) -> Indexer {
+    Indexer::builder()
+        .config(indexer_config(node))
+        .database(tx_keyspace)
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Keys of a miner and of receivers, new for every run so a reused node starts them empty
+pub struct Wallet {
+    keys: Vec<Keypair>,
+    spent: HashSet<TransactionOutpoint>,
+    /// Ids of the payments submitted so far
+    pub submitted: Vec<RpcTransactionId>,
+}
+
+impl Wallet {
+    pub fn new(receivers: usize) -> Self {
+        let run = unix_nanos().to_le_bytes();
+        let keys = (0..=receivers)
+            .map(|i| {
+                let mut seed = [1u8; 32];
+                seed[..16].copy_from_slice(&run);
+                seed[16] = i as u8;
+                Keypair::from_seckey_slice(SECP256K1, &seed).unwrap()
+            })
+            .collect();
+        Self {
+            keys,
+            spent: HashSet::new(),
+            submitted: Vec::new(),
+        }
+    }
+
+    fn address_of(key: &Keypair) -> Address {
+        Address::new(
+            Prefix::Simnet,
+            Version::PubKey,
+            &key.x_only_public_key().0.serialize(),
+        )
+    }
+
+    pub fn miner(&self) -> Address {
+        Self::address_of(&self.keys[0])
+    }
+
+    pub fn receivers(&self) -> Vec<Address> {
+        self.keys[1..].iter().map(Self::address_of).collect()
+    }
+
+    /// Mines until the miner can spend a coinbase, the maturity differs between node versions
+    pub async fn mine_to_maturity(&mut self, node: &mut SimnetNode) {
+        for _ in 0..MAX_MATURITY_STEPS {
+            node.mine(&self.miner(), MATURITY_STEP).await;
+            if self.pay(node, 1).await > 0 {
+                return;
+            }
+        }
+        panic!(
+            "no coinbase matured within {} blocks",
+            MATURITY_STEP * MAX_MATURITY_STEPS
+        );
+    }
+
+    /// Submits up to `count` payments from the miner to the receivers in turn, each with a
+    /// protocol payload. Returns how many the node took, immature coinbases are skipped
+    pub async fn pay(&mut self, node: &SimnetNode, count: usize) -> usize {
+        let miner = self.miner();
+        let receivers = self.receivers();
+        let mut utxos = node
+            .rpc
+            .get_utxos_by_addresses(vec![miner.clone()])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (TransactionOutpoint::from(entry.outpoint), entry.utxo_entry))
+            .filter(|(outpoint, entry)| {
+                !self.spent.contains(outpoint) && entry.amount > PAYMENT + FEE
+            })
+            .collect::<Vec<_>>();
+        // change outputs first, then the oldest coinbases
+        utxos.sort_by_key(|(_, entry)| (entry.is_coinbase, entry.block_daa_score));
+        let mut paid = 0;
+        for (outpoint, entry) in utxos {
+            if paid == count {
+                break;
+            }
+            let receiver = &receivers[self.submitted.len() % receivers.len()];
+            let tx = Transaction::new(
+                0,
+                vec![TransactionInput::new(outpoint, vec![], 0, 1)],
+                vec![
+                    TransactionOutput::new(PAYMENT, pay_to_address_script(receiver)),
+                    TransactionOutput::new(
+                        entry.amount - PAYMENT - FEE,
+                        pay_to_address_script(&miner),
+                    ),
+                ],
+                0,
+                SUBNETWORK_ID_NATIVE,
+                0,
+                format!("ciph_msg:1:payment:{:x}", self.submitted.len()).into_bytes(),
+            );
+            let utxo = UtxoEntry::new(
+                entry.amount,
+                entry.script_public_key.clone(),
+                entry.block_daa_score,
+                entry.is_coinbase,
+            );
+            let signed = sign(
+                MutableTransaction::with_entries(tx, vec![utxo]),
+                self.keys[0],
+            );
+            match node
+                .rpc
+                .submit_transaction((&signed.tx).into(), false)
+                .await
+            {
+                Ok(tx_id) => {
+                    self.spent.insert(outpoint);
+                    self.submitted.push(tx_id);
+                    paid += 1;
+                }
+                Err(_) if entry.is_coinbase => continue,
+                Err(err) => panic!("payment spending {outpoint} rejected: {err}"),
+            }
+        }
+        paid
+    }
+}
+
+/// The [`Indexer`] facade following a node, on a temporary database
+pub struct RunningIndexer {
+    pub indexer: Arc<Indexer>,
+    task: JoinHandle<IndexerResult<()>>,
+}
+
+impl RunningIndexer {
+    pub async fn start(node: &SimnetNode) -> Self {
+        let tx_keyspace = fjall::Config::new(std::env::temp_dir().join(format!(
+            "kasia-it-indexer-{}-{}",
+            std::process::id(),
+            unix_nanos()
+        )))
+        .temporary(true)
+        .open_transactional()
+        .unwrap();
+        let indexer = Arc::new(maintenance_indexer(node, tx_keyspace).await);
+        let task = tokio::spawn({
+            let indexer = indexer.clone();
+            async move { indexer.run().await }
+        });
+        Self { indexer, task }
+    }
+
+    /// Waits until the acceptance tip is the sink of `node` with no gaps left
+    pub async fn wait_for(&self, node: &SimnetNode) {
+        let started = Instant::now();
+        loop {
+            let sink = node.sink().await.to_string();
+            let status = self.indexer.status().unwrap();
+            let at_sink = status.vcp_tip.as_ref().is_some_and(|tip| tip.hash == sink);
+            if at_sink && status.gaps.is_empty() {
+                return;
+            }
+            assert!(
+                started.elapsed() < CATCH_UP_TIMEOUT,
+                "indexer never caught up with {sink}: {status:?}"
+            );
+            assert!(!self.task.is_finished(), "indexer stopped");
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Returns the database, removed once the last handle is dropped
+    pub async fn stop(self) -> TxKeyspace {
+        self.indexer.shutdown();
+        self.task.await.unwrap().unwrap();
+        self.indexer.tx_keyspace().clone()
+    }
+
+    pub fn is_chain_block(&self, hash: RpcHash) -> bool {
+        let block = self.indexer.get_block_full(hash).unwrap();
+        let block = block.unwrap_or_else(|| panic!("block {hash} is not indexed"));
+        block.is_chain_block == Some(true)
+    }
+
+    /// Every chain block of the node is a chain block of the index, every block is indexed
+    /// and the acceptance of each tracked transaction is the one the node reports. The
+    /// balances of `addresses` are the ones of the node UTXO index
+    pub async fn assert_matches(&self, node: &SimnetNode, addresses: &[Address]) {
+        let chain = node.virtual_chain().await;
+        assert!(chain.removed_chain_block_hashes.is_empty());
+        for hash in &chain.added_chain_block_hashes {
+            assert!(
+                self.is_chain_block(*hash),
+                "chain block {hash} is off the index chain"
+            );
+        }
+        let accepted = chain
+            .accepted_transaction_ids
+            .iter()
+            .flat_map(|accepted| {
+                accepted
+                    .accepted_transaction_ids
+                    .iter()
+                    .map(|tx_id| (*tx_id, accepted.accepting_block_hash))
+            })
+            .collect::<HashMap<_, _>>();
+
+        for block in node.blocks().await {
+            let hash = block.header.hash;
+            let indexed = self
+                .indexer
+                .get_block_full(hash)
+                .unwrap()
+                .unwrap_or_else(|| panic!("block {hash} is not indexed"));
+            for tx in indexed.transactions.unwrap_or_default() {
+                match tx.acceptance {
+                    TxAcceptance::Accepted {
+                        accepting_block_hash,
+                        ..
+                    } => assert_eq!(
+                        accepted.get(&tx.tx_id),
+                        Some(&accepting_block_hash),
+                        "acceptance of {}",
+                        tx.tx_id
+                    ),
+                    TxAcceptance::NotAccepted => assert!(
+                        !accepted.contains_key(&tx.tx_id),
+                        "{} is accepted by the node",
+                        tx.tx_id
+                    ),
+                    TxAcceptance::Untracked => {}
+                }
+            }
+        }
+
+        let balances = self.indexer.balances().expect("balances are configured");
+        for address in addresses {
+            let expected = node
+                .rpc
+                .get_balance_by_address(address.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                balances.get_balance(address).unwrap(),
+                expected as i64,
+                "balance of {address}"
+            );
+        }
+    }
+}
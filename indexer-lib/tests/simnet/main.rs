@@ -0,0 +1,152 @@
+//! End-to-end tests of the [`Indexer`](indexer_lib::indexer::Indexer) facade against simnet
+//! nodes, built with the `it` feature: `cargo test -p indexer-lib --features it --test simnet`.
+//! See [`harness`] for the nodes they run against.
+
+mod harness;
+
+use harness::{
+    BLOCKS_VAR, RunningIndexer, SimnetNode, TXS_VAR, Wallet, env_or, kaspad, maintenance_indexer,
+};
+use indexer_lib::database::difftest::{self, Whitelist};
+use indexer_lib::database::snapshot;
+use indexer_lib::reindex::{Reindex, ReindexTarget};
+
+/// Receivers of the payments, their balances are compared with the node
+const SAMPLE_ADDRESSES: usize = 4;
+/// Mined after the last payments, the transactions of the sink are only accepted by the
+/// virtual until a chain block merges it
+const SETTLE_BLOCKS: usize = 3;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_indexes_mined_blocks() {
+    let mut node = SimnetNode::start("pipeline").await;
+    let mut wallet = Wallet::new(SAMPLE_ADDRESSES);
+    let indexer = RunningIndexer::start(&node).await;
+
+    wallet.mine_to_maturity(&mut node).await;
+    for _ in 0..env_or(BLOCKS_VAR, 20) {
+        wallet.pay(&node, env_or(TXS_VAR, 4)).await;
+        node.mine(&wallet.miner(), 1).await;
+    }
+    node.mine(&wallet.miner(), SETTLE_BLOCKS).await;
+    indexer.wait_for(&node).await;
+
+    indexer.assert_matches(&node, &wallet.receivers()).await;
+    let chain = node.virtual_chain().await;
+    for tx_id in &wallet.submitted {
+        assert!(
+            chain
+                .accepted_transaction_ids
+                .iter()
+                .any(|accepted| accepted.accepted_transaction_ids.contains(tx_id)),
+            "payment {tx_id} was never accepted"
+        );
+    }
+    indexer.stop().await;
+}
+
+/// The indexed node mines a short chain, then connects to a node that mined a longer one on
+/// its own. The chain blocks of the short chain end up off the chain
+#[tokio::test(flavor = "multi_thread")]
+async fn test_follows_a_reorg_to_a_heavier_chain() {
+    if kaspad().is_none() {
+        eprintln!("skipped, the competing tips need two launched nodes");
+        return;
+    }
+    let mut primary = SimnetNode::launch("reorg-primary").await;
+    let mut competitor = SimnetNode::launch("reorg-competitor").await;
+    let wallet = Wallet::new(0);
+    let indexer = RunningIndexer::start(&primary).await;
+
+    primary.mine(&wallet.miner(), 5).await;
+    indexer.wait_for(&primary).await;
+    let orphaned = primary.virtual_chain().await.added_chain_block_hashes;
+    assert_eq!(orphaned.len(), 5);
+
+    competitor.mine(&wallet.miner(), 12).await;
+    primary.sync_with(&competitor).await;
+    indexer.wait_for(&primary).await;
+
+    for hash in orphaned {
+        assert!(
+            !indexer.is_chain_block(hash),
+            "{hash} is still a chain block"
+        );
+    }
+    assert_eq!(
+        primary.virtual_chain().await.added_chain_block_hashes.len(),
+        12
+    );
+    indexer.assert_matches(&primary, &[]).await;
+    indexer.stop().await;
+}
+
+/// The records of every reindex target are dropped for the whole index, reindexing the range
+/// writes them back as the live processors did
+#[tokio::test(flavor = "multi_thread")]
+async fn test_reindex_restores_a_corrupted_range() {
+    let mut node = SimnetNode::start("reindex").await;
+    let mut wallet = Wallet::new(SAMPLE_ADDRESSES);
+    let indexer = RunningIndexer::start(&node).await;
+
+    wallet.mine_to_maturity(&mut node).await;
+    for _ in 0..env_or(BLOCKS_VAR, 20) {
+        wallet.pay(&node, env_or(TXS_VAR, 4)).await;
+        node.mine(&wallet.miner(), 1).await;
+    }
+    node.mine(&wallet.miner(), SETTLE_BLOCKS).await;
+    indexer.wait_for(&node).await;
+    let tx_keyspace = indexer.stop().await;
+
+    let clean_path =
+        std::env::temp_dir().join(format!("kasia-it-reindex-clean-{}", std::process::id()));
+    snapshot::create_snapshot(&tx_keyspace, &clean_path).unwrap();
+    let (clean, _) = snapshot::open_snapshot(&clean_path).unwrap();
+
+    let daa_range = 0..u64::MAX;
+    let reindex = Reindex::new(&tx_keyspace).unwrap();
+    let blocks = reindex.blocks(&daa_range).unwrap();
+    let mut wtx = tx_keyspace.write_tx().unwrap();
+    let deleted = reindex
+        .delete_wtx(&mut wtx, &daa_range, &blocks, &ReindexTarget::ALL)
+        .unwrap();
+    wtx.commit().unwrap().unwrap();
+    assert!(deleted[&ReindexTarget::AddressIndex] > 0);
+    assert!(
+        !difftest::compare(&clean, &tx_keyspace, &Whitelist::default())
+            .unwrap()
+            .is_clean()
+    );
+
+    // reprocessing also rewrites the records keyed by transaction, only the partitions of the
+    // targets are compared
+    let targets = [
+        "block_stats",
+        "block_miner",
+        "miner_blocks",
+        "token_operations",
+        "handshake_by_receiver",
+        "payment_by_receiver",
+    ];
+    let whitelist = Whitelist::parse(
+        &tx_keyspace
+            .list_partitions()
+            .into_iter()
+            .map(|name| name.to_string())
+            .filter(|name| !targets.contains(&name.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    );
+    let summary = maintenance_indexer(&node, tx_keyspace.clone())
+        .await
+        .reindex(daa_range, &ReindexTarget::ALL, true)
+        .await
+        .unwrap();
+    assert_eq!(summary.blocks, blocks.len());
+    assert_eq!(summary.rewritten, deleted);
+    let report = difftest::compare(&clean, &tx_keyspace, &whitelist).unwrap();
+    assert!(report.is_clean(), "{report:?}");
+
+    drop(clean);
+    std::fs::remove_dir_all(clean_path).unwrap();
+}
@@ -1,381 +1,325 @@
 use dotenv::dotenv;
-use fjall::Config;
-use indexer_lib::database::headers::{
-    BlockCompactHeaderPartition, BlockGapsPartition, DaaIndexPartition,
-};
-use indexer_lib::database::messages::{
-    ContextualMessageBySenderPartition, HandshakeByReceiverPartition, HandshakeBySenderPartition,
-    PaymentByReceiverPartition, PaymentBySenderPartition, TxIdToHandshakePartition,
-    TxIdToPaymentPartition,
-};
-use indexer_lib::database::processing::{
-    AcceptingBlockToTxIDPartition, PendingSenderResolutionPartition, SkipTxByBlockPartition,
-    SkipTxPartition, TxIDToAcceptancePartition, UnknownAcceptingDaaPartition, UnknownTxPartition,
-};
-use indexer_lib::fifo_set::FifoSet;
-use indexer_lib::metrics::IndexerMetricsSnapshot;
-use indexer_lib::periodic_processor::{run_ticker, Notification, PeriodicProcessor};
-use indexer_lib::virtual_chain_processor::VirtualChainProcessor;
+use indexer_lib::config::{IndexerConfig, CONFIG_PATH_VAR};
+use indexer_lib::crash_handler::{self, CrashContext};
+use indexer_lib::database::crash_reports::CrashReportsPartition;
+use indexer_lib::database::headers::GapHistoryPartition;
+use indexer_lib::database::processing::AcceptanceHistoryPartition;
+use indexer_lib::database::provenance::Provenance;
+use indexer_lib::indexer::{create_rpc_client, Indexer};
+use indexer_lib::ingest_trace::TRACE_TARGET;
+use indexer_lib::reindex::ReindexTarget;
 use indexer_lib::{
-    block_processor::BlockProcessor,
-    database::{self},
-    metrics::create_shared_metrics_from_snapshot,
-    resolver::Resolver,
-    selected_chain_syncer::SelectedChainSyncer,
-    subscriber::Subscriber,
-    APP_IS_RUNNING,
+    database::{self, compaction, difftest, export, integrity, schema, snapshot},
+    metrics_exporter, status,
 };
+use kaspa_rpc_core::api::rpc::RpcApi;
+use kaspa_rpc_core::{RpcHash, RpcTransactionId};
 use kaspa_wrpc_client::client::{ConnectOptions, ConnectStrategy};
-use kaspa_wrpc_client::prelude::{NetworkId, NetworkType};
-use kaspa_wrpc_client::{KaspaRpcClient, WrpcEncoding};
-use parking_lot::Mutex;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use std::time::Duration;
 use time::macros::format_description;
-use tracing::{error, info};
+use tracing::{error, info, Level};
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_subscriber::filter::Directive;
+use tracing_subscriber::filter::{Directive, Targets};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
 
+#[cfg(feature = "profiling")]
+#[global_allocator]
+static ALLOCATOR: indexer_lib::profiling::ComponentAllocator<std::alloc::System> =
+    indexer_lib::profiling::ComponentAllocator(std::alloc::System);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
-    let db_path = std::env::var("KASIA_INDEXER_DB_PATH")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| std::env::home_dir().unwrap().join(".kasia-indexer"));
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let config_path = std::env::var(CONFIG_PATH_VAR)
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    // before anything is opened, so a config can be checked next to a running indexer
+    if let [command, subcommand, rest @ ..] = args.as_slice() {
+        if command == "config" && subcommand == "check" && rest.len() <= 1 {
+            let path = rest.first().map(PathBuf::from).or(config_path);
+            let config = IndexerConfig::resolve(path.as_deref())?;
+            print!("{}", config.to_toml()?);
+            return Ok(());
+        }
+    }
+    let config = IndexerConfig::resolve(config_path.as_deref())?;
+
+    let db_path = config.db_path();
+    #[cfg(unix)]
+    let snapshot_dir = config.snapshot_dir();
     let log_path = db_path.join("app_logs");
     std::fs::create_dir_all(&log_path)?;
-    let _g = init_logs(log_path)?;
-    let config = Config::new(&db_path).max_write_buffer_size(512 * 1024 * 1024);
-    let tx_keyspace = config.open_transactional()?;
-    let reorg_lock = Arc::new(Mutex::new(()));
-    // Partitions
-    let metadata_partition = database::metadata::MetadataPartition::new(&tx_keyspace)?;
+    let (_file_guard, _stdout_guard, tracer_provider) =
+        init_logs(log_path, config.telemetry.otlp_endpoint.as_deref())?;
+
+    // maintenance commands, the indexer itself is started when no command is given. Only the
+    // ones reading or writing the database open it, the others work next to a running indexer
+    let mut reprocess = None;
+    let mut reindex = None;
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
     {
-        metadata_partition.0.inner().major_compact()?;
+        [] => {}
+        ["snapshot", destination] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let info = snapshot::create_snapshot(&tx_keyspace, destination)?;
+            info!("Snapshot written to {destination}: {info:?}");
+            return Ok(());
+        }
+        ["provenance", "show"] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let provenance = Provenance::collect(&tx_keyspace, &tx_keyspace.read_tx())?;
+            println!("{provenance}");
+            return Ok(());
+        }
+        ["fsck"] | ["fsck", "--repair"] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let report = integrity::check(&tx_keyspace, args.len() == 2)?;
+            println!("{report}");
+            if !report.is_consistent() {
+                anyhow::bail!("Database is inconsistent");
+            }
+            return Ok(());
+        }
+        ["difftest", left, right] | ["difftest", left, right, "--whitelist", _] => {
+            let whitelist = match args.get(4) {
+                Some(manifest) => difftest::Whitelist::load(manifest)?,
+                None => Default::default(),
+            };
+            let report = difftest::compare_paths(left, right, &whitelist)?;
+            println!("{report}");
+            if !report.is_clean() {
+                anyhow::bail!("Databases diverged");
+            }
+            return Ok(());
+        }
+        ["export", "--partition", partition, "--out", out] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let mut writer = std::io::BufWriter::new(std::fs::File::create(out)?);
+            let header = export::export_partition(&tx_keyspace, partition, &mut writer)?;
+            info!("Exported {} records of {partition} to {out}", header.records);
+            return Ok(());
+        }
+        ["import", "--in", input] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let mut reader = std::io::BufReader::new(std::fs::File::open(input)?);
+            let header = export::import_partition(&tx_keyspace, &mut reader)?;
+            info!(
+                "Imported {} records into {} from {input}",
+                header.records, header.partition
+            );
+            return Ok(());
+        }
+        ["status"] => {
+            let rpc_client = create_rpc_client(&config.node)?;
+            rpc_client
+                .connect(Some(ConnectOptions {
+                    block_async_connect: true,
+                    connect_timeout: Some(Duration::from_millis(10_000)),
+                    strategy: ConnectStrategy::Fallback,
+                    ..Default::default()
+                }))
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to connect to node: {}", e))?;
+            let sink = rpc_client.get_block_dag_info().await?.sink;
+            let node_daa_score = rpc_client.get_block(sink, false).await?.header.daa_score;
+            rpc_client.disconnect().await?;
+            let tx_keyspace = database::open(&db_path)?;
+            let metadata_partition = database::metadata::MetadataPartition::new(&tx_keyspace)?;
+            println!(
+                "{}",
+                status::get_status(&metadata_partition, &tx_keyspace.read_tx(), node_daa_score)?
+            );
+            return Ok(());
+        }
+        ["status", "--running"] => {
+            let Some(addr) = &config.telemetry.metrics_addr else {
+                anyhow::bail!("The metrics address of the running indexer is not configured");
+            };
+            print!("{}", metrics_exporter::fetch(addr, "/status").await?);
+            return Ok(());
+        }
+        ["compact"] => {
+            let tx_keyspace = database::open(&db_path)?;
+            for run in compaction::compact_all(&tx_keyspace)? {
+                println!("{run}");
+            }
+            return Ok(());
+        }
+        ["compact", partition] => {
+            let tx_keyspace = database::open(&db_path)?;
+            println!("{}", compaction::compact_partition(&tx_keyspace, partition)?);
+            return Ok(());
+        }
+        ["schema", "describe"] => {
+            println!("{}", schema::describe_json());
+            return Ok(());
+        }
+        ["acceptance-history", tx_id] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let tx_id = RpcTransactionId::from_str(tx_id)?;
+            let records = AcceptanceHistoryPartition::new(&tx_keyspace)?
+                .get_rtx(&tx_keyspace.read_tx(), &tx_id.as_bytes())?;
+            if records.is_empty() {
+                println!("{tx_id} was never reorged");
+            }
+            for record in records {
+                println!("{record}");
+            }
+            return Ok(());
+        }
+        ["crash-reports", "list"] => {
+            let tx_keyspace = database::open(&db_path)?;
+            for report in CrashReportsPartition::new(&tx_keyspace)?.list()? {
+                println!("{report}");
+            }
+            return Ok(());
+        }
+        ["crash-reports", "show", id] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let crash_reports = CrashReportsPartition::new(&tx_keyspace)?;
+            let id = id.parse()?;
+            let Some(report) = crash_reports.get(id)? else {
+                anyhow::bail!("No crash report {id}");
+            };
+            println!("{}", report.report);
+            crash_reports.mark_viewed(id)?;
+            return Ok(());
+        }
+        ["crash-reports", "clear"] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let removed = CrashReportsPartition::new(&tx_keyspace)?.clear()?;
+            println!("Removed {removed} crash reports");
+            return Ok(());
+        }
+        ["gap-history"] | ["gap-history", _] => {
+            let tx_keyspace = database::open(&db_path)?;
+            let limit = match args.get(1) {
+                Some(limit) => limit.parse()?,
+                None => 20,
+            };
+            for record in GapHistoryPartition::new(&tx_keyspace)?.list_gap_history(limit)? {
+                println!("{record}");
+            }
+            return Ok(());
+        }
+        // needs the block worker, handled once it is built
+        ["reprocess", hash] => reprocess = Some(RpcHash::from_str(hash)?),
+        ["reindex", "--from-daa", from, "--to-daa", to, "--targets", targets, force @ ..]
+            if matches!(force, [] | ["--force"]) =>
+        {
+            reindex = Some((
+                from.parse::<u64>()?..to.parse::<u64>()?,
+                ReindexTarget::parse_list(targets)?,
+                !force.is_empty(),
+            ))
+        }
+        ["verify-snapshot", path] => {
+            let (_, info) = snapshot::open_snapshot(path)?;
+            info!("Snapshot {path}: {info:?}");
+            return Ok(());
+        }
+        _ => anyhow::bail!(
+            "Usage: indexer [snapshot <dest> | verify-snapshot <path> | provenance show | status [--running] | config check [<file>] | acceptance-history <tx-id> | crash-reports list|show <id>|clear | gap-history [<limit>] | reprocess <block-hash> | reindex --from-daa <daa> --to-daa <daa> --targets <fees,miners,token_operations,address_index> [--force] | fsck [--repair] | compact [<partition>] | schema describe | export --partition <name> --out <file> | import --in <file> | difftest <left-db> <right-db> [--whitelist <manifest>]]"
+        ),
     }
-
-    let handshake_by_receiver_partition = HandshakeByReceiverPartition::new(&tx_keyspace)?;
-    let tx_id_to_handshake_partition = TxIdToHandshakePartition::new(&tx_keyspace)?;
-    let contextual_message_partition = ContextualMessageBySenderPartition::new(&tx_keyspace)?;
-    let payment_by_receiver_partition = PaymentByReceiverPartition::new(&tx_keyspace)?;
-    let tx_id_to_payment_partition = TxIdToPaymentPartition::new(&tx_keyspace)?;
-    let tx_id_to_acceptance_partition = TxIDToAcceptancePartition::new(&tx_keyspace)?;
-    let skip_tx_partition = SkipTxPartition::new(&tx_keyspace)?;
-    let skip_tx_by_block_partition = SkipTxByBlockPartition::new(&tx_keyspace)?;
-    let block_compact_header_partition = BlockCompactHeaderPartition::new(&tx_keyspace)?;
-    let acceptance_to_tx_id_partition = AcceptingBlockToTxIDPartition::new(&tx_keyspace)?;
-    let unknown_tx_partition = UnknownTxPartition::new(&tx_keyspace)?;
-    let unknown_accepting_daa_partition = UnknownAcceptingDaaPartition::new(&tx_keyspace)?;
-    let pending_sender_resolution_partition = PendingSenderResolutionPartition::new(&tx_keyspace)?;
-    let handshake_by_sender_partition = HandshakeBySenderPartition::new(&tx_keyspace)?;
-    let payment_by_sender_partition = PaymentBySenderPartition::new(&tx_keyspace)?;
-    let block_gaps_partition = BlockGapsPartition::new(&tx_keyspace)?;
-    let block_daa_index_partition = DaaIndexPartition::new(&tx_keyspace)?;
-    info!(
-        "Gaps exist: {:?}",
-        block_gaps_partition
-            .get_all_gaps_since_daa(0)
-            .collect::<Result<Vec<_>, _>>()
-    );
-
-    let metrics = create_shared_metrics_from_snapshot(IndexerMetricsSnapshot {
-        handshakes_by_sender: handshake_by_sender_partition.approximate_len() as u64,
-        handshakes_by_receiver: tx_id_to_handshake_partition.approximate_len() as u64,
-        payments_by_sender: payment_by_sender_partition.approximate_len() as u64,
-        payments_by_receiver: tx_id_to_payment_partition.approximate_len() as u64,
-        contextual_messages: contextual_message_partition.len()? as u64,
-        blocks_processed: block_compact_header_partition.len()? as u64,
-        latest_block: metadata_partition
-            .get_latest_block_cursor_rtx(&tx_keyspace.read_tx())?
-            .unwrap_or_default()
-            .hash,
-        latest_accepting_block: metadata_partition
-            .get_latest_accepting_block_cursor()?
-            .unwrap_or_default()
-            .hash,
-        unknown_daa_entries: unknown_accepting_daa_partition.len()? as u64,
-        unknown_sender_entries: pending_sender_resolution_partition.len()? as u64,
-        unknown_tx_entries: 0,
-        resolved_daa: 0,
-        resolved_senders: 0,
-    });
-
-    let (block_intake_tx, block_intake_rx) = flume::bounded(4096);
-
-    let (vcc_intake_tx, vcc_intake_rx) = flume::bounded(4096);
-    let (shutdown_block_worker_tx, shutdown_block_worker_rx) = flume::bounded(1);
-    let (shutdown_acceptance_worker_tx, shutdown_acceptance_worker_rx) = flume::bounded(1);
-    let virtual_daa = Arc::new(AtomicU64::new(0));
-
-    let rpc_client = create_rpc_client()?;
-
-    let mut block_worker = BlockProcessor::builder()
-        .processed_blocks(FifoSet::new(256))
-        .intake(block_intake_rx)
-        .shutdown(shutdown_block_worker_rx)
-        .tx_keyspace(tx_keyspace.clone())
-        .metadata_partition(metadata_partition.clone())
-        .handshake_by_receiver_partition(handshake_by_receiver_partition.clone())
-        .tx_id_to_handshake_partition(tx_id_to_handshake_partition.clone())
-        .contextual_message_partition(contextual_message_partition.clone())
-        .payment_by_receiver_partition(payment_by_receiver_partition.clone())
-        .tx_id_to_payment_partition(tx_id_to_payment_partition.clone())
-        .tx_id_to_acceptance_partition(tx_id_to_acceptance_partition.clone())
-        .skip_tx_partition(skip_tx_partition.clone())
-        .skip_tx_by_block_partition(skip_tx_by_block_partition.clone())
-        .block_compact_header_partition(block_compact_header_partition.clone())
-        .metrics(metrics.clone())
-        .processed_txs(FifoSet::new(
-            300/*txs per block*/ * 255, /*max mergeset size*/
-        ))
-        .block_daa_index(block_daa_index_partition.clone())
-        .build();
-
-    let mut acceptance_worker = VirtualChainProcessor::builder()
-        .daa_resolution_attempt_count(5)
-        .reorg_log(reorg_lock.clone())
-        .vcc_rx(vcc_intake_rx)
-        .shutdown(shutdown_acceptance_worker_rx)
-        .tx_keyspace(tx_keyspace.clone())
-        .metadata_partition(metadata_partition.clone())
-        .skip_tx_partition(skip_tx_partition.clone())
-        .tx_id_to_acceptance_partition(tx_id_to_acceptance_partition.clone())
-        .acceptance_to_tx_id_partition(acceptance_to_tx_id_partition.clone())
-        .unknown_tx_partition(unknown_tx_partition.clone())
-        .unknown_accepting_daa_partition(unknown_accepting_daa_partition.clone())
-        .block_compact_header_partition(block_compact_header_partition.clone())
-        .pending_sender_resolution_partition(pending_sender_resolution_partition.clone())
-        .build();
-
-    let (resolver_block_request_tx, resolver_block_request_rx) =
-        workflow_core::channel::bounded(16384);
-    // workflow_core::channel::unbounded();
-    let (resolver_sender_request_tx, resolver_sender_request_rx) =
-        workflow_core::channel::bounded(16384);
-    // workflow_core::channel::unbounded();
-    let (resolver_response_tx, resolver_response_rx) = workflow_core::channel::bounded(32768);
-    // let (resolver_response_tx, resolver_response_rx) = workflow_core::channel::unbounded();
-    let (shutdown_resolver_tx, shutdown_resolver_rx) = tokio::sync::oneshot::channel();
-
-    let requests_in_progress = Arc::new(AtomicU64::new(0));
-    let mut resolver = Resolver::new(
-        shutdown_resolver_rx,
-        resolver_block_request_rx,
-        resolver_sender_request_rx,
-        resolver_response_tx.clone(),
-        rpc_client.clone(),
-        requests_in_progress.clone(),
-    );
-
-    let (scan_worker_job_done_tx, scan_worker_job_done_rx) = workflow_core::channel::bounded(1);
-
-    let mut scan_worker = PeriodicProcessor::builder()
-        .tick_and_resolution_rx(resolver_response_rx)
-        .resolver_request_block_tx(resolver_block_request_tx)
-        .resolver_request_sender_tx(resolver_sender_request_tx)
-        .job_done_tx(scan_worker_job_done_tx)
-        .reorg_lock(reorg_lock)
-        .tx_keyspace(tx_keyspace.clone())
-        .tx_id_to_acceptance_partition(tx_id_to_acceptance_partition.clone())
-        .unknown_tx_partition(unknown_tx_partition.clone())
-        .skip_tx_partition(skip_tx_partition.clone())
-        .skip_tx_by_block_partition(skip_tx_by_block_partition.clone())
-        .unknown_accepting_daa_partition(unknown_accepting_daa_partition.clone())
-        .block_compact_header_partition(block_compact_header_partition.clone())
-        .daa_resolution_attempt_count(5)
-        .pending_sender_resolution_partition(pending_sender_resolution_partition.clone())
-        .handshake_by_receiver_partition(handshake_by_receiver_partition.clone())
-        .handshake_by_sender_partition(handshake_by_sender_partition.clone())
-        .contextual_message_by_sender_partition(contextual_message_partition.clone())
-        .payment_by_receiver_partition(payment_by_receiver_partition.clone())
-        .payment_by_sender_partition(payment_by_sender_partition.clone())
-        .tx_id_to_payment_partition(tx_id_to_payment_partition.clone())
-        .tx_id_to_handshake_partition(tx_id_to_handshake_partition.clone())
-        .metrics(metrics)
-        .metrics_snapshot_interval(Duration::from_secs(10))
-        .metadata_partition(metadata_partition.clone())
-        .resolver_requests_in_progress(requests_in_progress)
-        .block_daa_index(block_daa_index_partition)
-        .virtual_daa(virtual_daa.clone())
-        .build();
-
-    let (selected_chain_intake_tx, selected_chain_intake_rx) = tokio::sync::mpsc::channel(4096);
-    let (historical_sync_done_tx, historical_sync_done_rx) = tokio::sync::mpsc::channel(1);
-
-    let (shutdown_selected_chain_syncer_tx, shutdown_selected_chain_syncer_rx) =
-        tokio::sync::oneshot::channel();
-    let mut selected_chain_syncer = SelectedChainSyncer::new(
-        rpc_client.clone(),
-        metadata_partition.clone(),
-        block_compact_header_partition.clone(),
-        selected_chain_intake_rx,
-        historical_sync_done_rx,
-        historical_sync_done_tx,
-        vcc_intake_tx,
-        shutdown_selected_chain_syncer_rx,
-    );
-
-    let (shutdown_subscriber_tx, shutdown_subscriber_rx) = tokio::sync::oneshot::channel();
-    let mut subscriber = Subscriber::new(
-        rpc_client.clone(),
-        block_intake_tx.clone(),
-        shutdown_subscriber_rx,
-        block_gaps_partition.clone(),
-        selected_chain_intake_tx,
-        metadata_partition.get_latest_block_cursor_rtx(&tx_keyspace.read_tx())?,
-        virtual_daa.clone(),
-    );
-
-    let (shutdown_ticker_tx, shutdown_ticker_rx) = tokio::sync::oneshot::channel();
-    tokio::spawn(run_ticker(
-        shutdown_ticker_rx,
-        scan_worker_job_done_rx,
-        resolver_response_tx.clone(),
-        Duration::from_secs(10),
-    ));
-
-    // Spawn workers
-    let block_worker_handle = std::thread::spawn(move || {
-        block_worker
-            .process()
-            .inspect(|_| info!("block worker has stopped"))
-            .inspect_err(|err| error!("block worker stopped with error: {err}"))
-    });
-    let acceptance_worker_handle = std::thread::spawn(move || {
-        acceptance_worker
-            .process()
-            .inspect(|_| info!("acceptance worker has stopped"))
-            .inspect_err(|err| error!("acceptance worker stopped with error: {err}"))
+    let tx_keyspace = database::open(&db_path)?;
+    let indexer = Indexer::builder()
+        .config(config)
+        .database(tx_keyspace.clone())
+        .build()
+        .await?;
+    crash_handler::install(CrashContext {
+        data_dir: db_path.clone(),
+        partition: Some(CrashReportsPartition::new(&tx_keyspace)?),
+        metrics: Some(indexer.metrics().clone()),
+        shutdown: Some(indexer.shutdown_signal()),
     });
-    let scan_worker_handle = std::thread::spawn(move || {
-        scan_worker
-            .worker()
-            .inspect_err(|err| {
-                error!("scan worker stopped with error: {err}");
-            })
-            .inspect(|_| info!("scan worker has stopped"))
+    if let Some(hash) = reprocess {
+        indexer.reprocess(hash).await?;
+        return Ok(());
+    }
+    if let Some((daa_range, targets, force)) = reindex {
+        let summary = indexer.reindex(daa_range, &targets, force).await?;
+        print!("{summary}");
+        return Ok(());
+    }
+    let indexer = Arc::new(indexer);
+
+    #[cfg(unix)]
+    tokio::spawn(snapshot_on_signal(tx_keyspace.clone(), snapshot_dir));
+    tokio::spawn({
+        let indexer = indexer.clone();
+        async move {
+            match tokio::signal::ctrl_c().await {
+                Ok(()) => info!("Termination signal received. Shutting down..."),
+                Err(err) => error!("Failed to listen for the termination signal: {err}"),
+            }
+            indexer.shutdown();
+        }
     });
 
-    let resolver_handle = tokio::spawn(async move { resolver.process().await });
-    let selected_chain_syncer_handle =
-        tokio::spawn(async move { selected_chain_syncer.process().await });
-    let subscriber_handle = tokio::spawn(async move { subscriber.task().await });
-
-    let options = ConnectOptions {
-        block_async_connect: false,
-        connect_timeout: Some(Duration::from_millis(10_000)),
-        strategy: ConnectStrategy::Retry,
-        ..Default::default()
-    };
-
-    tokio::time::sleep(Duration::from_secs(5)).await; // let time to spawn everything
-    info!("Connecting to Kaspa node...");
-    rpc_client
-        .connect(Some(options))
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to connect to node: {}", e))?;
-
-    // Handle shutdown
-    tokio::signal::ctrl_c().await?;
-    info!("Termination signal received. Shutting down...");
-    APP_IS_RUNNING.store(false, std::sync::atomic::Ordering::Relaxed);
-
-    _ = shutdown_subscriber_tx
-        .send(())
-        .inspect_err(|_err| error!("failed to shutdown subscriber"));
-
-    _ = shutdown_block_worker_tx
-        .send(())
-        .inspect_err(|err| error!("failed to shutdown block worker: {}", err));
-    info!("try shutdown acceptance worker");
-    _ = shutdown_acceptance_worker_tx
-        .send(())
-        .inspect_err(|err| error!("failed to shutdown acceptance worker: {}", err));
-    info!("try shutdown selected_chain_syncer");
-    _ = shutdown_selected_chain_syncer_tx
-        .send(())
-        .inspect_err(|_err| error!("failed to shutdown chain"));
-    info!("try shutdown ticker");
-    _ = shutdown_ticker_tx
-        .send(())
-        .inspect_err(|_err| error!("failed to shutdown ticker"));
-    info!("try shutdown scan worker");
-    _ = resolver_response_tx
-        .send(Notification::Shutdown)
-        .await
-        .inspect_err(|_err| error!("failed to shutdown scan worker"));
-    info!("try shutdown resolver");
-    _ = shutdown_resolver_tx
-        .send(())
-        .inspect_err(|_err| error!("failed to shutdown resolver"));
-
-    // Await on all tasks to complete
-    info!("waiting for resolver finish");
-    _ = resolver_handle
-        .await?
-        .inspect(|_| info!("resolver has stopped")); // todo logs
-    info!("waiting for syncer finish");
-    _ = selected_chain_syncer_handle
-        .await
-        .inspect(|_| info!("selected chain syncer has stopped"))?; // todo logs
-    info!("waiting for subscriber finish");
-    _ = subscriber_handle
-        .await
-        .inspect(|_| info!("subscriber has stopped"))?; // todo logs
-    info!("waiting for acceptance worker finish");
-    _ = acceptance_worker_handle
-        .join()
-        .expect("failed to join acceptance worker"); // todo logs
-    info!("waiting for scan worker finish");
-    _ = scan_worker_handle
-        .join()
-        .expect("failed to join scan_worker thread"); // todo logs
-    info!("waiting for block worker finish");
-    _ = block_worker_handle
-        .join()
-        .expect("failed to join block_worker thread"); // todo logs
-
-    info!("All tasks shut down.");
-
-    Ok(())
+    let result = indexer.run().await;
+    if let Some(tracer_provider) = tracer_provider {
+        _ = tracer_provider
+            .shutdown()
+            .inspect_err(|err| error!("failed to flush trace spans: {err}"));
+    }
+    Ok(result?)
 }
 
-fn create_rpc_client() -> anyhow::Result<KaspaRpcClient> {
-    let encoding = WrpcEncoding::Borsh;
-
-    let url = std::env::var("KASPA_NODE_WBORSH_URL").ok();
-    let resolver = if url.is_some() {
-        None
-    } else {
-        Some(kaspa_wrpc_client::Resolver::default())
+/// Creates a hot snapshot under `snapshots_dir` every time the process receives SIGUSR1
+#[cfg(unix)]
+async fn snapshot_on_signal(tx_keyspace: fjall::TxKeyspace, snapshots_dir: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("Failed to register snapshot signal handler: {err}");
+            return;
+        }
     };
-
-    let network_type = NetworkType::Mainnet;
-    let selected_network = Some(NetworkId::new(network_type));
-
-    let subscription_context = None;
-
-    info!("Creating RPC client for network: {:?}", network_type);
-
-    let client = KaspaRpcClient::new(
-        encoding,
-        url.as_deref(),
-        resolver,
-        selected_network,
-        subscription_context,
-    )
-    .map_err(|e| anyhow::anyhow!("Failed to create RPC client: {}", e))?;
-    Ok(client)
+    while signals.recv().await.is_some() {
+        let destination = snapshots_dir.join(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                .to_string(),
+        );
+        let keyspace = tx_keyspace.clone();
+        info!("Snapshot requested, writing to {}", destination.display());
+        match tokio::task::spawn_blocking(move || snapshot::create_snapshot(&keyspace, destination))
+            .await
+        {
+            Ok(Ok(info)) => info!("Snapshot done: {info:?}"),
+            Ok(Err(err)) => error!("Snapshot failed: {err}"),
+            Err(err) => error!("Snapshot task panicked: {err}"),
+        }
+    }
 }
 
-pub fn init_logs<P: AsRef<Path>>(logs_dir: P) -> anyhow::Result<(WorkerGuard, WorkerGuard)> {
+/// Also exports the ingestion spans to the OTLP endpoint if given, the returned provider
+/// flushes them on shutdown
+pub fn init_logs<P: AsRef<Path>>(
+    logs_dir: P,
+    otlp_endpoint: Option<&str>,
+) -> anyhow::Result<(WorkerGuard, WorkerGuard, Option<SdkTracerProvider>)> {
     let file_appender = rolling_file::BasicRollingFileAppender::new(
         logs_dir.as_ref().join("kasia-indexer.mainnet.log"),
         rolling_file::RollingConditionBasic::new()
@@ -406,10 +350,36 @@ pub fn init_logs<P: AsRef<Path>>(logs_dir: P) -> anyhow::Result<(WorkerGuard, Wo
                 .from_env_lossy(),
         );
 
-    tracing_subscriber::registry()
+    let tracer_provider = otlp_endpoint
+        .map(|endpoint| -> anyhow::Result<_> {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?;
+            Ok(SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name("kasia-indexer")
+                        .build(),
+                )
+                .build())
+        })
+        .transpose()?;
+    let otel_subscriber = tracer_provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("kasia-indexer"))
+            .with_filter(Targets::new().with_target(TRACE_TARGET, Level::DEBUG))
+    });
+
+    let registry = tracing_subscriber::registry()
         .with(file_subscriber)
         .with(stdout_subscriber)
-        .init();
+        .with(otel_subscriber);
+    // tags the pipeline stages of the profiles
+    #[cfg(feature = "profiling")]
+    let registry = registry.with(indexer_lib::profiling::ComponentLayer);
+    registry.init();
 
-    Ok((guard_file, guard_stdout))
+    Ok((guard_file, guard_stdout, tracer_provider))
 }